@@ -1,6 +1,7 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 use kikyo_core::engine::Engine;
-use kikyo_core::parser::parse_yab_content;
+use kikyo_core::parser::{layout_source_files, parse_yab_content};
+use std::path::PathBuf;
 
 const BENCH_LAYOUT: &str = r#"
 [ローマ字シフト無し]
@@ -79,11 +80,52 @@ fn bench_three_key_chord(c: &mut Criterion) {
     });
 }
 
+fn bench_key_repeat(c: &mut Criterion) {
+    let mut engine = make_engine(0.35);
+    c.bench_function("engine/key_repeat_a_x10", |b| {
+        b.iter(|| {
+            black_box(engine.process_key(0x1E, false, false, false)); // A down
+            for _ in 0..10 {
+                black_box(engine.process_key(0x1E, false, false, false)); // A repeat
+            }
+            black_box(engine.process_key(0x1E, false, true, false)); // A up
+        });
+    });
+}
+
+/// Text of the largest bundled layout.
+fn large_layout_source() -> String {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("../../layout/新下駄.yab");
+    let mut files = layout_source_files(&path).expect("failed to read benchmark layout");
+    files.remove(0).1
+}
+
+fn bench_layout_load(c: &mut Criterion) {
+    let source = large_layout_source();
+    c.bench_function("parser/parse_large_yab", |b| {
+        b.iter(|| black_box(parse_yab_content(black_box(&source)).unwrap()));
+    });
+
+    let layout = parse_yab_content(&source).unwrap();
+    let mut engine = Engine::default();
+    engine.set_ignore_ime(true);
+    c.bench_function("engine/load_large_layout", |b| {
+        b.iter_batched(
+            || layout.clone(),
+            |layout| engine.load_layout(layout),
+            BatchSize::SmallInput,
+        );
+    });
+}
+
 criterion_group!(
     benches,
     bench_single_tap,
     bench_undefined_passthrough,
     bench_two_key_chord,
-    bench_three_key_chord
+    bench_three_key_chord,
+    bench_key_repeat,
+    bench_layout_load
 );
 criterion_main!(benches);
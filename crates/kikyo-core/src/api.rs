@@ -0,0 +1,179 @@
+//! Stable entry point for embedding the converter, e.g. in an IME plugin.
+//!
+//! Items in this module follow semantic versioning: they only change in
+//! incompatible ways with a major version. The rest of the crate is
+//! Kikyo's own implementation and may change in any release.
+//! [`InputEvent`] and [`KeyAction`] are `#[non_exhaustive]`: new kinds of
+//! output come in minor versions, so match them with a wildcard arm.
+//!
+//! ```no_run
+//! use kikyo_core::api::{Converter, InputMode, KeyInput};
+//!
+//! let mut converter = Converter::new();
+//! converter.load_layout("新下駄配列.yab")?;
+//! converter.set_input_mode(InputMode::Japanese);
+//! let action = converter.process_key(KeyInput::press(0x1E));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::chord_engine::ImeMode;
+use crate::engine::Engine;
+use crate::foreground::foreground_exe_name;
+use crate::parser::{load_yab, parse_yab_content};
+use crate::scripting;
+use crate::types::RawKeyEvent;
+use anyhow::Result;
+use std::path::Path;
+
+pub use crate::types::{InputEvent, KeyAction};
+
+/// Where the converter takes the input mode from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputMode {
+    /// Follow the IME of the focused window.
+    #[default]
+    Auto,
+    /// Always use the layout's Japanese (ローマ字) sections.
+    Japanese,
+    /// Always use the layout's alphanumeric (英数) sections.
+    Alphanumeric,
+}
+
+/// A physical key event, by PC/AT scancode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyInput {
+    pub scancode: u16,
+    /// E0-prefixed key (arrows, right Ctrl etc.).
+    pub extended: bool,
+    pub up: bool,
+    /// Shift is held.
+    pub shift: bool,
+}
+
+impl KeyInput {
+    pub fn press(scancode: u16) -> Self {
+        Self {
+            scancode,
+            extended: false,
+            up: false,
+            shift: false,
+        }
+    }
+
+    pub fn release(scancode: u16) -> Self {
+        Self {
+            up: true,
+            ..Self::press(scancode)
+        }
+    }
+}
+
+/// A keyboard layout converter: feed it key events, inject what it returns.
+pub struct Converter {
+    engine: Engine,
+}
+
+impl Default for Converter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Converter {
+    /// A converter with no layout, which passes every key through.
+    pub fn new() -> Self {
+        Self {
+            engine: Engine::default(),
+        }
+    }
+
+    /// Loads a `.yab` layout file and the files it includes.
+    pub fn load_layout(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.engine.load_layout(load_yab(path)?);
+        Ok(())
+    }
+
+    /// Loads a layout from `.yab` source; `#include` is not followed.
+    pub fn load_layout_str(&mut self, source: &str) -> Result<()> {
+        self.engine.load_layout(parse_yab_content(source)?);
+        Ok(())
+    }
+
+    pub fn layout_name(&self) -> Option<String> {
+        self.engine.get_layout_name()
+    }
+
+    pub fn set_input_mode(&mut self, mode: InputMode) {
+        self.engine.set_ime_mode(match mode {
+            InputMode::Auto => ImeMode::Auto,
+            InputMode::Japanese => ImeMode::Ignore,
+            InputMode::Alphanumeric => ImeMode::ForceAlpha,
+        });
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.engine.is_enabled()
+    }
+
+    /// While disabled every key passes through.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.engine.set_enabled(enabled);
+    }
+
+    /// Converts one key event. Keys that may start a chord are held back
+    /// (`KeyAction::Block`) and come out with a later event.
+    pub fn process_key(&mut self, key: KeyInput) -> KeyAction {
+        let action = self.engine.process_event(&RawKeyEvent {
+            sc: key.scancode,
+            ext: key.extended,
+            up: key.up,
+            shift: key.shift.into(),
+            modifier: None,
+        });
+        match action {
+            KeyAction::Inject(events) => KeyAction::Inject(self.run_scripts(events)),
+            action => action,
+        }
+    }
+
+    /// Settles keys held back for a chord when focus moves to another
+    /// window; returns what to type into the old one.
+    pub fn focus_changed(&mut self) -> Vec<InputEvent> {
+        let events = self.engine.on_focus_change(foreground_exe_name());
+        self.run_scripts(events)
+    }
+
+    fn run_scripts(&mut self, events: Vec<InputEvent>) -> Vec<InputEvent> {
+        scripting::resolve(events, |call, outcome| {
+            self.engine.scripted_events(call, outcome)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_converter_types_the_loaded_layout() {
+        let mut converter = Converter::new();
+        converter
+            .load_layout_str(
+                "
+[ローマ字シフト無し]
+'l'
+",
+            )
+            .unwrap();
+        converter.set_input_mode(InputMode::Japanese);
+
+        let mut events = Vec::new();
+        for key in [KeyInput::press(0x02), KeyInput::release(0x02)] {
+            if let KeyAction::Inject(injected) = converter.process_key(key) {
+                events.extend(injected);
+            }
+        }
+        events.extend(converter.focus_changed());
+        assert!(events.contains(&InputEvent::Scancode(0x26, false, false)));
+    }
+}
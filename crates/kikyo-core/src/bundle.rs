@@ -0,0 +1,210 @@
+use crate::parser::{layout_source_files, normalize_relative};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Current `.kikyo` bundle format.
+pub const BUNDLE_FORMAT: u32 = 1;
+/// File extension of settings bundles.
+pub const BUNDLE_EXTENSION: &str = "kikyo";
+
+/// Settings, profiles and layout files in one JSON document, for moving a
+/// configuration to another PC or syncing it through a shared folder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    pub format: u32,
+    /// When the bundle was written. A bundle is only imported when it is
+    /// newer than the last one seen, so an older copy never wins.
+    pub exported_unix_ms: u64,
+    /// Computer that wrote the bundle.
+    #[serde(default)]
+    pub exported_by: String,
+    /// The app's settings as stored in settings.json.
+    pub settings: serde_json::Value,
+    #[serde(default)]
+    pub layouts: Vec<BundledLayout>,
+}
+
+/// A registered layout with the files it includes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundledLayout {
+    /// Id of the layout entry in `settings`.
+    pub entry_id: String,
+    /// The layout file first, then its includes.
+    pub files: Vec<BundledFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundledFile {
+    /// Path relative to the layout file's directory, '/'-separated.
+    pub path: String,
+    pub text: String,
+}
+
+impl SettingsBundle {
+    pub fn read(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("cannot open {}", path.display()))?;
+        let bundle: Self = serde_json::from_str(&content)?;
+        if bundle.format > BUNDLE_FORMAT {
+            bail!("unsupported bundle format {}", bundle.format);
+        }
+        Ok(bundle)
+    }
+
+    /// Writes through a temporary file, so a sync client never picks up a
+    /// half-written bundle.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension(format!("{}.tmp", BUNDLE_EXTENSION));
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl BundledLayout {
+    /// Reads the layout at `path` and the files it includes. Includes from
+    /// parent folders (`#include "../shared.yab"`) are kept by bundling from
+    /// the folder that holds them all, so the paths in the files still work.
+    pub fn from_layout(entry_id: &str, path: &Path) -> Result<Self> {
+        let sources = layout_source_files(path)?;
+        let mut depth = 0;
+        for (relative, _) in &sources {
+            if !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+            {
+                bail!("include by absolute path: {}", relative.display());
+            }
+            let ups = relative
+                .components()
+                .take_while(|c| *c == Component::ParentDir)
+                .count();
+            depth = depth.max(ups);
+        }
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("cannot open {}", dir.display()))?;
+        let names: Vec<_> = dir
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        if names.len() < depth {
+            bail!("include above the root folder of {}", path.display());
+        }
+        let root: PathBuf = names[names.len() - depth..].iter().collect();
+
+        let files = sources
+            .into_iter()
+            .map(|(relative, text)| BundledFile {
+                path: normalize_relative(&root.join(relative))
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                text,
+            })
+            .collect();
+        Ok(Self {
+            entry_id: entry_id.to_string(),
+            files,
+        })
+    }
+
+    /// Writes the files below `dir` and returns the layout file's path.
+    /// Nothing is written if any file would land outside `dir`.
+    pub fn extract(&self, dir: &Path) -> Result<PathBuf> {
+        let targets = self
+            .files
+            .iter()
+            .map(|file| {
+                let relative = Path::new(&file.path);
+                if !relative
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+                {
+                    bail!("file outside the layout folder: {}", file.path);
+                }
+                Ok(dir.join(relative))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (file, target) in self.files.iter().zip(&targets) {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(target, &file.text)?;
+        }
+        targets
+            .into_iter()
+            .next()
+            .with_context(|| format!("layout {} has no files", self.entry_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_round_trips_with_includes_and_rejects_escapes() {
+        let base = std::env::temp_dir().join(format!("kikyo-bundle-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let src = base.join("src");
+        fs::create_dir_all(src.join("common")).unwrap();
+        fs::write(
+            src.join("common").join("base.yab"),
+            "[ローマ字シフト無し]\n1\n",
+        )
+        .unwrap();
+        fs::write(
+            src.join("main.yab"),
+            "#include \"common/base.yab\"\n[ローマ字シフト無し]\n9\n",
+        )
+        .unwrap();
+
+        let layout = BundledLayout::from_layout("layout-1", &src.join("main.yab")).unwrap();
+        assert_eq!(layout.files[1].path, "common/base.yab");
+        let bundle = SettingsBundle {
+            format: BUNDLE_FORMAT,
+            exported_unix_ms: 1,
+            exported_by: "PC".to_string(),
+            settings: serde_json::json!({ "enabled": true }),
+            layouts: vec![layout],
+        };
+        let bundle_path = base.join("sync").join("settings.kikyo");
+        bundle.write(&bundle_path).unwrap();
+        let read = SettingsBundle::read(&bundle_path).unwrap();
+        assert_eq!(read, bundle);
+
+        let main = read.layouts[0].extract(&base.join("out")).unwrap();
+        assert_eq!(main, base.join("out").join("main.yab"));
+        assert!(crate::parser::load_yab(&main).is_ok());
+
+        let mut escaping = read.layouts[0].clone();
+        escaping.files[1].path = "../evil.yab".to_string();
+        assert!(escaping.extract(&base.join("out2")).is_err());
+        assert!(!base.join("out2").exists());
+
+        fs::create_dir_all(src.join("variant")).unwrap();
+        fs::write(
+            src.join("variant").join("main.yab"),
+            "#include \"../common/base.yab\"\n",
+        )
+        .unwrap();
+        let layout =
+            BundledLayout::from_layout("layout-2", &src.join("variant").join("main.yab")).unwrap();
+        let paths: Vec<&str> = layout.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["variant/main.yab", "common/base.yab"]);
+        let main = layout.extract(&base.join("out3")).unwrap();
+        assert_eq!(main, base.join("out3").join("variant").join("main.yab"));
+        assert!(crate::parser::load_yab(&main).is_ok());
+
+        let _ = fs::remove_dir_all(&base);
+    }
+}
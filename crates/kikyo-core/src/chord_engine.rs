@@ -1,3 +1,4 @@
+use crate::jis_map::function_key;
 use crate::types::ScKey;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -7,6 +8,8 @@ pub const EXTENDED_KEY_1_SC: u16 = 0x0201;
 pub const EXTENDED_KEY_2_SC: u16 = 0x0202;
 pub const EXTENDED_KEY_3_SC: u16 = 0x0203;
 pub const EXTENDED_KEY_4_SC: u16 = 0x0204;
+/// Space, when used as a SandS thumb shift.
+pub const SANDS_KEY: ScKey = ScKey::new(0x39, false);
 
 /// Internal event type for the engine
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -36,6 +39,8 @@ pub enum Decision {
     LatchOn(LatchKind),
     /// End a latch
     LatchOff,
+    /// Held alone past `Profile::long_press_ms`; types its `<long>` token.
+    LongPress(ScKey),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -67,6 +72,35 @@ pub struct ThumbKeys {
     pub right: HashSet<ScKey>,
     pub ext1: HashSet<ScKey>,
     pub ext2: HashSet<ScKey>,
+    /// Keys of `Profile::extra_thumbs`, by index.
+    pub extra: Vec<HashSet<ScKey>>,
+    /// Number-row keys acting as shifts under `ChordStyle::NNumberKey`.
+    pub number: HashSet<ScKey>,
+    /// Shift keys judged by overlap under `Profile::pinky_shift`.
+    pub shift: HashSet<ScKey>,
+}
+
+impl ThumbKeys {
+    pub fn contains(&self, key: &ScKey) -> bool {
+        self.iter().any(|k| k == key)
+    }
+
+    /// All thumb keys: left, right, ext1, ext2, the extra thumbs, the
+    /// number-row shift keys, then the pinky Shift keys.
+    pub fn iter(&self) -> impl Iterator<Item = &ScKey> {
+        self.left
+            .iter()
+            .chain(self.right.iter())
+            .chain(self.ext1.iter())
+            .chain(self.ext2.iter())
+            .chain(self.extra.iter().flatten())
+            .chain(self.number.iter())
+            .chain(self.shift.iter())
+    }
+
+    pub fn extra_index(&self, key: &ScKey) -> Option<usize> {
+        self.extra.iter().position(|keys| keys.contains(key))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -91,10 +125,107 @@ pub enum ThumbShiftSinglePress {
     SpaceKey,    // Spaceキー
 }
 
+/// Settings for `ChordStyle::NNumberKey`. Each number-row key 1–0 shifts
+/// the other keys into `[ローマ字数字<n>シフト]` / `[英数数字<n>シフト]`
+/// (`[ローマ字小指数字<n>シフト]` etc. while Shift is held).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NumberKeyCfg {
+    pub continuous: bool,
+    pub overlap_ratio: f64,
+    pub min_overlap_ms: u64,
+}
+
+impl Default for NumberKeyCfg {
+    fn default() -> Self {
+        Self {
+            continuous: false,
+            overlap_ratio: 0.35,
+            min_overlap_ms: 0,
+        }
+    }
+}
+
+/// Treats the physical Shift keys as chord keys. Shift is then kept from
+/// the OS and selects the `小指` sections only when it overlaps a key by
+/// the ratio below, like a thumb shift.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PinkyShiftCfg {
+    pub enabled: bool,
+    pub continuous: bool,
+    pub overlap_ratio: f64,
+    pub min_overlap_ms: u64,
+}
+
+impl Default for PinkyShiftCfg {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            continuous: true,
+            overlap_ratio: 0.35,
+            min_overlap_ms: 0,
+        }
+    }
+}
+
+/// Overlap ratios from the layout's `[タイミング]` section. A pair entry
+/// wins over its keys' entries; of two key entries the larger applies.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OverlapOverrides {
+    pub pairs: HashMap<(ScKey, ScKey), f64>,
+    pub keys: HashMap<ScKey, f64>,
+}
+
+impl OverlapOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty() && self.keys.is_empty()
+    }
+
+    pub fn ratio(&self, k1: ScKey, k2: ScKey) -> Option<f64> {
+        if let Some(ratio) = self.pairs.get(&(k1, k2)).or(self.pairs.get(&(k2, k1))) {
+            return Some(*ratio);
+        }
+        match (self.keys.get(&k1), self.keys.get(&k2)) {
+            (Some(r1), Some(r2)) => Some(r1.max(*r2)),
+            (r1, r2) => r1.or(r2).copied(),
+        }
+    }
+}
+
+/// An additional thumb modifier beyond left/right/extended 1/2.
+/// Its plane is the section `[ローマ字<suffix>]` / `[英数<suffix>]`,
+/// or `[ローマ字小指<suffix>]` / `[英数小指<suffix>]` while Shift is held.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtraThumbCfg {
+    /// Section name suffix, e.g. "左親指シフト2".
+    pub suffix: String,
+    pub keys: Vec<ScKey>,
+    pub continuous: bool,
+    pub single_press: ThumbShiftSinglePress,
+    /// See [`ThumbSideConfig::double_tap`].
+    pub double_tap: String,
+}
+
+impl Default for ExtraThumbCfg {
+    fn default() -> Self {
+        Self {
+            suffix: String::new(),
+            keys: Vec::new(),
+            continuous: false,
+            single_press: ThumbShiftSinglePress::None,
+            double_tap: String::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ImeMode {
     Auto,
     Imm,
+    /// Asks the default IME window only; named for the TSF-based IMEs
+    /// it was added for, and kept for saved settings.
     Tsf,
     Ignore,     // Force Japanese (Roman)
     ForceAlpha, // Force Alphanumeric
@@ -106,20 +237,190 @@ impl Default for ImeMode {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-pub enum SuspendKey {
-    None,
-    ScrollLock,
+/// How output that toggles the IME or confirms input is injected while the
+/// IME holds an uncommitted composition string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum CompositionPolicy {
+    /// Inject as usual.
+    #[default]
+    Ignore,
+    /// Wait briefly for the composition to be committed first.
+    Defer,
+    /// Commit the composition first.
+    FlushFirst,
+}
+
+/// What happens to keys still waiting for a chord partner when the
+/// foreground window changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum FocusChangePolicy {
+    /// Drop them so they cannot fire into the newly focused window.
+    #[default]
+    Discard,
+    /// Output them as single taps before the new window gets input.
+    Emit,
+}
+
+/// How two overlapping keys are judged to be a chord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum DecisionMode {
+    /// By how much of the later key's press the two overlap.
+    #[default]
+    OverlapRatio,
+    /// By whether the later key goes down within `chord_window_ms` of the
+    /// earlier one. A key left alone for the window is typed on its own.
+    FixedWindow,
+    /// Within the window is a chord at once; later presses fall back to
+    /// the overlap ratio.
+    Hybrid,
+}
+
+/// Width of a space typed from a layout in Japanese input mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum SpaceWidth {
+    /// Press the Space key and leave the width to the IME settings.
+    #[default]
+    Ime,
+    /// Type U+0020 directly.
+    Half,
+    /// Type U+3000 directly.
+    Full,
+}
+
+/// What happens to a key the active section does not define.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum UndefinedKeyPolicy {
+    /// Send the physical key unchanged.
+    #[default]
+    Pass,
+    /// Swallow the key.
+    Block,
+    /// Use the key from the unshifted section (e.g. `[ローマ字シフト無し]`
+    /// for a key missing from `[ローマ字小指シフト]`), passing it if that
+    /// section does not define it either.
+    Fallback,
+}
+
+/// Key that suspends and resumes Kikyo, with the modifiers held with it.
+/// Profiles from when only a few keys could be chosen name the key, e.g.
+/// `"ScrollLock"`; they still load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(from = "SuspendKeySetting")]
+pub struct SuspendKey {
+    /// `None` turns the suspend key off.
+    pub key: Option<ScKey>,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+}
+
+impl SuspendKey {
+    /// `key` pressed alone.
+    pub fn new(key: ScKey) -> Self {
+        Self {
+            key: Some(key),
+            ..Self::default()
+        }
+    }
+
+    /// The key the old `SuspendKey` choice `name` stood for.
+    fn legacy(name: &str) -> Option<ScKey> {
+        let key = match name {
+            "ScrollLock" => ScKey::new(0x46, false),
+            "Pause" => ScKey::new(0x45, false),
+            "Insert" => ScKey::new(0x52, true),
+            "RightShift" => ScKey::new(0x36, false),
+            "RightControl" => ScKey::new(0x1D, true),
+            "RightAlt" => ScKey::new(0x38, true),
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SuspendKeySetting {
+    Legacy(String),
+    Combo {
+        #[serde(default)]
+        key: Option<ScKey>,
+        #[serde(default)]
+        ctrl: bool,
+        #[serde(default)]
+        alt: bool,
+        #[serde(default)]
+        shift: bool,
+        #[serde(default)]
+        win: bool,
+    },
+}
+
+impl From<SuspendKeySetting> for SuspendKey {
+    fn from(setting: SuspendKeySetting) -> Self {
+        match setting {
+            SuspendKeySetting::Legacy(name) => Self {
+                key: Self::legacy(&name),
+                ..Self::default()
+            },
+            SuspendKeySetting::Combo {
+                key,
+                ctrl,
+                alt,
+                shift,
+                win,
+            } => Self {
+                key,
+                ctrl,
+                alt,
+                shift,
+                win,
+            },
+        }
+    }
+}
+
+/// Key of the emergency Ctrl+Alt+<key> combo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum PanicKey {
+    F9,
+    F10,
+    F11,
+    #[default]
+    F12,
     Pause,
-    Insert,
-    RightShift,
-    RightControl,
-    RightAlt,
 }
 
-impl Default for SuspendKey {
+impl PanicKey {
+    pub fn vk(self) -> u32 {
+        match self {
+            PanicKey::F9 => 0x78,
+            PanicKey::F10 => 0x79,
+            PanicKey::F11 => 0x7A,
+            PanicKey::F12 => 0x7B,
+            PanicKey::Pause => 0x13,
+        }
+    }
+}
+
+/// Emergency combo (Ctrl+Alt+`key` held for `hold_ms`) that forces every
+/// key to pass through, for when a broken layout makes typing impossible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PanicKeyCfg {
+    pub enabled: bool,
+    pub key: PanicKey,
+    pub hold_ms: u64,
+}
+
+impl Default for PanicKeyCfg {
     fn default() -> Self {
-        Self::None
+        Self {
+            enabled: true,
+            key: PanicKey::F12,
+            hold_ms: 2000,
+        }
     }
 }
 
@@ -130,6 +431,121 @@ pub struct SuccessiveCfg {
     // TODO: Add details
 }
 
+/// Undo chord: erases the output of the last resolved token.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct UndoCfg {
+    pub enabled: bool,
+    /// Keys that must be chorded together to trigger undo.
+    pub chord: Vec<ScKey>,
+}
+
+impl UndoCfg {
+    pub fn matches(&self, keys: &[ScKey]) -> bool {
+        self.enabled
+            && self.chord.len() >= 2
+            && keys.len() == self.chord.len()
+            && self.chord.iter().all(|k| keys.contains(k))
+    }
+}
+
+/// How injected key events are handed to SendInput. Consecutive key
+/// events go out in one call; apps that still drop some can get them in
+/// smaller chunks with a pause in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct InjectionCfg {
+    /// Most key events per SendInput call, 0 for no limit.
+    pub chunk_size: usize,
+    /// Pause between chunks (ms).
+    pub chunk_delay_ms: u64,
+}
+
+/// Thumb plane Space shifts into when SandS is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum SandsSide {
+    Left,
+    #[default]
+    Right,
+}
+
+/// SandS (Space and Shift): Space works as a thumb shift key while held
+/// together with other keys, and types a space when tapped alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandsCfg {
+    pub enabled: bool,
+    pub side: SandsSide,
+    /// A lone press held longer than this emits nothing on release.
+    pub tap_timeout_ms: u64,
+    /// Auto-repeat Space when it is held alone past `tap_timeout_ms`.
+    pub repeat: bool,
+}
+
+impl Default for SandsCfg {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            side: SandsSide::Right,
+            tap_timeout_ms: 200,
+            repeat: false,
+        }
+    }
+}
+
+/// Modifier a dual-role key acts as while held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum DualRoleModifier {
+    #[default]
+    Ctrl,
+    Shift,
+    Alt,
+    Win,
+}
+
+impl DualRoleModifier {
+    /// The left-hand key of the modifier.
+    pub fn key(self) -> ScKey {
+        match self {
+            DualRoleModifier::Ctrl => ScKey::new(0x1D, false),
+            DualRoleModifier::Shift => ScKey::new(0x2A, false),
+            DualRoleModifier::Alt => ScKey::new(0x38, false),
+            DualRoleModifier::Win => ScKey::new(0x5B, true),
+        }
+    }
+}
+
+/// A key that types its layout character when tapped, and acts as a real
+/// modifier when held past `hold_ms` or when another key is pressed and
+/// released inside it. Released before that other key, both are typed;
+/// held past `hold_ms` alone, nothing is sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DualRoleKeyCfg {
+    /// Key name as in `[機能キー]` or a layout section.
+    pub key: String,
+    pub modifier: DualRoleModifier,
+    pub hold_ms: u64,
+}
+
+impl Default for DualRoleKeyCfg {
+    fn default() -> Self {
+        Self {
+            key: String::new(),
+            modifier: DualRoleModifier::Ctrl,
+            hold_ms: 200,
+        }
+    }
+}
+
+/// A `[機能キー]` swap set in the settings: `from` acts as `to`, both named
+/// as in `[機能キー]`. It replaces the layout's swap of the same key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySwapCfg {
+    pub from: String,
+    pub to: String,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ThumbKeySelect {
     None,
@@ -166,7 +582,16 @@ pub struct ThumbSideConfig {
     pub key: ThumbKeySelect,
     pub continuous: bool,
     pub single_press: ThumbShiftSinglePress,
+    /// `[機能キー]` name of the key a second tap within
+    /// `Profile::double_tap_ms` types instead of its single press; empty
+    /// for none. The single press then waits until the interval has passed.
+    pub double_tap: String,
     pub repeat: bool,
+    /// Overlap ratio for chords with this thumb key
+    /// (falls back to `Profile::thumb_overlap_ratio`).
+    pub overlap_ratio: Option<f64>,
+    /// Minimum absolute overlap for chords with this thumb key.
+    pub min_overlap_ms: Option<u64>,
 }
 
 impl Default for ThumbSideConfig {
@@ -175,7 +600,10 @@ impl Default for ThumbSideConfig {
             key: ThumbKeySelect::None,
             continuous: false,
             single_press: ThumbShiftSinglePress::None,
+            double_tap: String::new(),
             repeat: false,
+            overlap_ratio: None,
+            min_overlap_ms: None,
         }
     }
 }
@@ -187,6 +615,8 @@ pub struct Profile {
     pub chord_style: ChordStyle,
     #[serde(default = "default_chord_window_ms")]
     pub chord_window_ms: u64,
+    #[serde(default)]
+    pub decision_mode: DecisionMode,
     #[serde(default = "default_max_chord_size")]
     pub max_chord_size: usize,
     #[serde(default)]
@@ -207,8 +637,50 @@ pub struct Profile {
 
     #[serde(default)]
     pub ime_mode: ImeMode,
+    /// Invert Shift for letters typed as keystrokes while CapsLock is on,
+    /// so layout letters keep their defined case.
+    #[serde(default = "default_caps_lock_compensation")]
+    pub caps_lock_compensation: bool,
+    /// Paste grapheme clusters made of several code points (emoji sequences,
+    /// combining marks) through the clipboard instead of Unicode key events.
+    #[serde(default)]
+    pub paste_grapheme_clusters: bool,
+    /// Direct strings with at least this many characters are pasted through
+    /// the clipboard instead of typed one character at a time.
+    #[serde(default)]
+    pub paste_min_chars: Option<usize>,
     #[serde(default)]
     pub suspend_key: SuspendKey,
+    #[serde(default)]
+    pub panic_key: PanicKeyCfg,
+    #[serde(default)]
+    pub focus_change: FocusChangePolicy,
+    #[serde(default)]
+    pub undefined_key: UndefinedKeyPolicy,
+    /// Keys by name (e.g. `F5`, `Esc`, `Mute`) that always pass through,
+    /// whatever the layout defines for them.
+    #[serde(default)]
+    pub pass_through_keys: Vec<String>,
+    #[serde(default)]
+    pub space_width: SpaceWidth,
+    /// An Enter held back until a rolled-over key is released is sent after
+    /// this long anyway. 0 waits for the release.
+    #[serde(default = "default_deferred_enter_timeout_ms")]
+    pub deferred_enter_timeout_ms: u64,
+    /// How long a thumb key with `repeat` has to be held alone before its
+    /// single-press action repeats.
+    #[serde(default = "default_thumb_repeat_delay_ms")]
+    pub thumb_repeat_delay_ms: u64,
+    /// Longest gap from the release of a thumb key tap to the next press
+    /// of the same key for the two to be a double tap.
+    #[serde(default = "default_double_tap_ms")]
+    pub double_tap_ms: u64,
+    /// How long a prefix shift waits for the key it shifts. 0 waits until
+    /// the next key.
+    #[serde(default)]
+    pub prefix_shift_timeout_ms: u64,
+    #[serde(default)]
+    pub ime_composition: CompositionPolicy,
 
     // New separate configurations
     #[serde(default)]
@@ -219,19 +691,88 @@ pub struct Profile {
     pub extended_thumb1: ThumbSideConfig,
     #[serde(default)]
     pub extended_thumb2: ThumbSideConfig,
-    #[serde(default = "default_thumb_shift_overlap_ratio")]
-    pub thumb_shift_overlap_ratio: f64, // Kept global as per implementation plan but not strictly required to be split by user yet
+    /// Overlap ratio for thumbs without their own `overlap_ratio`. Profiles
+    /// saved before thumbs had a ratio of their own lack it and keep using
+    /// `char_key_overlap_ratio`, which thumb pairs were judged by then.
+    #[serde(default)]
+    pub thumb_overlap_ratio: Option<f64>,
 
     #[serde(default)]
     pub char_key_continuous: bool,
     #[serde(default = "default_char_key_overlap_ratio")]
     pub char_key_overlap_ratio: f64,
+
+    #[serde(default)]
+    pub undo: UndoCfg,
+    #[serde(default)]
+    pub injection: InjectionCfg,
+    #[serde(default)]
+    pub sands: SandsCfg,
+    #[serde(default)]
+    pub dual_role_keys: Vec<DualRoleKeyCfg>,
+    /// Swaps on top of the layout's `[機能キー]` section.
+    #[serde(default)]
+    pub key_swaps: Vec<KeySwapCfg>,
+    #[serde(default)]
+    pub extra_thumbs: Vec<ExtraThumbCfg>,
+    #[serde(default)]
+    pub number_key: NumberKeyCfg,
+    #[serde(default)]
+    pub pinky_shift: PinkyShiftCfg,
+    /// Replacements applied in order to every output before it is typed.
+    #[serde(default)]
+    pub output_rules: Vec<OutputRule>,
+    /// Taken from the layout like `trigger_keys`, not saved.
+    #[serde(skip)]
+    pub overlap_overrides: OverlapOverrides,
+    /// How long a key with a `<long>` token is held alone before it types
+    /// that token.
+    #[serde(default = "default_long_press_ms")]
+    pub long_press_ms: u64,
+    /// Keys with a `<long>` token, taken from the layout, not saved.
+    #[serde(skip)]
+    pub long_press_keys: HashSet<ScKey>,
+}
+
+/// Text replacement on the output (e.g. `、` to `，`). An empty `to`
+/// deletes `from`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputRule {
+    pub from: String,
+    #[serde(default)]
+    pub to: String,
+}
+
+/// Applies `rules` to `text` one after another.
+pub fn apply_output_rules(rules: &[OutputRule], text: &str) -> String {
+    rules
+        .iter()
+        .filter(|rule| !rule.from.is_empty())
+        .fold(text.to_string(), |text, rule| {
+            text.replace(&rule.from, &rule.to)
+        })
 }
 
 fn default_chord_window_ms() -> u64 {
     200
 }
 
+fn default_deferred_enter_timeout_ms() -> u64 {
+    500
+}
+
+fn default_thumb_repeat_delay_ms() -> u64 {
+    300
+}
+
+fn default_double_tap_ms() -> u64 {
+    300
+}
+
+fn default_long_press_ms() -> u64 {
+    500
+}
+
 fn default_max_chord_size() -> usize {
     2
 }
@@ -240,8 +781,8 @@ fn default_char_key_repeat_unassigned() -> bool {
     true
 }
 
-fn default_thumb_shift_overlap_ratio() -> f64 {
-    0.35
+fn default_caps_lock_compensation() -> bool {
+    true
 }
 
 fn default_char_key_overlap_ratio() -> f64 {
@@ -253,6 +794,7 @@ impl Default for Profile {
         Self {
             chord_style: ChordStyle::TriggerKey,
             chord_window_ms: 200,
+            decision_mode: DecisionMode::OverlapRatio,
             max_chord_size: 2,
             adaptive_window: AdaptiveCfg { enabled: false },
             thumb_keys: None,
@@ -264,74 +806,168 @@ impl Default for Profile {
             char_key_repeat_unassigned: true,
 
             ime_mode: ImeMode::Auto,
-            suspend_key: SuspendKey::None,
+            caps_lock_compensation: true,
+            paste_grapheme_clusters: false,
+            paste_min_chars: None,
+            suspend_key: SuspendKey::default(),
+            panic_key: PanicKeyCfg::default(),
+            focus_change: FocusChangePolicy::Discard,
+            undefined_key: UndefinedKeyPolicy::Pass,
+            pass_through_keys: Vec::new(),
+            space_width: SpaceWidth::Ime,
+            deferred_enter_timeout_ms: 500,
+            thumb_repeat_delay_ms: default_thumb_repeat_delay_ms(),
+            double_tap_ms: default_double_tap_ms(),
+            prefix_shift_timeout_ms: 0,
+            ime_composition: CompositionPolicy::Ignore,
 
             thumb_left: ThumbSideConfig {
                 key: ThumbKeySelect::Muhenkan,
                 continuous: false,
                 single_press: ThumbShiftSinglePress::None,
+                double_tap: String::new(),
                 repeat: false,
+                overlap_ratio: None,
+                min_overlap_ms: None,
             },
             thumb_right: ThumbSideConfig {
                 key: ThumbKeySelect::Henkan,
                 continuous: false,
                 single_press: ThumbShiftSinglePress::None,
+                double_tap: String::new(),
                 repeat: false,
+                overlap_ratio: None,
+                min_overlap_ms: None,
             },
             extended_thumb1: ThumbSideConfig {
                 key: ThumbKeySelect::Extended1,
                 continuous: false,
                 single_press: ThumbShiftSinglePress::None,
+                double_tap: String::new(),
                 repeat: false,
+                overlap_ratio: None,
+                min_overlap_ms: None,
             },
             extended_thumb2: ThumbSideConfig {
                 key: ThumbKeySelect::Extended2,
                 continuous: false,
                 single_press: ThumbShiftSinglePress::None,
+                double_tap: String::new(),
                 repeat: false,
+                overlap_ratio: None,
+                min_overlap_ms: None,
             },
-            thumb_shift_overlap_ratio: 0.35,
+            thumb_overlap_ratio: None,
 
             char_key_continuous: false,
             char_key_overlap_ratio: 0.35,
+
+            undo: UndoCfg::default(),
+            injection: InjectionCfg::default(),
+            sands: SandsCfg::default(),
+            dual_role_keys: Vec::new(),
+            key_swaps: Vec::new(),
+            extra_thumbs: Vec::new(),
+            number_key: NumberKeyCfg::default(),
+            pinky_shift: PinkyShiftCfg::default(),
+            output_rules: Vec::new(),
+            overlap_overrides: OverlapOverrides::default(),
+            long_press_ms: default_long_press_ms(),
+            long_press_keys: HashSet::new(),
         }
     }
 }
 
 impl ThumbKeySelect {
+    const KEYS: [ThumbKeySelect; 25] = [
+        ThumbKeySelect::Esc,
+        ThumbKeySelect::Tab,
+        ThumbKeySelect::Muhenkan,
+        ThumbKeySelect::Space,
+        ThumbKeySelect::Henkan,
+        ThumbKeySelect::Enter,
+        ThumbKeySelect::BackSpace,
+        ThumbKeySelect::Delete,
+        ThumbKeySelect::Insert,
+        ThumbKeySelect::Up,
+        ThumbKeySelect::Left,
+        ThumbKeySelect::Right,
+        ThumbKeySelect::Down,
+        ThumbKeySelect::Home,
+        ThumbKeySelect::End,
+        ThumbKeySelect::PageUp,
+        ThumbKeySelect::PageDown,
+        ThumbKeySelect::LeftShift,
+        ThumbKeySelect::RightShift,
+        ThumbKeySelect::LeftCtrl,
+        ThumbKeySelect::RightCtrl,
+        ThumbKeySelect::Extended1,
+        ThumbKeySelect::Extended2,
+        ThumbKeySelect::Extended3,
+        ThumbKeySelect::Extended4,
+    ];
+
+    /// Reverse of [`ThumbKeySelect::to_sckey`].
+    pub fn from_sckey(key: ScKey) -> Option<ThumbKeySelect> {
+        Self::KEYS
+            .into_iter()
+            .find(|select| select.to_sckey() == Some(key))
+    }
+
     pub fn to_sckey(&self) -> Option<ScKey> {
+        crate::jis_map::function_key(self.key_name()?)
+    }
+
+    /// `[機能キー]` name of the key.
+    pub fn key_name(&self) -> Option<&'static str> {
         match self {
             ThumbKeySelect::None => None,
-            ThumbKeySelect::Esc => Some(ScKey::new(0x01, false)),
-            ThumbKeySelect::Tab => Some(ScKey::new(0x0F, false)),
-            ThumbKeySelect::Muhenkan => Some(ScKey::new(0x7B, false)),
-            ThumbKeySelect::Space => Some(ScKey::new(0x39, false)),
-            ThumbKeySelect::Henkan => Some(ScKey::new(0x79, false)),
-            ThumbKeySelect::Enter => Some(ScKey::new(0x1C, false)),
-            ThumbKeySelect::BackSpace => Some(ScKey::new(0x0E, false)),
-            ThumbKeySelect::Delete => Some(ScKey::new(0x53, true)),
-            ThumbKeySelect::Insert => Some(ScKey::new(0x52, true)),
-            ThumbKeySelect::Up => Some(ScKey::new(0x48, true)),
-            ThumbKeySelect::Left => Some(ScKey::new(0x4B, true)),
-            ThumbKeySelect::Right => Some(ScKey::new(0x4D, true)),
-            ThumbKeySelect::Down => Some(ScKey::new(0x50, true)),
-            ThumbKeySelect::Home => Some(ScKey::new(0x47, true)),
-            ThumbKeySelect::End => Some(ScKey::new(0x4F, true)),
-            ThumbKeySelect::PageUp => Some(ScKey::new(0x49, true)),
-            ThumbKeySelect::PageDown => Some(ScKey::new(0x51, true)),
-            ThumbKeySelect::LeftShift => Some(ScKey::new(0x2A, false)),
-            ThumbKeySelect::RightShift => Some(ScKey::new(0x36, false)),
-            ThumbKeySelect::LeftCtrl => Some(ScKey::new(0x1D, false)),
-            ThumbKeySelect::RightCtrl => Some(ScKey::new(0x1D, true)),
-            ThumbKeySelect::Extended1 => Some(ScKey::new(EXTENDED_KEY_1_SC, false)),
-            ThumbKeySelect::Extended2 => Some(ScKey::new(EXTENDED_KEY_2_SC, false)),
-            ThumbKeySelect::Extended3 => Some(ScKey::new(EXTENDED_KEY_3_SC, false)),
-            ThumbKeySelect::Extended4 => Some(ScKey::new(EXTENDED_KEY_4_SC, false)),
+            ThumbKeySelect::Esc => Some("Esc"),
+            ThumbKeySelect::Tab => Some("Tab"),
+            ThumbKeySelect::Muhenkan => Some("無変換"),
+            ThumbKeySelect::Space => Some("Space"),
+            ThumbKeySelect::Henkan => Some("変換"),
+            ThumbKeySelect::Enter => Some("Enter"),
+            ThumbKeySelect::BackSpace => Some("BackSpace"),
+            ThumbKeySelect::Delete => Some("Delete"),
+            ThumbKeySelect::Insert => Some("Insert"),
+            ThumbKeySelect::Up => Some("上"),
+            ThumbKeySelect::Left => Some("左"),
+            ThumbKeySelect::Right => Some("右"),
+            ThumbKeySelect::Down => Some("下"),
+            ThumbKeySelect::Home => Some("Home"),
+            ThumbKeySelect::End => Some("End"),
+            ThumbKeySelect::PageUp => Some("PageUp"),
+            ThumbKeySelect::PageDown => Some("PageDown"),
+            ThumbKeySelect::LeftShift => Some("左Shift"),
+            ThumbKeySelect::RightShift => Some("右Shift"),
+            ThumbKeySelect::LeftCtrl => Some("左Ctrl"),
+            ThumbKeySelect::RightCtrl => Some("右Ctrl"),
+            ThumbKeySelect::Extended1 => Some("拡張1"),
+            ThumbKeySelect::Extended2 => Some("拡張2"),
+            ThumbKeySelect::Extended3 => Some("拡張3"),
+            ThumbKeySelect::Extended4 => Some("拡張4"),
         }
     }
 }
 
 impl Profile {
+    /// Value of a `[section@flag]` condition in a layout file, or `None`
+    /// for an unknown flag.
+    pub fn layout_flag(&self, flag: &str) -> Option<bool> {
+        let ext1 = self.extended_thumb1.key != ThumbKeySelect::None;
+        let ext2 = self.extended_thumb2.key != ThumbKeySelect::None;
+        Some(match flag {
+            "拡張親指有効" => ext1 || ext2,
+            "拡張親指1有効" => ext1,
+            "拡張親指2有効" => ext2,
+            "連続シフト" => self.char_key_continuous,
+            "SandS" => self.sands.enabled,
+            "数字キーシフト" => self.chord_style == ChordStyle::NNumberKey,
+            _ => return None,
+        })
+    }
+
     pub fn update_thumb_keys(&mut self) {
         let mut left = HashSet::new();
         let mut right = HashSet::new();
@@ -350,12 +986,40 @@ impl Profile {
         if let Some(sck) = self.extended_thumb2.key.to_sckey() {
             ext2.insert(sck);
         }
+        if self.sands.enabled {
+            match self.sands.side {
+                SandsSide::Left => left.insert(SANDS_KEY),
+                SandsSide::Right => right.insert(SANDS_KEY),
+            };
+        }
+
+        let extra = self
+            .extra_thumbs
+            .iter()
+            .map(|cfg| cfg.keys.iter().copied().collect())
+            .collect();
+
+        let number = if self.chord_style == ChordStyle::NNumberKey {
+            // Number row 1–0
+            (0x02..=0x0B).map(|sc| ScKey::new(sc, false)).collect()
+        } else {
+            HashSet::new()
+        };
+
+        let shift = if self.pinky_shift.enabled {
+            [ScKey::new(0x2A, false), ScKey::new(0x36, false)].into()
+        } else {
+            HashSet::new()
+        };
 
         self.thumb_keys = Some(ThumbKeys {
             left,
             right,
             ext1,
             ext2,
+            extra,
+            number,
+            shift,
         });
     }
 }
@@ -371,6 +1035,15 @@ pub struct ChordState {
     pub used_modifiers: HashSet<ScKey>,
     // For Prefix Shift mode
     pub prefix_pending: Option<ScKey>,
+    /// When the `prefix_pending` thumb key was released.
+    pub prefix_released: Option<Instant>,
+    /// Thumb key with a double tap tapped last, and when it was released;
+    /// its single press waits for [`Profile::double_tap_ms`].
+    pub last_tap: Option<(ScKey, Instant)>,
+    /// Key typed as a long press and still held; its release types nothing.
+    pub long_pressed: Option<ScKey>,
+    /// When the chord window was last settled; deadlines up to it are spent.
+    pub window_flushed: Option<Instant>,
 }
 
 impl Default for ChordState {
@@ -384,6 +1057,10 @@ impl Default for ChordState {
             passed_keys: HashSet::new(),
             used_modifiers: HashSet::new(),
             prefix_pending: None,
+            prefix_released: None,
+            last_tap: None,
+            long_pressed: None,
+            window_flushed: None,
         }
     }
 }
@@ -395,6 +1072,9 @@ enum ModifierKind {
     ThumbRight,
     ThumbExt1,
     ThumbExt2,
+    ThumbExtra(usize),
+    NumberKey,
+    PinkyShift,
     CharShift,
 }
 
@@ -402,6 +1082,23 @@ impl ModifierKind {
     fn is_modifier(self) -> bool {
         !matches!(self, ModifierKind::None)
     }
+
+    fn is_thumb(self) -> bool {
+        matches!(
+            self,
+            ModifierKind::ThumbLeft
+                | ModifierKind::ThumbRight
+                | ModifierKind::ThumbExt1
+                | ModifierKind::ThumbExt2
+                | ModifierKind::ThumbExtra(_)
+        )
+    }
+
+    /// Thumb, number-row and pinky shifts select a section instead of a
+    /// sub-plane.
+    fn is_section_shift(self) -> bool {
+        self.is_thumb() || matches!(self, ModifierKind::NumberKey | ModifierKind::PinkyShift)
+    }
 }
 #[derive(Debug, Clone)]
 pub struct PendingKey {
@@ -411,7 +1108,8 @@ pub struct PendingKey {
     // kind_hint: PendingKindHint
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "plane")]
 pub enum LatchState {
     None,
     OneShot(PlaneTag),
@@ -439,12 +1137,19 @@ impl ChordEngine {
     }
 
     pub fn on_event(&mut self, event: KeyEvent) -> Vec<Decision> {
+        let mut output = Vec::new();
+        self.on_event_into(event, &mut output);
+        output
+    }
+
+    /// Like [`Self::on_event`], but appends to `output` so that a caller
+    /// can reuse its buffer.
+    pub fn on_event_into(&mut self, event: KeyEvent, output: &mut Vec<Decision>) {
         if event.injected {
-            return vec![];
+            return;
         }
 
         let now = event.t;
-        let mut output = Vec::new();
 
         // 0. Priority Handling Checklist
         match event.edge {
@@ -454,7 +1159,14 @@ impl ChordEngine {
                     self.state.passed_keys.remove(&event.key);
                     self.state.pressed.remove(&event.key);
                     self.state.down_ts.remove(&event.key);
-                    return vec![Decision::Passthrough(event.key, KeyEdge::Up)];
+                    output.push(Decision::Passthrough(event.key, KeyEdge::Up));
+                    return;
+                }
+                if self.state.long_pressed == Some(event.key) {
+                    self.state.long_pressed = None;
+                    self.state.pressed.remove(&event.key);
+                    self.state.down_ts.remove(&event.key);
+                    return;
                 }
             }
             KeyEdge::Down => {
@@ -469,7 +1181,7 @@ impl ChordEngine {
                     output.push(Decision::Passthrough(event.key, KeyEdge::Down));
 
                     // Since we handled Space, we return immediately with the sequence
-                    return output;
+                    return;
                 }
             }
         }
@@ -478,7 +1190,8 @@ impl ChordEngine {
         if let Some(ref targets) = self.profile.target_keys {
             if !targets.contains(&event.key) {
                 // Not in target list -> Pass through immediately
-                return vec![Decision::Passthrough(event.key, event.edge)];
+                output.push(Decision::Passthrough(event.key, event.edge));
+                return;
             }
         }
 
@@ -508,6 +1221,17 @@ impl ChordEngine {
                     self.state.used_modifiers.remove(&event.key);
                 }
 
+                // Any other key in between, or a press after the interval, keeps
+                // two taps from being a double tap: the first one types its
+                // single press now.
+                if let Some((key, released)) = self.state.last_tap {
+                    let interval = Duration::from_millis(self.profile.double_tap_ms);
+                    if key != event.key || now.saturating_duration_since(released) > interval {
+                        self.state.last_tap = None;
+                        self.thumb_single_press(key, released, output);
+                    }
+                }
+
                 // Handle Prefix Shift Logic
                 if let Some(prefix_thumb) = self.state.prefix_pending {
                     // If a valid key comes in (and it's not the thumb itself, though ScKey check assumes unique)
@@ -522,7 +1246,7 @@ impl ChordEngine {
                     // But we also need to consider if this key is a modifier?
                     // If the pressed key is "A", fine.
                     // We don't add to pending.
-                    return output;
+                    return;
                 }
 
                 // 2. Add to pending
@@ -560,9 +1284,11 @@ impl ChordEngine {
                 // 3. Flush Single Taps
                 if self.state.pending.len() == 1 {
                     let p = &self.state.pending[0];
-                    if p.t_up.is_some() {
+                    if let Some(t_up) = p.t_up {
                         // It's a lonely tap
                         let key = p.key;
+                        let t_down = p.t_down;
+                        let held = t_up.saturating_duration_since(t_down);
                         let mod_kind = self.modifier_kind(key);
 
                         self.state.pending.clear();
@@ -572,44 +1298,40 @@ impl ChordEngine {
                             ModifierKind::ThumbLeft
                             | ModifierKind::ThumbRight
                             | ModifierKind::ThumbExt1
-                            | ModifierKind::ThumbExt2 => {
+                            | ModifierKind::ThumbExt2
+                            | ModifierKind::ThumbExtra(_) => {
                                 if self.state.used_modifiers.contains(&key) {
                                     // Was used, so ignore single press
                                     self.state.used_modifiers.remove(&key);
+                                } else if self.is_sands_key(key) {
+                                    let timeout =
+                                        Duration::from_millis(self.profile.sands.tap_timeout_ms);
+                                    if held <= timeout {
+                                        output.push(Decision::KeyTap(key));
+                                    }
                                 } else {
-                                    let sp_setting = match mod_kind {
-                                        ModifierKind::ThumbLeft => {
-                                            self.profile.thumb_left.single_press
-                                        }
-                                        ModifierKind::ThumbRight => {
-                                            self.profile.thumb_right.single_press
-                                        }
-                                        ModifierKind::ThumbExt1 => {
-                                            self.profile.extended_thumb1.single_press
-                                        }
-                                        ModifierKind::ThumbExt2 => {
-                                            self.profile.extended_thumb2.single_press
-                                        }
-                                        _ => ThumbShiftSinglePress::None,
-                                    };
-
-                                    match sp_setting {
-                                        ThumbShiftSinglePress::None => {
-                                            // Disable single press (swallow)
-                                        }
-                                        ThumbShiftSinglePress::Enable => {
-                                            output.push(Decision::KeyTap(key));
-                                        }
-                                        ThumbShiftSinglePress::PrefixShift => {
-                                            self.state.prefix_pending = Some(key);
-                                        }
-                                        ThumbShiftSinglePress::SpaceKey => {
-                                            output.push(Decision::KeyTap(ScKey::new(0x39, false)));
+                                    let (_, double_tap) = self.thumb_tap_settings(mod_kind);
+                                    let interval =
+                                        Duration::from_millis(self.profile.double_tap_ms);
+                                    let second_tap = matches!(
+                                        self.state.last_tap.take(),
+                                        Some((last, released)) if last == key
+                                            && t_down.saturating_duration_since(released) <= interval
+                                    );
+                                    match double_tap {
+                                        Some(double_tap) if second_tap => {
+                                            output.push(Decision::KeyTap(double_tap));
                                         }
+                                        // The single press waits out the interval, as a
+                                        // second tap replaces it.
+                                        Some(_) => self.state.last_tap = Some((key, t_up)),
+                                        None => self.thumb_single_press(key, t_up, output),
                                     }
                                 }
                             }
-                            ModifierKind::CharShift => {
+                            ModifierKind::NumberKey
+                            | ModifierKind::PinkyShift
+                            | ModifierKind::CharShift => {
                                 if self.state.used_modifiers.contains(&key) {
                                     self.state.used_modifiers.remove(&key);
                                 } else {
@@ -624,53 +1346,187 @@ impl ChordEngine {
                 }
             }
         }
+    }
 
-        // Cycle flush to handle timeouts
-        // REMOVED: flush_expired logic to allow infinite window (overlap based only)
-        // output.extend(self.flush_expired(now));
+    /// Settles what the chord window decides once it has run out: pairs
+    /// left waiting for a third key, and in `FixedWindow` mode keys that
+    /// found no partner, which are typed on their own in press order.
+    /// Also types a key held alone past `long_press_ms` as a long press and
+    /// drops a prefix shift that waited past `prefix_shift_timeout_ms`,
+    /// after typing the single press of a thumb key not tapped twice.
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<Decision> {
+        let mut output = Vec::new();
+        if self
+            .double_tap_deadline()
+            .is_some_and(|deadline| now >= deadline)
+        {
+            if let Some((key, released)) = self.state.last_tap.take() {
+                self.thumb_single_press(key, released, &mut output);
+            }
+        }
+        if self
+            .prefix_deadline()
+            .is_some_and(|deadline| now >= deadline)
+        {
+            self.state.prefix_pending = None;
+        }
+        if self
+            .long_press_deadline()
+            .is_some_and(|deadline| now >= deadline)
+        {
+            let p = self.state.pending.remove(0);
+            self.state.long_pressed = Some(p.key);
+            output.push(Decision::LongPress(p.key));
+            return output;
+        }
+        if self.profile.decision_mode == DecisionMode::OverlapRatio {
+            return output;
+        }
+        self.state.window_flushed = Some(now);
+        output.extend(self.check_chords(now, None));
+        if self.profile.decision_mode != DecisionMode::FixedWindow {
+            return output;
+        }
 
+        let window = Duration::from_millis(self.profile.chord_window_ms);
+        let mut expired: Vec<PendingKey> = Vec::new();
+        let mut i = 0;
+        while i < self.state.pending.len() {
+            let p = &self.state.pending[i];
+            if !self.is_modifier_key(p.key)
+                && !self.awaits_long_press(p)
+                && now.saturating_duration_since(p.t_down) >= window
+            {
+                expired.push(self.state.pending.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        expired.sort_by_key(|p| p.t_down);
+        for p in expired {
+            output.push(Decision::KeyTap(p.key));
+            if !self.state.pressed.contains(&p.key) {
+                self.state.down_ts.remove(&p.key);
+            }
+        }
         output
     }
 
-    /* REMOVED flush_expired to disable time window
-    pub fn flush_expired(&mut self, now: Instant) -> Vec<Decision> {
-        // Simple flush: if > window, force KeyTap.
+    /// When [`Self::flush_expired`] next has something to settle. In
+    /// `OverlapRatio` mode, which waits for key releases instead, only long
+    /// presses and prefix shifts time out.
+    pub fn window_deadline(&self) -> Option<Instant> {
+        let timeouts = [
+            self.long_press_deadline(),
+            self.prefix_deadline(),
+            self.double_tap_deadline(),
+        ];
+        if self.profile.decision_mode == DecisionMode::OverlapRatio {
+            return timeouts.into_iter().flatten().min();
+        }
         let window = Duration::from_millis(self.profile.chord_window_ms);
-        let mut output = Vec::new();
+        let lone_keys = self
+            .state
+            .pending
+            .iter()
+            .filter(|p| {
+                self.profile.decision_mode == DecisionMode::FixedWindow
+                    && !self.is_modifier_key(p.key)
+                    && !self.awaits_long_press(p)
+            })
+            .map(|p| p.t_down + window);
+        let third_key_wait = self.third_key_window_start().map(|t_down| t_down + window);
+        lone_keys
+            .chain(third_key_wait)
+            .filter(|deadline| self.state.window_flushed.is_none_or(|t| *deadline > t))
+            .chain(timeouts.into_iter().flatten())
+            .min()
+    }
+
+    /// When a thumb key tapped once types its single press, unless it is
+    /// being pressed again.
+    fn double_tap_deadline(&self) -> Option<Instant> {
+        let (key, released) = self.state.last_tap?;
+        if self.state.pressed.contains(&key) {
+            return None;
+        }
+        Some(released + Duration::from_millis(self.profile.double_tap_ms))
+    }
 
-        // We use retain logic but we need to extract items.
-        // Identify expired keys first.
-        let mut expired_indices = Vec::new();
-        for (i, p) in self.state.pending.iter().enumerate() {
-            if now.duration_since(p.t_down) > window {
-                expired_indices.push(i);
+    /// Types the single press of thumb key `key`, tapped and released at
+    /// `released`.
+    fn thumb_single_press(&mut self, key: ScKey, released: Instant, output: &mut Vec<Decision>) {
+        let (single_press, _) = self.thumb_tap_settings(self.modifier_kind(key));
+        match single_press {
+            ThumbShiftSinglePress::None => {
+                // Disable single press (swallow)
+            }
+            ThumbShiftSinglePress::Enable => output.push(Decision::KeyTap(key)),
+            ThumbShiftSinglePress::PrefixShift => {
+                self.state.prefix_pending = Some(key);
+                self.state.prefix_released = Some(released);
+            }
+            ThumbShiftSinglePress::SpaceKey => {
+                output.push(Decision::KeyTap(ScKey::new(0x39, false)));
             }
         }
+    }
 
-        if expired_indices.is_empty() {
-            return output;
+    /// When a pending prefix shift is dropped unused.
+    fn prefix_deadline(&self) -> Option<Instant> {
+        self.state.prefix_pending?;
+        match self.profile.prefix_shift_timeout_ms {
+            0 => None,
+            ms => Some(self.state.prefix_released? + Duration::from_millis(ms)),
         }
+    }
 
-        // Process in reverse to keep indices valid
-        for &i in expired_indices.iter().rev() {
-            let p = self.state.pending.remove(i);
-            // It expired, so it's a Tap.
-            output.push(Decision::KeyTap(p.key));
+    /// True while `p` is held and may still turn into a long press.
+    fn awaits_long_press(&self, p: &PendingKey) -> bool {
+        p.t_up.is_none()
+            && self.profile.long_press_keys.contains(&p.key)
+            && !self.is_modifier_key(p.key)
+    }
 
-            // Clean up down_ts if it's not pressed
-            if !self.state.pressed.contains(&p.key) {
-                self.state.down_ts.remove(&p.key);
+    /// When the only pending key, held with no other, becomes a long press.
+    fn long_press_deadline(&self) -> Option<Instant> {
+        match self.state.pending.as_slice() {
+            [p] if self.awaits_long_press(p) => {
+                Some(p.t_down + Duration::from_millis(self.profile.long_press_ms))
             }
+            _ => None,
         }
+    }
 
-        // Restore order? Removing from Vec in reverse means we process B then A if both expired.
-        // This reverses output order relative to input order.
-        // Ideally we should process oldest first.
-        output.reverse();
+    /// True while `key` is held for a long press, waiting or typed; its
+    /// key repeats type nothing.
+    pub fn holds_for_long_press(&self, key: ScKey) -> bool {
+        self.state.long_pressed == Some(key)
+            || self
+                .state
+                .pending
+                .iter()
+                .any(|p| p.key == key && self.awaits_long_press(p))
+    }
 
-        output
+    /// Press time of the earlier of two held keys that chord within the
+    /// window while a third key may still join them.
+    fn third_key_window_start(&self) -> Option<Instant> {
+        if self.profile.max_chord_size < 3 {
+            return None;
+        }
+        let mut held: Vec<&PendingKey> = self
+            .state
+            .pending
+            .iter()
+            .filter(|p| p.t_up.is_none())
+            .collect();
+        held.sort_by_key(|p| p.t_down);
+        match held.as_slice() {
+            [p1, p2] if self.window_chord(p1, p2) == Some(true) => Some(p1.t_down),
+            _ => None,
+        }
     }
-    */
 
     pub fn flush_pending_with_cutoff(&mut self, now: Instant) -> Vec<Decision> {
         // Force-release pending keys at 'now' so chord ratio can be evaluated.
@@ -698,6 +1554,9 @@ impl ChordEngine {
 
     pub fn flush_all_pending(&mut self) -> Vec<Decision> {
         let mut output = Vec::new();
+        if let Some((key, released)) = self.state.last_tap.take() {
+            self.thumb_single_press(key, released, &mut output);
+        }
         // Drain all pending keys and output them as KeyTap
         let pending = std::mem::take(&mut self.state.pending);
 
@@ -750,41 +1609,50 @@ impl ChordEngine {
                         let p2 = &self.state.pending[idx2];
                         let p3 = &self.state.pending[idx3];
 
-                        // For 3-key chords, if two keys are both pressed (t_up=None),
-                        // their overlap is effectively 100%. We only need at least one
-                        // key in the triple to have been released (t_up=Some) so we can
-                        // evaluate the triple.
-                        let has_any_released =
-                            p1.t_up.is_some() || p2.t_up.is_some() || p3.t_up.is_some();
-                        if !has_any_released {
-                            // All three still held – wait for a release.
-                            break;
-                        }
+                        let in_window = [(p1, p2), (p2, p3), (p1, p3)]
+                            .iter()
+                            .all(|(pa, pb)| self.window_chord(pa, pb) == Some(true));
+                        let valid = if in_window
+                            || self.profile.decision_mode == DecisionMode::FixedWindow
+                        {
+                            in_window
+                        } else {
+                            // For 3-key chords, if two keys are both pressed (t_up=None),
+                            // their overlap is effectively 100%. We only need at least one
+                            // key in the triple to have been released (t_up=Some) so we can
+                            // evaluate the triple.
+                            let has_any_released =
+                                p1.t_up.is_some() || p2.t_up.is_some() || p3.t_up.is_some();
+                            if !has_any_released {
+                                // All three still held – wait for a release.
+                                break;
+                            }
 
-                        let ratio_or_both_pressed =
-                            |pa: &PendingKey, pb: &PendingKey| -> Option<f64> {
-                                if let Some(r) = self.pair_overlap_ratio(pa, pb, now, trigger) {
-                                    return Some(r);
-                                }
-                                // Both keys still pressed (t_up=None for both) means they
-                                // fully overlap – treat as 100%.
-                                if pa.t_up.is_none() && pb.t_up.is_none() {
-                                    return Some(1.0);
-                                }
-                                None
-                            };
+                            let ratio_or_both_pressed =
+                                |pa: &PendingKey, pb: &PendingKey| -> Option<f64> {
+                                    if let Some(r) = self.pair_overlap_ratio(pa, pb, now, trigger) {
+                                        return Some(r);
+                                    }
+                                    // Both keys still pressed (t_up=None for both) means they
+                                    // fully overlap – treat as 100%.
+                                    if pa.t_up.is_none() && pb.t_up.is_none() {
+                                        return Some(1.0);
+                                    }
+                                    None
+                                };
 
-                        let r12 = ratio_or_both_pressed(p1, p2);
-                        let r23 = ratio_or_both_pressed(p2, p3);
-                        let r13 = ratio_or_both_pressed(p1, p3);
+                            let r12 = ratio_or_both_pressed(p1, p2);
+                            let r23 = ratio_or_both_pressed(p2, p3);
+                            let r13 = ratio_or_both_pressed(p1, p3);
 
-                        if r12.is_none() || r23.is_none() || r13.is_none() {
-                            // Wait for release
-                            break;
-                        }
-                        let valid = r12.unwrap() >= self.profile.char_key_overlap_ratio
-                            && r23.unwrap() >= self.profile.char_key_overlap_ratio
-                            && r13.unwrap() >= self.profile.char_key_overlap_ratio;
+                            if r12.is_none() || r23.is_none() || r13.is_none() {
+                                // Wait for release
+                                break;
+                            }
+                            r12.unwrap() >= self.char_pair_ratio(p1.key, p2.key)
+                                && r23.unwrap() >= self.char_pair_ratio(p2.key, p3.key)
+                                && r13.unwrap() >= self.char_pair_ratio(p1.key, p3.key)
+                        };
 
                         if valid {
                             let k1 = p1.key;
@@ -864,45 +1732,53 @@ impl ChordEngine {
                 let p1 = &self.state.pending[idx1];
                 let p2 = &self.state.pending[idx2];
 
-                let ratio = match self.pair_overlap_ratio(p1, p2, now, trigger) {
-                    Some(ratio) => ratio,
-                    None => {
-                        if allow_three_key_chord {
-                            // Wait for more events when 3-key chord extension is enabled.
-                            return output;
-                        }
-                        // In 2-key mode, keep waiting by default. However, if p1 is already
-                        // released, p2 is still held, and a later key exists, p1 may block
-                        // chronological output ordering. If p1 can no longer reach the overlap
-                        // threshold against p2, flush p1 now.
-                        let has_later_pending = ordered_indices
-                            .iter()
-                            .skip(oj + 1)
-                            .any(|idx| !consumed_indices[*idx] && !flushed_indices[*idx]);
-
-                        if has_later_pending && p1.t_up.is_some() && p2.t_up.is_none() {
-                            if let Some(max_ratio_now) =
-                                Self::max_overlap_ratio_if_second_released_now(p1, p2, now)
-                            {
-                                if max_ratio_now < self.profile.char_key_overlap_ratio {
-                                    flushed_indices[idx1] = true;
-
-                                    let kind1 = self.modifier_kind(p1.key);
-                                    let suppress_p1_tap = kind1.is_modifier()
-                                        && self.modifier_is_continuous(kind1)
-                                        && self.state.used_modifiers.contains(&p1.key);
-
-                                    if !suppress_p1_tap {
-                                        output.push(Decision::KeyTap(p1.key));
+                let in_window = self.window_chord(p1, p2) == Some(true);
+                let valid_overlap =
+                    if in_window || self.profile.decision_mode == DecisionMode::FixedWindow {
+                        in_window
+                    } else {
+                        let ratio = match self.pair_overlap_ratio(p1, p2, now, trigger) {
+                            Some(ratio) => ratio,
+                            None => {
+                                if allow_three_key_chord {
+                                    // Wait for more events when 3-key chord extension is enabled.
+                                    return output;
+                                }
+                                // In 2-key mode, keep waiting by default. However, if p1 is already
+                                // released, p2 is still held, and a later key exists, p1 may block
+                                // chronological output ordering. If p1 can no longer reach the overlap
+                                // threshold against p2, flush p1 now.
+                                let has_later_pending = ordered_indices
+                                    .iter()
+                                    .skip(oj + 1)
+                                    .any(|idx| !consumed_indices[*idx] && !flushed_indices[*idx]);
+
+                                if has_later_pending && p1.t_up.is_some() && p2.t_up.is_none() {
+                                    if let Some(max_ratio_now) =
+                                        Self::max_overlap_ratio_if_second_released_now(p1, p2, now)
+                                    {
+                                        let (threshold, _) = self.pair_thresholds(p1.key, p2.key);
+                                        if max_ratio_now < threshold {
+                                            flushed_indices[idx1] = true;
+
+                                            let kind1 = self.modifier_kind(p1.key);
+                                            let suppress_p1_tap = kind1.is_modifier()
+                                                && self.modifier_is_continuous(kind1)
+                                                && self.state.used_modifiers.contains(&p1.key);
+
+                                            if !suppress_p1_tap {
+                                                output.push(Decision::KeyTap(p1.key));
+                                            }
+                                        }
                                     }
                                 }
+                                break;
                             }
-                        }
-                        break;
-                    }
-                };
+                        };
 
-                let valid_overlap = ratio >= self.profile.char_key_overlap_ratio;
+                        let (threshold, min_overlap) = self.pair_thresholds(p1.key, p2.key);
+                        ratio >= threshold && Self::overlap_duration_at(p1, p2, now) >= min_overlap
+                    };
 
                 if valid_overlap {
                     let has_later_pending = ordered_indices
@@ -935,7 +1811,17 @@ impl ChordEngine {
                         break;
                     }
 
-                    if allow_three_key_chord {
+                    if allow_three_key_chord && in_window {
+                        // A third key may still join while the window is open.
+                        let window = Duration::from_millis(self.profile.chord_window_ms);
+                        if !has_later_pending
+                            && p1.t_up.is_none()
+                            && p2.t_up.is_none()
+                            && now.saturating_duration_since(p1.t_down) < window
+                        {
+                            return output;
+                        }
+                    } else if allow_three_key_chord {
                         // EXTENSION CHECK:
                         // Check if p2 overlaps with any later key p3 that is still unresolved (None).
                         // If so, we should wait to see if it forms a 3-key chord.
@@ -1026,6 +1912,29 @@ impl ChordEngine {
         output
     }
 
+    /// Whether the later of two keys went down within the chord window
+    /// while the earlier was held; `None` in `OverlapRatio` mode. A held
+    /// continuous shift counts for as long as it is held.
+    fn window_chord(&self, p1: &PendingKey, p2: &PendingKey) -> Option<bool> {
+        if self.profile.decision_mode == DecisionMode::OverlapRatio {
+            return None;
+        }
+        let (first, second) = if p1.t_down <= p2.t_down {
+            (p1, p2)
+        } else {
+            (p2, p1)
+        };
+        if first.t_up.is_some_and(|up| up <= second.t_down) {
+            return Some(false);
+        }
+        let window = Duration::from_millis(self.profile.chord_window_ms);
+        let kind = self.modifier_kind(first.key);
+        Some(
+            second.t_down.duration_since(first.t_down) <= window
+                || (kind.is_modifier() && self.modifier_is_continuous(kind)),
+        )
+    }
+
     fn pair_overlap_ratio(
         &self,
         p1: &PendingKey,
@@ -1060,19 +1969,7 @@ impl ChordEngine {
                     (p1_end, 0.0)
                 }
             } else {
-                let is_char_pair = !matches!(
-                    kind1,
-                    ModifierKind::ThumbLeft
-                        | ModifierKind::ThumbRight
-                        | ModifierKind::ThumbExt1
-                        | ModifierKind::ThumbExt2
-                ) && !matches!(
-                    kind2,
-                    ModifierKind::ThumbLeft
-                        | ModifierKind::ThumbRight
-                        | ModifierKind::ThumbExt1
-                        | ModifierKind::ThumbExt2
-                );
+                let is_char_pair = !kind1.is_section_shift() && !kind2.is_section_shift();
                 let third_key_down = matches!(
                     trigger,
                     Some((k, KeyEdge::Down)) if k != p1.key && k != p2.key
@@ -1131,6 +2028,70 @@ impl ChordEngine {
         Some(overlap_dur.as_secs_f64() / p2_dur.as_secs_f64())
     }
 
+    /// Overlap of two pending keys, treating still-held keys as released at `now`.
+    fn overlap_duration_at(p1: &PendingKey, p2: &PendingKey, now: Instant) -> Duration {
+        let overlap_start = p1.t_down.max(p2.t_down);
+        let overlap_end = p1.t_up.unwrap_or(now).min(p2.t_up.unwrap_or(now));
+        overlap_end.saturating_duration_since(overlap_start)
+    }
+
+    /// Overlap ratio and minimum overlap a pair must reach to form a chord.
+    /// A `[タイミング]` ratio for the pair comes first; otherwise pairs
+    /// involving a thumb or number-row shift use that key's settings.
+    pub fn pair_thresholds(&self, k1: ScKey, k2: ScKey) -> (f64, Duration) {
+        let (ratio, min_overlap) = self.setting_thresholds(k1, k2);
+        let ratio = self
+            .profile
+            .overlap_overrides
+            .ratio(k1, k2)
+            .unwrap_or(ratio);
+        (ratio, min_overlap)
+    }
+
+    /// Character-key overlap ratio for a pair, after `[タイミング]`.
+    fn char_pair_ratio(&self, k1: ScKey, k2: ScKey) -> f64 {
+        self.profile
+            .overlap_overrides
+            .ratio(k1, k2)
+            .unwrap_or(self.profile.char_key_overlap_ratio)
+    }
+
+    fn setting_thresholds(&self, k1: ScKey, k2: ScKey) -> (f64, Duration) {
+        let kind1 = self.modifier_kind(k1);
+        let kind = if kind1.is_section_shift() {
+            kind1
+        } else {
+            self.modifier_kind(k2)
+        };
+        if kind == ModifierKind::NumberKey {
+            let cfg = &self.profile.number_key;
+            return (cfg.overlap_ratio, Duration::from_millis(cfg.min_overlap_ms));
+        }
+        if kind == ModifierKind::PinkyShift {
+            let cfg = &self.profile.pinky_shift;
+            return (cfg.overlap_ratio, Duration::from_millis(cfg.min_overlap_ms));
+        }
+        if !kind.is_thumb() {
+            return (self.profile.char_key_overlap_ratio, Duration::ZERO);
+        }
+
+        let side = match kind {
+            ModifierKind::ThumbLeft => Some(&self.profile.thumb_left),
+            ModifierKind::ThumbRight => Some(&self.profile.thumb_right),
+            ModifierKind::ThumbExt1 => Some(&self.profile.extended_thumb1),
+            ModifierKind::ThumbExt2 => Some(&self.profile.extended_thumb2),
+            _ => None,
+        };
+        let ratio = side
+            .and_then(|cfg| cfg.overlap_ratio)
+            .or(self.profile.thumb_overlap_ratio)
+            .unwrap_or(self.profile.char_key_overlap_ratio);
+        let min_overlap = side
+            .and_then(|cfg| cfg.min_overlap_ms)
+            .map_or(Duration::ZERO, Duration::from_millis);
+        (ratio, min_overlap)
+    }
+
     fn pair_overlap_duration(p1: &PendingKey, p2: &PendingKey) -> Option<Duration> {
         let p1_end = p1.t_up?;
         let p2_end = p2.t_up?;
@@ -1162,6 +2123,15 @@ impl ChordEngine {
             if tk.ext2.contains(&key) {
                 return ModifierKind::ThumbExt2;
             }
+            if let Some(i) = tk.extra_index(&key) {
+                return ModifierKind::ThumbExtra(i);
+            }
+            if tk.number.contains(&key) {
+                return ModifierKind::NumberKey;
+            }
+            if tk.shift.contains(&key) {
+                return ModifierKind::PinkyShift;
+            }
         }
 
         if self.profile.trigger_keys.contains_key(&key) {
@@ -1177,15 +2147,69 @@ impl ChordEngine {
             ModifierKind::ThumbRight => self.profile.thumb_right.continuous,
             ModifierKind::ThumbExt1 => self.profile.extended_thumb1.continuous,
             ModifierKind::ThumbExt2 => self.profile.extended_thumb2.continuous,
+            ModifierKind::ThumbExtra(i) => self
+                .profile
+                .extra_thumbs
+                .get(i)
+                .is_some_and(|cfg| cfg.continuous),
+            ModifierKind::NumberKey => self.profile.number_key.continuous,
+            ModifierKind::PinkyShift => self.profile.pinky_shift.continuous,
             ModifierKind::CharShift => self.profile.char_key_continuous,
             ModifierKind::None => false,
         }
     }
 
-    fn is_modifier_key(&self, key: ScKey) -> bool {
+    /// Single press setting and double tap key of a thumb shift.
+    fn thumb_tap_settings(&self, kind: ModifierKind) -> (ThumbShiftSinglePress, Option<ScKey>) {
+        let (single_press, double_tap) = match kind {
+            ModifierKind::ThumbLeft => (
+                self.profile.thumb_left.single_press,
+                &self.profile.thumb_left.double_tap,
+            ),
+            ModifierKind::ThumbRight => (
+                self.profile.thumb_right.single_press,
+                &self.profile.thumb_right.double_tap,
+            ),
+            ModifierKind::ThumbExt1 => (
+                self.profile.extended_thumb1.single_press,
+                &self.profile.extended_thumb1.double_tap,
+            ),
+            ModifierKind::ThumbExt2 => (
+                self.profile.extended_thumb2.single_press,
+                &self.profile.extended_thumb2.double_tap,
+            ),
+            ModifierKind::ThumbExtra(i) => match self.profile.extra_thumbs.get(i) {
+                Some(cfg) => (cfg.single_press, &cfg.double_tap),
+                None => return (ThumbShiftSinglePress::None, None),
+            },
+            _ => return (ThumbShiftSinglePress::None, None),
+        };
+        (single_press, function_key(double_tap))
+    }
+
+    /// True if `key` acts as a shift (thumb, number-row, pinky or character
+    /// shift).
+    pub fn is_modifier_key(&self, key: ScKey) -> bool {
         self.modifier_kind(key).is_modifier()
     }
 
+    /// Settings of the thumb key `key` stands for, if it is one of the
+    /// four configurable thumb keys.
+    pub fn thumb_side_config(&self, key: ScKey) -> Option<&ThumbSideConfig> {
+        match self.modifier_kind(key) {
+            ModifierKind::ThumbLeft => Some(&self.profile.thumb_left),
+            ModifierKind::ThumbRight => Some(&self.profile.thumb_right),
+            ModifierKind::ThumbExt1 => Some(&self.profile.extended_thumb1),
+            ModifierKind::ThumbExt2 => Some(&self.profile.extended_thumb2),
+            _ => None,
+        }
+    }
+
+    /// True if `key` is Space acting as a SandS thumb shift.
+    pub fn is_sands_key(&self, key: ScKey) -> bool {
+        self.profile.sands.enabled && key == SANDS_KEY && self.is_modifier_key(key)
+    }
+
     // Tests will be added later
 }
 
@@ -1426,6 +2450,104 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_fixed_window_decides_by_press_interval() {
+        let profile = Profile {
+            decision_mode: DecisionMode::FixedWindow,
+            chord_window_ms: 100,
+            ..Profile::default()
+        };
+        let mut engine = ChordEngine::new(profile);
+        let t0 = Instant::now();
+        let k_a = make_key(0x1E);
+        let k_b = make_key(0x30);
+        let k_c = make_key(0x2E);
+        let k_d = make_key(0x20);
+
+        // Within the window: a chord as soon as the second key goes down.
+        engine.on_event(make_event(k_a, KeyEdge::Down, t0));
+        let res = engine.on_event(make_event(
+            k_b,
+            KeyEdge::Down,
+            t0 + Duration::from_millis(50),
+        ));
+        assert_single_chord(&res, k_a, k_b);
+        engine.on_event(make_event(k_a, KeyEdge::Up, t0 + Duration::from_millis(80)));
+        engine.on_event(make_event(k_b, KeyEdge::Up, t0 + Duration::from_millis(90)));
+
+        // Outside it the earlier key is typed alone, however long they overlap.
+        let t1 = t0 + Duration::from_millis(500);
+        engine.on_event(make_event(k_c, KeyEdge::Down, t1));
+        let res = engine.on_event(make_event(
+            k_d,
+            KeyEdge::Down,
+            t1 + Duration::from_millis(150),
+        ));
+        assert_eq!(res, vec![Decision::KeyTap(k_c)]);
+    }
+
+    #[test]
+    fn test_flush_expired_taps_keys_left_alone_for_the_window() {
+        let profile = Profile {
+            decision_mode: DecisionMode::FixedWindow,
+            chord_window_ms: 200,
+            ..Profile::default()
+        };
+        let mut engine = ChordEngine::new(profile);
+        let t0 = Instant::now();
+        let k_a = make_key(0x1E);
+
+        engine.on_event(make_event(k_a, KeyEdge::Down, t0));
+        let deadline = t0 + Duration::from_millis(200);
+        assert_eq!(engine.window_deadline(), Some(deadline));
+        assert!(engine
+            .flush_expired(t0 + Duration::from_millis(100))
+            .is_empty());
+
+        assert_eq!(engine.flush_expired(deadline), vec![Decision::KeyTap(k_a)]);
+        assert_eq!(engine.window_deadline(), None);
+        // Already typed: the release adds nothing.
+        assert!(engine
+            .on_event(make_event(
+                k_a,
+                KeyEdge::Up,
+                deadline + Duration::from_millis(50)
+            ))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_hybrid_falls_back_to_ratio_outside_the_window() {
+        let profile = Profile {
+            decision_mode: DecisionMode::Hybrid,
+            chord_window_ms: 100,
+            ..Profile::default()
+        };
+        let mut engine = ChordEngine::new(profile);
+        let t0 = Instant::now();
+        let k_a = make_key(0x1E);
+        let k_b = make_key(0x30);
+
+        // No timeout: a lone key waits for its release as in ratio mode.
+        engine.on_event(make_event(k_a, KeyEdge::Down, t0));
+        assert_eq!(engine.window_deadline(), None);
+
+        let t_b = t0 + Duration::from_millis(300);
+        assert!(engine
+            .on_event(make_event(k_b, KeyEdge::Down, t_b))
+            .is_empty());
+        let res = engine.on_event(make_event(
+            k_b,
+            KeyEdge::Up,
+            t_b + Duration::from_millis(100),
+        ));
+        assert_single_chord(&res, k_a, k_b);
+
+        // Ratio mode never times out.
+        let engine = ChordEngine::new(Profile::default());
+        assert_eq!(engine.window_deadline(), None);
+    }
+
     #[test]
     fn test_char_continuous_case1_ab_then_ac() {
         let t0 = Instant::now();
@@ -1792,6 +2914,46 @@ mod tests {
         assert_eq!(res, vec![Decision::KeyTap(k_c)]);
     }
 
+    #[test]
+    fn test_thumb_double_tap_types_its_key() {
+        let t0 = Instant::now();
+        let thumb = make_key(0x7B);
+        let ms = |n| t0 + Duration::from_millis(n);
+
+        let mut profile = Profile::default();
+        profile.thumb_left.key = ThumbKeySelect::Muhenkan;
+        profile.thumb_left.double_tap = "Esc".to_string();
+        profile.thumb_left.single_press = ThumbShiftSinglePress::Enable;
+        profile.update_thumb_keys();
+        let mut engine = ChordEngine::new(profile);
+        let tap = |engine: &mut ChordEngine, down, up| {
+            let mut out = engine.on_event(make_event(thumb, KeyEdge::Down, ms(down)));
+            out.extend(engine.on_event(make_event(thumb, KeyEdge::Up, ms(up))));
+            out
+        };
+
+        assert!(tap(&mut engine, 0, 20).is_empty());
+        assert_eq!(
+            tap(&mut engine, 100, 120),
+            vec![Decision::KeyTap(make_key(0x01))]
+        );
+        // A third tap starts over; a tap after the interval types the
+        // single press held back for the one before.
+        assert!(tap(&mut engine, 200, 220).is_empty());
+        assert_eq!(tap(&mut engine, 600, 620), vec![Decision::KeyTap(thumb)]);
+        assert_eq!(
+            tap(&mut engine, 700, 720),
+            vec![Decision::KeyTap(make_key(0x01))]
+        );
+        assert!(tap(&mut engine, 800, 820).is_empty());
+        assert_eq!(engine.window_deadline(), Some(ms(1120)));
+        assert_eq!(
+            engine.flush_expired(ms(1120)),
+            vec![Decision::KeyTap(thumb)]
+        );
+        assert_eq!(engine.window_deadline(), None);
+    }
+
     #[test]
     fn test_thumb_prefix_shift_single_press_applies_every_time() {
         let t0 = Instant::now();
@@ -1853,6 +3015,39 @@ mod tests {
         assert_eq!(res, vec![Decision::Chord(vec![thumb, k_b])]);
     }
 
+    #[test]
+    fn test_prefix_shift_expires_after_its_timeout() {
+        let t0 = Instant::now();
+        let thumb = make_key(0x7B);
+        let k_a = make_key(0x1E);
+
+        let mut profile = Profile::default();
+        profile.thumb_left.key = ThumbKeySelect::Muhenkan;
+        profile.thumb_left.single_press = ThumbShiftSinglePress::PrefixShift;
+        profile.prefix_shift_timeout_ms = 1000;
+        profile.update_thumb_keys();
+        let mut engine = ChordEngine::new(profile);
+
+        engine.on_event(make_event(thumb, KeyEdge::Down, t0));
+        engine.on_event(make_event(
+            thumb,
+            KeyEdge::Up,
+            t0 + Duration::from_millis(10),
+        ));
+        let deadline = t0 + Duration::from_millis(1010);
+        assert_eq!(engine.window_deadline(), Some(deadline));
+        assert!(engine.flush_expired(deadline).is_empty());
+        assert_eq!(engine.state.prefix_pending, None);
+        assert_eq!(engine.window_deadline(), None);
+
+        let res = engine.on_event(make_event(
+            k_a,
+            KeyEdge::Down,
+            deadline + Duration::from_millis(10),
+        ));
+        assert!(!res.contains(&Decision::Chord(vec![thumb, k_a])));
+    }
+
     fn three_key_continuous_profile(threshold: f64, modifiers: &[ScKey]) -> Profile {
         let mut profile = Profile::default();
         profile.char_key_continuous = true;
@@ -2020,4 +3215,97 @@ mod tests {
         ));
         assert_single_three_key_chord(&res, k_d, k_f, k_l);
     }
+
+    #[test]
+    fn test_per_thumb_overlap_ratio_and_min_overlap() {
+        let k_a = make_key(0x1E);
+        let left = make_key(0x7B);
+        let right = make_key(0x79);
+
+        // A: 0-100ms, thumb: 50-150ms -> 50% overlap of the thumb stroke, 50ms absolute.
+        let run = |profile: &Profile, thumb: ScKey| {
+            let t0 = Instant::now();
+            let mut engine = ChordEngine::new(profile.clone());
+            let mut out = Vec::new();
+            out.extend(engine.on_event(make_event(k_a, KeyEdge::Down, t0)));
+            out.extend(engine.on_event(make_event(
+                thumb,
+                KeyEdge::Down,
+                t0 + Duration::from_millis(50),
+            )));
+            out.extend(engine.on_event(make_event(
+                k_a,
+                KeyEdge::Up,
+                t0 + Duration::from_millis(100),
+            )));
+            out.extend(engine.on_event(make_event(
+                thumb,
+                KeyEdge::Up,
+                t0 + Duration::from_millis(150),
+            )));
+            out.iter().any(|d| matches!(d, Decision::Chord(_)))
+        };
+
+        let mut profile = Profile::default();
+        profile.thumb_left.overlap_ratio = Some(0.8);
+        profile.update_thumb_keys();
+
+        assert!(!run(&profile, left), "left thumb requires 80% overlap");
+        assert!(run(&profile, right), "right thumb uses the global ratio");
+
+        profile.thumb_right.min_overlap_ms = Some(60);
+        assert!(!run(&profile, right), "right thumb requires 60ms overlap");
+    }
+
+    #[test]
+    fn test_thumb_pair_threshold_of_old_profiles_follows_char_ratio() {
+        let mut saved = serde_json::to_value(Profile::default()).unwrap();
+        let fields = saved.as_object_mut().unwrap();
+        fields.remove("thumb_overlap_ratio");
+        fields.insert("thumb_shift_overlap_ratio".into(), 0.2.into());
+        fields.insert("char_key_overlap_ratio".into(), 0.6.into());
+        let mut profile: Profile = serde_json::from_value(saved).unwrap();
+        profile.update_thumb_keys();
+        let k_a = make_key(0x1E);
+        let left = make_key(0x7B);
+
+        let engine = ChordEngine::new(profile.clone());
+        assert_eq!(engine.pair_thresholds(k_a, left), (0.6, Duration::ZERO));
+
+        profile.thumb_overlap_ratio = Some(0.4);
+        let engine = ChordEngine::new(profile);
+        assert_eq!(engine.pair_thresholds(left, k_a), (0.4, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_thumb_key_select_from_sckey_round_trips() {
+        for select in ThumbKeySelect::KEYS {
+            let key = select.to_sckey().unwrap();
+            assert_eq!(ThumbKeySelect::from_sckey(key), Some(select));
+        }
+        assert_eq!(ThumbKeySelect::from_sckey(ScKey::new(0x1E, false)), None);
+    }
+
+    #[test]
+    fn test_profile_without_panic_key_enables_default_combo() {
+        let profile: Profile = serde_json::from_str("{}").unwrap();
+        assert_eq!(profile.panic_key, PanicKeyCfg::default());
+        assert!(profile.panic_key.enabled);
+        assert_eq!(profile.panic_key.key.vk(), 0x7B);
+    }
+
+    #[test]
+    fn test_suspend_key_loads_old_choices_and_combos() {
+        let load = |json: &str| -> SuspendKey { serde_json::from_str(json).unwrap() };
+        assert_eq!(load(r#""None""#), SuspendKey::default());
+        assert_eq!(
+            load(r#""RightControl""#),
+            SuspendKey::new(ScKey::new(0x1D, true))
+        );
+        let combo = SuspendKey {
+            ctrl: true,
+            ..SuspendKey::new(ScKey::new(0x64, false))
+        };
+        assert_eq!(load(&serde_json::to_string(&combo).unwrap()), combo);
+    }
 }
@@ -0,0 +1,124 @@
+//! Chord tester: while it runs, the engine types nothing and reports what
+//! each chord would have typed, with its timings, so that chords can be
+//! tried out safely in the settings window.
+
+use crate::trace::{TraceEvent, TraceKind};
+use crate::types::ScKey;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Output the engine decided on while testing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChordTestEvent {
+    pub kind: TraceKind,
+    /// Readable form of the keys (e.g. "K+D").
+    pub label: String,
+    /// Plain text of the output, or `None` for non-text tokens.
+    pub output: Option<String>,
+    /// Section the output was looked up in.
+    pub section: Option<String>,
+    /// Time since the test began.
+    pub at_ms: u64,
+    /// Press of each key after the first key's press, in key order.
+    pub press_offsets_ms: Vec<u64>,
+    /// Time from the first key's press to the decision.
+    pub decided_after_ms: u64,
+}
+
+/// Time without a key press after which a chord test ends by itself, so
+/// that a test left running does not keep swallowing keys.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// State of a running chord test.
+#[derive(Debug)]
+pub struct ChordTest {
+    started: Instant,
+    last_press: Instant,
+    presses: HashMap<ScKey, Instant>,
+}
+
+impl ChordTest {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            last_press: now,
+            presses: HashMap::new(),
+        }
+    }
+
+    /// Notes a key press; repeats keep the time of the first press.
+    pub fn key_down(&mut self, key: ScKey, now: Instant) {
+        self.last_press = now;
+        self.presses.entry(key).or_insert(now);
+    }
+
+    /// When the test ends unless a key is pressed before.
+    pub fn idle_deadline(&self) -> Instant {
+        self.last_press + IDLE_TIMEOUT
+    }
+
+    pub fn key_up(&mut self, key: ScKey) {
+        self.presses.remove(&key);
+    }
+
+    /// Adds the timings of the keys of `event` to it.
+    pub fn event(
+        &self,
+        event: &TraceEvent,
+        section: Option<String>,
+        now: Instant,
+    ) -> ChordTestEvent {
+        let presses: Vec<Instant> = event
+            .keys
+            .iter()
+            .map(|key| self.presses.get(key).copied().unwrap_or(now))
+            .collect();
+        let first = presses.iter().min().copied().unwrap_or(now);
+        ChordTestEvent {
+            kind: event.kind,
+            label: event.label.clone(),
+            output: event.output.clone(),
+            section,
+            at_ms: millis(now.saturating_duration_since(self.started)),
+            press_offsets_ms: presses
+                .iter()
+                .map(|&press| millis(press.saturating_duration_since(first)))
+                .collect(),
+            decided_after_ms: millis(now.saturating_duration_since(first)),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Token;
+
+    #[test]
+    fn test_event_reports_press_offsets_and_decision_time() {
+        let start = Instant::now();
+        let k = ScKey::new(0x25, false);
+        let d = ScKey::new(0x20, false);
+        let mut test = ChordTest::new(start);
+        test.key_down(k, start + Duration::from_millis(10));
+        test.key_down(d, start + Duration::from_millis(40));
+        test.key_down(k, start + Duration::from_millis(60));
+
+        let trace = TraceEvent::new(TraceKind::Chord, &[d, k], &Token::ImeChar("が".to_string()));
+        let now = start + Duration::from_millis(90);
+        let event = test.event(&trace, Some("ローマ字シフト無し".to_string()), now);
+        assert_eq!(event.output.as_deref(), Some("が"));
+        assert_eq!(event.at_ms, 90);
+        assert_eq!(event.press_offsets_ms, vec![30, 0]);
+        assert_eq!(event.decided_after_ms, 80);
+        assert_eq!(
+            test.idle_deadline(),
+            start + Duration::from_millis(60) + IDLE_TIMEOUT
+        );
+    }
+}
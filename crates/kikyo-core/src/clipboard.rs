@@ -0,0 +1,132 @@
+#[cfg(feature = "os-windows")]
+use std::thread;
+#[cfg(feature = "os-windows")]
+use std::time::Duration;
+#[cfg(feature = "os-windows")]
+use windows::Win32::Foundation::{HANDLE, HGLOBAL, HWND};
+#[cfg(feature = "os-windows")]
+use windows::Win32::System::DataExchange::{
+    CloseClipboard, EmptyClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard,
+    SetClipboardData,
+};
+#[cfg(feature = "os-windows")]
+use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+#[cfg(feature = "os-windows")]
+use windows::Win32::System::Ole::CF_UNICODETEXT;
+
+/// Time the target application gets to read the clipboard before the
+/// previous contents are put back.
+#[cfg(feature = "os-windows")]
+const PASTE_SETTLE_MS: u64 = 80;
+#[cfg(feature = "os-windows")]
+const OPEN_RETRIES: u32 = 5;
+
+/// Pastes `text` with Ctrl+V through the clipboard. Only the previous text
+/// contents are restored; other formats on the clipboard are lost.
+#[cfg(feature = "os-windows")]
+pub fn paste_text(text: &str) -> anyhow::Result<()> {
+    let _restore = RestoreText(read_text()?);
+    write_text(text)?;
+    // Ctrl, V.
+    let pasted = [(0x1D, false), (0x2F, false), (0x2F, true), (0x1D, true)]
+        .into_iter()
+        .try_for_each(|(sc, up)| crate::keyboard_hook::inject_scancode(sc, false, up));
+    match pasted {
+        Ok(()) => thread::sleep(Duration::from_millis(PASTE_SETTLE_MS)),
+        Err(_) => {
+            let _ = crate::keyboard_hook::inject_scancode(0x1D, false, true);
+        }
+    }
+    pasted
+}
+
+/// Puts the previous text back on the clipboard when dropped, however the
+/// paste ended.
+#[cfg(feature = "os-windows")]
+struct RestoreText(Option<String>);
+
+#[cfg(feature = "os-windows")]
+impl Drop for RestoreText {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            if let Err(e) = write_text(&previous) {
+                tracing::warn!("Cannot restore the clipboard after pasting: {e}");
+            }
+        }
+    }
+}
+
+/// Another process may hold the clipboard briefly; retry a few times.
+#[cfg(feature = "os-windows")]
+fn open() -> anyhow::Result<ClipboardGuard> {
+    let mut attempt = 0;
+    loop {
+        match unsafe { OpenClipboard(HWND(0)) } {
+            Ok(()) => return Ok(ClipboardGuard),
+            Err(e) if attempt >= OPEN_RETRIES => return Err(e.into()),
+            Err(_) => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "os-windows")]
+struct ClipboardGuard;
+
+#[cfg(feature = "os-windows")]
+impl Drop for ClipboardGuard {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseClipboard() };
+    }
+}
+
+/// The text on the clipboard, if any.
+#[cfg(feature = "os-windows")]
+pub fn read_text() -> anyhow::Result<Option<String>> {
+    let _guard = open()?;
+    unsafe {
+        if IsClipboardFormatAvailable(CF_UNICODETEXT.0 as u32).is_err() {
+            return Ok(None);
+        }
+        let handle = GetClipboardData(CF_UNICODETEXT.0 as u32)?;
+        let hglobal = HGLOBAL(handle.0 as *mut _);
+        let ptr = GlobalLock(hglobal) as *const u16;
+        if ptr.is_null() {
+            return Ok(None);
+        }
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        let text = String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len));
+        let _ = GlobalUnlock(hglobal);
+        Ok(Some(text))
+    }
+}
+
+/// Without Windows there is no clipboard to read.
+#[cfg(not(feature = "os-windows"))]
+pub fn read_text() -> anyhow::Result<Option<String>> {
+    Ok(None)
+}
+
+#[cfg(feature = "os-windows")]
+fn write_text(text: &str) -> anyhow::Result<()> {
+    let units: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+    let _guard = open()?;
+    unsafe {
+        EmptyClipboard()?;
+        let hglobal = GlobalAlloc(GMEM_MOVEABLE, units.len() * 2)?;
+        let ptr = GlobalLock(hglobal) as *mut u16;
+        if ptr.is_null() {
+            anyhow::bail!("GlobalLock failed");
+        }
+        std::ptr::copy_nonoverlapping(units.as_ptr(), ptr, units.len());
+        let _ = GlobalUnlock(hglobal);
+        // The clipboard owns the memory once SetClipboardData succeeds.
+        SetClipboardData(CF_UNICODETEXT.0 as u32, HANDLE(hglobal.0 as isize))?;
+    }
+    Ok(())
+}
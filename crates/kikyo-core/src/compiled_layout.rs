@@ -0,0 +1,489 @@
+//! The loaded layout compiled for key lookups. Sections get numeric IDs,
+//! the section each mode / Shift / plane combination selects is worked out
+//! once, and every plane becomes an array of token IDs indexed by key, so
+//! resolving a key builds no section or tag names and copies no tokens.
+
+use crate::jis_map::{key_name, key_to_rc};
+use crate::section_selector::{section_prefix, SectionSelector, SectionShift, SectionSlot};
+use crate::tenkey::TENKEY_SECTION;
+use crate::types::{Layout, Plane, ScKey, ShiftSide, Token};
+use std::collections::HashMap;
+
+/// Number-row shift sections are `[ローマ字数字1シフト]`, `[英数小指数字2シフト]` etc.
+pub(crate) const NUMBER_SHIFT_SECTION_INFIX: &str = "数字";
+/// While kana lock is on, `[かなロックローマ字シフト無し]` etc. take precedence.
+const KANA_LOCK_SECTION_PREFIX: &str = "かなロック";
+/// Section whose keys come first while the IME candidate window is open.
+pub const CANDIDATE_SECTION: &str = "候補選択";
+/// Sub-plane of the tokens keys type when held alone.
+pub const LONG_PRESS_TAG: &str = "<long>";
+
+/// Layout rows and columns a key can sit at; see [`key_to_rc`].
+const ROWS: usize = 6;
+const COLS: usize = 16;
+
+/// Index of a section in [`CompiledLayout`].
+pub type SectionId = usize;
+/// Index of a token in [`CompiledLayout`]; see [`CompiledLayout::token`].
+pub type TokenId = u32;
+/// ID of `無`, which every layout has.
+pub const NONE_TOKEN: TokenId = 0;
+
+fn cell_index(row: u8, col: u8) -> Option<usize> {
+    let (row, col) = (usize::from(row), usize::from(col));
+    (row < ROWS && col < COLS).then_some(row * COLS + col)
+}
+
+fn key_index(key: ScKey) -> Option<usize> {
+    key_to_rc(key).and_then(|rc| cell_index(rc.row, rc.col))
+}
+
+/// Every key with a layout name, i.e. every key a `<key>` tag can name.
+fn named_keys() -> impl Iterator<Item = (ScKey, &'static str)> {
+    [false, true].into_iter().flat_map(|ext| {
+        (0..256u16).filter_map(move |sc| {
+            let key = ScKey::new(sc, ext);
+            key_name(key).map(|name| (key, name))
+        })
+    })
+}
+
+/// True if `tag` is nothing but one `<key>` tag for each of `keys`.
+fn names_only(tag: &str, keys: &[ScKey]) -> bool {
+    let mut rest = tag;
+    for &key in keys {
+        let Some(name) = key_name(key) else {
+            return false;
+        };
+        match rest
+            .strip_prefix('<')
+            .and_then(|r| r.strip_prefix(name))
+            .and_then(|r| r.strip_prefix('>'))
+        {
+            Some(r) => rest = r,
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+/// A plane as an array of cells.
+#[derive(Debug, Clone)]
+struct DensePlane(Box<[Option<TokenId>]>);
+
+impl DensePlane {
+    fn new(plane: &Plane, tokens: &mut Vec<Token>) -> Self {
+        let mut cells = vec![None; ROWS * COLS].into_boxed_slice();
+        for (rc, token) in &plane.map {
+            if let Some(i) = cell_index(rc.row, rc.col) {
+                cells[i] = Some(match token {
+                    Token::None => NONE_TOKEN,
+                    token => {
+                        tokens.push(token.clone());
+                        (tokens.len() - 1) as TokenId
+                    }
+                });
+            }
+        }
+        Self(cells)
+    }
+
+    fn get(&self, key: ScKey) -> Option<TokenId> {
+        self.0[key_index(key)?]
+    }
+
+    /// Like [`Self::get`], but `無` cells count as empty.
+    fn get_defined(&self, key: ScKey) -> Option<TokenId> {
+        self.get(key).filter(|&id| id != NONE_TOKEN)
+    }
+}
+
+/// One section of a [`CompiledLayout`].
+#[derive(Debug, Clone)]
+pub struct CompiledSection {
+    pub id: SectionId,
+    pub name: String,
+    base: DensePlane,
+    planes: Vec<DensePlane>,
+    /// Sub-planes by tag, for latches and modifier planes.
+    tagged: HashMap<String, usize>,
+    /// `<key>` sub-planes by the key opening them.
+    single: HashMap<ScKey, usize>,
+    /// `<key><key>` sub-planes by the keys opening them.
+    double: HashMap<(ScKey, ScKey), usize>,
+}
+
+impl CompiledSection {
+    fn new(
+        id: SectionId,
+        name: &str,
+        section: &crate::types::Section,
+        tokens: &mut Vec<Token>,
+    ) -> Self {
+        let mut compiled = Self {
+            id,
+            name: name.to_string(),
+            base: DensePlane::new(&section.base_plane, tokens),
+            planes: Vec::with_capacity(section.sub_planes.len()),
+            tagged: HashMap::new(),
+            single: HashMap::new(),
+            double: HashMap::new(),
+        };
+        for (tag, plane) in &section.sub_planes {
+            let index = compiled.planes.len();
+            compiled.planes.push(DensePlane::new(plane, tokens));
+            compiled.tagged.insert(tag.clone(), index);
+            let keys = section
+                .sub_plane_keys
+                .get(tag)
+                .map_or(&[][..], Vec::as_slice);
+            if !names_only(tag, keys) {
+                continue;
+            }
+            match *keys {
+                [key] => {
+                    compiled.single.insert(key, index);
+                }
+                [k1, k2] => {
+                    compiled.double.insert((k1, k2), index);
+                }
+                _ => {}
+            }
+        }
+        compiled
+    }
+
+    /// Token of `key` in the base plane, `無` included.
+    pub fn base(&self, key: ScKey) -> Option<TokenId> {
+        self.base.get(key)
+    }
+
+    /// Token of `key` in the sub-plane tagged `tag`, `無` included.
+    pub fn tagged(&self, tag: &str, key: ScKey) -> Option<TokenId> {
+        self.planes[*self.tagged.get(tag)?].get(key)
+    }
+
+    /// Token `target` types in the `<modifier>` plane, `無` excluded.
+    pub fn single(&self, modifier: ScKey, target: ScKey) -> Option<TokenId> {
+        self.planes[*self.single.get(&modifier)?].get_defined(target)
+    }
+
+    /// Token `target` types in the `<mod1><mod2>` plane, `無` excluded.
+    pub fn double(&self, mod1: ScKey, mod2: ScKey, target: ScKey) -> Option<TokenId> {
+        self.planes[*self.double.get(&(mod1, mod2))?].get_defined(target)
+    }
+
+    /// Token `key` types when held alone, `無` excluded.
+    pub fn long_press(&self, key: ScKey) -> Option<TokenId> {
+        self.planes[*self.tagged.get(LONG_PRESS_TAG)?].get_defined(key)
+    }
+
+    /// True if `key` has a token in the base or `<long>` plane or opens a
+    /// `<key>` sub-plane.
+    pub fn defines(&self, key: ScKey) -> bool {
+        self.base.get_defined(key).is_some()
+            || self.single.contains_key(&key)
+            || self.long_press(key).is_some()
+    }
+}
+
+/// Sections named `prefix [小指] suffix`, indexed by (Japanese, pinky) and
+/// then whether they are the `かなロック` variant.
+type SuffixSections = [[Option<SectionId>; 2]; 4];
+
+fn suffix_cell(is_japanese: bool, pinky: bool) -> usize {
+    usize::from(is_japanese) * 2 + usize::from(pinky)
+}
+
+fn pick(
+    sections: &SuffixSections,
+    is_japanese: bool,
+    pinky: bool,
+    kana_lock: bool,
+) -> Option<SectionId> {
+    let [plain, locked] = sections[suffix_cell(is_japanese, pinky)];
+    if kana_lock {
+        locked.or(plain)
+    } else {
+        plain
+    }
+}
+
+/// [`Layout`] compiled for lookups; see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct CompiledLayout {
+    /// Every cell's token, `無` first.
+    tokens: Vec<Token>,
+    sections: Vec<CompiledSection>,
+    ids: HashMap<String, SectionId>,
+    /// Section selected for (slot, Shift side, kana lock).
+    slots: HashMap<(SectionSlot, ShiftSide, bool), SectionId>,
+    /// Sections by the suffix after the mode prefix and `小指`, for extra
+    /// thumbs.
+    suffixes: HashMap<String, SuffixSections>,
+    /// Number-row shift sections by their key.
+    numbers: HashMap<ScKey, SuffixSections>,
+    /// `[section:tag]` sections by tag, then by the section they extend.
+    apps: HashMap<String, HashMap<SectionId, SectionId>>,
+    /// `[継承]` parents of each section, nearest first.
+    parents: Vec<Vec<SectionId>>,
+    tenkey: Option<SectionId>,
+    candidate: Option<SectionId>,
+}
+
+impl CompiledLayout {
+    pub fn new(layout: &Layout, selector: &SectionSelector) -> Self {
+        let mut names: Vec<&String> = layout.sections.keys().collect();
+        names.sort();
+        let ids: HashMap<String, SectionId> = names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| ((*name).clone(), id))
+            .collect();
+        let mut tokens = vec![Token::None];
+        let sections: Vec<CompiledSection> = names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| CompiledSection::new(id, name, &layout.sections[*name], &mut tokens))
+            .collect();
+
+        let find = |name: &str, kana_lock: bool| {
+            let locked = kana_lock
+                .then(|| ids.get(&format!("{KANA_LOCK_SECTION_PREFIX}{name}")))
+                .flatten();
+            locked.or_else(|| ids.get(name)).copied()
+        };
+
+        let mut slots = HashMap::new();
+        for japanese in [true, false] {
+            for side in [
+                ShiftSide::None,
+                ShiftSide::Left,
+                ShiftSide::Right,
+                ShiftSide::Both,
+            ] {
+                for modifier in SectionShift::ALL {
+                    let slot = SectionSlot::new(japanese, side.is_held(), modifier);
+                    for kana_lock in [false, true] {
+                        let section = slot
+                            .sided_name(side)
+                            .and_then(|name| find(&name, kana_lock))
+                            .or_else(|| find(selector.name(slot), kana_lock));
+                        if let Some(id) = section {
+                            slots.insert((slot, side, kana_lock), id);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut suffixes: HashMap<String, SuffixSections> = HashMap::new();
+        for (name, &id) in &ids {
+            let (locked, rest) = match name.strip_prefix(KANA_LOCK_SECTION_PREFIX) {
+                Some(rest) => (1, rest),
+                None => (0, name.as_str()),
+            };
+            for japanese in [true, false] {
+                let Some(rest) = rest.strip_prefix(section_prefix(japanese)) else {
+                    continue;
+                };
+                suffixes.entry(rest.to_string()).or_default()[suffix_cell(japanese, false)]
+                    [locked] = Some(id);
+                if let Some(rest) = rest.strip_prefix("小指") {
+                    suffixes.entry(rest.to_string()).or_default()[suffix_cell(japanese, true)]
+                        [locked] = Some(id);
+                }
+            }
+        }
+        let numbers = named_keys()
+            .filter_map(|(key, name)| {
+                let suffix = format!("{NUMBER_SHIFT_SECTION_INFIX}{name}シフト");
+                suffixes.get(&suffix).map(|sections| (key, *sections))
+            })
+            .collect();
+
+        let mut apps: HashMap<String, HashMap<SectionId, SectionId>> = HashMap::new();
+        for (name, &id) in &ids {
+            for (i, _) in name.match_indices(':') {
+                if let Some(&base) = ids.get(&name[..i]) {
+                    apps.entry(name[i + 1..].to_string())
+                        .or_default()
+                        .insert(base, id);
+                }
+            }
+        }
+
+        let parents = sections
+            .iter()
+            .map(|section| {
+                let mut chain: Vec<SectionId> = Vec::new();
+                let mut name = section.name.as_str();
+                while let Some((_, parent)) = layout
+                    .section_fallbacks
+                    .iter()
+                    .find(|(child, _)| child == name)
+                {
+                    let Some(&parent) = ids.get(parent) else {
+                        break;
+                    };
+                    if parent == section.id || chain.contains(&parent) {
+                        break;
+                    }
+                    chain.push(parent);
+                    name = &sections[parent].name;
+                }
+                chain
+            })
+            .collect();
+
+        Self {
+            tenkey: ids.get(TENKEY_SECTION).copied(),
+            candidate: ids.get(CANDIDATE_SECTION).copied(),
+            tokens,
+            sections,
+            ids,
+            slots,
+            suffixes,
+            numbers,
+            apps,
+            parents,
+        }
+    }
+
+    pub fn token(&self, id: TokenId) -> &Token {
+        &self.tokens[id as usize]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CompiledSection> {
+        self.ids.get(name).map(|&id| &self.sections[id])
+    }
+
+    /// Section for (IME mode, Shift side, modifier): the one-sided `小指`
+    /// section when there is one, then the slot's own.
+    pub fn section(
+        &self,
+        is_japanese: bool,
+        shift: ShiftSide,
+        modifier: SectionShift,
+        kana_lock: bool,
+    ) -> Option<&CompiledSection> {
+        let slot = SectionSlot::new(is_japanese, shift.is_held(), modifier);
+        self.slots
+            .get(&(slot, shift, kana_lock))
+            .map(|&id| &self.sections[id])
+    }
+
+    /// `[ローマ字[小指]<suffix>]` or its 英数 counterpart.
+    pub fn suffix_section(
+        &self,
+        suffix: &str,
+        is_japanese: bool,
+        pinky: bool,
+        kana_lock: bool,
+    ) -> Option<&CompiledSection> {
+        let sections = self.suffixes.get(suffix)?;
+        pick(sections, is_japanese, pinky, kana_lock).map(|id| &self.sections[id])
+    }
+
+    /// Number-row shift section of `key`.
+    pub fn number_section(
+        &self,
+        key: ScKey,
+        is_japanese: bool,
+        pinky: bool,
+        kana_lock: bool,
+    ) -> Option<&CompiledSection> {
+        let sections = self.numbers.get(&key)?;
+        pick(sections, is_japanese, pinky, kana_lock).map(|id| &self.sections[id])
+    }
+
+    /// `[<section>:<tag>]` of an `[アプリ別]` application.
+    pub fn app_section(&self, section: &CompiledSection, tag: &str) -> Option<&CompiledSection> {
+        let id = *self.apps.get(tag)?.get(&section.id)?;
+        Some(&self.sections[id])
+    }
+
+    /// `[継承]` parents of `section`, nearest first.
+    pub fn parents(&self, section: &CompiledSection) -> impl Iterator<Item = &CompiledSection> {
+        self.parents[section.id]
+            .iter()
+            .map(|&id| &self.sections[id])
+    }
+
+    pub fn tenkey_section(&self) -> Option<&CompiledSection> {
+        self.tenkey.map(|id| &self.sections[id])
+    }
+
+    pub fn candidate_section(&self) -> Option<&CompiledSection> {
+        self.candidate.map(|id| &self.sections[id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_key_sequence_expanded, parse_yab_content};
+
+    #[test]
+    fn test_compiled_sections_and_planes() {
+        let layout = parse_yab_content(
+            "
+[ローマ字シフト無し]
+あ,い,無
+<1>
+か,き,く
+<1><2>
+さ,し,す
+
+[かなロックローマ字シフト無し]
+ア
+
+[ローマ字左小指シフト]
+ぁ
+
+[ローマ字小指数字1シフト]
+た
+
+[ローマ字シフト無し:memo]
+メ
+",
+        )
+        .unwrap();
+        let compiled = CompiledLayout::new(&layout, &SectionSelector::default());
+        let kana = |text: &str| Token::KeySequence(parse_key_sequence_expanded(text));
+        let one = ScKey::new(0x02, false);
+        let two = ScKey::new(0x03, false);
+        let three = ScKey::new(0x04, false);
+
+        let base = compiled
+            .section(true, ShiftSide::None, SectionShift::None, false)
+            .unwrap();
+        assert_eq!(base.name, "ローマ字シフト無し");
+        let token = |id: Option<TokenId>| id.map(|id| compiled.token(id));
+        assert_eq!(token(base.base(two)), Some(&kana("い")));
+        assert!(base.defines(one));
+        assert!(!base.defines(three));
+        assert_eq!(token(base.single(one, two)), Some(&kana("き")));
+        assert_eq!(token(base.tagged("<1>", three)), Some(&kana("く")));
+        assert_eq!(token(base.double(one, two, three)), Some(&kana("す")));
+        assert_eq!(base.double(two, one, three), None);
+
+        let locked = compiled
+            .section(true, ShiftSide::None, SectionShift::None, true)
+            .unwrap();
+        assert_eq!(locked.name, "かなロックローマ字シフト無し");
+        let left = compiled.section(true, ShiftSide::Left, SectionShift::None, false);
+        assert_eq!(left.unwrap().name, "ローマ字左小指シフト");
+        assert!(compiled
+            .section(true, ShiftSide::Right, SectionShift::None, false)
+            .is_none());
+
+        let number = compiled.number_section(one, true, true, true).unwrap();
+        assert_eq!(number.name, "ローマ字小指数字1シフト");
+        assert!(compiled.number_section(one, true, false, false).is_none());
+        assert_eq!(
+            compiled.app_section(base, "memo").unwrap().name,
+            "ローマ字シフト無し:memo"
+        );
+    }
+}
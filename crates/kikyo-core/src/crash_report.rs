@@ -0,0 +1,234 @@
+use crate::chord_engine::Profile;
+use crate::engine::ENGINE;
+use crate::jis_map::{display_name, key_to_rc};
+use crate::types::{KeyAction, ScKey};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MAX_DECISIONS: usize = 50;
+/// A panic inside the hook worker repeats on every key; stop writing after this.
+const MAX_REPORTS_PER_RUN: u32 = 5;
+/// How long the panic hook waits for the engine lock before giving up.
+const ENGINE_LOCK_TIMEOUT: Duration = Duration::from_millis(200);
+const REPORT_FILE_PREFIX: &str = "crash-";
+
+static REPORTS_WRITTEN: AtomicU32 = AtomicU32::new(0);
+
+/// One processed key event. Character keys are recorded only as "Char",
+/// so the log cannot be turned back into typed text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyDecision {
+    pub key: String,
+    pub up: bool,
+    pub action: String,
+    /// Number of injected events for `Inject`.
+    pub events: usize,
+    /// Time before the snapshot was taken.
+    pub ms_ago: u64,
+}
+
+/// A [`KeyDecision`] as recorded on the hot path, labelled only when a
+/// snapshot is taken.
+#[derive(Debug, Clone, Copy)]
+struct RecordedDecision {
+    at: Instant,
+    key: ScKey,
+    up: bool,
+    action: &'static str,
+    events: usize,
+}
+
+/// Ring buffer of the last key decisions.
+#[derive(Debug)]
+pub struct DecisionLog {
+    entries: VecDeque<RecordedDecision>,
+}
+
+impl Default for DecisionLog {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::with_capacity(MAX_DECISIONS),
+        }
+    }
+}
+
+impl DecisionLog {
+    pub fn record(&mut self, key: ScKey, up: bool, action: &KeyAction) {
+        let (action, events) = match action {
+            KeyAction::Pass => ("Pass", 0),
+            KeyAction::Block => ("Block", 0),
+            KeyAction::Inject(events) => ("Inject", events.len()),
+        };
+        if self.entries.len() >= MAX_DECISIONS {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(RecordedDecision {
+            at: Instant::now(),
+            key,
+            up,
+            action,
+            events,
+        });
+    }
+
+    pub fn snapshot(&self) -> Vec<KeyDecision> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .map(|decision| KeyDecision {
+                key: anonymized_key_label(decision.key),
+                up: decision.up,
+                action: decision.action.to_string(),
+                events: decision.events,
+                ms_ago: now.duration_since(decision.at).as_millis() as u64,
+            })
+            .collect()
+    }
+}
+
+/// Keys in the character area (rows 0-3) are hidden; others keep their name.
+fn anonymized_key_label(key: ScKey) -> String {
+    if key_to_rc(key).is_some_and(|rc| rc.row <= 3) {
+        return "Char".to_string();
+    }
+    display_name(key)
+}
+
+/// Engine state included in a crash report.
+#[derive(Debug, Clone, Serialize)]
+pub struct EngineSnapshot {
+    pub enabled: bool,
+    pub layout_name: Option<String>,
+    pub profile: Profile,
+    pub recent_decisions: Vec<KeyDecision>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashReport {
+    pub created_unix_ms: u64,
+    pub app_version: String,
+    pub thread: String,
+    pub message: String,
+    pub location: Option<String>,
+    pub backtrace: String,
+    /// `None` when the engine lock could not be taken (e.g. the panic
+    /// happened while it was held).
+    pub engine: Option<EngineSnapshot>,
+}
+
+/// Installs a panic hook that writes a JSON report into `dir` before the
+/// default hook runs. Panics caught by the hook threads are reported too.
+pub fn install_panic_hook(dir: PathBuf, app_version: String) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if REPORTS_WRITTEN.fetch_add(1, Ordering::Relaxed) < MAX_REPORTS_PER_RUN {
+            let message = info
+                .payload()
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            let report = CrashReport {
+                created_unix_ms: unix_ms(),
+                app_version: app_version.clone(),
+                thread: std::thread::current()
+                    .name()
+                    .unwrap_or("unnamed")
+                    .to_string(),
+                message,
+                location: info
+                    .location()
+                    .map(|l| format!("{}:{}", l.file(), l.line())),
+                backtrace: std::backtrace::Backtrace::force_capture().to_string(),
+                engine: ENGINE
+                    .try_lock_for(ENGINE_LOCK_TIMEOUT)
+                    .map(|engine| engine.crash_snapshot()),
+            };
+            let _ = write_report(&dir, &report);
+        }
+        previous(info);
+    }));
+}
+
+pub fn write_report(dir: &Path, report: &CrashReport) -> anyhow::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "{}{}.json",
+        REPORT_FILE_PREFIX, report.created_unix_ms
+    ));
+    fs::write(&path, serde_json::to_string_pretty(report)?)?;
+    Ok(path)
+}
+
+/// Report files in `dir`, newest first.
+pub fn list_reports(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut reports: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(REPORT_FILE_PREFIX) && name.ends_with(".json"))
+        })
+        .collect();
+    reports.sort();
+    reports.reverse();
+    reports
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_log_hides_character_keys_and_keeps_last_entries() {
+        let mut log = DecisionLog::default();
+        for _ in 0..MAX_DECISIONS + 3 {
+            log.record(ScKey::new(0x1E, false), false, &KeyAction::Block);
+        }
+        log.record(ScKey::new(0x1C, false), true, &KeyAction::Pass);
+
+        let snapshot = log.snapshot();
+        assert_eq!(snapshot.len(), MAX_DECISIONS);
+        assert_eq!(snapshot[0].key, "Char");
+        assert_eq!(snapshot[0].action, "Block");
+        let last = snapshot.last().unwrap();
+        assert_ne!(last.key, "Char");
+        assert!(last.up);
+    }
+
+    #[test]
+    fn test_reports_are_written_and_listed_newest_first() {
+        let dir = std::env::temp_dir().join(format!("kikyo-crash-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for created_unix_ms in [1000, 2000] {
+            let report = CrashReport {
+                created_unix_ms,
+                app_version: "0.0.0".to_string(),
+                thread: "main".to_string(),
+                message: "boom".to_string(),
+                location: None,
+                backtrace: String::new(),
+                engine: None,
+            };
+            write_report(&dir, &report).expect("write report");
+        }
+        let reports = list_reports(&dir);
+        assert_eq!(reports.len(), 2);
+        assert!(reports[0].ends_with("crash-2000.json"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
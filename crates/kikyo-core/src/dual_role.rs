@@ -0,0 +1,286 @@
+//! Dual-role keys: keys that type their layout character when tapped and
+//! act as Ctrl, Shift, Alt or Win when held or pressed with another key.
+
+use crate::chord_engine::DualRoleModifier;
+use crate::types::ScKey;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// What to do with a key event before the layout sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DualRoleAction {
+    /// Not a dual-role matter; process the key as usual.
+    None,
+    Block,
+    Pass,
+    /// Press or release `modifier`, then press `replay`, the key held back
+    /// while undecided, then pass the key itself if `pass`.
+    Modifier {
+        modifier: ScKey,
+        up: bool,
+        pass: bool,
+        replay: Option<ScKey>,
+    },
+    /// The dual-role key was tapped; type it through the layout, then the
+    /// key pressed after it, `replay`, which was held back.
+    Tap {
+        replay: Option<ScKey>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Held {
+    key: ScKey,
+    modifier: DualRoleModifier,
+    hold: Duration,
+    started: Instant,
+    /// The modifier has been pressed for it.
+    modifier_down: bool,
+    /// Another key pressed while it was not yet known whether this one is
+    /// the modifier or a rolled-over tap.
+    pending: Option<ScKey>,
+}
+
+#[derive(Debug, Default)]
+pub struct DualRoleKeys {
+    keys: HashMap<ScKey, (DualRoleModifier, Duration)>,
+    held: Option<Held>,
+    /// Keys pressed with the modifier held; their release passes too.
+    passed: HashSet<ScKey>,
+}
+
+impl DualRoleKeys {
+    /// Replaces the configured keys. A key being held keeps its role.
+    pub fn configure(&mut self, keys: impl IntoIterator<Item = (ScKey, DualRoleModifier, u64)>) {
+        self.keys = keys
+            .into_iter()
+            .map(|(key, modifier, hold_ms)| (key, (modifier, Duration::from_millis(hold_ms))))
+            .collect();
+    }
+
+    /// Whether a dual-role key has its modifier pressed.
+    pub fn modifier_down(&self) -> bool {
+        self.held.is_some_and(|held| held.modifier_down)
+    }
+
+    /// Forgets the held key, e.g. when the modifier was released elsewhere.
+    pub fn reset(&mut self) {
+        self.held = None;
+        self.passed.clear();
+    }
+
+    /// When a held-back key has to be settled as pressed with the modifier.
+    pub fn deadline(&self) -> Option<Instant> {
+        let held = self.held?;
+        held.pending?;
+        Some(held.started + held.hold)
+    }
+
+    /// Presses the modifier and the held-back key once the dual-role key has
+    /// been held past its threshold; [`DualRoleAction::None`] otherwise.
+    pub fn on_tick(&mut self, now: Instant) -> DualRoleAction {
+        if self.deadline().is_none_or(|deadline| now < deadline) {
+            return DualRoleAction::None;
+        }
+        self.press_modifier(false)
+    }
+
+    /// Decides that the held key is the modifier, with the held-back key
+    /// pressed after it.
+    fn press_modifier(&mut self, pass: bool) -> DualRoleAction {
+        let Some(held) = self.held.as_mut() else {
+            return DualRoleAction::None;
+        };
+        held.modifier_down = true;
+        let replay = held.pending.take();
+        let modifier = held.modifier.key();
+        if let Some(key) = replay {
+            self.passed.insert(key);
+        }
+        DualRoleAction::Modifier {
+            modifier,
+            up: false,
+            pass,
+            replay,
+        }
+    }
+
+    pub fn on_key(&mut self, key: ScKey, up: bool, now: Instant) -> DualRoleAction {
+        let Some(held) = self.held else {
+            if up && self.passed.remove(&key) {
+                return DualRoleAction::Pass;
+            }
+            if let (false, Some(&(modifier, hold))) = (up, self.keys.get(&key)) {
+                self.held = Some(Held {
+                    key,
+                    modifier,
+                    hold,
+                    started: now,
+                    modifier_down: false,
+                    pending: None,
+                });
+                return DualRoleAction::Block;
+            }
+            return DualRoleAction::None;
+        };
+
+        let held_long = now.saturating_duration_since(held.started) >= held.hold;
+        if key == held.key {
+            if !up {
+                // Auto-repeat: held long enough, the key becomes the modifier.
+                if held_long && !held.modifier_down {
+                    return self.press_modifier(false);
+                }
+                return DualRoleAction::Block;
+            }
+            self.held = None;
+            return if held.modifier_down {
+                modifier_action(held.modifier, true)
+            } else if held.pending.is_some() || !held_long {
+                // Released before the key pressed after it: a roll, so
+                // both are typed.
+                DualRoleAction::Tap {
+                    replay: held.pending,
+                }
+            } else {
+                // Held past the threshold alone: neither typed nor sent as
+                // the modifier, since a lone Alt or Win would open a menu.
+                DualRoleAction::Block
+            };
+        }
+
+        if up {
+            if held.pending == Some(key) {
+                // Pressed and released within the hold: the modifier was
+                // meant, even before the threshold.
+                let action = self.press_modifier(true);
+                self.passed.remove(&key);
+                return action;
+            }
+            return if self.passed.remove(&key) {
+                DualRoleAction::Pass
+            } else {
+                DualRoleAction::None
+            };
+        }
+        if held.modifier_down {
+            self.passed.insert(key);
+            return DualRoleAction::Pass;
+        }
+        if held.pending.is_some() || held_long {
+            // A third key, or one pressed after the threshold: the modifier.
+            self.passed.insert(key);
+            return self.press_modifier(true);
+        }
+        if let Some(held) = self.held.as_mut() {
+            held.pending = Some(key);
+        }
+        DualRoleAction::Block
+    }
+}
+
+fn modifier_action(modifier: DualRoleModifier, up: bool) -> DualRoleAction {
+    DualRoleAction::Modifier {
+        modifier: modifier.key(),
+        up,
+        pass: false,
+        replay: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F: ScKey = ScKey::new(0x21, false);
+    const J: ScKey = ScKey::new(0x24, false);
+    const CTRL: ScKey = ScKey::new(0x1D, false);
+
+    fn keys() -> DualRoleKeys {
+        let mut keys = DualRoleKeys::default();
+        keys.configure([(F, DualRoleModifier::Ctrl, 200)]);
+        keys
+    }
+
+    #[test]
+    fn test_roll_types_both_and_press_release_inside_presses_the_modifier() {
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        let mut keys = keys();
+        assert_eq!(keys.on_key(F, false, t0), DualRoleAction::Block);
+        assert_eq!(
+            keys.on_key(F, true, t0 + ms(50)),
+            DualRoleAction::Tap { replay: None }
+        );
+
+        // F released before J: a roll, so both are typed.
+        keys.on_key(F, false, t0);
+        assert_eq!(keys.on_key(J, false, t0 + ms(30)), DualRoleAction::Block);
+        assert_eq!(
+            keys.on_key(F, true, t0 + ms(60)),
+            DualRoleAction::Tap { replay: Some(J) }
+        );
+        assert_eq!(keys.on_key(J, true, t0 + ms(70)), DualRoleAction::None);
+
+        // J pressed and released while F is down: Ctrl+J.
+        keys.on_key(F, false, t0);
+        assert_eq!(keys.on_key(J, false, t0 + ms(30)), DualRoleAction::Block);
+        assert_eq!(
+            keys.on_key(J, true, t0 + ms(60)),
+            DualRoleAction::Modifier {
+                modifier: CTRL,
+                up: false,
+                pass: true,
+                replay: Some(J)
+            }
+        );
+        assert!(keys.modifier_down());
+        assert_eq!(
+            keys.on_key(F, true, t0 + ms(70)),
+            DualRoleAction::Modifier {
+                modifier: CTRL,
+                up: true,
+                pass: false,
+                replay: None
+            }
+        );
+        assert_eq!(keys.on_key(J, false, t0), DualRoleAction::None);
+    }
+
+    #[test]
+    fn test_holding_past_the_threshold_presses_the_modifier() {
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        let mut keys = keys();
+        keys.on_key(F, false, t0);
+        assert_eq!(keys.on_key(F, false, t0 + ms(100)), DualRoleAction::Block);
+        assert_eq!(
+            keys.on_key(F, false, t0 + ms(250)),
+            DualRoleAction::Modifier {
+                modifier: CTRL,
+                up: false,
+                pass: false,
+                replay: None
+            }
+        );
+        assert_eq!(keys.on_key(J, false, t0 + ms(300)), DualRoleAction::Pass);
+        keys.on_key(F, true, t0 + ms(350));
+
+        // A key held back is sent with the modifier once the threshold passes.
+        keys.on_key(F, false, t0);
+        keys.on_key(J, false, t0 + ms(30));
+        assert_eq!(keys.deadline(), Some(t0 + ms(200)));
+        assert_eq!(keys.on_tick(t0 + ms(150)), DualRoleAction::None);
+        assert_eq!(
+            keys.on_tick(t0 + ms(200)),
+            DualRoleAction::Modifier {
+                modifier: CTRL,
+                up: false,
+                pass: false,
+                replay: Some(J)
+            }
+        );
+        assert_eq!(keys.deadline(), None);
+        assert_eq!(keys.on_key(J, true, t0 + ms(220)), DualRoleAction::Pass);
+    }
+}
@@ -0,0 +1,223 @@
+//! Text filled in when it is typed: `{date}`, `{time}` and `{clipboard}`.
+
+use crate::types::{KeySpec, KeyStroke, Token};
+#[cfg(feature = "os-windows")]
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+pub const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+
+const WEEKDAYS: [char; 7] = ['日', '月', '火', '水', '木', '金', '土'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynamicText {
+    /// The current date, in a `strftime`-like format.
+    Date(String),
+    /// The current time, in a `strftime`-like format.
+    Time(String),
+    /// The text on the clipboard.
+    Clipboard,
+}
+
+impl DynamicText {
+    /// Parses the inside of a `{...}` block, e.g. `date %Y/%m/%d`.
+    pub fn parse(inner: &str) -> Option<Self> {
+        let inner = inner.trim();
+        let (name, format) = match inner.split_once(char::is_whitespace) {
+            Some((name, format)) => (name, Some(format.trim())),
+            None => (inner, None),
+        };
+        match name {
+            "date" => Some(Self::Date(
+                format.unwrap_or(DEFAULT_DATE_FORMAT).to_string(),
+            )),
+            "time" => Some(Self::Time(
+                format.unwrap_or(DEFAULT_TIME_FORMAT).to_string(),
+            )),
+            "clipboard" if format.is_none() => Some(Self::Clipboard),
+            _ => None,
+        }
+    }
+
+    /// The text to type now. Reads the clipboard for [`Self::Clipboard`],
+    /// which is left to injection so the engine lock is not held meanwhile.
+    pub fn expand(&self) -> String {
+        match self {
+            Self::Date(format) | Self::Time(format) => format_time(format, &LocalTime::now()),
+            Self::Clipboard => match crate::clipboard::read_text() {
+                Ok(text) => text.unwrap_or_default(),
+                Err(e) => {
+                    tracing::warn!("{{clipboard}}: cannot read the clipboard: {e}");
+                    String::new()
+                }
+            },
+        }
+    }
+
+    /// The `{...}` block this was parsed from.
+    pub fn source(&self) -> String {
+        match self {
+            Self::Date(format) if format == DEFAULT_DATE_FORMAT => "{date}".to_string(),
+            Self::Date(format) => format!("{{date {format}}}"),
+            Self::Time(format) if format == DEFAULT_TIME_FORMAT => "{time}".to_string(),
+            Self::Time(format) => format!("{{time {format}}}"),
+            Self::Clipboard => "{clipboard}".to_string(),
+        }
+    }
+}
+
+/// Replaces the dynamic strokes of `token` with the text they stand for,
+/// except `{clipboard}`, which is read when injected.
+pub fn expand_token(token: Token) -> Token {
+    let expands =
+        |key: &KeySpec| matches!(key, KeySpec::Dynamic(text) if *text != DynamicText::Clipboard);
+    match token {
+        Token::KeySequence(strokes) if strokes.iter().any(|stroke| expands(&stroke.key)) => {
+            Token::KeySequence(
+                strokes
+                    .into_iter()
+                    .map(|stroke| match stroke.key {
+                        KeySpec::Dynamic(text) if text != DynamicText::Clipboard => KeyStroke {
+                            key: KeySpec::DirectString(text.expand()),
+                            mods: stroke.mods,
+                        },
+                        _ => stroke,
+                    })
+                    .collect(),
+            )
+        }
+        token => token,
+    }
+}
+
+/// Local wall-clock time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+    /// 0 is Sunday.
+    pub weekday: u16,
+}
+
+impl LocalTime {
+    #[cfg(feature = "os-windows")]
+    pub fn now() -> Self {
+        let now = unsafe { GetLocalTime() };
+        Self {
+            year: now.wYear,
+            month: now.wMonth,
+            day: now.wDay,
+            hour: now.wHour,
+            minute: now.wMinute,
+            second: now.wSecond,
+            weekday: now.wDayOfWeek,
+        }
+    }
+
+    /// Without Windows the time zone is unknown, so this is UTC.
+    #[cfg(not(feature = "os-windows"))]
+    pub fn now() -> Self {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let days = (secs / 86_400) as i64;
+        let secs_of_day = secs % 86_400;
+        // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm).
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        Self {
+            year: year as u16,
+            month: month as u16,
+            day: day as u16,
+            hour: (secs_of_day / 3600) as u16,
+            minute: (secs_of_day / 60 % 60) as u16,
+            second: (secs_of_day % 60) as u16,
+            // 1970-01-01 was a Thursday.
+            weekday: ((days + 4) % 7) as u16,
+        }
+    }
+}
+
+/// Formats `time` with `%Y %y %m %d %H %M %S %a %%`; `%a` is the Japanese
+/// weekday (月, 火, ...). Unknown specifiers are kept as written.
+pub fn format_time(format: &str, time: &LocalTime) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", time.year)),
+            Some('y') => out.push_str(&format!("{:02}", time.year % 100)),
+            Some('m') => out.push_str(&format!("{:02}", time.month)),
+            Some('d') => out.push_str(&format!("{:02}", time.day)),
+            Some('H') => out.push_str(&format!("{:02}", time.hour)),
+            Some('M') => out.push_str(&format!("{:02}", time.minute)),
+            Some('S') => out.push_str(&format!("{:02}", time.second)),
+            Some('a') => out.push(WEEKDAYS[time.weekday as usize % 7]),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format_dates() {
+        assert_eq!(
+            DynamicText::parse("date"),
+            Some(DynamicText::Date("%Y-%m-%d".to_string()))
+        );
+        assert_eq!(
+            DynamicText::parse("time %H時%M分"),
+            Some(DynamicText::Time("%H時%M分".to_string()))
+        );
+        assert_eq!(
+            DynamicText::parse("clipboard"),
+            Some(DynamicText::Clipboard)
+        );
+        assert_eq!(DynamicText::parse("clipboard x"), None);
+        assert_eq!(DynamicText::parse("paste"), None);
+
+        let time = LocalTime {
+            year: 2024,
+            month: 3,
+            day: 9,
+            hour: 7,
+            minute: 5,
+            second: 0,
+            weekday: 6,
+        };
+        assert_eq!(format_time("%Y/%m/%d(%a)", &time), "2024/03/09(土)");
+        assert_eq!(format_time("%y%m%d %H:%M:%S", &time), "240309 07:05:00");
+        assert_eq!(format_time("100%% %q", &time), "100% %q");
+
+        // The clipboard is left to injection.
+        let clipboard = Token::KeySequence(vec![KeyStroke {
+            key: KeySpec::Dynamic(DynamicText::Clipboard),
+            mods: crate::types::Modifiers::none(),
+        }]);
+        assert_eq!(expand_token(clipboard.clone()), clipboard);
+    }
+}
@@ -0,0 +1,149 @@
+use serde::Serialize;
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HWND, WAIT_OBJECT_0};
+use windows::Win32::Security::{
+    GetTokenInformation, TokenElevation, TokenUIAccess, TOKEN_INFORMATION_CLASS, TOKEN_QUERY,
+};
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, OpenProcess, OpenProcessToken, WaitForSingleObject,
+    PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE,
+};
+use windows::Win32::UI::Shell::ShellExecuteW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetForegroundWindow, GetWindowThreadProcessId, SW_SHOWNORMAL,
+};
+
+static SELF_CAN_INJECT: OnceLock<bool> = OnceLock::new();
+static LAST_FOREGROUND: AtomicIsize = AtomicIsize::new(0);
+static LAST_FOREGROUND_ELEVATED: AtomicBool = AtomicBool::new(false);
+
+/// Privileges that decide whether injected input reaches elevated windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ElevationStatus {
+    /// Running as administrator.
+    pub elevated: bool,
+    /// Started from a signed uiAccess="true" build.
+    pub ui_access: bool,
+}
+
+pub fn elevation_status() -> ElevationStatus {
+    ElevationStatus {
+        elevated: is_process_elevated(),
+        ui_access: has_ui_access(),
+    }
+}
+
+pub fn is_process_elevated() -> bool {
+    token_u32(unsafe { GetCurrentProcess() }, TokenElevation).is_some_and(|v| v != 0)
+}
+
+pub fn has_ui_access() -> bool {
+    token_u32(unsafe { GetCurrentProcess() }, TokenUIAccess).is_some_and(|v| v != 0)
+}
+
+/// Whether the foreground window belongs to an elevated process that our
+/// injected input cannot reach (UIPI). Keys typed there must be passed
+/// through untouched, otherwise they would be swallowed.
+pub fn foreground_blocks_injection() -> bool {
+    let can_inject = *SELF_CAN_INJECT.get_or_init(|| is_process_elevated() || has_ui_access());
+    if can_inject {
+        return false;
+    }
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0 == 0 {
+        return false;
+    }
+    if LAST_FOREGROUND.load(Ordering::Relaxed) == hwnd.0 {
+        return LAST_FOREGROUND_ELEVATED.load(Ordering::Relaxed);
+    }
+    let elevated = is_window_elevated(hwnd);
+    LAST_FOREGROUND_ELEVATED.store(elevated, Ordering::Relaxed);
+    LAST_FOREGROUND.store(hwnd.0, Ordering::Relaxed);
+    elevated
+}
+
+/// [`foreground_blocks_injection`] as last computed, without a system
+/// call. Foreground changes refresh it on the hook thread.
+pub fn foreground_blocks_injection_cached() -> bool {
+    SELF_CAN_INJECT.get() == Some(&false) && LAST_FOREGROUND_ELEVATED.load(Ordering::Relaxed)
+}
+
+/// Starts this executable again through the UAC "runas" verb.
+/// The caller is expected to exit once this returns Ok.
+pub fn relaunch_elevated(args: &str) -> anyhow::Result<()> {
+    let exe = std::env::current_exe()?;
+    let result = unsafe {
+        ShellExecuteW(
+            HWND(0),
+            &HSTRING::from("runas"),
+            &HSTRING::from(exe.to_string_lossy().as_ref()),
+            &HSTRING::from(args),
+            PCWSTR::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+    // ShellExecuteW returns a value greater than 32 on success.
+    if result.0 <= 32 {
+        return Err(anyhow::anyhow!(
+            "Failed to restart as administrator (code {})",
+            result.0
+        ));
+    }
+    Ok(())
+}
+
+/// Waits at most `timeout` for the process `pid` to exit. True once it has
+/// exited, or if it was already gone.
+pub fn wait_for_process_exit(pid: u32, timeout: Duration) -> bool {
+    let Ok(process) = (unsafe { OpenProcess(PROCESS_SYNCHRONIZE, false, pid) }) else {
+        return true;
+    };
+    let millis = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+    let exited = unsafe { WaitForSingleObject(process, millis) } == WAIT_OBJECT_0;
+    unsafe {
+        let _ = CloseHandle(process);
+    }
+    exited
+}
+
+fn is_window_elevated(hwnd: HWND) -> bool {
+    let mut pid = 0u32;
+    unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+    if pid == 0 {
+        return false;
+    }
+    let Ok(process) = (unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) })
+    else {
+        // Protected or higher-integrity processes refuse the query.
+        return true;
+    };
+    let elevated = token_u32(process, TokenElevation) != Some(0);
+    unsafe {
+        let _ = CloseHandle(process);
+    }
+    elevated
+}
+
+/// Reads a DWORD-sized token information class of `process`.
+fn token_u32(process: HANDLE, class: TOKEN_INFORMATION_CLASS) -> Option<u32> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(process, TOKEN_QUERY, &mut token).ok()?;
+        let mut value = 0u32;
+        let mut len = 0u32;
+        let result = GetTokenInformation(
+            token,
+            class,
+            Some(&mut value as *mut u32 as *mut c_void),
+            size_of::<u32>() as u32,
+            &mut len,
+        );
+        let _ = CloseHandle(token);
+        result.ok().map(|_| value)
+    }
+}
@@ -0,0 +1,590 @@
+//! Turns resolved tokens into the input events the keyboard hook injects.
+//! The engine decides what to type and hands each token to an [`Emitter`];
+//! [`ScancodeEmitter`] types through the JIS keyboard, [`TextEmitter`]
+//! types text as Unicode, which simulations can read back directly.
+
+use crate::chord_engine::{Profile, SpaceWidth};
+use crate::dynamic_text::DynamicText;
+use crate::types::{InputEvent, KeySpec, KeyStroke, Modifiers, Token};
+use unicode_segmentation::UnicodeSegmentation;
+#[cfg(feature = "os-windows")]
+use windows::Win32::UI::Input::KeyboardAndMouse::{MapVirtualKeyW, MAPVK_VK_TO_VSC_EX};
+
+/// State a token is typed under.
+#[derive(Debug, Clone, Copy)]
+pub struct EmitContext<'a> {
+    pub profile: &'a Profile,
+    /// Shift is physically held, so strokes needing it do not press it.
+    pub shift_held: bool,
+    pub is_japanese: bool,
+    /// CapsLock is on.
+    pub caps_lock: bool,
+    /// The IME is open, so confirmed text is typed with it closed.
+    pub ime_open: bool,
+}
+
+pub trait Emitter: Send {
+    /// Events typing `token`; `None` when it types nothing.
+    fn emit(&self, token: &Token, ctx: &EmitContext) -> Option<Vec<InputEvent>>;
+}
+
+/// Types keys as scancodes and text as Unicode events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScancodeEmitter;
+
+impl Emitter for ScancodeEmitter {
+    fn emit(&self, token: &Token, ctx: &EmitContext) -> Option<Vec<InputEvent>> {
+        let profile = ctx.profile;
+        let caps_lock = ctx.caps_lock && profile.caps_lock_compensation;
+        let mut events = Vec::new();
+        match token {
+            Token::None => return None,
+            Token::KeySequence(seq) => {
+                for stroke in seq {
+                    // Strict scancode only for KeySequence (which now comes from single-quote/bare tokens)
+                    append_keystroke_events(
+                        &mut events,
+                        stroke,
+                        ctx.shift_held,
+                        caps_lock,
+                        false,
+                        ctx.is_japanese,
+                        profile.space_width,
+                    );
+                }
+                if let Some(min_chars) = profile.paste_min_chars {
+                    for event in events.iter_mut() {
+                        if let InputEvent::DirectString(s) = event {
+                            if s.chars().count() >= min_chars {
+                                *event = InputEvent::Paste(std::mem::take(s));
+                            }
+                        }
+                    }
+                }
+            }
+            Token::ImeChar(text) => push_text_events(&mut events, text, profile),
+            Token::DirectChar(text) => {
+                // If IME is ON (Japanese Mode), we must temporarily turn it OFF to force "confirmed" input.
+                // Otherwise, even Unicode events are intercepted by IME as "unconfirmed" text (e.g. Hiragana).
+                let toggled_ime = ctx.is_japanese && ctx.ime_open;
+                if toggled_ime {
+                    events.push(InputEvent::ImeControl(false));
+                }
+
+                push_text_events(&mut events, text, profile);
+
+                if toggled_ime {
+                    events.push(InputEvent::ImeControl(true));
+                }
+            }
+        }
+        (!events.is_empty()).then_some(events)
+    }
+}
+
+/// Types every character as Unicode, IME or not: romaji keys come out as
+/// letters and confirmed strings as their text. Strokes without text
+/// (control keys, shortcuts) are typed as [`ScancodeEmitter`] would.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextEmitter;
+
+impl Emitter for TextEmitter {
+    fn emit(&self, token: &Token, ctx: &EmitContext) -> Option<Vec<InputEvent>> {
+        let mut events = Vec::new();
+        match token {
+            Token::None => return None,
+            Token::KeySequence(seq) => {
+                for stroke in seq {
+                    match stroke_text(stroke, ctx) {
+                        Some(text) => push_text_events(&mut events, &text, ctx.profile),
+                        None => append_keystroke_events(
+                            &mut events,
+                            stroke,
+                            ctx.shift_held,
+                            false,
+                            false,
+                            ctx.is_japanese,
+                            ctx.profile.space_width,
+                        ),
+                    }
+                }
+            }
+            Token::ImeChar(text) | Token::DirectChar(text) => {
+                push_text_events(&mut events, text, ctx.profile)
+            }
+        }
+        (!events.is_empty()).then_some(events)
+    }
+}
+
+/// Text a stroke types, if it is plain text.
+fn stroke_text(stroke: &KeyStroke, ctx: &EmitContext) -> Option<String> {
+    if stroke.mods.ctrl || stroke.mods.alt || stroke.mods.win {
+        return None;
+    }
+    if let Some(c) = fixed_width_space(stroke, ctx.profile.space_width, ctx.is_japanese) {
+        return Some(c.to_string());
+    }
+    match &stroke.key {
+        KeySpec::Char(c) if stroke.mods.shift => Some(c.to_ascii_uppercase().to_string()),
+        KeySpec::Char(c) => Some(c.to_string()),
+        KeySpec::Space(_) => Some(" ".to_string()),
+        KeySpec::DirectString(s) | KeySpec::Paste(s) => Some(s.clone()),
+        KeySpec::Voicing(voicing) => Some(voicing.mark().to_string()),
+        KeySpec::Dynamic(DynamicText::Clipboard) => None,
+        KeySpec::Dynamic(text) => Some(text.expand()),
+        _ => None,
+    }
+}
+
+/// Unicode events per grapheme cluster. Single code points use a
+/// down/up pair; longer clusters are kept together so they are not split.
+fn push_text_events(events: &mut Vec<InputEvent>, text: &str, profile: &Profile) {
+    for grapheme in text.graphemes(true) {
+        let mut chars = grapheme.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                events.push(InputEvent::Unicode(c, false));
+                events.push(InputEvent::Unicode(c, true));
+            }
+            _ if profile.paste_grapheme_clusters => {
+                events.push(InputEvent::Paste(grapheme.to_string()));
+            }
+            _ => events.push(InputEvent::Grapheme(grapheme.to_string())),
+        }
+    }
+}
+
+/// Space to type as a character: `{半角空白}`/`{全角空白}` always, a plain
+/// space only in Japanese input when the profile fixes its width.
+fn fixed_width_space(
+    stroke: &KeyStroke,
+    profile_width: SpaceWidth,
+    is_japanese: bool,
+) -> Option<char> {
+    let width = match stroke.key {
+        KeySpec::Space(width) => width,
+        KeySpec::Char(' ') if is_japanese && stroke.mods == Modifiers::none() => profile_width,
+        _ => return None,
+    };
+    match width {
+        SpaceWidth::Ime => None,
+        SpaceWidth::Half => Some(' '),
+        SpaceWidth::Full => Some('\u{3000}'),
+    }
+}
+
+/// VK_NUMPAD0 to VK_DIVIDE, typed by virtual key whatever NumLock is.
+const KEYPAD_VKS: std::ops::RangeInclusive<u16> = 0x60..=0x6F;
+
+fn append_keystroke_events(
+    events: &mut Vec<InputEvent>,
+    stroke: &KeyStroke,
+    shift_held: bool,
+    caps_lock: bool,
+    allow_unicode_fallback: bool,
+    is_japanese: bool,
+    space_width: SpaceWidth,
+) {
+    if let Some(c) = fixed_width_space(stroke, space_width, is_japanese) {
+        events.push(InputEvent::Unicode(c, false));
+        events.push(InputEvent::Unicode(c, true));
+        return;
+    }
+
+    let key_events = match stroke.key {
+        KeySpec::Scancode(sc, ext) => Some((sc, ext, false)),
+        // Sent by virtual key, which is enough without a scancode.
+        KeySpec::VirtualKey(vk) if KEYPAD_VKS.contains(&vk) => {
+            let (sc, ext) = vk_to_scancode(vk).unwrap_or((0, false));
+            Some((sc, ext, false))
+        }
+        KeySpec::VirtualKey(vk) => vk_to_scancode(vk).map(|(s, e)| (s, e, false)),
+        KeySpec::Char(c) => char_to_scancode(c, is_japanese),
+        KeySpec::ImeOn => {
+            events.push(InputEvent::ImeControl(true));
+            return;
+        }
+        KeySpec::ImeOff => {
+            events.push(InputEvent::ImeControl(false));
+            return;
+        }
+        KeySpec::Ime(command) => {
+            events.push(InputEvent::Ime(command));
+            return;
+        }
+        KeySpec::DirectString(ref s) => {
+            // Hand off the complex IME handling logic to the hook (outside the lock).
+            // This avoids deadlock when calling IME APIs while holding the Engine lock.
+            events.push(InputEvent::DirectString(s.clone()));
+            return;
+        }
+        KeySpec::Paste(ref s) => {
+            events.push(InputEvent::Paste(s.clone()));
+            return;
+        }
+        KeySpec::Dynamic(DynamicText::Clipboard) => {
+            events.push(InputEvent::ClipboardText);
+            return;
+        }
+        KeySpec::Dynamic(ref text) => {
+            events.push(InputEvent::DirectString(text.expand()));
+            return;
+        }
+        // Handled by the engine; types nothing.
+        KeySpec::TenkeyToggle => return,
+        KeySpec::Space(_) => Some((0x39, false, false)),
+        KeySpec::Voicing(voicing) => {
+            // Left only where the output skipped post-processing.
+            events.push(InputEvent::DirectString(voicing.mark().to_string()));
+            return;
+        }
+    };
+
+    if let Some((sc, ext, needs_shift)) = key_events {
+        let mut mods = stroke.mods;
+        if needs_shift {
+            mods.shift = true;
+        }
+        // CapsLock inverts the case of letter keys; undo that with Shift.
+        if caps_lock && matches!(stroke.key, KeySpec::Char(c) if c.is_ascii_alphabetic()) {
+            mods.shift = !mods.shift;
+        }
+
+        if mods.shift && shift_held {
+            mods.shift = false;
+        }
+
+        let mods_evs = modifier_scancodes(mods);
+        for (mod_sc, mod_ext) in mods_evs.iter() {
+            events.push(InputEvent::Scancode(*mod_sc, *mod_ext, false));
+        }
+        match stroke.key {
+            KeySpec::VirtualKey(vk) if KEYPAD_VKS.contains(&vk) => {
+                events.push(InputEvent::VirtualKey(vk, sc, ext, false));
+                events.push(InputEvent::VirtualKey(vk, sc, ext, true));
+            }
+            _ => {
+                events.push(InputEvent::Scancode(sc, ext, false));
+                events.push(InputEvent::Scancode(sc, ext, true));
+            }
+        }
+        for (mod_sc, mod_ext) in mods_evs.iter().rev() {
+            events.push(InputEvent::Scancode(*mod_sc, *mod_ext, true));
+        }
+        return;
+    }
+
+    if allow_unicode_fallback {
+        if let KeySpec::Char(c) = stroke.key {
+            events.push(InputEvent::Unicode(c, false));
+            events.push(InputEvent::Unicode(c, true));
+        }
+    }
+}
+
+fn modifier_scancodes(mods: Modifiers) -> Vec<(u16, bool)> {
+    let mut scancodes = Vec::new();
+    if mods.ctrl {
+        scancodes.push((0x1D, false));
+    }
+    if mods.shift {
+        scancodes.push((0x2A, false));
+    }
+    if mods.alt {
+        scancodes.push((0x38, false));
+    }
+    if mods.win {
+        scancodes.push((0x5B, true));
+    }
+    scancodes
+}
+
+#[cfg(feature = "os-windows")]
+fn vk_to_scancode(vk: u16) -> Option<(u16, bool)> {
+    let scan = unsafe { MapVirtualKeyW(vk as u32, MAPVK_VK_TO_VSC_EX) };
+    if scan == 0 {
+        return None;
+    }
+    let ext = (scan & 0xFF00) == 0xE000;
+    Some(((scan & 0x00FF) as u16, ext))
+}
+
+/// What MapVirtualKeyW answers for the JIS keyboard, for the keys layouts
+/// name by virtual-key code.
+#[cfg(not(feature = "os-windows"))]
+fn vk_to_scancode(vk: u16) -> Option<(u16, bool)> {
+    const LETTERS: [u16; 26] = [
+        0x1E, 0x30, 0x2E, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32, 0x31, 0x18,
+        0x19, 0x10, 0x13, 0x1F, 0x14, 0x16, 0x2F, 0x11, 0x2D, 0x15, 0x2C,
+    ];
+    const NUMPAD: [u16; 10] = [0x52, 0x4F, 0x50, 0x51, 0x4B, 0x4C, 0x4D, 0x47, 0x48, 0x49];
+    let scan = match vk {
+        0x41..=0x5A => LETTERS[(vk - 0x41) as usize],
+        0x30 => 0x0B,
+        0x31..=0x39 => vk - 0x31 + 0x02,
+        0x60..=0x69 => NUMPAD[(vk - 0x60) as usize],
+        0x70..=0x79 => vk - 0x70 + 0x3B, // F1-F10
+        0x7A => 0x57,
+        0x7B => 0x58,
+        0x08 => 0x0E,
+        0x09 => 0x0F,
+        0x0D => 0x1C,
+        0x10 | 0xA0 => 0x2A,
+        0xA1 => 0x36,
+        0x11 | 0xA2 => 0x1D,
+        0x12 | 0xA4 => 0x38,
+        0x14 | 0xF0 => 0x3A,
+        0x15 | 0xF2 => 0x70,
+        0x1B => 0x01,
+        0x1C => 0x79,
+        0x1D => 0x7B,
+        0x20 => 0x39,
+        0x6A => 0x37,
+        0x6B => 0x4E,
+        0x6D => 0x4A,
+        0x6E => 0x53,
+        0xBA => 0x28,
+        0xBB => 0x27,
+        0xBC => 0x33,
+        0xBD => 0x0C,
+        0xBE => 0x34,
+        0xBF => 0x35,
+        0xC0 => 0x1A,
+        0xDB => 0x1B,
+        0xDC => 0x7D,
+        0xDD => 0x2B,
+        0xDE => 0x0D,
+        0xE2 => 0x73,
+        0xF3 | 0xF4 => 0x29,
+        _ => {
+            let ext = match vk {
+                0x21 => 0x49,
+                0x22 => 0x51,
+                0x23 => 0x4F,
+                0x24 => 0x47,
+                0x25 => 0x4B,
+                0x26 => 0x48,
+                0x27 => 0x4D,
+                0x28 => 0x50,
+                0x2D => 0x52,
+                0x2E => 0x53,
+                0x5B => 0x5B,
+                0x5C => 0x5C,
+                0x5D => 0x5D,
+                0x6F => 0x35,
+                0xA3 => 0x1D,
+                0xA5 => 0x38,
+                _ => return None,
+            };
+            return Some((ext, true));
+        }
+    };
+    Some((scan, false))
+}
+
+fn char_to_scancode(c: char, is_japanese: bool) -> Option<(u16, bool, bool)> {
+    // JP-Specific overrides
+    if is_japanese {
+        match c {
+            '、' => return Some((0x33, false, false)), // ,
+            '。' => return Some((0x34, false, false)), // .
+            '・' => return Some((0x35, false, false)), // /
+            '「' => return Some((0x1B, false, false)), // [
+            '」' => return Some((0x2B, false, false)), // ]
+            _ => {}
+        }
+    }
+
+    match c {
+        // Lowercase
+        'a'..='z' => match c {
+            'a' => Some((0x1E, false, false)),
+            'b' => Some((0x30, false, false)),
+            'c' => Some((0x2E, false, false)),
+            'd' => Some((0x20, false, false)),
+            'e' => Some((0x12, false, false)),
+            'f' => Some((0x21, false, false)),
+            'g' => Some((0x22, false, false)),
+            'h' => Some((0x23, false, false)),
+            'i' => Some((0x17, false, false)),
+            'j' => Some((0x24, false, false)),
+            'k' => Some((0x25, false, false)),
+            'l' => Some((0x26, false, false)),
+            'm' => Some((0x32, false, false)),
+            'n' => Some((0x31, false, false)),
+            'o' => Some((0x18, false, false)),
+            'p' => Some((0x19, false, false)),
+            'q' => Some((0x10, false, false)),
+            'r' => Some((0x13, false, false)),
+            's' => Some((0x1F, false, false)),
+            't' => Some((0x14, false, false)),
+            'u' => Some((0x16, false, false)),
+            'v' => Some((0x2F, false, false)),
+            'w' => Some((0x11, false, false)),
+            'x' => Some((0x2D, false, false)),
+            'y' => Some((0x15, false, false)),
+            'z' => Some((0x2C, false, false)),
+            _ => None,
+        },
+        // Uppercase
+        'A'..='Z' => match c.to_ascii_lowercase() {
+            'a' => Some((0x1E, false, true)),
+            'b' => Some((0x30, false, true)),
+            'c' => Some((0x2E, false, true)),
+            'd' => Some((0x20, false, true)),
+            'e' => Some((0x12, false, true)),
+            'f' => Some((0x21, false, true)),
+            'g' => Some((0x22, false, true)),
+            'h' => Some((0x23, false, true)),
+            'i' => Some((0x17, false, true)),
+            'j' => Some((0x24, false, true)),
+            'k' => Some((0x25, false, true)),
+            'l' => Some((0x26, false, true)),
+            'm' => Some((0x32, false, true)),
+            'n' => Some((0x31, false, true)),
+            'o' => Some((0x18, false, true)),
+            'p' => Some((0x19, false, true)),
+            'q' => Some((0x10, false, true)),
+            'r' => Some((0x13, false, true)),
+            's' => Some((0x1F, false, true)),
+            't' => Some((0x14, false, true)),
+            'u' => Some((0x16, false, true)),
+            'v' => Some((0x2F, false, true)),
+            'w' => Some((0x11, false, true)),
+            'x' => Some((0x2D, false, true)),
+            'y' => Some((0x15, false, true)),
+            'z' => Some((0x2C, false, true)),
+            _ => None,
+        },
+        // Numbers
+        '1' => Some((0x02, false, false)),
+        '2' => Some((0x03, false, false)),
+        '3' => Some((0x04, false, false)),
+        '4' => Some((0x05, false, false)),
+        '5' => Some((0x06, false, false)),
+        '6' => Some((0x07, false, false)),
+        '7' => Some((0x08, false, false)),
+        '8' => Some((0x09, false, false)),
+        '9' => Some((0x0A, false, false)),
+        '0' => Some((0x0B, false, false)),
+
+        // Symbols (JIS Standard)
+        '-' => Some((0x0C, false, false)),
+        '^' => Some((0x0D, false, false)),
+        '\\' | '¥' | '￥' => Some((0x7D, false, false)), // Yen (0x7D)
+        '@' => Some((0x1A, false, false)),
+        '[' => Some((0x1B, false, false)),
+        ';' => Some((0x27, false, false)),
+        ':' => Some((0x28, false, false)),
+        ']' => Some((0x2B, false, false)),
+        ',' => Some((0x33, false, false)),
+        '.' => Some((0x34, false, false)),
+        '/' => Some((0x35, false, false)),
+        '_' => Some((0x73, false, true)), // JIS Backslash/Ro (0x73) Shifted
+
+        // Shifted Symbols
+        '!' => Some((0x02, false, true)),  // 1
+        '"' => Some((0x03, false, true)),  // 2
+        '#' => Some((0x04, false, true)),  // 3
+        '$' => Some((0x05, false, true)),  // 4
+        '%' => Some((0x06, false, true)),  // 5
+        '&' => Some((0x07, false, true)),  // 6
+        '\'' => Some((0x08, false, true)), // 7
+        '(' => Some((0x09, false, true)),  // 8
+        ')' => Some((0x0A, false, true)),  // 9
+        // 0 -> nothing
+        '=' => Some((0x0C, false, true)), // -
+        '~' => Some((0x0D, false, true)), // ^
+        '|' => Some((0x7D, false, true)), // Yen
+        '`' => Some((0x1A, false, true)), // @
+        '{' => Some((0x1B, false, true)), // [
+        '+' => Some((0x27, false, true)), // ;
+        '*' => Some((0x28, false, true)), // :
+        '}' => Some((0x2B, false, true)), // ]
+        '<' => Some((0x33, false, true)), // ,
+        '>' => Some((0x34, false, true)), // .
+        '?' => Some((0x35, false, true)), // /
+
+        // Other
+        ' ' => Some((0x39, false, false)),
+        '\u{0008}' => Some((0x0E, false, false)),  // BS
+        '\u{000D}' => Some((0x1C, false, false)),  // Enter
+        '\u{F702}' => Some((0x4B, true, false)),   // Left Arrow (Extended)
+        '\u{F703}' => Some((0x4D, true, false)),   // Right Arrow (Extended)
+        '－' | 'ー' => Some((0x0C, false, false)), // Minus / Long Vowel (Standard Hyphen)
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_key_sequence_expanded;
+
+    #[test]
+    fn test_char_to_scancode() {
+        // Updated to use 2 args (is_japanese=false) and return 3-tuple (sc, ext, shift)
+        assert_eq!(char_to_scancode('－', false), Some((0x0C, false, false)));
+        assert_eq!(char_to_scancode('ー', false), Some((0x0C, false, false)));
+        assert_eq!(char_to_scancode('1', false), Some((0x02, false, false)));
+        assert_eq!(char_to_scancode('a', false), Some((0x1E, false, false)));
+        // Shifted char
+        assert_eq!(char_to_scancode('!', false), Some((0x02, false, true)));
+        // Japanese punctuation
+        assert_eq!(char_to_scancode('。', true), Some((0x34, false, false)));
+        assert_eq!(char_to_scancode('。', false), None); // Should fallback to unicode if not JP mode scancode mapping
+    }
+
+    #[test]
+    fn test_text_emitter_types_romaji_as_letters() {
+        let profile = Profile::default();
+        let ctx = EmitContext {
+            profile: &profile,
+            shift_held: false,
+            is_japanese: true,
+            caps_lock: false,
+            ime_open: false,
+        };
+        let token = Token::KeySequence(parse_key_sequence_expanded("ka"));
+
+        let scancodes = ScancodeEmitter.emit(&token, &ctx).unwrap();
+        assert_eq!(scancodes[0], InputEvent::Scancode(0x25, false, false));
+
+        let text = TextEmitter.emit(&token, &ctx).unwrap();
+        assert_eq!(
+            text,
+            vec![
+                InputEvent::Unicode('k', false),
+                InputEvent::Unicode('k', true),
+                InputEvent::Unicode('a', false),
+                InputEvent::Unicode('a', true),
+            ]
+        );
+        assert_eq!(TextEmitter.emit(&Token::None, &ctx), None);
+    }
+
+    #[test]
+    fn test_direct_char_closes_the_ime_only_while_it_is_open() {
+        let profile = Profile::default();
+        let mut ctx = EmitContext {
+            profile: &profile,
+            shift_held: false,
+            is_japanese: true,
+            caps_lock: false,
+            ime_open: true,
+        };
+        let token = Token::DirectChar("A".to_string());
+
+        let events = ScancodeEmitter.emit(&token, &ctx).unwrap();
+        assert_eq!(events.first(), Some(&InputEvent::ImeControl(false)));
+        assert_eq!(events.last(), Some(&InputEvent::ImeControl(true)));
+
+        ctx.ime_open = false;
+        let events = ScancodeEmitter.emit(&token, &ctx).unwrap();
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, InputEvent::ImeControl(_))));
+    }
+}
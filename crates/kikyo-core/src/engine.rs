@@ -1,31 +1,55 @@
 use crate::chord_engine::{
-    ChordEngine, Decision, ImeMode, KeyEdge, KeyEvent, PendingKey, Profile, EXTENDED_KEY_1_SC,
-    EXTENDED_KEY_2_SC, EXTENDED_KEY_3_SC, EXTENDED_KEY_4_SC,
+    apply_output_rules, ChordEngine, CompositionPolicy, Decision, FocusChangePolicy, ImeMode,
+    KeyEdge, KeyEvent, OverlapOverrides, PendingKey, Profile, ThumbKeys, ThumbShiftSinglePress,
+    UndefinedKeyPolicy, EXTENDED_KEY_1_SC, EXTENDED_KEY_2_SC, EXTENDED_KEY_3_SC, EXTENDED_KEY_4_SC,
 };
-use crate::types::{InputEvent, KeyAction, KeySpec, KeyStroke, Layout, Modifiers, ScKey, Token};
+use crate::chord_test::ChordTest;
+use crate::clock::{Clock, SystemClock};
+use crate::compiled_layout::{
+    CompiledLayout, TokenId, LONG_PRESS_TAG, NONE_TOKEN, NUMBER_SHIFT_SECTION_INFIX,
+};
+use crate::crash_report::{DecisionLog, EngineSnapshot};
+use crate::dual_role::{DualRoleAction, DualRoleKeys};
+use crate::dynamic_text;
+use crate::emitter::{EmitContext, Emitter, ScancodeEmitter};
+use crate::feedback::{self, FeedbackEvent};
+use crate::foreground::exe_file_name;
+use crate::handle::{self, EngineEvent};
+use crate::inspect::EngineState;
+use crate::jis_map::key_from_name;
+use crate::key_swap::{
+    build_function_key_swap_map, has_shift_swaps, FunctionKeySwapTarget, KeySwapIssue, SwapMap,
+};
+use crate::layout_coverage::{self, CoverageTemplate, LayoutCoverage};
+use crate::parser::{
+    has_conditional_sections, resolve_conditional_sections, section_condition_states,
+};
+use crate::recorder;
+use crate::resolver::{Resolver, ThumbPlanes};
+use crate::scripting::{self, ScriptCall, ScriptOutcome};
+use crate::section_selector::{SectionSelector, SectionShift};
+use crate::stats::{LayoutSuggestions, UsageHeatmap, UsageStats};
+use crate::tenkey;
+use crate::trace::{keys_label, token_text, TraceEvent, TraceKind};
+use crate::training::{TrainingReport, TrainingSession};
+use crate::types::{
+    HeldModifier, InputEvent, KeyAction, KeySpec, KeyStroke, Layout, LockKey, LockState,
+    ModifierPlane, Modifiers, RawKeyEvent, Rc, ScKey, ShiftSide, Token,
+};
+use crate::undo::UndoTracker;
+use crate::virtual_keyboard::{self, VirtualKeyboard};
+use crate::voicing;
 use crate::JIS_SC_TO_RC;
 use parking_lot::Mutex;
-use std::cell::RefCell;
+use smallvec::SmallVec;
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::debug;
-use windows::Win32::UI::Input::KeyboardAndMouse::{MapVirtualKeyW, MAPVK_VK_TO_VSC_EX};
 
 lazy_static::lazy_static! {
-    pub static ref ENGINE: Mutex<Engine> = Mutex::new(Engine::default());
-}
-
-#[derive(Debug, Clone, Copy)]
-enum FunctionKeySwapTarget {
-    Key(ScKey),
-    CapsLock,
-    KanaLock,
-}
-
-#[derive(Debug, Clone, Copy)]
-enum FunctionPseudoKey {
-    CapsLock,
-    KanaLock,
+    pub static ref ENGINE: Mutex<Engine> = Mutex::new(Engine::publishing());
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -40,65 +64,88 @@ struct DeferredEnterRollover {
     source_key: ScKey,
     pass_through: PassThroughCurrent,
     wait_for: ScKey,
+    started: Instant,
     down_emitted: bool,
     up_seen_while_waiting: bool,
 }
 
-const EXTENDED_THUMB_SHIFT_1_SECTION: &str =
-    "\u{62e1}\u{5f35}\u{89aa}\u{6307}\u{30b7}\u{30d5}\u{30c8}1";
-const EXTENDED_THUMB_SHIFT_2_SECTION: &str =
-    "\u{62e1}\u{5f35}\u{89aa}\u{6307}\u{30b7}\u{30d5}\u{30c8}2";
-
-thread_local! {
-    static SECTION_NAME_SCRATCH: RefCell<String> = RefCell::new(String::with_capacity(64));
-    static TAG_NAME_SCRATCH: RefCell<String> = RefCell::new(String::with_capacity(32));
-    static DOUBLE_TAG_NAME_SCRATCH: RefCell<String> = RefCell::new(String::with_capacity(48));
-}
-
-fn with_section_name<T>(prefix: &str, suffix: &str, f: impl FnOnce(&str) -> T) -> T {
-    SECTION_NAME_SCRATCH.with(|cell| {
-        let mut buf = cell.borrow_mut();
-        buf.clear();
-        buf.push_str(prefix);
-        buf.push_str(suffix);
-        f(buf.as_str())
-    })
-}
-
-fn with_single_tag<T>(name: &str, f: impl FnOnce(&str) -> T) -> T {
-    TAG_NAME_SCRATCH.with(|cell| {
-        let mut buf = cell.borrow_mut();
-        buf.clear();
-        buf.push('<');
-        buf.push_str(name);
-        buf.push('>');
-        f(buf.as_str())
-    })
+/// How [`Engine::reload_layout`] applied the edited layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutReload {
+    /// Only cell contents changed; keys in flight were kept.
+    HotSwapped,
+    /// Loaded afresh, dropping keys in flight.
+    Replaced,
 }
 
-fn with_double_tag<T>(name1: &str, name2: &str, f: impl FnOnce(&str) -> T) -> T {
-    DOUBLE_TAG_NAME_SCRATCH.with(|cell| {
-        let mut buf = cell.borrow_mut();
-        buf.clear();
-        buf.push('<');
-        buf.push_str(name1);
-        buf.push('>');
-        buf.push('<');
-        buf.push_str(name2);
-        buf.push('>');
-        f(buf.as_str())
-    })
-}
+/// Pressing it counts the last output as undone in the usage statistics.
+const BACKSPACE_KEY: ScKey = ScKey::new(0x0E, false);
 
 pub struct Engine {
     chord_engine: ChordEngine,
     enabled: bool,
     layout: Option<Layout>,
-    on_enabled_change: Option<Box<dyn Fn(bool) + Send + Sync>>,
+    /// `layout` compiled for the resolver. Shared so that a token looked
+    /// up in it can be used while the engine changes.
+    compiled_layout: Option<Arc<CompiledLayout>>,
+    /// Layout as loaded while it has `[section@flag]` sections, which are
+    /// resolved again when the profile changes their outcome.
+    conditional_layout: Option<(Layout, Vec<bool>)>,
     repeat_plans: HashMap<ScKey, Vec<ScKey>>,
     pending_nonshift_for_shift: HashSet<ScKey>,
-    function_key_swaps: HashMap<ScKey, FunctionKeySwapTarget>,
+    function_key_swaps: SwapMap,
+    /// Whether Shift was held when each key down was swapped, while some
+    /// key is swapped differently with Shift, so its release matches.
+    swap_shift_at_press: Option<HashMap<ScKey, bool>>,
+    /// What is wrong with the layout's and the profile's key swaps.
+    key_swap_issues: Vec<KeySwapIssue>,
+    /// `[配置換え]`: physical key -> key it acts as.
+    key_remaps: HashMap<ScKey, ScKey>,
+    /// Physical keys from `Profile::pass_through_keys`.
+    pass_through_keys: HashSet<ScKey>,
+    dual_role: DualRoleKeys,
+    /// The virtual numeric keypad is on.
+    tenkey: bool,
+    /// The IME candidate window is open; only tracked for a `[候補選択]` layout.
+    candidate_window: bool,
+    /// (modifier, target) pairs whose `<modifier>` plane is tried first.
+    chord_priorities: HashSet<(ScKey, ScKey)>,
+    sections: SectionSelector,
+    locks: LockState,
+    /// Locks toggled an odd number of times since [`Engine::take_lock_toggles`].
+    lock_toggles: LockState,
     deferred_enter_rollover: Option<DeferredEnterRollover>,
+    /// Side of the OS Shift held with the event being processed.
+    shift_side: ShiftSide,
+    /// `ModifierPlane` bits of the `<Ctrl>`/`<Alt>`/`<Win>` planes the layout defines.
+    modifier_planes: u8,
+    /// Keys whose press was output through a modifier plane; their release is blocked.
+    modified_keys: HashSet<ScKey>,
+    undo: UndoTracker,
+    /// Press order of each key, to emit outputs in the order keys went down.
+    press_order: HashMap<ScKey, u64>,
+    next_press: u64,
+    /// Outputs held back until the keys pressed before them are resolved.
+    held_decisions: Vec<(u64, Decision)>,
+    /// Decisions of the event being processed, before and after
+    /// sequencing. Kept to reuse their buffers.
+    decision_buf: Vec<Decision>,
+    sequenced_buf: Vec<Decision>,
+    /// Executable name of the foreground application.
+    foreground_app: Option<String>,
+    /// `[アプリ別]` tag of the foreground application.
+    app_tag: Option<String>,
+    emitter: Box<dyn Emitter>,
+    trace_enabled: bool,
+    training: Option<TrainingSession>,
+    /// Running chord test; outputs are published as
+    /// `EngineEvent::ChordTest` instead of being typed.
+    chord_test: Option<ChordTest>,
+    usage: UsageStats,
+    decisions: DecisionLog,
+    clock: Arc<dyn Clock>,
+    /// Events go to [`handle::EngineHandle::subscribe`]; only for [`ENGINE`].
+    publishes: bool,
 }
 
 impl Default for Engine {
@@ -109,35 +156,226 @@ impl Default for Engine {
             chord_engine: ChordEngine::new(profile),
             enabled: true,
             layout: None,
-            on_enabled_change: None,
+            compiled_layout: None,
+            conditional_layout: None,
             repeat_plans: HashMap::new(),
             pending_nonshift_for_shift: HashSet::new(),
             function_key_swaps: HashMap::new(),
+            swap_shift_at_press: None,
+            key_swap_issues: Vec::new(),
+            key_remaps: HashMap::new(),
+            pass_through_keys: HashSet::new(),
+            dual_role: DualRoleKeys::default(),
+            tenkey: false,
+            candidate_window: false,
+            chord_priorities: HashSet::new(),
+            sections: SectionSelector::default(),
+            locks: LockState::default(),
+            lock_toggles: LockState::default(),
             deferred_enter_rollover: None,
+            shift_side: ShiftSide::None,
+            modifier_planes: 0,
+            modified_keys: HashSet::new(),
+            undo: UndoTracker::default(),
+            press_order: HashMap::new(),
+            next_press: 0,
+            held_decisions: Vec::new(),
+            decision_buf: Vec::new(),
+            sequenced_buf: Vec::new(),
+            foreground_app: None,
+            app_tag: None,
+            emitter: Box::new(ScancodeEmitter),
+            trace_enabled: false,
+            training: None,
+            chord_test: None,
+            usage: UsageStats::default(),
+            decisions: DecisionLog::default(),
+            clock: Arc::new(SystemClock),
+            publishes: false,
         }
     }
 }
 
 impl Engine {
+    /// An engine whose events go to the subscribers of [`handle`], as
+    /// [`ENGINE`]'s do. Other engines keep their events to themselves.
+    pub(crate) fn publishing() -> Self {
+        Self {
+            publishes: true,
+            ..Self::default()
+        }
+    }
+
+    fn publish(&self, event: impl FnOnce() -> EngineEvent) {
+        if self.publishes {
+            handle::publish(event);
+        }
+    }
+
     pub fn set_enabled(&mut self, enabled: bool) {
         if self.enabled != enabled {
             self.enabled = enabled;
             if !enabled {
-                // Reset state without discarding the user's profile.
-                let profile = self.chord_engine.profile.clone();
-                self.chord_engine = ChordEngine::new(profile);
-                self.repeat_plans.clear();
-                self.pending_nonshift_for_shift.clear();
-                self.deferred_enter_rollover = None;
+                self.reset_input_state();
+                self.dual_role.reset();
             }
-            if let Some(ref cb) = self.on_enabled_change {
-                cb(enabled);
+            self.publish(|| EngineEvent::EnabledChanged { enabled });
+        }
+    }
+
+    /// Reset state without discarding the user's profile.
+    fn reset_input_state(&mut self) {
+        let profile = self.chord_engine.profile.clone();
+        self.chord_engine = ChordEngine::new(profile);
+        self.repeat_plans.clear();
+        self.pending_nonshift_for_shift.clear();
+        self.modified_keys.clear();
+        self.deferred_enter_rollover = None;
+        self.undo.clear();
+        self.held_decisions.clear();
+    }
+
+    /// Called when the foreground window changes. Keys still waiting for a
+    /// chord are dropped or, with `FocusChangePolicy::Emit`, returned as
+    /// taps to inject before the new window receives input. `exe` is the
+    /// new foreground application, looked up before locking the engine.
+    pub fn on_focus_change(&mut self, exe: Option<String>) -> Vec<InputEvent> {
+        self.set_foreground_app(exe);
+        // The hook only reports other processes coming to the foreground,
+        // whose keys a chord test must not swallow.
+        self.end_chord_test();
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        if self.chord_engine.profile.focus_change == FocusChangePolicy::Emit {
+            let is_japanese =
+                crate::ime::is_japanese_input_active(self.chord_engine.profile.ime_mode);
+            let pending = self.chord_engine.flush_all_pending();
+            let mut decisions = Vec::new();
+            self.sequence_decisions(pending, &mut decisions);
+            for d in decisions {
+                let Decision::KeyTap(k) = d else {
+                    continue;
+                };
+                if self.repeat_plans.contains_key(&k) {
+                    continue;
+                }
+                match self
+                    .resolve(&[k], false, is_japanese)
+                    .and_then(|token| self.token_to_events_with_ime(&token, false, is_japanese))
+                {
+                    Some(ops) => events.extend(ops),
+                    None => {
+                        events.push(InputEvent::Scancode(k.sc, k.ext, false));
+                        events.push(InputEvent::Scancode(k.sc, k.ext, true));
+                    }
+                }
             }
         }
+        self.reset_input_state();
+        events
+    }
+
+    /// Sets the foreground application, whose `[アプリ別]` sections then
+    /// override the layout's cells.
+    pub fn set_foreground_app(&mut self, exe: Option<String>) {
+        self.app_tag = self
+            .layout
+            .as_ref()
+            .and_then(|layout| app_section_tag(&layout.app_sections, exe.as_deref()));
+        self.foreground_app = exe;
+    }
+
+    /// Replaces the time source used for key timestamps.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Replaces how resolved tokens become input events.
+    pub fn set_emitter(&mut self, emitter: impl Emitter + 'static) {
+        self.emitter = Box::new(emitter);
+    }
+
+    /// Enables publishing of resolved outputs as `EngineEvent::Trace`.
+    /// Outputs are always traced while a training session is running.
+    pub fn set_trace_enabled(&mut self, enabled: bool) {
+        self.trace_enabled = enabled;
+    }
+
+    /// Starts a training session, replacing any running one.
+    pub fn start_training(&mut self, target: &str) {
+        self.training = Some(TrainingSession::new(target));
+    }
+
+    /// Ends the training session and returns its final report.
+    pub fn stop_training(&mut self) -> Option<TrainingReport> {
+        self.training.take().map(|session| session.report())
+    }
+
+    /// Starts a chord test: until [`Self::end_chord_test`], outputs are
+    /// published as `EngineEvent::ChordTest` and nothing is typed. It also
+    /// ends when another application comes to the foreground and when no
+    /// key is pressed for a while.
+    pub fn begin_chord_test(&mut self) {
+        self.reset_input_state();
+        self.chord_test = Some(ChordTest::new(self.clock.now()));
+    }
+
+    pub fn end_chord_test(&mut self) {
+        if self.chord_test.take().is_some() {
+            self.reset_input_state();
+            self.publish(|| EngineEvent::ChordTestEnded);
+        }
+    }
+
+    /// When the running chord test ends for lack of key presses.
+    pub fn chord_test_deadline(&self) -> Option<Instant> {
+        self.chord_test.as_ref().map(ChordTest::idle_deadline)
+    }
+
+    pub fn is_chord_testing(&self) -> bool {
+        self.chord_test.is_some()
+    }
+
+    pub fn training_report(&self) -> Option<TrainingReport> {
+        self.training.as_ref().map(|session| session.report())
+    }
+
+    pub fn usage_heatmap(&self) -> UsageHeatmap {
+        self.usage.heatmap()
+    }
+
+    pub fn reset_usage_stats(&mut self) {
+        self.usage.clear();
+    }
+
+    /// The keyboard with the roles the current profile gives its keys.
+    pub fn virtual_keyboard(&self) -> VirtualKeyboard {
+        virtual_keyboard::keyboard_model(&self.chord_engine.profile)
+    }
+
+    /// See [`crate::layout_coverage`]. `None` while no layout is loaded.
+    pub fn layout_coverage(&self, template: CoverageTemplate) -> Option<LayoutCoverage> {
+        self.layout
+            .as_ref()
+            .map(|layout| layout_coverage::layout_coverage(layout, template))
     }
 
-    pub fn set_on_enabled_change(&mut self, cb: impl Fn(bool) + Send + Sync + 'static) {
-        self.on_enabled_change = Some(Box::new(cb));
+    /// Frequent unchorded key sequences and often-erased chords, checked
+    /// against the loaded layout in either IME mode.
+    pub fn layout_suggestions(&self, limit: usize) -> LayoutSuggestions {
+        self.usage.suggestions(
+            |keys| {
+                let reversed: Vec<ScKey> = keys.iter().rev().copied().collect();
+                let defined = |order: &[ScKey]| {
+                    self.resolve(order, false, true).is_some()
+                        || self.resolve(order, false, false).is_some()
+                };
+                defined(keys) || defined(&reversed)
+            },
+            limit,
+        )
     }
 
     pub fn set_ignore_ime(&mut self, ignore: bool) {
@@ -148,6 +386,26 @@ impl Engine {
         };
     }
 
+    pub fn lock_state(&self) -> LockState {
+        self.locks
+    }
+
+    /// Overrides the tracked lock state (e.g. after the OS state is known).
+    pub fn set_lock_state(&mut self, locks: LockState) {
+        self.locks = locks;
+    }
+
+    /// Overrides the tracked IME candidate window state.
+    pub fn set_candidate_window_open(&mut self, open: bool) {
+        self.candidate_window = open;
+    }
+
+    /// Locks the injected output toggled since the last call, for the hook
+    /// to check that the OS followed.
+    pub fn take_lock_toggles(&mut self) -> LockState {
+        std::mem::take(&mut self.lock_toggles)
+    }
+
     pub fn set_ime_mode(&mut self, mode: ImeMode) {
         self.chord_engine.profile.ime_mode = mode;
     }
@@ -172,26 +430,35 @@ impl Engine {
         self.chord_engine.profile.suspend_key
     }
 
+    pub fn get_injection(&self) -> crate::chord_engine::InjectionCfg {
+        self.chord_engine.profile.injection
+    }
+
+    pub fn get_panic_key(&self) -> crate::chord_engine::PanicKeyCfg {
+        self.chord_engine.profile.panic_key
+    }
+
+    /// See [`crate::key_swap`].
+    pub fn key_swap_issues(&self) -> &[KeySwapIssue] {
+        &self.key_swap_issues
+    }
+
     pub fn needs_alt_handling(&self) -> bool {
         let left_alt = ScKey::new(0x38, false);
         let right_alt = ScKey::new(0x38, true);
 
-        if self.function_key_swaps.contains_key(&left_alt)
-            || self.function_key_swaps.contains_key(&right_alt)
+        if self
+            .function_key_swaps
+            .keys()
+            .any(|(key, _)| *key == left_alt || *key == right_alt)
+            || self.key_remaps.contains_key(&left_alt)
+            || self.key_remaps.contains_key(&right_alt)
         {
             return true;
         }
 
         if let Some(ref tk) = self.chord_engine.profile.thumb_keys {
-            if tk.left.contains(&left_alt)
-                || tk.left.contains(&right_alt)
-                || tk.right.contains(&left_alt)
-                || tk.right.contains(&right_alt)
-                || tk.ext1.contains(&left_alt)
-                || tk.ext1.contains(&right_alt)
-                || tk.ext2.contains(&left_alt)
-                || tk.ext2.contains(&right_alt)
-            {
+            if tk.contains(&left_alt) || tk.contains(&right_alt) {
                 return true;
             }
         }
@@ -207,38 +474,78 @@ impl Engine {
 
     fn has_thumb_shift_sections_in_layout(&self) -> bool {
         if let Some(ref layout) = self.layout {
-            let targets = [
-                "ローマ字左親指シフト",
-                "ローマ字右親指シフト",
-                "英数左親指シフト",
-                "英数右親指シフト",
-            ];
-            for t in &targets {
-                if layout.sections.keys().any(|k| k.starts_with(t)) {
-                    return true;
-                }
-            }
-            if layout
+            let has_thumb_section = self
                 .sections
-                .contains_key("\u{62e1}\u{5f35}\u{89aa}\u{6307}\u{30b7}\u{30d5}\u{30c8}1")
-                || layout
-                    .sections
-                    .contains_key("\u{62e1}\u{5f35}\u{89aa}\u{6307}\u{30b7}\u{30d5}\u{30c8}2")
-            {
+                .thumb_section_names()
+                .any(|name| layout.sections.keys().any(|k| k.starts_with(name)));
+            if has_thumb_section {
+                return true;
+            }
+            let has_extra_section = self.chord_engine.profile.extra_thumbs.iter().any(|cfg| {
+                !cfg.suffix.is_empty() && layout.sections.keys().any(|k| k.ends_with(&cfg.suffix))
+            });
+            if has_extra_section || self.has_number_shift_sections_in_layout() {
                 return true;
             }
         }
         false
     }
 
+    fn has_number_shift_sections_in_layout(&self) -> bool {
+        self.layout.as_ref().is_some_and(|layout| {
+            layout
+                .sections
+                .keys()
+                .any(|k| k.contains(NUMBER_SHIFT_SECTION_INFIX) && k.ends_with("シフト"))
+        })
+    }
+
     pub fn set_profile(&mut self, mut profile: Profile) {
         // Update thumb keys based on mode
         profile.update_thumb_keys();
+        self.pass_through_keys = profile
+            .pass_through_keys
+            .iter()
+            .filter_map(|name| key_from_name(name))
+            .collect();
+        self.dual_role.configure(
+            profile
+                .dual_role_keys
+                .iter()
+                .filter_map(|cfg| Some((key_from_name(&cfg.key)?, cfg.modifier, cfg.hold_ms))),
+        );
+        let layout_swaps = self
+            .layout
+            .as_ref()
+            .map_or(&[][..], |layout| &layout.function_key_swaps);
+        let (swaps, issues) = build_function_key_swap_map(layout_swaps, &profile.key_swaps);
+        if issues != self.key_swap_issues {
+            for issue in &issues {
+                tracing::warn!("Key swap: {:?}", issue);
+            }
+        }
+        self.swap_shift_at_press = has_shift_swaps(&swaps).then(HashMap::new);
+        self.function_key_swaps = swaps;
+        self.key_swap_issues = issues;
 
         // Pattern 1: If layout does not have thumb shift sections, disable thumb keys.
         // This ensures they act as normal keys if the layout doesn't support thumb shift.
+        // Pinky Shift only needs the `小指` sections and is kept.
         if self.layout.is_some() && !self.has_thumb_shift_sections_in_layout() {
-            profile.thumb_keys = None;
+            profile.thumb_keys = profile
+                .thumb_keys
+                .take()
+                .filter(|tk| !tk.shift.is_empty())
+                .map(|tk| ThumbKeys {
+                    shift: tk.shift,
+                    ..ThumbKeys::default()
+                });
+        }
+        // Number-row keys stay normal keys unless the layout has their sections.
+        if self.layout.is_some() && !self.has_number_shift_sections_in_layout() {
+            if let Some(ref mut tk) = profile.thumb_keys {
+                tk.number.clear();
+            }
         }
 
         // Preserve layout-derived data if missing in new profile
@@ -249,18 +556,36 @@ impl Engine {
         if profile.trigger_keys.is_empty() && !current.trigger_keys.is_empty() {
             profile.trigger_keys = current.trigger_keys.clone();
         }
+        if profile.overlap_overrides.is_empty() {
+            profile.overlap_overrides = current.overlap_overrides.clone();
+        }
+        if profile.long_press_keys.is_empty() {
+            profile.long_press_keys = current.long_press_keys.clone();
+        }
 
         // Ensure new thumb keys are in target list
         if let Some(ref mut targets) = profile.target_keys {
             if let Some(ref tk) = profile.thumb_keys {
-                targets.extend(tk.left.iter());
-                targets.extend(tk.right.iter());
-                targets.extend(tk.ext1.iter());
-                targets.extend(tk.ext2.iter());
+                targets.extend(tk.iter());
+            }
+            if profile.undo.enabled {
+                targets.extend(profile.undo.chord.iter());
             }
         }
 
         self.chord_engine.set_profile(profile);
+
+        let reload = self
+            .conditional_layout
+            .as_ref()
+            .and_then(|(source, states)| {
+                let profile = &self.chord_engine.profile;
+                let current = section_condition_states(source, |flag| profile.layout_flag(flag));
+                (current != *states).then(|| source.clone())
+            });
+        if let Some(source) = reload {
+            self.load_layout(source);
+        }
     }
 
     pub fn load_layout(&mut self, layout: Layout) {
@@ -268,7 +593,31 @@ impl Engine {
             "Engine: Layout loaded with {} sections.",
             layout.sections.len()
         );
-        self.function_key_swaps = build_function_key_swap_map(&layout.function_key_swaps);
+        let mut layout = if has_conditional_sections(&layout) {
+            let profile = &self.chord_engine.profile;
+            let flag = |flag: &str| profile.layout_flag(flag);
+            let resolved = resolve_conditional_sections(&layout, flag);
+            let states = section_condition_states(&layout, flag);
+            self.conditional_layout = Some((layout, states));
+            resolved
+        } else {
+            self.conditional_layout = None;
+            layout
+        };
+        tenkey::merge_tenkey_section(&mut layout);
+        self.key_remaps = build_key_remap_map(&layout.key_remaps);
+        self.chord_priorities = build_chord_priority_set(&layout.chord_priorities);
+        self.sections = SectionSelector::with_overrides(&layout.section_names);
+        self.app_tag = app_section_tag(&layout.app_sections, self.foreground_app.as_deref());
+        self.modifier_planes = ModifierPlane::ALL
+            .into_iter()
+            .filter(|plane| {
+                layout
+                    .sections
+                    .values()
+                    .any(|section| section.sub_planes.contains_key(plane.tag()))
+            })
+            .fold(0, |mask, plane| mask | plane.bit());
 
         let mut profile = self.chord_engine.profile.clone();
         profile.max_chord_size = if layout.max_chord_size >= 3 { 3 } else { 2 };
@@ -302,77 +651,418 @@ impl Engine {
         }
 
         profile.trigger_keys.clear();
-
-        // MVP: Detect trigger keys from "<...>" sections and sub-planes.
+        // Set on the current profile too so that set_profile does not keep
+        // the previous layout's table when this one has none.
+        profile.overlap_overrides = build_overlap_overrides(&layout.overlap_overrides);
+        self.chord_engine.profile.overlap_overrides = profile.overlap_overrides.clone();
+        profile.long_press_keys = long_press_keys(&layout);
+        self.chord_engine.profile.long_press_keys = profile.long_press_keys.clone();
+
+        // Trigger keys from "<...>" sections and sub-planes, as the parser
+        // indexed them.
         for (name, section) in layout.sections.iter() {
-            // tracing::info!(" - Section: {}", name);
-            // Parse "<A><B>" style tags
-            let mut start = 0;
-            while let Some(open) = name[start..].find('<') {
-                if let Some(close) = name[start + open..].find('>') {
-                    let inner = &name[start + open + 1..start + open + close];
-                    if let Some(sc) = crate::jis_map::key_name_to_sc(inner) {
-                        let key = ScKey::new(sc, false);
-                        if !profile.trigger_keys.contains_key(&key) {
-                            profile.trigger_keys.insert(key, name.clone());
-                            tracing::info!(
-                                "   -> Registered TriggerKey: {} (sc={:02X}) from {}",
-                                inner,
-                                sc,
-                                name
-                            );
-                        }
-                        target_keys.insert(key);
-                    }
-                    start += open + close + 1;
-                } else {
-                    break;
-                }
-            }
-
-            for tag in section.sub_planes.keys() {
-                let mut start = 0;
-                while let Some(open) = tag[start..].find('<') {
-                    if let Some(close) = tag[start + open..].find('>') {
-                        let inner = &tag[start + open + 1..start + open + close];
-                        if let Some(sc) = crate::jis_map::key_name_to_sc(inner) {
-                            let key = ScKey::new(sc, false);
-                            if !profile.trigger_keys.contains_key(&key) {
-                                profile.trigger_keys.insert(key, tag.clone());
-                                tracing::info!(
-                                    "   -> Registered TriggerKey: {} (sc={:02X}) from subplane {}",
-                                    inner,
-                                    sc,
-                                    tag
-                                );
-                            }
-                            target_keys.insert(key);
-                        }
-                        start += open + close + 1;
-                    } else {
-                        break;
+            let tagged =
+                std::iter::once((name, &section.name_keys)).chain(section.sub_plane_keys.iter());
+            for (tag, keys) in tagged {
+                for &key in keys {
+                    if !profile.trigger_keys.contains_key(&key) {
+                        profile.trigger_keys.insert(key, tag.clone());
+                        tracing::info!(
+                            "   -> Registered TriggerKey: {} (sc={:02X}) from {}",
+                            crate::jis_map::key_name(key).unwrap_or_default(),
+                            key.sc,
+                            tag
+                        );
                     }
+                    target_keys.insert(key);
                 }
             }
         }
 
         // Add thumb keys if any (currently handled via profile manually or elsewhere, but let's ensure)
         if let Some(ref tk) = profile.thumb_keys {
-            target_keys.extend(tk.left.iter());
-            target_keys.extend(tk.right.iter());
-            target_keys.extend(tk.ext1.iter());
-            target_keys.extend(tk.ext2.iter());
+            target_keys.extend(tk.iter());
         }
 
         profile.target_keys = Some(target_keys);
 
         // Update layout FIRST so set_profile can check it
+        self.compiled_layout = Some(Arc::new(CompiledLayout::new(&layout, &self.sections)));
         self.layout = Some(layout);
         // Then set profile (processes logic to disable thumb keys if needed)
         self.set_profile(profile);
+        let name = self.layout.as_ref().and_then(|layout| layout.name.clone());
+        self.publish(|| EngineEvent::LayoutLoaded { name });
+    }
+
+    /// Applies an edited version of the loaded layout. When only what the
+    /// cells type changed, held keys and keys waiting for a chord carry on
+    /// under the new cells; otherwise the layout is loaded afresh.
+    pub fn reload_layout(&mut self, mut layout: Layout) -> LayoutReload {
+        tenkey::merge_tenkey_section(&mut layout);
+        let compatible = self.conditional_layout.is_none()
+            && !has_conditional_sections(&layout)
+            && self
+                .layout
+                .as_ref()
+                .is_some_and(|current| same_layout_structure(current, &layout));
+        if !compatible {
+            self.load_layout(layout);
+            self.reset_input_state();
+            return LayoutReload::Replaced;
+        }
+
+        tracing::info!(
+            "Engine: Layout hot-swapped with {} sections.",
+            layout.sections.len()
+        );
+        self.chord_priorities = build_chord_priority_set(&layout.chord_priorities);
+        self.app_tag = app_section_tag(&layout.app_sections, self.foreground_app.as_deref());
+        self.chord_engine.profile.overlap_overrides =
+            build_overlap_overrides(&layout.overlap_overrides);
+        self.chord_engine.profile.long_press_keys = long_press_keys(&layout);
+        let name = layout.name.clone();
+        self.compiled_layout = Some(Arc::new(CompiledLayout::new(&layout, &self.sections)));
+        self.layout = Some(layout);
+        self.publish(|| EngineEvent::LayoutLoaded { name });
+        LayoutReload::HotSwapped
+    }
+
+    pub fn is_tenkey_on(&self) -> bool {
+        self.tenkey
+    }
+
+    /// Turns the virtual numeric keypad on or off. Without a layout, one
+    /// holding only the keypad is loaded while it is on.
+    pub fn set_tenkey(&mut self, on: bool) {
+        if self.tenkey == on {
+            return;
+        }
+        self.tenkey = on;
+        tracing::info!("Engine: Virtual tenkey {}.", if on { "on" } else { "off" });
+        if on && self.layout.is_none() {
+            self.load_layout(Layout::default());
+        } else if !on && self.layout.as_ref().is_some_and(tenkey::is_tenkey_only) {
+            self.layout = None;
+            self.compiled_layout = None;
+            self.conditional_layout = None;
+            self.reset_input_state();
+        }
     }
 
     pub fn process_key(&mut self, sc: u16, ext: bool, up: bool, shift: bool) -> KeyAction {
+        self.process_key_with_shift(sc, ext, up, shift.into())
+    }
+
+    /// [`Self::process_key`] with the side of the held Shift, so that
+    /// `[ローマ字左小指シフト]` / `[ローマ字右小指シフト]` can be chosen.
+    pub fn process_key_with_shift(
+        &mut self,
+        sc: u16,
+        ext: bool,
+        up: bool,
+        shift: ShiftSide,
+    ) -> KeyAction {
+        self.shift_side = shift;
+        if !up && ScKey::new(sc, ext) == BACKSPACE_KEY {
+            self.usage.record_undo();
+        }
+        let expired = self.on_tick();
+        let action = self.process_dual_role_key(sc, ext, up, shift.is_held());
+        let action = prepend_events(expired, action, sc, ext, up);
+        self.decisions.record(ScKey::new(sc, ext), up, &action);
+        recorder::record_key(ScKey::new(sc, ext), up, shift, None, &action);
+        action
+    }
+
+    /// Lets a dual-role key act as its modifier, or types it through the
+    /// layout when it was tapped.
+    fn process_dual_role_key(&mut self, sc: u16, ext: bool, up: bool, shift: bool) -> KeyAction {
+        if !self.enabled {
+            return self.process_key_inner(sc, ext, up, shift);
+        }
+        match self
+            .dual_role
+            .on_key(ScKey::new(sc, ext), up, self.clock.now())
+        {
+            DualRoleAction::None => self.process_key_inner(sc, ext, up, shift),
+            DualRoleAction::Block => KeyAction::Block,
+            DualRoleAction::Pass => KeyAction::Pass,
+            action @ DualRoleAction::Modifier { pass, .. } => {
+                let events = self.dual_role_modifier_events(action);
+                let action = if pass {
+                    KeyAction::Pass
+                } else {
+                    KeyAction::Block
+                };
+                prepend_events(events, action, sc, ext, up)
+            }
+            DualRoleAction::Tap { replay } => {
+                let mut events = self.key_action_events(sc, ext, false, shift);
+                events.extend(self.key_action_events(sc, ext, true, shift));
+                if let Some(key) = replay {
+                    events.extend(self.key_action_events(key.sc, key.ext, false, shift));
+                }
+                prepend_events(events, KeyAction::Block, sc, ext, up)
+            }
+        }
+    }
+
+    /// Events of a dual-role modifier press or release, and of the key held
+    /// back until it was decided.
+    fn dual_role_modifier_events(&mut self, action: DualRoleAction) -> Vec<InputEvent> {
+        let DualRoleAction::Modifier {
+            modifier,
+            up,
+            replay,
+            ..
+        } = action
+        else {
+            return Vec::new();
+        };
+        #[cfg(feature = "os-windows")]
+        crate::keyboard_hook::set_dual_role_active(!up);
+        if !up {
+            self.undo.clear();
+        }
+        let mut events = vec![InputEvent::Scancode(modifier.sc, modifier.ext, up)];
+        if let Some(key) = replay {
+            events.push(InputEvent::Scancode(key.sc, key.ext, false));
+        }
+        events
+    }
+
+    /// What one key event sends through the layout, as events.
+    fn key_action_events(&mut self, sc: u16, ext: bool, up: bool, shift: bool) -> Vec<InputEvent> {
+        match self.process_key_inner(sc, ext, up, shift) {
+            KeyAction::Pass => vec![InputEvent::Scancode(sc, ext, up)],
+            KeyAction::Block => Vec::new(),
+            KeyAction::Inject(events) => events,
+        }
+    }
+
+    /// When a deferred Enter has to be sent even if the awaited key is
+    /// still held.
+    pub fn deferred_enter_deadline(&self) -> Option<Instant> {
+        let timeout = self.chord_engine.profile.deferred_enter_timeout_ms;
+        let deferred = self.deferred_enter_rollover?;
+        if timeout == 0 || deferred.down_emitted {
+            return None;
+        }
+        Some(deferred.started + Duration::from_millis(timeout))
+    }
+
+    /// When keys waiting for a chord partner have to be settled even if
+    /// no further key comes.
+    pub fn chord_deadline(&self) -> Option<Instant> {
+        if !self.enabled {
+            return None;
+        }
+        self.chord_engine.window_deadline()
+    }
+
+    /// When a key held back by a dual-role key has to be sent with its
+    /// modifier.
+    pub fn dual_role_deadline(&self) -> Option<Instant> {
+        if !self.enabled {
+            return None;
+        }
+        self.dual_role.deadline()
+    }
+
+    /// Earliest time [`Self::on_tick`] has something to do.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        [
+            self.deferred_enter_deadline(),
+            self.chord_deadline(),
+            self.dual_role_deadline(),
+            self.chord_test_deadline(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Makes the time-based decisions due by now: the deferred Enter, keys
+    /// left without a chord partner, long presses, keys held back by a
+    /// dual-role key and expired prefix shifts. Key events run it first; between them the hook worker runs
+    /// it at [`Self::next_deadline`].
+    pub fn on_tick(&mut self) -> Vec<InputEvent> {
+        let mut events = self.expire_deferred_enter();
+        events.extend(self.expire_chord_window());
+        if self.dual_role_deadline().is_some() {
+            let action = self.dual_role.on_tick(self.clock.now());
+            events.extend(self.dual_role_modifier_events(action));
+        }
+        if self.chord_test.is_some() {
+            if self
+                .chord_test_deadline()
+                .is_some_and(|deadline| self.clock.now() >= deadline)
+            {
+                tracing::info!("Chord test ended: no key pressed");
+                self.end_chord_test();
+            }
+            return key_releases(events);
+        }
+        events
+    }
+
+    /// Settles keys waiting for a chord partner once the chord window has
+    /// run out.
+    pub fn expire_chord_window(&mut self) -> Vec<InputEvent> {
+        let now = self.clock.now();
+        if self.chord_deadline().is_none_or(|deadline| now < deadline) {
+            return Vec::new();
+        }
+        let is_japanese = crate::ime::is_japanese_input_active(self.chord_engine.profile.ime_mode);
+        let expired = self.chord_engine.flush_expired(now);
+        let mut decisions = Vec::new();
+        self.sequence_decisions(expired, &mut decisions);
+        self.undo.begin_output();
+        let shift = self.shift_side.is_held();
+        self.apply_decisions(decisions, None, shift, is_japanese).0
+    }
+
+    /// Sends the deferred Enter once its deadline has passed.
+    pub fn expire_deferred_enter(&mut self) -> Vec<InputEvent> {
+        match self.deferred_enter_deadline() {
+            Some(deadline) if self.clock.now() >= deadline => self.emit_deferred_enter(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Processes one hook event, through a modifier plane when Ctrl, Alt
+    /// or Win is held.
+    pub fn process_event(&mut self, event: &RawKeyEvent) -> KeyAction {
+        let key = ScKey::new(event.sc, event.ext);
+        if let (Some(test), false) = (&mut self.chord_test, event.up) {
+            test.key_down(key, self.clock.now());
+        }
+        let mut action = match event.modifier {
+            Some(held) => self.process_modified_key(event.sc, event.ext, event.up, held),
+            None => self.process_key_with_shift(event.sc, event.ext, event.up, event.shift),
+        };
+        if let Some(ref mut test) = self.chord_test {
+            if event.up {
+                test.key_up(key);
+            }
+            action = match action {
+                KeyAction::Pass if event.up => KeyAction::Pass,
+                KeyAction::Pass | KeyAction::Block => KeyAction::Block,
+                KeyAction::Inject(events) => match key_releases(events) {
+                    events if events.is_empty() => KeyAction::Block,
+                    events => KeyAction::Inject(events),
+                },
+            };
+        }
+        if self.publishes {
+            handle::publish_key(|| EngineEvent::KeyProcessed {
+                sc: event.sc,
+                ext: event.ext,
+                up: event.up,
+                action: action.clone(),
+            });
+        }
+        action
+    }
+
+    /// Processes queued events in order under a single lock; one action
+    /// per event. Stops after an event whose output changes the IME state,
+    /// as the keys after it must be resolved once that output is injected;
+    /// the caller processes the rest then.
+    pub fn process_events(&mut self, events: &[RawKeyEvent]) -> Vec<KeyAction> {
+        let mut actions = Vec::with_capacity(events.len());
+        for event in events {
+            let action = self.process_event(event);
+            let changes_ime =
+                matches!(&action, KeyAction::Inject(events) if changes_ime_state(events));
+            actions.push(action);
+            if changes_ime {
+                break;
+            }
+        }
+        actions
+    }
+
+    /// `ModifierPlane` bits of the modifier planes the loaded layout defines.
+    /// Keys held with other modifiers never reach the engine.
+    pub fn modifier_plane_mask(&self) -> u8 {
+        self.modifier_planes
+    }
+
+    /// Looks a key pressed with Ctrl, Alt or Win up in that modifier's
+    /// sub-plane of the unshifted section. Keys the plane leaves undefined
+    /// pass with the modifier as usual.
+    pub fn process_modified_key(
+        &mut self,
+        sc: u16,
+        ext: bool,
+        up: bool,
+        held: HeldModifier,
+    ) -> KeyAction {
+        let key = ScKey::new(sc, ext);
+        let action = self.process_modified_key_inner(key, up, held);
+        self.decisions.record(key, up, &action);
+        recorder::record_key(key, up, ShiftSide::None, Some(held), &action);
+        action
+    }
+
+    fn process_modified_key_inner(
+        &mut self,
+        key: ScKey,
+        up: bool,
+        held: HeldModifier,
+    ) -> KeyAction {
+        if up {
+            return if self.modified_keys.remove(&key) {
+                KeyAction::Block
+            } else {
+                KeyAction::Pass
+            };
+        }
+        if !self.enabled || self.pass_through_keys.contains(&key) {
+            return KeyAction::Pass;
+        }
+
+        let is_japanese = crate::ime::is_japanese_input_active(self.chord_engine.profile.ime_mode);
+        let token = self.resolver().and_then(|resolver| {
+            resolver
+                .section(is_japanese, ShiftSide::None, SectionShift::None)?
+                .tagged(held.plane.tag(), key)
+                .filter(|&id| id != NONE_TOKEN)
+                .map(|id| resolver.token(id).clone())
+        });
+        let Some(events) =
+            token.and_then(|token| self.token_to_events_with_ime(&token, false, is_japanese))
+        else {
+            return KeyAction::Pass;
+        };
+
+        self.modified_keys.insert(key);
+        KeyAction::Inject(with_modifier_released(held, events))
+    }
+
+    /// State written into crash reports.
+    pub fn crash_snapshot(&self) -> EngineSnapshot {
+        EngineSnapshot {
+            enabled: self.enabled,
+            layout_name: self.layout.as_ref().and_then(|l| l.name.clone()),
+            profile: self.chord_engine.profile.clone(),
+            recent_decisions: self.decisions.snapshot(),
+        }
+    }
+
+    /// Pressed, pending and latched keys for the live debug view.
+    pub fn engine_state(&self) -> EngineState {
+        EngineState::capture(
+            self.enabled,
+            &self.chord_engine.state,
+            &self.repeat_plans,
+            self.clock.now(),
+        )
+    }
+
+    fn process_key_inner(&mut self, sc: u16, ext: bool, up: bool, shift: bool) -> KeyAction {
         if !self.enabled {
             return KeyAction::Pass;
         }
@@ -393,10 +1083,39 @@ impl Engine {
             return KeyAction::Pass;
         }
 
+        // Lock keys toggle asynchronously, so read the OS state on each press.
+        // With IME detection ignored only the internally tracked state is used.
+        if !up && self.chord_engine.profile.ime_mode != ImeMode::Ignore {
+            self.locks = crate::ime::query_lock_state();
+            if self
+                .compiled_layout
+                .as_ref()
+                .is_some_and(|layout| layout.candidate_section().is_some())
+            {
+                self.candidate_window = crate::ime::is_candidate_window_open();
+            }
+        }
+
         let source_key = ScKey::new(sc, ext);
-        let (key, pass_through_current, pseudo_key) = self.remap_input_key(source_key);
-        if let Some(pseudo) = pseudo_key {
-            return emit_pseudo_function_key(pseudo, up);
+        if self.pass_through_keys.contains(&source_key) {
+            if !up {
+                self.undo.clear();
+            }
+            return KeyAction::Pass;
+        }
+        let swap_shift = match self.swap_shift_at_press.as_mut() {
+            Some(at_press) if up => at_press.remove(&source_key).unwrap_or(shift),
+            Some(at_press) => *at_press.entry(source_key).or_insert(shift),
+            None => shift,
+        };
+        let (key, pass_through_current, pseudo_key) = self.remap_input_key(source_key, swap_shift);
+        if let Some(lock) = pseudo_key {
+            if up {
+                return KeyAction::Block;
+            }
+            self.locks.toggle(lock);
+            self.lock_toggles.toggle(lock);
+            return KeyAction::Inject(lock_toggle_events(lock));
         }
 
         if let Some(action) =
@@ -415,133 +1134,62 @@ impl Engine {
         // If not, we pass immediately to avoid ChordEngine buffering.
         {
             // 1. Determine local "Thumb Shift" status from ChordEngine state
-            let mut has_left_thumb = false;
-            let mut has_right_thumb = false;
-            let mut has_ext1_thumb = false;
-            let mut has_ext2_thumb = false;
+            let mut planes = ThumbPlanes::default();
             if let Some(ref tk) = self.chord_engine.profile.thumb_keys {
-                let mut mark_thumb_state = |k: &ScKey| {
-                    if tk.left.contains(k) {
-                        has_left_thumb = true;
-                    }
-                    if tk.right.contains(k) {
-                        has_right_thumb = true;
-                    }
-                    if tk.ext1.contains(k) {
-                        has_ext1_thumb = true;
-                    }
-                    if tk.ext2.contains(k) {
-                        has_ext2_thumb = true;
-                    }
-                };
-
                 for k in &self.chord_engine.state.pressed {
-                    mark_thumb_state(k);
+                    planes.mark(tk, k);
                 }
 
                 // PrefixShift uses a released thumb as the next one-shot modifier.
                 // Include it in section pre-check so the next key isn't passed through early.
                 if let Some(prefix_thumb) = self.chord_engine.state.prefix_pending {
-                    mark_thumb_state(&prefix_thumb);
+                    planes.mark(tk, &prefix_thumb);
                 }
             }
 
-            // 2. Select PREFIX & SUFFIX
-            let prefix = if is_japanese {
-                "ローマ字"
-            } else {
-                "英数"
-            };
-            let suffix = if shift {
-                if has_left_thumb {
-                    "小指左親指シフト"
-                } else if has_right_thumb {
-                    "小指右親指シフト"
-                } else {
-                    "小指シフト"
-                }
-            } else {
-                if has_left_thumb {
-                    "左親指シフト"
-                } else if has_right_thumb {
-                    "右親指シフト"
-                } else {
-                    "シフト無し"
+            // 3. Check Section Existence: `None` inside when there is no
+            // section, else whether it or its fallbacks define the key.
+            let defined = self.resolver().map(|resolver| {
+                if resolver
+                    .overriding_sections()
+                    .any(|section| resolver.section_defines(section, key))
+                {
+                    return Some(true);
                 }
-            };
-
-            let forced_section_name =
-                if is_japanese && !has_left_thumb && !has_right_thumb && has_ext1_thumb {
-                    Some(EXTENDED_THUMB_SHIFT_1_SECTION)
-                } else if is_japanese && !has_left_thumb && !has_right_thumb && has_ext2_thumb {
-                    Some(EXTENDED_THUMB_SHIFT_2_SECTION)
-                } else {
-                    None
-                };
-            // eprintln!("DEBUG: Resolve: section={} keys={:?} japanese={}", section_name, keys, is_japanese);
-
-            // 3. Check Section Existence
-            if let Some(layout) = &self.layout {
+                resolver
+                    .thumb_section(&planes, shift, is_japanese)
+                    .map(|section| {
+                        resolver.section_defines(section, key)
+                            || resolver
+                                .fallback_sections(section, is_japanese)
+                                .any(|fallback| resolver.section_defines(fallback, key))
+                    })
+            });
+            if let Some(defined) = defined {
                 let is_space = key.sc == 0x39;
                 let key_is_managed = self.chord_engine.state.pressed.contains(&key)
                     || self.chord_engine.state.down_ts.contains_key(&key)
                     || self.chord_engine.state.pending.iter().any(|p| p.key == key);
-                let mut is_thumb = false;
-                if let Some(ref tk) = self.chord_engine.profile.thumb_keys {
-                    if tk.left.contains(&key)
-                        || tk.right.contains(&key)
-                        || tk.ext1.contains(&key)
-                        || tk.ext2.contains(&key)
-                    {
-                        is_thumb = true;
-                    }
-                }
-
-                let section = if let Some(section_name) = forced_section_name {
-                    layout.sections.get(section_name)
-                } else {
-                    with_section_name(prefix, suffix, |section_name| {
-                        layout.sections.get(section_name)
-                    })
-                };
+                let is_thumb = self.is_thumb_key(key);
+                let undo_cfg = &self.chord_engine.profile.undo;
+                let is_undo_key = undo_cfg.enabled && undo_cfg.chord.contains(&key);
 
-                if let Some(section) = section {
+                if let Some(is_defined) = defined {
                     // Section exists. Check if key is defined.
-                    let mut is_defined = false;
+                    let policy = self.chord_engine.profile.undefined_key;
 
-                    // Check Base Plane
-                    if let Some(rc) = self.key_to_rc(key) {
-                        if let Some(token) = section.base_plane.map.get(&rc) {
-                            if !matches!(token, Token::None) {
-                                is_defined = true;
-                            }
+                    if !is_defined
+                        && !is_thumb
+                        && !is_space
+                        && !is_undo_key
+                        && !(up && key_is_managed)
+                    {
+                        if !up {
+                            self.undo.clear();
                         }
-                    }
-
-                    // Check Trigger Keys (Sub Planes)
-                    if !is_defined {
-                        if let Some(name) = crate::jis_map::sc_to_key_name(key.sc) {
-                            if with_single_tag(name, |tag| section.sub_planes.contains_key(tag)) {
-                                is_defined = true;
-                            }
-                            // Also check for 2-key prefix in subplanes?
-                            // No, current logic only checks single key triggers here?
-                            // Wait! <q><w> is a subplane key.
-                            // But checking 'q' -> tag '<q>'.
-                            // If section has '<q><w>', does it have '<q>'?
-                            // parser.rs: '<q><w>' creates a subplane keyed by "<q><w>".
-                            // It does NOT create '<q>'.
-                            // So if I press 'Q', and there is only '<q><w>', then 'Q' is NOT defined as a trigger??
-                            // THIS IS THE BUG!
-                            // For 3-key chords to work, the first key MUST be recognized as a trigger or defined key.
-                            // If 'Q' is not in base plane (it is in test).
-                            // But if 'Q' was 'xx' in base plane?
-                            // In test: `q` is in base plane.
-                            // So `is_defined` is true via base plane.
+                        if policy == UndefinedKeyPolicy::Block {
+                            return KeyAction::Block;
                         }
-                    }
-
-                    if !is_defined && !is_thumb && !is_space && !(up && key_is_managed) {
                         if self.start_deferred_enter_rollover(
                             source_key,
                             key,
@@ -551,12 +1199,17 @@ impl Engine {
                             return KeyAction::Block;
                         }
                         // Defined section, but key is not in it -> Pass
-                        return passthrough_action(pass_through_current, source_key, up);
+                        return self.passthrough_under_shift(
+                            &planes,
+                            pass_through_current,
+                            source_key,
+                            up,
+                        );
                     }
                 } else {
                     // Section does NOT exist -> Pass
                     // UNLESS it is a Thumb Key
-                    if !is_thumb && !is_space && !(up && key_is_managed) {
+                    if !is_thumb && !is_space && !is_undo_key && !(up && key_is_managed) {
                         if self.start_deferred_enter_rollover(
                             source_key,
                             key,
@@ -565,7 +1218,15 @@ impl Engine {
                         ) {
                             return KeyAction::Block;
                         }
-                        return passthrough_action(pass_through_current, source_key, up);
+                        if !up {
+                            self.undo.clear();
+                        }
+                        return self.passthrough_under_shift(
+                            &planes,
+                            pass_through_current,
+                            source_key,
+                            up,
+                        );
                     }
                 }
             }
@@ -575,43 +1236,97 @@ impl Engine {
             key,
             edge: if up { KeyEdge::Up } else { KeyEdge::Down },
             injected: false,
-            t: Instant::now(),
+            t: self.clock.now(),
         };
 
-        let decisions = self.chord_engine.on_event(event);
+        if !up {
+            self.press_order.insert(key, self.next_press);
+            self.next_press += 1;
+        }
+        let mut decisions = std::mem::take(&mut self.decision_buf);
+        let mut sequenced = std::mem::take(&mut self.sequenced_buf);
+        self.chord_engine.on_event_into(event, &mut decisions);
+        self.sequence_decisions(decisions.drain(..), &mut sequenced);
+        self.decision_buf = decisions;
+
+        self.undo.begin_output();
+        let (mut inject_ops, pass_current) =
+            self.apply_decisions(sequenced.drain(..), Some(key), shift, is_japanese);
+        self.sequenced_buf = sequenced;
+
+        if up {
+            inject_ops.extend(self.release_deferred_enter_on_wait_key_up(key));
+            self.repeat_plans.remove(&key);
+        }
+
+        if !inject_ops.is_empty() {
+            if pass_current {
+                // If we also need to pass the current key, append it to the injection sequence.
+                // This ensures "Flushed Keys" -> "Current Key" order.
+                if let Some(ev) = passthrough_event(pass_through_current, source_key, up) {
+                    inject_ops.push(ev);
+                }
+            }
+            return KeyAction::Inject(inject_ops);
+        }
+
+        if pass_current {
+            return passthrough_action(pass_through_current, source_key, up);
+        }
+
+        KeyAction::Block
+    }
 
+    /// Turns chord decisions into input events. Also tells whether
+    /// `current` is to pass through.
+    fn apply_decisions(
+        &mut self,
+        decisions: impl IntoIterator<Item = Decision>,
+        current: Option<ScKey>,
+        shift: bool,
+        is_japanese: bool,
+    ) -> (Vec<InputEvent>, bool) {
         let mut inject_ops = Vec::new();
         let mut pass_current = false;
-
         for d in decisions {
             match d {
-                Decision::Passthrough(k, _) => {
-                    if k == key {
+                Decision::Passthrough(k, edge) => {
+                    if Some(k) == current {
                         pass_current = true;
                     }
+                    if edge == KeyEdge::Down {
+                        self.undo.clear();
+                    }
                 }
                 Decision::KeyTap(k) => {
                     if self.repeat_plans.contains_key(&k) {
                         continue;
                     }
-                    if let Some(token) = self.resolve(&[k], shift, is_japanese) {
-                        if let Some(ops) = self.token_to_events_with_ime(&token, shift, is_japanese)
-                        {
-                            inject_ops.extend(ops);
-                        }
-                    } else {
-                        // Replay unmapped or failed resolution as original key
-                        inject_ops.push(InputEvent::Scancode(k.sc, k.ext, false)); // Down
-                        inject_ops.push(InputEvent::Scancode(k.sc, k.ext, true));
-                        // Up
-                    }
+                    feedback::notify(FeedbackEvent::Tap);
+                    self.tap_events(k, shift, is_japanese, &mut inject_ops);
                 }
                 Decision::Chord(keys) => {
-                    let (token, modifier) = self.resolve_with_modifier(&keys, shift, is_japanese);
+                    if self.chord_engine.profile.undo.matches(&keys) {
+                        self.usage.record_undo();
+                        inject_ops.extend(self.undo.take_undo_events());
+                        continue;
+                    }
+                    let layout = self.compiled_layout.clone();
+                    let (token, modifier) = self.resolve_id(&keys, shift, is_japanese);
+                    feedback::notify(if token.is_some() {
+                        FeedbackEvent::Chord
+                    } else {
+                        FeedbackEvent::Misfire
+                    });
+                    let token = layout_token(&layout, token)
+                        .map(|token| self.post_process_token(token, is_japanese));
                     if let Some(token) = token {
-                        if let Some(ops) = self.token_to_events_with_ime(&token, shift, is_japanese)
+                        self.toggle_tenkey_for(&token);
+                        if let Some(ops) =
+                            self.output_events(TraceKind::Chord, &keys, &token, shift, is_japanese)
                         {
-                            inject_ops.extend(ops);
+                            append_events(&mut inject_ops, ops);
+                            self.record_output(TraceKind::Chord, &keys, &token, is_japanese);
                         }
                         if let Some(mod_key) = modifier {
                             self.consume_non_modifier_keys(&keys, mod_key);
@@ -639,12 +1354,14 @@ impl Engine {
                                     self.token_to_events_with_ime(&token, shift, is_japanese)
                                 {
                                     inject_ops.extend(ops);
+                                    self.record_output(TraceKind::Tap, &[k], &token, is_japanese);
                                     resolved = true;
                                 }
                             }
                             if !resolved {
                                 inject_ops.push(InputEvent::Scancode(k.sc, k.ext, false));
                                 inject_ops.push(InputEvent::Scancode(k.sc, k.ext, true));
+                                self.undo.clear();
                             }
                         } else if undefined_rollover_pair && !older_pressed && newer_pressed {
                             // Older key was released first during rollover.
@@ -664,12 +1381,14 @@ impl Engine {
                                     self.token_to_events_with_ime(&token, shift, is_japanese)
                                 {
                                     inject_ops.extend(ops);
+                                    self.record_output(TraceKind::Tap, &[k], &token, is_japanese);
                                     resolved = true;
                                 }
                             }
                             if !resolved {
                                 inject_ops.push(InputEvent::Scancode(k.sc, k.ext, false));
                                 inject_ops.push(InputEvent::Scancode(k.sc, k.ext, true));
+                                self.undo.clear();
                             }
                         } else {
                             // Fallback: undefined chord -> treat as sequential inputs
@@ -681,6 +1400,12 @@ impl Engine {
                                         self.token_to_events_with_ime(&token, shift, is_japanese)
                                     {
                                         inject_ops.extend(ops);
+                                        self.record_output(
+                                            TraceKind::Tap,
+                                            &[k],
+                                            &token,
+                                            is_japanese,
+                                        );
                                         resolved = true;
                                     }
                                 }
@@ -690,11 +1415,39 @@ impl Engine {
                                     inject_ops.push(InputEvent::Scancode(k.sc, k.ext, false)); // Down
                                     inject_ops.push(InputEvent::Scancode(k.sc, k.ext, true));
                                     // Up
+                                    self.undo.clear();
                                 }
                             }
                         }
                     }
                 }
+                Decision::LongPress(k) => {
+                    feedback::notify(FeedbackEvent::Tap);
+                    let layout = self.compiled_layout.clone();
+                    let token = self
+                        .resolver()
+                        .and_then(|resolver| resolver.resolve_long_press_id(k, shift, is_japanese));
+                    if let Some(token) = layout_token(&layout, token)
+                        .map(|token| self.post_process_token(token, is_japanese))
+                    {
+                        self.toggle_tenkey_for(&token);
+                        if let Some(ops) = self.output_events(
+                            TraceKind::LongPress,
+                            &[k],
+                            &token,
+                            shift,
+                            is_japanese,
+                        ) {
+                            append_events(&mut inject_ops, ops);
+                            self.record_output(TraceKind::LongPress, &[k], &token, is_japanese);
+                        }
+                    } else {
+                        // The active section has no `<long>` token for the
+                        // key (e.g. only the ローマ字 sections define one):
+                        // type it as a tap rather than lose it.
+                        self.tap_events(k, shift, is_japanese, &mut inject_ops);
+                    }
+                }
                 Decision::LatchOn(kind) => {
                     debug!("LatchOn: {:?}", kind);
                 }
@@ -703,28 +1456,86 @@ impl Engine {
                 }
             }
         }
+        (inject_ops, pass_current)
+    }
 
-        if up {
-            inject_ops.extend(self.release_deferred_enter_on_wait_key_up(key));
-            self.repeat_plans.remove(&key);
+    /// Appends the output of a single key tap, or the key itself when the
+    /// layout does not map it.
+    fn tap_events(
+        &mut self,
+        k: ScKey,
+        shift: bool,
+        is_japanese: bool,
+        inject_ops: &mut Vec<InputEvent>,
+    ) {
+        let layout = self.compiled_layout.clone();
+        let token = self.resolve_id(&[k], shift, is_japanese).0;
+        if let Some(token) =
+            layout_token(&layout, token).map(|token| self.post_process_token(token, is_japanese))
+        {
+            self.toggle_tenkey_for(&token);
+            if let Some(ops) = self.output_events(TraceKind::Tap, &[k], &token, shift, is_japanese)
+            {
+                append_events(inject_ops, ops);
+                self.record_output(TraceKind::Tap, &[k], &token, is_japanese);
+            }
+        } else {
+            // Replay unmapped or failed resolution as original key
+            inject_ops.push(InputEvent::Scancode(k.sc, k.ext, false)); // Down
+            inject_ops.push(InputEvent::Scancode(k.sc, k.ext, true)); // Up
+            self.undo.clear();
         }
+    }
 
-        if !inject_ops.is_empty() {
-            if pass_current {
-                // If we also need to pass the current key, append it to the injection sequence.
-                // This ensures "Flushed Keys" -> "Current Key" order.
-                if let Some(ev) = passthrough_event(pass_through_current, source_key, up) {
-                    inject_ops.push(ev);
+    /// Puts taps and chords in the order their keys were pressed. Those
+    /// pressed after a character key that is still pending are held until
+    /// it resolves, even if that happens in a later call. Other decisions
+    /// release everything held before them.
+    fn sequence_decisions(
+        &mut self,
+        decisions: impl IntoIterator<Item = Decision>,
+        out: &mut Vec<Decision>,
+    ) {
+        for d in decisions {
+            let keys = match &d {
+                Decision::KeyTap(k) | Decision::LongPress(k) => std::slice::from_ref(k),
+                Decision::Chord(keys) => keys.as_slice(),
+                _ => {
+                    self.release_held_decisions(u64::MAX, out);
+                    out.push(d);
+                    continue;
                 }
-            }
-            return KeyAction::Inject(inject_ops);
+            };
+            let order = keys
+                .iter()
+                .filter_map(|k| self.press_order.get(k).copied())
+                .min()
+                .unwrap_or(u64::MAX);
+            self.held_decisions.push((order, d));
         }
 
-        if pass_current {
-            return passthrough_action(pass_through_current, source_key, up);
-        }
+        let blocker = self
+            .chord_engine
+            .state
+            .pending
+            .iter()
+            .filter(|p| !self.chord_engine.is_modifier_key(p.key))
+            .filter_map(|p| self.press_order.get(&p.key).copied())
+            .min()
+            .unwrap_or(u64::MAX);
+        self.release_held_decisions(blocker, out);
+    }
 
-        KeyAction::Block
+    /// Moves the held decisions of keys pressed before `before` to `out`,
+    /// oldest press first.
+    fn release_held_decisions(&mut self, before: u64, out: &mut Vec<Decision>) {
+        self.held_decisions.sort_by_key(|(order, _)| *order);
+        let count = self
+            .held_decisions
+            .iter()
+            .take_while(|(order, _)| *order < before || before == u64::MAX)
+            .count();
+        out.extend(self.held_decisions.drain(..count).map(|(_, d)| d));
     }
 
     fn is_enter_key(key: ScKey) -> bool {
@@ -766,6 +1577,7 @@ impl Engine {
             source_key,
             pass_through,
             wait_for,
+            started: self.clock.now(),
             down_emitted: false,
             up_seen_while_waiting: false,
         });
@@ -808,15 +1620,19 @@ impl Engine {
     }
 
     fn release_deferred_enter_on_wait_key_up(&mut self, key: ScKey) -> Vec<InputEvent> {
+        match self.deferred_enter_rollover {
+            Some(deferred) if !deferred.down_emitted && deferred.wait_for == key => {
+                self.emit_deferred_enter()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn emit_deferred_enter(&mut self) -> Vec<InputEvent> {
         let Some(mut deferred) = self.deferred_enter_rollover.take() else {
             return Vec::new();
         };
 
-        if deferred.down_emitted || deferred.wait_for != key {
-            self.deferred_enter_rollover = Some(deferred);
-            return Vec::new();
-        }
-
         let mut events = Vec::new();
         if let Some(event) = passthrough_event(deferred.pass_through, deferred.source_key, false) {
             events.push(event);
@@ -839,12 +1655,21 @@ impl Engine {
     fn remap_input_key(
         &self,
         source_key: ScKey,
-    ) -> (ScKey, PassThroughCurrent, Option<FunctionPseudoKey>) {
+        shift: bool,
+    ) -> (ScKey, PassThroughCurrent, Option<LockKey>) {
         let mut current = source_key;
         let mut changed = false;
         let mut visited = HashSet::new();
 
-        while let Some(target) = self.function_key_swaps.get(&current).copied() {
+        // Remaps are not chained, so that two keys can trade places.
+        if let Some(&moved) = self.key_remaps.get(&source_key) {
+            visited.insert(source_key);
+            current = moved;
+            changed = true;
+        }
+
+        // Swaps chain; see `crate::key_swap`.
+        while let Some(target) = self.function_key_swaps.get(&(current, shift)).copied() {
             if !visited.insert(current) {
                 break;
             }
@@ -852,23 +1677,15 @@ impl Engine {
             match target {
                 FunctionKeySwapTarget::Key(next) => current = next,
                 FunctionKeySwapTarget::CapsLock => {
-                    return (
-                        current,
-                        PassThroughCurrent::Block,
-                        Some(FunctionPseudoKey::CapsLock),
-                    );
+                    return (current, PassThroughCurrent::Block, Some(LockKey::Caps));
                 }
                 FunctionKeySwapTarget::KanaLock => {
-                    return (
-                        current,
-                        PassThroughCurrent::Block,
-                        Some(FunctionPseudoKey::KanaLock),
-                    );
+                    return (current, PassThroughCurrent::Block, Some(LockKey::Kana));
                 }
             }
         }
 
-        let pass = if !changed {
+        let pass = if !changed || current == source_key {
             PassThroughCurrent::Original
         } else if is_virtual_extended_key(current) {
             PassThroughCurrent::Block
@@ -879,6 +1696,20 @@ impl Engine {
         (current, pass, None)
     }
 
+    fn resolver(&self) -> Option<Resolver<'_>> {
+        Some(Resolver {
+            layout: self.compiled_layout.as_deref()?,
+            profile: &self.chord_engine.profile,
+            latch: &self.chord_engine.state.latch,
+            chord_priorities: &self.chord_priorities,
+            kana_lock: self.locks.kana,
+            tenkey: self.tenkey,
+            candidate_window: self.candidate_window,
+            shift_side: self.shift_side,
+            app_tag: self.app_tag.as_deref(),
+        })
+    }
+
     fn resolve(&self, keys: &[ScKey], shift: bool, is_japanese: bool) -> Option<Token> {
         self.resolve_with_modifier(keys, shift, is_japanese).0
     }
@@ -889,245 +1720,69 @@ impl Engine {
         shift: bool,
         is_japanese: bool,
     ) -> (Option<Token>, Option<ScKey>) {
-        let layout = match self.layout.as_ref() {
-            Some(layout) => layout,
-            None => return (None, None),
-        };
-
-        // 1. Determine "Thumb Shift" status
-        let mut has_left_thumb = false;
-        let mut has_right_thumb = false;
-        let mut has_ext1_thumb = false;
-        let mut has_ext2_thumb = false;
-
-        if let Some(ref tk) = self.chord_engine.profile.thumb_keys {
-            for k in keys {
-                if tk.left.contains(k) {
-                    has_left_thumb = true;
-                }
-                if tk.right.contains(k) {
-                    has_right_thumb = true;
-                }
-                if tk.ext1.contains(k) {
-                    has_ext1_thumb = true;
-                }
-                if tk.ext2.contains(k) {
-                    has_ext2_thumb = true;
-                }
-            }
-        }
-
-        // 2. Select PREFIX (Eng vs Roma)
-        let prefix = if is_japanese {
-            "ローマ字"
-        } else {
-            "英数"
-        };
-
-        // 3. Select SUFFIX
-        let suffix = if shift {
-            if has_left_thumb {
-                "小指左親指シフト"
-            } else if has_right_thumb {
-                "小指右親指シフト"
-            } else {
-                "小指シフト"
-            }
-        } else {
-            if has_left_thumb {
-                "左親指シフト"
-            } else if has_right_thumb {
-                "右親指シフト"
-            } else {
-                "シフト無し"
-            }
-        };
-
-        let forced_section_name =
-            if is_japanese && !has_left_thumb && !has_right_thumb && has_ext1_thumb {
-                Some(EXTENDED_THUMB_SHIFT_1_SECTION)
-            } else if is_japanese && !has_left_thumb && !has_right_thumb && has_ext2_thumb {
-                Some(EXTENDED_THUMB_SHIFT_2_SECTION)
-            } else {
-                None
-            };
-        // eprintln!("DEBUG: Resolve: section={} keys={:?} japanese={}", section_name, keys, is_japanese);
-
-        let section = match if let Some(section_name) = forced_section_name {
-            layout.sections.get(section_name)
-        } else {
-            with_section_name(prefix, suffix, |section_name| {
-                layout.sections.get(section_name)
-            })
-        } {
-            Some(section) => section,
-            None => return (None, None),
+        let Some(resolver) = self.resolver() else {
+            return (None, None);
         };
+        let (token, modifier) = self.resolve_id(keys, shift, is_japanese);
+        (token.map(|id| resolver.token(id).clone()), modifier)
+    }
 
-        // 4. Update keys for lookup (Remove Thumb Modifiers)
-        let lookup_keys: Vec<ScKey> =
-            if has_left_thumb || has_right_thumb || has_ext1_thumb || has_ext2_thumb {
-                if let Some(ref tk) = self.chord_engine.profile.thumb_keys {
-                    keys.iter()
-                        .filter(|&&k| {
-                            let is_left = tk.left.contains(&k);
-                            let is_right = tk.right.contains(&k);
-                            let is_ext1 = tk.ext1.contains(&k);
-                            let is_ext2 = tk.ext2.contains(&k);
-                            if has_left_thumb && is_left {
-                                return false;
-                            }
-                            if has_right_thumb && is_right {
-                                return false;
-                            }
-                            if has_ext1_thumb && is_ext1 {
-                                return false;
-                            }
-                            if has_ext2_thumb && is_ext2 {
-                                return false;
-                            }
-                            true
-                        })
-                        .cloned()
-                        .collect()
-                } else {
-                    keys.to_vec()
-                }
-            } else {
-                keys.to_vec()
-            };
-
-        if lookup_keys.is_empty() {
+    /// Like [`Self::resolve_with_modifier`], but leaves the token in
+    /// `compiled_layout`.
+    fn resolve_id(
+        &self,
+        keys: &[ScKey],
+        shift: bool,
+        is_japanese: bool,
+    ) -> (Option<TokenId>, Option<ScKey>) {
+        let Some(resolver) = self.resolver() else {
             return (None, None);
+        };
+        match self.with_held_ext_thumb(&resolver, keys, is_japanese) {
+            Some(both) => resolver.resolve_id(&both, shift, is_japanese),
+            None => resolver.resolve_id(keys, shift, is_japanese),
         }
-
-        if lookup_keys.len() == 1 {
-            let key = lookup_keys[0];
-            let latch = &self.chord_engine.state.latch;
-
-            if let crate::chord_engine::LatchState::OneShot(tag)
-            | crate::chord_engine::LatchState::Lock(tag) = latch
-            {
-                if let Some(sub) = section.sub_planes.get(tag) {
-                    if let Some(rc) = self.key_to_rc(key) {
-                        if let Some(token) = sub.map.get(&rc) {
-                            return (Some(token.clone()), None);
-                        }
-                    }
-                }
-            }
-
-            if let Some(rc) = self.key_to_rc(key) {
-                return (section.base_plane.map.get(&rc).cloned(), None);
-            }
-        } else if lookup_keys.len() == 2 {
-            let k1 = lookup_keys[0];
-            let k2 = lookup_keys[1];
-
-            if let Some(token) = self.try_resolve_modifier(section, k1, k2) {
-                return (Some(token), Some(k1));
-            }
-            if let Some(token) = self.try_resolve_modifier(section, k2, k1) {
-                return (Some(token), Some(k2));
-            }
-        } else if lookup_keys.len() == 3 {
-            // 3-key resolution (A, B, C)
-            // Check if any combination of 2 keys forms a modifier for the 3rd key
-            // Permutations:
-            // (A,B) -> C ?? Tag <A><B> or <B><A>
-            // (A,C) -> B
-            // (B,C) -> A
-            let k1 = lookup_keys[0];
-            let k2 = lookup_keys[1];
-            let k3 = lookup_keys[2];
-            // eprintln!("DEBUG: resolving 3 keys: {:?}, {:?}, {:?}", k1, k2, k3);
-
-            // 1. Modifiers: k1, k2. Target: k3
-            if let Some(token) = self.try_resolve_double_modifier(section, k1, k2, k3) {
-                // eprintln!("DEBUG: Resolved (k1, k2) -> k3: {:?}", token);
-                return (Some(token), Some(k1));
-            }
-            if let Some(token) = self.try_resolve_double_modifier(section, k2, k1, k3) {
-                return (Some(token), Some(k2));
-            }
-
-            // 2. Modifiers: k1, k3. Target: k2
-            if let Some(token) = self.try_resolve_double_modifier(section, k1, k3, k2) {
-                return (Some(token), Some(k1));
-            }
-            if let Some(token) = self.try_resolve_double_modifier(section, k3, k1, k2) {
-                return (Some(token), Some(k3));
-            }
-
-            // 3. Modifiers: k2, k3. Target: k1
-            if let Some(token) = self.try_resolve_double_modifier(section, k2, k3, k1) {
-                return (Some(token), Some(k2));
-            }
-            if let Some(token) = self.try_resolve_double_modifier(section, k3, k2, k1) {
-                return (Some(token), Some(k3));
-            }
-        }
-
-        (None, None)
     }
 
-    fn try_resolve_modifier(
+    /// Name of the section `keys` resolve in.
+    fn resolved_section_name(
         &self,
-        section: &crate::types::Section,
-        mod_key: ScKey,
-        target_key: ScKey,
-    ) -> Option<Token> {
-        let mod_name = crate::jis_map::sc_to_key_name(mod_key.sc)?;
-        with_single_tag(mod_name, |tag| {
-            if let Some(sub) = section.sub_planes.get(tag) {
-                if let Some(rc) = self.key_to_rc(target_key) {
-                    if let Some(token) = sub.map.get(&rc) {
-                        if !matches!(token, Token::None) {
-                            return Some(token.clone());
-                        }
-                    }
-                }
-            }
-            None
-        })
+        keys: &[ScKey],
+        shift: bool,
+        is_japanese: bool,
+    ) -> Option<String> {
+        let resolver = self.resolver()?;
+        let section = match self.with_held_ext_thumb(&resolver, keys, is_japanese) {
+            Some(both) => resolver.resolve_section(&both, shift, is_japanese),
+            None => resolver.resolve_section(keys, shift, is_japanese),
+        };
+        section.map(|section| section.name.clone())
     }
 
-    fn try_resolve_double_modifier(
+    /// A chord takes one extension thumb; the other one may be held too,
+    /// which selects the `[拡張親指シフト1+2]` plane when there is one.
+    /// `keys` with the other thumb added, or `None` when it is not held.
+    fn with_held_ext_thumb(
         &self,
-        section: &crate::types::Section,
-        mod1: ScKey,
-        mod2: ScKey,
-        target: ScKey,
-    ) -> Option<Token> {
-        let name1 = crate::jis_map::sc_to_key_name(mod1.sc)?;
-        let name2 = crate::jis_map::sc_to_key_name(mod2.sc)?;
-        with_double_tag(name1, name2, |tag1| {
-            // eprintln!("DEBUG: Checking tag: {}", tag1);
-            if let Some(sub) = section.sub_planes.get(tag1) {
-                // eprintln!("DEBUG: Sub-plane found for {}", tag1);
-                if let Some(rc) = self.key_to_rc(target) {
-                    // eprintln!("DEBUG: RC found for target: {:?}", rc);
-                    if let Some(token) = sub.map.get(&rc) {
-                        // eprintln!("DEBUG: Token found: {:?}", token);
-                        if !matches!(token, Token::None) {
-                            return Some(token.clone());
-                        }
-                    }
-                } // else {
-                  //     eprintln!("DEBUG: No token at RC {:?}", rc);
-                  // }
-            } // else {
-              //     eprintln!("DEBUG: No RC for target {:?}", target);
-              // }
-              // } else {
-              //     eprintln!(
-              //         "DEBUG: Sub-plane NOT found for {}. Available keys: {:?}",
-              //         tag1,
-              //         section.sub_planes.keys()
-              //     );
-              // }
-            None
-        })
+        resolver: &Resolver,
+        keys: &[ScKey],
+        is_japanese: bool,
+    ) -> Option<SmallVec<[ScKey; 4]>> {
+        let tk = self.chord_engine.profile.thumb_keys.as_ref()?;
+        let is_ext = |k: &ScKey| tk.ext1.contains(k) || tk.ext2.contains(k);
+        if !keys.iter().any(is_ext) {
+            return None;
+        }
+        resolver.section(is_japanese, ShiftSide::None, SectionShift::Ext1And2)?;
+        let mut held = self
+            .chord_engine
+            .state
+            .pressed
+            .iter()
+            .filter(|k| is_ext(k) && !keys.contains(k))
+            .peekable();
+        held.peek()?;
+        Some(keys.iter().chain(held).copied().collect())
     }
 
     fn is_char_shift_key(&self, key: ScKey) -> bool {
@@ -1232,7 +1887,7 @@ impl Engine {
             .down_ts
             .get(&key)
             .copied()
-            .unwrap_or_else(Instant::now);
+            .unwrap_or_else(|| self.clock.now());
 
         self.chord_engine.state.pending.push(PendingKey {
             key,
@@ -1304,72 +1959,29 @@ impl Engine {
         self.remove_keys_from_pending(&remove, false);
     }
 
-    fn key_to_rc(&self, key: ScKey) -> Option<crate::types::Rc> {
-        crate::jis_map::key_to_rc(key)
-    }
-
     fn token_to_events_with_ime(
         &self,
         token: &Token,
         shift_held: bool,
         is_japanese: bool,
     ) -> Option<Vec<InputEvent>> {
-        match token {
-            Token::None => None,
-            Token::KeySequence(seq) => {
-                let mut events = Vec::new();
-                for stroke in seq {
-                    // Strict scancode only for KeySequence (which now comes from single-quote/bare tokens)
-                    append_keystroke_events(&mut events, stroke, shift_held, false, is_japanese);
-                }
-                if events.is_empty() {
-                    None
-                } else {
-                    Some(events)
-                }
-            }
-            Token::ImeChar(text) => {
-                let mut events = Vec::new();
-                for c in text.chars() {
-                    events.push(InputEvent::Unicode(c, false));
-                    events.push(InputEvent::Unicode(c, true));
-                }
-                if events.is_empty() {
-                    None
-                } else {
-                    Some(events)
-                }
-            }
-            Token::DirectChar(text) => {
-                let mut events = Vec::new();
-                // If IME is ON (Japanese Mode), we must temporarily turn it OFF to force "confirmed" input.
-                // Otherwise, even Unicode events are intercepted by IME as "unconfirmed" text (e.g. Hiragana).
-                let mut toggled_ime = false;
-                if is_japanese {
-                    if let Ok(ime_on) = crate::ime::get_ime_open_status() {
-                        if ime_on {
-                            events.push(InputEvent::ImeControl(false));
-                            toggled_ime = true;
-                        }
-                    }
-                }
-
-                for c in text.chars() {
-                    events.push(InputEvent::Unicode(c, false));
-                    events.push(InputEvent::Unicode(c, true));
-                }
-
-                if toggled_ime {
-                    events.push(InputEvent::ImeControl(true));
-                }
-
-                if events.is_empty() {
-                    None
-                } else {
-                    Some(events)
-                }
+        let ctx = EmitContext {
+            profile: &self.chord_engine.profile,
+            shift_held,
+            is_japanese,
+            caps_lock: self.locks.caps,
+            ime_open: is_japanese
+                && matches!(token, Token::DirectChar(_))
+                && crate::ime::get_ime_open_status().unwrap_or(false),
+        };
+        let mut events = self.emitter.emit(token, &ctx)?;
+        let policy = self.chord_engine.profile.ime_composition;
+        if policy != CompositionPolicy::Ignore {
+            if let Some(index) = first_composition_sensitive_event(&events) {
+                events.insert(index, InputEvent::SettleComposition(policy));
             }
         }
+        Some(events)
     }
 
     fn repeat_fallback_events(
@@ -1394,17 +2006,201 @@ impl Engine {
 
     // ...
 
-    fn is_repeat_event(&self, key: ScKey) -> bool {
-        self.chord_engine.state.pressed.contains(&key)
+    /// Switches the keypad for a decided `{テンキー}` token. A chord test
+    /// only reports outputs, so it leaves the keypad as it is.
+    fn toggle_tenkey_for(&mut self, token: &Token) {
+        if self.chord_test.is_none() && tenkey::toggles_tenkey(token) {
+            self.set_tenkey(!self.tenkey);
+        }
     }
 
-    fn handle_repeat_event(&mut self, key: ScKey, shift: bool, is_japanese: bool) -> KeyAction {
-        let now = Instant::now();
-        let (keys, consume_pending) = if let Some(keys) = self.repeat_plans.get(&key) {
-            (keys.clone(), false)
-        } else {
-            self.compute_repeat_plan(key, now)
-        };
+    /// Rewrites a resolved token before it is typed: dynamic text first,
+    /// then voicing keys and the profile's output rules. Tokens none of
+    /// this applies to are passed on as they are.
+    fn post_process_token<'t>(
+        &mut self,
+        token: Cow<'t, Token>,
+        is_japanese: bool,
+    ) -> Cow<'t, Token> {
+        let rewritten = matches!(&*token, Token::KeySequence(strokes)
+        if strokes.iter().any(|stroke| {
+            matches!(stroke.key, KeySpec::Dynamic(_) | KeySpec::Voicing(_))
+        }));
+        let token = if rewritten {
+            Cow::Owned(match dynamic_text::expand_token(token.into_owned()) {
+                Token::KeySequence(strokes)
+                    if strokes
+                        .iter()
+                        .any(|stroke| matches!(stroke.key, KeySpec::Voicing(_))) =>
+                {
+                    Token::KeySequence(voicing::compose(
+                        strokes,
+                        self.undo.last_kana(),
+                        is_japanese,
+                    ))
+                }
+                token => token,
+            })
+        } else {
+            token
+        };
+        if self.chord_engine.profile.output_rules.is_empty() {
+            token
+        } else {
+            Cow::Owned(self.apply_output_rules(token.into_owned()))
+        }
+    }
+
+    /// Applies the output rules to the text of a token. Strokes with
+    /// Ctrl, Alt or Win are shortcuts and are left alone.
+    fn apply_output_rules(&self, token: Token) -> Token {
+        let rules = &self.chord_engine.profile.output_rules;
+        if rules.is_empty() {
+            return token;
+        }
+        let rewrite = |text: &str| apply_output_rules(rules, text);
+        match token {
+            Token::ImeChar(text) => Token::ImeChar(rewrite(&text)),
+            Token::DirectChar(text) => Token::DirectChar(rewrite(&text)),
+            Token::KeySequence(strokes) => Token::KeySequence(
+                strokes
+                    .into_iter()
+                    .filter_map(|stroke| {
+                        if stroke.mods.ctrl || stroke.mods.alt || stroke.mods.win {
+                            return Some(stroke);
+                        }
+                        let key = match stroke.key {
+                            KeySpec::Char(c) => {
+                                let text = rewrite(c.encode_utf8(&mut [0; 4]));
+                                let mut chars = text.chars();
+                                match (chars.next(), chars.next()) {
+                                    (None, _) => return None,
+                                    (Some(c), None) => KeySpec::Char(c),
+                                    _ => KeySpec::DirectString(text),
+                                }
+                            }
+                            KeySpec::DirectString(text) => KeySpec::DirectString(rewrite(&text)),
+                            KeySpec::Paste(text) => KeySpec::Paste(rewrite(&text)),
+                            key => key,
+                        };
+                        Some(KeyStroke { key, ..stroke })
+                    })
+                    .collect(),
+            ),
+            Token::None => Token::None,
+        }
+    }
+
+    /// Events of a resolved token. While output scripts are loaded, a token
+    /// with text becomes a single [`InputEvent::Script`] marker, resolved
+    /// outside the engine lock. Tokens without text (control keys,
+    /// shortcuts) are not shown to scripts.
+    fn output_events(
+        &self,
+        kind: TraceKind,
+        keys: &[ScKey],
+        token: &Token,
+        shift: bool,
+        is_japanese: bool,
+    ) -> Option<Vec<InputEvent>> {
+        let events = self.token_to_events_with_ime(token, shift, is_japanese)?;
+        if !scripting::is_active() {
+            return Some(events);
+        }
+        let Some(text) = token_text(token) else {
+            return Some(events);
+        };
+        Some(vec![InputEvent::Script(Box::new(ScriptCall {
+            kind,
+            keys: keys_label(keys),
+            text,
+            japanese: is_japanese,
+            shift,
+            token: token.clone(),
+            events,
+        }))])
+    }
+
+    /// Events for an output the scripts replaced or dropped. Undo forgets
+    /// it, as it no longer matches what was recorded.
+    pub(crate) fn scripted_events(
+        &mut self,
+        call: ScriptCall,
+        outcome: ScriptOutcome,
+    ) -> Vec<InputEvent> {
+        let token = match outcome {
+            ScriptOutcome::Keep => return call.events,
+            ScriptOutcome::Suppress => {
+                self.undo.clear();
+                return Vec::new();
+            }
+            ScriptOutcome::Replace(text) => match call.token {
+                Token::KeySequence(_) => Token::KeySequence(
+                    text.chars()
+                        .map(|c| KeyStroke {
+                            key: KeySpec::Char(c),
+                            mods: Modifiers::default(),
+                        })
+                        .collect(),
+                ),
+                Token::DirectChar(_) => Token::DirectChar(text),
+                _ => Token::ImeChar(text),
+            },
+        };
+        self.undo.clear();
+        self.token_to_events_with_ime(&token, call.shift, call.japanese)
+            .unwrap_or_default()
+    }
+
+    /// Bookkeeping for an emitted token: undo history, usage, training and trace.
+    fn record_output(&mut self, kind: TraceKind, keys: &[ScKey], token: &Token, is_japanese: bool) {
+        self.undo.record_token(token, is_japanese);
+        self.usage.record(keys);
+        recorder::record_output(kind, keys);
+        if !self.trace_enabled && self.training.is_none() && self.chord_test.is_none() {
+            return;
+        }
+        let mut event = TraceEvent::new(kind, keys, token);
+        if let Some(ref mut session) = self.training {
+            session.annotate(&mut event, self.clock.now());
+        }
+        if let Some(ref test) = self.chord_test {
+            self.publish(|| {
+                let shift = self.shift_side.is_held();
+                let section = self.resolved_section_name(keys, shift, is_japanese);
+                EngineEvent::ChordTest(test.event(&event, section, self.clock.now()))
+            });
+        }
+        if !self.trace_enabled && self.training.is_none() {
+            return;
+        }
+        self.publish(|| EngineEvent::Trace(event));
+    }
+
+    fn is_repeat_event(&self, key: ScKey) -> bool {
+        self.chord_engine.state.pressed.contains(&key)
+    }
+
+    fn handle_repeat_event(&mut self, key: ScKey, shift: bool, is_japanese: bool) -> KeyAction {
+        let now = self.clock.now();
+        if self.chord_engine.is_sands_key(key) {
+            return self.handle_sands_repeat(key, now);
+        }
+        if self
+            .chord_engine
+            .thumb_side_config(key)
+            .is_some_and(|cfg| cfg.repeat)
+        {
+            return self.handle_thumb_repeat(key, shift, is_japanese, now);
+        }
+        if self.is_pinky_shift_key(key) || self.chord_engine.holds_for_long_press(key) {
+            return KeyAction::Block;
+        }
+        let (keys, consume_pending) = if let Some(keys) = self.repeat_plans.get(&key) {
+            (keys.clone(), false)
+        } else {
+            self.compute_repeat_plan(key, now)
+        };
 
         let token = self.resolve(&keys, shift, is_japanese);
         let allow_repeat = self.repeat_allowed_for_token(token.as_ref());
@@ -1412,8 +2208,8 @@ impl Engine {
             return KeyAction::Block;
         }
 
-        let events = if let Some(token) = token {
-            self.token_to_events_with_ime(&token, shift, is_japanese)
+        let events = if let Some(ref token) = token {
+            self.token_to_events_with_ime(token, shift, is_japanese)
                 .unwrap_or_else(|| self.repeat_fallback_events(&keys, shift, is_japanese))
         } else {
             self.repeat_fallback_events(&keys, shift, is_japanese)
@@ -1423,6 +2219,12 @@ impl Engine {
             return KeyAction::Block;
         }
 
+        self.undo.begin_output();
+        match token {
+            Some(ref token) => self.record_output(TraceKind::Repeat, &keys, token, is_japanese),
+            None => self.undo.clear(),
+        }
+
         if consume_pending {
             self.consume_pending_for_repeat(&keys);
         }
@@ -1430,6 +2232,73 @@ impl Engine {
         KeyAction::Inject(events)
     }
 
+    /// Space held as SandS only repeats when held alone past the tap timeout.
+    /// Once it has repeated, its release no longer emits a tap.
+    fn handle_sands_repeat(&mut self, key: ScKey, now: Instant) -> KeyAction {
+        let sands = &self.chord_engine.profile.sands;
+        let state = &self.chord_engine.state;
+        let alone = state.pressed.len() == 1 && state.pending.iter().all(|p| p.key == key);
+        let held_long = state.down_ts.get(&key).is_some_and(|t| {
+            now.saturating_duration_since(*t) >= Duration::from_millis(sands.tap_timeout_ms)
+        });
+        if !(sands.repeat && alone && held_long) {
+            return KeyAction::Block;
+        }
+
+        self.chord_engine.state.used_modifiers.insert(key);
+        self.undo.clear();
+        KeyAction::Inject(vec![
+            InputEvent::Scancode(key.sc, key.ext, false),
+            InputEvent::Scancode(key.sc, key.ext, true),
+        ])
+    }
+
+    /// A thumb key with `repeat` held alone past `thumb_repeat_delay_ms`
+    /// repeats its single-press action. Once it has repeated, its release no
+    /// longer emits a tap, but it still shifts keys pressed while held.
+    fn handle_thumb_repeat(
+        &mut self,
+        key: ScKey,
+        shift: bool,
+        is_japanese: bool,
+        now: Instant,
+    ) -> KeyAction {
+        let Some(cfg) = self.chord_engine.thumb_side_config(key) else {
+            return KeyAction::Block;
+        };
+        let tap_key = match cfg.single_press {
+            ThumbShiftSinglePress::Enable => key,
+            ThumbShiftSinglePress::SpaceKey => ScKey::new(0x39, false),
+            ThumbShiftSinglePress::None | ThumbShiftSinglePress::PrefixShift => {
+                return KeyAction::Block;
+            }
+        };
+        let delay = Duration::from_millis(self.chord_engine.profile.thumb_repeat_delay_ms);
+        let state = &self.chord_engine.state;
+        let alone = state.pressed.len() == 1 && state.pending.iter().all(|p| p.key == key);
+        let held_long = state
+            .down_ts
+            .get(&key)
+            .is_some_and(|t| now.saturating_duration_since(*t) >= delay);
+        if !(alone && held_long) {
+            return KeyAction::Block;
+        }
+
+        self.chord_engine.state.used_modifiers.insert(key);
+        self.undo.begin_output();
+        if let Some(token) = self.resolve(&[tap_key], shift, is_japanese) {
+            if let Some(events) = self.token_to_events_with_ime(&token, shift, is_japanese) {
+                self.record_output(TraceKind::Repeat, &[tap_key], &token, is_japanese);
+                return KeyAction::Inject(events);
+            }
+        }
+        self.undo.clear();
+        KeyAction::Inject(vec![
+            InputEvent::Scancode(tap_key.sc, tap_key.ext, false),
+            InputEvent::Scancode(tap_key.sc, tap_key.ext, true),
+        ])
+    }
+
     fn compute_repeat_plan(&self, key: ScKey, now: Instant) -> (Vec<ScKey>, bool) {
         let (mut keys, consume_pending) =
             if let Some(chord_keys) = self.detect_repeat_chord(key, now) {
@@ -1452,12 +2321,7 @@ impl Engine {
         }
 
         if let Some(ref tk) = self.chord_engine.profile.thumb_keys {
-            let left = tk.left.iter().find(|k| self.is_active_thumb_key(**k));
-            let right = tk.right.iter().find(|k| self.is_active_thumb_key(**k));
-            let ext1 = tk.ext1.iter().find(|k| self.is_active_thumb_key(**k));
-            let ext2 = tk.ext2.iter().find(|k| self.is_active_thumb_key(**k));
-
-            if let Some(k) = left.or(right).or(ext1).or(ext2) {
+            if let Some(k) = tk.iter().find(|k| self.is_active_thumb_key(**k)) {
                 keys.push(*k);
             }
         }
@@ -1474,7 +2338,6 @@ impl Engine {
         let primary = pending.iter().find(|p| p.key == key)?;
         let mut best_ratio = 0.0;
         let mut best_key = None;
-        let threshold = self.chord_engine.profile.char_key_overlap_ratio;
 
         for other in pending.iter() {
             if other.key == key {
@@ -1488,6 +2351,7 @@ impl Engine {
             };
 
             let ratio = Self::pending_overlap_ratio(p1, p2, now);
+            let (threshold, _) = self.chord_engine.pair_thresholds(p1.key, p2.key);
             if ratio >= threshold && (best_key.is_none() || ratio > best_ratio) {
                 best_ratio = ratio;
                 best_key = Some(other.key);
@@ -1548,14 +2412,62 @@ impl Engine {
 
     fn is_thumb_key(&self, key: ScKey) -> bool {
         if let Some(ref tk) = self.chord_engine.profile.thumb_keys {
-            return tk.left.contains(&key)
-                || tk.right.contains(&key)
-                || tk.ext1.contains(&key)
-                || tk.ext2.contains(&key);
+            return tk.contains(&key);
         }
         false
     }
 
+    fn is_pinky_shift_key(&self, key: ScKey) -> bool {
+        self.chord_engine
+            .profile
+            .thumb_keys
+            .as_ref()
+            .is_some_and(|tk| tk.shift.contains(&key))
+    }
+
+    /// Whether Shift key events have to reach the engine rather than the OS.
+    pub fn needs_shift_handling(&self) -> bool {
+        self.enabled
+            && self
+                .chord_engine
+                .profile
+                .thumb_keys
+                .as_ref()
+                .is_some_and(|tk| !tk.shift.is_empty())
+    }
+
+    /// Passes a key the layout leaves alone. Pinky Shift is kept from the
+    /// OS, so a press made while it is held gets Shift added and the Shift
+    /// no longer taps on release.
+    fn passthrough_under_shift(
+        &mut self,
+        planes: &ThumbPlanes,
+        pass_through: PassThroughCurrent,
+        source_key: ScKey,
+        up: bool,
+    ) -> KeyAction {
+        if up || !planes.shift.is_held() {
+            return passthrough_action(pass_through, source_key, up);
+        }
+        let Some(event) = passthrough_event(pass_through, source_key, up) else {
+            return KeyAction::Block;
+        };
+        let held: Vec<ScKey> = self
+            .chord_engine
+            .state
+            .pressed
+            .iter()
+            .copied()
+            .filter(|k| self.is_pinky_shift_key(*k))
+            .collect();
+        self.chord_engine.state.used_modifiers.extend(held);
+        KeyAction::Inject(vec![
+            InputEvent::Scancode(0x2A, false, false),
+            event,
+            InputEvent::Scancode(0x2A, false, true),
+        ])
+    }
+
     fn is_active_thumb_key(&self, key: ScKey) -> bool {
         if !self.chord_engine.state.pressed.contains(&key) {
             return false;
@@ -1586,11 +2498,179 @@ impl Engine {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum FunctionKeySpec {
-    Key(ScKey),
-    CapsLock,
-    KanaLock,
+/// True if injecting `events` turns the IME on or off or otherwise changes
+/// its state.
+fn changes_ime_state(events: &[InputEvent]) -> bool {
+    events.iter().any(|event| match event {
+        InputEvent::ImeControl(_)
+        | InputEvent::Ime(_)
+        | InputEvent::DirectString(_)
+        | InputEvent::ClipboardText => true,
+        InputEvent::Script(call) => changes_ime_state(&call.events),
+        _ => false,
+    })
+}
+
+/// Flattens the actions from [`Engine::process_events`] into one list of
+/// events to inject; passed keys are re-sent as their own scancode.
+pub fn coalesce_actions(events: &[RawKeyEvent], actions: Vec<KeyAction>) -> Vec<InputEvent> {
+    let mut out = Vec::new();
+    for (event, action) in events.iter().zip(actions) {
+        match action {
+            KeyAction::Pass => out.push(InputEvent::Scancode(event.sc, event.ext, event.up)),
+            KeyAction::Block => {}
+            KeyAction::Inject(events) => out.extend(events),
+        }
+    }
+    out
+}
+
+/// One step of a scripted key sequence for [`invariant_check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptedKey {
+    pub key: ScKey,
+    pub up: bool,
+    /// Time since the previous step.
+    pub after_ms: u64,
+}
+
+/// Engine invariant broken by a scripted key sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// Keys left pending or pressed after every key was released.
+    StuckKeys {
+        pending: Vec<ScKey>,
+        pressed: Vec<ScKey>,
+    },
+    /// A press of a non-shift key never produced a decision.
+    UnresolvedKey { key: ScKey },
+    /// A non-shift key was decided more often than it was pressed.
+    DuplicateOutput { step: usize, key: ScKey },
+}
+
+/// Feeds `steps` to the chord engine of `engine` with synthetic timestamps,
+/// releases whatever is still held, and checks that every press of a
+/// non-shift key is decided exactly once and that nothing stays pending.
+/// Downs of a key that is already down are skipped. Input state is reset
+/// before and after; the layout and profile are kept.
+pub fn invariant_check(
+    engine: &mut Engine,
+    steps: &[ScriptedKey],
+) -> Result<(), InvariantViolation> {
+    engine.reset_input_state();
+    let start = engine.clock.now();
+    let result = run_invariant_check(&mut engine.chord_engine, steps, start);
+    engine.reset_input_state();
+    result
+}
+
+fn run_invariant_check(
+    chord_engine: &mut ChordEngine,
+    steps: &[ScriptedKey],
+    start: Instant,
+) -> Result<(), InvariantViolation> {
+    let mut run = InvariantRun {
+        now: start,
+        held: Vec::new(),
+        unresolved: HashMap::new(),
+    };
+    for (step, sk) in steps.iter().enumerate() {
+        run.feed(chord_engine, step, sk)?;
+    }
+    for key in run.held.clone() {
+        let release = ScriptedKey {
+            key,
+            up: true,
+            after_ms: 1,
+        };
+        run.feed(chord_engine, steps.len(), &release)?;
+    }
+
+    let state = &chord_engine.state;
+    if !state.pending.is_empty() || !state.pressed.is_empty() {
+        return Err(InvariantViolation::StuckKeys {
+            pending: state.pending.iter().map(|p| p.key).collect(),
+            pressed: state.pressed.iter().copied().collect(),
+        });
+    }
+    match run.unresolved.into_iter().find(|(_, count)| *count > 0) {
+        Some((key, _)) => Err(InvariantViolation::UnresolvedKey { key }),
+        None => Ok(()),
+    }
+}
+
+struct InvariantRun {
+    now: Instant,
+    held: Vec<ScKey>,
+    /// Presses of non-shift keys not yet seen in a decision.
+    unresolved: HashMap<ScKey, usize>,
+}
+
+impl InvariantRun {
+    fn feed(
+        &mut self,
+        chord_engine: &mut ChordEngine,
+        step: usize,
+        sk: &ScriptedKey,
+    ) -> Result<(), InvariantViolation> {
+        self.now += Duration::from_millis(sk.after_ms);
+        if sk.up {
+            let Some(i) = self.held.iter().position(|k| *k == sk.key) else {
+                return Ok(());
+            };
+            self.held.remove(i);
+        } else {
+            if self.held.contains(&sk.key) {
+                return Ok(());
+            }
+            self.held.push(sk.key);
+            if !chord_engine.is_modifier_key(sk.key) {
+                *self.unresolved.entry(sk.key).or_default() += 1;
+            }
+        }
+        let decisions = chord_engine.on_event(KeyEvent {
+            key: sk.key,
+            edge: if sk.up { KeyEdge::Up } else { KeyEdge::Down },
+            injected: false,
+            t: self.now,
+        });
+        for d in decisions {
+            let keys = match d {
+                Decision::KeyTap(k)
+                | Decision::LongPress(k)
+                | Decision::Passthrough(k, KeyEdge::Down) => vec![k],
+                Decision::Chord(keys) => keys,
+                _ => continue,
+            };
+            for key in keys
+                .into_iter()
+                .filter(|k| !chord_engine.is_modifier_key(*k))
+            {
+                match self.unresolved.get_mut(&key) {
+                    Some(count) if *count > 0 => *count -= 1,
+                    _ => return Err(InvariantViolation::DuplicateOutput { step, key }),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Index of the first event that would disturb an IME composition: IME
+/// toggles and operations, direct strings, pastes, and Enter following
+/// other output (an Enter on its own is left alone, as it is meant to confirm).
+fn first_composition_sensitive_event(events: &[InputEvent]) -> Option<usize> {
+    events
+        .iter()
+        .enumerate()
+        .position(|(index, event)| match event {
+            InputEvent::ImeControl(_)
+            | InputEvent::Ime(_)
+            | InputEvent::DirectString(_)
+            | InputEvent::Paste(_) => true,
+            InputEvent::Scancode(0x1C, _, false) => index > 0,
+            _ => false,
+        })
 }
 
 fn passthrough_event(mode: PassThroughCurrent, source_key: ScKey, up: bool) -> Option<InputEvent> {
@@ -1613,19 +2693,79 @@ fn passthrough_action(mode: PassThroughCurrent, _source_key: ScKey, up: bool) ->
     }
 }
 
-fn emit_pseudo_function_key(pseudo: FunctionPseudoKey, up: bool) -> KeyAction {
-    if up {
-        return KeyAction::Block;
+/// Releases the physical modifier around `events`. Alt and Win get a Ctrl
+/// tap on both sides so that releasing them opens no menu.
+fn with_modifier_released(held: HeldModifier, events: Vec<InputEvent>) -> Vec<InputEvent> {
+    let key = held.plane.key(held.right);
+    let mask: &[InputEvent] = if held.plane == ModifierPlane::Ctrl {
+        &[]
+    } else {
+        &[
+            InputEvent::Scancode(0x1D, false, false),
+            InputEvent::Scancode(0x1D, false, true),
+        ]
+    };
+    let mut out = mask.to_vec();
+    out.push(InputEvent::Scancode(key.sc, key.ext, true));
+    out.extend(events);
+    out.push(InputEvent::Scancode(key.sc, key.ext, false));
+    out.extend_from_slice(mask);
+    out
+}
+
+/// Appends `events` to `out`, taking over their buffer when `out` is empty.
+fn append_events(out: &mut Vec<InputEvent>, events: Vec<InputEvent>) {
+    if out.is_empty() {
+        *out = events;
+    } else {
+        out.extend(events);
+    }
+}
+
+/// Token `id` of `layout`, borrowed from the caller's handle on it.
+fn layout_token(
+    layout: &Option<Arc<CompiledLayout>>,
+    id: Option<TokenId>,
+) -> Option<Cow<'_, Token>> {
+    Some(Cow::Borrowed(layout.as_deref()?.token(id?)))
+}
+
+/// Key releases among `events`: all a chord test lets through, so that no
+/// key is left down.
+fn key_releases(mut events: Vec<InputEvent>) -> Vec<InputEvent> {
+    events.retain(|event| matches!(event, InputEvent::Scancode(_, _, true)));
+    events
+}
+
+/// Puts `events` ahead of whatever `action` does with the current key.
+fn prepend_events(
+    mut events: Vec<InputEvent>,
+    action: KeyAction,
+    sc: u16,
+    ext: bool,
+    up: bool,
+) -> KeyAction {
+    if events.is_empty() {
+        return action;
+    }
+    match action {
+        KeyAction::Pass => events.push(InputEvent::Scancode(sc, ext, up)),
+        KeyAction::Block => {}
+        KeyAction::Inject(rest) => events.extend(rest),
     }
+    KeyAction::Inject(events)
+}
 
-    let events = match pseudo {
-        FunctionPseudoKey::CapsLock => vec![
+/// Keystrokes that toggle `lock`: Shift+CapsLock and Ctrl+Shift+ひらがな.
+pub(crate) fn lock_toggle_events(lock: LockKey) -> Vec<InputEvent> {
+    match lock {
+        LockKey::Caps => vec![
             InputEvent::Scancode(0x2A, false, false),
             InputEvent::Scancode(0x3A, false, false),
             InputEvent::Scancode(0x3A, false, true),
             InputEvent::Scancode(0x2A, false, true),
         ],
-        FunctionPseudoKey::KanaLock => vec![
+        LockKey::Kana => vec![
             InputEvent::Scancode(0x1D, false, false),
             InputEvent::Scancode(0x2A, false, false),
             InputEvent::Scancode(0x70, false, false),
@@ -1633,8 +2773,7 @@ fn emit_pseudo_function_key(pseudo: FunctionPseudoKey, up: bool) -> KeyAction {
             InputEvent::Scancode(0x2A, false, true),
             InputEvent::Scancode(0x1D, false, true),
         ],
-    };
-    KeyAction::Inject(events)
+    }
 }
 
 fn is_virtual_extended_key(key: ScKey) -> bool {
@@ -1645,344 +2784,110 @@ fn is_virtual_extended_key(key: ScKey) -> bool {
         )
 }
 
-fn build_function_key_swap_map(
-    swaps: &[(String, String)],
-) -> HashMap<ScKey, FunctionKeySwapTarget> {
-    let mut map = HashMap::new();
-    for (source_name, target_name) in swaps {
-        let source_spec = match parse_function_key_spec(source_name) {
-            Some(spec) => spec,
-            None => continue,
-        };
-        let target_spec = match parse_function_key_spec(target_name) {
-            Some(spec) => spec,
-            None => continue,
-        };
-
-        let source_key = match source_spec {
-            FunctionKeySpec::Key(key) => key,
-            FunctionKeySpec::CapsLock | FunctionKeySpec::KanaLock => continue,
-        };
-
-        let target = match target_spec {
-            FunctionKeySpec::Key(key) => FunctionKeySwapTarget::Key(key),
-            FunctionKeySpec::CapsLock => FunctionKeySwapTarget::CapsLock,
-            FunctionKeySpec::KanaLock => FunctionKeySwapTarget::KanaLock,
-        };
-        map.insert(source_key, target);
-    }
-    map
-}
-
-fn parse_function_key_spec(name: &str) -> Option<FunctionKeySpec> {
-    let key = match name {
-        "Esc" => Some(ScKey::new(0x01, false)),
-        "Tab" => Some(ScKey::new(0x0F, false)),
-        "無変換" => Some(ScKey::new(0x7B, false)),
-        "Space" => Some(ScKey::new(0x39, false)),
-        "変換" => Some(ScKey::new(0x79, false)),
-        "Enter" => Some(ScKey::new(0x1C, false)),
-        "BackSpace" => Some(ScKey::new(0x0E, false)),
-        "Delete" => Some(ScKey::new(0x53, true)),
-        "Insert" => Some(ScKey::new(0x52, true)),
-        "左Shift" => Some(ScKey::new(0x2A, false)),
-        "右Shift" => Some(ScKey::new(0x36, false)),
-        "左Ctrl" => Some(ScKey::new(0x1D, false)),
-        "右Ctrl" => Some(ScKey::new(0x1D, true)),
-        "左Alt" => Some(ScKey::new(0x38, false)),
-        "右Alt" => Some(ScKey::new(0x38, true)),
-        "CapsLock/英数" | "CapsLock" => Some(ScKey::new(0x3A, false)),
-        "半角/全角" => Some(ScKey::new(0x29, false)),
-        "カタカナ/ひらがな" => Some(ScKey::new(0x70, false)),
-        "左Win" => Some(ScKey::new(0x5B, true)),
-        "右Win" => Some(ScKey::new(0x5C, true)),
-        "Applications" => Some(ScKey::new(0x5D, true)),
-        "上" => Some(ScKey::new(0x48, true)),
-        "左" => Some(ScKey::new(0x4B, true)),
-        "右" => Some(ScKey::new(0x4D, true)),
-        "下" => Some(ScKey::new(0x50, true)),
-        "Home" => Some(ScKey::new(0x47, true)),
-        "End" => Some(ScKey::new(0x4F, true)),
-        "PageUp" => Some(ScKey::new(0x49, true)),
-        "PageDown" => Some(ScKey::new(0x51, true)),
-        "拡張1" => Some(ScKey::new(EXTENDED_KEY_1_SC, false)),
-        "拡張2" => Some(ScKey::new(EXTENDED_KEY_2_SC, false)),
-        "拡張3" => Some(ScKey::new(EXTENDED_KEY_3_SC, false)),
-        "拡張4" => Some(ScKey::new(EXTENDED_KEY_4_SC, false)),
-        "Capsロック" => return Some(FunctionKeySpec::CapsLock),
-        "かなロック" => return Some(FunctionKeySpec::KanaLock),
-        _ => function_key_scancode_from_name(name).map(|sc| ScKey::new(sc, false)),
-    }?;
-
-    Some(FunctionKeySpec::Key(key))
+fn build_key_remap_map(remaps: &[(String, String)]) -> HashMap<ScKey, ScKey> {
+    remaps
+        .iter()
+        .filter_map(|(from, to)| Some((key_from_name(from)?, key_from_name(to)?)))
+        .filter(|(from, to)| from != to)
+        .collect()
 }
 
-fn function_key_scancode_from_name(name: &str) -> Option<u16> {
-    let number = name.strip_prefix('F')?.parse::<u8>().ok()?;
-    match number {
-        1 => Some(0x3B),
-        2 => Some(0x3C),
-        3 => Some(0x3D),
-        4 => Some(0x3E),
-        5 => Some(0x3F),
-        6 => Some(0x40),
-        7 => Some(0x41),
-        8 => Some(0x42),
-        9 => Some(0x43),
-        10 => Some(0x44),
-        11 => Some(0x57),
-        12 => Some(0x58),
-        13 => Some(0x64),
-        14 => Some(0x65),
-        15 => Some(0x66),
-        16 => Some(0x67),
-        17 => Some(0x68),
-        18 => Some(0x69),
-        19 => Some(0x6A),
-        20 => Some(0x6B),
-        21 => Some(0x6C),
-        22 => Some(0x6D),
-        23 => Some(0x6E),
-        24 => Some(0x76),
-        _ => None,
-    }
+/// Whether `new` differs from `old` only in what its cells type, so that
+/// the keys `load_layout` hooks and the planes it finds stay the same.
+fn same_layout_structure(old: &Layout, new: &Layout) -> bool {
+    old.max_chord_size == new.max_chord_size
+        && old.function_key_swaps == new.function_key_swaps
+        && old.key_remaps == new.key_remaps
+        && old.section_names == new.section_names
+        && defined_cells(old) == defined_cells(new)
 }
 
-fn append_keystroke_events(
-    events: &mut Vec<InputEvent>,
-    stroke: &KeyStroke,
-    shift_held: bool,
-    allow_unicode_fallback: bool,
-    is_japanese: bool,
-) {
-    let key_events = match stroke.key {
-        KeySpec::Scancode(sc, ext) => Some((sc, ext, false)),
-        KeySpec::VirtualKey(vk) => vk_to_scancode(vk).map(|(s, e)| (s, e, false)),
-        KeySpec::Char(c) => char_to_scancode(c, is_japanese),
-        KeySpec::ImeOn => {
-            events.push(InputEvent::ImeControl(true));
-            return;
-        }
-        KeySpec::ImeOff => {
-            events.push(InputEvent::ImeControl(false));
-            return;
-        }
-        KeySpec::DirectString(ref s) => {
-            // Hand off the complex IME handling logic to the hook (outside the lock).
-            // This avoids deadlock when calling IME APIs while holding the Engine lock.
-            events.push(InputEvent::DirectString(s.clone()));
-            return;
-        }
-    };
-
-    if let Some((sc, ext, needs_shift)) = key_events {
-        let mut mods = stroke.mods;
-        if needs_shift {
-            mods.shift = true;
-        }
-
-        if mods.shift && shift_held {
-            mods.shift = false;
-        }
-
-        let mods_evs = modifier_scancodes(mods);
-        for (mod_sc, mod_ext) in mods_evs.iter() {
-            events.push(InputEvent::Scancode(*mod_sc, *mod_ext, false));
-        }
-        events.push(InputEvent::Scancode(sc, ext, false));
-        events.push(InputEvent::Scancode(sc, ext, true));
-        for (mod_sc, mod_ext) in mods_evs.iter().rev() {
-            events.push(InputEvent::Scancode(*mod_sc, *mod_ext, true));
-        }
-        return;
-    }
-
-    if allow_unicode_fallback {
-        if let KeySpec::Char(c) = stroke.key {
-            events.push(InputEvent::Unicode(c, false));
-            events.push(InputEvent::Unicode(c, true));
+/// Section, plane tag (empty for the base plane) and position of every
+/// defined cell, plus an entry per plane so empty planes count too.
+fn defined_cells(layout: &Layout) -> HashSet<(&str, &str, Option<Rc>)> {
+    let mut cells = HashSet::new();
+    for (name, section) in &layout.sections {
+        let planes = std::iter::once(("", &section.base_plane)).chain(
+            section
+                .sub_planes
+                .iter()
+                .map(|(tag, plane)| (tag.as_str(), plane)),
+        );
+        for (tag, plane) in planes {
+            cells.insert((name.as_str(), tag, None));
+            cells.extend(
+                plane
+                    .map
+                    .iter()
+                    .filter(|(_, token)| !matches!(token, Token::None))
+                    .map(|(rc, _)| (name.as_str(), tag, Some(*rc))),
+            );
         }
     }
+    cells
 }
 
-fn modifier_scancodes(mods: Modifiers) -> Vec<(u16, bool)> {
-    let mut scancodes = Vec::new();
-    if mods.ctrl {
-        scancodes.push((0x1D, false));
-    }
-    if mods.shift {
-        scancodes.push((0x2A, false));
-    }
-    if mods.alt {
-        scancodes.push((0x38, false));
-    }
-    if mods.win {
-        scancodes.push((0x5B, true));
-    }
-    scancodes
+fn build_chord_priority_set(priorities: &[(String, String)]) -> HashSet<(ScKey, ScKey)> {
+    let key = crate::jis_map::key_name_to_key;
+    priorities
+        .iter()
+        .filter_map(|(modifier, target)| Some((key(modifier)?, key(target)?)))
+        .collect()
 }
 
-fn vk_to_scancode(vk: u16) -> Option<(u16, bool)> {
-    let scan = unsafe { MapVirtualKeyW(vk as u32, MAPVK_VK_TO_VSC_EX) };
-    if scan == 0 {
-        return None;
-    }
-    let ext = (scan & 0xFF00) == 0xE000;
-    Some(((scan & 0x00FF) as u16, ext))
+/// Keys with a token in some section's `<long>` plane.
+fn long_press_keys(layout: &Layout) -> HashSet<ScKey> {
+    let rcs: HashSet<_> = layout
+        .sections
+        .values()
+        .filter_map(|section| section.sub_planes.get(LONG_PRESS_TAG))
+        .flat_map(|plane| &plane.map)
+        .filter(|(_, token)| !matches!(token, Token::None))
+        .map(|(rc, _)| *rc)
+        .collect();
+    JIS_SC_TO_RC
+        .iter()
+        .filter(|(_, rc)| rcs.contains(rc))
+        .map(|(key, _)| *key)
+        .collect()
 }
 
-fn char_to_scancode(c: char, is_japanese: bool) -> Option<(u16, bool, bool)> {
-    // JP-Specific overrides
-    if is_japanese {
-        match c {
-            '、' => return Some((0x33, false, false)), // ,
-            '。' => return Some((0x34, false, false)), // .
-            '・' => return Some((0x35, false, false)), // /
-            '「' => return Some((0x1B, false, false)), // [
-            '」' => return Some((0x2B, false, false)), // ]
+fn build_overlap_overrides(entries: &[(Vec<String>, f64)]) -> OverlapOverrides {
+    let key = crate::jis_map::key_name_to_key;
+    let mut overrides = OverlapOverrides::default();
+    for (names, ratio) in entries {
+        match names.as_slice() {
+            [name] => {
+                if let Some(k) = key(name) {
+                    overrides.keys.insert(k, *ratio);
+                }
+            }
+            [a, b] => {
+                if let (Some(a), Some(b)) = (key(a), key(b)) {
+                    overrides.pairs.insert((a, b), *ratio);
+                }
+            }
             _ => {}
         }
     }
+    overrides
+}
 
-    match c {
-        // Lowercase
-        'a'..='z' => match c {
-            'a' => Some((0x1E, false, false)),
-            'b' => Some((0x30, false, false)),
-            'c' => Some((0x2E, false, false)),
-            'd' => Some((0x20, false, false)),
-            'e' => Some((0x12, false, false)),
-            'f' => Some((0x21, false, false)),
-            'g' => Some((0x22, false, false)),
-            'h' => Some((0x23, false, false)),
-            'i' => Some((0x17, false, false)),
-            'j' => Some((0x24, false, false)),
-            'k' => Some((0x25, false, false)),
-            'l' => Some((0x26, false, false)),
-            'm' => Some((0x32, false, false)),
-            'n' => Some((0x31, false, false)),
-            'o' => Some((0x18, false, false)),
-            'p' => Some((0x19, false, false)),
-            'q' => Some((0x10, false, false)),
-            'r' => Some((0x13, false, false)),
-            's' => Some((0x1F, false, false)),
-            't' => Some((0x14, false, false)),
-            'u' => Some((0x16, false, false)),
-            'v' => Some((0x2F, false, false)),
-            'w' => Some((0x11, false, false)),
-            'x' => Some((0x2D, false, false)),
-            'y' => Some((0x15, false, false)),
-            'z' => Some((0x2C, false, false)),
-            _ => None,
-        },
-        // Uppercase
-        'A'..='Z' => match c.to_ascii_lowercase() {
-            'a' => Some((0x1E, false, true)),
-            'b' => Some((0x30, false, true)),
-            'c' => Some((0x2E, false, true)),
-            'd' => Some((0x20, false, true)),
-            'e' => Some((0x12, false, true)),
-            'f' => Some((0x21, false, true)),
-            'g' => Some((0x22, false, true)),
-            'h' => Some((0x23, false, true)),
-            'i' => Some((0x17, false, true)),
-            'j' => Some((0x24, false, true)),
-            'k' => Some((0x25, false, true)),
-            'l' => Some((0x26, false, true)),
-            'm' => Some((0x32, false, true)),
-            'n' => Some((0x31, false, true)),
-            'o' => Some((0x18, false, true)),
-            'p' => Some((0x19, false, true)),
-            'q' => Some((0x10, false, true)),
-            'r' => Some((0x13, false, true)),
-            's' => Some((0x1F, false, true)),
-            't' => Some((0x14, false, true)),
-            'u' => Some((0x16, false, true)),
-            'v' => Some((0x2F, false, true)),
-            'w' => Some((0x11, false, true)),
-            'x' => Some((0x2D, false, true)),
-            'y' => Some((0x15, false, true)),
-            'z' => Some((0x2C, false, true)),
-            _ => None,
-        },
-        // Numbers
-        '1' => Some((0x02, false, false)),
-        '2' => Some((0x03, false, false)),
-        '3' => Some((0x04, false, false)),
-        '4' => Some((0x05, false, false)),
-        '5' => Some((0x06, false, false)),
-        '6' => Some((0x07, false, false)),
-        '7' => Some((0x08, false, false)),
-        '8' => Some((0x09, false, false)),
-        '9' => Some((0x0A, false, false)),
-        '0' => Some((0x0B, false, false)),
-
-        // Symbols (JIS Standard)
-        '-' => Some((0x0C, false, false)),
-        '^' => Some((0x0D, false, false)),
-        '\\' | '¥' | '￥' => Some((0x7D, false, false)), // Yen (0x7D)
-        '@' => Some((0x1A, false, false)),
-        '[' => Some((0x1B, false, false)),
-        ';' => Some((0x27, false, false)),
-        ':' => Some((0x28, false, false)),
-        ']' => Some((0x2B, false, false)),
-        ',' => Some((0x33, false, false)),
-        '.' => Some((0x34, false, false)),
-        '/' => Some((0x35, false, false)),
-        '_' => Some((0x73, false, true)), // JIS Backslash/Ro (0x73) Shifted
-
-        // Shifted Symbols
-        '!' => Some((0x02, false, true)),  // 1
-        '"' => Some((0x03, false, true)),  // 2
-        '#' => Some((0x04, false, true)),  // 3
-        '$' => Some((0x05, false, true)),  // 4
-        '%' => Some((0x06, false, true)),  // 5
-        '&' => Some((0x07, false, true)),  // 6
-        '\'' => Some((0x08, false, true)), // 7
-        '(' => Some((0x09, false, true)),  // 8
-        ')' => Some((0x0A, false, true)),  // 9
-        // 0 -> nothing
-        '=' => Some((0x0C, false, true)), // -
-        '~' => Some((0x0D, false, true)), // ^
-        '|' => Some((0x7D, false, true)), // Yen
-        '`' => Some((0x1A, false, true)), // @
-        '{' => Some((0x1B, false, true)), // [
-        '+' => Some((0x27, false, true)), // ;
-        '*' => Some((0x28, false, true)), // :
-        '}' => Some((0x2B, false, true)), // ]
-        '<' => Some((0x33, false, true)), // ,
-        '>' => Some((0x34, false, true)), // .
-        '?' => Some((0x35, false, true)), // /
-
-        // Other
-        ' ' => Some((0x39, false, false)),
-        '\u{0008}' => Some((0x0E, false, false)),  // BS
-        '\u{000D}' => Some((0x1C, false, false)),  // Enter
-        '\u{F702}' => Some((0x4B, true, false)),   // Left Arrow (Extended)
-        '\u{F703}' => Some((0x4D, true, false)),   // Right Arrow (Extended)
-        '－' | 'ー' => Some((0x0C, false, false)), // Minus / Long Vowel (Standard Hyphen)
-
-        _ => None,
-    }
+/// Tag of the `[アプリ別]` entry for the executable `exe`.
+fn app_section_tag(entries: &[(String, String)], exe: Option<&str>) -> Option<String> {
+    let exe = exe_file_name(exe?);
+    entries
+        .iter()
+        .find(|(name, _)| exe_file_name(name) == exe)
+        .map(|(_, tag)| tag.clone())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn test_char_to_scancode() {
-        // Updated to use 2 args (is_japanese=false) and return 3-tuple (sc, ext, shift)
-        assert_eq!(char_to_scancode('－', false), Some((0x0C, false, false)));
-        assert_eq!(char_to_scancode('ー', false), Some((0x0C, false, false)));
-        assert_eq!(char_to_scancode('1', false), Some((0x02, false, false)));
-        assert_eq!(char_to_scancode('a', false), Some((0x1E, false, false)));
-        // Shifted char
-        assert_eq!(char_to_scancode('!', false), Some((0x02, false, true)));
-        // Japanese punctuation
-        assert_eq!(char_to_scancode('。', true), Some((0x34, false, false)));
-        assert_eq!(char_to_scancode('。', false), None); // Should fallback to unicode if not JP mode scancode mapping
-    }
+    use crate::chord_engine::{DecisionMode, OutputRule, SpaceWidth};
+    use crate::clock::ManualClock;
+    use crate::dynamic_text::DynamicText;
 
     use crate::parser::parse_yab_content;
 
@@ -2412,37 +3317,376 @@ xx,xx,s,t,xx,xx,xx,xx,xx,xx,xx,xx
     }
 
     #[test]
-    fn test_repeat_assigned_key_emits_repeat_and_suppresses_release() {
-        let config = "
-[ローマ字シフト無し]
-; R0
-dummy
-; R1
-dummy
-; R2
-xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
-";
-        let layout = parse_yab_content(config).expect("Failed to parse config");
+    fn test_grapheme_clusters_are_injected_whole() {
         let mut engine = Engine::default();
-        engine.set_ignore_ime(true);
-        engine.load_layout(layout);
+        // "e" + combining acute, then a single astral emoji.
+        let token = Token::DirectChar("e\u{301}\u{1F600}".to_string());
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .expect("Should return events");
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Grapheme("e\u{301}".to_string()),
+                InputEvent::Unicode('\u{1F600}', false),
+                InputEvent::Unicode('\u{1F600}', true),
+            ]
+        );
 
-        let mut profile = engine.get_profile();
-        profile.char_key_repeat_assigned = true;
-        profile.char_key_repeat_unassigned = false;
+        let mut profile = engine.chord_engine.profile.clone();
+        profile.paste_grapheme_clusters = true;
         engine.set_profile(profile);
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .expect("Should return events");
+        assert_eq!(events[0], InputEvent::Paste("e\u{301}".to_string()));
+    }
 
-        let res_down = engine.process_key(0x20, false, false, false);
-        assert_eq!(res_down, KeyAction::Block);
+    #[test]
+    fn test_long_direct_strings_are_pasted_when_threshold_is_set() {
+        let mut engine = Engine::default();
+        let token = Token::KeySequence(vec![
+            KeyStroke {
+                key: KeySpec::DirectString("よろしくお願いします".to_string()),
+                mods: Modifiers::none(),
+            },
+            KeyStroke {
+                key: KeySpec::DirectString("。".to_string()),
+                mods: Modifiers::none(),
+            },
+        ]);
 
-        let res_repeat = engine.process_key(0x20, false, false, false);
-        match res_repeat {
-            KeyAction::Inject(evs) => {
-                assert_eq!(evs.len(), 2);
-                assert_eq!(evs[0], InputEvent::Scancode(0x1E, false, false));
-                assert_eq!(evs[1], InputEvent::Scancode(0x1E, false, true));
-            }
-            _ => panic!("Expected Inject for repeat, got {:?}", res_repeat),
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .unwrap();
+        assert!(matches!(events[0], InputEvent::DirectString(_)));
+
+        let mut profile = engine.chord_engine.profile.clone();
+        profile.paste_min_chars = Some(5);
+        engine.set_profile(profile);
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Paste("よろしくお願いします".to_string()),
+                InputEvent::DirectString("。".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output_rules_rewrite_tokens_in_order() {
+        let mut engine = Engine::default();
+        let mut profile = engine.chord_engine.profile.clone();
+        profile.output_rules = vec![
+            OutputRule {
+                from: "、".to_string(),
+                to: "，".to_string(),
+            },
+            OutputRule {
+                from: "。".to_string(),
+                to: "．".to_string(),
+            },
+            OutputRule {
+                from: "．".to_string(),
+                to: ".".to_string(),
+            },
+            OutputRule {
+                from: "～".to_string(),
+                to: String::new(),
+            },
+        ];
+        engine.set_profile(profile);
+
+        assert_eq!(
+            engine.apply_output_rules(Token::DirectChar("はい、".to_string())),
+            Token::DirectChar("はい，".to_string())
+        );
+        let ctrl_c = KeyStroke {
+            key: KeySpec::Char('c'),
+            mods: Modifiers {
+                ctrl: true,
+                ..Modifiers::none()
+            },
+        };
+        let token = Token::KeySequence(vec![
+            KeyStroke {
+                key: KeySpec::DirectString("。".to_string()),
+                mods: Modifiers::none(),
+            },
+            KeyStroke {
+                key: KeySpec::Char('～'),
+                mods: Modifiers::none(),
+            },
+            ctrl_c.clone(),
+        ]);
+        assert_eq!(
+            engine.apply_output_rules(token),
+            Token::KeySequence(vec![
+                KeyStroke {
+                    key: KeySpec::DirectString(".".to_string()),
+                    mods: Modifiers::none(),
+                },
+                ctrl_c,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_voicing_key_retypes_the_last_kana() {
+        let mut engine = Engine::default();
+        engine
+            .undo
+            .record_token(&Token::DirectChar("は".to_string()), true);
+        let token = Token::KeySequence(crate::parser::parse_key_sequence_expanded("{半濁点}"));
+
+        let token = engine
+            .post_process_token(Cow::Owned(token), true)
+            .into_owned();
+        assert_eq!(
+            token,
+            Token::KeySequence(vec![
+                KeyStroke {
+                    key: KeySpec::Scancode(0x0E, false),
+                    mods: Modifiers::none(),
+                },
+                KeyStroke {
+                    key: KeySpec::DirectString("ぱ".to_string()),
+                    mods: Modifiers::none(),
+                },
+            ])
+        );
+        engine.undo.record_token(&token, true);
+        assert_eq!(engine.undo.last_kana().map(|last| last.kana), Some('ぱ'));
+    }
+
+    #[test]
+    fn test_dynamic_text_is_expanded_before_output() {
+        let mut engine = Engine::default();
+        let token = Token::KeySequence(vec![KeyStroke {
+            key: KeySpec::Dynamic(DynamicText::Date("令和%%".to_string())),
+            mods: Modifiers::none(),
+        }]);
+
+        let token = engine
+            .post_process_token(Cow::Owned(token), true)
+            .into_owned();
+        assert_eq!(
+            token,
+            Token::KeySequence(vec![KeyStroke {
+                key: KeySpec::DirectString("令和%".to_string()),
+                mods: Modifiers::none(),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_space_width_follows_profile_and_fixed_width_tokens() {
+        let mut engine = Engine::default();
+        let space = Token::KeySequence(vec![KeyStroke {
+            key: KeySpec::Char(' '),
+            mods: Modifiers::none(),
+        }]);
+        let full = Token::KeySequence(vec![KeyStroke {
+            key: KeySpec::Space(SpaceWidth::Full),
+            mods: Modifiers::none(),
+        }]);
+
+        let events = engine
+            .token_to_events_with_ime(&space, false, true)
+            .unwrap();
+        assert_eq!(events[0], InputEvent::Scancode(0x39, false, false));
+        let events = engine
+            .token_to_events_with_ime(&full, false, false)
+            .unwrap();
+        assert_eq!(events[0], InputEvent::Unicode('\u{3000}', false));
+
+        let mut profile = engine.get_profile();
+        profile.space_width = SpaceWidth::Half;
+        engine.set_profile(profile);
+        let events = engine
+            .token_to_events_with_ime(&space, false, true)
+            .unwrap();
+        assert_eq!(events[0], InputEvent::Unicode(' ', false));
+        // Alphanumeric input keeps pressing Space.
+        let events = engine
+            .token_to_events_with_ime(&space, false, false)
+            .unwrap();
+        assert_eq!(events[0], InputEvent::Scancode(0x39, false, false));
+        let events = engine.token_to_events_with_ime(&full, false, true).unwrap();
+        assert_eq!(events[0], InputEvent::Unicode('\u{3000}', false));
+    }
+
+    #[test]
+    fn test_composition_policy_settles_before_sensitive_events() {
+        assert_eq!(
+            first_composition_sensitive_event(&[
+                InputEvent::Scancode(0x1C, false, false),
+                InputEvent::Scancode(0x1C, false, true),
+            ]),
+            None
+        );
+        assert_eq!(
+            first_composition_sensitive_event(&[
+                InputEvent::Scancode(0x1E, false, false),
+                InputEvent::Scancode(0x1E, false, true),
+                InputEvent::Scancode(0x1C, false, false),
+            ]),
+            Some(2)
+        );
+
+        let mut engine = Engine::default();
+        let mut profile = engine.get_profile();
+        profile.ime_composition = CompositionPolicy::FlushFirst;
+        engine.set_profile(profile);
+        let token = Token::KeySequence(vec![
+            KeyStroke {
+                key: KeySpec::Scancode(0x1E, false),
+                mods: Modifiers::none(),
+            },
+            KeyStroke {
+                key: KeySpec::DirectString("abc".to_string()),
+                mods: Modifiers::none(),
+            },
+        ]);
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .unwrap();
+        assert_eq!(
+            events[2..],
+            [
+                InputEvent::SettleComposition(CompositionPolicy::FlushFirst),
+                InputEvent::DirectString("abc".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_focus_change_discards_or_emits_pending_keys() {
+        let config = "
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2
+xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        assert_eq!(
+            engine.process_key(0x20, false, false, false),
+            KeyAction::Block
+        );
+        assert!(engine.on_focus_change(None).is_empty());
+        assert!(engine.chord_engine.state.pending.is_empty());
+        assert_eq!(
+            engine.process_key(0x20, false, true, false),
+            KeyAction::Block
+        );
+
+        let mut profile = engine.get_profile();
+        profile.focus_change = FocusChangePolicy::Emit;
+        engine.set_profile(profile);
+
+        assert_eq!(
+            engine.process_key(0x20, false, false, false),
+            KeyAction::Block
+        );
+        assert_eq!(
+            engine.on_focus_change(None),
+            vec![
+                InputEvent::Scancode(0x1E, false, false),
+                InputEvent::Scancode(0x1E, false, true),
+            ]
+        );
+        assert!(engine.chord_engine.state.pending.is_empty());
+    }
+
+    #[test]
+    fn test_conditional_section_follows_profile_flag() {
+        let config = "
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2
+xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
+[ローマ字シフト無し@拡張親指有効]
+; R0
+dummy
+; R1
+dummy
+; R2
+xx,xx,b,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        let mut profile = engine.get_profile();
+        profile.extended_thumb1.key = crate::chord_engine::ThumbKeySelect::None;
+        profile.extended_thumb2.key = crate::chord_engine::ThumbKeySelect::None;
+        engine.set_profile(profile);
+        engine.load_layout(layout);
+
+        let tap = |engine: &mut Engine| {
+            engine.process_key(0x20, false, false, false);
+            match engine.process_key(0x20, false, true, false) {
+                KeyAction::Inject(evs) => evs[0].clone(),
+                other => panic!("Expected Inject, got {:?}", other),
+            }
+        };
+        assert_eq!(tap(&mut engine), InputEvent::Scancode(0x1E, false, false));
+
+        let mut profile = engine.get_profile();
+        profile.extended_thumb1.key = crate::chord_engine::ThumbKeySelect::Henkan;
+        engine.set_profile(profile);
+        assert_eq!(tap(&mut engine), InputEvent::Scancode(0x30, false, false));
+
+        let mut profile = engine.get_profile();
+        profile.extended_thumb1.key = crate::chord_engine::ThumbKeySelect::None;
+        engine.set_profile(profile);
+        assert_eq!(tap(&mut engine), InputEvent::Scancode(0x1E, false, false));
+    }
+
+    #[test]
+    fn test_repeat_assigned_key_emits_repeat_and_suppresses_release() {
+        let config = "
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2
+xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let mut profile = engine.get_profile();
+        profile.char_key_repeat_assigned = true;
+        profile.char_key_repeat_unassigned = false;
+        engine.set_profile(profile);
+
+        let res_down = engine.process_key(0x20, false, false, false);
+        assert_eq!(res_down, KeyAction::Block);
+
+        let res_repeat = engine.process_key(0x20, false, false, false);
+        match res_repeat {
+            KeyAction::Inject(evs) => {
+                assert_eq!(evs.len(), 2);
+                assert_eq!(evs[0], InputEvent::Scancode(0x1E, false, false));
+                assert_eq!(evs[1], InputEvent::Scancode(0x1E, false, true));
+            }
+            _ => panic!("Expected Inject for repeat, got {:?}", res_repeat),
         }
 
         let res_up = engine.process_key(0x20, false, true, false);
@@ -2646,6 +3890,122 @@ a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
         );
     }
 
+    #[test]
+    fn test_undefined_key_policy_blocks_or_falls_back_to_base_section() {
+        let config = "
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2
+a,s,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+[ローマ字小指シフト]
+; R0
+dummy
+; R1
+dummy
+; R2
+ka,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let set_policy = |engine: &mut Engine, policy| {
+            let mut profile = engine.get_profile();
+            profile.undefined_key = policy;
+            engine.set_profile(profile);
+        };
+
+        // Shift+S is missing from [ローマ字小指シフト].
+        assert_eq!(
+            engine.process_key(0x1F, false, false, true),
+            KeyAction::Pass
+        );
+        assert_eq!(engine.process_key(0x1F, false, true, true), KeyAction::Pass);
+
+        set_policy(&mut engine, UndefinedKeyPolicy::Block);
+        assert_eq!(
+            engine.process_key(0x1F, false, false, true),
+            KeyAction::Block
+        );
+        assert_eq!(
+            engine.process_key(0x1F, false, true, true),
+            KeyAction::Block
+        );
+
+        set_policy(&mut engine, UndefinedKeyPolicy::Fallback);
+        assert_eq!(
+            engine.process_key(0x1F, false, false, true),
+            KeyAction::Block
+        );
+        match engine.process_key(0x1F, false, true, true) {
+            KeyAction::Inject(evs) => assert!(
+                evs.contains(&InputEvent::Scancode(0x1F, false, false)),
+                "Expected s from [ローマ字シフト無し], got {:?}",
+                evs
+            ),
+            other => panic!("Expected Inject, got {:?}", other),
+        }
+        // Keys missing from both sections still pass.
+        assert_eq!(
+            engine.process_key(0x20, false, false, true),
+            KeyAction::Pass
+        );
+    }
+
+    #[test]
+    fn test_fallback_chain_inherits_keys_from_parent_sections() {
+        let config = "
+[継承]
+ローマ字小指シフト,記号
+記号,ローマ字シフト無し
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2
+a,s,d,xx,xx,xx,xx,xx,xx,xx,xx,xx
+[記号]
+; R0
+dummy
+; R1
+dummy
+; R2
+xx,'z',xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+[ローマ字小指シフト]
+; R0
+dummy
+; R1
+dummy
+; R2
+'q',xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let mut shifted_tap = |sc: u16| {
+            assert_eq!(engine.process_key(sc, false, false, true), KeyAction::Block);
+            match engine.process_key(sc, false, true, true) {
+                KeyAction::Inject(evs) => evs[0].clone(),
+                other => panic!("Expected Inject, got {:?}", other),
+            }
+        };
+        assert_eq!(shifted_tap(0x1E), InputEvent::Scancode(0x10, false, false));
+        assert_eq!(shifted_tap(0x1F), InputEvent::Scancode(0x2C, false, false));
+        assert_eq!(shifted_tap(0x20), InputEvent::Scancode(0x20, false, false));
+        // Missing from the whole chain: passes.
+        assert_eq!(
+            engine.process_key(0x21, false, false, true),
+            KeyAction::Pass
+        );
+    }
+
     #[test]
     fn test_space_rollover_flushes_previous_key() {
         // Space is not defined in the layout and not a thumb key.
@@ -2766,23 +4126,406 @@ a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
     }
 
     #[test]
-    fn test_set_profile_preserves_targets() {
+    fn test_fixed_window_types_a_held_key_when_the_window_runs_out() {
         let config = "
 [ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
 ; R2 (A only defined)
 a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
 ";
-        let layout = parse_yab_content(config).expect("Failed to parse config");
         let mut engine = Engine::default();
-        engine.load_layout(layout);
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).unwrap());
+        let mut profile = engine.get_profile();
+        profile.decision_mode = DecisionMode::FixedWindow;
+        profile.chord_window_ms = 150;
+        engine.set_profile(profile);
 
-        // Verify target_keys is set
-        assert!(engine.get_profile().target_keys.is_some());
+        assert_eq!(
+            engine.process_key(0x1E, false, false, false),
+            KeyAction::Block
+        );
+        clock.advance(Duration::from_millis(100));
+        assert!(engine.expire_chord_window().is_empty());
 
-        // Update profile (e.g. changing timeout)
-        let mut new_profile = Profile::default();
-        new_profile.chord_window_ms = 999;
-        // target_keys is None in default()
+        clock.advance(Duration::from_millis(50));
+        let events = engine.expire_chord_window();
+        assert!(events.contains(&InputEvent::Scancode(0x1E, false, false)));
+        assert_eq!(engine.chord_deadline(), None);
+        assert_eq!(
+            engine.process_key(0x1E, false, true, false),
+            KeyAction::Block
+        );
+    }
+
+    #[test]
+    fn test_key_held_alone_types_its_long_press_token() {
+        let config = "
+[ローマ字シフト無し]
+dummy
+dummy
+a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+
+<long>
+無,無,無,無,無,無,無,無,無,無,無,無,無
+無,無,無,無,無,無,無,無,無,無,無,無
+i,無,無,無,無,無,無,無,無,無,無,無
+";
+        let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).unwrap());
+        let mut profile = engine.get_profile();
+        profile.long_press_ms = 400;
+        engine.set_profile(profile);
+
+        // Released before the threshold: a tap.
+        engine.process_key(0x1E, false, false, false);
+        clock.advance(Duration::from_millis(100));
+        let tap = engine.process_key(0x1E, false, true, false);
+        assert!(matches!(tap, KeyAction::Inject(ref events)
+            if events.contains(&InputEvent::Scancode(0x1E, false, false))));
+
+        // Held past it: the `<long>` token, and nothing on release.
+        engine.process_key(0x1E, false, false, false);
+        clock.advance(Duration::from_millis(399));
+        assert!(engine.expire_chord_window().is_empty());
+        clock.advance(Duration::from_millis(1));
+        let events = engine.expire_chord_window();
+        assert!(events.contains(&InputEvent::Scancode(0x17, false, false)));
+        assert!(!events.contains(&InputEvent::Scancode(0x1E, false, false)));
+        assert_eq!(engine.chord_deadline(), None);
+        assert_eq!(
+            engine.process_key(0x1E, false, false, false),
+            KeyAction::Block
+        );
+        assert_eq!(
+            engine.process_key(0x1E, false, true, false),
+            KeyAction::Block
+        );
+    }
+
+    #[test]
+    fn test_long_press_without_token_in_active_section_types_the_tap() {
+        let config = "
+[ローマ字シフト無し]
+dummy
+dummy
+a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+
+<long>
+無,無,無,無,無,無,無,無,無,無,無,無,無
+無,無,無,無,無,無,無,無,無,無,無,無
+i,無,無,無,無,無,無,無,無,無,無,無
+
+[英数シフト無し]
+dummy
+dummy
+a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.set_ime_mode(ImeMode::ForceAlpha);
+        engine.load_layout(parse_yab_content(config).unwrap());
+        let mut profile = engine.get_profile();
+        profile.long_press_ms = 400;
+        engine.set_profile(profile);
+
+        // IME off: held past the threshold, A still types A.
+        engine.process_key(0x1E, false, false, false);
+        clock.advance(Duration::from_millis(400));
+        let events = engine.expire_chord_window();
+        assert!(events.contains(&InputEvent::Scancode(0x1E, false, false)));
+        assert!(!events.contains(&InputEvent::Scancode(0x17, false, false)));
+        assert_eq!(
+            engine.process_key(0x1E, false, true, false),
+            KeyAction::Block
+        );
+    }
+
+    #[test]
+    fn test_reload_layout_keeps_keys_in_flight_when_only_cells_change() {
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(
+            parse_yab_content(
+                "
+[ローマ字シフト無し]
+a,b
+",
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(
+            engine.process_key(0x02, false, false, false),
+            KeyAction::Block
+        );
+        let edited = parse_yab_content(
+            "
+[ローマ字シフト無し]
+c,b
+",
+        )
+        .unwrap();
+        assert_eq!(engine.reload_layout(edited), LayoutReload::HotSwapped);
+
+        // The key pressed before the swap types the edited cell.
+        let KeyAction::Inject(events) = engine.process_key(0x02, false, true, false) else {
+            panic!("expected the pending key to be typed");
+        };
+        assert!(events.contains(&InputEvent::Scancode(0x2E, false, false)));
+
+        // A newly defined key changes which keys are hooked.
+        let extended = parse_yab_content(
+            "
+[ローマ字シフト無し]
+c,b,x
+",
+        )
+        .unwrap();
+        assert_eq!(engine.reload_layout(extended), LayoutReload::Replaced);
+    }
+
+    #[test]
+    fn test_pass_through_keys_ignore_layout_definitions() {
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(
+            parse_yab_content(
+                "
+[ローマ字シフト無し]
+a,b
+",
+            )
+            .unwrap(),
+        );
+        let mut profile = engine.get_profile();
+        profile.pass_through_keys = vec!["1".to_string(), "F5".to_string()];
+        engine.set_profile(profile);
+
+        assert_eq!(
+            engine.process_key(0x02, false, false, false),
+            KeyAction::Pass
+        );
+        assert_eq!(
+            engine.process_key(0x02, false, true, false),
+            KeyAction::Pass
+        );
+        assert_eq!(
+            engine.process_key(0x03, false, false, false),
+            KeyAction::Block
+        );
+    }
+
+    #[test]
+    fn test_virtual_tenkey_works_without_a_layout_and_from_a_token() {
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        let j = 0x24;
+        assert_eq!(engine.process_key(j, false, false, false), KeyAction::Pass);
+        engine.process_key(j, false, true, false);
+
+        engine.set_tenkey(true);
+        assert_eq!(engine.process_key(j, false, false, false), KeyAction::Block);
+        let KeyAction::Inject(events) = engine.process_key(j, false, true, false) else {
+            panic!("expected keypad 1");
+        };
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, InputEvent::VirtualKey(0x61, _, _, false))));
+        engine.set_tenkey(false);
+        assert_eq!(engine.process_key(j, false, false, false), KeyAction::Pass);
+        engine.process_key(j, false, true, false);
+
+        engine.load_layout(
+            parse_yab_content(
+                "
+[ローマ字シフト無し]
+{テンキー}
+",
+            )
+            .unwrap(),
+        );
+        engine.process_key(0x02, false, false, false);
+        engine.process_key(0x02, false, true, false);
+        assert!(engine.is_tenkey_on());
+    }
+
+    #[test]
+    fn test_candidate_section_applies_while_the_candidate_window_is_open() {
+        let config = "
+[ローマ字シフト無し]
+xx
+xx
+xx,xx,a
+
+[候補選択]
+xx
+xx
+xx,xx,'1'
+";
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).unwrap());
+        let tap = |engine: &mut Engine| {
+            engine.process_key(0x20, false, false, false);
+            match engine.process_key(0x20, false, true, false) {
+                KeyAction::Inject(events) => events,
+                action => panic!("expected output, got {action:?}"),
+            }
+        };
+
+        assert!(tap(&mut engine).contains(&InputEvent::Scancode(0x1E, false, false)));
+        engine.set_candidate_window_open(true);
+        assert!(tap(&mut engine).contains(&InputEvent::Scancode(0x02, false, false)));
+        engine.set_candidate_window_open(false);
+        assert!(tap(&mut engine).contains(&InputEvent::Scancode(0x1E, false, false)));
+    }
+
+    #[test]
+    fn test_dual_role_key_types_when_tapped_or_rolled_and_holds_ctrl_around_a_key() {
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(
+            parse_yab_content(
+                "
+[ローマ字シフト無し]
+a,b
+",
+            )
+            .unwrap(),
+        );
+        let mut profile = engine.get_profile();
+        profile.dual_role_keys = vec![crate::chord_engine::DualRoleKeyCfg {
+            key: "1".to_string(),
+            ..Default::default()
+        }];
+        engine.set_profile(profile);
+
+        assert_eq!(
+            engine.process_key(0x02, false, false, false),
+            KeyAction::Block
+        );
+        let KeyAction::Inject(events) = engine.process_key(0x02, false, true, false) else {
+            panic!("expected the tap to be typed");
+        };
+        assert!(events.contains(&InputEvent::Scancode(0x1E, false, false)));
+
+        // Rolled over by another key, both are typed.
+        engine.process_key(0x02, false, false, false);
+        assert_eq!(
+            engine.process_key(0x03, false, false, false),
+            KeyAction::Block
+        );
+        let KeyAction::Inject(events) = engine.process_key(0x02, false, true, false) else {
+            panic!("expected the roll to be typed");
+        };
+        assert!(events.contains(&InputEvent::Scancode(0x1E, false, false)));
+        let KeyAction::Inject(events) = engine.process_key(0x03, false, true, false) else {
+            panic!("expected the rolled key to be typed");
+        };
+        assert!(events.contains(&InputEvent::Scancode(0x30, false, false)));
+
+        // Another key pressed and released inside it is Ctrl+that key,
+        // unconverted.
+        engine.process_key(0x02, false, false, false);
+        engine.process_key(0x03, false, false, false);
+        assert_eq!(
+            engine.process_key(0x03, false, true, false),
+            KeyAction::Inject(vec![
+                InputEvent::Scancode(0x1D, false, false),
+                InputEvent::Scancode(0x03, false, false),
+                InputEvent::Scancode(0x03, false, true),
+            ])
+        );
+        assert_eq!(
+            engine.process_key(0x02, false, true, false),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x1D, false, true)])
+        );
+    }
+
+    #[test]
+    fn test_deferred_enter_times_out_while_key_held() {
+        let config = "
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2 (A only defined)
+a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).unwrap());
+        let mut profile = engine.get_profile();
+        profile.deferred_enter_timeout_ms = 300;
+        engine.set_profile(profile);
+
+        engine.process_key(0x1E, false, false, false);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(
+            engine.process_key(0x1C, false, false, false),
+            KeyAction::Block
+        );
+        clock.advance(Duration::from_millis(200));
+        assert!(engine.expire_deferred_enter().is_empty());
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(
+            engine.expire_deferred_enter(),
+            vec![InputEvent::Scancode(0x1C, false, false)]
+        );
+        assert_eq!(engine.deferred_enter_deadline(), None);
+        assert_eq!(
+            engine.process_key(0x1C, false, true, false),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x1C, false, true)])
+        );
+
+        // Expiry is also checked when the next key arrives.
+        engine.process_key(0x1C, false, false, false);
+        engine.process_key(0x1C, false, true, false);
+        clock.advance(Duration::from_millis(400));
+        match engine.process_key(0x1E, false, true, false) {
+            KeyAction::Inject(events) => assert_eq!(
+                &events[..2],
+                &[
+                    InputEvent::Scancode(0x1C, false, false),
+                    InputEvent::Scancode(0x1C, false, true),
+                ]
+            ),
+            other => panic!("Expected Inject, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_profile_preserves_targets() {
+        let config = "
+[ローマ字シフト無し]
+; R2 (A only defined)
+a,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::default();
+        engine.load_layout(layout);
+
+        // Verify target_keys is set
+        assert!(engine.get_profile().target_keys.is_some());
+
+        // Update profile (e.g. changing timeout)
+        let mut new_profile = Profile::default();
+        new_profile.chord_window_ms = 999;
+        // target_keys is None in default()
 
         engine.set_profile(new_profile);
 
@@ -2963,6 +4706,9 @@ thumb_a
             right: HashSet::new(),
             ext1: HashSet::new(),
             ext2: HashSet::new(),
+            extra: Vec::new(),
+            number: HashSet::new(),
+            shift: HashSet::new(),
         });
 
         // Set profile BEFORE loading layout (although load_layout merges triggers, thumb keys are separate)
@@ -3718,6 +5464,8 @@ xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
         let layout = parse_yab_content(config).expect("Failed to parse config");
 
         let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
         engine.set_ignore_ime(true);
         engine.load_layout(layout);
 
@@ -3731,10 +5479,12 @@ xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
             engine.process_key(0x1E, false, false, false),
             KeyAction::Block
         );
+        clock.advance(Duration::from_millis(10));
         assert_eq!(
             engine.process_key(0x18, false, false, false),
             KeyAction::Block
         );
+        clock.advance(Duration::from_millis(20));
         let res = engine.process_key(0x1E, false, true, false);
         match res {
             KeyAction::Inject(evs) => {
@@ -3752,12 +5502,12 @@ xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
             engine.process_key(0x23, false, false, false),
             KeyAction::Block
         );
-        std::thread::sleep(Duration::from_millis(5));
+        clock.advance(Duration::from_millis(5));
         assert_eq!(
             engine.process_key(0x18, false, true, false),
             KeyAction::Block
         );
-        std::thread::sleep(Duration::from_millis(40));
+        clock.advance(Duration::from_millis(40));
         let res = engine.process_key(0x23, false, true, false);
         match res {
             KeyAction::Inject(evs) => {
@@ -3811,6 +5561,111 @@ xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
         }
     }
 
+    #[test]
+    fn test_profile_key_swaps_replace_layout_swaps() {
+        let config = "
+[機能キー]
+左Ctrl, 右Ctrl
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+        let mut profile = engine.get_profile();
+        profile.key_swaps = vec![
+            crate::chord_engine::KeySwapCfg {
+                from: "左Ctrl".to_string(),
+                to: "左Alt".to_string(),
+            },
+            crate::chord_engine::KeySwapCfg {
+                from: "CapsLock".to_string(),
+                to: "左Ctrl".to_string(),
+            },
+        ];
+        engine.set_profile(profile);
+
+        assert_eq!(
+            engine.process_key(0x1D, false, false, false),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x38, false, false)])
+        );
+        // Chains on through the swap of 左Ctrl.
+        assert_eq!(
+            engine.process_key(0x3A, false, false, false),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x38, false, false)])
+        );
+    }
+
+    #[test]
+    fn test_shift_swap_applies_with_shift_and_releases_the_pressed_target() {
+        let config = "
+[機能キー]
+CapsLock, 左Ctrl
+Shift+CapsLock, 半角/全角
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        assert_eq!(
+            engine.process_key(0x3A, false, false, false),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x1D, false, false)])
+        );
+        assert_eq!(
+            engine.process_key(0x3A, false, true, false),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x1D, false, true)])
+        );
+        assert_eq!(
+            engine.process_key(0x3A, false, false, true),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x29, false, false)])
+        );
+        // Shift let go first: the release still matches the press.
+        assert_eq!(
+            engine.process_key(0x3A, false, true, false),
+            KeyAction::Inject(vec![InputEvent::Scancode(0x29, false, true)])
+        );
+    }
+
+    #[test]
+    fn test_key_remap_section_swaps_key_positions() {
+        let config = "
+[ローマ字シフト無し]
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,b,c,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+
+[配置換え]
+:<->;
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        assert_eq!(
+            layout.key_remaps,
+            vec![
+                (":".to_string(), ";".to_string()),
+                (";".to_string(), ":".to_string()),
+            ]
+        );
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let mut tap = |sc: u16| {
+            let mut events = Vec::new();
+            for up in [false, true] {
+                if let KeyAction::Inject(evs) = engine.process_key(sc, false, up, false) {
+                    events.extend(evs);
+                }
+            }
+            events
+        };
+        assert!(tap(0x27).contains(&InputEvent::Scancode(0x2E, false, false)));
+        assert!(tap(0x28).contains(&InputEvent::Scancode(0x30, false, false)));
+    }
+
     #[test]
     fn test_needs_alt_handling_for_function_key_swap_source() {
         let config = "
@@ -4136,18 +5991,79 @@ xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
     }
 
     #[test]
-    fn test_suspend_key_persists_when_disabled() {
+    fn test_extended_thumb_keys_held_together_select_combined_section() {
+        let config = "
+[拡張親指シフト1]
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+x,w,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+
+[拡張親指シフト2]
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+y,w,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+
+[拡張親指シフト1+2]
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+z,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
         let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+
         let mut profile = engine.get_profile();
-        profile.suspend_key = crate::chord_engine::SuspendKey::Pause;
+        profile.thumb_left.key = crate::chord_engine::ThumbKeySelect::None;
+        profile.thumb_right.key = crate::chord_engine::ThumbKeySelect::None;
+        profile.extended_thumb1.key = crate::chord_engine::ThumbKeySelect::Muhenkan;
+        profile.extended_thumb2.key = crate::chord_engine::ThumbKeySelect::Henkan;
         engine.set_profile(profile);
+        engine.load_layout(layout);
 
-        engine.set_enabled(false);
+        let mut events = Vec::new();
+        for (sc, up) in [
+            (0x7B, false),
+            (0x79, false),
+            (0x1E, false),
+            (0x1E, true),
+            (0x79, true),
+            (0x7B, true),
+            (0x7B, false),
+            (0x79, false),
+            (0x1F, false),
+            (0x1F, true),
+        ] {
+            if let KeyAction::Inject(evs) = engine.process_key(sc, false, up, false) {
+                events.extend(evs);
+            }
+        }
         assert_eq!(
-            engine.get_profile().suspend_key,
-            crate::chord_engine::SuspendKey::Pause
+            events,
+            vec![
+                InputEvent::Scancode(0x2C, false, false),
+                InputEvent::Scancode(0x2C, false, true),
+                InputEvent::Scancode(0x11, false, false),
+                InputEvent::Scancode(0x11, false, true),
+            ],
+            "Expected 'z' from [拡張親指シフト1+2], then 'w' from the single plane"
         );
     }
+
+    #[test]
+    fn test_suspend_key_persists_when_disabled() {
+        let mut engine = Engine::default();
+        let mut profile = engine.get_profile();
+        let pause = crate::chord_engine::SuspendKey::new(ScKey::new(0x45, false));
+        profile.suspend_key = pause;
+        engine.set_profile(profile);
+
+        engine.set_enabled(false);
+        assert_eq!(engine.get_profile().suspend_key, pause);
+    }
     #[test]
     fn test_3key_chord_resolution() {
         // Define a layout where <q><w> defines 'a' (0x1E)
@@ -4166,6 +6082,8 @@ xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
         let layout = parse_yab_content(config).expect("Failed to parse config");
 
         let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
         engine.set_ignore_ime(true);
         engine.load_layout(layout);
 
@@ -4177,22 +6095,22 @@ xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
         // T=0: Q down
         engine.process_key(0x10, false, false, false);
         // T=10: W down
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         engine.process_key(0x11, false, false, false);
         // T=20: E down
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         engine.process_key(0x12, false, false, false);
 
         // T=100: Q Up
-        std::thread::sleep(Duration::from_millis(80));
+        clock.advance(Duration::from_millis(80));
         let res1 = engine.process_key(0x10, false, true, false);
 
         // T=110: W Up
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         let res2 = engine.process_key(0x11, false, true, false);
 
         // T=120: E Up
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         let res3 = engine.process_key(0x12, false, true, false);
 
         // Aggregated events from all releases
@@ -4220,6 +6138,54 @@ xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
         );
     }
 
+    #[test]
+    fn test_timing_section_overrides_pair_overlap_ratio() {
+        let planes = "
+[ローマ字シフト無し]
+xx
+xx
+a,s,d,f,g,h,j,k,l
+
+<j>
+xx
+xx
+xx,xx,xx,xx,xx,xx,xx,z,x
+";
+        // J down, K down 50ms later, J up, K up: K overlaps J by half.
+        let roll = |config: &str, second: u16| {
+            let mut engine = Engine::default();
+            let clock = ManualClock::new();
+            engine.set_clock(Arc::new(clock.clone()));
+            engine.set_ignore_ime(true);
+            engine.load_layout(parse_yab_content(config).expect("Failed to parse config"));
+            let mut out = Vec::new();
+            let steps = [
+                (0x24, false, 0),
+                (second, false, 50),
+                (0x24, true, 50),
+                (second, true, 50),
+            ];
+            for (sc, up, after_ms) in steps {
+                clock.advance(Duration::from_millis(after_ms));
+                if let KeyAction::Inject(evs) = engine.process_key(sc, false, up, false) {
+                    out.extend(evs);
+                }
+            }
+            out.iter()
+                .filter_map(|e| match e {
+                    InputEvent::Scancode(sc, _, false) => Some(*sc),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(roll(planes, 0x25), vec![0x2C]);
+        let timed = format!("[タイミング]\nj,k,90\n{}", planes);
+        assert_eq!(roll(&timed, 0x25), vec![0x24, 0x25]);
+        // Other pairs with J keep the global ratio.
+        assert_eq!(roll(&timed, 0x26), vec![0x2D]);
+    }
+
     #[test]
     fn test_mixed_2key_and_3key_definitions() {
         // q = 0x10, w = 0x11, e = 0x12
@@ -4254,6 +6220,8 @@ xx,xx,3,xx,xx,xx,xx,xx,xx,xx,xx,xx
         let layout = parse_yab_content(config).expect("Failed to parse config");
 
         let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
         engine.set_ignore_ime(true); // Force consistent behavior if possible, but sections cover both.
         engine.load_layout(layout);
 
@@ -4264,15 +6232,15 @@ xx,xx,3,xx,xx,xx,xx,xx,xx,xx,xx,xx
         // Case 1: 3-key chord (q + w + e) -> '3' (0x04)
         // q down
         engine.process_key(0x10, false, false, false);
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         // w down
         engine.process_key(0x11, false, false, false);
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         // e down
         engine.process_key(0x12, false, false, false);
 
         // Release
-        std::thread::sleep(Duration::from_millis(100)); // wait for overlap
+        clock.advance(Duration::from_millis(100)); // wait for overlap
         let r1 = engine.process_key(0x10, false, true, false);
         let r2 = engine.process_key(0x11, false, true, false);
         let r3 = engine.process_key(0x12, false, true, false);
@@ -4302,15 +6270,15 @@ xx,xx,3,xx,xx,xx,xx,xx,xx,xx,xx,xx
         );
 
         // Case 2: 2-key chord (q + w) -> '2' (0x03)
-        std::thread::sleep(Duration::from_millis(500));
+        clock.advance(Duration::from_millis(500));
 
         // q down
         engine.process_key(0x10, false, false, false);
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         // w down
         engine.process_key(0x11, false, false, false);
 
-        std::thread::sleep(Duration::from_millis(100));
+        clock.advance(Duration::from_millis(100));
         let r1 = engine.process_key(0x10, false, true, false);
         let r2 = engine.process_key(0x11, false, true, false);
 
@@ -4432,6 +6400,8 @@ xx,xx,xx,xx,xx,4,xx,xx,xx,xx,xx
         let layout = parse_yab_content(config).expect("Failed to parse config");
 
         let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
         engine.set_ignore_ime(true);
         engine.load_layout(layout);
 
@@ -4442,11 +6412,11 @@ xx,xx,xx,xx,xx,4,xx,xx,xx,xx,xx
 
         // ==== First chord: D+F+N → should output '4' ====
         engine.process_key(0x20, false, false, false); // D down
-        std::thread::sleep(Duration::from_millis(5));
+        clock.advance(Duration::from_millis(5));
         engine.process_key(0x21, false, false, false); // F down
-        std::thread::sleep(Duration::from_millis(15));
+        clock.advance(Duration::from_millis(15));
         engine.process_key(0x31, false, false, false); // N down
-        std::thread::sleep(Duration::from_millis(30));
+        clock.advance(Duration::from_millis(30));
         let r1 = engine.process_key(0x31, false, true, false); // N up
 
         let events1 = match r1 {
@@ -4462,9 +6432,9 @@ xx,xx,xx,xx,xx,4,xx,xx,xx,xx,xx
         );
 
         // ==== Second chord: D+F still held, N tapped again → should also output '4' ====
-        std::thread::sleep(Duration::from_millis(10));
+        clock.advance(Duration::from_millis(10));
         engine.process_key(0x31, false, false, false); // N down
-        std::thread::sleep(Duration::from_millis(30));
+        clock.advance(Duration::from_millis(30));
         let r2 = engine.process_key(0x31, false, true, false); // N up
 
         let events2 = match r2 {
@@ -4544,4 +6514,1068 @@ xx
             other => panic!("Expected Inject for direct string + key, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_undo_chord_erases_last_output() {
+        let config = "
+[ローマ字シフト無し]
+xx
+kyo,xx
+xx,xx,xx,xx,xx,xx,ji,ka
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+        let mut profile = engine.get_profile();
+        profile.undo = crate::chord_engine::UndoCfg {
+            enabled: true,
+            chord: vec![ScKey::new(0x24, false), ScKey::new(0x25, false)],
+        };
+        engine.set_profile(profile);
+
+        // Q -> "kyo" (one kana)
+        assert_eq!(
+            engine.process_key(0x10, false, false, false),
+            KeyAction::Block
+        );
+        assert!(matches!(
+            engine.process_key(0x10, false, true, false),
+            KeyAction::Inject(_)
+        ));
+
+        // J + K chord -> one Backspace instead of the J/K outputs
+        assert_eq!(
+            engine.process_key(0x24, false, false, false),
+            KeyAction::Block
+        );
+        assert_eq!(
+            engine.process_key(0x25, false, false, false),
+            KeyAction::Block
+        );
+        let res = engine.process_key(0x25, false, true, false);
+        assert_eq!(
+            res,
+            KeyAction::Inject(vec![
+                InputEvent::Scancode(0x0E, false, false),
+                InputEvent::Scancode(0x0E, false, true),
+            ])
+        );
+        assert_eq!(
+            engine.process_key(0x24, false, true, false),
+            KeyAction::Block
+        );
+
+        // A second undo has nothing left to erase.
+        engine.process_key(0x24, false, false, false);
+        engine.process_key(0x25, false, false, false);
+        assert_eq!(
+            engine.process_key(0x25, false, true, false),
+            KeyAction::Block
+        );
+    }
+
+    #[test]
+    fn test_training_annotates_traced_output() {
+        let config = "
+[ローマ字シフト無し]
+xx
+きょ,xx
+xx,xx,xx,xx,xx,xx,ji,ka
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::publishing();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let _serial = handle::SUBSCRIBER_TESTS.lock();
+        let rx = handle::EngineHandle::global().subscribe();
+        engine.start_training("きょか");
+
+        for sc in [0x10, 0x24, 0x25] {
+            engine.process_key(sc, false, false, false);
+            engine.process_key(sc, false, true, false);
+        }
+
+        let traced: Vec<TraceEvent> = rx
+            .try_iter()
+            .filter_map(|event| match event {
+                EngineEvent::Trace(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+        let matched: Vec<Option<bool>> = traced.iter().map(|e| e.matched).collect();
+        assert_eq!(matched, vec![Some(true), Some(false), Some(true)]);
+        assert_eq!(traced[1].expected.as_deref(), Some("ka"));
+
+        let report = engine.stop_training().expect("training was running");
+        assert!(report.finished);
+        assert_eq!((report.correct, report.errors), (3, 1));
+        assert!(engine.training_report().is_none());
+    }
+
+    #[test]
+    fn test_chord_test_reports_outputs_without_typing_them() {
+        let config = "
+[ローマ字シフト無し]
+xx
+xx
+xx,xx,か,な,xx,xx,xx,xx,xx,xx,xx,xx
+
+<k>
+無
+無
+xx,xx,が,無
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::publishing();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let _serial = handle::SUBSCRIBER_TESTS.lock();
+        let rx = handle::EngineHandle::global().subscribe();
+        engine.begin_chord_test();
+
+        let raw = |sc, up| RawKeyEvent {
+            sc,
+            ext: false,
+            up,
+            shift: ShiftSide::None,
+            modifier: None,
+        };
+        let actions = engine.process_events(&[
+            raw(0x25, false),
+            raw(0x20, false),
+            raw(0x20, true),
+            raw(0x25, true),
+            raw(0x21, false),
+            raw(0x21, true),
+        ]);
+        for action in actions {
+            if let KeyAction::Inject(events) = action {
+                assert!(events
+                    .iter()
+                    .all(|e| matches!(e, InputEvent::Scancode(_, _, true))));
+            }
+        }
+
+        let tested: Vec<_> = rx
+            .try_iter()
+            .filter_map(|event| match event {
+                EngineEvent::ChordTest(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+        let outputs: Vec<Option<&str>> = tested.iter().map(|e| e.output.as_deref()).collect();
+        assert_eq!(outputs, vec![Some("ga"), Some("na")]);
+        assert_eq!(tested[0].section.as_deref(), Some("ローマ字シフト無し"));
+        assert_eq!(tested[0].press_offsets_ms.len(), 2);
+
+        engine.end_chord_test();
+        assert!(!engine.is_chord_testing());
+        assert!(rx
+            .try_iter()
+            .any(|event| event == EngineEvent::ChordTestEnded));
+
+        // A test left alone ends by itself, and so does one the user left.
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.begin_chord_test();
+        clock.advance(Duration::from_secs(59));
+        engine.on_tick();
+        assert!(engine.is_chord_testing());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(engine.next_deadline(), Some(clock.now()));
+        engine.on_tick();
+        assert!(!engine.is_chord_testing());
+        engine.begin_chord_test();
+        engine.on_focus_change(None);
+        assert!(!engine.is_chord_testing());
+        engine.process_key(0x21, false, false, false);
+        match engine.process_key(0x21, false, true, false) {
+            KeyAction::Inject(events) => {
+                assert!(events.contains(&InputEvent::Scancode(0x31, false, false)))
+            }
+            other => panic!("expected typed output, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_three_key_rolls_are_output_in_press_order() {
+        let layout = parse_yab_content("[ローマ字シフト無し]\nxx\nxx\na,b,c\n")
+            .expect("Failed to parse config");
+        let keys = [0x1E, 0x1F, 0x20];
+        let releases = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+
+        for (press_gap, release_gap) in [(5, 5), (20, 5), (60, 20), (5, 60)] {
+            for release in releases {
+                let mut engine = Engine::default();
+                let clock = ManualClock::new();
+                engine.set_clock(Arc::new(clock.clone()));
+                engine.set_ignore_ime(true);
+                engine.load_layout(layout.clone());
+
+                let steps = keys
+                    .iter()
+                    .map(|&sc| (sc, false, press_gap))
+                    .chain(release.iter().map(|&i| (keys[i], true, release_gap)));
+                let mut out = Vec::new();
+                for (sc, up, after_ms) in steps {
+                    if let KeyAction::Inject(evs) = engine.process_key(sc, false, up, false) {
+                        out.extend(evs.into_iter().filter_map(|e| match e {
+                            InputEvent::Scancode(sc, _, false) => Some(sc),
+                            _ => None,
+                        }));
+                    }
+                    clock.advance(Duration::from_millis(after_ms));
+                }
+
+                assert_eq!(
+                    out,
+                    vec![0x1E, 0x30, 0x2E],
+                    "gaps {}/{} ms, release order {:?}",
+                    press_gap,
+                    release_gap,
+                    release
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sands_space_shifts_when_held_and_types_space_when_tapped() {
+        let config = r#"
+[ローマ字シフト無し]
+xx
+xx
+xx,xx,a
+
+[ローマ字右親指シフト]
+xx
+xx
+xx,xx,r
+"#;
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+        let mut profile = engine.get_profile();
+        profile.sands = crate::chord_engine::SandsCfg {
+            enabled: true,
+            tap_timeout_ms: 30,
+            ..Default::default()
+        };
+        engine.set_profile(profile);
+
+        let sc_space = 0x39;
+        let sc_d = 0x20;
+        let space_tap = KeyAction::Inject(vec![
+            InputEvent::Scancode(sc_space, false, false),
+            InputEvent::Scancode(sc_space, false, true),
+        ]);
+
+        // Quick tap -> Space
+        assert_eq!(
+            engine.process_key(sc_space, false, false, false),
+            KeyAction::Block
+        );
+        assert_eq!(engine.process_key(sc_space, false, true, false), space_tap);
+
+        // Space + D -> right thumb plane, no Space on release
+        engine.process_key(sc_space, false, false, false);
+        clock.advance(Duration::from_millis(5));
+        engine.process_key(sc_d, false, false, false);
+        clock.advance(Duration::from_millis(10));
+        let res = engine.process_key(sc_d, false, true, false);
+        match res {
+            KeyAction::Inject(evs) => assert!(
+                evs.iter()
+                    .any(|e| matches!(e, InputEvent::Scancode(0x13, _, _))),
+                "Expected r from the right thumb plane, got {:?}",
+                evs
+            ),
+            _ => panic!("Expected Inject for Space+D, got {:?}", res),
+        }
+        assert_eq!(
+            engine.process_key(sc_space, false, true, false),
+            KeyAction::Block
+        );
+
+        // Held alone past the timeout -> nothing, and repeats are swallowed
+        engine.process_key(sc_space, false, false, false);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(
+            engine.process_key(sc_space, false, false, false),
+            KeyAction::Block
+        );
+        assert_eq!(
+            engine.process_key(sc_space, false, true, false),
+            KeyAction::Block
+        );
+
+        // With repeat enabled, a long lone hold repeats Space and the release stays silent
+        let mut profile = engine.get_profile();
+        profile.sands.repeat = true;
+        engine.set_profile(profile);
+        engine.process_key(sc_space, false, false, false);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(engine.process_key(sc_space, false, false, false), space_tap);
+        assert_eq!(
+            engine.process_key(sc_space, false, true, false),
+            KeyAction::Block
+        );
+    }
+
+    #[test]
+    fn test_app_sections_override_cells_for_the_foreground_app() {
+        let config = r#"
+[アプリ別]
+WindowsTerminal.exe, 端末
+
+[ローマ字シフト無し]
+a,b
+
+[ローマ字シフト無し:端末]
+c
+"#;
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).expect("Failed to parse config"));
+        let tap = |engine: &mut Engine, sc: u16| {
+            engine.process_key(sc, false, false, false);
+            engine.process_key(sc, false, true, false)
+        };
+        let typed = |sc: u16| {
+            KeyAction::Inject(vec![
+                InputEvent::Scancode(sc, false, false),
+                InputEvent::Scancode(sc, false, true),
+            ])
+        };
+
+        assert_eq!(tap(&mut engine, 0x02), typed(0x1E));
+        engine.set_foreground_app(Some("windowsterminal.exe".to_string()));
+        assert_eq!(tap(&mut engine, 0x02), typed(0x2E));
+        // Cells the app section leaves out come from the usual section.
+        assert_eq!(tap(&mut engine, 0x03), typed(0x30));
+        engine.set_foreground_app(Some("notepad.exe".to_string()));
+        assert_eq!(tap(&mut engine, 0x02), typed(0x1E));
+    }
+
+    #[test]
+    fn test_thumb_repeat_repeats_single_press_when_held_alone() {
+        let config = r#"
+[ローマ字シフト無し]
+xx
+xx
+xx,xx,a
+
+[ローマ字左親指シフト]
+xx
+xx
+xx,xx,l
+"#;
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+        let mut profile = engine.get_profile();
+        profile.thumb_left.single_press = ThumbShiftSinglePress::SpaceKey;
+        profile.thumb_left.repeat = true;
+        profile.thumb_repeat_delay_ms = 30;
+        engine.set_profile(profile);
+
+        let sc_muhenkan = 0x7B;
+        let sc_d = 0x20;
+        let space_tap = KeyAction::Inject(vec![
+            InputEvent::Scancode(0x39, false, false),
+            InputEvent::Scancode(0x39, false, true),
+        ]);
+
+        // OS repeats before the delay are swallowed
+        engine.process_key(sc_muhenkan, false, false, false);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(
+            engine.process_key(sc_muhenkan, false, false, false),
+            KeyAction::Block
+        );
+
+        // Past the delay the single-press action repeats
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(
+            engine.process_key(sc_muhenkan, false, false, false),
+            space_tap
+        );
+        assert_eq!(
+            engine.process_key(sc_muhenkan, false, false, false),
+            space_tap
+        );
+
+        // Still a shift for keys pressed while held
+        engine.process_key(sc_d, false, false, false);
+        clock.advance(Duration::from_millis(10));
+        match engine.process_key(sc_d, false, true, false) {
+            KeyAction::Inject(evs) => assert!(
+                evs.iter()
+                    .any(|e| matches!(e, InputEvent::Scancode(0x26, _, _))),
+                "Expected l from the left thumb plane, got {:?}",
+                evs
+            ),
+            res => panic!("Expected Inject for 無変換+D, got {:?}", res),
+        }
+
+        // The release after repeating emits no extra tap
+        assert_eq!(
+            engine.process_key(sc_muhenkan, false, true, false),
+            KeyAction::Block
+        );
+    }
+
+    #[test]
+    fn test_extra_thumb_modifiers_select_their_own_sections() {
+        let config = r#"
+[ローマ字シフト無し]
+xx
+xx
+xx,xx,a
+
+[ローマ字中親指シフト]
+xx
+xx
+xx,xx,b
+
+[ローマ字小指中親指シフト]
+xx
+xx
+xx,xx,c
+
+[ローマ字左親指シフト2]
+xx
+xx
+xx,xx,e
+"#;
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        let mut profile = engine.get_profile();
+        profile.extra_thumbs = vec![
+            crate::chord_engine::ExtraThumbCfg {
+                suffix: "中親指シフト".to_string(),
+                keys: vec![ScKey::new(0x0F, false)],
+                ..Default::default()
+            },
+            crate::chord_engine::ExtraThumbCfg {
+                suffix: "左親指シフト2".to_string(),
+                keys: vec![ScKey::new(0x3A, false)],
+                ..Default::default()
+            },
+        ];
+        engine.set_profile(profile);
+        engine.load_layout(layout);
+
+        let sc_d = 0x20;
+        let press_with = |engine: &mut Engine, thumb: u16, shift: bool| {
+            engine.process_key(thumb, false, false, shift);
+            engine.process_key(sc_d, false, false, shift);
+            let res = engine.process_key(sc_d, false, true, shift);
+            engine.process_key(thumb, false, true, shift);
+            res
+        };
+        let emits = |res: &KeyAction, sc: u16| match res {
+            KeyAction::Inject(evs) => evs
+                .iter()
+                .any(|e| matches!(e, InputEvent::Scancode(s, _, false) if *s == sc)),
+            _ => false,
+        };
+
+        // Tab + D -> [ローマ字中親指シフト] (b)
+        let res = press_with(&mut engine, 0x0F, false);
+        assert!(emits(&res, 0x30), "expected b, got {:?}", res);
+        // Shift + Tab + D -> [ローマ字小指中親指シフト] (c)
+        let res = press_with(&mut engine, 0x0F, true);
+        assert!(emits(&res, 0x2E), "expected c, got {:?}", res);
+        // CapsLock + D -> [ローマ字左親指シフト2] (e)
+        let res = press_with(&mut engine, 0x3A, false);
+        assert!(emits(&res, 0x12), "expected e, got {:?}", res);
+    }
+
+    #[test]
+    fn test_priority_section_selects_modifier_plane() {
+        let planes = "
+[ローマ字シフト無し]
+1,2,3,4,5,6,7,8,9,0,-,^,\\\\
+q,w,e,r,t,y,u,i,o,p,@,[
+xx,xx,db,xx,xx,xx,xx,kb,xx,xx,xx,xx
+z,x,c,v,b,n,m,,,.,/,\\\\
+
+<k>
+無,無,無,無,無,無,無,無,無,無,無,無,無
+無,無,無,無,無,無,無,無,無,無,無,無
+xx,xx,dc,無,無,無,無,無,無,無,無,無
+
+<d>
+無,無,無,無,無,無,無,無,無,無,無,無,無
+無,無,無,無,無,無,無,無,無,無,無,無
+無,無,無,無,無,無,無,kd,無,無,無,無
+";
+        let chord_second_char = |config: &str| {
+            let mut engine = Engine::default();
+            engine.set_ignore_ime(true);
+            engine.load_layout(parse_yab_content(config).expect("Failed to parse config"));
+            engine.process_key(0x25, false, false, false);
+            engine.process_key(0x20, false, false, false);
+            match engine.process_key(0x20, false, true, false) {
+                KeyAction::Inject(evs) => match evs[2] {
+                    InputEvent::Scancode(sc, _, _) => sc,
+                    ref other => panic!("Expected Scancode, got {:?}", other),
+                },
+                other => panic!("Expected Inject, got {:?}", other),
+            }
+        };
+
+        // Without a priority the first pressed key (K) acts as the modifier.
+        assert_eq!(chord_second_char(planes), 0x2E);
+
+        let prioritized = format!("[優先順位]\nd,k\n{}", planes);
+        assert_eq!(chord_second_char(&prioritized), 0x20);
+    }
+
+    #[test]
+    fn test_pinky_shift_is_judged_by_overlap() {
+        let config = r#"
+[ローマ字シフト無し]
+xx
+xx
+a
+
+[ローマ字小指シフト]
+xx
+xx
+b
+"#;
+        let mut engine = Engine::default();
+        let clock = ManualClock::new();
+        engine.set_clock(Arc::new(clock.clone()));
+        engine.set_ignore_ime(true);
+        let mut profile = engine.get_profile();
+        profile.pinky_shift.enabled = true;
+        engine.set_profile(profile);
+        engine.load_layout(parse_yab_content(config).expect("Failed to parse config"));
+        assert!(engine.needs_shift_handling());
+
+        let (shift, a, tab) = (0x2A, 0x1E, 0x0F);
+        let mut step = |sc: u16, up: bool, after_ms: u64| {
+            clock.advance(Duration::from_millis(after_ms));
+            match engine.process_key(sc, false, up, false) {
+                KeyAction::Inject(evs) => evs,
+                _ => Vec::new(),
+            }
+        };
+        let emits = |evs: &[InputEvent], sc: u16| {
+            evs.iter()
+                .any(|e| matches!(e, InputEvent::Scancode(s, _, false) if *s == sc))
+        };
+
+        // Held across A -> [ローマ字小指シフト], without an OS Shift.
+        let mut out = step(shift, false, 0);
+        out.extend(step(a, false, 20));
+        out.extend(step(a, true, 60));
+        out.extend(step(shift, true, 20));
+        assert!(emits(&out, 0x30), "expected b, got {:?}", out);
+        assert!(!emits(&out, shift), "Shift leaked: {:?}", out);
+
+        // Shift caught only the tail of A -> unshifted a, then a lone Shift tap.
+        let mut out = step(a, false, 100);
+        out.extend(step(shift, false, 80));
+        out.extend(step(a, true, 10));
+        assert!(
+            emits(&out, a) && !emits(&out, 0x30),
+            "expected a, got {:?}",
+            out
+        );
+        let out = step(shift, true, 50);
+        assert!(emits(&out, shift), "expected Shift tap, got {:?}", out);
+
+        // Keys outside the layout get Shift added while it is held.
+        step(shift, false, 100);
+        assert_eq!(
+            step(tab, false, 20),
+            vec![
+                InputEvent::Scancode(shift, false, false),
+                InputEvent::Scancode(tab, false, false),
+                InputEvent::Scancode(shift, false, true),
+            ]
+        );
+        step(tab, true, 20);
+        let out = step(shift, true, 20);
+        assert!(!emits(&out, shift), "Shift tapped after use: {:?}", out);
+    }
+
+    #[test]
+    fn test_left_and_right_shift_select_sided_sections() {
+        let config = r#"
+[ローマ字シフト無し]
+xx
+xx
+a
+
+[ローマ字小指シフト]
+xx
+xx
+b
+
+[ローマ字右小指シフト]
+xx
+xx
+c
+"#;
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).expect("Failed to parse config"));
+        let tap = |engine: &mut Engine, shift: ShiftSide| {
+            engine.process_key_with_shift(0x1E, false, false, shift);
+            match engine.process_key_with_shift(0x1E, false, true, shift) {
+                KeyAction::Inject(evs) => evs
+                    .iter()
+                    .find_map(|e| match e {
+                        InputEvent::Scancode(sc, _, false) if *sc != 0x2A => Some(*sc),
+                        _ => None,
+                    })
+                    .unwrap(),
+                other => panic!("Expected Inject, got {:?}", other),
+            }
+        };
+
+        assert_eq!(tap(&mut engine, ShiftSide::Right), 0x2E);
+        // No [ローマ字左小指シフト]: the plain pinky section applies.
+        assert_eq!(tap(&mut engine, ShiftSide::Left), 0x30);
+        assert_eq!(tap(&mut engine, ShiftSide::Both), 0x30);
+        assert_eq!(
+            engine.process_key(0x1E, false, false, true),
+            KeyAction::Block
+        );
+        assert!(matches!(
+            engine.process_key(0x1E, false, true, true),
+            KeyAction::Inject(evs) if evs.contains(&InputEvent::Scancode(0x30, false, false))
+        ));
+
+        // Pinky shift knows its side from the key itself.
+        let mut profile = engine.get_profile();
+        profile.pinky_shift.enabled = true;
+        engine.set_profile(profile);
+        engine.process_key(0x36, false, false, false);
+        assert_eq!(tap(&mut engine, ShiftSide::None), 0x2E);
+        engine.process_key(0x36, false, true, false);
+    }
+
+    #[test]
+    fn test_modifier_planes_remap_with_physical_modifier_released() {
+        let config = r#"
+[ローマ字シフト無し]
+xx
+xx
+a,s
+<Ctrl>
+xx
+xx
+b
+<Alt>
+xx
+xx
+c
+"#;
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).expect("Failed to parse config"));
+        assert_eq!(
+            engine.modifier_plane_mask(),
+            ModifierPlane::Ctrl.bit() | ModifierPlane::Alt.bit()
+        );
+
+        let event = |sc, up, plane, right| RawKeyEvent {
+            sc,
+            ext: false,
+            up,
+            shift: ShiftSide::None,
+            modifier: Some(HeldModifier { plane, right }),
+        };
+        assert_eq!(
+            engine.process_event(&event(0x1E, false, ModifierPlane::Ctrl, true)),
+            KeyAction::Inject(vec![
+                InputEvent::Scancode(0x1D, true, true),
+                InputEvent::Scancode(0x30, false, false),
+                InputEvent::Scancode(0x30, false, true),
+                InputEvent::Scancode(0x1D, true, false),
+            ])
+        );
+        assert_eq!(
+            engine.process_event(&event(0x1E, true, ModifierPlane::Ctrl, true)),
+            KeyAction::Block
+        );
+        // Undefined in the plane: Ctrl+S stays a shortcut.
+        assert_eq!(
+            engine.process_event(&event(0x1F, false, ModifierPlane::Ctrl, false)),
+            KeyAction::Pass
+        );
+        assert_eq!(
+            engine.process_event(&event(0x1F, true, ModifierPlane::Ctrl, false)),
+            KeyAction::Pass
+        );
+
+        // Alt is masked with a Ctrl tap so its release opens no menu.
+        match engine.process_event(&event(0x1E, false, ModifierPlane::Alt, false)) {
+            KeyAction::Inject(evs) => {
+                assert_eq!(evs[2], InputEvent::Scancode(0x38, false, true));
+                assert_eq!(evs[3], InputEvent::Scancode(0x2E, false, false));
+                assert_eq!(evs[5], InputEvent::Scancode(0x38, false, false));
+                assert_eq!(evs.last(), Some(&InputEvent::Scancode(0x1D, false, true)));
+            }
+            other => panic!("Expected Inject, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_number_key_style_selects_number_sections() {
+        let config = r#"
+[ローマ字シフト無し]
+1,2,3
+xx
+xx,xx,a
+
+[ローマ字数字1シフト]
+xx
+xx
+xx,xx,b
+
+[ローマ字数字2シフト]
+xx
+xx
+xx,xx,c
+"#;
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        let mut profile = engine.get_profile();
+        profile.chord_style = crate::chord_engine::ChordStyle::NNumberKey;
+        engine.set_profile(profile);
+        engine.load_layout(layout);
+
+        let sc_d = 0x20;
+        let press_with = |engine: &mut Engine, number: u16| {
+            engine.process_key(number, false, false, false);
+            engine.process_key(sc_d, false, false, false);
+            let res = engine.process_key(sc_d, false, true, false);
+            engine.process_key(number, false, true, false);
+            res
+        };
+        let emits = |res: &KeyAction, sc: u16| match res {
+            KeyAction::Inject(evs) => evs
+                .iter()
+                .any(|e| matches!(e, InputEvent::Scancode(s, _, false) if *s == sc)),
+            _ => false,
+        };
+
+        // 1 + D -> [ローマ字数字1シフト] (b)
+        let res = press_with(&mut engine, 0x02);
+        assert!(emits(&res, 0x30), "expected b, got {:?}", res);
+        // 2 + D -> [ローマ字数字2シフト] (c)
+        let res = press_with(&mut engine, 0x03);
+        assert!(emits(&res, 0x2E), "expected c, got {:?}", res);
+
+        // A lone number key still types itself.
+        engine.process_key(0x02, false, false, false);
+        let res = engine.process_key(0x02, false, true, false);
+        assert!(emits(&res, 0x02), "expected 1, got {:?}", res);
+    }
+
+    #[test]
+    fn test_section_name_directive_renames_planes() {
+        let config = r#"
+[セクション名]
+ローマ字シフト無し,かな
+ローマ字左親指シフト,かな左
+
+[かな]
+xx
+xx
+xx,xx,a
+
+[かな左]
+xx
+xx
+xx,xx,b
+"#;
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let emits = |res: &KeyAction, sc: u16| match res {
+            KeyAction::Inject(evs) => evs
+                .iter()
+                .any(|e| matches!(e, InputEvent::Scancode(s, _, false) if *s == sc)),
+            _ => false,
+        };
+
+        engine.process_key(0x20, false, false, false);
+        let res = engine.process_key(0x20, false, true, false);
+        assert!(emits(&res, 0x1E), "expected a, got {:?}", res);
+
+        // 無変換 + D -> [かな左] (b)
+        engine.process_key(0x7B, false, false, false);
+        engine.process_key(0x20, false, false, false);
+        let res = engine.process_key(0x20, false, true, false);
+        engine.process_key(0x7B, false, true, false);
+        assert!(emits(&res, 0x30), "expected b, got {:?}", res);
+    }
+
+    #[test]
+    fn test_kana_lock_selects_kana_lock_sections() {
+        let config = r#"
+[機能キー]
+F13,かなロック
+
+[ローマ字シフト無し]
+xx
+xx
+xx,xx,a
+
+[かなロックローマ字シフト無し]
+xx
+xx
+xx,xx,c
+"#;
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let emits = |res: &KeyAction, sc: u16| match res {
+            KeyAction::Inject(evs) => evs
+                .iter()
+                .any(|e| matches!(e, InputEvent::Scancode(s, _, false) if *s == sc)),
+            _ => false,
+        };
+        let tap = |engine: &mut Engine, sc: u16| {
+            engine.process_key(sc, false, false, false);
+            engine.process_key(sc, false, true, false)
+        };
+
+        assert!(!engine.lock_state().kana);
+        let res = tap(&mut engine, 0x20);
+        assert!(emits(&res, 0x1E), "expected a, got {:?}", res);
+
+        // F13 toggles kana lock through the pseudo key.
+        tap(&mut engine, 0x64);
+        assert!(engine.lock_state().kana);
+        let res = tap(&mut engine, 0x20);
+        assert!(emits(&res, 0x2E), "expected c, got {:?}", res);
+        assert_eq!(
+            engine.take_lock_toggles(),
+            LockState {
+                caps: false,
+                kana: true
+            }
+        );
+        assert_eq!(engine.take_lock_toggles(), LockState::default());
+
+        tap(&mut engine, 0x64);
+        assert!(!engine.lock_state().kana);
+        // Toggled twice, the OS ends where it started: nothing to check.
+        tap(&mut engine, 0x64);
+        assert_eq!(engine.take_lock_toggles(), LockState::default());
+    }
+
+    #[test]
+    fn test_caps_lock_compensation_inverts_shift_for_letters() {
+        let mut engine = Engine::default();
+        let token = Token::KeySequence(vec![
+            KeyStroke {
+                key: KeySpec::Char('a'),
+                mods: Modifiers::none(),
+            },
+            KeyStroke {
+                key: KeySpec::Char('1'),
+                mods: Modifiers::none(),
+            },
+        ]);
+        let shift_downs = |events: &[InputEvent]| {
+            events
+                .iter()
+                .filter(|e| matches!(e, InputEvent::Scancode(0x2A, false, false)))
+                .count()
+        };
+
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .unwrap();
+        assert_eq!(shift_downs(&events), 0);
+
+        engine.set_lock_state(LockState {
+            caps: true,
+            kana: false,
+        });
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .unwrap();
+        assert_eq!(shift_downs(&events), 1, "only the letter is shifted");
+
+        let mut profile = engine.get_profile();
+        profile.caps_lock_compensation = false;
+        engine.set_profile(profile);
+        let events = engine
+            .token_to_events_with_ime(&token, false, false)
+            .unwrap();
+        assert_eq!(shift_downs(&events), 0);
+    }
+
+    #[test]
+    fn test_numpad_and_nav_rows_can_be_defined() {
+        let config = r#"
+[ローマ字シフト無し]
+xx
+xx
+xx
+xx
+xx,x
+y
+"#;
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let emits = |res: &KeyAction, sc: u16| match res {
+            KeyAction::Inject(evs) => evs
+                .iter()
+                .any(|e| matches!(e, InputEvent::Scancode(s, _, false) if *s == sc)),
+            _ => false,
+        };
+
+        assert_eq!(
+            crate::jis_map::key_name_to_key("テン1"),
+            Some(ScKey::new(0x4F, false))
+        );
+        assert_eq!(
+            crate::jis_map::key_name_to_key("上"),
+            Some(ScKey::new(0x48, true))
+        );
+
+        // Numpad 1 -> row 4, col 1
+        engine.process_key(0x4F, false, false, false);
+        let res = engine.process_key(0x4F, false, true, false);
+        assert!(emits(&res, 0x2D), "expected x, got {:?}", res);
+
+        // Up arrow -> row 5, col 0
+        engine.process_key(0x48, true, false, false);
+        let res = engine.process_key(0x48, true, true, false);
+        assert!(emits(&res, 0x15), "expected y, got {:?}", res);
+
+        // Numpad 8 shares the Up scancode but is not extended.
+        let res = engine.process_key(0x48, false, false, false);
+        assert_eq!(res, KeyAction::Pass);
+    }
+
+    #[test]
+    fn test_process_events_coalesces_batch_output() {
+        let config = "
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2
+xx,xx,a,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(config).expect("Failed to parse config");
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(layout);
+
+        let raw = |sc, up| RawKeyEvent {
+            sc,
+            ext: false,
+            up,
+            shift: ShiftSide::None,
+            modifier: None,
+        };
+        // D tap, then F1 (unhandled) tap.
+        let batch = [
+            raw(0x20, false),
+            raw(0x20, true),
+            raw(0x3B, false),
+            raw(0x3B, true),
+        ];
+        let actions = engine.process_events(&batch);
+        assert_eq!(actions.len(), batch.len());
+        assert_eq!(actions[0], KeyAction::Block);
+        assert_eq!(actions[2], KeyAction::Pass);
+
+        assert_eq!(
+            coalesce_actions(&batch, actions),
+            vec![
+                InputEvent::Scancode(0x1E, false, false),
+                InputEvent::Scancode(0x1E, false, true),
+                InputEvent::Scancode(0x3B, false, false),
+                InputEvent::Scancode(0x3B, false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_events_stops_after_ime_change() {
+        let config = "
+[ローマ字シフト無し]
+; R0
+dummy
+; R1
+dummy
+; R2
+xx,xx,英,a,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let mut engine = Engine::default();
+        engine.set_ignore_ime(true);
+        engine.load_layout(parse_yab_content(config).unwrap());
+
+        let raw = |sc, up| RawKeyEvent {
+            sc,
+            ext: false,
+            up,
+            shift: ShiftSide::None,
+            modifier: None,
+        };
+        // D turns the IME off; F must wait until that is injected.
+        let batch = [
+            raw(0x20, false),
+            raw(0x20, true),
+            raw(0x21, false),
+            raw(0x21, true),
+        ];
+        let actions = engine.process_events(&batch);
+        assert_eq!(actions.len(), 2);
+        assert_eq!(
+            coalesce_actions(&batch[..2], actions),
+            vec![InputEvent::ImeControl(false)]
+        );
+        assert_eq!(engine.process_events(&batch[2..]).len(), 2);
+    }
 }
@@ -0,0 +1,223 @@
+//! Audible clicks for chord decisions, to help learning thumb-shift timing.
+//! Sounds are played on their own thread; clicks arriving while one plays
+//! are dropped rather than delaying output.
+
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::thread;
+#[cfg(feature = "os-windows")]
+use windows::core::PCWSTR;
+#[cfg(feature = "os-windows")]
+use windows::Win32::Media::Audio::{PlaySoundW, SND_MEMORY, SND_NODEFAULT, SND_SYNC};
+
+const SAMPLE_RATE: u32 = 22050;
+
+static SOUND_FEEDBACK: RwLock<SoundFeedback> = RwLock::new(SoundFeedback {
+    tap: false,
+    chord: false,
+    misfire: false,
+    volume: 50,
+});
+/// `FeedbackEvent` bits that play a sound, checked before touching the lock.
+static ENABLED_EVENTS: AtomicU8 = AtomicU8::new(0);
+static PLAYER_STARTED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref SOUND_QUEUE: (Sender<FeedbackEvent>, Receiver<FeedbackEvent>) =
+        crossbeam_channel::bounded(1);
+}
+
+/// How the engine decided a key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FeedbackEvent {
+    /// A key typed on its own.
+    Tap,
+    /// Keys combined into a defined chord.
+    Chord,
+    /// Keys pressed together that have no chord, typed one by one.
+    Misfire,
+}
+
+impl FeedbackEvent {
+    fn bit(self) -> u8 {
+        match self {
+            FeedbackEvent::Tap => 1,
+            FeedbackEvent::Chord => 2,
+            FeedbackEvent::Misfire => 4,
+        }
+    }
+
+    /// Pitch (Hz) and length (ms) of the click.
+    fn tone(self) -> (u32, u32) {
+        match self {
+            FeedbackEvent::Tap => (1800, 12),
+            FeedbackEvent::Chord => (1000, 25),
+            FeedbackEvent::Misfire => (300, 70),
+        }
+    }
+}
+
+/// Which decisions click, and how loud.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundFeedback {
+    pub tap: bool,
+    pub chord: bool,
+    pub misfire: bool,
+    /// 0 to 100.
+    pub volume: u8,
+}
+
+impl Default for SoundFeedback {
+    fn default() -> Self {
+        Self {
+            tap: false,
+            chord: false,
+            misfire: false,
+            volume: 50,
+        }
+    }
+}
+
+impl SoundFeedback {
+    pub fn plays(&self, event: FeedbackEvent) -> bool {
+        self.volume > 0
+            && match event {
+                FeedbackEvent::Tap => self.tap,
+                FeedbackEvent::Chord => self.chord,
+                FeedbackEvent::Misfire => self.misfire,
+            }
+    }
+
+    fn enabled_events(&self) -> u8 {
+        [
+            FeedbackEvent::Tap,
+            FeedbackEvent::Chord,
+            FeedbackEvent::Misfire,
+        ]
+        .into_iter()
+        .filter(|event| self.plays(*event))
+        .fold(0, |bits, event| bits | event.bit())
+    }
+}
+
+pub fn set_sound_feedback(feedback: SoundFeedback) {
+    let enabled = feedback.enabled_events();
+    *SOUND_FEEDBACK.write() = feedback;
+    ENABLED_EVENTS.store(enabled, Ordering::Release);
+    if enabled != 0 {
+        ensure_player_thread();
+    }
+}
+
+/// Queues the click for `event` if it is enabled. Never blocks.
+pub(crate) fn notify(event: FeedbackEvent) {
+    if ENABLED_EVENTS.load(Ordering::Acquire) & event.bit() == 0 {
+        return;
+    }
+    let _ = SOUND_QUEUE.0.try_send(event);
+}
+
+fn ensure_player_thread() {
+    if PLAYER_STARTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let rx = SOUND_QUEUE.1.clone();
+    thread::Builder::new()
+        .name("kikyo-sound-feedback".to_string())
+        .spawn(move || player(rx))
+        .expect("Failed to spawn sound feedback thread");
+}
+
+fn player(rx: Receiver<FeedbackEvent>) {
+    let mut sounds: HashMap<(FeedbackEvent, u8), Vec<u8>> = HashMap::new();
+    while let Ok(event) = rx.recv() {
+        let volume = SOUND_FEEDBACK.read().volume;
+        let wav = sounds
+            .entry((event, volume))
+            .or_insert_with(|| click_wav(event, volume));
+        play(wav);
+    }
+}
+
+#[cfg(feature = "os-windows")]
+fn play(wav: &[u8]) {
+    unsafe {
+        let _ = PlaySoundW(
+            PCWSTR(wav.as_ptr() as *const u16),
+            None,
+            SND_MEMORY | SND_SYNC | SND_NODEFAULT,
+        );
+    }
+}
+
+/// Without Windows the clicks are not played.
+#[cfg(not(feature = "os-windows"))]
+fn play(_wav: &[u8]) {}
+
+/// 16-bit mono WAV image of a decaying sine click.
+fn click_wav(event: FeedbackEvent, volume: u8) -> Vec<u8> {
+    let (freq, ms) = event.tone();
+    let samples = SAMPLE_RATE * ms / 1000;
+    let amplitude = f32::from(volume.min(100)) / 100.0 * f32::from(i16::MAX) * 0.8;
+    let data_len = samples * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for i in 0..samples {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        let envelope = 1.0 - i as f32 / samples as f32;
+        let sample = (t * freq as f32 * std::f32::consts::TAU).sin() * envelope * amplitude;
+        wav.extend_from_slice(&(sample as i16).to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_click_wav_is_pcm_of_the_tone_length() {
+        let wav = click_wav(FeedbackEvent::Chord, 100);
+        let samples = SAMPLE_RATE * 25 / 1000;
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav.len(), 44 + samples as usize * 2);
+        assert!(click_wav(FeedbackEvent::Chord, 0)[44..]
+            .iter()
+            .all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_muted_feedback_plays_nothing() {
+        let feedback = SoundFeedback {
+            tap: true,
+            misfire: true,
+            ..SoundFeedback::default()
+        };
+        assert_eq!(
+            feedback.enabled_events(),
+            FeedbackEvent::Tap.bit() | FeedbackEvent::Misfire.bit()
+        );
+        let muted = SoundFeedback {
+            volume: 0,
+            ..feedback
+        };
+        assert_eq!(muted.enabled_events(), 0);
+    }
+}
@@ -0,0 +1,103 @@
+#[cfg(feature = "os-windows")]
+use parking_lot::Mutex;
+#[cfg(feature = "os-windows")]
+use windows::core::PWSTR;
+#[cfg(feature = "os-windows")]
+use windows::Win32::Foundation::{CloseHandle, HWND};
+#[cfg(feature = "os-windows")]
+use windows::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+#[cfg(feature = "os-windows")]
+use windows::Win32::UI::Shell::{SHQueryUserNotificationState, QUNS_RUNNING_D3D_FULL_SCREEN};
+#[cfg(feature = "os-windows")]
+use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+/// Last looked-up foreground window and its executable name.
+#[cfg(feature = "os-windows")]
+static LAST_FOREGROUND: Mutex<Option<(isize, Option<String>)>> = Mutex::new(None);
+
+/// Lower-cased executable file name of the foreground window's process
+/// (e.g. "windowsterminal.exe"). Cached per window handle.
+#[cfg(feature = "os-windows")]
+pub fn foreground_exe_name() -> Option<String> {
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0 == 0 {
+        return None;
+    }
+    let mut last = LAST_FOREGROUND.lock();
+    if let Some((cached, name)) = last.as_ref() {
+        if *cached == hwnd.0 {
+            return name.clone();
+        }
+    }
+    let name = window_exe_name(hwnd);
+    *last = Some((hwnd.0, name.clone()));
+    name
+}
+
+/// Without Windows there is no foreground window.
+#[cfg(not(feature = "os-windows"))]
+pub fn foreground_exe_name() -> Option<String> {
+    None
+}
+
+/// Whether a Direct3D application runs in exclusive full screen, as games
+/// usually do. Borderless windowed games are not detected.
+#[cfg(feature = "os-windows")]
+pub fn exclusive_fullscreen_active() -> bool {
+    unsafe { SHQueryUserNotificationState() }
+        .is_ok_and(|state| state == QUNS_RUNNING_D3D_FULL_SCREEN)
+}
+
+#[cfg(not(feature = "os-windows"))]
+pub fn exclusive_fullscreen_active() -> bool {
+    false
+}
+
+#[cfg(feature = "os-windows")]
+fn window_exe_name(hwnd: HWND) -> Option<String> {
+    let mut pid = 0u32;
+    unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+    if pid == 0 {
+        return None;
+    }
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut buf = [0u16; 1024];
+        let mut len = buf.len() as u32;
+        let result = QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(buf.as_mut_ptr()),
+            &mut len,
+        );
+        let _ = CloseHandle(process);
+        result.ok()?;
+        Some(exe_file_name(&String::from_utf16_lossy(
+            &buf[..len as usize],
+        )))
+    }
+}
+
+/// File name part of `path`, lower-cased for case-insensitive matching.
+pub fn exe_file_name(path: &str) -> String {
+    path.rsplit(['\\', '/'])
+        .next()
+        .unwrap_or(path)
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exe_file_name_strips_directory_and_case() {
+        assert_eq!(
+            exe_file_name("C:\\Program Files\\WindowsApps\\WindowsTerminal.exe"),
+            "windowsterminal.exe"
+        );
+        assert_eq!(exe_file_name("mstsc.EXE"), "mstsc.exe");
+    }
+}
@@ -0,0 +1,230 @@
+//! Library entry point to the engine the keyboard hook drives. Frontends
+//! call it through [`EngineHandle`] and follow its state with
+//! [`EngineHandle::subscribe`] instead of locking [`ENGINE`] themselves.
+
+use crate::chord_engine::Profile;
+use crate::chord_test::ChordTestEvent;
+use crate::engine::{Engine, LayoutReload, ENGINE};
+use crate::trace::TraceEvent;
+use crate::types::{KeyAction, Layout, LockKey};
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Events a subscriber falls behind by before further key, trace and chord
+/// test events are dropped. State events are never dropped.
+const EVENT_QUEUE_SIZE: usize = 256;
+
+struct Subscriber {
+    tx: Sender<EngineEvent>,
+    /// Takes [`EngineEvent::KeyProcessed`] too.
+    keys: bool,
+}
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+/// Set while anyone subscribes, so events are only built when needed.
+static HAS_SUBSCRIBERS: AtomicBool = AtomicBool::new(false);
+/// Set while anyone takes key events; each one copies the key's action.
+static HAS_KEY_SUBSCRIBERS: AtomicBool = AtomicBool::new(false);
+/// Held by tests that subscribe, which would otherwise see each other's
+/// subscriptions come and go.
+#[cfg(test)]
+pub(crate) static SUBSCRIBER_TESTS: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    /// A key event from the hook and what the engine did with it. Only for
+    /// [`EngineHandle::subscribe_keys`].
+    KeyProcessed {
+        sc: u16,
+        ext: bool,
+        up: bool,
+        action: KeyAction,
+    },
+    LayoutLoaded {
+        name: Option<String>,
+    },
+    EnabledChanged {
+        enabled: bool,
+    },
+    /// The OS did not follow an injected CapsLock / kana lock toggle,
+    /// retries included, and `lock` is not `expected`.
+    LockToggleFailed {
+        lock: LockKey,
+        expected: bool,
+    },
+    /// A resolved output, while tracing or training
+    /// (see [`Engine::set_trace_enabled`]).
+    Trace(TraceEvent),
+    /// An output the running chord test reports instead of typing it.
+    ChordTest(ChordTestEvent),
+    /// The chord test ended, also when it ended by itself.
+    ChordTestEnded,
+}
+
+impl EngineEvent {
+    /// A change of state a subscriber must not miss, however far behind.
+    fn is_state(&self) -> bool {
+        matches!(
+            self,
+            Self::LayoutLoaded { .. }
+                | Self::EnabledChanged { .. }
+                | Self::LockToggleFailed { .. }
+                | Self::ChordTestEnded
+        )
+    }
+}
+
+/// Handle to the global engine.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineHandle;
+
+impl EngineHandle {
+    pub fn global() -> Self {
+        Self
+    }
+
+    /// Receives the engine's events from now on, except key events.
+    /// Dropping the receiver ends the subscription.
+    pub fn subscribe(&self) -> Receiver<EngineEvent> {
+        add_subscriber(false)
+    }
+
+    /// Like [`Self::subscribe`], with [`EngineEvent::KeyProcessed`] for
+    /// every key. Only subscribe while showing them: each one copies the
+    /// key's action under the engine lock.
+    pub fn subscribe_keys(&self) -> Receiver<EngineEvent> {
+        add_subscriber(true)
+    }
+
+    /// Runs `f` with the engine locked, for anything the handle does not
+    /// cover. Keep it short: the keyboard hook waits for the lock.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> R {
+        f(&mut ENGINE.lock())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        ENGINE.lock().is_enabled()
+    }
+
+    /// Enables or disables the engine; disabling releases the modifiers it
+    /// may have left held.
+    pub fn set_enabled(&self, enabled: bool) {
+        ENGINE.lock().set_enabled(enabled);
+        #[cfg(feature = "os-windows")]
+        if !enabled {
+            crate::keyboard_hook::release_after_disable();
+        }
+    }
+
+    pub fn load_layout(&self, layout: Layout) {
+        ENGINE.lock().load_layout(layout);
+    }
+
+    /// See [`Engine::reload_layout`].
+    pub fn reload_layout(&self, layout: Layout) -> LayoutReload {
+        ENGINE.lock().reload_layout(layout)
+    }
+
+    pub fn profile(&self) -> Profile {
+        ENGINE.lock().get_profile()
+    }
+
+    pub fn set_profile(&self, profile: Profile) {
+        ENGINE.lock().set_profile(profile);
+    }
+}
+
+fn add_subscriber(keys: bool) -> Receiver<EngineEvent> {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let mut subscribers = SUBSCRIBERS.lock();
+    subscribers.push(Subscriber { tx, keys });
+    HAS_SUBSCRIBERS.store(true, Ordering::Release);
+    if keys {
+        HAS_KEY_SUBSCRIBERS.store(true, Ordering::Release);
+    }
+    rx
+}
+
+/// Sends the event built by `event` to every subscriber. Never blocks:
+/// a subscriber [`EVENT_QUEUE_SIZE`] events behind misses all but state
+/// events.
+pub(crate) fn publish(event: impl FnOnce() -> EngineEvent) {
+    if !HAS_SUBSCRIBERS.load(Ordering::Acquire) {
+        return;
+    }
+    send(event());
+}
+
+/// [`publish`] for [`EngineEvent::KeyProcessed`], built only while someone
+/// takes key events.
+pub(crate) fn publish_key(event: impl FnOnce() -> EngineEvent) {
+    if !HAS_KEY_SUBSCRIBERS.load(Ordering::Acquire) {
+        return;
+    }
+    send(event());
+}
+
+fn send(event: EngineEvent) {
+    let key = matches!(event, EngineEvent::KeyProcessed { .. });
+    let lossy = !event.is_state();
+    let mut subscribers = SUBSCRIBERS.lock();
+    subscribers.retain(|subscriber| {
+        if (key && !subscriber.keys) || (lossy && subscriber.tx.len() >= EVENT_QUEUE_SIZE) {
+            return true;
+        }
+        subscriber.tx.send(event.clone()).is_ok()
+    });
+    HAS_SUBSCRIBERS.store(!subscribers.is_empty(), Ordering::Release);
+    HAS_KEY_SUBSCRIBERS.store(
+        subscribers.iter().any(|subscriber| subscriber.keys),
+        Ordering::Release,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::TraceKind;
+    use crate::types::Token;
+
+    #[test]
+    fn test_subscribers_receive_engine_events() {
+        let _serial = SUBSCRIBER_TESTS.lock();
+        let rx = EngineHandle::global().subscribe();
+        let loaded = |name: &str| EngineEvent::LayoutLoaded {
+            name: Some(name.to_string()),
+        };
+        let layout = |name: &str| Layout {
+            name: Some(name.to_string()),
+            ..Layout::default()
+        };
+        Engine::default().load_layout(layout("local"));
+        let mut engine = Engine::publishing();
+        engine.load_layout(layout("handle-test"));
+        engine.process_key(0x1E, false, false, false);
+
+        // Only the publishing engine's events come, and no key events.
+        let events: Vec<_> = rx.try_iter().collect();
+        assert!(events.contains(&loaded("handle-test")));
+        assert!(!events.contains(&loaded("local")));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, EngineEvent::KeyProcessed { .. })));
+
+        // State events get through however far behind the subscriber is.
+        let trace = TraceEvent::new(TraceKind::Tap, &[], &Token::None);
+        for _ in 0..EVENT_QUEUE_SIZE {
+            publish(|| EngineEvent::Trace(trace.clone()));
+        }
+        publish(|| EngineEvent::EnabledChanged { enabled: true });
+        assert!(rx
+            .try_iter()
+            .any(|event| event == EngineEvent::EnabledChanged { enabled: true }));
+
+        // The next event drops the closed subscription.
+        drop(rx);
+        publish(|| EngineEvent::EnabledChanged { enabled: true });
+        assert!(SUBSCRIBERS.lock().is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! How long the keyboard hook callback takes. Windows removes a low-level
+//! hook without notice once a callback runs past `LowLevelHooksTimeout`,
+//! so the hook times itself and reports calls that get close to it.
+
+use serde::Serialize;
+#[cfg(feature = "os-windows")]
+use std::ffi::c_void;
+#[cfg(feature = "os-windows")]
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(feature = "os-windows")]
+use windows::core::w;
+#[cfg(feature = "os-windows")]
+use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+
+/// Timeout Windows applies when the registry has no value.
+pub const DEFAULT_HOOK_TIMEOUT_MS: u32 = 300;
+/// Windows 10 and later cap the registry value at this.
+pub const MAX_HOOK_TIMEOUT_MS: u32 = 1000;
+/// Share of the timeout, in percent, from which a call is reported.
+const WARN_PERCENT: u64 = 50;
+
+/// Callback timings, shared between the hook thread and readers.
+#[derive(Debug)]
+pub struct HookTimings {
+    calls: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    slow_calls: AtomicU64,
+    /// Slowest call over the warning threshold not reported yet, 0 if none.
+    unreported_ns: AtomicU64,
+    timeout_ms: AtomicU64,
+}
+
+/// Snapshot of [`HookTimings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HookHealth {
+    pub calls: u64,
+    pub mean_us: u64,
+    pub max_us: u64,
+    /// Calls that took at least half of `timeout_ms`.
+    pub slow_calls: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for HookTimings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HookTimings {
+    pub const fn new() -> Self {
+        Self {
+            calls: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+            slow_calls: AtomicU64::new(0),
+            unreported_ns: AtomicU64::new(0),
+            timeout_ms: AtomicU64::new(DEFAULT_HOOK_TIMEOUT_MS as u64),
+        }
+    }
+
+    pub fn set_timeout_ms(&self, timeout_ms: u32) {
+        self.timeout_ms
+            .store(u64::from(timeout_ms), Ordering::Relaxed);
+    }
+
+    /// Records one callback. Only atomics, so the hook can call it.
+    pub fn record(&self, elapsed: Duration) {
+        let ns = elapsed.as_nanos().min(u128::from(u64::MAX)) as u64;
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+        let warn_ns = self.timeout_ms.load(Ordering::Relaxed) * 1_000_000 * WARN_PERCENT / 100;
+        if ns >= warn_ns {
+            self.slow_calls.fetch_add(1, Ordering::Relaxed);
+            self.unreported_ns.fetch_max(ns, Ordering::Relaxed);
+        }
+    }
+
+    /// Slowest call over the warning threshold since the last time this
+    /// was asked, if any.
+    pub fn take_slow(&self) -> Option<Duration> {
+        match self.unreported_ns.swap(0, Ordering::Relaxed) {
+            0 => None,
+            ns => Some(Duration::from_nanos(ns)),
+        }
+    }
+
+    pub fn snapshot(&self) -> HookHealth {
+        let calls = self.calls.load(Ordering::Relaxed);
+        let total_ns = self.total_ns.load(Ordering::Relaxed);
+        HookHealth {
+            calls,
+            mean_us: total_ns.checked_div(calls).unwrap_or(0) / 1000,
+            max_us: self.max_ns.load(Ordering::Relaxed) / 1000,
+            slow_calls: self.slow_calls.load(Ordering::Relaxed),
+            timeout_ms: self.timeout_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// `LowLevelHooksTimeout` from the registry, as Windows applies it.
+#[cfg(feature = "os-windows")]
+pub fn low_level_hooks_timeout_ms() -> u32 {
+    let mut value = 0u32;
+    let mut len = size_of::<u32>() as u32;
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            w!("Control Panel\\Desktop"),
+            w!("LowLevelHooksTimeout"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut u32 as *mut c_void),
+            Some(&mut len),
+        )
+    };
+    effective_timeout_ms(result.is_ok().then_some(value))
+}
+
+/// Without Windows there is no registry; the default applies.
+#[cfg(not(feature = "os-windows"))]
+pub fn low_level_hooks_timeout_ms() -> u32 {
+    effective_timeout_ms(None)
+}
+
+fn effective_timeout_ms(registry: Option<u32>) -> u32 {
+    match registry {
+        Some(0) | None => DEFAULT_HOOK_TIMEOUT_MS,
+        Some(ms) => ms.min(MAX_HOOK_TIMEOUT_MS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slow_calls_are_reported_once() {
+        let timings = HookTimings::new();
+        timings.set_timeout_ms(effective_timeout_ms(Some(200)));
+        timings.record(Duration::from_micros(20));
+        timings.record(Duration::from_micros(40));
+        assert_eq!(timings.take_slow(), None);
+
+        timings.record(Duration::from_millis(120));
+        timings.record(Duration::from_millis(110));
+        assert_eq!(timings.take_slow(), Some(Duration::from_millis(120)));
+        assert_eq!(timings.take_slow(), None);
+
+        let health = timings.snapshot();
+        assert_eq!(health.calls, 4);
+        assert_eq!(health.slow_calls, 2);
+        assert_eq!(health.max_us, 120_000);
+        assert_eq!(health.timeout_ms, 200);
+        assert_eq!(effective_timeout_ms(None), DEFAULT_HOOK_TIMEOUT_MS);
+        assert_eq!(effective_timeout_ms(Some(5000)), MAX_HOOK_TIMEOUT_MS);
+    }
+}
@@ -1,41 +1,316 @@
 use crate::chord_engine::ImeMode;
+use crate::foreground::{exe_file_name, foreground_exe_name};
+#[cfg(feature = "os-windows")]
+use crate::types::ImeCommand;
+use crate::types::LockState;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "os-windows")]
 use std::mem::size_of;
-use tracing;
+#[cfg(feature = "os-windows")]
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::time::{Duration, Instant};
+#[cfg(feature = "os-windows")]
 use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+#[cfg(feature = "os-windows")]
+use windows::Win32::System::Threading::{AttachThreadInput, GetCurrentThreadId};
+#[cfg(feature = "os-windows")]
 use windows::Win32::UI::Input::Ime::{
-    ImmGetContext, ImmGetConversionStatus, ImmGetDefaultIMEWnd, ImmGetOpenStatus,
-    ImmReleaseContext, ImmSetOpenStatus, IME_CMODE_NATIVE, IME_CONVERSION_MODE, IME_SENTENCE_MODE,
+    ImmGetCompositionStringW, ImmGetContext, ImmGetConversionStatus, ImmGetDefaultIMEWnd,
+    ImmGetOpenStatus, ImmNotifyIME, ImmReleaseContext, ImmSetConversionStatus, ImmSetOpenStatus,
+    CPS_COMPLETE, GCS_COMPSTR, IME_CONVERSION_MODE, IME_SENTENCE_MODE, IMR_RECONVERTSTRING,
+    NI_COMPOSITIONSTR,
 };
+#[cfg(feature = "os-windows")]
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    GetKeyState, GetKeyboardState, VK_CAPITAL, VK_KANA,
+};
+#[cfg(feature = "os-windows")]
 use windows::Win32::UI::WindowsAndMessaging::{
-    GetForegroundWindow, GetGUIThreadInfo, GetWindowThreadProcessId, SendMessageW, GUITHREADINFO,
-    WM_IME_CONTROL,
+    GetClassNameW, GetForegroundWindow, GetGUIThreadInfo, GetWindowThreadProcessId,
+    IsWindowVisible, SendMessageTimeoutW, SendMessageW, GUITHREADINFO, SMTO_ABORTIFHUNG,
+    WM_IME_CONTROL, WM_IME_REQUEST,
 };
 
+static APP_OVERRIDES: RwLock<Vec<AppImeOverride>> = RwLock::new(Vec::new());
+static STATE_CACHE: Mutex<StateCache> = Mutex::new(StateCache::new());
+/// Candidate window last shown, or 0.
+#[cfg(feature = "os-windows")]
+static CANDIDATE_WINDOW: AtomicIsize = AtomicIsize::new(0);
+/// Composition window last shown, or 0.
+#[cfg(feature = "os-windows")]
+static COMPOSITION_WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+/// A detected state is reused this long unless a focus or IME event
+/// invalidates it first.
+const STATE_CACHE_TTL: Duration = Duration::from_millis(250);
+
+#[cfg(feature = "os-windows")]
 const IMC_GETCONVERSIONMODE: WPARAM = WPARAM(0x0001);
+#[cfg(feature = "os-windows")]
 const IMC_GETOPENSTATUS: WPARAM = WPARAM(0x0005);
+#[cfg(feature = "os-windows")]
+const IMC_SETOPENSTATUS: WPARAM = WPARAM(0x0006);
+
+/// Conversion mode bits (IME_CMODE_*).
+const CMODE_NATIVE: u32 = 0x0001;
+#[cfg(any(feature = "os-windows", test))]
+const CMODE_KATAKANA: u32 = 0x0002;
+#[cfg(any(feature = "os-windows", test))]
+const CMODE_FULLSHAPE: u32 = 0x0008;
+
+/// IME mode forced for one application whose IME state is misreported
+/// (games, some Electron editors).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppImeOverride {
+    /// Executable file name, e.g. "code.exe". Matched case-insensitively.
+    pub exe: String,
+    pub mode: ImeMode,
+}
+
+pub fn set_app_overrides(overrides: Vec<AppImeOverride>) {
+    *APP_OVERRIDES.write() = overrides;
+}
+
+fn override_for(overrides: &[AppImeOverride], exe: Option<&str>) -> Option<ImeMode> {
+    let exe = exe?;
+    overrides
+        .iter()
+        .find(|o| exe_file_name(&o.exe) == exe)
+        .map(|o| o.mode)
+}
+
+/// `mode`, or the override for the foreground application.
+pub fn effective_mode(mode: ImeMode) -> ImeMode {
+    let overrides = APP_OVERRIDES.read();
+    if overrides.is_empty() {
+        return mode;
+    }
+    override_for(&overrides, foreground_exe_name().as_deref()).unwrap_or(mode)
+}
+
+/// Way of reading the IME state of another application's window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ImeBackend {
+    /// Input context of the focused window (ImmGetContext). Only answers
+    /// for windows whose input context is reachable from this process.
+    Imm,
+    /// WM_IME_CONTROL to the focused window's default IME window, which
+    /// also reaches other processes. TSF-based IMEs answer it through
+    /// their IMM compatibility layer.
+    ImeWindow,
+}
+
+impl ImeBackend {
+    /// Backends tried in order for `mode`, each falling back to the next.
+    /// `Imm` keeps the IME window as its fallback, as the input context of
+    /// another process's window is out of reach; `Tsf` (the setting's old
+    /// name for the message route) asks the IME window only.
+    pub fn chain(mode: ImeMode) -> &'static [ImeBackend] {
+        match mode {
+            ImeMode::Auto | ImeMode::Imm => &[ImeBackend::Imm, ImeBackend::ImeWindow],
+            ImeMode::Tsf => &[ImeBackend::ImeWindow],
+            ImeMode::Ignore | ImeMode::ForceAlpha => &[],
+        }
+    }
+
+    #[cfg(feature = "os-windows")]
+    fn probe(self, hwnd: HWND) -> Option<ImeState> {
+        match self {
+            ImeBackend::Imm => probe_imm(hwnd),
+            ImeBackend::ImeWindow => probe_ime_window(hwnd),
+        }
+    }
+}
+
+/// IME state as reported by one backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ImeState {
+    pub open: bool,
+    /// Conversion mode bits, when the backend could read them.
+    pub conversion_mode: Option<u32>,
+}
+
+impl ImeState {
+    /// Open and in native (kana) conversion mode. An unknown conversion
+    /// mode counts as native.
+    pub fn is_japanese(&self) -> bool {
+        self.open && !matches!(self.conversion_mode, Some(bits) if bits & CMODE_NATIVE == 0)
+    }
+}
+
+/// Result of one backend for [`ImeDebugInfo`].
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendProbe {
+    pub backend: ImeBackend,
+    pub state: Option<ImeState>,
+}
+
+/// What IME detection sees for the current foreground window.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImeDebugInfo {
+    /// Mode in effect, after per-application overrides.
+    pub mode: ImeMode,
+    pub app_override: bool,
+    pub foreground_exe: Option<String>,
+    pub focused_window: Option<isize>,
+    /// Every backend of the chain, including those after the one used.
+    pub probes: Vec<BackendProbe>,
+    /// Backend whose answer is used.
+    pub backend: Option<ImeBackend>,
+    pub japanese_input_active: bool,
+}
+
+/// First answer along the backend chain for `mode`.
+#[cfg(feature = "os-windows")]
+pub fn detect(mode: ImeMode) -> Option<(ImeBackend, ImeState)> {
+    let chain = ImeBackend::chain(mode);
+    if chain.is_empty() {
+        return None;
+    }
+    let hwnd = focused_window()?;
+    chain
+        .iter()
+        .find_map(|backend| backend.probe(hwnd).map(|state| (*backend, state)))
+}
+
+#[cfg(feature = "os-windows")]
+pub fn debug_info(profile_mode: ImeMode) -> ImeDebugInfo {
+    let mode = effective_mode(profile_mode);
+    let hwnd = focused_window();
+    let probes: Vec<BackendProbe> = ImeBackend::chain(mode)
+        .iter()
+        .map(|backend| BackendProbe {
+            backend: *backend,
+            state: hwnd.and_then(|hwnd| backend.probe(hwnd)),
+        })
+        .collect();
+    ImeDebugInfo {
+        mode,
+        app_override: mode != profile_mode,
+        foreground_exe: foreground_exe_name(),
+        focused_window: hwnd.map(|hwnd| hwnd.0),
+        backend: probes
+            .iter()
+            .find(|probe| probe.state.is_some())
+            .map(|probe| probe.backend),
+        probes,
+        japanese_input_active: refresh_japanese_input_active(profile_mode),
+    }
+}
+
+/// Without Windows there is no focused window, so no IME answers.
+#[cfg(not(feature = "os-windows"))]
+pub fn detect(_mode: ImeMode) -> Option<(ImeBackend, ImeState)> {
+    None
+}
 
 pub fn is_ime_on(mode: ImeMode) -> bool {
-    // ... existing ...
     match mode {
         ImeMode::Ignore => true,
         ImeMode::ForceAlpha => true,
-        ImeMode::Auto => get_ime_open_status().unwrap_or(false),
-        ImeMode::Tsf => query_tsf().unwrap_or(false),
-        ImeMode::Imm => query_imm().unwrap_or(false),
+        _ => detect(mode).is_some_and(|(_, state)| state.open),
     }
 }
 
 pub fn get_ime_open_status() -> anyhow::Result<bool> {
-    if let Some(open) = query_tsf() {
-        return Ok(open);
+    detect(ImeMode::Auto)
+        .map(|(_, state)| state.open)
+        .ok_or_else(|| anyhow::anyhow!("Failed to query IME status"))
+}
+
+/// Reads the CapsLock and kana lock toggles from the OS. The calling
+/// thread's own key state only follows the keyboard messages it reads, so
+/// it attaches to the foreground thread's input state for the read.
+#[cfg(feature = "os-windows")]
+pub fn query_lock_state() -> LockState {
+    let mut keys = [0u8; 256];
+    let read = unsafe {
+        let current = GetCurrentThreadId();
+        let target = GetWindowThreadProcessId(GetForegroundWindow(), None);
+        let attached =
+            target != 0 && target != current && AttachThreadInput(current, target, true).as_bool();
+        let read = GetKeyboardState(&mut keys).is_ok();
+        if attached {
+            let _ = AttachThreadInput(current, target, false);
+        }
+        read
+    };
+    let toggled = |vk: u16| {
+        if read {
+            keys[vk as usize] & 1 != 0
+        } else {
+            (unsafe { GetKeyState(vk as i32) } & 1) != 0
+        }
+    };
+    LockState {
+        caps: toggled(VK_CAPITAL.0),
+        kana: toggled(VK_KANA.0),
     }
-    if let Some(open) = query_imm() {
-        return Ok(open);
+}
+
+/// Without Windows both locks count as off.
+#[cfg(not(feature = "os-windows"))]
+pub fn query_lock_state() -> LockState {
+    LockState::default()
+}
+
+/// Last answer of [`is_japanese_input_active`]. Invalidation bumps the
+/// generation so a query racing with an IME change is not stored.
+struct StateCache {
+    generation: u64,
+    entry: Option<(ImeMode, Instant, bool)>,
+}
+
+impl StateCache {
+    const fn new() -> Self {
+        Self {
+            generation: 0,
+            entry: None,
+        }
+    }
+
+    fn get(&self, mode: ImeMode, now: Instant) -> Option<bool> {
+        match self.entry {
+            Some((cached_mode, at, japanese))
+                if cached_mode == mode && now.duration_since(at) < STATE_CACHE_TTL =>
+            {
+                Some(japanese)
+            }
+            _ => None,
+        }
+    }
+
+    fn store(&mut self, generation: u64, mode: ImeMode, at: Instant, japanese: bool) {
+        if generation == self.generation {
+            self.entry = Some((mode, at, japanese));
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.entry = None;
     }
-    Err(anyhow::anyhow!("Failed to query IME status"))
+}
+
+/// Drops the cached IME state. Called on focus changes, IME window events
+/// and when Kikyo switches the IME itself.
+pub fn invalidate_state_cache() {
+    STATE_CACHE.lock().invalidate();
 }
 
 pub fn is_japanese_input_active(mode: ImeMode) -> bool {
+    query_japanese_input_active(mode, true)
+}
+
+/// Like [`is_japanese_input_active`] but always asks the OS; for code
+/// waiting for an IME change to take effect.
+pub fn refresh_japanese_input_active(mode: ImeMode) -> bool {
+    query_japanese_input_active(mode, false)
+}
+
+fn query_japanese_input_active(mode: ImeMode, use_cache: bool) -> bool {
+    let mode = effective_mode(mode);
     // If ImeMode is Ignore, we treat it as "Force Enable" -> True (Japanese Mode)
     if matches!(mode, ImeMode::Ignore) {
         return true;
@@ -43,105 +318,66 @@ pub fn is_japanese_input_active(mode: ImeMode) -> bool {
     if matches!(mode, ImeMode::ForceAlpha) {
         return false;
     }
-
-    // Check if IME is Open
-    let ime_on = is_ime_on(mode);
-    if !ime_on {
-        // tracing::info!("IME Check: OFF (OpenStatus=false)");
-        return false;
-    }
-
-    // If Open, check Conversion Mode
-    // If IME is ON but in Alpha mode, we treat it as non-Japanese.
-    if let Some(mode_bits) = query_conversion_mode().or_else(query_conversion_mode_msg) {
-        let is_native = (mode_bits & IME_CMODE_NATIVE) != IME_CONVERSION_MODE(0);
-        // tracing::info!("IME Check: ON, Native={}", is_native);
-        is_native
-    } else {
-        // Fallback: If we can't get conversion mode, assume True if IME is Open?
-        // Or False? Let's assume True to be safe (preserve existing behavior).
-        // tracing::info!("IME Check: ON, ConversionMode=Unknown -> Assume True");
-        true
-    }
+    let now = Instant::now();
+    let generation = {
+        let cache = STATE_CACHE.lock();
+        if use_cache {
+            if let Some(japanese) = cache.get(mode, now) {
+                return japanese;
+            }
+        }
+        cache.generation
+    };
+    // IME state unknown: treat as closed.
+    let japanese = detect(mode).is_some_and(|(_, state)| state.is_japanese());
+    STATE_CACHE.lock().store(generation, mode, now, japanese);
+    japanese
 }
 
-fn query_tsf() -> Option<bool> {
-    let hwnd = focused_window()?;
+#[cfg(feature = "os-windows")]
+fn probe_imm(hwnd: HWND) -> Option<ImeState> {
     unsafe {
         let himc = ImmGetContext(hwnd);
         if himc.0 == 0 {
-            // tracing::warn!(
-            //     "query_tsf: ImmGetContext failed for HWND {:?}. Trying fallback...",
-            //     hwnd
-            // );
-            return query_ime_msg(hwnd);
-        }
-        let open = ImmGetOpenStatus(himc).as_bool();
-        let _ = ImmReleaseContext(hwnd, himc);
-        Some(open)
-    }
-}
-
-fn query_imm() -> Option<bool> {
-    unsafe {
-        let hwnd_fg = GetForegroundWindow();
-        if hwnd_fg.0 == 0 {
-            tracing::warn!("query_imm: No Foreground Window");
             return None;
         }
-
-        let himc = ImmGetContext(hwnd_fg);
-        if himc.0 == 0 {
-            // tracing::warn!(
-            //     "query_imm: ImmGetContext failed for FG HWND {:?}. Trying fallback...",
-            //     hwnd_fg
-            // );
-            return query_ime_msg(hwnd_fg);
-        }
-
         let open = ImmGetOpenStatus(himc).as_bool();
-        let _ = ImmReleaseContext(hwnd_fg, himc);
-        Some(open)
+        let mut conversion = IME_CONVERSION_MODE::default();
+        let mut sentence = IME_SENTENCE_MODE::default();
+        let res = ImmGetConversionStatus(
+            himc,
+            Some(&mut conversion as *mut _),
+            Some(&mut sentence as *mut _),
+        );
+        let _ = ImmReleaseContext(hwnd, himc);
+        Some(ImeState {
+            open,
+            conversion_mode: res.as_bool().then_some(conversion.0),
+        })
     }
 }
 
-fn query_ime_msg(hwnd: HWND) -> Option<bool> {
+#[cfg(feature = "os-windows")]
+fn probe_ime_window(hwnd: HWND) -> Option<ImeState> {
     unsafe {
         let hwnd_ime = ImmGetDefaultIMEWnd(hwnd);
         if hwnd_ime.0 == 0 {
             tracing::warn!(
-                "query_ime_msg: ImmGetDefaultIMEWnd returned 0 for HWND {:?}",
+                "probe_ime_window: ImmGetDefaultIMEWnd returned 0 for HWND {:?}",
                 hwnd
             );
             return None;
         }
-
-        let res = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_GETOPENSTATUS, LPARAM(0));
-
-        Some(res.0 != 0)
-    }
-}
-
-fn query_conversion_mode_msg() -> Option<IME_CONVERSION_MODE> {
-    unsafe {
-        let hwnd_fg = GetForegroundWindow();
-        if hwnd_fg.0 == 0 {
-            return None;
-        }
-        // Try focused window first? Or just foreground.
-        // Usually DefaultIMEWnd is per thread/window.
-        let hwnd_ime = ImmGetDefaultIMEWnd(hwnd_fg);
-        if hwnd_ime.0 == 0 {
-            return None;
-        }
-
-        let res = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_GETCONVERSIONMODE, LPARAM(0));
-
-        // res.0 is the conversion mode (u32/isize)
-        Some(IME_CONVERSION_MODE(res.0 as u32))
+        let open = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_GETOPENSTATUS, LPARAM(0));
+        let conversion = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_GETCONVERSIONMODE, LPARAM(0));
+        Some(ImeState {
+            open: open.0 != 0,
+            conversion_mode: Some(conversion.0 as u32),
+        })
     }
 }
 
+#[cfg(feature = "os-windows")]
 fn focused_window() -> Option<HWND> {
     unsafe {
         let hwnd_fg = GetForegroundWindow();
@@ -168,41 +404,62 @@ fn focused_window() -> Option<HWND> {
     }
 }
 
-fn query_conversion_mode() -> Option<IME_CONVERSION_MODE> {
+/// True while the focused window has an uncommitted composition string.
+/// The input context of another process's window is out of reach, so
+/// there the IME must be open (asked through its default IME window) with
+/// a composition or candidate window showing. Compositions drawn inline
+/// without either window go unnoticed.
+#[cfg(feature = "os-windows")]
+pub fn is_composing() -> bool {
+    let Some(hwnd) = focused_window() else {
+        return false;
+    };
     unsafe {
-        let hwnd_fg = GetForegroundWindow();
-        if hwnd_fg.0 == 0 {
-            tracing::warn!("query_conversion_mode: No Foreground Window");
-            return None;
+        let himc = ImmGetContext(hwnd);
+        if himc.0 != 0 {
+            let len = ImmGetCompositionStringW(himc, GCS_COMPSTR, None, 0);
+            let _ = ImmReleaseContext(hwnd, himc);
+            return len > 0;
         }
-
-        let himc = ImmGetContext(hwnd_fg);
-        if himc.0 == 0 {
-            // tracing::warn!("query_conversion_mode: ImmGetContext failed");
-            return None;
+        let hwnd_ime = ImmGetDefaultIMEWnd(hwnd);
+        if hwnd_ime.0 == 0 {
+            return false;
         }
+        let open = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_GETOPENSTATUS, LPARAM(0));
+        open.0 != 0 && (is_window_shown(&COMPOSITION_WINDOW) || is_candidate_window_open())
+    }
+}
 
-        let mut conversion = IME_CONVERSION_MODE::default();
-        let mut sentence = IME_SENTENCE_MODE::default();
-        let res = ImmGetConversionStatus(
-            himc,
-            Some(&mut conversion as *mut _),
-            Some(&mut sentence as *mut _),
-        );
-        let _ = ImmReleaseContext(hwnd_fg, himc);
-
-        if res.as_bool() {
-            Some(conversion)
-        } else {
-            tracing::warn!("query_conversion_mode: ImmGetConversionStatus failed");
-            None
+/// Commits the focused window's composition string as it stands. In
+/// another process's window this closes and reopens the IME through its
+/// default IME window, which makes IMEs commit the composition.
+#[cfg(feature = "os-windows")]
+pub fn complete_composition() -> bool {
+    let Some(hwnd) = focused_window() else {
+        return false;
+    };
+    unsafe {
+        let himc = ImmGetContext(hwnd);
+        if himc.0 != 0 {
+            let res = ImmNotifyIME(himc, NI_COMPOSITIONSTR, CPS_COMPLETE, 0);
+            let _ = ImmReleaseContext(hwnd, himc);
+            return res.as_bool();
+        }
+        let hwnd_ime = ImmGetDefaultIMEWnd(hwnd);
+        if hwnd_ime.0 == 0 {
+            tracing::warn!("complete_composition: ImmGetDefaultIMEWnd failed");
+            return false;
         }
+        let _ = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_SETOPENSTATUS, LPARAM(0));
+        let _ = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_SETOPENSTATUS, LPARAM(1));
+        invalidate_state_cache();
+        true
     }
 }
 
-const IMC_SETOPENSTATUS: WPARAM = WPARAM(0x0006);
-
+#[cfg(feature = "os-windows")]
 pub fn set_force_ime_status(open: bool) {
+    invalidate_state_cache();
     // Try both ImmSetOpenStatus and TSF-like approaches if needed.
     // For now, standard ImmSetOpenStatus on the focused window context usually works for legacy apps.
     // For TSF apps, it might be more complex, but let's start with IMM.
@@ -232,6 +489,7 @@ pub fn set_force_ime_status(open: bool) {
     }
 }
 
+#[cfg(feature = "os-windows")]
 fn set_force_ime_status_msg(hwnd: HWND, open: bool) {
     unsafe {
         let hwnd_ime = ImmGetDefaultIMEWnd(hwnd);
@@ -247,3 +505,331 @@ fn set_force_ime_status_msg(hwnd: HWND, open: bool) {
         );
     }
 }
+
+#[cfg(feature = "os-windows")]
+const IMC_SETCONVERSIONMODE: WPARAM = WPARAM(0x0002);
+
+/// Conversion mode bits that select hiragana, katakana or half-width kana.
+#[cfg(any(feature = "os-windows", test))]
+const KANA_MODE_BITS: u32 = CMODE_NATIVE | CMODE_KATAKANA | CMODE_FULLSHAPE;
+
+/// True for the window class of an IME candidate window. IMEs name them
+/// differently, but with "cand" in the name.
+#[cfg(any(feature = "os-windows", test))]
+fn is_candidate_window_class(class: &str) -> bool {
+    class.to_ascii_lowercase().contains("cand")
+}
+
+/// True for the window class of an IME composition window, shown by IMEs
+/// in windows that do not draw the composition themselves.
+#[cfg(any(feature = "os-windows", test))]
+fn is_composition_window_class(class: &str) -> bool {
+    class.to_ascii_lowercase().contains("composition")
+}
+
+#[cfg(feature = "os-windows")]
+fn window_class(hwnd: HWND) -> String {
+    let mut class = [0u16; 128];
+    let len = unsafe { GetClassNameW(hwnd, &mut class) };
+    String::from_utf16_lossy(&class[..len.max(0) as usize])
+}
+
+/// Follows IME windows being shown and hidden (EVENT_OBJECT_IME_SHOW and
+/// EVENT_OBJECT_IME_HIDE) to know when a candidate or composition window
+/// is open.
+#[cfg(feature = "os-windows")]
+pub fn on_ime_window_event(hwnd: HWND, shown: bool) {
+    if shown {
+        let class = window_class(hwnd);
+        if is_candidate_window_class(&class) {
+            CANDIDATE_WINDOW.store(hwnd.0, Ordering::Relaxed);
+        } else if is_composition_window_class(&class) {
+            COMPOSITION_WINDOW.store(hwnd.0, Ordering::Relaxed);
+        }
+    } else {
+        for window in [&CANDIDATE_WINDOW, &COMPOSITION_WINDOW] {
+            let _ = window.compare_exchange(hwnd.0, 0, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "os-windows")]
+fn is_window_shown(window: &AtomicIsize) -> bool {
+    let hwnd = window.load(Ordering::Relaxed);
+    hwnd != 0 && unsafe { IsWindowVisible(HWND(hwnd)) }.as_bool()
+}
+
+/// True while the IME candidate window last shown is visible.
+#[cfg(feature = "os-windows")]
+pub fn is_candidate_window_open() -> bool {
+    is_window_shown(&CANDIDATE_WINDOW)
+}
+
+#[cfg(not(feature = "os-windows"))]
+pub fn is_candidate_window_open() -> bool {
+    false
+}
+
+#[cfg(feature = "os-windows")]
+const EM_GETSEL: u32 = 0x00B0;
+
+/// Window classes whose selection is read with EM_GETSEL.
+#[cfg(feature = "os-windows")]
+const EDIT_CLASSES: [&str; 3] = ["Edit", "RichEdit20W", "RICHEDIT50W"];
+
+/// What the IME reconverts on 変換 in the focused window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconversionTarget {
+    /// The window hands the IME its text (IMR_RECONVERTSTRING), and the
+    /// IME takes the selection or the word at the caret from it.
+    Window,
+    /// The selection. Also assumed when the window tells nothing.
+    Selection,
+    /// Nothing is selected in an edit control that keeps its text to
+    /// itself, so the word before the caret has to be selected first.
+    WordBeforeCaret,
+}
+
+#[cfg(any(feature = "os-windows", test))]
+fn reconversion_target_for(
+    reconvert_size: isize,
+    edit_selection: Option<(u32, u32)>,
+) -> ReconversionTarget {
+    match edit_selection {
+        _ if reconvert_size > 0 => ReconversionTarget::Window,
+        Some((start, end)) if start == end => ReconversionTarget::WordBeforeCaret,
+        _ => ReconversionTarget::Selection,
+    }
+}
+
+/// What 変換 would reconvert in the focused window. A window that does not
+/// answer in time is left to reconvert its selection.
+#[cfg(feature = "os-windows")]
+pub fn reconversion_target() -> ReconversionTarget {
+    let Some(hwnd) = focused_window() else {
+        return ReconversionTarget::Selection;
+    };
+    // With no buffer, the window only answers the size it needs.
+    let Some(reconvert_size) = query_window(
+        hwnd,
+        WM_IME_REQUEST,
+        WPARAM(IMR_RECONVERTSTRING as usize),
+        LPARAM(0),
+    ) else {
+        return ReconversionTarget::Selection;
+    };
+    reconversion_target_for(reconvert_size, edit_selection(hwnd))
+}
+
+/// Selection of a standard edit control, as (start, end).
+#[cfg(feature = "os-windows")]
+fn edit_selection(hwnd: HWND) -> Option<(u32, u32)> {
+    let class = window_class(hwnd);
+    if !EDIT_CLASSES.iter().any(|c| class.eq_ignore_ascii_case(c)) {
+        return None;
+    }
+    let selection = query_window(hwnd, EM_GETSEL, WPARAM(0), LPARAM(0))? as u32;
+    Some((selection & 0xFFFF, selection >> 16))
+}
+
+/// Time another application's window gets to answer a query.
+#[cfg(feature = "os-windows")]
+const QUERY_TIMEOUT_MS: u32 = 50;
+
+/// `SendMessageW` to another application's window that gives up when it
+/// is hung or slow: `None` if it did not answer in time. The hook worker
+/// calls this while keys wait behind it.
+#[cfg(feature = "os-windows")]
+fn query_window(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> Option<isize> {
+    let mut result = 0usize;
+    let answered = unsafe {
+        SendMessageTimeoutW(
+            hwnd,
+            msg,
+            wparam,
+            lparam,
+            SMTO_ABORTIFHUNG,
+            QUERY_TIMEOUT_MS,
+            Some(&mut result),
+        )
+    };
+    (answered.0 != 0).then_some(result as isize)
+}
+
+/// Carries out `command` on the focused window's IME. For `Reconvert`
+/// this only turns the IME on; the 変換 key typed after it reconverts.
+#[cfg(feature = "os-windows")]
+pub fn run_command(command: ImeCommand) {
+    let kana_mode = match command {
+        ImeCommand::Reconvert => return set_force_ime_status(true),
+        ImeCommand::Hiragana => CMODE_NATIVE | CMODE_FULLSHAPE,
+        ImeCommand::Katakana => CMODE_NATIVE | CMODE_KATAKANA | CMODE_FULLSHAPE,
+        ImeCommand::HalfWidthKana => CMODE_NATIVE | CMODE_KATAKANA,
+    };
+    set_kana_mode(kana_mode);
+}
+
+/// `current` with its kana mode replaced, keeping romaji input and the like.
+#[cfg(any(feature = "os-windows", test))]
+fn with_kana_mode(current: u32, kana_mode: u32) -> u32 {
+    current & !KANA_MODE_BITS | kana_mode
+}
+
+#[cfg(feature = "os-windows")]
+fn set_kana_mode(kana_mode: u32) {
+    invalidate_state_cache();
+    let Some(hwnd) = focused_window() else {
+        tracing::warn!("set_kana_mode: No focused window found");
+        return;
+    };
+
+    unsafe {
+        let himc = ImmGetContext(hwnd);
+        if himc.0 != 0 {
+            let mut conversion = IME_CONVERSION_MODE::default();
+            let mut sentence = IME_SENTENCE_MODE::default();
+            let done = ImmGetConversionStatus(
+                himc,
+                Some(&mut conversion as *mut _),
+                Some(&mut sentence as *mut _),
+            )
+            .as_bool()
+                && ImmSetOpenStatus(himc, true).as_bool()
+                && ImmSetConversionStatus(
+                    himc,
+                    IME_CONVERSION_MODE(with_kana_mode(conversion.0, kana_mode)),
+                    sentence,
+                )
+                .as_bool();
+            let _ = ImmReleaseContext(hwnd, himc);
+            if done {
+                return;
+            }
+        }
+        set_kana_mode_msg(hwnd, kana_mode);
+    }
+}
+
+#[cfg(feature = "os-windows")]
+fn set_kana_mode_msg(hwnd: HWND, kana_mode: u32) {
+    unsafe {
+        let hwnd_ime = ImmGetDefaultIMEWnd(hwnd);
+        if hwnd_ime.0 == 0 {
+            tracing::warn!("set_kana_mode_msg: ImmGetDefaultIMEWnd failed");
+            return;
+        }
+        let _ = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_SETOPENSTATUS, LPARAM(1));
+        let current = SendMessageW(hwnd_ime, WM_IME_CONTROL, IMC_GETCONVERSIONMODE, LPARAM(0));
+        let mode = with_kana_mode(current.0 as u32, kana_mode);
+        let _ = SendMessageW(
+            hwnd_ime,
+            WM_IME_CONTROL,
+            IMC_SETCONVERSIONMODE,
+            LPARAM(mode as isize),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_chain_and_japanese_state() {
+        assert_eq!(
+            ImeBackend::chain(ImeMode::Auto),
+            &[ImeBackend::Imm, ImeBackend::ImeWindow]
+        );
+        assert_eq!(
+            ImeBackend::chain(ImeMode::Imm),
+            &[ImeBackend::Imm, ImeBackend::ImeWindow]
+        );
+        assert_eq!(ImeBackend::chain(ImeMode::Tsf), &[ImeBackend::ImeWindow]);
+        assert!(ImeBackend::chain(ImeMode::ForceAlpha).is_empty());
+
+        let native = CMODE_NATIVE;
+        let state = |open, conversion_mode| ImeState {
+            open,
+            conversion_mode,
+        };
+        assert!(state(true, Some(native)).is_japanese());
+        assert!(state(true, None).is_japanese());
+        assert!(!state(true, Some(0)).is_japanese());
+        assert!(!state(false, Some(native)).is_japanese());
+    }
+
+    #[test]
+    fn test_kana_mode_keeps_other_conversion_bits() {
+        let roman = 0x10;
+        let hiragana = CMODE_NATIVE | CMODE_FULLSHAPE;
+        let half_kana = CMODE_NATIVE | CMODE_KATAKANA;
+        assert_eq!(
+            with_kana_mode(hiragana | roman, half_kana),
+            half_kana | roman
+        );
+        assert_eq!(with_kana_mode(CMODE_FULLSHAPE, hiragana), hiragana);
+    }
+
+    #[test]
+    fn test_reconversion_target() {
+        assert_eq!(
+            reconversion_target_for(64, Some((3, 3))),
+            ReconversionTarget::Window
+        );
+        assert_eq!(
+            reconversion_target_for(0, Some((3, 3))),
+            ReconversionTarget::WordBeforeCaret
+        );
+        assert_eq!(
+            reconversion_target_for(0, Some((1, 3))),
+            ReconversionTarget::Selection
+        );
+        assert_eq!(
+            reconversion_target_for(0, None),
+            ReconversionTarget::Selection
+        );
+    }
+
+    #[test]
+    fn test_candidate_window_classes() {
+        assert!(is_candidate_window_class(
+            "Microsoft.IME.UIManager.CandidateWindow.Host"
+        ));
+        assert!(is_candidate_window_class(
+            "GoogleJapaneseInputCandidateWindow"
+        ));
+        assert!(!is_candidate_window_class("IME"));
+        assert!(is_composition_window_class("MSCTFIME Composition"));
+        assert!(!is_composition_window_class("MSCTFIME UI"));
+    }
+
+    #[test]
+    fn test_state_cache_expires_and_rejects_stale_stores() {
+        let mut cache = StateCache::new();
+        let t0 = Instant::now();
+        cache.store(cache.generation, ImeMode::Auto, t0, true);
+        assert_eq!(cache.get(ImeMode::Auto, t0), Some(true));
+        assert_eq!(cache.get(ImeMode::Imm, t0), None);
+        assert_eq!(cache.get(ImeMode::Auto, t0 + STATE_CACHE_TTL), None);
+
+        let generation = cache.generation;
+        cache.invalidate();
+        assert_eq!(cache.get(ImeMode::Auto, t0), None);
+        cache.store(generation, ImeMode::Auto, t0, false);
+        assert_eq!(cache.get(ImeMode::Auto, t0), None);
+    }
+
+    #[test]
+    fn test_app_override_matches_exe_name() {
+        let overrides = vec![AppImeOverride {
+            exe: "Game.EXE".to_string(),
+            mode: ImeMode::ForceAlpha,
+        }];
+        assert_eq!(
+            override_for(&overrides, Some("game.exe")),
+            Some(ImeMode::ForceAlpha)
+        );
+        assert_eq!(override_for(&overrides, Some("notepad.exe")), None);
+        assert_eq!(override_for(&overrides, None), None);
+    }
+}
@@ -0,0 +1,142 @@
+//! How SendInput fares. Windows refuses injected input without a sound,
+//! e.g. into an elevated window (UIPI) or onto the secure desktop, so
+//! injection counts the events it could not send and the watchdog
+//! reports them.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// SendInput counters, shared between the injecting threads and readers.
+#[derive(Debug)]
+pub struct InjectionStats {
+    sends: AtomicU64,
+    retried_sends: AtomicU64,
+    dropped_events: AtomicU64,
+    dropped_elevated: AtomicU64,
+    /// Events dropped and not reported yet.
+    unreported: AtomicU64,
+    /// Of `unreported`, those aimed at an elevated window.
+    unreported_elevated: AtomicU64,
+}
+
+/// Events dropped since the previous report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct DroppedInput {
+    pub events: u64,
+    /// Of `events`, those aimed at an elevated window.
+    pub elevated: u64,
+}
+
+/// Snapshot of [`InjectionStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct InjectionHealth {
+    pub sends: u64,
+    /// Sends that took more than one SendInput call.
+    pub retried_sends: u64,
+    /// Events given up on after the retries.
+    pub dropped_events: u64,
+    /// Of `dropped_events`, those aimed at an elevated window.
+    pub dropped_elevated: u64,
+}
+
+impl Default for InjectionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InjectionStats {
+    pub const fn new() -> Self {
+        Self {
+            sends: AtomicU64::new(0),
+            retried_sends: AtomicU64::new(0),
+            dropped_events: AtomicU64::new(0),
+            dropped_elevated: AtomicU64::new(0),
+            unreported: AtomicU64::new(0),
+            unreported_elevated: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one send of `attempts` SendInput calls, after which
+    /// `dropped` events were still not through.
+    pub fn record(&self, attempts: u32, dropped: usize, elevated: bool) {
+        self.sends.fetch_add(1, Ordering::Relaxed);
+        if attempts > 1 {
+            self.retried_sends.fetch_add(1, Ordering::Relaxed);
+        }
+        let dropped = dropped as u64;
+        if dropped > 0 {
+            self.dropped_events.fetch_add(dropped, Ordering::Relaxed);
+            self.unreported.fetch_add(dropped, Ordering::Relaxed);
+            if elevated {
+                self.dropped_elevated.fetch_add(dropped, Ordering::Relaxed);
+                self.unreported_elevated
+                    .fetch_add(dropped, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Events dropped since the last time this was asked, if any.
+    pub fn take_dropped(&self) -> Option<DroppedInput> {
+        match self.unreported.swap(0, Ordering::Relaxed) {
+            0 => None,
+            events => Some(DroppedInput {
+                events,
+                elevated: self
+                    .unreported_elevated
+                    .swap(0, Ordering::Relaxed)
+                    .min(events),
+            }),
+        }
+    }
+
+    pub fn snapshot(&self) -> InjectionHealth {
+        InjectionHealth {
+            sends: self.sends.load(Ordering::Relaxed),
+            retried_sends: self.retried_sends.load(Ordering::Relaxed),
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            dropped_elevated: self.dropped_elevated.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drops_are_counted_and_reported_once() {
+        let stats = InjectionStats::new();
+        stats.record(1, 0, false);
+        stats.record(2, 0, false);
+        assert_eq!(stats.take_dropped(), None);
+
+        stats.record(3, 4, false);
+        stats.record(1, 2, true);
+        assert_eq!(
+            stats.take_dropped(),
+            Some(DroppedInput {
+                events: 6,
+                elevated: 2
+            })
+        );
+        assert_eq!(stats.take_dropped(), None);
+        stats.record(1, 1, false);
+        assert_eq!(
+            stats.take_dropped(),
+            Some(DroppedInput {
+                events: 1,
+                elevated: 0
+            })
+        );
+        assert_eq!(
+            stats.snapshot(),
+            InjectionHealth {
+                sends: 5,
+                retried_sends: 2,
+                dropped_events: 7,
+                dropped_elevated: 2,
+            }
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::chord_engine::{ChordState, LatchState};
+use crate::trace::keys_label;
+use crate::types::ScKey;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+/// Live view of the engine's input state for the settings debug panel.
+/// Keys are shown by name, falling back to hex scancodes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EngineState {
+    pub enabled: bool,
+    pub pressed: Vec<String>,
+    pub pending: Vec<PendingKeyState>,
+    pub latch: LatchState,
+    pub prefix_pending: Option<String>,
+    pub used_modifiers: Vec<String>,
+    pub repeat_plans: Vec<RepeatPlanState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingKeyState {
+    pub key: String,
+    /// Time since the key went down.
+    pub age_ms: u64,
+    /// Time since the key was released, while it waits for a chord partner.
+    pub released_ms_ago: Option<u64>,
+}
+
+/// Keys typed by auto-repeat while `key` is held.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepeatPlanState {
+    pub key: String,
+    pub keys: Vec<String>,
+}
+
+impl EngineState {
+    pub fn capture(
+        enabled: bool,
+        state: &ChordState,
+        repeat_plans: &HashMap<ScKey, Vec<ScKey>>,
+        now: Instant,
+    ) -> Self {
+        let age = |t: Instant| now.saturating_duration_since(t).as_millis() as u64;
+        let mut repeat_plans: Vec<RepeatPlanState> = repeat_plans
+            .iter()
+            .map(|(key, keys)| RepeatPlanState {
+                key: keys_label(&[*key]),
+                keys: keys.iter().map(|k| keys_label(&[*k])).collect(),
+            })
+            .collect();
+        repeat_plans.sort_by(|a, b| a.key.cmp(&b.key));
+        Self {
+            enabled,
+            pressed: sorted_labels(&state.pressed),
+            pending: state
+                .pending
+                .iter()
+                .map(|p| PendingKeyState {
+                    key: keys_label(&[p.key]),
+                    age_ms: age(p.t_down),
+                    released_ms_ago: p.t_up.map(age),
+                })
+                .collect(),
+            latch: state.latch.clone(),
+            prefix_pending: state.prefix_pending.map(|k| keys_label(&[k])),
+            used_modifiers: sorted_labels(&state.used_modifiers),
+            repeat_plans,
+        }
+    }
+}
+
+fn sorted_labels(keys: &HashSet<ScKey>) -> Vec<String> {
+    let mut keys: Vec<ScKey> = keys.iter().copied().collect();
+    keys.sort_by_key(|k| (k.ext, k.sc));
+    keys.iter().map(|k| keys_label(&[*k])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chord_engine::PendingKey;
+    use std::time::Duration;
+
+    #[test]
+    fn test_capture_reports_pending_ages_and_sorted_keys() {
+        let now = Instant::now();
+        let a = ScKey::new(0x1E, false);
+        let space = ScKey::new(0x39, false);
+        let mut state = ChordState::default();
+        state.pressed.extend([space, a]);
+        state.pending.push(PendingKey {
+            key: a,
+            t_down: now - Duration::from_millis(40),
+            t_up: Some(now - Duration::from_millis(10)),
+        });
+        state.latch = LatchState::OneShot("<k>".to_string());
+
+        let view = EngineState::capture(true, &state, &HashMap::new(), now);
+        assert_eq!(view.pressed, vec![keys_label(&[a]), keys_label(&[space])]);
+        assert_eq!(view.pending[0].age_ms, 40);
+        assert_eq!(view.pending[0].released_ms_ago, Some(10));
+        assert_eq!(
+            serde_json::to_value(&view.latch).unwrap(),
+            serde_json::json!({ "kind": "OneShot", "plane": "<k>" })
+        );
+    }
+}
@@ -1,4 +1,8 @@
+use crate::chord_engine::{
+    EXTENDED_KEY_1_SC, EXTENDED_KEY_2_SC, EXTENDED_KEY_3_SC, EXTENDED_KEY_4_SC,
+};
 use crate::types::{Rc, ScKey};
+use serde::Serialize;
 
 /// Maps Scancode to (Row, Col) for standard JIS layout.
 /// Based on the request specification.
@@ -59,11 +63,54 @@ pub const JIS_SC_TO_RC: &[(ScKey, Rc)] = &[
     (ScKey::new(0x34, false), Rc::new(3, 8)),  // . / >
     (ScKey::new(0x35, false), Rc::new(3, 9)),  // / / ?
     (ScKey::new(0x73, false), Rc::new(3, 10)), // \ / _ (JIS Backslash/Ro, usually next to right shift)
+    // Row 4: Numpad (16 keys)
+    (ScKey::new(0x52, false), Rc::new(4, 0)),  // テン0
+    (ScKey::new(0x4F, false), Rc::new(4, 1)),  // テン1
+    (ScKey::new(0x50, false), Rc::new(4, 2)),  // テン2
+    (ScKey::new(0x51, false), Rc::new(4, 3)),  // テン3
+    (ScKey::new(0x4B, false), Rc::new(4, 4)),  // テン4
+    (ScKey::new(0x4C, false), Rc::new(4, 5)),  // テン5
+    (ScKey::new(0x4D, false), Rc::new(4, 6)),  // テン6
+    (ScKey::new(0x47, false), Rc::new(4, 7)),  // テン7
+    (ScKey::new(0x48, false), Rc::new(4, 8)),  // テン8
+    (ScKey::new(0x49, false), Rc::new(4, 9)),  // テン9
+    (ScKey::new(0x53, false), Rc::new(4, 10)), // テン.
+    (ScKey::new(0x35, true), Rc::new(4, 11)),  // テン/
+    (ScKey::new(0x37, false), Rc::new(4, 12)), // テン*
+    (ScKey::new(0x4A, false), Rc::new(4, 13)), // テン-
+    (ScKey::new(0x4E, false), Rc::new(4, 14)), // テン+
+    (ScKey::new(0x1C, true), Rc::new(4, 15)),  // テンEnter
+    // Row 5: Arrows and navigation cluster (10 keys)
+    (ScKey::new(0x48, true), Rc::new(5, 0)), // 上
+    (ScKey::new(0x50, true), Rc::new(5, 1)), // 下
+    (ScKey::new(0x4B, true), Rc::new(5, 2)), // 左
+    (ScKey::new(0x4D, true), Rc::new(5, 3)), // 右
+    (ScKey::new(0x52, true), Rc::new(5, 4)), // Insert
+    (ScKey::new(0x53, true), Rc::new(5, 5)), // Delete
+    (ScKey::new(0x47, true), Rc::new(5, 6)), // Home
+    (ScKey::new(0x4F, true), Rc::new(5, 7)), // End
+    (ScKey::new(0x49, true), Rc::new(5, 8)), // PageUp
+    (ScKey::new(0x51, true), Rc::new(5, 9)), // PageDown
 ];
 
 pub fn key_to_rc(key: ScKey) -> Option<Rc> {
     if key.ext {
-        return None;
+        return match key.sc {
+            0x35 => Some(Rc::new(4, 11)),
+            0x1C => Some(Rc::new(4, 15)),
+
+            0x48 => Some(Rc::new(5, 0)),
+            0x50 => Some(Rc::new(5, 1)),
+            0x4B => Some(Rc::new(5, 2)),
+            0x4D => Some(Rc::new(5, 3)),
+            0x52 => Some(Rc::new(5, 4)),
+            0x53 => Some(Rc::new(5, 5)),
+            0x47 => Some(Rc::new(5, 6)),
+            0x4F => Some(Rc::new(5, 7)),
+            0x49 => Some(Rc::new(5, 8)),
+            0x51 => Some(Rc::new(5, 9)),
+            _ => None,
+        };
     }
 
     match key.sc {
@@ -118,6 +165,21 @@ pub fn key_to_rc(key: ScKey) -> Option<Rc> {
         0x34 => Some(Rc::new(3, 8)),
         0x35 => Some(Rc::new(3, 9)),
         0x73 => Some(Rc::new(3, 10)),
+
+        0x52 => Some(Rc::new(4, 0)),
+        0x4F => Some(Rc::new(4, 1)),
+        0x50 => Some(Rc::new(4, 2)),
+        0x51 => Some(Rc::new(4, 3)),
+        0x4B => Some(Rc::new(4, 4)),
+        0x4C => Some(Rc::new(4, 5)),
+        0x4D => Some(Rc::new(4, 6)),
+        0x47 => Some(Rc::new(4, 7)),
+        0x48 => Some(Rc::new(4, 8)),
+        0x49 => Some(Rc::new(4, 9)),
+        0x53 => Some(Rc::new(4, 10)),
+        0x37 => Some(Rc::new(4, 12)),
+        0x4A => Some(Rc::new(4, 13)),
+        0x4E => Some(Rc::new(4, 14)),
         _ => None,
     }
 }
@@ -179,9 +241,63 @@ pub fn sc_to_key_name(sc: u16) -> Option<&'static str> {
         0x39 => Some("space"),
         0x79 => Some("henkan"),
         0x7B => Some("muhenkan"),
+
+        0x52 => Some("テン0"),
+        0x4F => Some("テン1"),
+        0x50 => Some("テン2"),
+        0x51 => Some("テン3"),
+        0x4B => Some("テン4"),
+        0x4C => Some("テン5"),
+        0x4D => Some("テン6"),
+        0x47 => Some("テン7"),
+        0x48 => Some("テン8"),
+        0x49 => Some("テン9"),
+        0x53 => Some("テン."),
+        0x37 => Some("テン*"),
+        0x4A => Some("テン-"),
+        0x4E => Some("テン+"),
         _ => None,
     }
 }
+
+/// Name of an extended (E0-prefixed) key.
+fn ext_key_name(sc: u16) -> Option<&'static str> {
+    match sc {
+        0x35 => Some("テン/"),
+        0x1C => Some("テンEnter"),
+        0x48 => Some("上"),
+        0x50 => Some("下"),
+        0x4B => Some("左"),
+        0x4D => Some("右"),
+        0x52 => Some("Insert"),
+        0x53 => Some("Delete"),
+        0x47 => Some("Home"),
+        0x4F => Some("End"),
+        0x49 => Some("PageUp"),
+        0x51 => Some("PageDown"),
+        _ => None,
+    }
+}
+
+/// Layout name of a key, including numpad and extended keys.
+pub fn key_name(key: ScKey) -> Option<&'static str> {
+    if key.ext {
+        ext_key_name(key.sc)
+    } else {
+        sc_to_key_name(key.sc)
+    }
+}
+
+/// Reverse of [`key_name`].
+pub fn key_name_to_key(name: &str) -> Option<ScKey> {
+    if let Some(sc) = key_name_to_sc(name) {
+        return Some(ScKey::new(sc, false));
+    }
+    (0..256u16)
+        .find(|&sc| ext_key_name(sc) == Some(name))
+        .map(|sc| ScKey::new(sc, true))
+}
+
 pub fn key_name_to_sc(name: &str) -> Option<u16> {
     // Brute-force reverse search for MVP (map is small)
     for sc in 0..256 {
@@ -193,3 +309,167 @@ pub fn key_name_to_sc(name: &str) -> Option<u16> {
     }
     None
 }
+
+/// `[機能キー]` names, as thumb key and pass-through settings use them too.
+/// A key's first entry is the name the UI shows for it.
+const FUNCTION_KEY_NAMES: &[(&str, ScKey)] = &[
+    ("Esc", ScKey::new(0x01, false)),
+    ("Tab", ScKey::new(0x0F, false)),
+    ("無変換", ScKey::new(0x7B, false)),
+    ("Space", ScKey::new(0x39, false)),
+    ("変換", ScKey::new(0x79, false)),
+    ("Enter", ScKey::new(0x1C, false)),
+    ("BackSpace", ScKey::new(0x0E, false)),
+    ("Delete", ScKey::new(0x53, true)),
+    ("Insert", ScKey::new(0x52, true)),
+    ("左Shift", ScKey::new(0x2A, false)),
+    ("右Shift", ScKey::new(0x36, false)),
+    ("左Ctrl", ScKey::new(0x1D, false)),
+    ("右Ctrl", ScKey::new(0x1D, true)),
+    ("左Alt", ScKey::new(0x38, false)),
+    ("右Alt", ScKey::new(0x38, true)),
+    ("CapsLock/英数", ScKey::new(0x3A, false)),
+    ("CapsLock", ScKey::new(0x3A, false)),
+    ("半角/全角", ScKey::new(0x29, false)),
+    ("カタカナ/ひらがな", ScKey::new(0x70, false)),
+    ("左Win", ScKey::new(0x5B, true)),
+    ("右Win", ScKey::new(0x5C, true)),
+    ("Applications", ScKey::new(0x5D, true)),
+    ("上", ScKey::new(0x48, true)),
+    ("左", ScKey::new(0x4B, true)),
+    ("右", ScKey::new(0x4D, true)),
+    ("下", ScKey::new(0x50, true)),
+    ("Home", ScKey::new(0x47, true)),
+    ("End", ScKey::new(0x4F, true)),
+    ("PageUp", ScKey::new(0x49, true)),
+    ("PageDown", ScKey::new(0x51, true)),
+    ("Mute", ScKey::new(0x20, true)),
+    ("VolumeDown", ScKey::new(0x2E, true)),
+    ("VolumeUp", ScKey::new(0x30, true)),
+    ("MediaPlayPause", ScKey::new(0x22, true)),
+    ("MediaStop", ScKey::new(0x24, true)),
+    ("MediaPrev", ScKey::new(0x10, true)),
+    ("MediaNext", ScKey::new(0x19, true)),
+    ("拡張1", ScKey::new(EXTENDED_KEY_1_SC, false)),
+    ("拡張2", ScKey::new(EXTENDED_KEY_2_SC, false)),
+    ("拡張3", ScKey::new(EXTENDED_KEY_3_SC, false)),
+    ("拡張4", ScKey::new(EXTENDED_KEY_4_SC, false)),
+    ("F1", ScKey::new(0x3B, false)),
+    ("F2", ScKey::new(0x3C, false)),
+    ("F3", ScKey::new(0x3D, false)),
+    ("F4", ScKey::new(0x3E, false)),
+    ("F5", ScKey::new(0x3F, false)),
+    ("F6", ScKey::new(0x40, false)),
+    ("F7", ScKey::new(0x41, false)),
+    ("F8", ScKey::new(0x42, false)),
+    ("F9", ScKey::new(0x43, false)),
+    ("F10", ScKey::new(0x44, false)),
+    ("F11", ScKey::new(0x57, false)),
+    ("F12", ScKey::new(0x58, false)),
+    ("F13", ScKey::new(0x64, false)),
+    ("F14", ScKey::new(0x65, false)),
+    ("F15", ScKey::new(0x66, false)),
+    ("F16", ScKey::new(0x67, false)),
+    ("F17", ScKey::new(0x68, false)),
+    ("F18", ScKey::new(0x69, false)),
+    ("F19", ScKey::new(0x6A, false)),
+    ("F20", ScKey::new(0x6B, false)),
+    ("F21", ScKey::new(0x6C, false)),
+    ("F22", ScKey::new(0x6D, false)),
+    ("F23", ScKey::new(0x6E, false)),
+    ("F24", ScKey::new(0x76, false)),
+];
+
+/// Key with the `[機能キー]` name `name`.
+pub fn function_key(name: &str) -> Option<ScKey> {
+    FUNCTION_KEY_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(_, key)| key)
+}
+
+/// Reverse of [`function_key`], giving the name shown for the key.
+pub fn function_key_name(key: ScKey) -> Option<&'static str> {
+    FUNCTION_KEY_NAMES
+        .iter()
+        .find(|(_, k)| *k == key)
+        .map(|&(name, _)| name)
+}
+
+/// Key by its `[機能キー]` name or its layout name.
+pub fn key_from_name(name: &str) -> Option<ScKey> {
+    function_key(name).or_else(|| key_name_to_key(name))
+}
+
+/// Name to show for a key: its `[機能キー]` name, else its layout name,
+/// else its scancode in hex (`7Be` for an extended key).
+pub fn display_name(key: ScKey) -> String {
+    match function_key_name(key).or_else(|| key_name(key)) {
+        Some(name) => name.to_string(),
+        None => format!("{:02X}{}", key.sc, if key.ext { "e" } else { "" }),
+    }
+}
+
+/// A key and its names, for the UI.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyNameInfo {
+    pub sc: u16,
+    pub ext: bool,
+    /// Name in .yab cells and `<key>` tags.
+    pub layout_name: Option<&'static str>,
+    /// `[機能キー]` name.
+    pub function_name: Option<&'static str>,
+    pub display: String,
+}
+
+impl KeyNameInfo {
+    pub fn new(key: ScKey) -> Self {
+        Self {
+            sc: key.sc,
+            ext: key.ext,
+            layout_name: key_name(key),
+            function_name: function_key_name(key),
+            display: display_name(key),
+        }
+    }
+}
+
+/// Every key with a layout or `[機能キー]` name, layout keys first.
+pub fn key_names() -> Vec<KeyNameInfo> {
+    let mut keys: Vec<ScKey> = Vec::new();
+    let layout_keys = [false, true]
+        .into_iter()
+        .flat_map(|ext| (0..256u16).map(move |sc| ScKey::new(sc, ext)))
+        .filter(|&key| key_name(key).is_some());
+    for key in layout_keys.chain(FUNCTION_KEY_NAMES.iter().map(|&(_, key)| key)) {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys.into_iter().map(KeyNameInfo::new).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_names_round_trip() {
+        let muhenkan = ScKey::new(0x7B, false);
+        assert_eq!(key_from_name("無変換"), Some(muhenkan));
+        assert_eq!(key_from_name("muhenkan"), Some(muhenkan));
+        assert_eq!(display_name(muhenkan), "無変換");
+        assert_eq!(key_from_name("CapsLock"), key_from_name("CapsLock/英数"));
+        assert_eq!(display_name(ScKey::new(0x3A, false)), "CapsLock/英数");
+        assert_eq!(display_name(ScKey::new(0x1E, false)), "a");
+        assert_eq!(display_name(ScKey::new(0xF0, true)), "F0e");
+
+        let names = key_names();
+        for info in &names {
+            let key = ScKey::new(info.sc, info.ext);
+            assert_eq!(key_from_name(&info.display), Some(key), "{}", info.display);
+        }
+        let unique: std::collections::HashSet<_> = names.iter().map(|i| &i.display).collect();
+        assert_eq!(unique.len(), names.len());
+    }
+}
@@ -0,0 +1,258 @@
+//! `[機能キー]` swaps, from the layout and the profile, and what is wrong
+//! with them.
+//!
+//! Swaps chain: when a acts as b and b acts as c, a acts as c. A chain
+//! ends at a key with no swap, at Capsロック / かなロック, or right before
+//! it would come back to a key it went through, so every key of a cycle
+//! acts as itself. A key swapped twice takes the later swap: the profile's
+//! over the layout's, and within a layout the including file's over an
+//! included one's.
+//!
+//! `Shift+CapsLock/英数, 英数` only swaps presses made with Shift held.
+//! Such a swap wins over the plain swap of the same key from the same
+//! table, whichever comes first; chains keep the Shift state they start
+//! with.
+
+use crate::chord_engine::KeySwapCfg;
+use crate::jis_map::{display_name, function_key};
+use crate::types::ScKey;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// In front of a swap's key, limits the swap to presses with Shift held.
+const SHIFT_PREFIX: &str = "Shift+";
+
+/// Swaps by key and whether Shift is held.
+pub(crate) type SwapMap = HashMap<(ScKey, bool), FunctionKeySwapTarget>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FunctionKeySwapTarget {
+    Key(ScKey),
+    CapsLock,
+    KanaLock,
+}
+
+/// A swap that does not work as written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind")]
+pub enum KeySwapIssue {
+    /// `name` is no `[機能キー]` name; the swap is ignored.
+    UnknownKey { name: String },
+    /// Capsロック / かなロック only work as the key swapped into.
+    LockAsSource { name: String },
+    /// `from` is swapped twice in the same table; `used` wins.
+    Conflict {
+        from: String,
+        ignored: String,
+        used: String,
+    },
+    /// These keys swap into each other in a circle, so each acts as itself.
+    /// `shift` is set for a circle only presses with Shift held go round.
+    Cycle { keys: Vec<String>, shift: bool },
+}
+
+fn parse_target(name: &str) -> Option<FunctionKeySwapTarget> {
+    match name {
+        "Capsロック" => Some(FunctionKeySwapTarget::CapsLock),
+        "かなロック" => Some(FunctionKeySwapTarget::KanaLock),
+        _ => function_key(name).map(FunctionKeySwapTarget::Key),
+    }
+}
+
+/// Adds one table of swaps to `map`, replacing swaps of the same keys.
+fn add_swaps<'a>(
+    map: &mut SwapMap,
+    issues: &mut Vec<KeySwapIssue>,
+    swaps: impl IntoIterator<Item = (&'a str, &'a str)>,
+) {
+    let mut plain = HashMap::new();
+    let mut shifted = HashMap::new();
+    for (from, to) in swaps {
+        let (added, name) = match from.strip_prefix(SHIFT_PREFIX) {
+            Some(name) => (&mut shifted, name),
+            None => (&mut plain, from),
+        };
+        let parsed = match (parse_target(name), parse_target(to)) {
+            (Some(FunctionKeySwapTarget::Key(source)), Some(target)) => Ok((source, target)),
+            (None, _) => Err(KeySwapIssue::UnknownKey {
+                name: name.to_string(),
+            }),
+            (_, None) => Err(KeySwapIssue::UnknownKey {
+                name: to.to_string(),
+            }),
+            (Some(_), Some(_)) => Err(KeySwapIssue::LockAsSource {
+                name: name.to_string(),
+            }),
+        };
+        let (source, target) = match parsed {
+            Ok(swap) => swap,
+            Err(issue) => {
+                issues.push(issue);
+                continue;
+            }
+        };
+        if let Some((previous, previous_target)) = added.insert(source, (to, target)) {
+            if previous_target != target {
+                issues.push(KeySwapIssue::Conflict {
+                    from: from.to_string(),
+                    ignored: previous.to_string(),
+                    used: to.to_string(),
+                });
+            }
+        }
+    }
+    for (source, (_, target)) in plain {
+        map.insert((source, false), target);
+        if !shifted.contains_key(&source) {
+            map.insert((source, true), target);
+        }
+    }
+    for (source, (_, target)) in shifted {
+        map.insert((source, true), target);
+    }
+}
+
+/// Cycles of two or more keys in `map` with Shift held or not, each once,
+/// starting from its lowest key.
+fn find_cycles(map: &SwapMap, shift: bool) -> Vec<Vec<ScKey>> {
+    let order = |key: &ScKey| (key.ext, key.sc);
+    let mut starts: Vec<ScKey> = map
+        .keys()
+        .filter(|(_, s)| *s == shift)
+        .map(|&(key, _)| key)
+        .collect();
+    starts.sort_by_key(order);
+    let mut cycles = Vec::new();
+    for start in starts {
+        let mut chain = vec![start];
+        let mut current = start;
+        while let Some(&FunctionKeySwapTarget::Key(next)) = map.get(&(current, shift)) {
+            if next == start {
+                // A key swapped into itself is left alone on purpose.
+                if chain.len() > 1 && chain.iter().all(|key| order(key) >= order(&start)) {
+                    cycles.push(chain);
+                }
+                break;
+            }
+            if chain.contains(&next) {
+                break;
+            }
+            chain.push(next);
+            current = next;
+        }
+    }
+    cycles
+}
+
+/// Swap map from the layout's `[機能キー]` entries and the profile's swaps,
+/// with what is wrong with them.
+pub(crate) fn build_function_key_swap_map(
+    layout: &[(String, String)],
+    profile: &[KeySwapCfg],
+) -> (SwapMap, Vec<KeySwapIssue>) {
+    let mut map = HashMap::new();
+    let mut issues = Vec::new();
+    let layout = layout.iter().map(|(from, to)| (from.as_str(), to.as_str()));
+    add_swaps(&mut map, &mut issues, layout);
+    let profile = profile
+        .iter()
+        .map(|swap| (swap.from.as_str(), swap.to.as_str()));
+    add_swaps(&mut map, &mut issues, profile);
+    let plain = find_cycles(&map, false);
+    let shifted: Vec<_> = find_cycles(&map, true)
+        .into_iter()
+        .filter(|cycle| !plain.contains(cycle))
+        .collect();
+    let cycles = plain
+        .into_iter()
+        .map(|keys| (keys, false))
+        .chain(shifted.into_iter().map(|keys| (keys, true)));
+    issues.extend(cycles.map(|(keys, shift)| KeySwapIssue::Cycle {
+        keys: keys.into_iter().map(display_name).collect(),
+        shift,
+    }));
+    (map, issues)
+}
+
+/// True if some key is swapped differently with Shift held.
+pub(crate) fn has_shift_swaps(map: &SwapMap) -> bool {
+    map.iter()
+        .any(|(&(key, shift), target)| shift && map.get(&(key, false)) != Some(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swaps(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_swap_issues() {
+        let layout = swaps(&[
+            ("無変換", "変換"),
+            ("変換", "無変換"),
+            ("Esc", "F1"),
+            ("Esc", "F2"),
+            ("かなロック", "Esc"),
+            ("F99", "Esc"),
+            ("Tab", "Capsロック"),
+        ]);
+        let profile = [KeySwapCfg {
+            from: "Esc".to_string(),
+            to: "F3".to_string(),
+        }];
+        let (map, issues) = build_function_key_swap_map(&layout, &profile);
+
+        assert_eq!(
+            map.get(&(ScKey::new(0x01, false), true)),
+            Some(&FunctionKeySwapTarget::Key(ScKey::new(0x3D, false)))
+        );
+        assert_eq!(
+            map.get(&(ScKey::new(0x0F, false), false)),
+            Some(&FunctionKeySwapTarget::CapsLock)
+        );
+        assert!(!has_shift_swaps(&map));
+        assert_eq!(
+            issues,
+            vec![
+                KeySwapIssue::Conflict {
+                    from: "Esc".to_string(),
+                    ignored: "F1".to_string(),
+                    used: "F2".to_string(),
+                },
+                KeySwapIssue::LockAsSource {
+                    name: "かなロック".to_string(),
+                },
+                KeySwapIssue::UnknownKey {
+                    name: "F99".to_string(),
+                },
+                KeySwapIssue::Cycle {
+                    keys: vec!["変換".to_string(), "無変換".to_string()],
+                    shift: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shift_swaps_win_over_plain_swaps() {
+        let layout = swaps(&[("Shift+CapsLock/英数", "CapsLock"), ("CapsLock", "左Ctrl")]);
+        let (map, issues) = build_function_key_swap_map(&layout, &[]);
+        let caps = ScKey::new(0x3A, false);
+        assert_eq!(
+            map.get(&(caps, false)),
+            Some(&FunctionKeySwapTarget::Key(ScKey::new(0x1D, false)))
+        );
+        assert_eq!(
+            map.get(&(caps, true)),
+            Some(&FunctionKeySwapTarget::Key(caps))
+        );
+        assert!(has_shift_swaps(&map));
+        assert!(issues.is_empty());
+    }
+}
@@ -1,17 +1,32 @@
-use crate::engine::ENGINE;
-use crate::types::InputEvent;
+use crate::chord_engine::{CompositionPolicy, SuspendKey, ThumbKeySelect};
+use crate::dynamic_text::DynamicText;
+use crate::engine::{coalesce_actions, lock_toggle_events, ENGINE};
+use crate::foreground::foreground_exe_name;
+use crate::handle::{self, EngineEvent};
+use crate::hook_health::{low_level_hooks_timeout_ms, HookHealth, HookTimings};
+use crate::ime::ReconversionTarget;
+use crate::injection_health::{DroppedInput, InjectionHealth, InjectionStats};
+use crate::jis_map::{display_name, key_name};
+use crate::scripting;
 use crate::types::KeyAction;
-use crossbeam_channel::{Receiver, Sender, TrySendError};
+use crate::types::ScKey;
+use crate::types::{HeldModifier, ModifierPlane, RawKeyEvent, ShiftSide};
+use crate::types::{ImeCommand, InputEvent};
+use crate::types::{LockKey, LockState};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Mutex;
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 use std::thread;
-use std::time::Duration;
-use tracing::{error, info, warn};
-use windows::Win32::Foundation::{HINSTANCE, LPARAM, LRESULT, WPARAM};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM};
 use windows::Win32::System::SystemInformation::GetTickCount;
 use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::Accessibility::{
+    SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK, WINEVENTPROC,
+};
 use windows::Win32::UI::Input::KeyboardAndMouse::{
     GetAsyncKeyState,
     GetLastInputInfo,
@@ -20,6 +35,7 @@ use windows::Win32::UI::Input::KeyboardAndMouse::{
     INPUT_0,
     INPUT_KEYBOARD,
     KEYBDINPUT,
+    KEYBD_EVENT_FLAGS,
     KEYEVENTF_EXTENDEDKEY,
     KEYEVENTF_KEYUP,
     KEYEVENTF_SCANCODE,
@@ -41,39 +57,350 @@ use windows::Win32::UI::Input::KeyboardAndMouse::{
 };
 use windows::Win32::UI::WindowsAndMessaging::{
     CallNextHookEx, DispatchMessageW, GetMessageW, PeekMessageW, PostThreadMessageW,
-    SetWindowsHookExW, TranslateMessage, UnhookWindowsHookEx, HHOOK, KBDLLHOOKSTRUCT,
-    LLKHF_ALTDOWN, MSG, WH_KEYBOARD_LL, WM_APP, WM_KEYUP, WM_SYSKEYUP,
+    SetWindowsHookExW, TranslateMessage, UnhookWindowsHookEx, EVENT_OBJECT_FOCUS,
+    EVENT_OBJECT_IME_CHANGE, EVENT_OBJECT_IME_HIDE, EVENT_OBJECT_IME_SHOW,
+    EVENT_SYSTEM_DESKTOPSWITCH, EVENT_SYSTEM_FOREGROUND, HHOOK, KBDLLHOOKSTRUCT, LLKHF_ALTDOWN,
+    LLKHF_INJECTED, MSG, WH_KEYBOARD_LL, WINEVENT_OUTOFCONTEXT, WINEVENT_SKIPOWNPROCESS, WM_APP,
+    WM_KEYUP, WM_SYSKEYUP,
 };
 /// Magic number to identify our own injected events.
 const INJECTED_EXTRA_INFO: usize = 0xFFC3C3C3;
+/// How long the OS gets to follow a lock toggle whose injection dropped
+/// events.
+const LOCK_TOGGLE_TIMEOUT_MS: u64 = 50;
+const LOCK_TOGGLE_POLL_MS: u64 = 5;
+/// SendInput calls made for one send before the events left are dropped.
+const SEND_INPUT_ATTEMPTS: u32 = 3;
+const SEND_INPUT_RETRY_DELAY_MS: u64 = 5;
 
 static HOOK_HANDLE: Mutex<Option<HHOOK>> = Mutex::new(None);
+static WIN_EVENT_HOOKS: Mutex<Vec<HWINEVENTHOOK>> = Mutex::new(Vec::new());
 static HOOK_WORKER_STARTED: AtomicBool = AtomicBool::new(false);
 static HOOK_WATCHDOG_STARTED: AtomicBool = AtomicBool::new(false);
 static HOOK_THREAD_ID: AtomicU32 = AtomicU32::new(0);
 static LAST_HOOK_MS: AtomicU64 = AtomicU64::new(0);
+/// Last key event typed on a keyboard, not injected by any program.
+static LAST_TYPED_MS: AtomicU64 = AtomicU64::new(0);
+/// Set when the next key typed on a keyboard runs the activity callback.
+static WAKE_ON_TYPING: AtomicBool = AtomicBool::new(false);
+static ON_ACTIVITY: Mutex<Option<ActivityCallback>> = Mutex::new(None);
 static LAST_REINSTALL_MS: AtomicU64 = AtomicU64::new(0);
+/// When the unanswered heartbeat message was posted (0 = none pending).
+static HEARTBEAT_SENT_MS: AtomicU64 = AtomicU64::new(0);
+static ON_HOOK_RECOVERED: Mutex<Option<HookRecoveredCallback>> = Mutex::new(None);
+static KEY_CAPTURE_ARMED: AtomicBool = AtomicBool::new(false);
+static KEY_CAPTURE: Mutex<Option<KeyCaptureCallback>> = Mutex::new(None);
+/// Captured key whose release is still to be swallowed, as [`capture_id`].
+static CAPTURED_KEY_UP: AtomicU32 = AtomicU32::new(0);
+/// Set while any hotkey is registered, to skip the lookup otherwise.
+static HOTKEYS_ACTIVE: AtomicBool = AtomicBool::new(false);
+static HOTKEYS: Mutex<Vec<(String, Hotkey)>> = Mutex::new(Vec::new());
+static ON_HOTKEY: Mutex<Option<HotkeyCallback>> = Mutex::new(None);
+/// VK of the hotkey still held down; its repeats and release are swallowed.
+static HOTKEY_KEY_UP: AtomicU32 = AtomicU32::new(0);
+/// Suspend key as [`capture_id`], or 0 when there is none.
+static SUSPEND_KEY_ID: AtomicU32 = AtomicU32::new(0);
+/// Modifiers held with the suspend key, as [`modifier_mask`] bits.
+static SUSPEND_MODIFIERS: AtomicU8 = AtomicU8::new(0);
+/// Suspend key still held down, as [`capture_id`]; its repeats and release
+/// are swallowed.
+static SUSPEND_KEY_UP: AtomicU32 = AtomicU32::new(0);
+/// VK of the panic key (with Ctrl+Alt), or 0 when disabled.
+static PANIC_KEY_VK: AtomicU32 = AtomicU32::new(0x7B);
+static PANIC_HOLD_MS: AtomicU64 = AtomicU64::new(2000);
+/// When the panic combo went down, 0 when it is not held.
+static PANIC_KEY_DOWN_MS: AtomicU64 = AtomicU64::new(0);
+/// Set by the panic key. The hook passes keys straight through while set.
+static FORCE_PASS_THROUGH: AtomicBool = AtomicBool::new(false);
+/// A dual-role key holds its modifier; keys still go to the engine.
+static DUAL_ROLE_ACTIVE: AtomicBool = AtomicBool::new(false);
+static INJECTED_MODIFIERS: InjectedModifiers = InjectedModifiers::new();
+static EXIT_GUARD_INSTALLED: AtomicBool = AtomicBool::new(false);
 static ALT_NEEDS_HANDLING: AtomicBool = AtomicBool::new(false);
+/// Set while Shift is a chord key (pinky shift); its events then go to the engine.
+static SHIFT_NEEDS_HANDLING: AtomicBool = AtomicBool::new(false);
+/// `ModifierPlane` bits of the layout's `<Ctrl>`/`<Alt>`/`<Win>` planes.
+static MODIFIER_PLANES: AtomicU8 = AtomicU8::new(0);
 static START_INSTANT: OnceLock<std::time::Instant> = OnceLock::new();
+/// `Profile::injection`: key events per SendInput call (0 = all) and the
+/// pause between calls.
+static INJECT_CHUNK_SIZE: AtomicUsize = AtomicUsize::new(0);
+static INJECT_CHUNK_DELAY_MS: AtomicU64 = AtomicU64::new(0);
+static HOOK_TIMINGS: HookTimings = HookTimings::new();
+static ON_HOOK_SLOW: Mutex<Option<HookSlowCallback>> = Mutex::new(None);
+static INJECTION_STATS: InjectionStats = InjectionStats::new();
+static ON_INJECTION_FAILED: Mutex<Option<InjectionFailedCallback>> = Mutex::new(None);
+/// Set in fast-path mode: the hook only queues keys, the worker decides.
+static FAST_PATH: AtomicBool = AtomicBool::new(false);
+/// [`HeldKeys`] as followed by the hook from the modifier events it sees.
+static HELD_KEYS: AtomicU8 = AtomicU8::new(0);
 
 const HOOK_QUEUE_SIZE: usize = 1024;
 const WATCHDOG_INTERVAL_MS: u64 = 1000;
 const HOOK_STALL_MS: u64 = 5000;
 const INPUT_RECENT_MS: u64 = 2000;
 const REINSTALL_BACKOFF_MS: u64 = 10000;
+const HEARTBEAT_IDLE_MS: u64 = 30000;
+const HEARTBEAT_TIMEOUT_MS: u64 = 2000;
 const WM_HOOK_REINSTALL: u32 = WM_APP + 0x4B10;
+/// Heartbeat posted to the hook thread, answered by its message loop.
+const WM_HOOK_HEARTBEAT: u32 = WM_APP + 0x4B11;
+/// Longest wait for the IME to commit its composition under
+/// `CompositionPolicy::Defer`.
+const COMPOSITION_DEFER_MS: u64 = 300;
+/// `PANIC_KEY_DOWN_MS` value once the combo has fired for the current hold.
+const PANIC_TRIGGERED: u64 = u64::MAX;
+/// How long the panic key waits for the engine before reporting it busy.
+const PANIC_ENGINE_LOCK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Modifier keys (sc, ext) tracked in `INJECTED_MODIFIERS`, one bit each.
+const TRACKED_MODIFIERS: [(u16, bool); 8] = [
+    (0x2A, false), // LShift
+    (0x36, false), // RShift
+    (0x1D, false), // LCtrl
+    (0x1D, true),  // RCtrl
+    (0x38, false), // LAlt
+    (0x38, true),  // RAlt
+    (0x5B, true),  // LWin
+    (0x5C, true),  // RWin
+];
+
+/// Why the watchdog re-installed the hook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum HookRecoveryReason {
+    /// Input arrived but the hook stopped seeing it.
+    Stalled,
+    /// The hook thread's message loop did not answer the idle heartbeat,
+    /// so Windows may have removed the hook for timing out.
+    HeartbeatMissed,
+}
+
+impl HookRecoveryReason {
+    fn to_wparam(self) -> WPARAM {
+        WPARAM(self as usize)
+    }
+
+    fn from_wparam(wparam: WPARAM) -> Self {
+        if wparam.0 == HookRecoveryReason::HeartbeatMissed as usize {
+            HookRecoveryReason::HeartbeatMissed
+        } else {
+            HookRecoveryReason::Stalled
+        }
+    }
+}
+
+type HookRecoveredCallback = Box<dyn Fn(HookRecoveryReason) + Send>;
+type HookSlowCallback = Box<dyn Fn(HookHealth) + Send>;
+type InjectionFailedCallback = Box<dyn Fn(DroppedInput) + Send>;
+type ActivityCallback = Box<dyn Fn() + Send>;
+type KeyCaptureCallback = Box<dyn FnOnce(CapturedKey) + Send>;
+type HotkeyCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Key combination the hook handles itself instead of typing it. The
+/// modifiers must match exactly; a `vk` of 0 never matches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Hotkey {
+    pub vk: u32,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+}
+
+/// Id of the registered hotkey equal to `pressed`.
+fn find_hotkey<'a>(hotkeys: &'a [(String, Hotkey)], pressed: &Hotkey) -> Option<&'a str> {
+    hotkeys
+        .iter()
+        .find(|(_, hotkey)| hotkey.vk != 0 && hotkey == pressed)
+        .map(|(id, _)| id.as_str())
+}
+
+/// Physical key reported by [`capture_next_key`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct CapturedKey {
+    pub sc: u16,
+    pub ext: bool,
+    pub vk: u32,
+    /// Layout name of the key (as used in .yab files), if it has one.
+    pub name: Option<String>,
+    /// Name to show for the key.
+    pub display: String,
+    /// Matching thumb key choice, if the key can be one.
+    pub thumb_key: Option<ThumbKeySelect>,
+}
+
+impl CapturedKey {
+    fn new(sc: u16, ext: bool, vk: u32) -> Self {
+        let key = ScKey::new(sc, ext);
+        Self {
+            sc,
+            ext,
+            vk,
+            name: key_name(key).map(str::to_string),
+            display: display_name(key),
+            thumb_key: ThumbKeySelect::from_sckey(key),
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 struct HookEvent {
     sc: u16,
     ext: bool,
     up: bool,
-    shift: bool,
+    shift: ShiftSide,
+    modifier: Option<HeldModifier>,
+}
+
+impl HookEvent {
+    fn raw(&self) -> RawKeyEvent {
+        RawKeyEvent {
+            sc: self.sc,
+            ext: self.ext,
+            up: self.up,
+            shift: self.shift,
+            modifier: self.modifier,
+        }
+    }
+}
+
+/// Physical key event as the hook received it.
+#[derive(Clone, Copy, Debug)]
+struct HookKey {
+    sc: u16,
+    ext: bool,
+    up: bool,
     vk: u32,
+    /// Alt was down (`LLKHF_ALTDOWN`).
+    alt_down: bool,
+}
+
+/// Left and right Ctrl, Shift, Alt and Win held, one bit each.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct HeldKeys(u8);
+
+impl HeldKeys {
+    const LCTRL: u8 = 1 << 0;
+    const RCTRL: u8 = 1 << 1;
+    const LSHIFT: u8 = 1 << 2;
+    const RSHIFT: u8 = 1 << 3;
+    const LALT: u8 = 1 << 4;
+    const RALT: u8 = 1 << 5;
+    const LWIN: u8 = 1 << 6;
+    const RWIN: u8 = 1 << 7;
+
+    /// Bit of a modifier VK, 0 for other keys.
+    fn bit(vk: u32) -> u8 {
+        match VIRTUAL_KEY(vk as u16) {
+            VK_LCONTROL | VK_CONTROL => Self::LCTRL,
+            VK_RCONTROL => Self::RCTRL,
+            VK_LSHIFT | VK_SHIFT => Self::LSHIFT,
+            VK_RSHIFT => Self::RSHIFT,
+            VK_LMENU | VK_MENU => Self::LALT,
+            VK_RMENU => Self::RALT,
+            VK_LWIN => Self::LWIN,
+            VK_RWIN => Self::RWIN,
+            _ => 0,
+        }
+    }
+
+    /// Follows a key event seen by the hook.
+    fn track(vk: u32, up: bool) {
+        let bit = Self::bit(vk);
+        if bit == 0 {
+            return;
+        }
+        if up {
+            HELD_KEYS.fetch_and(!bit, Ordering::Relaxed);
+        } else {
+            HELD_KEYS.fetch_or(bit, Ordering::Relaxed);
+        }
+    }
+
+    /// Forgets every held modifier, for when key-ups can no longer be seen.
+    fn clear() {
+        HELD_KEYS.store(0, Ordering::Relaxed);
+    }
+
+    /// Drops bits that were already stale at the previous call and still are,
+    /// so a key-up the hook missed does not leave a modifier held forever.
+    /// Returns the bits that are stale now, for the next call.
+    fn resync(stale: u8) -> u8 {
+        let now_stale = Self::tracked().0 & !Self::from_async_state().0;
+        let drop = stale & now_stale;
+        if drop != 0 {
+            HELD_KEYS.fetch_and(!drop, Ordering::Relaxed);
+            debug!("Cleared modifiers whose key-up was missed: {:#04x}", drop);
+        }
+        now_stale & !drop
+    }
+
+    fn tracked() -> Self {
+        Self(HELD_KEYS.load(Ordering::Relaxed))
+    }
+
+    fn from_async_state() -> Self {
+        let held = |vk: VIRTUAL_KEY| unsafe { GetAsyncKeyState(vk.0 as i32) } as u16 & 0x8000 != 0;
+        let mut bits = 0;
+        for (vk, bit) in [
+            (VK_LCONTROL, Self::LCTRL),
+            (VK_RCONTROL, Self::RCTRL),
+            (VK_LSHIFT, Self::LSHIFT),
+            (VK_RSHIFT, Self::RSHIFT),
+            (VK_LMENU, Self::LALT),
+            (VK_RMENU, Self::RALT),
+            (VK_LWIN, Self::LWIN),
+            (VK_RWIN, Self::RWIN),
+        ] {
+            if held(vk) {
+                bits |= bit;
+            }
+        }
+        Self(bits)
+    }
+
+    fn has(self, bit: u8) -> bool {
+        self.0 & bit != 0
+    }
+
+    /// These keys without the modifier `vk`, if it is one.
+    fn without(self, vk: u32) -> Self {
+        Self(self.0 & !Self::bit(vk))
+    }
+
+    fn ctrl(self) -> bool {
+        self.has(Self::LCTRL | Self::RCTRL)
+    }
+
+    fn alt(self) -> bool {
+        self.has(Self::LALT | Self::RALT)
+    }
+
+    fn win(self) -> bool {
+        self.has(Self::LWIN | Self::RWIN)
+    }
+
+    fn shift(self) -> ShiftSide {
+        ShiftSide::from_keys(self.has(Self::LSHIFT), self.has(Self::RSHIFT))
+    }
+}
+
+/// Work handed from the hook thread to the worker.
+#[derive(Clone, Copy, Debug)]
+enum HookMessage {
+    Key(HookEvent),
+    /// A key the worker has to decide about (fast-path mode).
+    Undecided(HookKey, HeldKeys),
+    /// The foreground window changed.
+    FocusChanged,
+    /// The suspend key was pressed.
+    ToggleSuspended,
+    /// A key was typed after [`wake_on_next_typing`].
+    Typed,
 }
 
 lazy_static::lazy_static! {
-    static ref HOOK_QUEUE: (Sender<HookEvent>, Receiver<HookEvent>) =
+    static ref HOOK_QUEUE: (Sender<HookMessage>, Receiver<HookMessage>) =
         crossbeam_channel::bounded(HOOK_QUEUE_SIZE);
 }
 
@@ -105,9 +432,338 @@ fn ensure_watchdog_thread() {
         .expect("Failed to spawn hook watchdog thread");
 }
 
+/// Turns fast-path mode on or off. In it the hook callback only queues
+/// keys and the worker decides about them, keeping the callback well
+/// below `LowLevelHooksTimeout` on a loaded system.
+pub fn set_fast_path(enabled: bool) {
+    FAST_PATH.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_fast_path() -> bool {
+    FAST_PATH.load(Ordering::Relaxed)
+}
+
+/// Timings of the hook callback so far.
+pub fn hook_health() -> HookHealth {
+    HOOK_TIMINGS.snapshot()
+}
+
+/// Registers a callback run when hook calls came close to
+/// `LowLevelHooksTimeout`. The watchdog checks about once a second.
+pub fn set_on_hook_slow(cb: impl Fn(HookHealth) + Send + 'static) {
+    *ON_HOOK_SLOW.lock().unwrap() = Some(Box::new(cb));
+}
+
+pub fn injection_health() -> InjectionHealth {
+    INJECTION_STATS.snapshot()
+}
+
+/// Registers a callback run with the events SendInput dropped since the
+/// previous call. The watchdog checks about once a second.
+pub fn set_on_injection_failed(cb: impl Fn(DroppedInput) + Send + 'static) {
+    *ON_INJECTION_FAILED.lock().unwrap() = Some(Box::new(cb));
+}
+
+/// Registers a callback run after the watchdog re-installed the hook.
+pub fn set_on_hook_recovered(cb: impl Fn(HookRecoveryReason) + Send + 'static) {
+    *ON_HOOK_RECOVERED.lock().unwrap() = Some(Box::new(cb));
+}
+
+/// Registers a callback run on the hook worker when another application
+/// comes to the foreground, and for the first key typed after
+/// [`wake_on_next_typing`]. Keep it short: keys wait behind it.
+pub fn set_on_activity(cb: impl Fn() + Send + 'static) {
+    *ON_ACTIVITY.lock().unwrap() = Some(Box::new(cb));
+}
+
+/// Runs the activity callback once, for the next key typed on a keyboard.
+pub fn wake_on_next_typing() {
+    WAKE_ON_TYPING.store(true, Ordering::Relaxed);
+}
+
+fn notify_activity() {
+    if let Some(cb) = ON_ACTIVITY.lock().unwrap().as_ref() {
+        cb();
+    }
+}
+
+/// Reports the next physical key press to `cb` instead of processing it.
+/// The press and its release are both swallowed. Replaces a pending capture.
+pub fn capture_next_key(cb: impl FnOnce(CapturedKey) + Send + 'static) {
+    *KEY_CAPTURE.lock().unwrap() = Some(Box::new(cb));
+    KEY_CAPTURE_ARMED.store(true, Ordering::Release);
+}
+
+pub fn cancel_key_capture() {
+    KEY_CAPTURE_ARMED.store(false, Ordering::Release);
+    KEY_CAPTURE.lock().unwrap().take();
+}
+
+/// Replaces the registered hotkeys. Each press of one is reported to the
+/// [`set_on_hotkey`] callback with its id and not typed.
+pub fn set_hotkeys(hotkeys: Vec<(String, Hotkey)>) {
+    let mut registered = HOTKEYS.lock().unwrap();
+    *registered = hotkeys.into_iter().filter(|(_, h)| h.vk != 0).collect();
+    HOTKEYS_ACTIVE.store(!registered.is_empty(), Ordering::Release);
+}
+
+/// Registers the callback for hotkey presses. It runs on its own thread.
+pub fn set_on_hotkey(cb: impl Fn(&str) + Send + Sync + 'static) {
+    *ON_HOTKEY.lock().unwrap() = Some(Arc::new(cb));
+}
+
+/// Returns true when the event belongs to a hotkey and must be swallowed.
+fn hotkey_event(vk: u32, up: bool, held: HeldKeys) -> bool {
+    if up {
+        return HOTKEY_KEY_UP
+            .compare_exchange(vk, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok();
+    }
+    if HOTKEY_KEY_UP.load(Ordering::Acquire) == vk {
+        return true; // Auto-repeat of the held hotkey.
+    }
+    if !HOTKEYS_ACTIVE.load(Ordering::Acquire) {
+        return false;
+    }
+    let pressed = Hotkey {
+        vk,
+        ctrl: held.ctrl(),
+        alt: held.alt(),
+        shift: held.shift().is_held(),
+        win: held.win(),
+    };
+    let Some(id) = find_hotkey(&HOTKEYS.lock().unwrap(), &pressed).map(str::to_string) else {
+        return false;
+    };
+    HOTKEY_KEY_UP.store(vk, Ordering::Release);
+    if let Some(cb) = ON_HOTKEY.lock().unwrap().clone() {
+        thread::spawn(move || cb(&id));
+    }
+    true
+}
+
+fn capture_id(sc: u16, ext: bool) -> u32 {
+    0x8000_0000 | (u32::from(ext) << 16) | u32::from(sc)
+}
+
+fn modifier_mask(ctrl: bool, alt: bool, shift: bool, win: bool) -> u8 {
+    u8::from(ctrl) | u8::from(alt) << 1 | u8::from(shift) << 2 | u8::from(win) << 3
+}
+
+fn set_suspend_key(suspend_key: SuspendKey) {
+    let id = suspend_key.key.map_or(0, |key| capture_id(key.sc, key.ext));
+    let modifiers = modifier_mask(
+        suspend_key.ctrl,
+        suspend_key.alt,
+        suspend_key.shift,
+        suspend_key.win,
+    );
+    SUSPEND_MODIFIERS.store(modifiers, Ordering::Relaxed);
+    SUSPEND_KEY_ID.store(id, Ordering::Release);
+}
+
+/// What to do with an event of the suspend key, `None` for other events.
+/// The key itself may be a modifier, so it does not count as held.
+fn suspend_key_event(key: &HookKey, held: HeldKeys) -> Option<HookDecision> {
+    let id = capture_id(key.sc, key.ext);
+    if key.up {
+        return SUSPEND_KEY_UP
+            .compare_exchange(id, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+            .then_some(HookDecision::Swallow);
+    }
+    if SUSPEND_KEY_ID.load(Ordering::Acquire) != id {
+        return None;
+    }
+    if SUSPEND_KEY_UP.load(Ordering::Acquire) == id {
+        return Some(HookDecision::Swallow); // Auto-repeat of the held key.
+    }
+    let held = held.without(key.vk);
+    let modifiers = modifier_mask(held.ctrl(), held.alt(), held.shift().is_held(), held.win());
+    if modifiers != SUSPEND_MODIFIERS.load(Ordering::Relaxed) {
+        return None;
+    }
+    SUSPEND_KEY_UP.store(id, Ordering::Release);
+    Some(HookDecision::ToggleSuspended)
+}
+
+/// Returns true when the event was consumed by a key capture.
+fn capture_key_event(sc: u16, ext: bool, vk: u32, up: bool) -> bool {
+    let id = capture_id(sc, ext);
+    if up {
+        return CAPTURED_KEY_UP
+            .compare_exchange(id, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok();
+    }
+    if !KEY_CAPTURE_ARMED.swap(false, Ordering::AcqRel) {
+        return false;
+    }
+    let Some(cb) = KEY_CAPTURE.lock().unwrap().take() else {
+        return false;
+    };
+    CAPTURED_KEY_UP.store(id, Ordering::Release);
+    // Keep the hook callback short; the receiver may emit to the UI.
+    let key = CapturedKey::new(sc, ext, vk);
+    thread::spawn(move || cb(key));
+    true
+}
+
+/// Set while the engine holds a modifier for a dual-role key, so that the
+/// modifier does not keep the following keys from the engine.
+pub fn set_dual_role_active(active: bool) {
+    DUAL_ROLE_ACTIVE.store(active, Ordering::Relaxed);
+}
+
 pub fn refresh_runtime_flags_from_engine() {
     let engine = ENGINE.lock();
     ALT_NEEDS_HANDLING.store(engine.needs_alt_handling(), Ordering::Relaxed);
+    SHIFT_NEEDS_HANDLING.store(engine.needs_shift_handling(), Ordering::Relaxed);
+    MODIFIER_PLANES.store(engine.modifier_plane_mask(), Ordering::Relaxed);
+    let panic_key = engine.get_panic_key();
+    let vk = if panic_key.enabled {
+        panic_key.key.vk()
+    } else {
+        0
+    };
+    PANIC_KEY_VK.store(vk, Ordering::Relaxed);
+    PANIC_HOLD_MS.store(panic_key.hold_ms, Ordering::Relaxed);
+    set_suspend_key(engine.get_suspend_key());
+    let injection = engine.get_injection();
+    INJECT_CHUNK_SIZE.store(injection.chunk_size, Ordering::Relaxed);
+    INJECT_CHUNK_DELAY_MS.store(injection.chunk_delay_ms, Ordering::Relaxed);
+}
+
+/// Tracks the panic combo. Returns true once it has been held long enough.
+/// Runs before anything that touches the engine.
+fn panic_key_event(vk: u32, up: bool) -> bool {
+    let panic_vk = PANIC_KEY_VK.load(Ordering::Relaxed);
+    if panic_vk == 0 || vk != panic_vk {
+        return false;
+    }
+    let now = monotonic_ms().max(1);
+    let hold_ms = PANIC_HOLD_MS.load(Ordering::Relaxed);
+    if up {
+        // Also covers setups without key repeat.
+        let start = PANIC_KEY_DOWN_MS.swap(0, Ordering::Relaxed);
+        return start != 0 && start != PANIC_TRIGGERED && now.saturating_sub(start) >= hold_ms;
+    }
+    let combo = unsafe {
+        GetAsyncKeyState(VK_CONTROL.0 as i32) as u16 & 0x8000 != 0
+            && GetAsyncKeyState(VK_MENU.0 as i32) as u16 & 0x8000 != 0
+    };
+    if !combo {
+        PANIC_KEY_DOWN_MS.store(0, Ordering::Relaxed);
+        return false;
+    }
+    match PANIC_KEY_DOWN_MS.load(Ordering::Relaxed) {
+        0 => {
+            PANIC_KEY_DOWN_MS.store(now, Ordering::Relaxed);
+            false
+        }
+        PANIC_TRIGGERED => false,
+        start if now.saturating_sub(start) >= hold_ms => {
+            PANIC_KEY_DOWN_MS.store(PANIC_TRIGGERED, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Passes every key through, releases modifiers we left pressed and disables
+/// the engine. Does not wait on the engine lock from the hook callback; the
+/// keys pass through until the engine could be disabled.
+fn force_pass_through() {
+    FORCE_PASS_THROUGH.store(true, Ordering::Relaxed);
+    warn!("Panic key triggered; passing all keys through");
+    thread::spawn(|| {
+        release_injected_modifiers();
+        let mut engine = match ENGINE.try_lock_for(PANIC_ENGINE_LOCK_TIMEOUT) {
+            Some(engine) => engine,
+            None => {
+                error!("Panic key: engine is busy; keys pass through until it is free");
+                ENGINE.lock()
+            }
+        };
+        engine.set_enabled(false);
+        drop(engine);
+        release_after_disable();
+        // A disabled engine passes keys through on its own, and enabling
+        // it again must not be overridden by this flag.
+        FORCE_PASS_THROUGH.store(false, Ordering::Relaxed);
+    });
+}
+
+/// Modifier presses we injected and have not released yet. If injection
+/// stops between a Down and its Up, the OS keeps the modifier held.
+struct InjectedModifiers {
+    held: AtomicU32,
+}
+
+impl InjectedModifiers {
+    const fn new() -> Self {
+        Self {
+            held: AtomicU32::new(0),
+        }
+    }
+
+    fn record(&self, sc: u16, ext: bool, up: bool) {
+        let Some(index) = TRACKED_MODIFIERS.iter().position(|&m| m == (sc, ext)) else {
+            return;
+        };
+        if up {
+            self.held.fetch_and(!(1 << index), Ordering::Relaxed);
+        } else {
+            self.held.fetch_or(1 << index, Ordering::Relaxed);
+        }
+    }
+
+    /// Clears and returns the modifiers still held.
+    fn take(&self) -> Vec<(u16, bool)> {
+        let held = self.held.swap(0, Ordering::Relaxed);
+        TRACKED_MODIFIERS
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| held & (1 << index) != 0)
+            .map(|(_, &key)| key)
+            .collect()
+    }
+}
+
+/// Undoes what a disabled engine may have left held: the dual-role
+/// modifier and any modifier we injected. Call it without the engine lock.
+pub fn release_after_disable() {
+    set_dual_role_active(false);
+    release_injected_modifiers();
+}
+
+/// Sends a release for every modifier still held by our own injection.
+pub fn release_injected_modifiers() {
+    for (sc, ext) in INJECTED_MODIFIERS.take() {
+        warn!(
+            "Releasing leaked injected modifier sc={:#04X} ext={}",
+            sc, ext
+        );
+        let _ = inject_scancode(sc, ext, true);
+    }
+}
+
+/// Releases leaked modifiers when a thread panics. Call once at startup;
+/// call [`shutdown`] on the normal exit paths.
+pub fn install_exit_guard() {
+    if EXIT_GUARD_INSTALLED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        release_injected_modifiers();
+        previous(info);
+    }));
+}
+
+/// Removes the hook and releases any injected modifiers before exiting.
+pub fn shutdown() {
+    uninstall_hook();
+    release_injected_modifiers();
 }
 
 /// Starts the keyboard hook.
@@ -118,9 +774,12 @@ pub fn install_hook() -> anyhow::Result<()> {
     refresh_runtime_flags_from_engine();
 
     info!("Installing keyboard hook...");
+    HOOK_TIMINGS.set_timeout_ms(low_level_hooks_timeout_ms());
 
     // Avoid leaking an old handle if this is a reinstall request.
     uninstall_hook();
+    // Key-ups may have been missed while no hook was installed.
+    HeldKeys::clear();
 
     // Low-level hooks require hMod to be NULL if threadId is 0.
     // However, Rust/Windows crates handle Option<HINSTANCE> -> 0.
@@ -136,9 +795,104 @@ pub fn install_hook() -> anyhow::Result<()> {
         "Keyboard hook installed successfully. Handle: {:?}",
         hook_id
     );
+    install_win_event_hooks();
+    crate::elevation::foreground_blocks_injection();
     Ok(())
 }
 
+/// Watches foreground-window changes so pending chord keys are not typed
+/// into the next window, and focus/IME events that invalidate the cached
+/// IME state, and desktop switches that hide modifier key-ups. Failure only
+/// loses these; the IME cache still expires and the watchdog resyncs modifiers.
+fn install_win_event_hooks() {
+    let hooks: [(u32, u32, WINEVENTPROC); 4] = [
+        (
+            EVENT_SYSTEM_FOREGROUND,
+            EVENT_SYSTEM_FOREGROUND,
+            Some(focus_event_proc),
+        ),
+        (EVENT_OBJECT_FOCUS, EVENT_OBJECT_FOCUS, Some(ime_event_proc)),
+        (
+            EVENT_OBJECT_IME_SHOW,
+            EVENT_OBJECT_IME_CHANGE,
+            Some(ime_event_proc),
+        ),
+        (
+            EVENT_SYSTEM_DESKTOPSWITCH,
+            EVENT_SYSTEM_DESKTOPSWITCH,
+            Some(desktop_switch_proc),
+        ),
+    ];
+    let mut handles = WIN_EVENT_HOOKS.lock().unwrap();
+    for (min, max, proc) in hooks {
+        let hook = unsafe {
+            SetWinEventHook(
+                min,
+                max,
+                None,
+                proc,
+                0,
+                0,
+                WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
+            )
+        };
+        if hook.is_invalid() {
+            warn!("Failed to install WinEvent hook for {:#x}", min);
+            continue;
+        }
+        handles.push(hook);
+    }
+}
+
+unsafe extern "system" fn focus_event_proc(
+    _hook: HWINEVENTHOOK,
+    _event: u32,
+    _hwnd: HWND,
+    _id_object: i32,
+    _id_child: i32,
+    _event_thread: u32,
+    _event_time: u32,
+) {
+    crate::ime::invalidate_state_cache();
+    crate::secure_input::request_check();
+    // Caches whether the new foreground is elevated for the hook's fast path.
+    crate::elevation::foreground_blocks_injection();
+    // Queued behind any keys already sent, so they are decided first.
+    let _ = HOOK_QUEUE.0.try_send(HookMessage::FocusChanged);
+}
+
+/// Win+L, UAC and session switches move input to another desktop, whose
+/// key-ups the hook never sees.
+unsafe extern "system" fn desktop_switch_proc(
+    _hook: HWINEVENTHOOK,
+    _event: u32,
+    _hwnd: HWND,
+    _id_object: i32,
+    _id_child: i32,
+    _event_thread: u32,
+    _event_time: u32,
+) {
+    HeldKeys::clear();
+}
+
+unsafe extern "system" fn ime_event_proc(
+    _hook: HWINEVENTHOOK,
+    event: u32,
+    hwnd: HWND,
+    _id_object: i32,
+    _id_child: i32,
+    _event_thread: u32,
+    _event_time: u32,
+) {
+    crate::ime::invalidate_state_cache();
+    if event == EVENT_OBJECT_FOCUS {
+        crate::secure_input::request_check();
+    }
+    if event == EVENT_OBJECT_IME_SHOW || event == EVENT_OBJECT_IME_HIDE {
+        crate::ime::on_ime_window_event(hwnd, event == EVENT_OBJECT_IME_SHOW);
+    }
+}
+
 pub fn uninstall_hook() {
     let mut handle = HOOK_HANDLE.lock().unwrap();
     if let Some(h) = *handle {
@@ -148,6 +902,11 @@ pub fn uninstall_hook() {
         info!("Keyboard hook uninstalled.");
     }
     *handle = None;
+    for h in WIN_EVENT_HOOKS.lock().unwrap().drain(..) {
+        unsafe {
+            let _ = UnhookWinEvent(h);
+        };
+    }
 }
 
 /// Runs a blocking message loop.
@@ -168,7 +927,11 @@ pub fn run_event_loop() {
 
         while GetMessageW(&mut msg, None, 0, 0).as_bool() {
             if msg.message == WM_HOOK_REINSTALL {
-                reinstall_hook();
+                reinstall_hook(HookRecoveryReason::from_wparam(msg.wParam));
+                continue;
+            }
+            if msg.message == WM_HOOK_HEARTBEAT {
+                HEARTBEAT_SENT_MS.store(0, Ordering::Relaxed);
                 continue;
             }
 
@@ -180,14 +943,29 @@ pub fn run_event_loop() {
 }
 
 unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let started = Instant::now();
     let result = catch_unwind(AssertUnwindSafe(|| {
-        LAST_HOOK_MS.store(monotonic_ms(), Ordering::Relaxed);
+        let now = monotonic_ms();
+        LAST_HOOK_MS.store(now, Ordering::Relaxed);
 
         if code < 0 {
             return CallNextHookEx(None, code, wparam, lparam);
         }
 
         let kbd = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        if kbd.flags.0 & LLKHF_INJECTED.0 == 0 {
+            LAST_TYPED_MS.store(now, Ordering::Relaxed);
+            if WAKE_ON_TYPING.load(Ordering::Relaxed)
+                && WAKE_ON_TYPING.swap(false, Ordering::Relaxed)
+            {
+                let _ = HOOK_QUEUE.0.try_send(HookMessage::Typed);
+            }
+        }
+
+        // Log visible events
+        let msg = wparam.0 as u32;
+        let up = msg == WM_KEYUP || msg == WM_SYSKEYUP;
+        HeldKeys::track(kbd.vkCode, up);
 
         // Check self-injection guard
         if kbd.dwExtraInfo == INJECTED_EXTRA_INFO {
@@ -195,9 +973,12 @@ unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -
             return CallNextHookEx(None, code, wparam, lparam);
         }
 
-        // Log visible events
-        let msg = wparam.0 as u32;
-        let up = msg == WM_KEYUP || msg == WM_SYSKEYUP;
+        if panic_key_event(kbd.vkCode, up) {
+            force_pass_through();
+        }
+        if FORCE_PASS_THROUGH.load(Ordering::Relaxed) {
+            return CallNextHookEx(None, code, wparam, lparam);
+        }
 
         // Emergency stop is intentionally disabled for now.
         // To restore Ctrl+Alt+Esc shutdown behavior, uncomment this block.
@@ -212,90 +993,313 @@ unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -
         }
         */
 
-        // Check for modifiers to disable hook
-        let is_shift_vk = kbd.vkCode == VK_SHIFT.0 as u32
-            || kbd.vkCode == VK_LSHIFT.0 as u32
-            || kbd.vkCode == VK_RSHIFT.0 as u32;
-        let is_ctrl_vk = kbd.vkCode == VK_CONTROL.0 as u32
-            || kbd.vkCode == VK_LCONTROL.0 as u32
-            || kbd.vkCode == VK_RCONTROL.0 as u32;
-        let is_alt_vk = kbd.vkCode == VK_MENU.0 as u32
-            || kbd.vkCode == VK_LMENU.0 as u32
-            || kbd.vkCode == VK_RMENU.0 as u32;
-        let is_win_vk = kbd.vkCode == VK_LWIN.0 as u32 || kbd.vkCode == VK_RWIN.0 as u32;
-
-        // Alt may be used as a logical key source via [機能キー] swap.
-        // In that case we must feed Alt events into the engine.
-        let alt_needs_handling = ALT_NEEDS_HANDLING.load(Ordering::Relaxed);
-
-        // Pass through Modifier key events themselves to ensure OS state is updated
-        if is_shift_vk || is_ctrl_vk || is_win_vk || (is_alt_vk && !alt_needs_handling) {
-            return CallNextHookEx(None, code, wparam, lparam);
-        }
-
-        // Check modifier states only for non-modifier keys that can be handled.
-        let ctrl_pressed = GetAsyncKeyState(VK_CONTROL.0 as i32) as u16 & 0x8000 != 0;
-        let shift_pressed = GetAsyncKeyState(VK_SHIFT.0 as i32) as u16 & 0x8000 != 0;
-        let lwin_pressed = GetAsyncKeyState(VK_LWIN.0 as i32) as u16 & 0x8000 != 0;
-        let rwin_pressed = GetAsyncKeyState(VK_RWIN.0 as i32) as u16 & 0x8000 != 0;
-        let alt_pressed = is_alt_vk || (kbd.flags.0 & LLKHF_ALTDOWN.0) != 0;
-
-        if ctrl_pressed || lwin_pressed || rwin_pressed || (alt_pressed && !alt_needs_handling) {
-            return CallNextHookEx(None, code, wparam, lparam);
-        }
-
-        let ext = (kbd.flags.0 & windows::Win32::UI::WindowsAndMessaging::LLKHF_EXTENDED.0) != 0;
-
-        let event = HookEvent {
+        let key = HookKey {
             sc: kbd.scanCode as u16,
-            ext,
+            ext: (kbd.flags.0 & windows::Win32::UI::WindowsAndMessaging::LLKHF_EXTENDED.0) != 0,
             up,
-            shift: shift_pressed,
             vk: kbd.vkCode,
+            alt_down: (kbd.flags.0 & LLKHF_ALTDOWN.0) != 0,
         };
 
-        match HOOK_QUEUE.0.try_send(event) {
+        // Fast path: leave every decision that is not a plain atomic load
+        // to the worker, which passes the key on by injecting it again.
+        // Elevated and secure foregrounds reject that injection, so their
+        // keys are decided here.
+        let fast = FAST_PATH.load(Ordering::Relaxed);
+        let held = if fast {
+            HeldKeys::tracked()
+        } else {
+            HeldKeys::from_async_state()
+        };
+        if fast && passes_immediately(&key, held) {
+            return CallNextHookEx(None, code, wparam, lparam);
+        }
+        let reinjectable = !crate::elevation::foreground_blocks_injection_cached()
+            && !crate::secure_input::is_active();
+        let message = if fast && reinjectable {
+            HookMessage::Undecided(key, held)
+        } else {
+            match decide(&key, held) {
+                HookDecision::Pass => return CallNextHookEx(None, code, wparam, lparam),
+                HookDecision::Swallow => return LRESULT(1),
+                HookDecision::Queue(event) => HookMessage::Key(event),
+                HookDecision::ToggleSuspended => HookMessage::ToggleSuspended,
+            }
+        };
+
+        match HOOK_QUEUE.0.try_send(message) {
             Ok(()) => LRESULT(1), // Block original; worker will decide inject/pass.
             Err(TrySendError::Full(_)) => CallNextHookEx(None, code, wparam, lparam),
             Err(TrySendError::Disconnected(_)) => CallNextHookEx(None, code, wparam, lparam),
         }
     }));
 
-    match result {
+    let result = match result {
         Ok(res) => res,
         Err(_) => {
             error!("Panic in hook_proc; falling back to CallNextHookEx");
             CallNextHookEx(None, code, wparam, lparam)
         }
+    };
+    HOOK_TIMINGS.record(started.elapsed());
+    result
+}
+
+/// What to do with a physical key event.
+#[derive(Clone, Copy, Debug)]
+enum HookDecision {
+    /// Let it through unchanged.
+    Pass,
+    /// Drop it; a key capture or hotkey consumed it.
+    Swallow,
+    /// Hand it to the engine.
+    Queue(HookEvent),
+    /// Drop it and suspend or resume after the keys queued before it.
+    ToggleSuspended,
+}
+
+/// Decides a key event from the modifiers `held` when it arrived. Runs on
+/// the hook thread, or on the worker in fast-path mode.
+fn decide(key: &HookKey, held: HeldKeys) -> HookDecision {
+    // Key capture sees every physical key, modifiers included.
+    if capture_key_event(key.sc, key.ext, key.vk, key.up) {
+        return HookDecision::Swallow;
+    }
+
+    if hotkey_event(key.vk, key.up, held) {
+        return HookDecision::Swallow;
+    }
+
+    if let Some(decision) = suspend_key_event(key, held) {
+        return decision;
+    }
+
+    // Check for modifiers to disable hook
+    let is_shift_vk =
+        key.vk == VK_SHIFT.0 as u32 || key.vk == VK_LSHIFT.0 as u32 || key.vk == VK_RSHIFT.0 as u32;
+    let is_ctrl_vk = key.vk == VK_CONTROL.0 as u32
+        || key.vk == VK_LCONTROL.0 as u32
+        || key.vk == VK_RCONTROL.0 as u32;
+    let is_alt_vk =
+        key.vk == VK_MENU.0 as u32 || key.vk == VK_LMENU.0 as u32 || key.vk == VK_RMENU.0 as u32;
+    let is_win_vk = key.vk == VK_LWIN.0 as u32 || key.vk == VK_RWIN.0 as u32;
+
+    // Alt may be used as a logical key source via [機能キー] swap.
+    // In that case we must feed Alt events into the engine.
+    let alt_needs_handling = ALT_NEEDS_HANDLING.load(Ordering::Relaxed);
+
+    // Pinky shift judges Shift by overlap, so its events go to the engine.
+    let shift_needs_handling = SHIFT_NEEDS_HANDLING.load(Ordering::Relaxed);
+
+    // Pass through Modifier key events themselves to ensure OS state is updated
+    if (is_shift_vk && !shift_needs_handling)
+        || is_ctrl_vk
+        || is_win_vk
+        || (is_alt_vk && !alt_needs_handling)
+    {
+        return HookDecision::Pass;
+    }
+
+    // Check modifier states only for non-modifier keys that can be handled.
+    let ctrl_pressed = held.ctrl();
+    let shift_pressed = held.shift();
+    let lwin_pressed = held.has(HeldKeys::LWIN);
+    let rwin_pressed = held.has(HeldKeys::RWIN);
+    let alt_pressed = is_alt_vk || key.alt_down;
+
+    let dual_role_active = DUAL_ROLE_ACTIVE.load(Ordering::Relaxed);
+    let modifier = if dual_role_active {
+        None
+    } else if ctrl_pressed || lwin_pressed || rwin_pressed || alt_pressed {
+        held_modifier_plane(
+            [
+                (ModifierPlane::Ctrl, ctrl_pressed, held.has(HeldKeys::RCTRL)),
+                (
+                    ModifierPlane::Alt,
+                    alt_pressed && !alt_needs_handling,
+                    held.has(HeldKeys::RALT),
+                ),
+                (
+                    ModifierPlane::Win,
+                    lwin_pressed || rwin_pressed,
+                    rwin_pressed,
+                ),
+            ],
+            shift_pressed,
+        )
+    } else {
+        None
+    };
+
+    if (ctrl_pressed || lwin_pressed || rwin_pressed || (alt_pressed && !alt_needs_handling))
+        && modifier.is_none()
+        && !dual_role_active
+    {
+        return HookDecision::Pass;
+    }
+
+    // Injection into elevated windows is rejected by UIPI unless we run
+    // elevated or with UIAccess, so leave their keys alone.
+    if crate::elevation::foreground_blocks_injection() {
+        return HookDecision::Pass;
+    }
+
+    // Passwords are typed as-is rather than through the layout.
+    if crate::secure_input::is_active() {
+        return HookDecision::Pass;
+    }
+
+    HookDecision::Queue(HookEvent {
+        sc: key.sc,
+        ext: key.ext,
+        up: key.up,
+        shift: shift_pressed,
+        modifier,
+    })
+}
+
+/// Fast-path shortcut for keys that [`decide`] would certainly pass:
+/// modifiers the engine ignores, and keys typed with Ctrl, Alt or Win
+/// while the layout has no plane for them and no hotkey is registered.
+fn passes_immediately(key: &HookKey, held: HeldKeys) -> bool {
+    if KEY_CAPTURE_ARMED.load(Ordering::Relaxed) {
+        return false;
+    }
+    let id = capture_id(key.sc, key.ext);
+    if SUSPEND_KEY_ID.load(Ordering::Relaxed) == id || SUSPEND_KEY_UP.load(Ordering::Relaxed) == id
+    {
+        return false;
+    }
+    let alt_needs_handling = ALT_NEEDS_HANDLING.load(Ordering::Relaxed);
+    match HeldKeys::bit(key.vk) {
+        HeldKeys::LSHIFT | HeldKeys::RSHIFT => {
+            return !SHIFT_NEEDS_HANDLING.load(Ordering::Relaxed)
+        }
+        HeldKeys::LALT | HeldKeys::RALT => return !alt_needs_handling,
+        0 => {}
+        _ => return true,
+    }
+    let shortcut = held.ctrl() || held.win() || (key.alt_down && !alt_needs_handling);
+    shortcut
+        && MODIFIER_PLANES.load(Ordering::Relaxed) == 0
+        && !HOTKEYS_ACTIVE.load(Ordering::Relaxed)
+        && !DUAL_ROLE_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// The only Ctrl, Alt or Win held (plane, held, right-hand held), when the
+/// layout has its sub-plane. With Shift on top the OS shortcut is kept.
+fn held_modifier_plane(
+    held: [(ModifierPlane, bool, bool); 3],
+    shift: ShiftSide,
+) -> Option<HeldModifier> {
+    let mut pressed = held.into_iter().filter(|(_, held, _)| *held);
+    let (plane, _, right) = pressed.next()?;
+    let defined = MODIFIER_PLANES.load(Ordering::Relaxed) & plane.bit() != 0;
+    if pressed.next().is_some() || shift.is_held() || !defined {
+        return None;
+    }
+    Some(HeldModifier { plane, right })
+}
+
+fn hook_worker(rx: Receiver<HookMessage>) {
+    let mut keys = Vec::new();
+    while let Some(message) = next_message(&rx) {
+        // Drain whatever queued up meanwhile so a backlog is handled in one batch.
+        let mut next = Some(message);
+        while let Some(message) = next {
+            match message {
+                HookMessage::Key(event) => keys.push(event),
+                HookMessage::Undecided(key, held) => match decide(&key, held) {
+                    HookDecision::Queue(event) => keys.push(event),
+                    HookDecision::Swallow => {}
+                    HookDecision::Pass => {
+                        run_guarded(|| process_keys(std::mem::take(&mut keys)));
+                        let _ = inject_passed_key(&key);
+                    }
+                    HookDecision::ToggleSuspended => {
+                        run_guarded(|| process_keys(std::mem::take(&mut keys)));
+                        run_guarded(toggle_suspended);
+                    }
+                },
+                HookMessage::ToggleSuspended => {
+                    run_guarded(|| process_keys(std::mem::take(&mut keys)));
+                    run_guarded(toggle_suspended);
+                }
+                HookMessage::FocusChanged => {
+                    run_guarded(|| process_keys(std::mem::take(&mut keys)));
+                    run_guarded(|| {
+                        let exe = foreground_exe_name();
+                        let events = ENGINE.lock().on_focus_change(exe);
+                        inject_events(events);
+                    });
+                    run_guarded(notify_activity);
+                }
+                HookMessage::Typed => run_guarded(notify_activity),
+            }
+            next = rx.try_recv().ok();
+        }
+        run_guarded(|| process_keys(std::mem::take(&mut keys)));
     }
 }
 
-fn hook_worker(rx: Receiver<HookEvent>) {
-    for event in rx.iter() {
-        let result = catch_unwind(AssertUnwindSafe(|| process_event(event)));
-        if result.is_err() {
-            error!("Panic in hook worker; dropping event");
+/// Waits for the next message. While the engine has a time-based decision
+/// ahead, wakes up at its deadline to tick the engine and sends the output.
+fn next_message(rx: &Receiver<HookMessage>) -> Option<HookMessage> {
+    loop {
+        let deadline = ENGINE.lock().next_deadline();
+        let Some(deadline) = deadline else {
+            return rx.recv().ok();
+        };
+        match rx.recv_deadline(deadline) {
+            Ok(message) => return Some(message),
+            Err(RecvTimeoutError::Timeout) => run_guarded(|| {
+                let events = ENGINE.lock().on_tick();
+                inject_events(events);
+            }),
+            Err(RecvTimeoutError::Disconnected) => return None,
         }
     }
 }
 
+fn run_guarded(f: impl FnOnce()) {
+    if catch_unwind(AssertUnwindSafe(f)).is_err() {
+        error!("Panic in hook worker; dropping event");
+        release_injected_modifiers();
+    }
+}
+
+fn process_keys(keys: Vec<HookEvent>) {
+    match keys.as_slice() {
+        [] => {}
+        [event] => process_event(*event),
+        _ => process_batch(&keys),
+    }
+}
+
+fn toggle_suspended() {
+    let enabled = {
+        let mut engine = ENGINE.lock();
+        let enabled = !engine.is_enabled();
+        engine.set_enabled(enabled);
+        enabled
+    };
+    info!(
+        "Suspend Key triggered. Toggled enabled state to: {}",
+        enabled
+    );
+    if !enabled {
+        release_after_disable();
+    }
+}
+
 fn process_event(event: HookEvent) {
-    let action = {
+    let (action, toggled) = {
         let mut engine = ENGINE.lock();
         ALT_NEEDS_HANDLING.store(engine.needs_alt_handling(), Ordering::Relaxed);
 
-        if let Some(vk) = suspend_key_vk(engine.get_suspend_key()) {
-            if event.vk == vk && !event.up {
-                let current = engine.is_enabled();
-                engine.set_enabled(!current);
-                info!(
-                    "Suspend Key triggered. Toggled enabled state to: {}",
-                    !current
-                );
-            }
-        }
-
-        engine.process_key(event.sc, event.ext, event.up, event.shift)
+        let action = engine.process_event(&event.raw());
+        SHIFT_NEEDS_HANDLING.store(engine.needs_shift_handling(), Ordering::Relaxed);
+        (action, engine.take_lock_toggles())
     };
 
     match action {
@@ -303,140 +1307,389 @@ fn process_event(event: HookEvent) {
             let _ = inject_scancode(event.sc, event.ext, event.up);
         }
         KeyAction::Block => {}
-        KeyAction::Inject(events) => {
-            for ev in events {
-                match ev {
-                    InputEvent::Scancode(sc, ext, up) => {
-                        let _ = inject_scancode(sc, ext, up);
-                    }
-                    InputEvent::Unicode(c, up) => {
-                        let _ = inject_unicode(c, up);
-                    }
-                    InputEvent::ImeControl(open) => {
-                        // IME Control is a state change, not a key press/release pair.
-                        // Ideally we should execute it only once.
-                        // Since engine emits it as a single event, we just execute it.
-                        crate::ime::set_force_ime_status(open);
+        KeyAction::Inject(events) => inject_toggling_locks(events, toggled),
+    }
+}
+
+/// Processes a backlog under one engine lock and injects the combined output.
+/// A suspend key press ends the backlog, so the toggle applies from that
+/// key on, as it would one event at a time. Output that changes the IME
+/// state cuts it too: that part is injected before the rest is resolved.
+fn process_batch(batch: &[HookEvent]) {
+    let raw: Vec<RawKeyEvent> = batch.iter().map(HookEvent::raw).collect();
+    let mut rest = raw.as_slice();
+    while !rest.is_empty() {
+        let (events, toggled, done) = {
+            let mut engine = ENGINE.lock();
+            ALT_NEEDS_HANDLING.store(engine.needs_alt_handling(), Ordering::Relaxed);
+
+            let actions = engine.process_events(rest);
+            let done = actions.len();
+            let out = coalesce_actions(&rest[..done], actions);
+            SHIFT_NEEDS_HANDLING.store(engine.needs_shift_handling(), Ordering::Relaxed);
+            (out, engine.take_lock_toggles(), done)
+        };
+        inject_toggling_locks(events, toggled);
+        rest = &rest[done..];
+    }
+}
+
+/// Injects `events`, which toggle the locks set in `toggled`. Once SendInput
+/// took every event the OS follows in its own time; only when it dropped
+/// some is the lock state checked, as the toggle may be lost.
+fn inject_toggling_locks(events: Vec<InputEvent>, toggled: LockState) {
+    if toggled == LockState::default() {
+        inject_events(events);
+        return;
+    }
+    let locks = [LockKey::Caps, LockKey::Kana]
+        .into_iter()
+        .filter(|&lock| toggled.get(lock));
+    let before = crate::ime::query_lock_state();
+    let expected: Vec<_> = locks.map(|lock| (lock, !before.get(lock))).collect();
+    let dropped = INJECTION_STATS.snapshot().dropped_events;
+    inject_events(events);
+    if INJECTION_STATS.snapshot().dropped_events == dropped {
+        return;
+    }
+    for (lock, state) in expected {
+        verify_lock_toggle(lock, state);
+    }
+}
+
+/// Waits for `lock`, whose toggle may have been dropped, to become
+/// `expected`. If it does not, injects the toggle once more unless the
+/// foreground window refuses injection anyway, and reports it if the lock
+/// still did not follow.
+fn verify_lock_toggle(lock: LockKey, expected: bool) {
+    if wait_for_lock(lock, expected) {
+        return;
+    }
+    if !crate::elevation::foreground_blocks_injection() {
+        warn!("{lock:?} lock toggle was dropped; injecting it again");
+        inject_events(lock_toggle_events(lock));
+        if wait_for_lock(lock, expected) {
+            return;
+        }
+    }
+    warn!("{lock:?} lock did not toggle to {expected}; giving up");
+    ENGINE.lock().set_lock_state(crate::ime::query_lock_state());
+    handle::publish(|| EngineEvent::LockToggleFailed { lock, expected });
+}
+
+fn wait_for_lock(lock: LockKey, expected: bool) -> bool {
+    let deadline = Instant::now() + Duration::from_millis(LOCK_TOGGLE_TIMEOUT_MS);
+    loop {
+        if crate::ime::query_lock_state().get(lock) == expected {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(LOCK_TOGGLE_POLL_MS));
+    }
+}
+
+/// Injects `events`. Runs of key events are batched into SendInput calls
+/// unless the output throttle asks for a delay between every event.
+fn inject_events(events: Vec<InputEvent>) {
+    let events = scripting::resolve(events, |call, outcome| {
+        ENGINE.lock().scripted_events(call, outcome)
+    });
+    let delay_ms = crate::throttle::current_delay_ms();
+    if delay_ms > 0 {
+        for (index, ev) in events.into_iter().enumerate() {
+            if index > 0 {
+                thread::sleep(Duration::from_millis(delay_ms));
+            }
+            inject_event(ev);
+        }
+        return;
+    }
+
+    let mut batch = Vec::new();
+    for ev in events {
+        if !push_key_input(&mut batch, &ev) {
+            send_paced(&mut batch);
+            inject_event(ev);
+        }
+    }
+    send_paced(&mut batch);
+}
+
+/// Appends the SendInput records of a key event to `batch`. Returns false
+/// for events that are not plain key events.
+fn push_key_input(batch: &mut Vec<INPUT>, ev: &InputEvent) -> bool {
+    match *ev {
+        InputEvent::Scancode(sc, ext, up) => {
+            INJECTED_MODIFIERS.record(sc, ext, up);
+            batch.push(scancode_input(sc, ext, up));
+        }
+        InputEvent::Unicode(c, up) => {
+            let mut buf = [0; 2];
+            batch.extend(
+                c.encode_utf16(&mut buf)
+                    .iter()
+                    .map(|&unit| unicode_input(unit, up)),
+            );
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Sends and clears `batch`, in chunks of `Profile::injection` size.
+/// A surrogate pair is never split across chunks.
+fn send_paced(batch: &mut Vec<INPUT>) {
+    let delay_ms = INJECT_CHUNK_DELAY_MS.load(Ordering::Relaxed);
+    let mut rest = batch.as_slice();
+    let mut first = true;
+    while !rest.is_empty() {
+        let len = chunk_len(rest, INJECT_CHUNK_SIZE.load(Ordering::Relaxed));
+        if !first && delay_ms > 0 {
+            thread::sleep(Duration::from_millis(delay_ms));
+        }
+        let _ = send_inputs(&rest[..len]);
+        rest = &rest[len..];
+        first = false;
+    }
+    batch.clear();
+}
+
+fn chunk_len(inputs: &[INPUT], chunk_size: usize) -> usize {
+    if chunk_size == 0 || chunk_size >= inputs.len() {
+        return inputs.len();
+    }
+    let is_high_surrogate = |input: &INPUT| {
+        let ki = unsafe { input.Anonymous.ki };
+        ki.dwFlags.contains(KEYEVENTF_UNICODE) && (0xD800..0xDC00).contains(&ki.wScan)
+    };
+    if is_high_surrogate(&inputs[chunk_size - 1]) {
+        chunk_size + 1
+    } else {
+        chunk_size
+    }
+}
+
+/// Reconverts the selection or, with nothing selected, the word before
+/// the caret.
+fn reconvert() {
+    let target = crate::ime::reconversion_target();
+    crate::ime::run_command(ImeCommand::Reconvert);
+    let mut events = Vec::new();
+    if target == ReconversionTarget::WordBeforeCaret {
+        // Ctrl+Shift+←
+        events.extend([
+            InputEvent::Scancode(0x1D, false, false),
+            InputEvent::Scancode(0x2A, false, false),
+            InputEvent::Scancode(0x4B, true, false),
+            InputEvent::Scancode(0x4B, true, true),
+            InputEvent::Scancode(0x2A, false, true),
+            InputEvent::Scancode(0x1D, false, true),
+        ]);
+    }
+    // 変換
+    events.push(InputEvent::Scancode(0x79, false, false));
+    events.push(InputEvent::Scancode(0x79, false, true));
+    inject_events(events);
+}
+
+fn inject_event(ev: InputEvent) {
+    match ev {
+        InputEvent::Scancode(sc, ext, up) => {
+            let _ = inject_scancode(sc, ext, up);
+        }
+        InputEvent::Unicode(c, up) => {
+            let _ = inject_unicode(c, up);
+        }
+        InputEvent::ImeControl(open) => {
+            // IME Control is a state change, not a key press/release pair.
+            // Ideally we should execute it only once.
+            // Since engine emits it as a single event, we just execute it.
+            crate::ime::set_force_ime_status(open);
+        }
+        InputEvent::Ime(ImeCommand::Reconvert) => reconvert(),
+        InputEvent::Ime(command) => crate::ime::run_command(command),
+        InputEvent::WaitUntilImeStatus(expected, timeout_ms) => {
+            let start = monotonic_ms();
+            loop {
+                // Check current IME status (using relaxed check to avoid excessive overhead?)
+                // is_japanese_input_active queries OS.
+                // If expected is true (ON), we want is_japanese_input_active to be true.
+                // If expected is false (OFF), we want it to be false.
+
+                // Note: We might want to pass ImeMode here if needed, but Engine manages it.
+                // For now, assume Ignore mode behavior (check actual status) or use Auto.
+                // Let's use ImeMode::Ignore to force check actual OS status without mode override logic.
+                let current =
+                    crate::ime::refresh_japanese_input_active(crate::chord_engine::ImeMode::Auto);
+                if current == expected {
+                    break;
+                }
+
+                if monotonic_ms() - start >= timeout_ms {
+                    warn!(
+                        "WaitUntilImeStatus timed out after {}ms (expected: {}, actual: {})",
+                        timeout_ms, expected, current
+                    );
+                    break;
+                }
+
+                // Sleep briefly to yield CPU
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        InputEvent::Grapheme(text) => {
+            let _ = inject_text(&text);
+        }
+        InputEvent::Paste(text) => {
+            if let Err(e) = crate::clipboard::paste_text(&text) {
+                warn!("Clipboard paste failed, typing instead: {}", e);
+                let _ = inject_text(&text);
+            }
+        }
+        InputEvent::Delay(ms) => {
+            thread::sleep(Duration::from_millis(ms));
+        }
+        InputEvent::SettleComposition(policy) => settle_composition(policy),
+        InputEvent::Script(call) => inject_events(call.events),
+        InputEvent::VirtualKey(vk, sc, ext, up) => {
+            let _ = send_inputs(&[key_input(VIRTUAL_KEY(vk), sc, ext, up)]);
+        }
+        InputEvent::ClipboardText => {
+            inject_event(InputEvent::DirectString(DynamicText::Clipboard.expand()))
+        }
+        InputEvent::DirectString(s) => {
+            // Robust IME handling implemented here to avoid deadlock in Engine.
+            let ime_active =
+                crate::ime::refresh_japanese_input_active(crate::chord_engine::ImeMode::Auto);
+
+            if ime_active {
+                crate::ime::set_force_ime_status(false);
+                // Wait for OFF
+                let start = monotonic_ms();
+                loop {
+                    if !crate::ime::refresh_japanese_input_active(
+                        crate::chord_engine::ImeMode::Auto,
+                    ) {
+                        break;
                     }
-                    InputEvent::WaitUntilImeStatus(expected, timeout_ms) => {
-                        let start = monotonic_ms();
-                        loop {
-                            // Check current IME status (using relaxed check to avoid excessive overhead?)
-                            // is_japanese_input_active queries OS.
-                            // If expected is true (ON), we want is_japanese_input_active to be true.
-                            // If expected is false (OFF), we want it to be false.
-
-                            // Note: We might want to pass ImeMode here if needed, but Engine manages it.
-                            // For now, assume Ignore mode behavior (check actual status) or use Auto.
-                            // Let's use ImeMode::Ignore to force check actual OS status without mode override logic.
-                            let current = crate::ime::is_japanese_input_active(
-                                crate::chord_engine::ImeMode::Auto,
-                            );
-                            if current == expected {
-                                break;
-                            }
-
-                            if monotonic_ms() - start >= timeout_ms {
-                                warn!(
-                                    "WaitUntilImeStatus timed out after {}ms (expected: {}, actual: {})",
-                                    timeout_ms, expected, current
-                                );
-                                break;
-                            }
-
-                            // Sleep briefly to yield CPU
-                            thread::sleep(Duration::from_millis(1));
-                        }
+                    if monotonic_ms() - start >= 50 {
+                        warn!("DirectString: Wait for IME OFF timed out");
+                        break;
                     }
-                    InputEvent::Delay(ms) => {
-                        thread::sleep(Duration::from_millis(ms));
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+
+            let _ = inject_text(&s);
+
+            if ime_active {
+                // Delay to prevent overtaking
+                thread::sleep(Duration::from_millis(10));
+
+                crate::ime::set_force_ime_status(true);
+                // Wait for ON
+                let start = monotonic_ms();
+                loop {
+                    if crate::ime::refresh_japanese_input_active(crate::chord_engine::ImeMode::Auto)
+                    {
+                        break;
                     }
-                    InputEvent::DirectString(s) => {
-                        // Robust IME handling implemented here to avoid deadlock in Engine.
-                        let ime_active = crate::ime::is_japanese_input_active(
-                            crate::chord_engine::ImeMode::Auto,
-                        );
-
-                        if ime_active {
-                            crate::ime::set_force_ime_status(false);
-                            // Wait for OFF
-                            let start = monotonic_ms();
-                            loop {
-                                if !crate::ime::is_japanese_input_active(
-                                    crate::chord_engine::ImeMode::Auto,
-                                ) {
-                                    break;
-                                }
-                                if monotonic_ms() - start >= 50 {
-                                    warn!("DirectString: Wait for IME OFF timed out");
-                                    break;
-                                }
-                                thread::sleep(Duration::from_millis(1));
-                            }
-                        }
-
-                        for c in s.chars() {
-                            let _ = inject_unicode(c, false);
-                            let _ = inject_unicode(c, true);
-                        }
-
-                        if ime_active {
-                            // Delay to prevent overtaking
-                            thread::sleep(Duration::from_millis(10));
-
-                            crate::ime::set_force_ime_status(true);
-                            // Wait for ON
-                            let start = monotonic_ms();
-                            loop {
-                                if crate::ime::is_japanese_input_active(
-                                    crate::chord_engine::ImeMode::Auto,
-                                ) {
-                                    break;
-                                }
-                                if monotonic_ms() - start >= 50 {
-                                    warn!("DirectString: Wait for IME ON timed out");
-                                    break;
-                                }
-                                thread::sleep(Duration::from_millis(1));
-                            }
-                        }
+                    if monotonic_ms() - start >= 50 {
+                        warn!("DirectString: Wait for IME ON timed out");
+                        break;
                     }
+                    thread::sleep(Duration::from_millis(1));
                 }
             }
         }
     }
 }
 
-fn suspend_key_vk(suspend_key: crate::chord_engine::SuspendKey) -> Option<u32> {
-    match suspend_key {
-        crate::chord_engine::SuspendKey::None => None,
-        crate::chord_engine::SuspendKey::ScrollLock => Some(0x91), // VK_SCROLL
-        crate::chord_engine::SuspendKey::Pause => Some(0x13),      // VK_PAUSE
-        crate::chord_engine::SuspendKey::Insert => Some(0x2D),     // VK_INSERT
-        crate::chord_engine::SuspendKey::RightShift => Some(0xA1), // VK_RSHIFT
-        crate::chord_engine::SuspendKey::RightControl => Some(0xA3), // VK_RCONTROL
-        crate::chord_engine::SuspendKey::RightAlt => Some(0xA5),   // VK_RMENU
+fn settle_composition(policy: CompositionPolicy) {
+    match policy {
+        CompositionPolicy::Ignore => {}
+        CompositionPolicy::Defer => {
+            let start = monotonic_ms();
+            while crate::ime::is_composing() {
+                if monotonic_ms() - start >= COMPOSITION_DEFER_MS {
+                    warn!("Composition still active after {}ms", COMPOSITION_DEFER_MS);
+                    break;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+        CompositionPolicy::FlushFirst => {
+            if crate::ime::is_composing() && !crate::ime::complete_composition() {
+                warn!("Failed to commit the IME composition");
+            }
+        }
     }
 }
 
-fn reinstall_hook() {
+fn reinstall_hook(reason: HookRecoveryReason) {
     if let Err(e) = install_hook() {
         error!("Failed to reinstall hook: {}", e);
     } else {
-        info!("Keyboard hook reinstalled by watchdog.");
+        info!("Keyboard hook reinstalled by watchdog ({:?}).", reason);
+        if let Some(cb) = ON_HOOK_RECOVERED.lock().unwrap().as_ref() {
+            cb(reason);
+        }
     }
 }
 
-fn request_reinstall() -> bool {
+fn request_reinstall(reason: HookRecoveryReason) -> bool {
     let thread_id = HOOK_THREAD_ID.load(Ordering::Acquire);
     if thread_id == 0 {
         return false;
     }
 
-    unsafe { PostThreadMessageW(thread_id, WM_HOOK_REINSTALL, WPARAM(0), LPARAM(0)).is_ok() }
+    unsafe {
+        PostThreadMessageW(thread_id, WM_HOOK_REINSTALL, reason.to_wparam(), LPARAM(0)).is_ok()
+    }
+}
+
+/// Posts the heartbeat to the hook thread. Returns false if it was not sent.
+fn send_heartbeat() -> bool {
+    let thread_id = HOOK_THREAD_ID.load(Ordering::Acquire);
+    thread_id != 0
+        && unsafe { PostThreadMessageW(thread_id, WM_HOOK_HEARTBEAT, WPARAM(0), LPARAM(0)).is_ok() }
+}
+
+/// Probes the hook thread of an idle hook with a message round trip, which
+/// is not user input and leaves the system idle timer alone. Returns true
+/// when a heartbeat went unanswered: a hook thread that stops pumping
+/// messages has its hook removed by Windows once a callback times out.
+fn heartbeat_missed(now: u64) -> bool {
+    let sent = HEARTBEAT_SENT_MS.load(Ordering::Relaxed);
+    if sent != 0 {
+        if now.saturating_sub(sent) < HEARTBEAT_TIMEOUT_MS {
+            return false;
+        }
+        HEARTBEAT_SENT_MS.store(0, Ordering::Relaxed);
+        return true;
+    }
+
+    let last_hook = LAST_HOOK_MS.load(Ordering::Relaxed);
+    if now.saturating_sub(last_hook) < HEARTBEAT_IDLE_MS {
+        return false;
+    }
+    if send_heartbeat() {
+        HEARTBEAT_SENT_MS.store(now.max(1), Ordering::Relaxed);
+    }
+    false
+}
+
+/// Time since a key was last typed, ignoring input injected by programs
+/// (Kikyo's own output, remote tools, key repeaters). Counts from the
+/// hook's start when nothing was typed yet.
+pub(crate) fn last_typed_age_ms() -> u64 {
+    let now = monotonic_ms();
+    now.saturating_sub(LAST_TYPED_MS.load(Ordering::Relaxed))
 }
 
-fn last_input_age_ms() -> Option<u64> {
+pub(crate) fn last_input_age_ms() -> Option<u64> {
     let mut lii = LASTINPUTINFO {
         cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
         dwTime: 0,
@@ -454,6 +1707,7 @@ fn last_input_age_ms() -> Option<u64> {
 }
 
 fn watchdog_loop() {
+    let mut stale_modifiers = 0;
     loop {
         thread::sleep(Duration::from_millis(WATCHDOG_INTERVAL_MS));
 
@@ -462,12 +1716,43 @@ fn watchdog_loop() {
             continue;
         }
 
+        stale_modifiers = HeldKeys::resync(stale_modifiers);
+
+        if let Some(slow) = HOOK_TIMINGS.take_slow() {
+            let health = HOOK_TIMINGS.snapshot();
+            warn!(
+                "Hook callback took {}ms (LowLevelHooksTimeout {}ms)",
+                slow.as_millis(),
+                health.timeout_ms
+            );
+            if let Some(cb) = ON_HOOK_SLOW.lock().unwrap().as_ref() {
+                cb(health);
+            }
+        }
+
+        if let Some(dropped) = INJECTION_STATS.take_dropped() {
+            if let Some(cb) = ON_INJECTION_FAILED.lock().unwrap().as_ref() {
+                cb(dropped);
+            }
+        }
+
+        let now = monotonic_ms();
+        if heartbeat_missed(now) {
+            let last_reinstall = LAST_REINSTALL_MS.load(Ordering::Relaxed);
+            if now.saturating_sub(last_reinstall) >= REINSTALL_BACKOFF_MS
+                && request_reinstall(HookRecoveryReason::HeartbeatMissed)
+            {
+                LAST_REINSTALL_MS.store(now, Ordering::Relaxed);
+                warn!("Hook watchdog requested reinstall: heartbeat was not received");
+            }
+            continue;
+        }
+
         let last_hook = LAST_HOOK_MS.load(Ordering::Relaxed);
         if last_hook == 0 {
             continue;
         }
 
-        let now = monotonic_ms();
         let since_hook = now.saturating_sub(last_hook);
         if since_hook < HOOK_STALL_MS {
             continue;
@@ -490,7 +1775,7 @@ fn watchdog_loop() {
             continue;
         }
 
-        if request_reinstall() {
+        if request_reinstall(HookRecoveryReason::Stalled) {
             LAST_REINSTALL_MS.store(now, Ordering::Relaxed);
             warn!(
                 "Hook watchdog requested reinstall: last_hook={}ms ago, last_input={}ms ago",
@@ -503,7 +1788,33 @@ fn watchdog_loop() {
 /// Inject a key event (scancode).
 /// up: true for KeyUp, false for KeyDown.
 pub fn inject_scancode(sc: u16, ext: bool, up: bool) -> anyhow::Result<()> {
-    let mut flags = KEYEVENTF_SCANCODE;
+    INJECTED_MODIFIERS.record(sc, ext, up);
+    send_inputs(&[scancode_input(sc, ext, up)])
+}
+
+/// Sends a key the fast path held back on as it was typed, with its
+/// virtual key as well as its scancode.
+fn inject_passed_key(key: &HookKey) -> anyhow::Result<()> {
+    INJECTED_MODIFIERS.record(key.sc, key.ext, key.up);
+    send_inputs(&[key_input(
+        VIRTUAL_KEY(key.vk as u16),
+        key.sc,
+        key.ext,
+        key.up,
+    )])
+}
+
+fn scancode_input(sc: u16, ext: bool, up: bool) -> INPUT {
+    key_input(VIRTUAL_KEY(0), sc, ext, up)
+}
+
+/// Key event by scancode, or by virtual key with `sc` when `vk` is set.
+fn key_input(vk: VIRTUAL_KEY, sc: u16, ext: bool, up: bool) -> INPUT {
+    let mut flags = if vk.0 == 0 {
+        KEYEVENTF_SCANCODE
+    } else {
+        KEYBD_EVENT_FLAGS(0)
+    };
     if ext {
         flags |= KEYEVENTF_EXTENDEDKEY;
     }
@@ -511,53 +1822,180 @@ pub fn inject_scancode(sc: u16, ext: bool, up: bool) -> anyhow::Result<()> {
         flags |= KEYEVENTF_KEYUP;
     }
 
-    let input = INPUT {
+    INPUT {
         r#type: INPUT_KEYBOARD,
         Anonymous: INPUT_0 {
             ki: KEYBDINPUT {
-                wVk: VIRTUAL_KEY(0),
+                wVk: vk,
                 wScan: sc,
                 dwFlags: flags,
                 time: 0,
                 dwExtraInfo: INJECTED_EXTRA_INFO,
             },
         },
-    };
-
-    unsafe {
-        SendInput(&[input], std::mem::size_of::<INPUT>() as i32);
     }
-    Ok(())
 }
 
-/// Inject a unicode character.
+/// Inject a unicode character. Both halves of a surrogate pair go out in
+/// one SendInput call so nothing can be injected between them.
 pub fn inject_unicode(c: char, up: bool) -> anyhow::Result<()> {
+    let mut buf = [0; 2];
+    let inputs: Vec<INPUT> = c
+        .encode_utf16(&mut buf)
+        .iter()
+        .map(|&unit| unicode_input(unit, up))
+        .collect();
+    send_inputs(&inputs)
+}
+
+/// Inject `text` as Unicode key events in a single batch, so grapheme
+/// clusters (emoji sequences, combining marks) arrive uninterrupted.
+pub fn inject_text(text: &str) -> anyhow::Result<()> {
+    let inputs: Vec<INPUT> = unicode_key_sequence(text)
+        .into_iter()
+        .map(|(unit, up)| unicode_input(unit, up))
+        .collect();
+    send_inputs(&inputs)
+}
+
+/// UTF-16 units with their up flag: per code point, all downs then all ups.
+fn unicode_key_sequence(text: &str) -> Vec<(u16, bool)> {
+    let mut sequence = Vec::new();
+    let mut buf = [0; 2];
+    for c in text.chars() {
+        let units = c.encode_utf16(&mut buf);
+        sequence.extend(units.iter().map(|&unit| (unit, false)));
+        sequence.extend(units.iter().map(|&unit| (unit, true)));
+    }
+    sequence
+}
+
+fn unicode_input(unit: u16, up: bool) -> INPUT {
     let mut flags = KEYEVENTF_UNICODE;
     if up {
         flags |= KEYEVENTF_KEYUP;
     }
-
-    // Convert char to utf-16
-    let mut buf = [0; 2];
-    let encoded = c.encode_utf16(&mut buf);
-
-    for code_unit in encoded {
-        let input = INPUT {
-            r#type: INPUT_KEYBOARD,
-            Anonymous: INPUT_0 {
-                ki: KEYBDINPUT {
-                    wVk: VIRTUAL_KEY(0),
-                    wScan: *code_unit,
-                    dwFlags: flags,
-                    time: 0,
-                    dwExtraInfo: INJECTED_EXTRA_INFO,
-                },
+    INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: VIRTUAL_KEY(0),
+                wScan: unit,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: INJECTED_EXTRA_INFO,
             },
-        };
+        },
+    }
+}
 
-        unsafe {
-            SendInput(&[input], std::mem::size_of::<INPUT>() as i32);
+/// Sends `inputs`, calling SendInput again for the events it did not take.
+/// What is still left after [`SEND_INPUT_ATTEMPTS`] calls is dropped.
+fn send_inputs(inputs: &[INPUT]) -> anyhow::Result<()> {
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    let mut rest = inputs;
+    let mut attempts = 0;
+    let mut elevated = false;
+    while !rest.is_empty() && attempts < SEND_INPUT_ATTEMPTS {
+        if attempts > 0 {
+            thread::sleep(Duration::from_millis(SEND_INPUT_RETRY_DELAY_MS));
+        }
+        attempts += 1;
+        let sent = unsafe { SendInput(rest, std::mem::size_of::<INPUT>() as i32) } as usize;
+        rest = &rest[sent.min(rest.len())..];
+        // UIPI keeps refusing; retrying only delays the next events.
+        if sent == 0 && crate::elevation::foreground_blocks_injection() {
+            elevated = true;
+            break;
         }
     }
-    Ok(())
+    INJECTION_STATS.record(attempts, rest.len(), elevated);
+    if rest.is_empty() {
+        return Ok(());
+    }
+    let error = windows::core::Error::from_win32();
+    warn!(
+        "SendInput dropped {} of {} events{}: {}",
+        rest.len(),
+        inputs.len(),
+        if elevated { " (elevated window)" } else { "" },
+        error
+    );
+    Err(anyhow::anyhow!("SendInput dropped {} events", rest.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_events_are_batched_without_splitting_surrogates() {
+        let mut batch = Vec::new();
+        assert!(push_key_input(
+            &mut batch,
+            &InputEvent::Unicode('😀', false)
+        ));
+        assert!(push_key_input(
+            &mut batch,
+            &InputEvent::Scancode(0x1E, false, false)
+        ));
+        assert!(!push_key_input(&mut batch, &InputEvent::Delay(5)));
+        assert_eq!(batch.len(), 3);
+
+        assert_eq!(chunk_len(&batch, 0), 3);
+        assert_eq!(chunk_len(&batch, 1), 2);
+        assert_eq!(chunk_len(&batch[2..], 1), 1);
+    }
+
+    #[test]
+    fn test_injected_modifiers_track_unreleased_downs() {
+        let modifiers = InjectedModifiers::new();
+        modifiers.record(0x2A, false, false); // LShift down
+        modifiers.record(0x1D, true, false); // RCtrl down
+        modifiers.record(0x1E, false, false); // 'a' is not a modifier
+        modifiers.record(0x2A, false, true); // LShift up
+
+        assert_eq!(modifiers.take(), vec![(0x1D, true)]);
+        assert!(modifiers.take().is_empty());
+    }
+
+    #[test]
+    fn test_hotkey_needs_exact_modifiers() {
+        let hotkey = Hotkey {
+            vk: 0x7A, // F11
+            ctrl: true,
+            alt: true,
+            ..Hotkey::default()
+        };
+        let hotkeys = vec![
+            ("unset".to_string(), Hotkey::default()),
+            ("previous_layout".to_string(), hotkey),
+        ];
+
+        assert_eq!(find_hotkey(&hotkeys, &hotkey), Some("previous_layout"));
+        let with_shift = Hotkey {
+            shift: true,
+            ..hotkey
+        };
+        assert_eq!(find_hotkey(&hotkeys, &with_shift), None);
+        assert_eq!(find_hotkey(&hotkeys, &Hotkey::default()), None);
+    }
+
+    #[test]
+    fn test_unicode_key_sequence_keeps_surrogate_pairs_together() {
+        // U+1F600 is D83D DE00 in UTF-16.
+        assert_eq!(
+            unicode_key_sequence("a\u{1F600}"),
+            vec![
+                (0x61, false),
+                (0x61, true),
+                (0xD83D, false),
+                (0xDE00, false),
+                (0xD83D, true),
+                (0xDE00, true),
+            ]
+        );
+    }
 }
@@ -0,0 +1,228 @@
+//! Which key positions a layout defines, section by section, and what it
+//! lacks for the kind of layout it is meant to be.
+
+use crate::jis_map::{display_name, key_to_rc};
+use crate::section_selector::{SectionSelector, SectionShift, SectionSlot};
+use crate::types::{Layout, Plane, Rc, Section};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Kind of layout whose sections [`layout_coverage`] expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CoverageTemplate {
+    /// One kana section whose sub-planes hold the chords, as in 新下駄.
+    #[default]
+    Chord,
+    /// Kana and alphanumeric sections, each unshifted and with either
+    /// thumb shift: six sections.
+    Nicola,
+    /// The NICOLA sections plus 拡張親指シフト1 and 2.
+    ExtendedThumb,
+}
+
+impl CoverageTemplate {
+    fn slots(self) -> Vec<SectionSlot> {
+        let nicola = [true, false].into_iter().flat_map(|japanese| {
+            [
+                SectionShift::None,
+                SectionShift::LeftThumb,
+                SectionShift::RightThumb,
+            ]
+            .map(|modifier| SectionSlot::new(japanese, false, modifier))
+        });
+        match self {
+            CoverageTemplate::Chord => vec![SectionSlot::new(true, false, SectionShift::None)],
+            CoverageTemplate::Nicola => nicola.collect(),
+            CoverageTemplate::ExtendedThumb => nicola
+                .chain(
+                    [SectionShift::Ext1, SectionShift::Ext2]
+                        .map(|modifier| SectionSlot::new(true, false, modifier)),
+                )
+                .collect(),
+        }
+    }
+}
+
+/// Result of [`layout_coverage`]. Lists are sorted for stable display.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LayoutCoverage {
+    pub sections: Vec<SectionCoverage>,
+    /// Sections the template expects that the layout lacks, by the name
+    /// the layout would use for them.
+    pub missing_sections: Vec<String>,
+    /// Sub-plane tags naming keys that type nothing on their own.
+    pub undefined_tag_keys: Vec<UndefinedTagKeys>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SectionCoverage {
+    pub name: String,
+    /// The base plane first, then sub-planes by tag.
+    pub planes: Vec<PlaneCoverage>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PlaneCoverage {
+    /// Sub-plane tag, or `None` for the base plane.
+    pub tag: Option<String>,
+    /// Defined positions as (row, col).
+    pub defined: Vec<(u8, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UndefinedTagKeys {
+    pub section: String,
+    pub tag: String,
+    /// Keys of the tag that neither the section's base plane nor a section
+    /// it inherits from (`[継承]`) defines.
+    pub keys: Vec<String>,
+}
+
+/// Reports what `layout` defines and what it lacks for `template`.
+pub fn layout_coverage(layout: &Layout, template: CoverageTemplate) -> LayoutCoverage {
+    let mut names: Vec<&String> = layout.sections.keys().collect();
+    names.sort();
+    let fallbacks: HashMap<&str, &str> = layout
+        .section_fallbacks
+        .iter()
+        .map(|(section, parent)| (section.as_str(), parent.as_str()))
+        .collect();
+
+    let mut coverage = LayoutCoverage::default();
+    for name in names {
+        let section = &layout.sections[name];
+        let mut tags: Vec<&String> = section.sub_planes.keys().collect();
+        tags.sort();
+        let mut planes = vec![plane_coverage(None, &section.base_plane)];
+        planes.extend(
+            tags.iter()
+                .map(|tag| plane_coverage(Some(tag), &section.sub_planes[*tag])),
+        );
+        coverage.sections.push(SectionCoverage {
+            name: name.clone(),
+            planes,
+        });
+
+        let mut tags: Vec<&String> = section.sub_plane_keys.keys().collect();
+        tags.sort();
+        for tag in tags {
+            let keys: Vec<String> = section.sub_plane_keys[tag]
+                .iter()
+                .filter(|key| {
+                    key_to_rc(**key)
+                        .is_some_and(|rc| !base_defines(layout, &fallbacks, section, rc))
+                })
+                .map(|key| display_name(*key))
+                .collect();
+            if !keys.is_empty() {
+                coverage.undefined_tag_keys.push(UndefinedTagKeys {
+                    section: name.clone(),
+                    tag: tag.clone(),
+                    keys,
+                });
+            }
+        }
+    }
+
+    let selector = SectionSelector::with_overrides(&layout.section_names);
+    coverage.missing_sections = template
+        .slots()
+        .into_iter()
+        .map(|slot| selector.name(slot).to_string())
+        .filter(|name| !layout.sections.contains_key(name))
+        .collect();
+    coverage
+}
+
+fn plane_coverage(tag: Option<&String>, plane: &Plane) -> PlaneCoverage {
+    let defined: BTreeSet<(u8, u8)> = plane.map.keys().map(|rc| (rc.row, rc.col)).collect();
+    PlaneCoverage {
+        tag: tag.cloned(),
+        defined: defined.into_iter().collect(),
+    }
+}
+
+/// Whether the base plane of `section`, or of a section it inherits from,
+/// defines `rc`.
+fn base_defines(
+    layout: &Layout,
+    fallbacks: &HashMap<&str, &str>,
+    section: &Section,
+    rc: Rc,
+) -> bool {
+    let mut visited = vec![section.name.as_str()];
+    let mut current = Some(section);
+    while let Some(section) = current {
+        if section.base_plane.map.contains_key(&rc) {
+            return true;
+        }
+        current = fallbacks
+            .get(section.name.as_str())
+            .filter(|parent| !visited.contains(parent))
+            .and_then(|parent| {
+                visited.push(parent);
+                layout.sections.get(*parent)
+            });
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_yab_content;
+
+    #[test]
+    fn test_coverage_of_a_chord_layout() {
+        let layout = parse_yab_content(
+            "
+[ローマ字シフト無し]
+1,2
+q,xx
+
+<w>
+無,あ
+い,無
+
+<e>
+う,無
+",
+        )
+        .unwrap();
+        let coverage = layout_coverage(&layout, CoverageTemplate::Chord);
+        assert!(coverage.missing_sections.is_empty());
+        assert_eq!(coverage.sections.len(), 1);
+        let planes = &coverage.sections[0].planes;
+        assert_eq!(planes[0].tag, None);
+        assert_eq!(planes[0].defined, vec![(0, 0), (0, 1), (1, 0)]);
+        assert_eq!(planes[1].tag.as_deref(), Some("<e>"));
+        assert_eq!(planes[2].defined, vec![(0, 1), (1, 0)]);
+        assert_eq!(
+            coverage.undefined_tag_keys,
+            vec![
+                UndefinedTagKeys {
+                    section: "ローマ字シフト無し".to_string(),
+                    tag: "<e>".to_string(),
+                    keys: vec!["e".to_string()],
+                },
+                UndefinedTagKeys {
+                    section: "ローマ字シフト無し".to_string(),
+                    tag: "<w>".to_string(),
+                    keys: vec!["w".to_string()],
+                },
+            ]
+        );
+
+        let nicola = layout_coverage(&layout, CoverageTemplate::Nicola);
+        assert_eq!(
+            nicola.missing_sections,
+            vec![
+                "ローマ字左親指シフト",
+                "ローマ字右親指シフト",
+                "英数シフト無し",
+                "英数左親指シフト",
+                "英数右親指シフト",
+            ]
+        );
+    }
+}
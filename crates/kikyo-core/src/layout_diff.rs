@@ -0,0 +1,314 @@
+use crate::chord_engine::SpaceWidth;
+use crate::parser::{
+    self, DAKUTEN_TOKEN, FULL_WIDTH_SPACE_TOKEN, HALF_WIDTH_SPACE_TOKEN, HANDAKUTEN_TOKEN,
+    IME_COMMAND_TOKENS,
+};
+use crate::tenkey::TENKEY_TOKEN;
+use crate::types::{KeySpec, Layout, Plane, Rc, Section, Token};
+use crate::voicing::Voicing;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Structured difference between two layouts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LayoutDiff {
+    pub name_a: Option<String>,
+    pub name_b: Option<String>,
+    /// Sections only present in layout B.
+    pub added_sections: Vec<String>,
+    /// Sections only present in layout A.
+    pub removed_sections: Vec<String>,
+    /// Sections present in both layouts whose contents differ.
+    pub changed_sections: Vec<SectionDiff>,
+    pub added_function_key_swaps: Vec<(String, String)>,
+    pub removed_function_key_swaps: Vec<(String, String)>,
+}
+
+impl LayoutDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_sections.is_empty()
+            && self.removed_sections.is_empty()
+            && self.changed_sections.is_empty()
+            && self.added_function_key_swaps.is_empty()
+            && self.removed_function_key_swaps.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SectionDiff {
+    pub name: String,
+    /// Sub-plane tags (e.g. "<k>") only present in layout B.
+    pub added_planes: Vec<String>,
+    /// Sub-plane tags only present in layout A.
+    pub removed_planes: Vec<String>,
+    /// Planes present in both sections whose cells differ.
+    pub changed_planes: Vec<PlaneDiff>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PlaneDiff {
+    /// Sub-plane tag, or `None` for the base plane.
+    pub tag: Option<String>,
+    pub cells: Vec<CellDiff>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CellDiff {
+    pub row: u8,
+    pub col: u8,
+    /// Cell text in layout A (`None` if undefined).
+    pub before: Option<String>,
+    /// Cell text in layout B (`None` if undefined).
+    pub after: Option<String>,
+}
+
+/// Loads two .yab files and compares them.
+pub fn compare_layouts<P: AsRef<Path>, Q: AsRef<Path>>(
+    path_a: P,
+    path_b: Q,
+) -> anyhow::Result<LayoutDiff> {
+    let a = parser::load_yab(path_a)?;
+    let b = parser::load_yab(path_b)?;
+    Ok(diff_layouts(&a, &b))
+}
+
+/// Compares two parsed layouts. Output lists are sorted for stable display.
+pub fn diff_layouts(a: &Layout, b: &Layout) -> LayoutDiff {
+    let mut diff = LayoutDiff {
+        name_a: a.name.clone(),
+        name_b: b.name.clone(),
+        ..LayoutDiff::default()
+    };
+
+    let names: BTreeSet<&String> = a.sections.keys().chain(b.sections.keys()).collect();
+    for name in names {
+        match (a.sections.get(name), b.sections.get(name)) {
+            (Some(sa), Some(sb)) => {
+                if let Some(section_diff) = diff_sections(name, sa, sb) {
+                    diff.changed_sections.push(section_diff);
+                }
+            }
+            (None, Some(_)) => diff.added_sections.push(name.clone()),
+            (Some(_), None) => diff.removed_sections.push(name.clone()),
+            (None, None) => {}
+        }
+    }
+
+    for swap in &b.function_key_swaps {
+        if !a.function_key_swaps.contains(swap) {
+            diff.added_function_key_swaps.push(swap.clone());
+        }
+    }
+    for swap in &a.function_key_swaps {
+        if !b.function_key_swaps.contains(swap) {
+            diff.removed_function_key_swaps.push(swap.clone());
+        }
+    }
+
+    diff
+}
+
+fn diff_sections(name: &str, a: &Section, b: &Section) -> Option<SectionDiff> {
+    let mut diff = SectionDiff {
+        name: name.to_string(),
+        ..SectionDiff::default()
+    };
+
+    if let Some(plane_diff) = diff_planes(None, &a.base_plane, &b.base_plane) {
+        diff.changed_planes.push(plane_diff);
+    }
+
+    let tags: BTreeSet<&String> = a.sub_planes.keys().chain(b.sub_planes.keys()).collect();
+    for tag in tags {
+        match (a.sub_planes.get(tag), b.sub_planes.get(tag)) {
+            (Some(pa), Some(pb)) => {
+                if let Some(plane_diff) = diff_planes(Some(tag), pa, pb) {
+                    diff.changed_planes.push(plane_diff);
+                }
+            }
+            (None, Some(_)) => diff.added_planes.push(tag.clone()),
+            (Some(_), None) => diff.removed_planes.push(tag.clone()),
+            (None, None) => {}
+        }
+    }
+
+    if diff.added_planes.is_empty()
+        && diff.removed_planes.is_empty()
+        && diff.changed_planes.is_empty()
+    {
+        None
+    } else {
+        Some(diff)
+    }
+}
+
+fn diff_planes(tag: Option<&String>, a: &Plane, b: &Plane) -> Option<PlaneDiff> {
+    let positions: BTreeSet<(u8, u8)> = a
+        .map
+        .keys()
+        .chain(b.map.keys())
+        .map(|rc| (rc.row, rc.col))
+        .collect();
+
+    let mut cells = Vec::new();
+    for (row, col) in positions {
+        let rc = Rc::new(row, col);
+        let before = a.map.get(&rc).filter(|t| !matches!(t, Token::None));
+        let after = b.map.get(&rc).filter(|t| !matches!(t, Token::None));
+        if before != after {
+            cells.push(CellDiff {
+                row,
+                col,
+                before: before.map(token_display),
+                after: after.map(token_display),
+            });
+        }
+    }
+
+    if cells.is_empty() {
+        None
+    } else {
+        Some(PlaneDiff {
+            tag: tag.cloned(),
+            cells,
+        })
+    }
+}
+
+/// Renders a token in a compact, human-readable form for display.
+pub fn token_display(token: &Token) -> String {
+    match token {
+        Token::None => String::new(),
+        Token::ImeChar(text) => format!("'{}'", text),
+        Token::DirectChar(text) => format!("\"{}\"", text),
+        Token::KeySequence(seq) => {
+            let mut out = String::new();
+            for stroke in seq {
+                if stroke.mods.shift {
+                    out.push('S');
+                }
+                if stroke.mods.ctrl {
+                    out.push('C');
+                }
+                if stroke.mods.alt {
+                    out.push('A');
+                }
+                if stroke.mods.win {
+                    out.push('W');
+                }
+                match &stroke.key {
+                    KeySpec::Char(c) => out.push(*c),
+                    KeySpec::Scancode(sc, ext) => {
+                        out.push_str(&format!("{{sc:{:02X}{}}}", sc, if *ext { "e" } else { "" }))
+                    }
+                    KeySpec::VirtualKey(vk) => out.push_str(&format!("V{:02X}", vk)),
+                    KeySpec::ImeOn => out.push('日'),
+                    KeySpec::ImeOff => out.push('英'),
+                    KeySpec::Ime(command) => {
+                        if let Some((token, _)) =
+                            IME_COMMAND_TOKENS.iter().find(|(_, c)| c == command)
+                        {
+                            out.push_str(token);
+                        }
+                    }
+                    KeySpec::DirectString(s) => {
+                        out.push('"');
+                        out.push_str(s);
+                        out.push('"');
+                    }
+                    KeySpec::Paste(s) => {
+                        out.push_str("{paste \"");
+                        out.push_str(s);
+                        out.push_str("\"}");
+                    }
+                    KeySpec::Space(SpaceWidth::Full) => out.push_str(FULL_WIDTH_SPACE_TOKEN),
+                    KeySpec::Space(_) => out.push_str(HALF_WIDTH_SPACE_TOKEN),
+                    KeySpec::Voicing(Voicing::Dakuten) => out.push_str(DAKUTEN_TOKEN),
+                    KeySpec::Voicing(Voicing::Handakuten) => out.push_str(HANDAKUTEN_TOKEN),
+                    KeySpec::Dynamic(text) => out.push_str(&text.source()),
+                    KeySpec::TenkeyToggle => out.push_str(TENKEY_TOKEN),
+                }
+            }
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_yab_content;
+
+    const BASE: &str = "
+; Sample
+[ローマ字シフト無し]
+a,b,c
+<k>
+x,y,z
+
+[機能キー]
+無変換,拡張1
+";
+
+    #[test]
+    fn test_identical_layouts_have_empty_diff() {
+        let a = parse_yab_content(BASE).unwrap();
+        let b = parse_yab_content(BASE).unwrap();
+        let diff = diff_layouts(&a, &b);
+        assert!(diff.is_empty(), "{:?}", diff);
+    }
+
+    #[test]
+    fn test_cell_plane_and_section_changes() {
+        let a = parse_yab_content(BASE).unwrap();
+        let b = parse_yab_content(
+            "
+; Sample v2
+[ローマ字シフト無し]
+a,q,c,d
+<d>
+x
+
+[英数シフト無し]
+1
+",
+        )
+        .unwrap();
+        let diff = diff_layouts(&a, &b);
+
+        assert_eq!(diff.name_b.as_deref(), Some("Sample v2"));
+        assert_eq!(diff.added_sections, vec!["英数シフト無し".to_string()]);
+        assert_eq!(diff.removed_sections, vec!["機能キー".to_string()]);
+        assert_eq!(
+            diff.removed_function_key_swaps,
+            vec![("無変換".to_string(), "拡張1".to_string())]
+        );
+
+        assert_eq!(diff.changed_sections.len(), 1);
+        let section = &diff.changed_sections[0];
+        assert_eq!(section.added_planes, vec!["<d>".to_string()]);
+        assert_eq!(section.removed_planes, vec!["<k>".to_string()]);
+        assert_eq!(section.changed_planes.len(), 1);
+
+        let base = &section.changed_planes[0];
+        assert_eq!(base.tag, None);
+        assert_eq!(
+            base.cells,
+            vec![
+                CellDiff {
+                    row: 0,
+                    col: 1,
+                    before: Some("b".to_string()),
+                    after: Some("q".to_string()),
+                },
+                CellDiff {
+                    row: 0,
+                    col: 3,
+                    before: None,
+                    after: Some("d".to_string()),
+                },
+            ]
+        );
+    }
+}
@@ -1,11 +1,58 @@
+//! Kikyo's chord keyboard layout converter. To embed it, use [`api`],
+//! which is kept semver-stable; the other modules are Kikyo's internals.
+//!
+//! Without the `os-windows` feature only the modules that do not need
+//! Windows are built, so the engine can be tested anywhere.
+
+pub mod api;
+pub mod bundle;
 pub mod chord_engine;
+pub mod chord_test;
+pub mod clipboard;
+pub mod clock;
+pub mod compiled_layout;
+pub mod crash_report;
+pub mod dual_role;
+pub mod dynamic_text;
+#[cfg(feature = "os-windows")]
+pub mod elevation;
+pub mod emitter;
 pub mod engine;
+pub mod feedback;
+pub mod foreground;
+pub mod handle;
+pub mod hook_health;
 pub mod ime;
+pub mod injection_health;
+pub mod inspect;
 pub mod jis_map;
+pub mod key_swap;
+#[cfg(feature = "os-windows")]
 pub mod keyboard_hook;
+pub mod layout_coverage;
+pub mod layout_diff;
+#[cfg(feature = "os-windows")]
+pub mod notify;
 pub mod parser;
+pub mod recorder;
+pub mod resolver;
 pub mod romaji_map;
+pub mod schedule;
+pub mod scripting;
+pub mod section_selector;
+#[cfg(feature = "os-windows")]
+pub mod secure_input;
+pub mod stats;
+#[cfg(feature = "os-windows")]
+pub mod system_theme;
+pub mod tenkey;
+pub mod throttle;
+pub mod trace;
+pub mod training;
 pub mod types;
+pub mod undo;
+pub mod virtual_keyboard;
+pub mod voicing;
 
 #[cfg(test)]
 mod verify_ime_quotes;
@@ -0,0 +1,50 @@
+use windows::core::HSTRING;
+use windows::Data::Xml::Dom::XmlDocument;
+use windows::UI::Notifications::{ToastNotification, ToastNotificationManager};
+
+/// Shows a Windows toast. `app_id` must be the AppUserModelID the installer
+/// registered for the Start menu shortcut, otherwise Windows drops it.
+pub fn show_toast(app_id: &str, title: &str, body: &str) -> anyhow::Result<()> {
+    let xml = XmlDocument::new()?;
+    xml.LoadXml(&HSTRING::from(toast_xml(title, body)))?;
+    let toast = ToastNotification::CreateToastNotification(&xml)?;
+    ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(app_id))?.Show(&toast)?;
+    Ok(())
+}
+
+fn toast_xml(title: &str, body: &str) -> String {
+    format!(
+        "<toast><visual><binding template=\"ToastGeneric\">\
+         <text>{}</text><text>{}</text>\
+         </binding></visual><audio silent=\"true\"/></toast>",
+        escape_xml(title),
+        escape_xml(body)
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toast_xml_escapes_text() {
+        let xml = toast_xml("Kikyo", "<新下駄> & \"月\"");
+        assert!(xml.contains("<text>Kikyo</text>"));
+        assert!(xml.contains("<text>&lt;新下駄&gt; &amp; &quot;月&quot;</text>"));
+    }
+}
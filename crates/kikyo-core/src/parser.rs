@@ -1,13 +1,258 @@
-﻿use crate::types::{KeySpec, KeyStroke, Layout, Modifiers, Plane, Rc, Section, Token};
-use anyhow::Result;
+use crate::chord_engine::SpaceWidth;
+use crate::dynamic_text::DynamicText;
+use crate::tenkey::TENKEY_TOKEN;
+use crate::types::{
+    ImeCommand, KeySpec, KeyStroke, Layout, Modifiers, Plane, Rc, ScKey, Section, Token,
+};
+use crate::voicing::Voicing;
+use anyhow::{Context, Result};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tracing::{debug, warn};
 
+/// Token typing a half-width space whatever the IME setting.
+pub const HALF_WIDTH_SPACE_TOKEN: &str = "{半角空白}";
+/// Token typing a full-width space whatever the IME setting.
+pub const FULL_WIDTH_SPACE_TOKEN: &str = "{全角空白}";
+/// Token adding a dakuten to the kana typed before.
+pub const DAKUTEN_TOKEN: &str = "{濁点}";
+/// Token adding a handakuten to the kana typed before.
+pub const HANDAKUTEN_TOKEN: &str = "{半濁点}";
+/// Tokens of IME operations.
+pub const IME_COMMAND_TOKENS: [(&str, ImeCommand); 4] = [
+    ("{再変換}", ImeCommand::Reconvert),
+    ("{ひらがな}", ImeCommand::Hiragana),
+    ("{カタカナ}", ImeCommand::Katakana),
+    ("{半角カナ}", ImeCommand::HalfWidthKana),
+];
+
+/// Loads a layout file, resolving `#include "file"` directives.
 pub fn load_yab<P: AsRef<Path>>(path: P) -> Result<Layout> {
+    load_yab_included(path.as_ref(), &mut Vec::new())
+}
+
+/// `stack` holds the files currently being loaded, for cycle detection.
+fn load_yab_included(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Layout> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("cannot open {}", path.display()))?;
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        anyhow::bail!("include cycle: {}", chain.join(" -> "));
+    }
     let raw = std::fs::read(path)?;
     let text = decode_yab_bytes(&raw);
-    parse_yab_content(text.as_ref())
+    let mut layout = parse_yab_content(text.as_ref())?;
+
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for include in include_directives(text.as_ref()) {
+        let included = load_yab_included(&dir.join(&include), stack)
+            .with_context(|| format!("#include \"{}\" in {}", include, path.display()))?;
+        merge_included_layout(&mut layout, included);
+    }
+    stack.pop();
+
+    layout.max_chord_size = detect_max_chord_size(&layout);
+    Ok(layout)
+}
+
+/// Decoded text of a layout file and of every file it includes, with paths
+/// relative to the layout's directory. The layout itself comes first.
+pub fn layout_source_files<P: AsRef<Path>>(path: P) -> Result<Vec<(PathBuf, String)>> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let name = path
+        .file_name()
+        .with_context(|| format!("not a file: {}", path.display()))?;
+    let mut files: Vec<(PathBuf, String)> = Vec::new();
+    let mut queue = vec![PathBuf::from(name)];
+    while let Some(relative) = queue.pop() {
+        if files.iter().any(|(seen, _)| *seen == relative) {
+            continue;
+        }
+        let file = dir.join(&relative);
+        let raw =
+            std::fs::read(&file).with_context(|| format!("cannot open {}", file.display()))?;
+        let text = decode_yab_bytes(&raw).into_owned();
+        let base = relative.parent().unwrap_or(Path::new(""));
+        for include in include_directives(&text) {
+            queue.push(normalize_relative(&base.join(include)));
+        }
+        files.push((relative, text));
+    }
+    Ok(files)
+}
+
+/// Resolves `.` and `..` without touching the file system. Leading `..`
+/// are kept.
+pub(crate) fn normalize_relative(path: &Path) -> PathBuf {
+    use std::path::Component;
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                _ => out.push(".."),
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Splits `name@flag@!other` into the section name and its conditions,
+/// each as (negated, flag).
+pub fn split_section_conditions(name: &str) -> (&str, Vec<(bool, &str)>) {
+    let mut parts = name.split('@');
+    let base = parts.next().unwrap_or(name);
+    let conditions = parts
+        .map(|flag| match flag.strip_prefix('!') {
+            Some(flag) => (true, flag),
+            None => (false, flag),
+        })
+        .collect();
+    (base, conditions)
+}
+
+pub fn has_conditional_sections(layout: &Layout) -> bool {
+    layout.sections.keys().any(|name| name.contains('@'))
+}
+
+/// Truth of every conditional section, in a stable order. Unknown flags
+/// count as false.
+pub fn section_condition_states(layout: &Layout, flag: impl Fn(&str) -> Option<bool>) -> Vec<bool> {
+    let mut names: Vec<&String> = layout
+        .sections
+        .keys()
+        .filter(|name| name.contains('@'))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| conditions_hold(name, &flag))
+        .collect()
+}
+
+fn conditions_hold(name: &str, flag: &impl Fn(&str) -> Option<bool>) -> bool {
+    split_section_conditions(name)
+        .1
+        .iter()
+        .all(|(negated, name)| match flag(name) {
+            Some(value) => value != *negated,
+            None => {
+                warn!("Unknown section condition: {}", name);
+                false
+            }
+        })
+}
+
+/// Layout with `[section@flag]` sections applied: when all its conditions
+/// hold, a section's cells override those of `[section]`; otherwise it is
+/// dropped.
+pub fn resolve_conditional_sections(
+    layout: &Layout,
+    flag: impl Fn(&str) -> Option<bool>,
+) -> Layout {
+    let mut resolved = layout.clone();
+    resolved.sections.retain(|name, _| !name.contains('@'));
+    let mut conditional: Vec<(&String, &Section)> = layout
+        .sections
+        .iter()
+        .filter(|(name, _)| name.contains('@'))
+        .collect();
+    conditional.sort_by_key(|(name, _)| *name);
+    for (name, section) in conditional {
+        if !conditions_hold(name, &flag) {
+            continue;
+        }
+        let base = split_section_conditions(name).0.to_string();
+        let target = resolved
+            .sections
+            .entry(base.clone())
+            .or_insert_with(|| Section {
+                name_keys: tag_keys(&base),
+                name: base,
+                ..Section::default()
+            });
+        target.base_plane.map.extend(section.base_plane.map.clone());
+        for (tag, plane) in &section.sub_planes {
+            sub_plane_mut(target, tag).map.extend(plane.map.clone());
+        }
+    }
+    resolved
+}
+
+/// Path of an `#include "file"` line.
+fn parse_include_line(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("#include")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    rest.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+fn include_directives(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| parse_include_line(line.trim()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Adds what `included` defines and `layout` does not, cell by cell.
+/// The including file always wins; earlier includes win over later ones.
+fn merge_included_layout(layout: &mut Layout, included: Layout) {
+    let merge_plane = |plane: &mut Plane, from: Plane| {
+        for (rc, token) in from.map {
+            plane.map.entry(rc).or_insert(token);
+        }
+    };
+    for (name, section) in included.sections {
+        match layout.sections.get_mut(&name) {
+            Some(existing) => {
+                merge_plane(&mut existing.base_plane, section.base_plane);
+                for (tag, plane) in section.sub_planes {
+                    merge_plane(sub_plane_mut(existing, &tag), plane);
+                }
+            }
+            None => {
+                layout.sections.insert(name, section);
+            }
+        }
+    }
+    let mut function_key_swaps = included.function_key_swaps;
+    function_key_swaps.append(&mut layout.function_key_swaps);
+    layout.function_key_swaps = function_key_swaps;
+    let mut key_remaps = included.key_remaps;
+    key_remaps.append(&mut layout.key_remaps);
+    layout.key_remaps = key_remaps;
+    let mut chord_priorities = included.chord_priorities;
+    chord_priorities.append(&mut layout.chord_priorities);
+    layout.chord_priorities = chord_priorities;
+    let mut section_names: Vec<(String, String)> = included
+        .section_names
+        .into_iter()
+        .filter(|(standard, _)| !layout.section_names.iter().any(|(s, _)| s == standard))
+        .collect();
+    section_names.append(&mut layout.section_names);
+    layout.section_names = section_names;
+    let mut section_fallbacks = included.section_fallbacks;
+    section_fallbacks.append(&mut layout.section_fallbacks);
+    layout.section_fallbacks = section_fallbacks;
+    let mut overlap_overrides = included.overlap_overrides;
+    overlap_overrides.append(&mut layout.overlap_overrides);
+    layout.overlap_overrides = overlap_overrides;
+    let mut app_sections = included.app_sections;
+    app_sections.append(&mut layout.app_sections);
+    layout.app_sections = app_sections;
 }
 
 fn decode_yab_bytes<'a>(raw: &'a [u8]) -> std::borrow::Cow<'a, str> {
@@ -74,6 +319,7 @@ pub fn parse_yab_content(content: &str) -> Result<Layout> {
         let plane = Plane { map };
 
         if let Some(t) = tag {
+            sec.sub_plane_keys.insert(t.clone(), tag_keys(&t));
             sec.sub_planes.insert(t, plane);
         } else {
             sec.base_plane = plane;
@@ -82,6 +328,13 @@ pub fn parse_yab_content(content: &str) -> Result<Layout> {
 
     for line in content.lines() {
         let line = line.trim();
+        if current_section_name.is_none() {
+            if let Some(comment) = line.strip_prefix(';') {
+                if apply_info_line(&mut layout, comment) {
+                    continue;
+                }
+            }
+        }
         if layout.name.is_none() && line.starts_with(';') {
             let name = line.trim_start_matches(';').trim().to_string();
             if !name.is_empty() {
@@ -90,7 +343,7 @@ pub fn parse_yab_content(content: &str) -> Result<Layout> {
             continue;
         }
 
-        if line.is_empty() || line.starts_with(';') {
+        if line.is_empty() || line.starts_with(';') || parse_include_line(line).is_some() {
             continue;
         }
 
@@ -106,6 +359,7 @@ pub fn parse_yab_content(content: &str) -> Result<Layout> {
                 );
                 current_rows.clear();
 
+                current_section.name_keys = tag_keys(&name);
                 current_section.name = name.clone();
                 layout.sections.insert(name, current_section);
                 current_section = Section::default();
@@ -145,6 +399,72 @@ pub fn parse_yab_content(content: &str) -> Result<Layout> {
             continue;
         }
 
+        if current_section_name
+            .as_deref()
+            .is_some_and(is_key_remap_section_name)
+        {
+            layout.key_remaps.extend(parse_key_remap_line(line));
+            continue;
+        }
+
+        if current_section_name
+            .as_deref()
+            .is_some_and(is_priority_section_name)
+        {
+            if let Some((modifier, target)) = parse_priority_line(line) {
+                layout.chord_priorities.push((modifier, target));
+            }
+            continue;
+        }
+
+        if current_section_name
+            .as_deref()
+            .is_some_and(is_timing_section_name)
+        {
+            if let Some(entry) = parse_timing_line(line) {
+                layout.overlap_overrides.push(entry);
+            }
+            continue;
+        }
+
+        if current_section_name
+            .as_deref()
+            .is_some_and(is_info_section_name)
+        {
+            apply_info_line(&mut layout, line);
+            continue;
+        }
+
+        if current_section_name
+            .as_deref()
+            .is_some_and(is_section_name_directive)
+        {
+            if let Some((standard, custom)) = parse_function_key_swap_line(line) {
+                layout.section_names.push((standard, custom));
+            }
+            continue;
+        }
+
+        if current_section_name
+            .as_deref()
+            .is_some_and(is_fallback_section_name)
+        {
+            if let Some((section, parent)) = parse_function_key_swap_line(line) {
+                layout.section_fallbacks.push((section, parent));
+            }
+            continue;
+        }
+
+        if current_section_name
+            .as_deref()
+            .is_some_and(is_app_section_name)
+        {
+            if let Some((exe, tag)) = parse_function_key_swap_line(line) {
+                layout.app_sections.push((exe, tag));
+            }
+            continue;
+        }
+
         let tokens: Vec<String> = line.split(',').map(|s| s.trim().to_string()).collect();
         current_rows.push(tokens);
     }
@@ -152,6 +472,7 @@ pub fn parse_yab_content(content: &str) -> Result<Layout> {
     // Flush final
     if let Some(name) = current_section_name {
         flush_plane(&mut current_section, current_plane_tag, &current_rows);
+        current_section.name_keys = tag_keys(&name);
         current_section.name = name.clone();
         layout.sections.insert(name, current_section);
     }
@@ -162,39 +483,41 @@ pub fn parse_yab_content(content: &str) -> Result<Layout> {
 }
 
 fn detect_max_chord_size(layout: &Layout) -> usize {
-    for (section_name, section) in &layout.sections {
-        if count_valid_chord_keys(section_name) >= 2 {
-            return 3;
-        }
-        if section
-            .sub_planes
-            .keys()
-            .any(|tag| count_valid_chord_keys(tag) >= 2)
-        {
-            return 3;
-        }
+    let chorded = layout.sections.values().any(|section| {
+        section.name_keys.len() >= 2 || section.sub_plane_keys.values().any(|keys| keys.len() >= 2)
+    });
+    if chorded {
+        3
+    } else {
+        2
     }
-    2
 }
 
-fn count_valid_chord_keys(tag: &str) -> usize {
-    let mut count = 0;
-    let mut start = 0;
-    while let Some(open_rel) = tag[start..].find('<') {
-        let open = start + open_rel;
-        let Some(close_rel) = tag[open..].find('>') else {
+/// Keys named by the `<key>` tags in a section name or plane tag, in
+/// order. Tags naming no key are skipped.
+pub fn tag_keys(tag: &str) -> Vec<ScKey> {
+    let mut keys = Vec::new();
+    let mut rest = tag;
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>') else {
             break;
         };
-        let close = open + close_rel;
-        if close > open + 1 {
-            let key_name = &tag[open + 1..close];
-            if crate::jis_map::key_name_to_sc(key_name).is_some() {
-                count += 1;
-            }
-        }
-        start = close + 1;
+        keys.extend(crate::jis_map::key_name_to_key(
+            &rest[open + 1..open + close],
+        ));
+        rest = &rest[open + close + 1..];
     }
-    count
+    keys
+}
+
+/// Sub-plane `tag` of `section`, added with its keys if missing.
+fn sub_plane_mut<'s>(section: &'s mut Section, tag: &str) -> &'s mut Plane {
+    if !section.sub_planes.contains_key(tag) {
+        section
+            .sub_plane_keys
+            .insert(tag.to_string(), tag_keys(tag));
+    }
+    section.sub_planes.entry(tag.to_string()).or_default()
 }
 
 fn parse_token(raw: &str) -> Token {
@@ -219,6 +542,25 @@ fn parse_token(raw: &str) -> Token {
     let mut i = 0;
 
     while i < chars.len() {
+        // 0. {paste "..."} -> pasted through the clipboard
+        if let Some((text, next)) = parse_paste_block(&chars, i) {
+            seq.push(KeyStroke {
+                key: KeySpec::Paste(text),
+                mods: Modifiers::none(),
+            });
+            i = next;
+            continue;
+        }
+        // {date %Y/%m/%d}, {time}, {clipboard} -> filled in when typed
+        if let Some((text, next)) = parse_dynamic_block(&chars, i) {
+            seq.push(KeyStroke {
+                key: KeySpec::Dynamic(text),
+                mods: Modifiers::none(),
+            });
+            i = next;
+            continue;
+        }
+
         // 1. Check for quoted string
         if chars[i] == '"' || chars[i] == '\'' {
             let quote = chars[i];
@@ -268,7 +610,12 @@ fn parse_token(raw: &str) -> Token {
 
         // Let's grab a chunk of chars until a quote is seen.
         let mut j = i;
-        while j < chars.len() && chars[j] != '"' && chars[j] != '\'' {
+        while j < chars.len()
+            && chars[j] != '"'
+            && chars[j] != '\''
+            && parse_paste_block(&chars, j).is_none()
+            && parse_dynamic_block(&chars, j).is_none()
+        {
             j += 1;
         }
 
@@ -298,6 +645,57 @@ fn parse_token(raw: &str) -> Token {
     }
 }
 
+/// Parses `{paste "text"}` starting at `start`. Returns the unquoted text and
+/// the index just past the closing brace.
+fn parse_paste_block(chars: &[char], start: usize) -> Option<(String, usize)> {
+    const OPEN: &str = "{paste";
+    let rest = chars.get(start..)?;
+    if rest.len() < OPEN.len() || !rest.iter().zip(OPEN.chars()).all(|(a, b)| *a == b) {
+        return None;
+    }
+    let mut i = start + OPEN.len();
+    while chars.get(i).is_some_and(|c| c.is_whitespace()) {
+        i += 1;
+    }
+    if chars.get(i) != Some(&'"') {
+        return None;
+    }
+    let open_quote = i;
+    i += 1;
+    let mut escaped = false;
+    while i < chars.len() {
+        if escaped {
+            escaped = false;
+        } else if chars[i] == '\\' {
+            escaped = true;
+        } else if chars[i] == '"' {
+            break;
+        }
+        i += 1;
+    }
+    let close_quote = i;
+    i += 1;
+    while chars.get(i).is_some_and(|c| c.is_whitespace()) {
+        i += 1;
+    }
+    if close_quote >= chars.len() || chars.get(i) != Some(&'}') {
+        return None;
+    }
+    let inner: String = chars[open_quote + 1..close_quote].iter().collect();
+    Some((parse_quoted(&inner, '"'), i + 1))
+}
+
+/// Parses `{date ...}`, `{time ...}` or `{clipboard}` starting at `start`.
+/// Returns the block and the index just past the closing brace.
+fn parse_dynamic_block(chars: &[char], start: usize) -> Option<(DynamicText, usize)> {
+    if chars.get(start) != Some(&'{') {
+        return None;
+    }
+    let close = start + chars[start..].iter().position(|&c| c == '}')?;
+    let inner: String = chars[start + 1..close].iter().collect();
+    Some((DynamicText::parse(&inner)?, close + 1))
+}
+
 pub fn parse_key_sequence_expanded(raw: &str) -> Vec<KeyStroke> {
     let mut seq = Vec::new();
     let chars: Vec<char> = raw.chars().collect();
@@ -358,6 +756,59 @@ fn parse_unit(chars: &[char]) -> (Vec<KeyStroke>, usize) {
     }
     let c = chars[0];
 
+    for (token, width) in [
+        (HALF_WIDTH_SPACE_TOKEN, SpaceWidth::Half),
+        (FULL_WIDTH_SPACE_TOKEN, SpaceWidth::Full),
+    ] {
+        let len = token.chars().count();
+        if chars.len() >= len && chars[..len].iter().copied().eq(token.chars()) {
+            return (
+                vec![KeyStroke {
+                    key: KeySpec::Space(width),
+                    mods: Modifiers::none(),
+                }],
+                len,
+            );
+        }
+    }
+    let len = TENKEY_TOKEN.chars().count();
+    if chars.len() >= len && chars[..len].iter().copied().eq(TENKEY_TOKEN.chars()) {
+        return (
+            vec![KeyStroke {
+                key: KeySpec::TenkeyToggle,
+                mods: Modifiers::none(),
+            }],
+            len,
+        );
+    }
+    for (token, command) in IME_COMMAND_TOKENS {
+        let len = token.chars().count();
+        if chars.len() >= len && chars[..len].iter().copied().eq(token.chars()) {
+            return (
+                vec![KeyStroke {
+                    key: KeySpec::Ime(command),
+                    mods: Modifiers::none(),
+                }],
+                len,
+            );
+        }
+    }
+    for (token, voicing) in [
+        (DAKUTEN_TOKEN, Voicing::Dakuten),
+        (HANDAKUTEN_TOKEN, Voicing::Handakuten),
+    ] {
+        let len = token.chars().count();
+        if chars.len() >= len && chars[..len].iter().copied().eq(token.chars()) {
+            return (
+                vec![KeyStroke {
+                    key: KeySpec::Voicing(voicing),
+                    mods: Modifiers::none(),
+                }],
+                len,
+            );
+        }
+    }
+
     // 1. Try Kana -> Romaji
     if let Some(romaji) = crate::romaji_map::kana_to_romaji(c) {
         let mut seq = Vec::new();
@@ -498,6 +949,105 @@ fn parse_function_key_swap_line(line: &str) -> Option<(String, String)> {
     Some((left, right))
 }
 
+fn is_key_remap_section_name(name: &str) -> bool {
+    compact_function_key_name(name) == "配置換え"
+}
+
+/// Reads "a,b" (a acts as b) or "a<->b" / "a⇔b" (a and b trade places).
+/// The swap form lets a pair with ';' be written, which starts a comment.
+fn parse_key_remap_line(line: &str) -> Vec<(String, String)> {
+    let swap = ["<->", "⇔"]
+        .iter()
+        .find_map(|sep| line.split_once(sep))
+        .map(|(a, b)| (compact_function_key_name(a), compact_function_key_name(b)));
+    match swap {
+        Some((a, b)) if !a.is_empty() && !b.is_empty() => vec![(a.clone(), b.clone()), (b, a)],
+        Some(_) => Vec::new(),
+        None => parse_function_key_swap_line(line).into_iter().collect(),
+    }
+}
+
+fn is_priority_section_name(name: &str) -> bool {
+    compact_function_key_name(name) == "優先順位"
+}
+
+fn is_section_name_directive(name: &str) -> bool {
+    compact_function_key_name(name) == "セクション名"
+}
+
+fn is_fallback_section_name(name: &str) -> bool {
+    compact_function_key_name(name) == "継承"
+}
+
+fn is_app_section_name(name: &str) -> bool {
+    compact_function_key_name(name) == "アプリ別"
+}
+
+fn is_timing_section_name(name: &str) -> bool {
+    compact_function_key_name(name) == "タイミング"
+}
+
+fn is_info_section_name(name: &str) -> bool {
+    compact_function_key_name(name) == "情報"
+}
+
+/// Reads "作者: name" style lines (also "=", "," and full-width "：").
+/// Returns false when the key is not a known metadata field.
+fn apply_info_line(layout: &mut Layout, line: &str) -> bool {
+    let Some(sep) = line.find([':', '：', '=', ',']) else {
+        return false;
+    };
+    let key = compact_function_key_name(&line[..sep]).to_lowercase();
+    let value = line[sep..]
+        .chars()
+        .skip(1)
+        .collect::<String>()
+        .trim()
+        .to_string();
+    if value.is_empty() {
+        return false;
+    }
+    let field = match key.as_str() {
+        "名前" | "名称" | "name" => &mut layout.name,
+        "作者" | "author" => &mut layout.info.author,
+        "バージョン" | "版" | "version" => &mut layout.info.version,
+        "説明" | "description" => &mut layout.info.description,
+        "url" | "ホームページ" | "homepage" => &mut layout.info.url,
+        _ => return false,
+    };
+    *field = Some(value);
+    true
+}
+
+/// Parses "k,j" (or "<k>,j"): the `<k>` plane wins for the k+j chord.
+fn parse_priority_line(line: &str) -> Option<(String, String)> {
+    let (left, right) = parse_function_key_swap_line(line)?;
+    Some((strip_key_brackets(left), strip_key_brackets(right)))
+}
+
+fn strip_key_brackets(name: String) -> String {
+    name.strip_prefix('<')
+        .and_then(|n| n.strip_suffix('>'))
+        .map(str::to_string)
+        .unwrap_or(name)
+}
+
+/// Reads "j,k,60" (a pair) or "j,60" (every pair with j); the overlap
+/// ratio is in percent.
+fn parse_timing_line(line: &str) -> Option<(Vec<String>, f64)> {
+    let parts: Vec<String> = line.split(',').map(compact_function_key_name).collect();
+    let (percent, keys) = parts.split_last()?;
+    let percent: f64 = percent.trim_end_matches('%').parse().ok()?;
+    if !(1..=2).contains(&keys.len())
+        || keys.iter().any(|k| k.is_empty())
+        || !(0.0..=100.0).contains(&percent)
+    {
+        return None;
+    }
+    let keys = keys.iter().cloned().map(strip_key_brackets).collect();
+    Some((keys, percent / 100.0))
+}
+
 fn parse_single_key_char(c: char) -> KeySpec {
     if let Some((sc, ext)) = special_key_scancode(c) {
         return KeySpec::Scancode(sc, ext);
@@ -641,6 +1191,68 @@ mod tests {
             }])
         );
 
+        // {paste "..."} -> Paste, mixable with other keys
+        assert_eq!(
+            parse_token("{paste \"お世話になっております\"}\"。\""),
+            Token::KeySequence(vec![
+                KeyStroke {
+                    key: KeySpec::Paste("お世話になっております".to_string()),
+                    mods: Modifiers::none(),
+                },
+                KeyStroke {
+                    key: KeySpec::DirectString("。".to_string()),
+                    mods: Modifiers::none(),
+                },
+            ])
+        );
+
+        // {date ...} / {clipboard} -> filled in when typed, mixable with other keys
+        assert_eq!(
+            parse_token("\"今日は\"{date %m月%d日}{clipboard}"),
+            Token::KeySequence(vec![
+                KeyStroke {
+                    key: KeySpec::DirectString("今日は".to_string()),
+                    mods: Modifiers::none(),
+                },
+                KeyStroke {
+                    key: KeySpec::Dynamic(DynamicText::Date("%m月%d日".to_string())),
+                    mods: Modifiers::none(),
+                },
+                KeyStroke {
+                    key: KeySpec::Dynamic(DynamicText::Clipboard),
+                    mods: Modifiers::none(),
+                },
+            ])
+        );
+
+        // {全角空白} / {半角空白} -> space of a fixed width
+        assert_eq!(
+            parse_token("a{全角空白}"),
+            Token::KeySequence(vec![
+                stroke_char('a'),
+                KeyStroke {
+                    key: KeySpec::Space(SpaceWidth::Full),
+                    mods: Modifiers::none(),
+                },
+            ])
+        );
+        assert_eq!(
+            parse_token("'{半角空白}'"),
+            Token::KeySequence(vec![KeyStroke {
+                key: KeySpec::Space(SpaceWidth::Half),
+                mods: Modifiers::none(),
+            }])
+        );
+
+        // {濁点} / {半濁点} -> voices the kana typed before
+        assert_eq!(
+            parse_token("{半濁点}"),
+            Token::KeySequence(vec![KeyStroke {
+                key: KeySpec::Voicing(Voicing::Handakuten),
+                mods: Modifiers::none(),
+            }])
+        );
+
         // 'です' -> Expanded to d,e,s,u
         assert_eq!(
             parse_token("'です'"),
@@ -783,6 +1395,13 @@ mod tests {
                 mods: Modifiers::none(),
             }])
         );
+        assert_eq!(
+            parse_token("'{カタカナ}'"),
+            Token::KeySequence(vec![KeyStroke {
+                key: KeySpec::Ime(ImeCommand::Katakana),
+                mods: Modifiers::none(),
+            }])
+        );
     }
 
     #[test]
@@ -916,6 +1535,124 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_conditional_sections_override_when_flag_holds() {
+        let content = "
+[ローマ字シフト無し]
+1,2
+[ローマ字シフト無し@拡張親指有効]
+9
+[英数シフト無し@!拡張親指有効]
+x
+";
+        let layout = parse_yab_content(content).expect("Failed to parse");
+        assert!(has_conditional_sections(&layout));
+
+        let on = resolve_conditional_sections(&layout, |flag| Some(flag == "拡張親指有効"));
+        let base = &on.sections["ローマ字シフト無し"].base_plane.map;
+        assert_eq!(base.get(&Rc::new(0, 0)), Some(&parse_token("9")));
+        assert_eq!(base.get(&Rc::new(0, 1)), Some(&parse_token("2")));
+        assert!(!on.sections.contains_key("英数シフト無し"));
+
+        let off = resolve_conditional_sections(&layout, |_| Some(false));
+        let base = &off.sections["ローマ字シフト無し"].base_plane.map;
+        assert_eq!(base.get(&Rc::new(0, 0)), Some(&parse_token("1")));
+        assert!(off.sections.contains_key("英数シフト無し"));
+        assert!(!has_conditional_sections(&off));
+    }
+
+    #[test]
+    fn test_include_merges_sections_and_detects_cycles() {
+        let dir = std::env::temp_dir().join(format!("kikyo-include-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+        std::fs::write(
+            dir.join("common").join("base.yab"),
+            "[セクション名]\nローマ字シフト無し,基本\n[ローマ字シフト無し]\n1,2\n[ローマ字左親指シフト]\nx\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("variant.yab"),
+            "; Variant\n#include \"common/base.yab\"\n[セクション名]\nローマ字シフト無し,基本\n[ローマ字シフト無し]\n9\n",
+        )
+        .unwrap();
+
+        let layout = load_yab(dir.join("variant.yab")).expect("load with include");
+        assert_eq!(layout.name, Some("Variant".to_string()));
+        let base = &layout.sections["ローマ字シフト無し"].base_plane.map;
+        assert_eq!(base.get(&Rc::new(0, 0)), Some(&parse_token("9")));
+        assert_eq!(base.get(&Rc::new(0, 1)), Some(&parse_token("2")));
+        assert!(layout.sections.contains_key("ローマ字左親指シフト"));
+        assert_eq!(
+            layout.section_names,
+            vec![("ローマ字シフト無し".to_string(), "基本".to_string())]
+        );
+
+        std::fs::write(dir.join("a.yab"), "#include \"b.yab\"\n").unwrap();
+        std::fs::write(dir.join("b.yab"), "#include \"a.yab\"\n").unwrap();
+        let err = load_yab(dir.join("a.yab")).unwrap_err();
+        assert!(format!("{:#}", err).contains("include cycle"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_layout_source_files_follow_includes() {
+        let dir =
+            std::env::temp_dir().join(format!("kikyo-include-src-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+        std::fs::write(
+            dir.join("common").join("base.yab"),
+            "#include \"../variant.yab\"\n[ローマ字シフト無し]\n1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("variant.yab"),
+            "#include \"./common/base.yab\"\n[ローマ字シフト無し]\n9\n",
+        )
+        .unwrap();
+
+        let files = layout_source_files(dir.join("variant.yab")).expect("read sources");
+        let paths: Vec<PathBuf> = files.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("variant.yab"),
+                PathBuf::from("common").join("base.yab")
+            ]
+        );
+        assert!(files[1].1.contains("[ローマ字シフト無し]"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_layout_info() {
+        let content = "; 新下駄配列
+;作者：sample
+; バージョン: 1.2
+; URL: https://example.com/shingeta
+[情報]
+説明 = 下駄配列の改良版
+[ローマ字シフト無し]
+; 作者: not metadata inside a section
+a,b
+";
+        let layout = parse_yab_content(content).expect("Failed to parse");
+        assert_eq!(layout.name, Some("新下駄配列".to_string()));
+        assert_eq!(layout.info.author, Some("sample".to_string()));
+        assert_eq!(layout.info.version, Some("1.2".to_string()));
+        assert_eq!(
+            layout.info.url,
+            Some("https://example.com/shingeta".to_string())
+        );
+        assert_eq!(
+            layout.info.description,
+            Some("下駄配列の改良版".to_string())
+        );
+    }
+
     #[test]
     fn test_parse_layout_name() {
         // Case 1: Standard (First line)
@@ -976,6 +1713,111 @@ a,b
         );
     }
 
+    #[test]
+    fn test_parse_priority_section() {
+        let content = "
+[優先順位]
+k, j
+<d>,f
+a,b,c
+
+[ローマ字シフト無し]
+a,b
+";
+        let layout = parse_yab_content(content).expect("Failed");
+        assert_eq!(
+            layout.chord_priorities,
+            vec![
+                ("k".to_string(), "j".to_string()),
+                ("d".to_string(), "f".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_timing_section() {
+        let content = "
+[タイミング]
+j, k, 60
+<f>,50%
+a,b,c,10
+j,200
+
+[ローマ字シフト無し]
+a,b
+";
+        let layout = parse_yab_content(content).expect("Failed");
+        assert_eq!(
+            layout.overlap_overrides,
+            vec![
+                (vec!["j".to_string(), "k".to_string()], 0.6),
+                (vec!["f".to_string()], 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_fallback_section() {
+        let content = "
+[継承]
+ローマ字小指シフト, 記号
+記号,ローマ字シフト無し
+不正な行
+
+[ローマ字シフト無し]
+a,b
+";
+        let layout = parse_yab_content(content).expect("Failed");
+        assert_eq!(
+            layout.section_fallbacks,
+            vec![
+                ("ローマ字小指シフト".to_string(), "記号".to_string()),
+                ("記号".to_string(), "ローマ字シフト無し".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_app_sections() {
+        let content = "
+[アプリ別]
+EXCEL.EXE, 表計算
+WindowsTerminal.exe,端末
+
+[ローマ字シフト無し:端末]
+a,b
+";
+        let layout = parse_yab_content(content).expect("Failed");
+        assert_eq!(
+            layout.app_sections,
+            vec![
+                ("EXCEL.EXE".to_string(), "表計算".to_string()),
+                ("WindowsTerminal.exe".to_string(), "端末".to_string()),
+            ]
+        );
+        assert!(layout.sections.contains_key("ローマ字シフト無し:端末"));
+    }
+
+    #[test]
+    fn test_parse_section_name_directive() {
+        let content = "
+[セクション名]
+ローマ字シフト無し, かな
+ローマ字左親指シフト,かな左
+
+[かな]
+a,b
+";
+        let layout = parse_yab_content(content).expect("Failed");
+        assert_eq!(
+            layout.section_names,
+            vec![
+                ("ローマ字シフト無し".to_string(), "かな".to_string()),
+                ("ローマ字左親指シフト".to_string(), "かな左".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_sets_max_chord_size_to_two_without_double_modifier_tag() {
         let content = "
@@ -1008,6 +1850,36 @@ xx,xx,3,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
         assert_eq!(layout.max_chord_size, 3);
     }
 
+    #[test]
+    fn test_parse_indexes_tag_keys() {
+        let content = "
+[<q>ローマ字]
+xx
+
+[ローマ字シフト無し]
+<q><無効><w>
+xx,xx,3,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+
+[ローマ字シフト無し@左手]
+<e>
+xx,xx,xx,4,xx,xx,xx,xx,xx,xx,xx,xx,xx
+";
+        let layout = parse_yab_content(content).expect("Failed");
+        let q = crate::jis_map::key_name_to_key("q").unwrap();
+        let w = crate::jis_map::key_name_to_key("w").unwrap();
+        assert_eq!(layout.sections["<q>ローマ字"].name_keys, vec![q]);
+        let section = &layout.sections["ローマ字シフト無し"];
+        assert!(section.name_keys.is_empty());
+        assert_eq!(section.sub_plane_keys["<q><無効><w>"], vec![q, w]);
+
+        let resolved = resolve_conditional_sections(&layout, |_| Some(true));
+        let e = crate::jis_map::key_name_to_key("e").unwrap();
+        assert_eq!(
+            resolved.sections["ローマ字シフト無し"].sub_plane_keys["<e>"],
+            vec![e]
+        );
+    }
+
     #[test]
     fn test_decode_sjis() {
         // "テスト" in Shift_JIS
@@ -0,0 +1,233 @@
+use crate::chord_engine::Profile;
+use crate::trace::{keys_label, TraceKind};
+use crate::types::{HeldModifier, KeyAction, ScKey, ShiftSide};
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Longest recording; a forgotten recorder stops on its own.
+pub const MAX_RECORDING: Duration = Duration::from_secs(60 * 60);
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static RECORDER: Mutex<Option<Recording>> = Mutex::new(None);
+
+struct Recording {
+    /// Entries for the writer thread, so the hook never waits on the disk.
+    tx: Sender<RecordEntry>,
+    writer: JoinHandle<()>,
+    path: PathBuf,
+    started: Instant,
+    until: Instant,
+}
+
+/// One line of a recording. Output text is not written, only key positions,
+/// but together with the layout in `Start` they show what was typed: treat a
+/// recording like the text typed while it ran.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordEntry {
+    Start {
+        profile: Box<Profile>,
+    },
+    Key {
+        t_ms: u64,
+        key: String,
+        up: bool,
+        shift: bool,
+        modifier: Option<&'static str>,
+        action: &'static str,
+        /// Number of injected events for `Inject`.
+        events: usize,
+    },
+    Output {
+        t_ms: u64,
+        kind: TraceKind,
+        keys: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecordingStatus {
+    pub path: String,
+    pub remaining_ms: u64,
+}
+
+/// Starts writing key events into `path` for `duration` (at most
+/// [`MAX_RECORDING`]), replacing a running recording.
+pub fn start(path: &Path, duration: Duration, profile: &Profile) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut writer = LineWriter::new(File::create(path)?);
+    write_entry(
+        &mut writer,
+        &RecordEntry::Start {
+            profile: Box::new(profile.clone()),
+        },
+    )?;
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let writer = thread::Builder::new()
+        .name("kikyo-key-recorder".to_string())
+        .spawn(move || write_entries(writer, rx))?;
+    let now = Instant::now();
+    *RECORDER.lock() = Some(Recording {
+        tx,
+        writer,
+        path: path.to_path_buf(),
+        started: now,
+        until: now + duration.min(MAX_RECORDING),
+    });
+    RECORDING.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stops the recording and returns its file once everything is written.
+pub fn stop() -> Option<PathBuf> {
+    RECORDING.store(false, Ordering::Relaxed);
+    let recording = RECORDER.lock().take()?;
+    drop(recording.tx);
+    let _ = recording.writer.join();
+    Some(recording.path)
+}
+
+/// The running recording, if its time is not up yet.
+pub fn status() -> Option<RecordingStatus> {
+    let mut recorder = RECORDER.lock();
+    let now = Instant::now();
+    if recorder.as_ref().is_some_and(|r| now >= r.until) {
+        *recorder = None;
+        RECORDING.store(false, Ordering::Relaxed);
+    }
+    recorder.as_ref().map(|r| RecordingStatus {
+        path: r.path.to_string_lossy().into_owned(),
+        remaining_ms: r.until.duration_since(now).as_millis() as u64,
+    })
+}
+
+pub(crate) fn record_key(
+    key: ScKey,
+    up: bool,
+    shift: ShiftSide,
+    modifier: Option<HeldModifier>,
+    action: &KeyAction,
+) {
+    if !RECORDING.load(Ordering::Relaxed) {
+        return;
+    }
+    let (action, events) = match action {
+        KeyAction::Pass => ("Pass", 0),
+        KeyAction::Block => ("Block", 0),
+        KeyAction::Inject(events) => ("Inject", events.len()),
+    };
+    record(|t_ms| RecordEntry::Key {
+        t_ms,
+        key: keys_label(&[key]),
+        up,
+        shift: shift.is_held(),
+        modifier: modifier.map(|m| m.plane.tag()),
+        action,
+        events,
+    });
+}
+
+pub(crate) fn record_output(kind: TraceKind, keys: &[ScKey]) {
+    if !RECORDING.load(Ordering::Relaxed) {
+        return;
+    }
+    record(|t_ms| RecordEntry::Output {
+        t_ms,
+        kind,
+        keys: keys_label(keys),
+    });
+}
+
+fn record(entry: impl FnOnce(u64) -> RecordEntry) {
+    let mut recorder = RECORDER.lock();
+    let Some(recording) = recorder.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    if now >= recording.until {
+        *recorder = None;
+        RECORDING.store(false, Ordering::Relaxed);
+        return;
+    }
+    let t_ms = now.duration_since(recording.started).as_millis() as u64;
+    if recording.tx.send(entry(t_ms)).is_err() {
+        *recorder = None;
+        RECORDING.store(false, Ordering::Relaxed);
+    }
+}
+
+fn write_entries(mut writer: LineWriter<File>, rx: Receiver<RecordEntry>) {
+    while let Ok(entry) = rx.recv() {
+        if let Err(e) = write_entry(&mut writer, &entry) {
+            tracing::warn!("Stopped key recording: {}", e);
+            RECORDING.store(false, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+fn write_entry(writer: &mut impl Write, entry: &RecordEntry) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *writer, entry)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{InputEvent, ModifierPlane};
+
+    #[test]
+    fn test_recording_keeps_key_positions_only() {
+        let path = std::env::temp_dir().join(format!("kikyo-record-{}.jsonl", std::process::id()));
+        start(&path, Duration::from_secs(60), &Profile::default()).unwrap();
+        let a = ScKey::new(0x1E, false);
+        record_key(
+            a,
+            false,
+            ShiftSide::Left,
+            None,
+            &KeyAction::Inject(vec![InputEvent::Unicode('あ', false)]),
+        );
+        record_key(
+            a,
+            true,
+            ShiftSide::None,
+            Some(HeldModifier {
+                plane: ModifierPlane::Ctrl,
+                right: false,
+            }),
+            &KeyAction::Block,
+        );
+        record_output(TraceKind::Tap, &[a]);
+        assert!(status().is_some());
+        assert_eq!(stop(), Some(path.clone()));
+        assert!(status().is_none());
+        record_output(TraceKind::Tap, &[a]);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["type"], "start");
+        assert_eq!(lines[1]["action"], "Inject");
+        assert_eq!(lines[1]["events"], 1);
+        assert_eq!(lines[1]["shift"], true);
+        assert_eq!(lines[2]["modifier"], "<Ctrl>");
+        assert_eq!(lines[3]["type"], "output");
+        assert_eq!(lines[3]["keys"], keys_label(&[a]));
+        assert!(!text.contains('あ'));
+    }
+}
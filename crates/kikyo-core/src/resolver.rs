@@ -0,0 +1,463 @@
+//! Looks keys up in the loaded layout: picks the section for the IME mode,
+//! Shift and held thumb planes, and finds the token a key or chord types
+//! there. The engine decides which keys form a chord; [`Resolver`] only
+//! answers what they type.
+
+use crate::chord_engine::{LatchState, Profile, ThumbKeys, UndefinedKeyPolicy};
+use crate::compiled_layout::{CompiledLayout, CompiledSection, TokenId, NONE_TOKEN};
+use crate::section_selector::SectionShift;
+use crate::types::{ScKey, ShiftSide, Token};
+use smallvec::SmallVec;
+use std::collections::HashSet;
+
+/// Thumb shift planes held for a key set.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ThumbPlanes {
+    left: bool,
+    right: bool,
+    ext1: bool,
+    ext2: bool,
+    /// First held extra thumb (index into `Profile::extra_thumbs`).
+    extra: Option<usize>,
+    /// First held number-row shift key.
+    number: Option<ScKey>,
+    /// Shift keys under `Profile::pinky_shift`.
+    pub(crate) shift: ShiftSide,
+}
+
+impl ThumbPlanes {
+    pub(crate) fn mark(&mut self, tk: &ThumbKeys, key: &ScKey) {
+        self.left |= tk.left.contains(key);
+        self.right |= tk.right.contains(key);
+        self.ext1 |= tk.ext1.contains(key);
+        self.ext2 |= tk.ext2.contains(key);
+        if self.extra.is_none() {
+            self.extra = tk.extra_index(key);
+        }
+        if self.number.is_none() && tk.number.contains(key) {
+            self.number = Some(*key);
+        }
+        if tk.shift.contains(key) {
+            let right = *key == RIGHT_SHIFT_KEY;
+            self.shift =
+                ShiftSide::from_keys(self.shift.left() || !right, self.shift.right() || right);
+        }
+    }
+
+    pub(crate) fn any(&self) -> bool {
+        self.left
+            || self.right
+            || self.ext1
+            || self.ext2
+            || self.extra.is_some()
+            || self.number.is_some()
+            || self.shift.is_held()
+    }
+
+    /// True if `key` is a thumb key of one of the held planes.
+    pub(crate) fn covers(&self, tk: &ThumbKeys, key: &ScKey) -> bool {
+        (self.left && tk.left.contains(key))
+            || (self.right && tk.right.contains(key))
+            || (self.ext1 && tk.ext1.contains(key))
+            || (self.ext2 && tk.ext2.contains(key))
+            || self
+                .extra
+                .and_then(|i| tk.extra.get(i))
+                .is_some_and(|keys| keys.contains(key))
+            || self.number == Some(*key)
+            || (self.shift.is_held() && tk.shift.contains(key))
+    }
+}
+
+const RIGHT_SHIFT_KEY: ScKey = ScKey::new(0x36, false);
+
+/// Layout lookups under the engine's current state.
+#[derive(Debug, Clone, Copy)]
+pub struct Resolver<'a> {
+    pub layout: &'a CompiledLayout,
+    pub profile: &'a Profile,
+    /// Sub-plane latched by a one-shot or lock key.
+    pub latch: &'a LatchState,
+    /// (modifier, target) pairs whose `<modifier>` plane is tried first.
+    pub chord_priorities: &'a HashSet<(ScKey, ScKey)>,
+    pub kana_lock: bool,
+    /// The virtual numeric keypad is on.
+    pub tenkey: bool,
+    /// The IME candidate window is open.
+    pub candidate_window: bool,
+    /// Side of the OS Shift held with the keys.
+    pub shift_side: ShiftSide,
+    /// `[アプリ別]` tag of the foreground application.
+    pub app_tag: Option<&'a str>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Picks the section for the held thumb planes: left/right thumb, then
+    /// number-row shifts, extra thumbs, and the extended thumb sections
+    /// (Japanese input only).
+    pub(crate) fn thumb_section(
+        &self,
+        planes: &ThumbPlanes,
+        shift: bool,
+        is_japanese: bool,
+    ) -> Option<&'a CompiledSection> {
+        let side = if planes.shift.is_held() {
+            planes.shift
+        } else if !shift {
+            ShiftSide::None
+        } else if self.shift_side.is_held() {
+            self.shift_side
+        } else {
+            ShiftSide::Both
+        };
+        let shift = side.is_held();
+
+        if !planes.left && !planes.right {
+            if let Some(key) = planes.number {
+                if crate::jis_map::key_name(key).is_some() {
+                    return self
+                        .layout
+                        .number_section(key, is_japanese, shift, self.kana_lock);
+                }
+            }
+            let extra = planes.extra.and_then(|i| self.profile.extra_thumbs.get(i));
+            if let Some(cfg) = extra {
+                return self
+                    .layout
+                    .suffix_section(&cfg.suffix, is_japanese, shift, self.kana_lock);
+            }
+            // Without a section of its own the combination acts as 拡張1.
+            if is_japanese && planes.ext1 && planes.ext2 {
+                let both = self.section(is_japanese, side, SectionShift::Ext1And2);
+                if both.is_some() {
+                    return both;
+                }
+            }
+            if is_japanese && planes.ext1 {
+                return self.section(is_japanese, side, SectionShift::Ext1);
+            }
+            if is_japanese && planes.ext2 {
+                return self.section(is_japanese, side, SectionShift::Ext2);
+            }
+        }
+
+        let modifier = if planes.left {
+            SectionShift::LeftThumb
+        } else if planes.right {
+            SectionShift::RightThumb
+        } else {
+            SectionShift::None
+        };
+        self.section(is_japanese, side, modifier)
+    }
+
+    /// Section for the slot, preferring the one-sided `小指` section and
+    /// the `[かなロック…]` variant while kana lock is on.
+    pub fn section(
+        &self,
+        is_japanese: bool,
+        shift: ShiftSide,
+        modifier: SectionShift,
+    ) -> Option<&'a CompiledSection> {
+        self.layout
+            .section(is_japanese, shift, modifier, self.kana_lock)
+    }
+
+    /// True if `key` has a token in the section's base plane or opens one
+    /// of its `<key>` sub-planes.
+    pub fn section_defines(&self, section: &CompiledSection, key: ScKey) -> bool {
+        section.defines(key)
+    }
+
+    /// The `[テンキー]` section while the virtual keypad is on.
+    pub fn tenkey_section(&self) -> Option<&'a CompiledSection> {
+        self.layout.tenkey_section().filter(|_| self.tenkey)
+    }
+
+    /// The `[候補選択]` section while the IME candidate window is open.
+    pub fn candidate_section(&self) -> Option<&'a CompiledSection> {
+        self.layout
+            .candidate_section()
+            .filter(|_| self.candidate_window)
+    }
+
+    /// Sections that take their keys whatever the mode: `[候補選択]`, then
+    /// `[テンキー]`.
+    pub fn overriding_sections(&self) -> impl Iterator<Item = &'a CompiledSection> {
+        self.candidate_section()
+            .into_iter()
+            .chain(self.tenkey_section())
+    }
+
+    /// Sections consulted, in order, for keys `section` leaves undefined:
+    /// its `[継承]` parents, `[拡張親指シフト1]` and `[拡張親指シフト2]` for
+    /// `[拡張親指シフト1+2]`, then the unshifted section under
+    /// `UndefinedKeyPolicy::Fallback`. The physical Shift stays held, so
+    /// keys inherited from an unshifted section come out shifted.
+    pub fn fallback_sections(
+        &self,
+        section: &'a CompiledSection,
+        is_japanese: bool,
+    ) -> impl Iterator<Item = &'a CompiledSection> + 'a {
+        let layout = self.layout;
+        let base = (self.profile.undefined_key == UndefinedKeyPolicy::Fallback)
+            .then(|| self.section(is_japanese, ShiftSide::None, SectionShift::None))
+            .flatten()
+            .filter(|base| {
+                base.id != section.id && layout.parents(section).all(|p| p.id != base.id)
+            });
+        let resolver = *self;
+        let ext_planes = self
+            .section(is_japanese, ShiftSide::None, SectionShift::Ext1And2)
+            .filter(|both| both.id == section.id)
+            .into_iter()
+            .flat_map(|_| [SectionShift::Ext1, SectionShift::Ext2])
+            .filter_map(move |shift| resolver.section(is_japanese, ShiftSide::None, shift));
+        layout.parents(section).chain(ext_planes).chain(base)
+    }
+
+    pub fn token(&self, id: TokenId) -> &'a Token {
+        self.layout.token(id)
+    }
+
+    pub fn resolve(&self, keys: &[ScKey], shift: bool, is_japanese: bool) -> Option<Token> {
+        self.resolve_with_modifier(keys, shift, is_japanese).0
+    }
+
+    pub fn resolve_with_modifier(
+        &self,
+        keys: &[ScKey],
+        shift: bool,
+        is_japanese: bool,
+    ) -> (Option<Token>, Option<ScKey>) {
+        let (token, modifier) = self.resolve_id(keys, shift, is_japanese);
+        (token.map(|id| self.token(id).clone()), modifier)
+    }
+
+    /// Like [`Self::resolve_with_modifier`], but leaves the token in the
+    /// layout.
+    pub fn resolve_id(
+        &self,
+        keys: &[ScKey],
+        shift: bool,
+        is_japanese: bool,
+    ) -> (Option<TokenId>, Option<ScKey>) {
+        let (token, modifier, _) = self.lookup(keys, shift, is_japanese);
+        (token, modifier)
+    }
+
+    /// Section `keys` resolve in: the app section, the selected one or
+    /// one it falls back to. `None` when they type nothing.
+    pub fn resolve_section(
+        &self,
+        keys: &[ScKey],
+        shift: bool,
+        is_japanese: bool,
+    ) -> Option<&'a CompiledSection> {
+        match self.lookup(keys, shift, is_japanese) {
+            (token, _, section) if is_defined(token) => section,
+            _ => None,
+        }
+    }
+
+    /// [`Self::resolve_id`] with the section that answered.
+    fn lookup(
+        &self,
+        keys: &[ScKey],
+        shift: bool,
+        is_japanese: bool,
+    ) -> (Option<TokenId>, Option<ScKey>, Option<&'a CompiledSection>) {
+        // 0. The candidate window and the virtual keypad take their keys
+        // whatever the mode; keys of them pressed together are typed one
+        // by one.
+        for section in self.overriding_sections() {
+            if keys.iter().all(|&key| section.defines(key)) {
+                return match keys {
+                    [key] => (section.base(*key), None, Some(section)),
+                    _ => (None, None, None),
+                };
+            }
+        }
+
+        // 1. Determine "Thumb Shift" status
+        let mut planes = ThumbPlanes::default();
+        if let Some(ref tk) = self.profile.thumb_keys {
+            for k in keys {
+                planes.mark(tk, k);
+            }
+        }
+
+        // 2. Select section from IME mode, Shift and thumb planes
+        let section = match self.thumb_section(&planes, shift, is_japanese) {
+            Some(section) => section,
+            None => return (None, None, None),
+        };
+
+        // 3. Update keys for lookup (Remove Thumb Modifiers)
+        let lookup_keys: SmallVec<[ScKey; 4]> = match self.profile.thumb_keys {
+            Some(ref tk) if planes.any() => keys
+                .iter()
+                .filter(|k| !planes.covers(tk, k))
+                .cloned()
+                .collect(),
+            _ => keys.iter().copied().collect(),
+        };
+
+        if lookup_keys.is_empty() {
+            return (None, None, None);
+        }
+
+        if let Some(tag) = self.app_tag {
+            if let Some(app_section) = self.layout.app_section(section, tag) {
+                let (token, modifier) = self.resolve_in_section(app_section, &lookup_keys);
+                if is_defined(token) {
+                    return (token, modifier, Some(app_section));
+                }
+            }
+        }
+        let (token, modifier) = self.resolve_in_section(section, &lookup_keys);
+        if is_defined(token) {
+            return (token, modifier, Some(section));
+        }
+        for fallback in self.fallback_sections(section, is_japanese) {
+            let inherited = self.resolve_in_section(fallback, &lookup_keys);
+            if is_defined(inherited.0) {
+                return (inherited.0, inherited.1, Some(fallback));
+            }
+        }
+        (token, modifier, Some(section))
+    }
+
+    /// Token of `key` held alone: from the `<long>` plane of the section it
+    /// is typed from, or of that section's fallbacks.
+    pub fn resolve_long_press_id(
+        &self,
+        key: ScKey,
+        shift: bool,
+        is_japanese: bool,
+    ) -> Option<TokenId> {
+        let section = self.thumb_section(&ThumbPlanes::default(), shift, is_japanese)?;
+        let app_section = self
+            .app_tag
+            .and_then(|tag| self.layout.app_section(section, tag));
+        app_section
+            .into_iter()
+            .chain([section])
+            .chain(self.fallback_sections(section, is_japanese))
+            .find_map(|section| section.long_press(key))
+    }
+
+    /// Looks up thumb-free `lookup_keys` in one section: a single key in the
+    /// latched or base plane, two or three keys through `<key>` sub-planes.
+    fn resolve_in_section(
+        &self,
+        section: &CompiledSection,
+        lookup_keys: &[ScKey],
+    ) -> (Option<TokenId>, Option<ScKey>) {
+        if lookup_keys.len() == 1 {
+            let key = lookup_keys[0];
+
+            if let LatchState::OneShot(tag) | LatchState::Lock(tag) = self.latch {
+                if let Some(token) = section.tagged(tag, key) {
+                    return (Some(token), None);
+                }
+            }
+
+            return (section.base(key), None);
+        } else if lookup_keys.len() == 2 {
+            let (k1, k2) = if self
+                .chord_priorities
+                .contains(&(lookup_keys[1], lookup_keys[0]))
+            {
+                (lookup_keys[1], lookup_keys[0])
+            } else {
+                (lookup_keys[0], lookup_keys[1])
+            };
+
+            if let Some(token) = section.single(k1, k2) {
+                return (Some(token), Some(k1));
+            }
+            if let Some(token) = section.single(k2, k1) {
+                return (Some(token), Some(k2));
+            }
+        } else if lookup_keys.len() == 3 {
+            // 3-key resolution (A, B, C): two of the keys open a `<A><B>`
+            // plane (in either order) in which the third is looked up.
+            let k1 = lookup_keys[0];
+            let k2 = lookup_keys[1];
+            let k3 = lookup_keys[2];
+            let orders = [
+                (k1, k2, k3),
+                (k2, k1, k3),
+                (k1, k3, k2),
+                (k3, k1, k2),
+                (k2, k3, k1),
+                (k3, k2, k1),
+            ];
+            for (mod1, mod2, target) in orders {
+                if let Some(token) = section.double(mod1, mod2, target) {
+                    return (Some(token), Some(mod1));
+                }
+            }
+        }
+
+        (None, None)
+    }
+}
+
+fn is_defined(token: Option<TokenId>) -> bool {
+    token.is_some_and(|id| id != NONE_TOKEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_key_sequence_expanded, parse_yab_content};
+    use crate::section_selector::SectionSelector;
+
+    #[test]
+    fn test_resolver_looks_up_single_keys_and_chords() {
+        let layout = parse_yab_content(
+            "
+[ローマ字シフト無し]
+あ,い,う
+<1>
+か,き,く
+",
+        )
+        .unwrap();
+        let layout = CompiledLayout::new(&layout, &SectionSelector::default());
+        let profile = Profile::default();
+        let priorities = HashSet::new();
+        let resolver = Resolver {
+            layout: &layout,
+            profile: &profile,
+            latch: &LatchState::None,
+            chord_priorities: &priorities,
+            kana_lock: false,
+            tenkey: false,
+            candidate_window: false,
+            shift_side: ShiftSide::None,
+            app_tag: None,
+        };
+        let one = ScKey::new(0x02, false);
+        let two = ScKey::new(0x03, false);
+
+        assert_eq!(
+            resolver.resolve(&[two], false, true),
+            Some(Token::KeySequence(parse_key_sequence_expanded("い")))
+        );
+        assert_eq!(
+            resolver.resolve_with_modifier(&[two, one], false, true),
+            (
+                Some(Token::KeySequence(parse_key_sequence_expanded("き"))),
+                Some(one)
+            )
+        );
+        let section = resolver
+            .section(true, ShiftSide::None, SectionShift::None)
+            .unwrap();
+        assert!(resolver.section_defines(section, one));
+        assert!(!resolver.section_defines(section, ScKey::new(0x10, false)));
+    }
+}
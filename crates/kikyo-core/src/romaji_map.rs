@@ -32,12 +32,18 @@ lazy_static::lazy_static! {
 
         m
     };
+    static ref ROMAJI_KANA_MAP: HashMap<&'static str, char> =
+        KANA_ROMAJI_MAP.iter().map(|(&kana, &romaji)| (romaji, kana)).collect();
 }
 
 pub fn kana_to_romaji(c: char) -> Option<&'static str> {
     KANA_ROMAJI_MAP.get(&c).copied()
 }
 
+pub fn romaji_to_kana(romaji: &str) -> Option<char> {
+    ROMAJI_KANA_MAP.get(romaji).copied()
+}
+
 pub fn normalize_symbol(c: char) -> Option<char> {
     match c {
         '！' => Some('!'),
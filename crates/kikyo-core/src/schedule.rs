@@ -0,0 +1,203 @@
+#[cfg(feature = "os-windows")]
+use crate::dynamic_text::LocalTime;
+use crate::foreground::exe_file_name;
+#[cfg(feature = "os-windows")]
+use crate::foreground::{exclusive_fullscreen_active, foreground_exe_name};
+#[cfg(feature = "os-windows")]
+use crate::keyboard_hook::last_typed_age_ms;
+use serde::{Deserialize, Serialize};
+
+/// Rules for suspending the engine automatically, e.g. while recording or
+/// gaming. The app checks them when the foreground changes and at
+/// [`Self::next_check_ms`], and resumes the engine once none applies, such
+/// as when focus leaves a game.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoDisableSchedule {
+    /// Local hours in which the engine is suspended.
+    pub hours: Option<TimeRange>,
+    /// Minutes without typing before the engine is suspended. Injected
+    /// input does not count. 0 turns the idle rule off.
+    pub idle_minutes: u32,
+    /// Suspend while a game runs in exclusive full screen.
+    pub fullscreen_games: bool,
+    /// Executable file names, e.g. "game.exe", suspended while in the
+    /// foreground. Matched case-insensitively.
+    pub game_apps: Vec<String>,
+}
+
+/// Span of the day in minutes after midnight. `end` is exclusive and may be
+/// earlier than `start` for a span crossing midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl TimeRange {
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+/// Longest wait between checks while full-screen games suspend the engine:
+/// a game can enter exclusive full screen without the foreground changing.
+const FULLSCREEN_CHECK_INTERVAL_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoDisableReason {
+    Hours,
+    Game,
+    Idle,
+}
+
+impl AutoDisableSchedule {
+    /// Which rule suspends the engine at `minute` of the local day after
+    /// `idle_ms` without input, if any. `game` is [`Self::is_game`] for the
+    /// foreground window.
+    pub fn reason(&self, minute: u16, idle_ms: u64, game: bool) -> Option<AutoDisableReason> {
+        if self.hours.is_some_and(|hours| hours.contains(minute)) {
+            return Some(AutoDisableReason::Hours);
+        }
+        if game {
+            return Some(AutoDisableReason::Game);
+        }
+        let idle_limit_ms = u64::from(self.idle_minutes) * 60_000;
+        (idle_limit_ms > 0 && idle_ms >= idle_limit_ms).then_some(AutoDisableReason::Idle)
+    }
+
+    /// Time until a rule may start or stop applying on its own, at `second`
+    /// of `minute` after `idle_ms` without typing: the next edge of `hours`
+    /// or the idle limit. `None` if only a foreground change or typing can
+    /// change the outcome.
+    pub fn next_check_ms(&self, minute: u16, second: u16, idle_ms: u64) -> Option<u64> {
+        let mut next: Option<u64> = None;
+        let mut check_in = |ms: u64| next = Some(next.map_or(ms, |next| next.min(ms)));
+        if let Some(hours) = self.hours {
+            for edge in [hours.start, hours.end] {
+                let minutes = match (edge + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY {
+                    0 => MINUTES_PER_DAY,
+                    minutes => minutes,
+                };
+                check_in((u64::from(minutes) * 60 - u64::from(second)) * 1000);
+            }
+        }
+        let idle_limit_ms = u64::from(self.idle_minutes) * 60_000;
+        if idle_limit_ms > 0 && idle_ms < idle_limit_ms {
+            check_in(idle_limit_ms - idle_ms);
+        }
+        if self.fullscreen_games {
+            check_in(FULLSCREEN_CHECK_INTERVAL_MS);
+        }
+        next
+    }
+
+    /// Whether the foreground application `exe` counts as a game.
+    pub fn is_game(&self, exe: Option<&str>, exclusive_fullscreen: bool) -> bool {
+        (self.fullscreen_games && exclusive_fullscreen)
+            || exe.is_some_and(|exe| self.game_apps.iter().any(|app| exe_file_name(app) == exe))
+    }
+
+    /// [`Self::reason`] for the current local time, typing idle time and
+    /// foreground window.
+    #[cfg(feature = "os-windows")]
+    pub fn current_reason(&self) -> Option<AutoDisableReason> {
+        let idle_ms = if self.idle_minutes > 0 {
+            last_typed_age_ms()
+        } else {
+            0
+        };
+        let game = (self.fullscreen_games || !self.game_apps.is_empty())
+            && self.is_game(
+                foreground_exe_name().as_deref(),
+                self.fullscreen_games && exclusive_fullscreen_active(),
+            );
+        self.reason(local_minute_of_day(), idle_ms, game)
+    }
+
+    /// [`Self::next_check_ms`] for the current local time and typing idle
+    /// time.
+    #[cfg(feature = "os-windows")]
+    pub fn next_check(&self) -> Option<std::time::Duration> {
+        let now = LocalTime::now();
+        self.next_check_ms(now.hour * 60 + now.minute, now.second, last_typed_age_ms())
+            .map(std::time::Duration::from_millis)
+    }
+}
+
+#[cfg(feature = "os-windows")]
+fn local_minute_of_day() -> u16 {
+    let now = LocalTime::now();
+    now.hour * 60 + now.minute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hours_cross_midnight_and_idle_needs_limit() {
+        let schedule = AutoDisableSchedule {
+            hours: Some(TimeRange {
+                start: 22 * 60,
+                end: 6 * 60,
+            }),
+            idle_minutes: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            schedule.reason(23 * 60, 0, false),
+            Some(AutoDisableReason::Hours)
+        );
+        assert_eq!(
+            schedule.reason(5 * 60 + 59, 0, false),
+            Some(AutoDisableReason::Hours)
+        );
+        assert_eq!(schedule.reason(6 * 60, 0, false), None);
+        assert_eq!(schedule.reason(12 * 60, 599_999, false), None);
+        assert_eq!(
+            schedule.reason(12 * 60, 600_000, false),
+            Some(AutoDisableReason::Idle)
+        );
+
+        assert_eq!(
+            schedule.reason(12 * 60, 600_000, true),
+            Some(AutoDisableReason::Game)
+        );
+
+        let off = AutoDisableSchedule::default();
+        assert_eq!(off.reason(0, u64::MAX, false), None);
+
+        // Woken at the next edge of the hours or at the idle limit.
+        assert_eq!(schedule.next_check_ms(21 * 60 + 59, 30, 0), Some(30_000));
+        assert_eq!(schedule.next_check_ms(12 * 60, 0, 599_000), Some(1_000));
+        assert_eq!(
+            schedule.next_check_ms(6 * 60, 0, 600_000),
+            Some(16 * 3_600_000)
+        );
+        assert_eq!(off.next_check_ms(0, 0, 0), None);
+    }
+
+    #[test]
+    fn test_game_apps_and_exclusive_fullscreen() {
+        let schedule = AutoDisableSchedule {
+            game_apps: vec!["C:\\Games\\Game.EXE".to_string()],
+            ..Default::default()
+        };
+        assert!(schedule.is_game(Some("game.exe"), false));
+        assert!(!schedule.is_game(Some("notepad.exe"), true));
+
+        let fullscreen = AutoDisableSchedule {
+            fullscreen_games: true,
+            ..Default::default()
+        };
+        assert!(fullscreen.is_game(Some("notepad.exe"), true));
+        assert!(!fullscreen.is_game(None, false));
+    }
+}
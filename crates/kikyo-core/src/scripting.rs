@@ -0,0 +1,442 @@
+//! Output hooks run by user scripts. A script is a `.rhai` file in the
+//! scripts folder that defines `fn on_output(event)`, where `event` is a
+//! map with `kind`, `keys`, `text` and `japanese`. What it returns decides
+//! the output:
+//!
+//! - nothing or `#{}` keeps it,
+//! - `#{ text: "..." }` replaces its text,
+//! - `#{ suppress: true }` drops it.
+//!
+//! `this` is a map the script keeps between calls. Scripts run in a
+//! sandboxed interpreter on their own worker thread: they cannot start
+//! processes, touch files or import modules, and they never run under the
+//! engine lock. The engine hands over outputs as [`InputEvent::Script`]
+//! markers, which are resolved just before injection. A script that fails
+//! or runs longer than [`SCRIPT_TIMEOUT`] is unloaded and the output is kept.
+
+use crate::trace::TraceKind;
+use crate::types::{InputEvent, Token};
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{CallFnOptions, Dynamic, Engine as Interpreter, Map, Scope, AST};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub const SCRIPT_TIMEOUT: Duration = Duration::from_millis(50);
+/// Files loaded as scripts; anything else in the folder is left alone.
+const SCRIPT_EXTENSION: &str = "rhai";
+const HOOK_FN: &str = "on_output";
+/// Operations between two checks of the deadline.
+const PROGRESS_INTERVAL: u64 = 256;
+
+/// Names of the loaded scripts, kept by the worker.
+static LOADED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// Set while any script is loaded, so outputs skip the worker otherwise.
+static SCRIPTS_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref JOBS: Sender<Job> = spawn_worker();
+}
+
+enum Job {
+    Load(PathBuf, Sender<Vec<String>>),
+    Unload,
+    Run(ScriptEvent, Sender<ScriptOutcome>),
+}
+
+/// What the scripts are shown of an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScriptEvent {
+    kind: TraceKind,
+    /// Keys that produced the output (e.g. "K+無変換").
+    keys: String,
+    text: String,
+    japanese: bool,
+}
+
+/// What the scripts made of an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOutcome {
+    Keep,
+    Replace(String),
+    Suppress,
+}
+
+/// An output waiting for the scripts, carried by [`InputEvent::Script`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptCall {
+    pub kind: TraceKind,
+    pub keys: String,
+    pub text: String,
+    pub japanese: bool,
+    pub shift: bool,
+    /// Token the events were made from, whose kind a replacement keeps.
+    pub token: Token,
+    /// Events to inject when the scripts keep the output.
+    pub events: Vec<InputEvent>,
+}
+
+struct Script {
+    name: String,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+}
+
+/// The interpreter and the loaded scripts. Lives on the worker thread only.
+struct ScriptHost {
+    interpreter: Interpreter,
+    deadline: Rc<Cell<Instant>>,
+    scripts: Vec<Script>,
+}
+
+impl ScriptHost {
+    fn new() -> Self {
+        let deadline = Rc::new(Cell::new(Instant::now()));
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval")
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(64 * 1024)
+            .set_max_array_size(10_000)
+            .set_max_map_size(10_000)
+            .on_print(|text| info!("Output script: {}", text))
+            .on_debug(|text, _, pos| debug!("Output script {:?}: {}", pos, text));
+        let until = deadline.clone();
+        interpreter.on_progress(move |ops| {
+            let late = ops % PROGRESS_INTERVAL == 0 && Instant::now() >= until.get();
+            late.then_some(Dynamic::UNIT)
+        });
+        Self {
+            interpreter,
+            deadline,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Replaces the loaded scripts with those in `dir`. Scripts that fail
+    /// to compile or have no `on_output` are logged and skipped.
+    fn load(&mut self, dir: &Path) -> Vec<String> {
+        self.scripts.clear();
+        for path in script_files(dir) {
+            match self.compile(&path) {
+                Ok(script) => {
+                    info!("Loaded output script {}", script.name);
+                    self.scripts.push(script);
+                }
+                Err(e) => warn!("Output script {}: {}", path.display(), e),
+            }
+        }
+        self.names()
+    }
+
+    fn compile(&mut self, path: &Path) -> Result<Script, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let ast = self
+            .interpreter
+            .compile(&source)
+            .map_err(|e| e.to_string())?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == HOOK_FN && f.params.len() == 1)
+        {
+            return Err(format!("no {HOOK_FN}(event) function"));
+        }
+        let mut scope = Scope::new();
+        self.deadline.set(Instant::now() + SCRIPT_TIMEOUT);
+        self.interpreter
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| e.to_string())?;
+        Ok(Script {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            ast,
+            scope,
+            this: Dynamic::from_map(Map::new()),
+        })
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.scripts
+            .iter()
+            .map(|script| script.name.clone())
+            .collect()
+    }
+
+    /// Passes an output's text through every script in turn, all within
+    /// one [`SCRIPT_TIMEOUT`].
+    fn run(&mut self, event: &ScriptEvent) -> ScriptOutcome {
+        self.deadline.set(Instant::now() + SCRIPT_TIMEOUT);
+        let mut current = event.text.clone();
+        let mut i = 0;
+        while i < self.scripts.len() {
+            let script = &mut self.scripts[i];
+            let mut map = Map::new();
+            map.insert("kind".into(), format!("{:?}", event.kind).into());
+            map.insert("keys".into(), event.keys.clone().into());
+            map.insert("text".into(), current.clone().into());
+            map.insert("japanese".into(), event.japanese.into());
+            let options = CallFnOptions::new()
+                .eval_ast(false)
+                .rewind_scope(true)
+                .bind_this_ptr(&mut script.this);
+            match self.interpreter.call_fn_with_options::<Dynamic>(
+                options,
+                &mut script.scope,
+                &script.ast,
+                HOOK_FN,
+                (map,),
+            ) {
+                Ok(reply) => match parse_reply(reply) {
+                    Some(Reply::Suppress) => return ScriptOutcome::Suppress,
+                    Some(Reply::Text(text)) => current = text,
+                    Some(Reply::Keep) => {}
+                    None => warn!("Output script {}: ignoring its answer", script.name),
+                },
+                Err(e) => {
+                    warn!("Unloaded output script {}: {}", script.name, e);
+                    self.scripts.remove(i);
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        if current == event.text {
+            ScriptOutcome::Keep
+        } else {
+            ScriptOutcome::Replace(current)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Reply {
+    Keep,
+    Text(String),
+    Suppress,
+}
+
+fn parse_reply(reply: Dynamic) -> Option<Reply> {
+    if reply.is_unit() {
+        return Some(Reply::Keep);
+    }
+    let map = reply.try_cast::<Map>()?;
+    if map
+        .get("suppress")
+        .is_some_and(|value| value.as_bool() == Ok(true))
+    {
+        return Some(Reply::Suppress);
+    }
+    match map.get("text") {
+        None => Some(Reply::Keep),
+        Some(text) => text.clone().into_string().ok().map(Reply::Text),
+    }
+}
+
+fn spawn_worker() -> Sender<Job> {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::Builder::new()
+        .name("kikyo-script-worker".to_string())
+        .spawn(move || script_worker(rx))
+        .expect("Failed to spawn script worker thread");
+    tx
+}
+
+fn script_worker(rx: Receiver<Job>) {
+    let mut host = ScriptHost::new();
+    for job in rx {
+        match job {
+            Job::Load(dir, reply) => {
+                let _ = reply.send(host.load(&dir));
+            }
+            Job::Unload => host.scripts.clear(),
+            Job::Run(event, reply) => {
+                let _ = reply.send(host.run(&event));
+            }
+        }
+        let names = host.names();
+        SCRIPTS_ACTIVE.store(!names.is_empty(), Ordering::Release);
+        *LOADED.lock() = names;
+    }
+}
+
+/// Scripts in `dir`, in file name order. A missing folder has none.
+pub fn script_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(SCRIPT_EXTENSION))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Replaces the loaded scripts with those in `dir`. Returns the names of
+/// the loaded ones.
+pub fn load_scripts(dir: &Path) -> Vec<String> {
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    if JOBS.send(Job::Load(dir.to_path_buf(), tx)).is_err() {
+        return Vec::new();
+    }
+    rx.recv().unwrap_or_default()
+}
+
+pub fn unload_scripts() {
+    SCRIPTS_ACTIVE.store(false, Ordering::Release);
+    LOADED.lock().clear();
+    let _ = JOBS.send(Job::Unload);
+}
+
+pub(crate) fn is_active() -> bool {
+    SCRIPTS_ACTIVE.load(Ordering::Acquire)
+}
+
+/// Names of the scripts still loaded.
+pub fn loaded_scripts() -> Vec<String> {
+    LOADED.lock().clone()
+}
+
+/// Asks the worker what the scripts make of `call`. Waits at most about
+/// [`SCRIPT_TIMEOUT`], keeping the output if the answer is late.
+fn run(call: &ScriptCall) -> ScriptOutcome {
+    if !is_active() {
+        return ScriptOutcome::Keep;
+    }
+    let event = ScriptEvent {
+        kind: call.kind,
+        keys: call.keys.clone(),
+        text: call.text.clone(),
+        japanese: call.japanese,
+    };
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    if JOBS.send(Job::Run(event, tx)).is_err() {
+        return ScriptOutcome::Keep;
+    }
+    rx.recv_timeout(SCRIPT_TIMEOUT * 2).unwrap_or_else(|_| {
+        warn!("Output scripts did not answer in time");
+        ScriptOutcome::Keep
+    })
+}
+
+/// Replaces the [`InputEvent::Script`] markers in `events` with what the
+/// scripts made of them: the marker's own events when kept, otherwise
+/// those `apply` builds from the outcome. Must not be called under the
+/// engine lock.
+pub fn resolve(
+    events: Vec<InputEvent>,
+    mut apply: impl FnMut(ScriptCall, ScriptOutcome) -> Vec<InputEvent>,
+) -> Vec<InputEvent> {
+    if !events
+        .iter()
+        .any(|event| matches!(event, InputEvent::Script(_)))
+    {
+        return events;
+    }
+    let mut out = Vec::with_capacity(events.len());
+    for event in events {
+        match event {
+            InputEvent::Script(call) => match run(&call) {
+                ScriptOutcome::Keep => out.extend(call.events),
+                outcome => out.extend(apply(*call, outcome)),
+            },
+            event => out.push(event),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kikyo-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_script_files_are_sorted_and_filtered_by_extension() {
+        let dir = test_dir("scripts-test");
+        for name in ["b.rhai", "a.RHAI", "notes.txt", "c.cmd"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let names: Vec<String> = script_files(&dir)
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(names, vec!["a.RHAI", "b.rhai"]);
+        assert!(script_files(&dir).is_empty());
+    }
+
+    #[test]
+    fn test_scripts_rewrite_outputs_and_stop_when_slow() {
+        let dir = test_dir("scripts-run");
+        std::fs::write(
+            dir.join("1-comma.rhai"),
+            r#"
+fn on_output(event) {
+    this.count = (this.count ?? 0) + 1;
+    if event.text == "、" { #{ text: "，" } }
+    else if event.text == "x" { #{ suppress: true } }
+    else if event.text == "n" { #{ text: `${this.count}` } }
+}
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("2-spin.rhai"),
+            r#"fn on_output(event) { if event.text == "loop" { loop {} } }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("3-none.rhai"), "let x = 1;").unwrap();
+        std::fs::write(dir.join("4-shell.rhai"), r#"import "os" as os;"#).unwrap();
+
+        let mut host = ScriptHost::new();
+        let names = host.load(&dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(names, vec!["1-comma.rhai", "2-spin.rhai"]);
+
+        let event = |text: &str| ScriptEvent {
+            kind: TraceKind::Chord,
+            keys: "K+D".to_string(),
+            text: text.to_string(),
+            japanese: true,
+        };
+        assert_eq!(
+            host.run(&event("、")),
+            ScriptOutcome::Replace("，".to_string())
+        );
+        assert_eq!(host.run(&event("x")), ScriptOutcome::Suppress);
+        assert_eq!(host.run(&event("か")), ScriptOutcome::Keep);
+        assert_eq!(
+            host.run(&event("n")),
+            ScriptOutcome::Replace("4".to_string())
+        );
+
+        let started = Instant::now();
+        assert_eq!(host.run(&event("loop")), ScriptOutcome::Keep);
+        assert!(started.elapsed() < SCRIPT_TIMEOUT * 10);
+        assert_eq!(host.names(), vec!["1-comma.rhai"]);
+    }
+}
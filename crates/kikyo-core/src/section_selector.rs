@@ -0,0 +1,210 @@
+use crate::types::ShiftSide;
+use std::collections::HashMap;
+
+/// Plane-selecting modifier held for a lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectionShift {
+    None,
+    LeftThumb,
+    RightThumb,
+    Ext1,
+    Ext2,
+    /// 拡張1 and 拡張2 held together.
+    Ext1And2,
+}
+
+impl SectionShift {
+    pub(crate) const ALL: [SectionShift; 6] = [
+        SectionShift::None,
+        SectionShift::LeftThumb,
+        SectionShift::RightThumb,
+        SectionShift::Ext1,
+        SectionShift::Ext2,
+        SectionShift::Ext1And2,
+    ];
+}
+
+/// (IME state, Shift, modifier) combination that selects one section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SectionSlot {
+    pub japanese: bool,
+    pub shift: bool,
+    pub modifier: SectionShift,
+}
+
+impl SectionSlot {
+    pub fn new(japanese: bool, shift: bool, modifier: SectionShift) -> Self {
+        Self {
+            japanese,
+            shift,
+            modifier,
+        }
+    }
+
+    fn all() -> impl Iterator<Item = SectionSlot> {
+        [true, false].into_iter().flat_map(|japanese| {
+            [false, true].into_iter().flat_map(move |shift| {
+                SectionShift::ALL
+                    .into_iter()
+                    .map(move |modifier| SectionSlot::new(japanese, shift, modifier))
+            })
+        })
+    }
+
+    /// Standard .yab section name, e.g. "ローマ字小指左親指シフト".
+    pub fn default_name(&self) -> String {
+        let suffix = match (self.modifier, self.shift) {
+            (SectionShift::Ext1, _) => return "拡張親指シフト1".to_string(),
+            (SectionShift::Ext2, _) => return "拡張親指シフト2".to_string(),
+            (SectionShift::Ext1And2, _) => return "拡張親指シフト1+2".to_string(),
+            (SectionShift::None, false) => "シフト無し",
+            (SectionShift::None, true) => "小指シフト",
+            (SectionShift::LeftThumb, false) => "左親指シフト",
+            (SectionShift::LeftThumb, true) => "小指左親指シフト",
+            (SectionShift::RightThumb, false) => "右親指シフト",
+            (SectionShift::RightThumb, true) => "小指右親指シフト",
+        };
+        format!("{}{}", section_prefix(self.japanese), suffix)
+    }
+
+    /// Section for one Shift side, e.g. "ローマ字左小指シフト" or
+    /// "英数右小指左親指シフト". Tried before the plain `小指` section.
+    pub fn sided_name(&self, side: ShiftSide) -> Option<String> {
+        let pinky = match side {
+            ShiftSide::Left => "左小指",
+            ShiftSide::Right => "右小指",
+            ShiftSide::None | ShiftSide::Both => return None,
+        };
+        let suffix = match self.modifier {
+            SectionShift::None => "シフト",
+            SectionShift::LeftThumb => "左親指シフト",
+            SectionShift::RightThumb => "右親指シフト",
+            SectionShift::Ext1 | SectionShift::Ext2 | SectionShift::Ext1And2 => return None,
+        };
+        Some(format!(
+            "{}{}{}",
+            section_prefix(self.japanese),
+            pinky,
+            suffix
+        ))
+    }
+}
+
+/// Prefix of sections built from a suffix (extra thumbs, number-row shifts).
+pub fn section_prefix(japanese: bool) -> &'static str {
+    if japanese {
+        "ローマ字"
+    } else {
+        "英数"
+    }
+}
+
+/// Maps each [`SectionSlot`] to the section name the layout uses for it.
+#[derive(Debug, Clone)]
+pub struct SectionSelector {
+    names: HashMap<SectionSlot, String>,
+}
+
+impl Default for SectionSelector {
+    fn default() -> Self {
+        Self {
+            names: SectionSlot::all()
+                .map(|slot| (slot, slot.default_name()))
+                .collect(),
+        }
+    }
+}
+
+impl SectionSelector {
+    /// Builds a selector from `[セクション名]` entries (standard name, custom
+    /// name). Unknown standard names are ignored.
+    pub fn with_overrides(overrides: &[(String, String)]) -> Self {
+        let mut selector = Self::default();
+        for (standard, custom) in overrides {
+            for slot in SectionSlot::all() {
+                if slot.default_name() == *standard {
+                    selector.names.insert(slot, custom.clone());
+                }
+            }
+        }
+        selector
+    }
+
+    pub fn name(&self, slot: SectionSlot) -> &str {
+        &self.names[&slot]
+    }
+
+    /// Names of all thumb-shift sections (left/right/extended).
+    pub fn thumb_section_names(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .filter(|(slot, _)| slot.modifier != SectionShift::None)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_names_match_standard_sections() {
+        let selector = SectionSelector::default();
+        let name = |japanese, shift, modifier| {
+            selector
+                .name(SectionSlot::new(japanese, shift, modifier))
+                .to_string()
+        };
+        assert_eq!(name(true, false, SectionShift::None), "ローマ字シフト無し");
+        assert_eq!(name(false, true, SectionShift::None), "英数小指シフト");
+        assert_eq!(
+            name(true, true, SectionShift::RightThumb),
+            "ローマ字小指右親指シフト"
+        );
+        assert_eq!(name(true, true, SectionShift::Ext1), "拡張親指シフト1");
+        assert_eq!(
+            name(true, false, SectionShift::Ext1And2),
+            "拡張親指シフト1+2"
+        );
+    }
+
+    #[test]
+    fn test_sided_names_for_one_shift_key() {
+        let slot = |modifier| SectionSlot::new(true, true, modifier);
+        assert_eq!(
+            slot(SectionShift::None)
+                .sided_name(ShiftSide::Left)
+                .as_deref(),
+            Some("ローマ字左小指シフト")
+        );
+        assert_eq!(
+            slot(SectionShift::LeftThumb)
+                .sided_name(ShiftSide::Right)
+                .as_deref(),
+            Some("ローマ字右小指左親指シフト")
+        );
+        assert_eq!(slot(SectionShift::None).sided_name(ShiftSide::Both), None);
+        assert_eq!(slot(SectionShift::Ext1).sided_name(ShiftSide::Left), None);
+    }
+
+    #[test]
+    fn test_overrides_replace_matching_slots() {
+        let selector = SectionSelector::with_overrides(&[
+            ("ローマ字シフト無し".to_string(), "かな".to_string()),
+            ("拡張親指シフト2".to_string(), "記号".to_string()),
+            ("存在しない".to_string(), "x".to_string()),
+        ]);
+        assert_eq!(
+            selector.name(SectionSlot::new(true, false, SectionShift::None)),
+            "かな"
+        );
+        assert_eq!(
+            selector.name(SectionSlot::new(true, false, SectionShift::LeftThumb)),
+            "ローマ字左親指シフト"
+        );
+        assert_eq!(
+            selector.name(SectionSlot::new(false, true, SectionShift::Ext2)),
+            "記号"
+        );
+    }
+}
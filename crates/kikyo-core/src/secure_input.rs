@@ -0,0 +1,104 @@
+//! Bypass for password fields. Layouts turn keys into kana through the IME,
+//! which garbles passwords, so keys pass through untouched while a password
+//! edit control has the keyboard focus.
+
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use tracing::{info, warn};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED,
+};
+use windows::Win32::UI::Accessibility::{CUIAutomation, IUIAutomation};
+
+type SecureInputCallback = Box<dyn Fn(bool) + Send>;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Set while a password field has the focus and the bypass is enabled.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static WORKER_STARTED: AtomicBool = AtomicBool::new(false);
+static ON_CHANGE: Mutex<Option<SecureInputCallback>> = Mutex::new(None);
+
+lazy_static::lazy_static! {
+    /// Pending focus checks. One queued check covers any number of changes.
+    static ref CHECK_QUEUE: (Sender<()>, Receiver<()>) = crossbeam_channel::bounded(1);
+}
+
+/// Turns the bypass on or off. Checks the focused control right away.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Release);
+    if enabled {
+        ensure_worker_thread();
+        request_check();
+    } else {
+        set_active(false);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Whether keys currently bypass the layout because of a password field.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Registers a callback run whenever [`is_active`] changes.
+pub fn set_on_change(cb: impl Fn(bool) + Send + 'static) {
+    *ON_CHANGE.lock().unwrap() = Some(Box::new(cb));
+}
+
+/// Schedules a check of the focused control. Cheap enough for WinEvent
+/// callbacks; UI Automation itself runs on the worker thread.
+pub(crate) fn request_check() {
+    if is_enabled() {
+        let _ = CHECK_QUEUE.0.try_send(());
+    }
+}
+
+fn set_active(active: bool) {
+    if ACTIVE.swap(active, Ordering::AcqRel) == active {
+        return;
+    }
+    info!("Password field focus: {}", active);
+    if let Some(cb) = ON_CHANGE.lock().unwrap().as_ref() {
+        cb(active);
+    }
+}
+
+fn ensure_worker_thread() {
+    if WORKER_STARTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let rx = CHECK_QUEUE.1.clone();
+    thread::Builder::new()
+        .name("kikyo-secure-input".to_string())
+        .spawn(move || secure_input_worker(rx))
+        .expect("Failed to spawn secure input thread");
+}
+
+/// UI Automation calls can block on the target application, so they never
+/// run on the hook thread.
+fn secure_input_worker(rx: Receiver<()>) {
+    let automation: Option<IUIAutomation> = unsafe {
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+        CoCreateInstance(&CUIAutomation, None, CLSCTX_INPROC_SERVER)
+            .map_err(|e| warn!("UI Automation is unavailable: {}", e))
+            .ok()
+    };
+    while rx.recv().is_ok() {
+        let secure = is_enabled() && automation.as_ref().is_some_and(focused_is_password);
+        set_active(secure);
+    }
+}
+
+fn focused_is_password(automation: &IUIAutomation) -> bool {
+    unsafe {
+        automation
+            .GetFocusedElement()
+            .and_then(|element| element.CurrentIsPassword())
+            .is_ok_and(|is_password| is_password.as_bool())
+    }
+}
@@ -0,0 +1,378 @@
+use crate::trace::keys_label;
+use crate::types::ScKey;
+use crate::JIS_SC_TO_RC;
+use serde::Serialize;
+use smallvec::SmallVec;
+use std::collections::HashMap;
+
+/// Chords typed fewer times than this are left out of the undo ranking.
+const MIN_CHORD_SAMPLES: u64 = 5;
+
+/// Counts which physical keys and chords produced output.
+/// Only key positions are recorded, never the typed text.
+#[derive(Debug, Clone, Default)]
+pub struct UsageStats {
+    keys: HashMap<ScKey, u64>,
+    chords: HashMap<Vec<ScKey>, u64>,
+    total: u64,
+    /// Two single-key outputs in a row.
+    sequences: HashMap<(ScKey, ScKey), u64>,
+    /// Outputs erased right after they were typed, by sorted keys.
+    undone: HashMap<Vec<ScKey>, u64>,
+    last: Option<SortedKeys>,
+}
+
+/// Keys of one output, sorted. Kept inline: most outputs have one or two.
+type SortedKeys = SmallVec<[ScKey; 4]>;
+
+impl UsageStats {
+    /// Records one output produced by `keys` (a tap, chord or repeat).
+    pub fn record(&mut self, keys: &[ScKey]) {
+        if keys.is_empty() {
+            return;
+        }
+        self.total += 1;
+        for key in keys {
+            *self.keys.entry(*key).or_insert(0) += 1;
+        }
+        let sorted = sorted_keys(keys);
+        if keys.len() >= 2 {
+            match self.chords.get_mut(sorted.as_slice()) {
+                Some(count) => *count += 1,
+                None => {
+                    self.chords.insert(sorted.to_vec(), 1);
+                }
+            }
+        }
+        if let (Some([prev]), [key]) = (self.last.as_deref(), keys) {
+            if prev != key {
+                *self.sequences.entry((*prev, *key)).or_insert(0) += 1;
+            }
+        }
+        self.last = Some(sorted);
+    }
+
+    /// Records that the last output was erased (Backspace or the undo
+    /// chord). Further erasing is not counted against it.
+    pub fn record_undo(&mut self) {
+        if let Some(keys) = self.last.take() {
+            *self.undone.entry(keys.to_vec()).or_insert(0) += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Builds a heatmap sorted by position (keys) and frequency (chords).
+    pub fn heatmap(&self) -> UsageHeatmap {
+        let mut keys: Vec<KeyUsage> = self
+            .keys
+            .iter()
+            .map(|(key, &count)| KeyUsage {
+                pos: KeyPos::from_key(*key),
+                count,
+                share: share(count, self.total),
+            })
+            .collect();
+        keys.sort_by_key(|k| (k.pos.sort_key(), k.pos.name.clone()));
+
+        let mut chords: Vec<ChordUsage> = self
+            .chords
+            .iter()
+            .map(|(chord, &count)| ChordUsage {
+                label: keys_label(chord),
+                keys: chord.iter().map(|k| KeyPos::from_key(*k)).collect(),
+                count,
+                share: share(count, self.total),
+            })
+            .collect();
+        chords.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.label.cmp(&b.label)));
+
+        UsageHeatmap {
+            total_outputs: self.total,
+            keys,
+            chords,
+        }
+    }
+}
+
+impl UsageStats {
+    /// Up to `limit` of the most frequent two-key sequences for which
+    /// `is_chorded` finds no chord, and of the chords most often erased
+    /// right after being typed.
+    pub fn suggestions(
+        &self,
+        is_chorded: impl Fn(&[ScKey]) -> bool,
+        limit: usize,
+    ) -> LayoutSuggestions {
+        let mut pairs: HashMap<Vec<ScKey>, u64> = HashMap::new();
+        for ((a, b), &count) in &self.sequences {
+            *pairs.entry(sorted_keys(&[*a, *b]).to_vec()).or_insert(0) += count;
+        }
+        let mut unchorded_sequences: Vec<SequenceSuggestion> = pairs
+            .into_iter()
+            .filter(|(keys, _)| !is_chorded(keys))
+            .map(|(keys, count)| SequenceSuggestion {
+                label: keys_label(&keys),
+                keys: keys.iter().map(|k| KeyPos::from_key(*k)).collect(),
+                count,
+                share: share(count, self.total),
+            })
+            .collect();
+        unchorded_sequences
+            .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.label.cmp(&b.label)));
+        unchorded_sequences.truncate(limit);
+
+        let mut undone_chords: Vec<UndoneChord> = self
+            .chords
+            .iter()
+            .filter(|(_, &count)| count >= MIN_CHORD_SAMPLES)
+            .filter_map(|(chord, &count)| {
+                let undone = *self.undone.get(chord)?;
+                Some(UndoneChord {
+                    label: keys_label(chord),
+                    keys: chord.iter().map(|k| KeyPos::from_key(*k)).collect(),
+                    count,
+                    undone,
+                    rate: share(undone, count),
+                })
+            })
+            .collect();
+        undone_chords.sort_by(|a, b| {
+            b.rate
+                .total_cmp(&a.rate)
+                .then_with(|| b.count.cmp(&a.count))
+                .then_with(|| a.label.cmp(&b.label))
+        });
+        undone_chords.truncate(limit);
+
+        LayoutSuggestions {
+            unchorded_sequences,
+            undone_chords,
+        }
+    }
+}
+
+fn sorted_keys(keys: &[ScKey]) -> SortedKeys {
+    let mut sorted = SortedKeys::from_slice(keys);
+    sorted.sort_by_key(|k| (k.sc, k.ext));
+    sorted
+}
+
+fn share(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+/// Physical key, located by layout row/column when it has one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyPos {
+    pub row: Option<u8>,
+    pub col: Option<u8>,
+    pub name: String,
+}
+
+impl KeyPos {
+    fn from_key(key: ScKey) -> Self {
+        let rc = JIS_SC_TO_RC
+            .iter()
+            .find(|(sc, _)| *sc == key)
+            .map(|(_, rc)| *rc);
+        let name = keys_label(&[key]);
+        Self {
+            row: rc.map(|rc| rc.row),
+            col: rc.map(|rc| rc.col),
+            name,
+        }
+    }
+
+    /// Keys outside the layout grid (thumb keys etc.) sort last.
+    fn sort_key(&self) -> (u8, u8) {
+        (self.row.unwrap_or(u8::MAX), self.col.unwrap_or(u8::MAX))
+    }
+
+    fn rc_text(&self) -> String {
+        match (self.row, self.col) {
+            (Some(row), Some(col)) => format!("{}:{}", row, col),
+            _ => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyUsage {
+    #[serde(flatten)]
+    pub pos: KeyPos,
+    pub count: u64,
+    /// Fraction of all outputs this key took part in.
+    pub share: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChordUsage {
+    pub label: String,
+    pub keys: Vec<KeyPos>,
+    pub count: u64,
+    pub share: f64,
+}
+
+/// Per-key and per-chord usage, suitable for JSON export or a UI heatmap.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageHeatmap {
+    pub total_outputs: u64,
+    pub keys: Vec<KeyUsage>,
+    pub chords: Vec<ChordUsage>,
+}
+
+/// Two keys often typed one after the other that could become a chord.
+/// Both orders are counted together.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SequenceSuggestion {
+    pub label: String,
+    pub keys: Vec<KeyPos>,
+    pub count: u64,
+    pub share: f64,
+}
+
+/// Chord whose output was often erased right away, hinting at misfires.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UndoneChord {
+    pub label: String,
+    pub keys: Vec<KeyPos>,
+    pub count: u64,
+    pub undone: u64,
+    /// `undone / count`.
+    pub rate: f64,
+}
+
+/// Hints for layout authors, from the usage statistics.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayoutSuggestions {
+    pub unchorded_sequences: Vec<SequenceSuggestion>,
+    pub undone_chords: Vec<UndoneChord>,
+}
+
+impl UsageHeatmap {
+    /// Renders the heatmap as CSV with one row per key and per chord.
+    /// The `rc` column holds "row:col" positions joined with '+'.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("kind,label,rc,count,share\n");
+        for key in &self.keys {
+            out.push_str(&format!(
+                "key,{},{},{},{:.6}\n",
+                csv_field(&key.pos.name),
+                key.pos.rc_text(),
+                key.count,
+                key.share
+            ));
+        }
+        for chord in &self.chords {
+            let rc = chord
+                .keys
+                .iter()
+                .map(KeyPos::rc_text)
+                .collect::<Vec<_>>()
+                .join("+");
+            out.push_str(&format!(
+                "chord,{},{},{},{:.6}\n",
+                csv_field(&chord.label),
+                rc,
+                chord.count,
+                chord.share
+            ));
+        }
+        out
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_counts_keys_and_chords() {
+        let j = ScKey::new(0x24, false);
+        let k = ScKey::new(0x25, false);
+        let muhenkan = ScKey::new(0x7B, false);
+
+        let mut stats = UsageStats::default();
+        stats.record(&[j]);
+        stats.record(&[k, j]);
+        stats.record(&[j, k]);
+        stats.record(&[muhenkan, k]);
+
+        let heatmap = stats.heatmap();
+        assert_eq!(heatmap.total_outputs, 4);
+        assert_eq!(heatmap.keys.len(), 3);
+        assert_eq!(heatmap.keys[0].pos.row, Some(2));
+        assert_eq!(heatmap.keys[0].count, 3);
+        assert_eq!(heatmap.keys[2].pos.row, None);
+
+        assert_eq!(heatmap.chords.len(), 2);
+        assert_eq!(heatmap.chords[0].count, 2);
+        assert_eq!(heatmap.chords[0].keys.len(), 2);
+
+        let csv = heatmap.to_csv();
+        assert!(csv.starts_with("kind,label,rc,count,share\n"));
+        assert!(csv.contains("chord,"));
+        assert_eq!(csv.lines().count(), 1 + 3 + 2);
+    }
+
+    #[test]
+    fn test_suggestions_rank_unchorded_sequences_and_undone_chords() {
+        let j = ScKey::new(0x24, false);
+        let k = ScKey::new(0x25, false);
+        let l = ScKey::new(0x26, false);
+        let d = ScKey::new(0x20, false);
+
+        let mut stats = UsageStats::default();
+        for _ in 0..3 {
+            stats.record(&[j]);
+            stats.record(&[k]);
+        }
+        stats.record(&[l]);
+        stats.record(&[d]);
+        for i in 0..MIN_CHORD_SAMPLES {
+            stats.record(&[d, k]);
+            if i % 2 == 0 {
+                stats.record_undo();
+                stats.record_undo();
+            }
+        }
+
+        let suggestions = stats.suggestions(|keys| keys.contains(&l), 10);
+        let sequences = &suggestions.unchorded_sequences;
+        assert_eq!(sequences[0].label, keys_label(&[j, k]));
+        assert_eq!(sequences[0].count, 5);
+        assert!(sequences
+            .iter()
+            .all(|s| !s.label.contains(&keys_label(&[l]))));
+
+        let undone = &suggestions.undone_chords;
+        assert_eq!(undone.len(), 1);
+        assert_eq!(undone[0].count, MIN_CHORD_SAMPLES);
+        assert_eq!(undone[0].undone, 3);
+    }
+
+    #[test]
+    fn test_csv_field_quotes_separators() {
+        assert_eq!(csv_field("J"), "J");
+        assert_eq!(csv_field(","), "\",\"");
+    }
+}
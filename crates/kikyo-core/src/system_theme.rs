@@ -0,0 +1,23 @@
+use std::ffi::c_void;
+use std::mem::size_of;
+use windows::core::w;
+use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+
+/// Whether the taskbar (and so the tray) uses the light theme.
+/// Windows defaults to a dark taskbar when the value is missing.
+pub fn taskbar_uses_light_theme() -> bool {
+    let mut value = 0u32;
+    let mut len = size_of::<u32>() as u32;
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            w!("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize"),
+            w!("SystemUsesLightTheme"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut u32 as *mut c_void),
+            Some(&mut len),
+        )
+    };
+    result.is_ok() && value != 0
+}
@@ -0,0 +1,107 @@
+//! Virtual numeric keypad for keyboards without one. While it is on, the
+//! `[テンキー]` section comes before the layout. It follows the keypad
+//! printed on laptop keyboards rather than only JKL;UIOP, so that the
+//! keys stay where those users know them: 789 / UIO / JKL / M type the
+//! keypad digits, 0 P ; / its operators and . its decimal point.
+
+use crate::jis_map::key_to_rc;
+use crate::types::{KeySpec, KeyStroke, Layout, Modifiers, Plane, ScKey, Section, Token};
+
+/// Section of the keypad; a layout may define its own to change it.
+pub const TENKEY_SECTION: &str = "テンキー";
+/// Token turning the keypad on or off.
+pub const TENKEY_TOKEN: &str = "{テンキー}";
+
+/// (physical key, keypad virtual key) of the built-in `[テンキー]` section.
+/// Virtual keys, as the keypad scancodes type arrows with NumLock off.
+const TENKEY_KEYS: [(u16, u16); 15] = [
+    (0x08, 0x67), // 7 -> VK_NUMPAD7
+    (0x09, 0x68), // 8 -> VK_NUMPAD8
+    (0x0A, 0x69), // 9 -> VK_NUMPAD9
+    (0x0B, 0x6F), // 0 -> VK_DIVIDE
+    (0x16, 0x64), // U -> VK_NUMPAD4
+    (0x17, 0x65), // I -> VK_NUMPAD5
+    (0x18, 0x66), // O -> VK_NUMPAD6
+    (0x19, 0x6A), // P -> VK_MULTIPLY
+    (0x24, 0x61), // J -> VK_NUMPAD1
+    (0x25, 0x62), // K -> VK_NUMPAD2
+    (0x26, 0x63), // L -> VK_NUMPAD3
+    (0x27, 0x6D), // ; -> VK_SUBTRACT
+    (0x32, 0x60), // M -> VK_NUMPAD0
+    (0x34, 0x6E), // . -> VK_DECIMAL
+    (0x35, 0x6B), // / -> VK_ADD
+];
+
+/// The built-in `[テンキー]` section.
+pub fn tenkey_section() -> Section {
+    let map = TENKEY_KEYS
+        .iter()
+        .filter_map(|&(key, vk)| {
+            let token = Token::KeySequence(vec![KeyStroke {
+                key: KeySpec::VirtualKey(vk),
+                mods: Modifiers::none(),
+            }]);
+            key_to_rc(ScKey::new(key, false)).map(|rc| (rc, token))
+        })
+        .collect();
+    Section {
+        name: TENKEY_SECTION.to_string(),
+        base_plane: Plane { map },
+        sub_planes: Default::default(),
+        name_keys: Vec::new(),
+        sub_plane_keys: Default::default(),
+    }
+}
+
+/// Adds the built-in `[テンキー]` section unless the layout has one.
+pub fn merge_tenkey_section(layout: &mut Layout) {
+    layout
+        .sections
+        .entry(TENKEY_SECTION.to_string())
+        .or_insert_with(tenkey_section);
+}
+
+/// True if typing `token` turns the keypad on or off.
+pub fn toggles_tenkey(token: &Token) -> bool {
+    matches!(token, Token::KeySequence(strokes)
+        if strokes.iter().any(|stroke| stroke.key == KeySpec::TenkeyToggle))
+}
+
+/// True for a layout holding nothing but the keypad, as loaded when it is
+/// turned on without a layout.
+pub fn is_tenkey_only(layout: &Layout) -> bool {
+    layout.name.is_none()
+        && layout.sections.len() == 1
+        && layout.sections.contains_key(TENKEY_SECTION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_keeps_the_layouts_own_section() {
+        let mut layout = Layout::default();
+        merge_tenkey_section(&mut layout);
+        assert!(is_tenkey_only(&layout));
+        let j = key_to_rc(ScKey::new(0x24, false)).unwrap();
+        assert_eq!(
+            layout.sections[TENKEY_SECTION].base_plane.map.get(&j),
+            Some(&Token::KeySequence(vec![KeyStroke {
+                key: KeySpec::VirtualKey(0x61),
+                mods: Modifiers::none(),
+            }]))
+        );
+
+        let mut own = Layout::default();
+        own.sections.insert(
+            TENKEY_SECTION.to_string(),
+            Section {
+                name: TENKEY_SECTION.to_string(),
+                ..Section::default()
+            },
+        );
+        merge_tenkey_section(&mut own);
+        assert!(own.sections[TENKEY_SECTION].base_plane.map.is_empty());
+    }
+}
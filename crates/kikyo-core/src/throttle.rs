@@ -0,0 +1,73 @@
+use crate::foreground::{exe_file_name, foreground_exe_name};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+static OUTPUT_THROTTLE: RwLock<OutputThrottle> = RwLock::new(OutputThrottle {
+    delay_ms: 0,
+    apps: Vec::new(),
+});
+
+/// Pause between injected events, for terminals and remote sessions that
+/// drop input arriving too quickly.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputThrottle {
+    /// Delay for every application not listed in `apps`.
+    pub delay_ms: u64,
+    pub apps: Vec<AppDelay>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppDelay {
+    /// Executable file name, e.g. "mstsc.exe". Matched case-insensitively.
+    pub exe: String,
+    pub delay_ms: u64,
+}
+
+impl OutputThrottle {
+    pub fn delay_for(&self, exe: Option<&str>) -> u64 {
+        exe.and_then(|exe| {
+            self.apps
+                .iter()
+                .find(|app| exe_file_name(&app.exe) == exe)
+                .map(|app| app.delay_ms)
+        })
+        .unwrap_or(self.delay_ms)
+    }
+
+    fn is_off(&self) -> bool {
+        self.delay_ms == 0 && self.apps.iter().all(|app| app.delay_ms == 0)
+    }
+}
+
+pub fn set_output_throttle(throttle: OutputThrottle) {
+    *OUTPUT_THROTTLE.write() = throttle;
+}
+
+/// Delay to insert between injected events for the foreground application.
+pub fn current_delay_ms() -> u64 {
+    let throttle = OUTPUT_THROTTLE.read();
+    if throttle.is_off() {
+        return 0;
+    }
+    throttle.delay_for(foreground_exe_name().as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app_delay_overrides_global_delay() {
+        let throttle = OutputThrottle {
+            delay_ms: 2,
+            apps: vec![AppDelay {
+                exe: "MSTSC.exe".to_string(),
+                delay_ms: 15,
+            }],
+        };
+        assert_eq!(throttle.delay_for(Some("mstsc.exe")), 15);
+        assert_eq!(throttle.delay_for(Some("notepad.exe")), 2);
+        assert_eq!(throttle.delay_for(None), 2);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::chord_engine::SpaceWidth;
+use crate::jis_map::display_name;
+use crate::types::{KeySpec, ScKey, Token};
+use serde::Serialize;
+
+/// How the engine arrived at an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TraceKind {
+    Tap,
+    Chord,
+    Repeat,
+    LongPress,
+}
+
+/// One resolved output, published as `EngineEvent::Trace`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceEvent {
+    pub kind: TraceKind,
+    /// Physical keys that produced the output.
+    pub keys: Vec<ScKey>,
+    /// Readable form of `keys` (e.g. "K+無変換").
+    pub label: String,
+    /// Plain text of the output, or `None` for non-text tokens.
+    pub output: Option<String>,
+    /// Text the training session expected at this point.
+    pub expected: Option<String>,
+    /// Whether `output` matched `expected` (only set while training).
+    pub matched: Option<bool>,
+}
+
+impl TraceEvent {
+    pub fn new(kind: TraceKind, keys: &[ScKey], token: &Token) -> Self {
+        Self {
+            kind,
+            keys: keys.to_vec(),
+            label: keys_label(keys),
+            output: token_text(token),
+            expected: None,
+            matched: None,
+        }
+    }
+}
+
+/// Joins the keys' display names with '+'.
+pub fn keys_label(keys: &[ScKey]) -> String {
+    keys.iter()
+        .map(|&k| display_name(k))
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// Text a token types, ignoring shift. Tokens with control keys or
+/// Ctrl/Alt/Win modifiers have no text.
+pub fn token_text(token: &Token) -> Option<String> {
+    match token {
+        Token::None => None,
+        Token::ImeChar(text) | Token::DirectChar(text) => Some(text.clone()),
+        Token::KeySequence(seq) => {
+            let mut out = String::new();
+            for stroke in seq {
+                if stroke.mods.ctrl || stroke.mods.alt || stroke.mods.win {
+                    return None;
+                }
+                match &stroke.key {
+                    KeySpec::Char(c) => out.push(*c),
+                    KeySpec::DirectString(s) | KeySpec::Paste(s) => out.push_str(s),
+                    KeySpec::Space(SpaceWidth::Full) => out.push('\u{3000}'),
+                    KeySpec::Space(_) => out.push(' '),
+                    KeySpec::Voicing(voicing) => out.push(voicing.mark()),
+                    _ => return None,
+                }
+            }
+            Some(out)
+        }
+    }
+}
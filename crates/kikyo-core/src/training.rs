@@ -0,0 +1,218 @@
+use crate::romaji_map::{kana_to_romaji, normalize_symbol};
+use crate::trace::TraceEvent;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Hit/miss counters for one physical chord.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChordStat {
+    pub label: String,
+    pub attempts: u32,
+    pub errors: u32,
+    pub error_rate: f64,
+}
+
+/// Snapshot of a training session.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrainingReport {
+    pub target: String,
+    /// Number of target characters typed correctly so far.
+    pub position: usize,
+    pub total: usize,
+    pub correct: u32,
+    pub errors: u32,
+    pub finished: bool,
+    pub elapsed_ms: u64,
+    pub chars_per_minute: f64,
+    /// Words per minute, counting five characters as one word.
+    pub wpm: f64,
+    pub accuracy: f64,
+    /// Per-chord statistics, worst error rate first.
+    pub chords: Vec<ChordStat>,
+}
+
+/// Compares engine output against a target text. Text is compared in the
+/// form the engine types it, so kana targets are matched as romaji.
+pub struct TrainingSession {
+    target: String,
+    /// Target characters in typed form.
+    units: Vec<String>,
+    position: usize,
+    correct: u32,
+    errors: u32,
+    started: Option<Instant>,
+    last: Option<Instant>,
+    chords: HashMap<String, (u32, u32)>,
+}
+
+impl TrainingSession {
+    pub fn new(target: &str) -> Self {
+        let units = target
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| normalize_text(&c.to_string()))
+            .collect();
+        Self {
+            target: target.to_string(),
+            units,
+            position: 0,
+            correct: 0,
+            errors: 0,
+            started: None,
+            last: None,
+            chords: HashMap::new(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.units.len()
+    }
+
+    /// Checks a traced output against the target and fills in
+    /// `expected`/`matched`. Non-text output is ignored.
+    pub fn annotate(&mut self, event: &mut TraceEvent, now: Instant) {
+        let Some(output) = event.output.as_deref() else {
+            return;
+        };
+        if self.is_finished() {
+            return;
+        }
+        self.started.get_or_insert(now);
+        self.last = Some(now);
+
+        let output = normalize_text(output);
+        let mut expected = String::new();
+        let mut consumed = 0;
+        for unit in &self.units[self.position..] {
+            if expected.len() >= output.len() {
+                break;
+            }
+            expected.push_str(unit);
+            consumed += 1;
+        }
+
+        let matched = !output.is_empty() && expected == output;
+        let stat = self.chords.entry(event.label.clone()).or_default();
+        stat.0 += 1;
+        if matched {
+            self.position += consumed;
+            self.correct += consumed as u32;
+        } else {
+            stat.1 += 1;
+            self.errors += 1;
+        }
+
+        event.expected = Some(expected);
+        event.matched = Some(matched);
+    }
+
+    pub fn report(&self) -> TrainingReport {
+        let elapsed = match (self.started, self.last) {
+            (Some(start), Some(last)) => last.duration_since(start),
+            _ => Duration::ZERO,
+        };
+        let minutes = elapsed.as_secs_f64() / 60.0;
+        let chars_per_minute = if minutes > 0.0 {
+            self.correct as f64 / minutes
+        } else {
+            0.0
+        };
+        let attempts = self.correct + self.errors;
+        let accuracy = if attempts > 0 {
+            self.correct as f64 / attempts as f64
+        } else {
+            1.0
+        };
+
+        let mut chords: Vec<ChordStat> = self
+            .chords
+            .iter()
+            .map(|(label, &(attempts, errors))| ChordStat {
+                label: label.clone(),
+                attempts,
+                errors,
+                error_rate: errors as f64 / attempts as f64,
+            })
+            .collect();
+        chords.sort_by(|a, b| {
+            b.error_rate
+                .total_cmp(&a.error_rate)
+                .then_with(|| a.label.cmp(&b.label))
+        });
+
+        TrainingReport {
+            target: self.target.clone(),
+            position: self.position,
+            total: self.units.len(),
+            correct: self.correct,
+            errors: self.errors,
+            finished: self.is_finished(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            chars_per_minute,
+            wpm: chars_per_minute / 5.0,
+            accuracy,
+            chords,
+        }
+    }
+}
+
+/// Converts text to the key characters the engine types for it
+/// (kana to romaji, full-width symbols to ASCII).
+fn normalize_text(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if let Some(romaji) = kana_to_romaji(c) {
+            out.push_str(romaji);
+        } else if let Some(norm) = normalize_symbol(c) {
+            out.push(norm);
+        } else {
+            out.push(c.to_ascii_lowercase());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::TraceKind;
+    use crate::types::{ScKey, Token};
+
+    fn typed(session: &mut TrainingSession, sc: u16, text: &str, now: Instant) -> TraceEvent {
+        let token = Token::ImeChar(text.to_string());
+        let mut event = TraceEvent::new(TraceKind::Tap, &[ScKey::new(sc, false)], &token);
+        session.annotate(&mut event, now);
+        event
+    }
+
+    #[test]
+    fn test_correct_output_advances_and_errors_are_counted() {
+        let mut session = TrainingSession::new("かき");
+        let start = Instant::now();
+
+        let event = typed(&mut session, 0x25, "ka", start);
+        assert_eq!(event.matched, Some(true));
+        assert_eq!(event.expected.as_deref(), Some("ka"));
+
+        let event = typed(&mut session, 0x26, "ku", start + Duration::from_secs(1));
+        assert_eq!(event.matched, Some(false));
+
+        typed(&mut session, 0x17, "き", start + Duration::from_secs(6));
+        let report = session.report();
+        assert!(report.finished);
+        assert_eq!((report.correct, report.errors), (2, 1));
+        assert_eq!(report.elapsed_ms, 6000);
+        assert!((report.chars_per_minute - 20.0).abs() < 1e-9);
+        assert_eq!(report.chords[0].errors, 1);
+    }
+
+    #[test]
+    fn test_non_text_output_is_ignored() {
+        let mut session = TrainingSession::new("a");
+        let mut event = TraceEvent::new(TraceKind::Tap, &[ScKey::new(0x1C, false)], &Token::None);
+        session.annotate(&mut event, Instant::now());
+        assert_eq!(event.matched, None);
+        assert_eq!(session.report().errors, 0);
+    }
+}
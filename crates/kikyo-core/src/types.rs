@@ -1,3 +1,7 @@
+use crate::chord_engine::{CompositionPolicy, SpaceWidth};
+use crate::dynamic_text::DynamicText;
+use crate::scripting::ScriptCall;
+use crate::voicing::Voicing;
 use serde::{Deserialize, Serialize};
 
 /// Windows Scancode + Extended flag key identifier.
@@ -13,25 +17,149 @@ impl ScKey {
     }
 }
 
-/// Event to be injected.
+/// Physical Shift keys held with a key event. `Both` also stands for a
+/// Shift whose side is unknown and selects only the plain `小指` sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ShiftSide {
+    #[default]
+    None,
+    Left,
+    Right,
+    Both,
+}
+
+impl ShiftSide {
+    pub fn from_keys(left: bool, right: bool) -> Self {
+        match (left, right) {
+            (false, false) => ShiftSide::None,
+            (true, false) => ShiftSide::Left,
+            (false, true) => ShiftSide::Right,
+            (true, true) => ShiftSide::Both,
+        }
+    }
+
+    pub fn is_held(self) -> bool {
+        self != ShiftSide::None
+    }
+
+    pub fn left(self) -> bool {
+        matches!(self, ShiftSide::Left | ShiftSide::Both)
+    }
+
+    pub fn right(self) -> bool {
+        matches!(self, ShiftSide::Right | ShiftSide::Both)
+    }
+}
+
+impl From<bool> for ShiftSide {
+    fn from(held: bool) -> Self {
+        if held {
+            ShiftSide::Both
+        } else {
+            ShiftSide::None
+        }
+    }
+}
+
+/// Real modifier selecting a `<Ctrl>`, `<Alt>` or `<Win>` sub-plane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModifierPlane {
+    Ctrl,
+    Alt,
+    Win,
+}
+
+impl ModifierPlane {
+    pub const ALL: [ModifierPlane; 3] =
+        [ModifierPlane::Ctrl, ModifierPlane::Alt, ModifierPlane::Win];
+
+    /// Sub-plane tag in a layout section.
+    pub fn tag(self) -> &'static str {
+        match self {
+            ModifierPlane::Ctrl => "<Ctrl>",
+            ModifierPlane::Alt => "<Alt>",
+            ModifierPlane::Win => "<Win>",
+        }
+    }
+
+    /// Bit in [`crate::engine::Engine::modifier_plane_mask`].
+    pub fn bit(self) -> u8 {
+        match self {
+            ModifierPlane::Ctrl => 1,
+            ModifierPlane::Alt => 2,
+            ModifierPlane::Win => 4,
+        }
+    }
+
+    /// Physical key of the left or right modifier.
+    pub fn key(self, right: bool) -> ScKey {
+        match (self, right) {
+            (ModifierPlane::Ctrl, right) => ScKey::new(0x1D, right),
+            (ModifierPlane::Alt, right) => ScKey::new(0x38, right),
+            (ModifierPlane::Win, false) => ScKey::new(0x5B, true),
+            (ModifierPlane::Win, true) => ScKey::new(0x5C, true),
+        }
+    }
+}
+
+/// The only modifier held with a key event, and whether it is the
+/// right-hand key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldModifier {
+    pub plane: ModifierPlane,
+    pub right: bool,
+}
+
+/// Event to be injected. New kinds of output may be added.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum InputEvent {
     /// Scancode injection (scancode, ext, up).
     Scancode(u16, bool, bool),
+    /// Virtual-key injection with its scancode (vk, scancode, ext, up), for
+    /// keypad keys: by scancode alone they type arrows and Home with
+    /// NumLock off.
+    VirtualKey(u16, u16, bool, bool),
     /// Unicode character injection (char, up).
     Unicode(char, bool),
     /// IME Control (true=ON, false=OFF).
     ImeControl(bool),
+    /// IME operation other than turning it on or off.
+    Ime(ImeCommand),
     /// Wait until IME status matches the expected value (expected, timeout_ms).
     WaitUntilImeStatus(bool, u64),
     /// Delay for a specified duration (ms).
     Delay(u64),
     /// Inject a string with robust IME handling (check status -> OFF -> inject -> ON).
     DirectString(String),
+    /// The clipboard's text, typed as [`Self::DirectString`]. Read when it
+    /// is injected, not while the engine is locked.
+    ClipboardText,
+    /// Grapheme cluster of several code points, injected in one batch.
+    Grapheme(String),
+    /// Text pasted through the clipboard.
+    Paste(String),
+    /// Handle an active IME composition before the events that follow.
+    SettleComposition(CompositionPolicy),
+    /// Output for the output scripts, resolved before injection. Never
+    /// returned by `crate::api`.
+    Script(Box<ScriptCall>),
+}
+
+/// Physical key event as delivered by the hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawKeyEvent {
+    pub sc: u16,
+    pub ext: bool,
+    pub up: bool,
+    pub shift: ShiftSide,
+    /// Ctrl, Alt or Win held when the layout has its sub-plane.
+    pub modifier: Option<HeldModifier>,
 }
 
 /// Action to be taken by the hook.
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum KeyAction {
     Pass,
     Block,
@@ -76,6 +204,50 @@ impl Modifiers {
     }
 }
 
+/// Toggle state of CapsLock and kana lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct LockState {
+    pub caps: bool,
+    pub kana: bool,
+}
+
+impl LockState {
+    pub const fn get(self, lock: LockKey) -> bool {
+        match lock {
+            LockKey::Caps => self.caps,
+            LockKey::Kana => self.kana,
+        }
+    }
+
+    pub fn toggle(&mut self, lock: LockKey) {
+        match lock {
+            LockKey::Caps => self.caps = !self.caps,
+            LockKey::Kana => self.kana = !self.kana,
+        }
+    }
+}
+
+/// CapsLock or kana lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LockKey {
+    Caps,
+    Kana,
+}
+
+/// IME operation a layout can type. New operations may be added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImeCommand {
+    /// Reconverts the selected text, or the word before the caret.
+    Reconvert,
+    /// Turns the IME on in full-width hiragana.
+    Hiragana,
+    /// Turns the IME on in full-width katakana.
+    Katakana,
+    /// Turns the IME on in half-width katakana.
+    HalfWidthKana,
+}
+
 /// Key specification inside a keystroke sequence.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeySpec {
@@ -89,8 +261,21 @@ pub enum KeySpec {
     ImeOn,
     /// Turn IME Off (Alphanumeric Input).
     ImeOff,
+    /// IME operation (`{再変換}`, `{ひらがな}`, `{カタカナ}`, `{半角カナ}`).
+    Ime(ImeCommand),
     /// Direct string output (IME confirmed).
     DirectString(String),
+    /// Text pasted through the clipboard (`{paste "..."}`).
+    Paste(String),
+    /// Space of a fixed width (`{半角空白}`, `{全角空白}`), typed regardless
+    /// of the IME settings.
+    Space(SpaceWidth),
+    /// Voices the kana typed before (`{濁点}`, `{半濁点}`).
+    Voicing(Voicing),
+    /// Text filled in when typed (`{date}`, `{time}`, `{clipboard}`).
+    Dynamic(DynamicText),
+    /// Turns the virtual numeric keypad on or off (`{テンキー}`).
+    TenkeyToggle,
 }
 
 /// A single keystroke with optional modifiers.
@@ -136,22 +321,60 @@ pub struct Section {
     pub base_plane: Plane,
     // Map from plane tag (e.g. "<k>") to Plane
     pub sub_planes: std::collections::HashMap<String, Plane>,
+    /// Keys named by `<key>` tags in `name`, in order.
+    pub name_keys: Vec<ScKey>,
+    /// Keys named by each sub-plane tag, in order. Filled by the parser.
+    pub sub_plane_keys: std::collections::HashMap<String, Vec<ScKey>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Layout {
     pub name: Option<String>,
+    /// Author, version etc. from header comments or the `[情報]` section.
+    pub info: LayoutInfo,
     pub sections: std::collections::HashMap<String, Section>,
     pub function_key_swaps: Vec<(String, String)>,
+    /// `[配置換え]` entries as (physical key, key it acts as). All keys move
+    /// at once, before `[機能キー]` swaps and the layout lookup.
+    pub key_remaps: Vec<(String, String)>,
+    /// `[優先順位]` entries as (modifier, target): when both `<a>` for b and
+    /// `<b>` for a define the pair, the modifier's plane wins.
+    pub chord_priorities: Vec<(String, String)>,
+    /// `[セクション名]` entries as (standard section name, name used instead).
+    pub section_names: Vec<(String, String)>,
+    /// `[継承]` entries as (section, parent): keys a section leaves
+    /// undefined are looked up in its parent, and so on up the chain.
+    pub section_fallbacks: Vec<(String, String)>,
+    /// `[タイミング]` entries as (one or two key names, overlap ratio).
+    pub overlap_overrides: Vec<(Vec<String>, f64)>,
+    /// `[アプリ別]` entries as (executable name, tag): while that app is in
+    /// the foreground, `[section:tag]` cells override those of `[section]`.
+    pub app_sections: Vec<(String, String)>,
     pub max_chord_size: usize,
 }
 
+/// Descriptive fields of a layout file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayoutInfo {
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+}
+
 impl Default for Layout {
     fn default() -> Self {
         Self {
             name: None,
+            info: LayoutInfo::default(),
             sections: std::collections::HashMap::new(),
             function_key_swaps: Vec::new(),
+            key_remaps: Vec::new(),
+            chord_priorities: Vec::new(),
+            section_names: Vec::new(),
+            section_fallbacks: Vec::new(),
+            overlap_overrides: Vec::new(),
+            app_sections: Vec::new(),
             max_chord_size: 2,
         }
     }
@@ -0,0 +1,234 @@
+use crate::types::{InputEvent, KeySpec, KeyStroke, Token};
+use crate::voicing::{self, LastKana};
+
+/// Scancode used to erase injected output.
+const BACKSPACE_SC: u16 = 0x0E;
+
+/// Remembers how many visible characters the last emitted token produced,
+/// so that an undo chord can erase exactly that output, and the last kana
+/// for the voicing keys.
+#[derive(Debug, Clone, Default)]
+pub struct UndoTracker {
+    last_len: Option<usize>,
+    group_open: bool,
+    last_kana: Option<LastKana>,
+}
+
+impl UndoTracker {
+    /// Starts a new output group. Tokens recorded until the next call are
+    /// undone together (e.g. the sequential fallback of an undefined chord).
+    pub fn begin_output(&mut self) {
+        self.group_open = false;
+    }
+
+    /// Records the output of a token. Tokens that are not plain text
+    /// (cursor keys, IME control, ...) make the history non-undoable.
+    pub fn record_token(&mut self, token: &Token, is_japanese: bool) {
+        let len = visible_len(token, is_japanese);
+        self.last_len = if self.group_open {
+            self.last_len.zip(len).map(|(a, b)| a + b)
+        } else {
+            len
+        };
+        self.group_open = true;
+        self.last_kana = match token {
+            Token::None => self.last_kana,
+            Token::ImeChar(text) => text.chars().last().map(|kana| LastKana {
+                kana,
+                direct: false,
+            }),
+            Token::DirectChar(text) => text
+                .chars()
+                .last()
+                .map(|kana| LastKana { kana, direct: true }),
+            Token::KeySequence(seq) => voicing::trailing_kana(seq, is_japanese),
+        };
+    }
+
+    /// Forgets the last output (e.g. after a passthrough key).
+    pub fn clear(&mut self) {
+        self.last_len = None;
+        self.group_open = false;
+        self.last_kana = None;
+    }
+
+    pub fn last_len(&self) -> Option<usize> {
+        self.last_len
+    }
+
+    /// Kana typed last, unless other keys were typed since.
+    pub fn last_kana(&self) -> Option<LastKana> {
+        self.last_kana
+    }
+
+    /// Returns Backspace events erasing the last output and forgets it.
+    pub fn take_undo_events(&mut self) -> Vec<InputEvent> {
+        let count = self.last_len.take().unwrap_or(0);
+        let mut events = Vec::with_capacity(count * 2);
+        for _ in 0..count {
+            events.push(InputEvent::Scancode(BACKSPACE_SC, false, false));
+            events.push(InputEvent::Scancode(BACKSPACE_SC, false, true));
+        }
+        events
+    }
+}
+
+/// Number of characters a token leaves in the editor (or IME composition).
+/// In Japanese mode, romaji key sequences are counted as kana.
+pub fn visible_len(token: &Token, is_japanese: bool) -> Option<usize> {
+    match token {
+        Token::None => None,
+        Token::ImeChar(text) | Token::DirectChar(text) => Some(text.chars().count()),
+        Token::KeySequence(seq) => {
+            let mut total = 0;
+            // Start of the romaji keys typed since the last text.
+            let mut romaji = 0;
+            for (i, stroke) in seq.iter().enumerate() {
+                if stroke.mods.ctrl || stroke.mods.alt || stroke.mods.win {
+                    return None;
+                }
+                match &stroke.key {
+                    KeySpec::Char(_) => {}
+                    KeySpec::DirectString(s) | KeySpec::Paste(s) => {
+                        total += count_chars(&seq[romaji..i], is_japanese);
+                        romaji = i + 1;
+                        total += s.chars().count();
+                    }
+                    KeySpec::Space(_) => {
+                        total += count_chars(&seq[romaji..i], is_japanese);
+                        romaji = i + 1;
+                        total += 1;
+                    }
+                    KeySpec::TenkeyToggle => {}
+                    KeySpec::Scancode(..)
+                    | KeySpec::VirtualKey(_)
+                    | KeySpec::ImeOn
+                    | KeySpec::ImeOff
+                    | KeySpec::Ime(_)
+                    | KeySpec::Voicing(_)
+                    | KeySpec::Dynamic(_) => return None,
+                }
+            }
+            total += count_chars(&seq[romaji..], is_japanese);
+            Some(total)
+        }
+    }
+}
+
+/// Characters typed by the character keys among `strokes`.
+fn count_chars(strokes: &[KeyStroke], is_japanese: bool) -> usize {
+    let chars = strokes.iter().filter_map(|stroke| match stroke.key {
+        KeySpec::Char(c) => Some(c),
+        _ => None,
+    });
+    if is_japanese {
+        kana_count(chars)
+    } else {
+        chars.count()
+    }
+}
+
+/// Counts the kana an IME produces from a romaji key sequence
+/// (e.g. "kya" -> 1, "nn" -> 1, "tte" -> 2).
+pub fn romaji_kana_count(romaji: &str) -> usize {
+    kana_count(romaji.chars())
+}
+
+fn kana_count(romaji: impl Iterator<Item = char>) -> usize {
+    let mut chars = romaji.map(|c| c.to_ascii_lowercase()).peekable();
+    let is_vowel = |c: char| matches!(c, 'a' | 'i' | 'u' | 'e' | 'o');
+    let mut count = 0;
+    let mut pending_consonants = 0;
+
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        if is_vowel(c) {
+            count += 1;
+            pending_consonants = 0;
+        } else if c == 'n' && next == Some('n') {
+            count += 1;
+            pending_consonants = 0;
+            chars.next();
+        } else if c == 'n'
+            && next.is_some_and(|next| next.is_ascii_alphabetic() && !is_vowel(next) && next != 'y')
+        {
+            // "n" before another consonant is read as ん.
+            count += 1;
+            pending_consonants = 0;
+        } else if c.is_ascii_alphabetic() {
+            if next == Some(c) {
+                // Doubled consonant becomes a small tsu.
+                count += 1;
+            } else {
+                pending_consonants += 1;
+            }
+        } else {
+            count += pending_consonants + 1;
+            pending_consonants = 0;
+        }
+    }
+
+    count + pending_consonants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_key_sequence_expanded;
+
+    fn seq(text: &str) -> Token {
+        Token::KeySequence(parse_key_sequence_expanded(text))
+    }
+
+    #[test]
+    fn test_romaji_kana_count() {
+        assert_eq!(romaji_kana_count("ka"), 1);
+        assert_eq!(romaji_kana_count("kya"), 1);
+        assert_eq!(romaji_kana_count("shi"), 1);
+        assert_eq!(romaji_kana_count("nn"), 1);
+        assert_eq!(romaji_kana_count("tte"), 2);
+        assert_eq!(romaji_kana_count("ltu"), 1);
+        assert_eq!(romaji_kana_count("kanji"), 3);
+        assert_eq!(romaji_kana_count("a-"), 2);
+    }
+
+    #[test]
+    fn test_visible_len() {
+        assert_eq!(visible_len(&seq("nyo"), true), Some(1));
+        assert_eq!(visible_len(&seq("nyo"), false), Some(3));
+        assert_eq!(
+            visible_len(&Token::ImeChar("「」".to_string()), true),
+            Some(2)
+        );
+        assert_eq!(visible_len(&seq("左"), true), None);
+        assert_eq!(visible_len(&Token::None, true), None);
+    }
+
+    #[test]
+    fn test_take_undo_events_emits_backspaces_once() {
+        let mut tracker = UndoTracker::default();
+        tracker.record_token(&seq("kyo"), true);
+        let events = tracker.take_undo_events();
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Scancode(BACKSPACE_SC, false, false),
+                InputEvent::Scancode(BACKSPACE_SC, false, true),
+            ]
+        );
+        assert!(tracker.take_undo_events().is_empty());
+    }
+
+    #[test]
+    fn test_tokens_in_one_group_are_undone_together() {
+        let mut tracker = UndoTracker::default();
+        tracker.begin_output();
+        tracker.record_token(&seq("ka"), true);
+        tracker.record_token(&seq("nn"), true);
+        assert_eq!(tracker.last_len(), Some(2));
+
+        tracker.begin_output();
+        tracker.record_token(&seq("to"), true);
+        assert_eq!(tracker.last_len(), Some(1));
+    }
+}
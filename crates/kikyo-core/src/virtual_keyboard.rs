@@ -0,0 +1,356 @@
+//! Geometry of a JIS 109-key keyboard with each key's names and current
+//! roles, for the settings window to draw the keyboard from.
+
+use crate::chord_engine::{Profile, SANDS_KEY};
+use crate::jis_map::{display_name, key_from_name, key_name, key_to_rc};
+use crate::types::ScKey;
+use serde::Serialize;
+
+/// Keys laid out one after another from `x` on row `y`, in key units.
+struct Run {
+    x: f32,
+    y: f32,
+    /// (scancode, extended, width, height)
+    keys: &'static [(u16, bool, f32, f32)],
+}
+
+const fn run(x: f32, y: f32, keys: &'static [(u16, bool, f32, f32)]) -> Run {
+    Run { x, y, keys }
+}
+
+const U: f32 = 1.0;
+
+const RUNS: &[Run] = &[
+    // Function row
+    run(0.0, 0.0, &[(0x01, false, U, U)]),
+    run(
+        2.0,
+        0.0,
+        &[
+            (0x3B, false, U, U),
+            (0x3C, false, U, U),
+            (0x3D, false, U, U),
+            (0x3E, false, U, U),
+        ],
+    ),
+    run(
+        6.5,
+        0.0,
+        &[
+            (0x3F, false, U, U),
+            (0x40, false, U, U),
+            (0x41, false, U, U),
+            (0x42, false, U, U),
+        ],
+    ),
+    run(
+        11.0,
+        0.0,
+        &[
+            (0x43, false, U, U),
+            (0x44, false, U, U),
+            (0x57, false, U, U),
+            (0x58, false, U, U),
+        ],
+    ),
+    // Number row: 半角/全角, 1 to ¥, Backspace
+    run(
+        0.0,
+        1.0,
+        &[
+            (0x29, false, U, U),
+            (0x02, false, U, U),
+            (0x03, false, U, U),
+            (0x04, false, U, U),
+            (0x05, false, U, U),
+            (0x06, false, U, U),
+            (0x07, false, U, U),
+            (0x08, false, U, U),
+            (0x09, false, U, U),
+            (0x0A, false, U, U),
+            (0x0B, false, U, U),
+            (0x0C, false, U, U),
+            (0x0D, false, U, U),
+            (0x7D, false, U, U),
+            (0x0E, false, U, U),
+        ],
+    ),
+    // Tab, Q to [
+    run(
+        0.0,
+        2.0,
+        &[
+            (0x0F, false, 1.5, U),
+            (0x10, false, U, U),
+            (0x11, false, U, U),
+            (0x12, false, U, U),
+            (0x13, false, U, U),
+            (0x14, false, U, U),
+            (0x15, false, U, U),
+            (0x16, false, U, U),
+            (0x17, false, U, U),
+            (0x18, false, U, U),
+            (0x19, false, U, U),
+            (0x1A, false, U, U),
+            (0x1B, false, U, U),
+        ],
+    ),
+    // Enter spans the Tab and CapsLock rows.
+    run(13.75, 2.0, &[(0x1C, false, 1.25, 2.0)]),
+    // CapsLock, A to ]
+    run(
+        0.0,
+        3.0,
+        &[
+            (0x3A, false, 1.75, U),
+            (0x1E, false, U, U),
+            (0x1F, false, U, U),
+            (0x20, false, U, U),
+            (0x21, false, U, U),
+            (0x22, false, U, U),
+            (0x23, false, U, U),
+            (0x24, false, U, U),
+            (0x25, false, U, U),
+            (0x26, false, U, U),
+            (0x27, false, U, U),
+            (0x28, false, U, U),
+            (0x2B, false, U, U),
+        ],
+    ),
+    // Shift, Z to ろ, Shift
+    run(
+        0.0,
+        4.0,
+        &[
+            (0x2A, false, 2.25, U),
+            (0x2C, false, U, U),
+            (0x2D, false, U, U),
+            (0x2E, false, U, U),
+            (0x2F, false, U, U),
+            (0x30, false, U, U),
+            (0x31, false, U, U),
+            (0x32, false, U, U),
+            (0x33, false, U, U),
+            (0x34, false, U, U),
+            (0x35, false, U, U),
+            (0x73, false, U, U),
+            (0x36, false, 1.75, U),
+        ],
+    ),
+    // Ctrl, Win, Alt, 無変換, Space, 変換, カタカナ/ひらがな, Alt, Win,
+    // Menu, Ctrl
+    run(
+        0.0,
+        5.0,
+        &[
+            (0x1D, false, 1.25, U),
+            (0x5B, true, 1.25, U),
+            (0x38, false, 1.25, U),
+            (0x7B, false, 1.25, U),
+            (0x39, false, 2.5, U),
+            (0x79, false, 1.25, U),
+            (0x70, false, 1.25, U),
+            (0x38, true, 1.25, U),
+            (0x5C, true, 1.25, U),
+            (0x5D, true, 1.25, U),
+            (0x1D, true, 1.25, U),
+        ],
+    ),
+    // Navigation cluster and arrows
+    run(
+        15.25,
+        1.0,
+        &[(0x52, true, U, U), (0x47, true, U, U), (0x49, true, U, U)],
+    ),
+    run(
+        15.25,
+        2.0,
+        &[(0x53, true, U, U), (0x4F, true, U, U), (0x51, true, U, U)],
+    ),
+    run(16.25, 4.0, &[(0x48, true, U, U)]),
+    run(
+        15.25,
+        5.0,
+        &[(0x4B, true, U, U), (0x50, true, U, U), (0x4D, true, U, U)],
+    ),
+    // Numeric keypad
+    run(
+        18.5,
+        1.0,
+        &[
+            (0x45, true, U, U),
+            (0x35, true, U, U),
+            (0x37, false, U, U),
+            (0x4A, false, U, U),
+        ],
+    ),
+    run(
+        18.5,
+        2.0,
+        &[
+            (0x47, false, U, U),
+            (0x48, false, U, U),
+            (0x49, false, U, U),
+            (0x4E, false, U, 2.0),
+        ],
+    ),
+    run(
+        18.5,
+        3.0,
+        &[
+            (0x4B, false, U, U),
+            (0x4C, false, U, U),
+            (0x4D, false, U, U),
+        ],
+    ),
+    run(
+        18.5,
+        4.0,
+        &[
+            (0x4F, false, U, U),
+            (0x50, false, U, U),
+            (0x51, false, U, U),
+            (0x1C, true, U, 2.0),
+        ],
+    ),
+    run(18.5, 5.0, &[(0x52, false, 2.0, U), (0x53, false, U, U)]),
+];
+
+/// What a key does in the chord engine with the current profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum KeyRole {
+    LeftThumb,
+    RightThumb,
+    ExtendedThumb1,
+    ExtendedThumb2,
+    /// Thumb key of `Profile::extra_thumbs`, by index.
+    ExtraThumb(usize),
+    /// Number-row key acting as a shift.
+    NumberShift,
+    PinkyShift,
+    /// Opens a `<key>` sub-plane of the layout.
+    CharShift,
+    SandS,
+    /// Acts as a modifier when held (`Profile::dual_role_keys`).
+    DualRole,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VirtualKey {
+    pub sc: u16,
+    pub ext: bool,
+    /// Name in .yab cells and `<key>` tags.
+    pub layout_name: Option<&'static str>,
+    pub display: String,
+    /// Position in layout sections as (row, col), for keys they can define.
+    pub rc: Option<(u8, u8)>,
+    /// Top-left corner and size, in key units.
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub roles: Vec<KeyRole>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VirtualKeyboard {
+    pub keys: Vec<VirtualKey>,
+    /// Size of the whole keyboard, in key units.
+    pub width: f32,
+    pub height: f32,
+}
+
+/// The keyboard with the roles `profile` gives its keys.
+pub fn keyboard_model(profile: &Profile) -> VirtualKeyboard {
+    let mut keys = Vec::new();
+    for run in RUNS {
+        let mut x = run.x;
+        for &(sc, ext, width, height) in run.keys {
+            let key = ScKey::new(sc, ext);
+            keys.push(VirtualKey {
+                sc,
+                ext,
+                layout_name: key_name(key),
+                display: display_name(key),
+                rc: key_to_rc(key).map(|rc| (rc.row, rc.col)),
+                x,
+                y: run.y,
+                width,
+                height,
+                roles: key_roles(profile, key),
+            });
+            x += width;
+        }
+    }
+    let width = keys.iter().map(|k| k.x + k.width).fold(0.0, f32::max);
+    let height = keys.iter().map(|k| k.y + k.height).fold(0.0, f32::max);
+    VirtualKeyboard {
+        keys,
+        width,
+        height,
+    }
+}
+
+fn key_roles(profile: &Profile, key: ScKey) -> Vec<KeyRole> {
+    let mut roles = Vec::new();
+    if let Some(ref tk) = profile.thumb_keys {
+        let sets = [
+            (&tk.left, KeyRole::LeftThumb),
+            (&tk.right, KeyRole::RightThumb),
+            (&tk.ext1, KeyRole::ExtendedThumb1),
+            (&tk.ext2, KeyRole::ExtendedThumb2),
+            (&tk.number, KeyRole::NumberShift),
+            (&tk.shift, KeyRole::PinkyShift),
+        ];
+        roles.extend(
+            sets.into_iter()
+                .filter(|(keys, _)| keys.contains(&key))
+                .map(|(_, role)| role),
+        );
+        roles.extend(tk.extra_index(&key).map(KeyRole::ExtraThumb));
+    }
+    if profile.trigger_keys.contains_key(&key) {
+        roles.push(KeyRole::CharShift);
+    }
+    if profile.sands.enabled && key == SANDS_KEY {
+        roles.push(KeyRole::SandS);
+    }
+    if profile
+        .dual_role_keys
+        .iter()
+        .any(|cfg| key_from_name(&cfg.key) == Some(key))
+    {
+        roles.push(KeyRole::DualRole);
+    }
+    roles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyboard_model_places_keys_and_roles() {
+        let mut profile = Profile::default();
+        profile.update_thumb_keys();
+        let model = keyboard_model(&profile);
+
+        let key = |sc, ext| {
+            model
+                .keys
+                .iter()
+                .find(|k| k.sc == sc && k.ext == ext)
+                .unwrap()
+        };
+        let a = key(0x1E, false);
+        assert_eq!((a.x, a.y, a.rc), (1.75, 3.0, Some((2, 0))));
+        assert_eq!(a.layout_name, Some("a"));
+        assert!(a.roles.is_empty());
+        assert_eq!(key(0x7B, false).roles, vec![KeyRole::LeftThumb]);
+        assert_eq!(key(0x79, false).roles, vec![KeyRole::RightThumb]);
+        assert_eq!((model.width, model.height), (22.5, 6.0));
+
+        let mut seen = std::collections::HashSet::new();
+        assert!(model.keys.iter().all(|k| seen.insert((k.sc, k.ext))));
+    }
+}
@@ -0,0 +1,216 @@
+//! Dakuten and handakuten keys for kana layouts that type them separately
+//! from the base kana. `{濁点}` and `{半濁点}` voice the kana typed just
+//! before by erasing it with Backspace and typing the voiced one instead.
+
+use crate::romaji_map::{kana_to_romaji, romaji_to_kana};
+use crate::types::{KeySpec, KeyStroke, Modifiers};
+
+const BACKSPACE_SC: u16 = 0x0E;
+
+const DAKUTEN_PAIRS: &[(char, char)] = &[
+    ('か', 'が'),
+    ('き', 'ぎ'),
+    ('く', 'ぐ'),
+    ('け', 'げ'),
+    ('こ', 'ご'),
+    ('さ', 'ざ'),
+    ('し', 'じ'),
+    ('す', 'ず'),
+    ('せ', 'ぜ'),
+    ('そ', 'ぞ'),
+    ('た', 'だ'),
+    ('ち', 'ぢ'),
+    ('つ', 'づ'),
+    ('て', 'で'),
+    ('と', 'ど'),
+    ('は', 'ば'),
+    ('ひ', 'び'),
+    ('ふ', 'ぶ'),
+    ('へ', 'べ'),
+    ('ほ', 'ぼ'),
+];
+
+const HANDAKUTEN_PAIRS: &[(char, char)] = &[
+    ('は', 'ぱ'),
+    ('ひ', 'ぴ'),
+    ('ふ', 'ぷ'),
+    ('へ', 'ぺ'),
+    ('ほ', 'ぽ'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Voicing {
+    Dakuten,
+    Handakuten,
+}
+
+impl Voicing {
+    /// Spacing mark typed when there is no kana to voice.
+    pub fn mark(self) -> char {
+        match self {
+            Voicing::Dakuten => '゛',
+            Voicing::Handakuten => '゜',
+        }
+    }
+
+    fn pairs(self) -> &'static [(char, char)] {
+        match self {
+            Voicing::Dakuten => DAKUTEN_PAIRS,
+            Voicing::Handakuten => HANDAKUTEN_PAIRS,
+        }
+    }
+}
+
+/// Last kana of the output and how it was typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastKana {
+    pub kana: char,
+    /// Typed as confirmed text rather than romaji keys.
+    pub direct: bool,
+}
+
+/// Voices `kana`. Pressing the same mark again takes it off (が -> か),
+/// and the other mark swaps it (ば -> ぱ).
+pub fn voice(kana: char, voicing: Voicing) -> Option<char> {
+    let base = DAKUTEN_PAIRS
+        .iter()
+        .chain(HANDAKUTEN_PAIRS)
+        .find(|(_, voiced)| *voiced == kana)
+        .map_or(kana, |(base, _)| *base);
+    let voiced = voicing
+        .pairs()
+        .iter()
+        .find(|(plain, _)| *plain == base)
+        .map(|(_, voiced)| *voiced)?;
+    Some(if voiced == kana { base } else { voiced })
+}
+
+/// Kana typed last by `strokes`. Romaji keys count only in Japanese input.
+pub fn trailing_kana(strokes: &[KeyStroke], is_japanese: bool) -> Option<LastKana> {
+    let last = strokes.last()?;
+    if last.mods.ctrl || last.mods.alt || last.mods.win {
+        return None;
+    }
+    match &last.key {
+        KeySpec::DirectString(s) | KeySpec::Paste(s) => {
+            s.chars().last().map(|kana| LastKana { kana, direct: true })
+        }
+        KeySpec::Char(_) if is_japanese => {
+            // Up to the last three romaji keys, last first.
+            let mut romaji = ['\0'; 3];
+            let mut count = 0;
+            let chars = strokes.iter().rev().map_while(|stroke| match stroke.key {
+                KeySpec::Char(c) => Some(c.to_ascii_lowercase()),
+                _ => None,
+            });
+            for c in chars.take(3) {
+                romaji[count] = c;
+                count += 1;
+            }
+            (1..=count).rev().find_map(|len| {
+                let mut buf = [0u8; 12];
+                let mut end = 0;
+                for c in romaji[..len].iter().rev() {
+                    end += c.encode_utf8(&mut buf[end..]).len();
+                }
+                let suffix = std::str::from_utf8(&buf[..end]).ok()?;
+                romaji_to_kana(suffix).map(|kana| LastKana {
+                    kana,
+                    direct: false,
+                })
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Replaces the voicing keys in `strokes`. `last` is the kana typed before
+/// them by earlier outputs.
+pub fn compose(
+    strokes: Vec<KeyStroke>,
+    mut last: Option<LastKana>,
+    is_japanese: bool,
+) -> Vec<KeyStroke> {
+    let mut out = Vec::with_capacity(strokes.len());
+    for stroke in strokes {
+        let KeySpec::Voicing(voicing) = stroke.key else {
+            out.push(stroke);
+            last = trailing_kana(&out, is_japanese);
+            continue;
+        };
+        let target = last.and_then(|prev| voice(prev.kana, voicing).map(|kana| (prev, kana)));
+        let Some((prev, kana)) = target else {
+            out.push(KeyStroke {
+                key: KeySpec::DirectString(voicing.mark().to_string()),
+                mods: Modifiers::none(),
+            });
+            last = None;
+            continue;
+        };
+        out.push(KeyStroke {
+            key: KeySpec::Scancode(BACKSPACE_SC, false),
+            mods: Modifiers::none(),
+        });
+        match kana_to_romaji(kana) {
+            Some(romaji) if !prev.direct => out.extend(romaji.chars().map(|c| KeyStroke {
+                key: KeySpec::Char(c),
+                mods: Modifiers::none(),
+            })),
+            _ => out.push(KeyStroke {
+                key: KeySpec::DirectString(kana.to_string()),
+                mods: Modifiers::none(),
+            }),
+        }
+        last = Some(LastKana {
+            kana,
+            direct: prev.direct,
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_key_sequence_expanded;
+
+    #[test]
+    fn test_voice_toggles_and_swaps_marks() {
+        assert_eq!(voice('か', Voicing::Dakuten), Some('が'));
+        assert_eq!(voice('が', Voicing::Dakuten), Some('か'));
+        assert_eq!(voice('は', Voicing::Handakuten), Some('ぱ'));
+        assert_eq!(voice('ば', Voicing::Handakuten), Some('ぱ'));
+        assert_eq!(voice('ぱ', Voicing::Dakuten), Some('ば'));
+        assert_eq!(voice('か', Voicing::Handakuten), None);
+        assert_eq!(voice('あ', Voicing::Dakuten), None);
+    }
+
+    #[test]
+    fn test_compose_erases_and_retypes_the_previous_kana() {
+        let last = trailing_kana(&parse_key_sequence_expanded("し"), true);
+        assert_eq!(
+            last,
+            Some(LastKana {
+                kana: 'し',
+                direct: false
+            })
+        );
+
+        let strokes = compose(parse_key_sequence_expanded("{濁点}"), last, true);
+        let mut expected = vec![KeyStroke {
+            key: KeySpec::Scancode(BACKSPACE_SC, false),
+            mods: Modifiers::none(),
+        }];
+        expected.extend(parse_key_sequence_expanded("じ"));
+        assert_eq!(strokes, expected);
+
+        // Nothing to voice: the mark itself is typed.
+        assert_eq!(
+            compose(parse_key_sequence_expanded("{半濁点}"), None, true),
+            vec![KeyStroke {
+                key: KeySpec::DirectString("゜".to_string()),
+                mods: Modifiers::none(),
+            }]
+        );
+    }
+}
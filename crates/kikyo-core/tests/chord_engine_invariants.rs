@@ -0,0 +1,51 @@
+use kikyo_core::engine::{invariant_check, Engine, ScriptedKey};
+use kikyo_core::parser;
+use kikyo_core::types::ScKey;
+use proptest::prelude::*;
+use std::path::PathBuf;
+
+/// Home-row and neighbouring character keys, plus Muhenkan/Henkan/Space.
+const KEYS: &[u16] = &[
+    0x1E, 0x1F, 0x20, 0x21, 0x24, 0x25, 0x26, 0x12, 0x17, 0x30, 0x31, 0x7B, 0x79, 0x39,
+];
+
+fn engine_for(layout_file: &str) -> Engine {
+    let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("..");
+    p.push("..");
+    p.push("layout");
+    p.push(layout_file);
+    let layout = parser::load_yab(&p).expect("load layout");
+
+    let mut engine = Engine::default();
+    engine.set_ignore_ime(true);
+    engine.load_layout(layout);
+    engine
+}
+
+/// Random presses and releases of `KEYS` with gaps of up to 150ms.
+fn key_sequence() -> impl Strategy<Value = Vec<ScriptedKey>> {
+    prop::collection::vec((0..KEYS.len(), any::<bool>(), 0u64..150), 1..40).prop_map(|raw| {
+        raw.into_iter()
+            .map(|(i, up, after_ms)| ScriptedKey {
+                key: ScKey::new(KEYS[i], false),
+                up,
+                after_ms,
+            })
+            .collect()
+    })
+}
+
+proptest! {
+    #[test]
+    fn nicola_sequences_keep_invariants(steps in key_sequence()) {
+        let mut engine = engine_for("NICOLA.yab");
+        prop_assert_eq!(invariant_check(&mut engine, &steps), Ok(()));
+    }
+
+    #[test]
+    fn sin_geta_sequences_keep_invariants(steps in key_sequence()) {
+        let mut engine = engine_for("sin-geta.yab");
+        prop_assert_eq!(invariant_check(&mut engine, &steps), Ok(()));
+    }
+}
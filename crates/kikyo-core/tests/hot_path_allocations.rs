@@ -0,0 +1,88 @@
+//! The common tap path must not touch the heap: a key going down and
+//! coming up again allocates nothing but the events handed back to the
+//! hook.
+
+use kikyo_core::engine::Engine;
+use kikyo_core::parser::parse_yab_content;
+use kikyo_core::types::{InputEvent, KeyAction};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts the allocations of the current thread; tests run in parallel.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+const LAYOUT: &str = "
+[ローマ字シフト無し]
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+q,w,e,xx,xx,xx,xx,xx,xx,xx,xx,xx
+a,s,d,f,xx,xx,xx,k,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+
+<k>
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx
+xx,xx,x,y,xx,xx,xx,xx,xx,xx,xx,xx
+";
+
+fn tap(engine: &mut Engine, sc: u16) -> ((KeyAction, usize), (KeyAction, usize)) {
+    let down = allocations(|| engine.process_key(sc, false, false, false));
+    let up = allocations(|| engine.process_key(sc, false, true, false));
+    (down, up)
+}
+
+#[test]
+fn test_tap_path_does_not_allocate() {
+    let mut engine = Engine::default();
+    engine.set_ignore_ime(true);
+    engine.load_layout(parse_yab_content(LAYOUT).unwrap());
+    // The first events size the engine's reusable buffers.
+    for _ in 0..3 {
+        tap(&mut engine, 0x1E);
+        tap(&mut engine, 0x2C);
+    }
+
+    let ((down, down_allocs), (up, up_allocs)) = tap(&mut engine, 0x1E); // A
+    assert_eq!(down, KeyAction::Block);
+    assert_eq!(down_allocs, 0);
+    assert_eq!(
+        up,
+        KeyAction::Inject(vec![
+            InputEvent::Scancode(0x1E, false, false),
+            InputEvent::Scancode(0x1E, false, true),
+        ])
+    );
+    assert_eq!(up_allocs, 1, "only the injected events may be allocated");
+
+    let ((down, down_allocs), (up, up_allocs)) = tap(&mut engine, 0x2C); // Z, not in the layout
+    assert_eq!((down, up), (KeyAction::Pass, KeyAction::Pass));
+    assert_eq!((down_allocs, up_allocs), (0, 0));
+}
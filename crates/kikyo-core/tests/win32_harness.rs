@@ -0,0 +1,207 @@
+//! End-to-end tests through the real keyboard hook. Keys are sent with
+//! SendInput as the keyboard would send them, go through the hook, the
+//! engine and injection, and end up in an edit control of this process,
+//! whose text is then checked.
+//!
+//! They need an interactive desktop whose foreground window the test may
+//! take, so they only build with the `win32-harness` feature:
+//!
+//! ```text
+//! cargo test -p kikyo-core --features win32-harness --test win32_harness
+//! ```
+
+use kikyo_core::engine::ENGINE;
+use kikyo_core::keyboard_hook;
+use kikyo_core::parser::parse_yab_content;
+use std::mem::size_of;
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+use windows::core::w;
+use windows::Win32::Foundation::{HINSTANCE, HWND};
+use windows::Win32::Globalization::HIMC;
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Input::Ime::ImmAssociateContext;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    SendInput, SetFocus, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP,
+    KEYEVENTF_SCANCODE, VIRTUAL_KEY,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DestroyWindow, DispatchMessageW, GetForegroundWindow, GetWindowTextW,
+    PeekMessageW, SetForegroundWindow, TranslateMessage, ES_AUTOHSCROLL, MSG, PM_REMOVE,
+    WINDOW_STYLE, WS_EX_TOOLWINDOW, WS_POPUP, WS_VISIBLE,
+};
+
+/// Longest wait for the typed text to arrive.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Pause between sent keys, so presses do not count as chords by accident.
+const KEY_GAP: Duration = Duration::from_millis(30);
+
+static HOOK: Once = Once::new();
+/// The hook, the engine and the foreground window are shared.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Starts the hook on a thread of its own, as the app does.
+fn start_hook() {
+    HOOK.call_once(|| {
+        thread::spawn(|| {
+            keyboard_hook::install_hook().expect("install hook");
+            keyboard_hook::run_event_loop();
+        });
+        thread::sleep(Duration::from_millis(200));
+    });
+}
+
+fn load_layout(config: &str) {
+    let mut engine = ENGINE.lock();
+    engine.set_ignore_ime(true);
+    engine.load_layout(parse_yab_content(config).expect("parse layout"));
+    drop(engine);
+    keyboard_hook::refresh_runtime_flags_from_engine();
+}
+
+/// Sends a key the way the keyboard does: by scancode and without the
+/// marker of Kikyo's own output.
+fn send_key(sc: u16, up: bool) {
+    let mut flags = KEYEVENTF_SCANCODE;
+    if up {
+        flags |= KEYEVENTF_KEYUP;
+    }
+    let input = INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: VIRTUAL_KEY(0),
+                wScan: sc,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
+    unsafe { SendInput(&[input], size_of::<INPUT>() as i32) };
+    thread::sleep(KEY_GAP);
+}
+
+fn tap(sc: u16) {
+    send_key(sc, false);
+    send_key(sc, true);
+}
+
+/// Edit control off-screen with the keyboard focus. It has no input
+/// context, so letters arrive as typed whatever IME the machine has.
+struct EditControl(HWND);
+
+impl EditControl {
+    fn open() -> Self {
+        let edit = unsafe {
+            let instance = GetModuleHandleW(None).expect("module handle");
+            let hwnd = CreateWindowExW(
+                WS_EX_TOOLWINDOW,
+                w!("EDIT"),
+                w!(""),
+                WS_POPUP | WS_VISIBLE | WINDOW_STYLE(ES_AUTOHSCROLL as u32),
+                -32000,
+                -32000,
+                200,
+                30,
+                None,
+                None,
+                HINSTANCE::from(instance),
+                None,
+            );
+            assert_ne!(hwnd.0, 0, "CreateWindowExW failed");
+            ImmAssociateContext(hwnd, HIMC::default());
+            let _ = SetForegroundWindow(hwnd);
+            SetFocus(hwnd);
+            Self(hwnd)
+        };
+        edit.pump(Duration::from_millis(100));
+        assert_eq!(
+            unsafe { GetForegroundWindow() },
+            edit.0,
+            "the edit control did not get the foreground"
+        );
+        edit
+    }
+
+    /// Runs this thread's messages for `duration`.
+    fn pump(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        let mut msg = MSG::default();
+        while Instant::now() < deadline {
+            unsafe {
+                while PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE).as_bool() {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn text(&self) -> String {
+        let mut buf = [0u16; 256];
+        let len = unsafe { GetWindowTextW(self.0, &mut buf) };
+        String::from_utf16_lossy(&buf[..len.max(0) as usize])
+    }
+
+    /// The text once it is `expected`, or as it is after [`SETTLE_TIMEOUT`].
+    fn wait_for_text(&self, expected: &str) -> String {
+        let deadline = Instant::now() + SETTLE_TIMEOUT;
+        loop {
+            self.pump(Duration::from_millis(20));
+            let text = self.text();
+            if text == expected || Instant::now() >= deadline {
+                return text;
+            }
+        }
+    }
+}
+
+impl Drop for EditControl {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = DestroyWindow(self.0);
+        }
+    }
+}
+
+const LAYOUT: &str = "
+[ローマ字シフト無し]
+1,2,3,4,5,6,7,8,9,0,-,^,\\
+q,w,e,r,t,y,u,i,o,p,@,[
+xx,xx,か,な,xx,xx,xx,xx,xx,xx,xx,xx
+z,x,c,v,b,n,m,,,.,/,\\
+
+<k>
+無,無,無,無,無,無,無,無,無,無,無,無,無
+無,無,無,無,無,無,無,無,無,無,無,無
+xx,xx,が,無,無,無,無,無,無,無,無,無
+";
+
+#[test]
+fn test_keys_are_typed_through_the_layout() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    load_layout(LAYOUT);
+    start_hook();
+    let edit = EditControl::open();
+
+    tap(0x20); // D
+    tap(0x21); // F
+    assert_eq!(edit.wait_for_text("kana"), "kana");
+}
+
+#[test]
+fn test_chord_types_the_sub_plane() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    load_layout(LAYOUT);
+    start_hook();
+    let edit = EditControl::open();
+
+    send_key(0x25, false); // K
+    send_key(0x20, false); // D
+    send_key(0x20, true);
+    send_key(0x25, true);
+    assert_eq!(edit.wait_for_text("ga"), "ga");
+}
@@ -1,3 +1,15 @@
 fn main() {
-    tauri_build::build()
+    // KIKYO_UIACCESS=1 embeds a uiAccess="true" manifest so injected keys
+    // reach elevated windows. Such a build only starts when it is signed and
+    // installed under Program Files.
+    println!("cargo:rerun-if-env-changed=KIKYO_UIACCESS");
+    println!("cargo:rerun-if-changed=uiaccess.manifest");
+    if std::env::var("KIKYO_UIACCESS").as_deref() == Ok("1") {
+        let windows =
+            tauri_build::WindowsAttributes::new().app_manifest(include_str!("uiaccess.manifest"));
+        tauri_build::try_build(tauri_build::Attributes::new().windows_attributes(windows))
+            .expect("failed to run tauri build");
+    } else {
+        tauri_build::build()
+    }
 }
@@ -0,0 +1,160 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+static CURRENT: AtomicU8 = AtomicU8::new(Language::Ja as u8);
+
+/// Language of the tray menu, window title and command error messages.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    Ja = 0,
+    En = 1,
+}
+
+pub fn set_language(language: Language) {
+    CURRENT.store(language as u8, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    match CURRENT.load(Ordering::Relaxed) {
+        1 => Language::En,
+        _ => Language::Ja,
+    }
+}
+
+/// User-facing strings, looked up with [`tr`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Text {
+    AppTitle,
+    NoLayout,
+    PreviousLayout,
+    NoPreviousLayout,
+    Profiles,
+    ReloadLayout,
+    Settings,
+    Suspend,
+    Resume,
+    Quit,
+    PathEmpty,
+    DuplicateLayoutPath,
+    LayoutEntryNotFound,
+    InvalidLayoutIdCount,
+    UnknownLayoutId,
+    MissingLayoutIds,
+    PresetNameEmpty,
+    ProfilePresetNotFound,
+    TrainingTextEmpty,
+    UnsupportedExportFormat,
+    AlreadyElevated,
+    LayoutSwitched,
+    LayoutReloadFailed,
+    LayoutFellBack,
+    EngineSuspended,
+    EngineResumed,
+    CrashReportNotFound,
+    LogDirUnavailable,
+    SettingsSynced,
+    SyncBundleNewer,
+    SafeModeStarted,
+    AutoSuspendedHours,
+    AutoSuspendedGame,
+    AutoSuspendedIdle,
+    PasswordFieldBypass,
+}
+
+/// (Japanese, English) form of `text`.
+fn entry(text: Text) -> (&'static str, &'static str) {
+    match text {
+        Text::AppTitle => ("桔梗", "Kikyo"),
+        Text::NoLayout => ("配列定義なし", "No layout"),
+        Text::PreviousLayout => ("前の配列に切り替え", "Switch to previous layout"),
+        Text::NoPreviousLayout => (
+            "前に使った配列定義がありません",
+            "No previously used layout",
+        ),
+        Text::Profiles => ("プロファイル", "Profiles"),
+        Text::ReloadLayout => ("配列定義再読み込み", "Reload layout"),
+        Text::Settings => ("設定", "Settings"),
+        Text::Suspend => ("一時停止", "Suspend"),
+        Text::Resume => ("再開", "Resume"),
+        Text::Quit => ("終了", "Quit"),
+        Text::PathEmpty => ("パスが指定されていません", "Path is empty"),
+        Text::DuplicateLayoutPath => (
+            "すでに登録されている定義ファイルです",
+            "This layout file is already registered",
+        ),
+        Text::LayoutEntryNotFound => ("配列定義が見つかりません", "Layout entry not found"),
+        Text::InvalidLayoutIdCount => {
+            ("配列定義の数が一致しません", "Invalid number of layout ids")
+        }
+        Text::UnknownLayoutId => ("不明な配列定義です", "Unknown layout id"),
+        Text::MissingLayoutIds => (
+            "並べ替えに含まれていない配列定義があります",
+            "Some layout ids are missing in order payload",
+        ),
+        Text::PresetNameEmpty => ("プロファイル名が空です", "Preset name is empty"),
+        Text::ProfilePresetNotFound => ("プロファイルが見つかりません", "Profile preset not found"),
+        Text::TrainingTextEmpty => ("練習する文字列が空です", "Training text is empty"),
+        Text::UnsupportedExportFormat => ("未対応の出力形式です", "Unsupported export format"),
+        Text::AlreadyElevated => (
+            "すでに管理者として実行中です",
+            "Already running as administrator",
+        ),
+        Text::LayoutSwitched => ("配列を切り替えました", "Switched layout"),
+        Text::LayoutReloadFailed => ("配列定義の読み込みに失敗しました", "Failed to load layout"),
+        Text::LayoutFellBack => (
+            "配列定義を読み込めないため、前に使った配列に切り替えました",
+            "Could not load the layout, switched to a previously used one",
+        ),
+        Text::EngineSuspended => ("一時停止しました", "Suspended"),
+        Text::EngineResumed => ("再開しました", "Resumed"),
+        Text::SafeModeStarted => (
+            "前回の起動で問題が発生したため、配列を無効にして起動しました",
+            "Started with the layout disabled because the previous run failed",
+        ),
+        Text::AutoSuspendedHours => (
+            "設定した時間帯のため自動で停止中",
+            "Suspended for the scheduled hours",
+        ),
+        Text::AutoSuspendedGame => ("ゲーム中のため自動で停止中", "Suspended while gaming"),
+        Text::AutoSuspendedIdle => ("キー入力がないため自動で停止中", "Suspended while idle"),
+        Text::PasswordFieldBypass => (
+            "パスワード入力欄のため配列を使わずに入力中",
+            "Typing directly into a password field",
+        ),
+        Text::LogDirUnavailable => ("ログの保存先がありません", "Log folder is unavailable"),
+        Text::SettingsSynced => (
+            "同期フォルダの設定を読み込みました",
+            "Loaded the settings from the sync folder",
+        ),
+        Text::SyncBundleNewer => (
+            "同期フォルダの設定が他のPCで更新されています。先に読み込んでください",
+            "The synced settings were changed on another PC; import them first",
+        ),
+        Text::CrashReportNotFound => (
+            "クラッシュレポートが見つかりません",
+            "Crash report not found",
+        ),
+    }
+}
+
+/// Returns `text` in the current language.
+pub fn tr(text: Text) -> &'static str {
+    let (ja, en) = entry(text);
+    match language() {
+        Language::Ja => ja,
+        Language::En => en,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_deserializes_from_lowercase_code() {
+        let parsed: Language = serde_json::from_str("\"en\"").expect("language json");
+        assert_eq!(parsed, Language::En);
+        assert_eq!(Language::default(), Language::Ja);
+    }
+}
@@ -1,23 +1,104 @@
-use image::GenericImageView;
-use kikyo_core::chord_engine::Profile;
-use kikyo_core::engine::ENGINE;
-use kikyo_core::{keyboard_hook, parser};
+mod i18n;
+mod logging;
+mod safe_mode;
+mod tray_icon;
+
+use i18n::{tr, Language, Text};
+use kikyo_core::bundle::{BundledLayout, SettingsBundle, BUNDLE_FORMAT};
+use kikyo_core::chord_engine::{KeySwapCfg, Profile};
+use kikyo_core::crash_report;
+use kikyo_core::elevation::{self, ElevationStatus};
+use kikyo_core::feedback::{self, SoundFeedback};
+use kikyo_core::handle::{EngineEvent, EngineHandle};
+use kikyo_core::hook_health::HookHealth;
+use kikyo_core::ime;
+use kikyo_core::injection_health::InjectionHealth;
+use kikyo_core::inspect::EngineState;
+use kikyo_core::jis_map::{self, KeyNameInfo};
+use kikyo_core::key_swap::KeySwapIssue;
+use kikyo_core::keyboard_hook::Hotkey;
+use kikyo_core::layout_coverage::{CoverageTemplate, LayoutCoverage};
+use kikyo_core::layout_diff::{self, LayoutDiff};
+use kikyo_core::recorder::{self, RecordingStatus};
+use kikyo_core::schedule::{AutoDisableReason, AutoDisableSchedule};
+use kikyo_core::scripting;
+use kikyo_core::secure_input;
+use kikyo_core::stats::{LayoutSuggestions, UsageHeatmap};
+use kikyo_core::throttle::{self, OutputThrottle};
+use kikyo_core::training::TrainingReport;
+use kikyo_core::types::LayoutInfo;
+use kikyo_core::virtual_keyboard::VirtualKeyboard;
+use kikyo_core::{keyboard_hook, notify, parser, system_theme};
+use logging::LogLevel;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::image::Image;
-use tauri::menu::{CheckMenuItem, Menu, MenuItem, PredefinedMenuItem};
+use tauri::menu::{CheckMenuItem, Menu, MenuItem, PredefinedMenuItem, Submenu};
 use tauri::tray::{MouseButton, TrayIconBuilder, TrayIconEvent};
 use tauri::Emitter;
 use tauri::Manager;
 use tauri::WindowEvent;
+use tauri_plugin_opener::OpenerExt;
+use tray_icon::TrayIconState;
 
 static ENTRY_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+static TRAY_SHOW_LAYOUT_INITIAL: AtomicBool = AtomicBool::new(false);
+static NOTIFICATIONS_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Last icon drawn in the tray; redraws are skipped while it is unchanged.
+static TRAY_ICON_STATE: Mutex<Option<TrayIconState>> = Mutex::new(None);
+static AUTO_DISABLE: Mutex<AutoDisableState> = Mutex::new(AutoDisableState {
+    schedule: AutoDisableSchedule {
+        hours: None,
+        idle_minutes: 0,
+        fullscreen_games: false,
+        game_apps: Vec::new(),
+    },
+    active: None,
+    overridden: false,
+});
+/// Wakes [`spawn_auto_disable_scheduler`] to check the rules now.
+static AUTO_DISABLE_WAKE: OnceLock<std::sync::mpsc::Sender<()>> = OnceLock::new();
+/// Bundle in the sync folder, watched by [`spawn_settings_sync_watcher`].
+static SYNC_BUNDLE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+/// How often the sync bundle is checked for changes.
+const SYNC_POLL_INTERVAL_MS: u64 = 5000;
+/// Folder under the config directory that imported layout files go to.
+const SYNCED_LAYOUT_DIR: &str = "synced_layouts";
+/// Machine-specific settings that a bundle neither carries nor replaces.
+const LOCAL_SETTINGS_KEYS: &[&str] = &[
+    "last_layout_path",
+    "enabled",
+    "run_elevated",
+    "sync_bundle_path",
+    "sync_last_unix_ms",
+    "layout_mru",
+    "output_scripts_enabled",
+    "hook_fast_path",
+];
+/// How often the tray icon follows the IME mode and taskbar theme.
+const TRAY_ICON_POLL_INTERVAL_MS: u64 = 500;
 const TRAY_LAYOUT_ITEM_ID_PREFIX: &str = "layout_entry::";
-const DUPLICATE_LAYOUT_PATH_MESSAGE: &str = "\u{3059}\u{3067}\u{306b}\u{767b}\u{9332}\u{3055}\u{308c}\u{3066}\u{3044}\u{308b}\u{5b9a}\u{7fa9}\u{30d5}\u{30a1}\u{30a4}\u{30eb}\u{3067}\u{3059}";
+const TRAY_PROFILE_ITEM_ID_PREFIX: &str = "profile_preset::";
+/// Hotkey id and tray menu id of "switch to previous layout".
+const PREVIOUS_LAYOUT_ID: &str = "previous_layout";
+/// Hotkey id of "turn the virtual numeric keypad on or off".
+const TENKEY_ID: &str = "tenkey";
+/// Layout entries remembered in the recently-used list.
+const LAYOUT_MRU_LEN: usize = 8;
+/// Passed to the instance started by an elevated restart.
+const ELEVATED_RESTART_ARG: &str = "--elevated-restart";
+/// Makes a running instance hand over to this one, e.g. after an update.
+const REPLACE_ARG: &str = "--replace";
+/// Passed with the previous instance's process id to the instance started
+/// by a `--replace` handover.
+const TAKEOVER_ARG: &str = "--takeover";
+/// Longest wait of a takeover for the previous instance to exit.
+const TAKEOVER_WAIT_MS: u64 = 10_000;
+const CRASH_REPORT_ISSUE_URL: &str = "https://github.com/forestail/Kikyo/issues/new";
 
 fn tray_layout_item_menu_id(entry_id: &str) -> String {
     format!("{TRAY_LAYOUT_ITEM_ID_PREFIX}{entry_id}")
@@ -27,9 +108,30 @@ fn tray_layout_id_from_menu_id(menu_id: &str) -> Option<&str> {
     menu_id.strip_prefix(TRAY_LAYOUT_ITEM_ID_PREFIX)
 }
 
+fn tray_profile_item_menu_id(preset_id: &str) -> String {
+    format!("{TRAY_PROFILE_ITEM_ID_PREFIX}{preset_id}")
+}
+
+fn tray_profile_id_from_menu_id(menu_id: &str) -> Option<&str> {
+    menu_id.strip_prefix(TRAY_PROFILE_ITEM_ID_PREFIX)
+}
+
+/// Scheduler state; see [`spawn_auto_disable_scheduler`].
+struct AutoDisableState {
+    schedule: AutoDisableSchedule,
+    /// Rule that suspended the engine, cleared once it is resumed.
+    active: Option<AutoDisableReason>,
+    /// The user resumed while a rule applied; rules stay quiet until none does.
+    overridden: bool,
+}
+
 struct AppState {
     current_yab_path: Mutex<Option<String>>,
     layout_name: Mutex<Option<String>>,
+    /// Version declared by the loaded layout file, shown in the tray tooltip.
+    layout_version: Mutex<Option<String>>,
+    /// Startup layout fallback the window has not shown yet.
+    layout_fallback: Mutex<Option<LayoutFallback>>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
@@ -44,14 +146,58 @@ struct LayoutEntry {
     path: String,
     #[serde(default)]
     order: usize,
+    /// Free-form labels for filtering long layout lists.
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    favorite: bool,
+}
+
+/// Named timing/behavior settings the user can switch between.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct ProfilePreset {
+    id: String,
+    name: String,
+    profile: Profile,
+}
+
+#[derive(serde::Serialize)]
+struct ProfilePresetSummary {
+    id: String,
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+struct ProfilePresetsResponse {
+    presets: Vec<ProfilePresetSummary>,
+    active_preset_id: Option<String>,
 }
 
 #[derive(serde::Serialize)]
 struct LayoutEntriesResponse {
-    entries: Vec<LayoutEntry>,
+    entries: Vec<LayoutEntryView>,
     active_layout_id: Option<String>,
 }
 
+/// Layout entry with the metadata read from its file.
+#[derive(serde::Serialize)]
+struct LayoutEntryView {
+    #[serde(flatten)]
+    entry: LayoutEntry,
+    info: LayoutInfo,
+    /// Why the file is missing or fails to parse.
+    load_error: Option<String>,
+}
+
+/// The startup layout failed to load and another entry was applied
+/// instead; see [`take_layout_fallback`].
+#[derive(serde::Serialize, Clone)]
+struct LayoutFallback {
+    broken_id: Option<String>,
+    fallback_id: String,
+    error: String,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct Settings {
     #[serde(default, alias = "last_yab_path")]
@@ -60,10 +206,67 @@ struct Settings {
     layout_entries: Vec<LayoutEntry>,
     #[serde(default)]
     active_layout_id: Option<String>,
+    /// Layout entry ids, most recently activated first.
+    #[serde(default)]
+    layout_mru: Vec<String>,
+    /// Hotkey that switches back to the previously used layout.
+    #[serde(default)]
+    previous_layout_hotkey: Option<Hotkey>,
+    /// Hotkey that turns the virtual numeric keypad on or off.
+    #[serde(default)]
+    tenkey_hotkey: Option<Hotkey>,
     #[serde(default)]
     profile: Option<Profile>,
     #[serde(default = "default_enabled")]
     enabled: bool,
+    #[serde(default)]
+    language: Language,
+    #[serde(default)]
+    log_level: LogLevel,
+    #[serde(default)]
+    profile_presets: Vec<ProfilePreset>,
+    #[serde(default)]
+    active_profile_preset_id: Option<String>,
+    /// Show toasts for layout switches, suspend/resume and load failures.
+    #[serde(default)]
+    notifications_enabled: bool,
+    /// Draw the active layout's initial into the tray icon.
+    #[serde(default)]
+    tray_show_layout_initial: bool,
+    /// List only favorite layouts (and the active one) in the tray menu.
+    #[serde(default)]
+    tray_favorites_only: bool,
+    /// Type straight into password fields instead of through the layout.
+    #[serde(default = "default_enabled")]
+    bypass_password_fields: bool,
+    /// Let the keyboard hook only queue keys, for systems where Windows
+    /// drops it for answering too slowly.
+    #[serde(default)]
+    hook_fast_path: bool,
+    /// Restart through UAC at startup so elevated windows are remapped too.
+    #[serde(default)]
+    run_elevated: bool,
+    /// Delay between injected events, globally and per application.
+    #[serde(default)]
+    output_throttle: OutputThrottle,
+    /// Clicks played for taps, chords and misfired chords.
+    #[serde(default)]
+    sound_feedback: SoundFeedback,
+    /// Load the `.rhai` scripts in the scripts folder as output hooks.
+    #[serde(default)]
+    output_scripts_enabled: bool,
+    /// IME modes forced for applications that misreport their IME state.
+    #[serde(default)]
+    ime_app_overrides: Vec<ime::AppImeOverride>,
+    /// Hours and idle time in which the engine suspends itself.
+    #[serde(default)]
+    auto_disable: AutoDisableSchedule,
+    /// `.kikyo` bundle in a synced folder (Dropbox, OneDrive, ...).
+    #[serde(default)]
+    sync_bundle_path: Option<String>,
+    /// `exported_unix_ms` of the last bundle written or imported here.
+    #[serde(default)]
+    sync_last_unix_ms: u64,
 }
 
 fn default_enabled() -> bool {
@@ -76,8 +279,28 @@ impl Default for Settings {
             last_layout_path: None,
             layout_entries: Vec::new(),
             active_layout_id: None,
+            layout_mru: Vec::new(),
+            previous_layout_hotkey: None,
+            tenkey_hotkey: None,
             profile: None,
             enabled: true,
+            language: Language::Ja,
+            log_level: LogLevel::Info,
+            profile_presets: Vec::new(),
+            active_profile_preset_id: None,
+            notifications_enabled: false,
+            tray_show_layout_initial: false,
+            tray_favorites_only: false,
+            bypass_password_fields: true,
+            hook_fast_path: false,
+            run_elevated: false,
+            output_throttle: OutputThrottle::default(),
+            sound_feedback: SoundFeedback::default(),
+            output_scripts_enabled: false,
+            ime_app_overrides: Vec::new(),
+            auto_disable: AutoDisableSchedule::default(),
+            sync_bundle_path: None,
+            sync_last_unix_ms: 0,
         }
     }
 }
@@ -91,6 +314,15 @@ fn generate_layout_entry_id() -> String {
     format!("layout-{}-{}", now_ms, seq)
 }
 
+fn generate_profile_preset_id() -> String {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let seq = ENTRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("profile-{}-{}", now_ms, seq)
+}
+
 fn fallback_alias_from_path(path: &str) -> String {
     Path::new(path)
         .file_stem()
@@ -255,6 +487,7 @@ fn migrate_settings(settings: &mut Settings) -> bool {
                 layout_name,
                 path,
                 order: 0,
+                ..Default::default()
             });
             changed = true;
         }
@@ -297,6 +530,112 @@ fn get_settings_path(app: &tauri::AppHandle) -> Option<PathBuf> {
         .ok()
 }
 
+fn get_log_dir(app: &tauri::AppHandle) -> Option<PathBuf> {
+    app.path().app_config_dir().map(|dir| dir.join("logs")).ok()
+}
+
+fn get_scripts_dir(app: &tauri::AppHandle) -> Option<PathBuf> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("scripts"))
+        .ok()
+}
+
+/// (Re)loads the output scripts, or unloads them when disabled.
+fn apply_output_scripts(app: &tauri::AppHandle, enabled: bool) -> Vec<String> {
+    match get_scripts_dir(app).filter(|_| enabled) {
+        Some(dir) => scripting::load_scripts(&dir),
+        None => {
+            scripting::unload_scripts();
+            Vec::new()
+        }
+    }
+}
+
+fn get_crash_report_dir(app: &tauri::AppHandle) -> Option<PathBuf> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("crash_reports"))
+        .ok()
+}
+
+/// Layout applied at startup: the active entry, else the last loaded file.
+fn startup_layout_path(settings: &Settings) -> Option<String> {
+    settings
+        .active_layout_id
+        .as_ref()
+        .and_then(|active_id| {
+            settings
+                .layout_entries
+                .iter()
+                .find(|entry| &entry.id == active_id)
+                .map(|entry| entry.path.clone())
+        })
+        .or_else(|| settings.last_layout_path.clone())
+}
+
+/// Entries to try when the startup layout at `failed_path` does not load:
+/// recently used ones first, then the rest in list order.
+fn startup_fallback_entries<'a>(settings: &'a Settings, failed_path: &str) -> Vec<&'a LayoutEntry> {
+    let recent = settings
+        .layout_mru
+        .iter()
+        .filter_map(|id| settings.layout_entries.iter().find(|entry| &entry.id == id));
+    let mut entries: Vec<&LayoutEntry> = Vec::new();
+    for entry in recent.chain(&settings.layout_entries) {
+        let failed =
+            settings.active_layout_id.as_ref() == Some(&entry.id) || entry.path == failed_path;
+        if !failed && !entries.iter().any(|seen| seen.id == entry.id) {
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+/// Applies the layout the app starts with. When it is missing or broken,
+/// switches to the most recently used entry that loads and tells the user.
+fn load_startup_layout(app: &tauri::AppHandle, state: &AppState, settings: &Settings, path: &str) {
+    let display_name = preferred_display_name_for_path(settings, path);
+    let Err(error) = apply_layout_from_path(app, state, path, display_name.clone()) else {
+        return;
+    };
+    tracing::warn!("Failed to load startup layout {}: {}", path, error);
+    let fallback = startup_fallback_entries(settings, path)
+        .into_iter()
+        .find_map(|entry| {
+            let name = preferred_entry_display_name(entry);
+            match apply_layout_from_path(app, state, &entry.path, Some(name.clone())) {
+                Ok(_) => Some((entry.clone(), name)),
+                Err(e) => {
+                    tracing::warn!("Fallback layout {} failed too: {}", entry.path, e);
+                    None
+                }
+            }
+        });
+    let Some((entry, name)) = fallback else {
+        safe_mode::mark_failed(&format!("layout: {}", error));
+        show_notification(app, format!("{}: {}", tr(Text::LayoutReloadFailed), error));
+        return;
+    };
+
+    let mut settings = load_settings_with_migration(app);
+    let broken_id = settings.active_layout_id.take();
+    push_layout_mru(&mut settings.layout_mru, &entry.id);
+    settings.active_layout_id = Some(entry.id.clone());
+    settings.last_layout_path = Some(entry.path.clone());
+    save_settings(app, &settings);
+    *state.layout_fallback.lock().unwrap() = Some(LayoutFallback {
+        broken_id,
+        fallback_id: entry.id,
+        error,
+    });
+    let broken_name = display_name.unwrap_or_else(|| fallback_alias_from_path(path));
+    show_notification(
+        app,
+        format!("{}: {} → {}", tr(Text::LayoutFellBack), broken_name, name),
+    );
+}
+
 fn load_settings(app: &tauri::AppHandle) -> Settings {
     if let Some(path) = get_settings_path(app) {
         if path.exists() {
@@ -329,6 +668,32 @@ fn save_settings(app: &tauri::AppHandle, settings: &Settings) {
     }
 }
 
+/// Settings kept outside the engine, applied at startup and after an import.
+fn apply_runtime_settings(settings: &Settings) {
+    i18n::set_language(settings.language);
+    if let Err(e) = logging::set_level(settings.log_level) {
+        tracing::warn!("Failed to set log level: {}", e);
+    }
+    TRAY_SHOW_LAYOUT_INITIAL.store(settings.tray_show_layout_initial, Ordering::Relaxed);
+    NOTIFICATIONS_ENABLED.store(settings.notifications_enabled, Ordering::Relaxed);
+    throttle::set_output_throttle(settings.output_throttle.clone());
+    feedback::set_sound_feedback(settings.sound_feedback.clone());
+    ime::set_app_overrides(settings.ime_app_overrides.clone());
+    AUTO_DISABLE.lock().unwrap().schedule = settings.auto_disable.clone();
+    wake_auto_disable_scheduler();
+    secure_input::set_enabled(settings.bypass_password_fields);
+    keyboard_hook::set_fast_path(settings.hook_fast_path);
+    keyboard_hook::set_hotkeys(
+        [
+            (PREVIOUS_LAYOUT_ID, settings.previous_layout_hotkey),
+            (TENKEY_ID, settings.tenkey_hotkey),
+        ]
+        .into_iter()
+        .filter_map(|(id, hotkey)| Some((id.to_string(), hotkey?)))
+        .collect(),
+    );
+}
+
 fn sanitize_profile_for_save(mut profile: Profile) -> Profile {
     // Keep only user-facing settings; derived layout data is re-built on load.
     profile.thumb_keys = None;
@@ -339,7 +704,7 @@ fn sanitize_profile_for_save(mut profile: Profile) -> Profile {
 
 fn update_tray_menu(app: &tauri::AppHandle) -> tauri::Result<()> {
     let layout_name = app.state::<AppState>().layout_name.lock().unwrap().clone();
-    let enabled = ENGINE.lock().is_enabled();
+    let enabled = EngineHandle::global().is_enabled();
     update_tray_menu_with_state(app, layout_name, enabled)
 }
 
@@ -365,15 +730,25 @@ fn update_tray_menu_with_state(
         .filter(|value| !value.is_empty())
         .map(|value| value.to_string())
         .or(active_name)
-        .unwrap_or_else(|| "配列定義なし".to_string());
+        .unwrap_or_else(|| tr(Text::NoLayout).to_string());
+
+    let tray_entries: Vec<&LayoutEntry> = settings
+        .layout_entries
+        .iter()
+        .filter(|entry| {
+            !settings.tray_favorites_only
+                || entry.favorite
+                || active_layout_id.as_deref() == Some(entry.id.as_str())
+        })
+        .collect();
 
     let menu = Menu::new(app)?;
-    if settings.layout_entries.is_empty() {
+    if tray_entries.is_empty() {
         let item_empty =
-            MenuItem::with_id(app, "layout_name", "配列定義なし", false, None::<&str>)?;
+            MenuItem::with_id(app, "layout_name", tr(Text::NoLayout), false, None::<&str>)?;
         menu.append(&item_empty)?;
     } else {
-        for entry in &settings.layout_entries {
+        for entry in tray_entries {
             let display_name = preferred_entry_display_name(entry);
             let item = CheckMenuItem::with_id(
                 app,
@@ -386,14 +761,41 @@ fn update_tray_menu_with_state(
             menu.append(&item)?;
         }
     }
+    if previous_layout_id(&settings).is_some() {
+        let item_previous = MenuItem::with_id(
+            app,
+            PREVIOUS_LAYOUT_ID,
+            tr(Text::PreviousLayout),
+            true,
+            None::<&str>,
+        )?;
+        menu.append(&item_previous)?;
+    }
 
     // Separator
     let sep1 = PredefinedMenuItem::separator(app)?;
     menu.append(&sep1)?;
 
+    // Profile presets
+    if !settings.profile_presets.is_empty() {
+        let profile_menu = Submenu::new(app, tr(Text::Profiles), true)?;
+        for preset in &settings.profile_presets {
+            let item = CheckMenuItem::with_id(
+                app,
+                tray_profile_item_menu_id(&preset.id),
+                &preset.name,
+                true,
+                settings.active_profile_preset_id.as_deref() == Some(preset.id.as_str()),
+                None::<&str>,
+            )?;
+            profile_menu.append(&item)?;
+        }
+        menu.append(&profile_menu)?;
+    }
+
     // Reload & Settings
-    let item_reload = MenuItem::with_id(app, "reload", "配列定義再読み込み", true, None::<&str>)?;
-    let item_settings = MenuItem::with_id(app, "show", "設定", true, None::<&str>)?;
+    let item_reload = MenuItem::with_id(app, "reload", tr(Text::ReloadLayout), true, None::<&str>)?;
+    let item_settings = MenuItem::with_id(app, "show", tr(Text::Settings), true, None::<&str>)?;
     menu.append(&item_reload)?;
     menu.append(&item_settings)?;
 
@@ -402,7 +804,11 @@ fn update_tray_menu_with_state(
     menu.append(&sep2)?;
 
     // Toggle
-    let toggle_text = if enabled { "一時停止" } else { "再開" };
+    let toggle_text = if enabled {
+        tr(Text::Suspend)
+    } else {
+        tr(Text::Resume)
+    };
     let item_toggle = MenuItem::with_id(app, "toggle", toggle_text, true, None::<&str>)?;
     menu.append(&item_toggle)?;
 
@@ -411,47 +817,40 @@ fn update_tray_menu_with_state(
     menu.append(&sep3)?;
 
     // Quit
-    let item_quit = MenuItem::with_id(app, "quit", "終了", true, None::<&str>)?;
+    let item_quit = MenuItem::with_id(app, "quit", tr(Text::Quit), true, None::<&str>)?;
     menu.append(&item_quit)?;
 
     if let Some(tray) = app.tray_by_id("kikyo-tray") {
         tray.set_menu(Some(menu))?;
-        tray.set_tooltip(Some(format!("Kikyo: {}", name_text)))?;
-
-        let icon_bytes = include_bytes!("../icons/128x128.png");
-        match image::load_from_memory(icon_bytes) {
-            Ok(mut img) => {
-                let (width, height) = img.dimensions();
-
-                if !enabled {
-                    // Draw a red diagonal line
-                    // Simple algorithm: line thickness = 10% of width
-                    let thickness = (width as i32) / 10;
-                    let mut rgba_img = img.to_rgba8();
-
-                    for x in 0..width {
-                        for y in 0..height {
-                            // Check if point (x, y) is close to the diagonal x=y
-                            let dist = (x as i32 - y as i32).abs();
-                            if dist < thickness / 2 {
-                                // Set to Red (255, 0, 0, 255)
-                                rgba_img.put_pixel(x, y, image::Rgba([255, 0, 0, 255]));
-                            }
-                        }
-                    }
-                    img = image::DynamicImage::ImageRgba8(rgba_img);
-                }
-
-                let rgba_bytes = img.into_rgba8().into_raw();
-                let icon = Image::new(&rgba_bytes, width, height);
-                if let Err(e) = tray.set_icon(Some(icon)) {
-                    tracing::error!("Failed to set tray icon: {}", e);
-                } else {
-                    tracing::info!("Tray icon updated successfully");
-                }
-            }
-            Err(e) => tracing::error!("Failed to load icon from memory: {}", e),
+        let version = app
+            .state::<AppState>()
+            .layout_version
+            .lock()
+            .unwrap()
+            .clone();
+        let mut tooltip = match version {
+            Some(version) => format!("{}: {} ({})", tr(Text::AppTitle), name_text, version),
+            None => format!("{}: {}", tr(Text::AppTitle), name_text),
+        };
+        if let Some(reason) = AUTO_DISABLE.lock().unwrap().active {
+            tooltip = format!("{}\n{}", tooltip, tr(auto_disable_text(reason)));
+        }
+        if enabled && secure_input::is_active() {
+            tooltip = format!("{}\n{}", tooltip, tr(Text::PasswordFieldBypass));
         }
+        tray.set_tooltip(Some(tooltip))?;
+
+        // Keep the last known input mode; the poller refreshes it.
+        let japanese = if enabled {
+            TRAY_ICON_STATE
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|state| state.japanese)
+        } else {
+            None
+        };
+        update_tray_icon(app, enabled, japanese);
     } else {
         tracing::warn!("Tray 'kikyo-tray' not found");
     }
@@ -459,13 +858,204 @@ fn update_tray_menu_with_state(
     Ok(())
 }
 
+fn update_tray_icon(app: &tauri::AppHandle, enabled: bool, japanese: Option<bool>) {
+    // Looks suspended while keys bypass the layout for a password field.
+    let enabled = enabled && !secure_input::is_active();
+    let japanese = japanese.filter(|_| enabled);
+    let state = app.state::<AppState>();
+    let initial = if TRAY_SHOW_LAYOUT_INITIAL.load(Ordering::Relaxed) {
+        let layout_name = state.layout_name.lock().unwrap().clone();
+        let path = state.current_yab_path.lock().unwrap().clone();
+        let stem = path.as_deref().map(fallback_alias_from_path);
+        tray_icon::layout_initial(layout_name.as_deref().into_iter().chain(stem.as_deref()))
+    } else {
+        None
+    };
+    let icon_state = TrayIconState {
+        enabled,
+        japanese,
+        light_taskbar: system_theme::taskbar_uses_light_theme(),
+        initial,
+    };
+
+    // Not held across set_icon, which may wait for the main thread.
+    if TRAY_ICON_STATE.lock().unwrap().as_ref() == Some(&icon_state) {
+        return;
+    }
+    let Some(tray) = app.tray_by_id("kikyo-tray") else {
+        return;
+    };
+    let icon_bytes = include_bytes!("../icons/128x128.png");
+    match image::load_from_memory(icon_bytes) {
+        Ok(base) => {
+            let img = tray_icon::render(&base, &icon_state);
+            let (width, height) = img.dimensions();
+            let icon = Image::new(img.as_raw(), width, height);
+            if let Err(e) = tray.set_icon(Some(icon)) {
+                tracing::error!("Failed to set tray icon: {}", e);
+            } else {
+                tracing::info!("Tray icon updated successfully");
+                *TRAY_ICON_STATE.lock().unwrap() = Some(icon_state);
+            }
+        }
+        Err(e) => tracing::error!("Failed to load icon from memory: {}", e),
+    }
+}
+
+/// Follows IME mode and taskbar theme changes, which raise no app event.
+fn spawn_tray_icon_poller(app: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_millis(TRAY_ICON_POLL_INTERVAL_MS));
+        let (enabled, ime_mode) =
+            EngineHandle::global().with(|engine| (engine.is_enabled(), engine.get_ime_mode()));
+        let japanese = enabled.then(|| ime::is_japanese_input_active(ime_mode));
+        update_tray_icon(&app, enabled, japanese);
+    });
+}
+
+fn auto_disable_text(reason: AutoDisableReason) -> Text {
+    match reason {
+        AutoDisableReason::Hours => Text::AutoSuspendedHours,
+        AutoDisableReason::Game => Text::AutoSuspendedGame,
+        AutoDisableReason::Idle => Text::AutoSuspendedIdle,
+    }
+}
+
+/// Follows the engine's events on a thread of its own, outside the engine
+/// lock, and brings the tray, the settings and the webview up to date.
+fn spawn_engine_event_listener(app: tauri::AppHandle) {
+    let events = EngineHandle::global().subscribe();
+    std::thread::spawn(move || {
+        for event in events {
+            match event {
+                EngineEvent::EnabledChanged { enabled } => on_enabled_changed(&app, enabled),
+                EngineEvent::Trace(event) => {
+                    let _ = app.emit("engine-trace", event);
+                }
+                EngineEvent::ChordTest(event) => {
+                    let _ = app.emit("chord-test", event);
+                }
+                EngineEvent::ChordTestEnded => {
+                    let _ = app.emit("chord-test-ended", ());
+                }
+                _ => {}
+            }
+        }
+    });
+}
+
+fn on_enabled_changed(app: &tauri::AppHandle, enabled: bool) {
+    // Resuming by hand overrides the rule that suspended the engine; its
+    // own suspensions are not saved as the user's.
+    let auto_reason = {
+        let mut state = AUTO_DISABLE.lock().unwrap();
+        if enabled && state.active.take().is_some() {
+            state.overridden = true;
+        }
+        state.active
+    };
+    if auto_reason.is_none() {
+        let mut settings = load_settings_with_migration(app);
+        settings.enabled = enabled;
+        save_settings(app, &settings);
+    }
+    let _ = app.emit("enabled-state-changed", enabled);
+    let layout_name = app.state::<AppState>().layout_name.lock().unwrap().clone();
+    let _ = update_tray_menu_with_state(app, layout_name, enabled);
+    // Toggling from the settings window needs no extra feedback.
+    let window_focused = app
+        .get_webview_window("main")
+        .and_then(|window| window.is_focused().ok())
+        .unwrap_or(false);
+    if !window_focused {
+        let text = match auto_reason {
+            Some(reason) => auto_disable_text(reason),
+            None if enabled => Text::EngineResumed,
+            None => Text::EngineSuspended,
+        };
+        show_notification(app, tr(text).to_string());
+    }
+}
+
+/// Has the auto-disable rules checked now, e.g. after they changed.
+fn wake_auto_disable_scheduler() {
+    if let Some(wake) = AUTO_DISABLE_WAKE.get() {
+        let _ = wake.send(());
+    }
+}
+
+/// Suspends the engine while an auto-disable rule applies and resumes it once
+/// none does. Only a suspension made here is undone. Checks when another
+/// application comes to the foreground, on the first key typed while idle,
+/// and when a rule's time comes.
+fn spawn_auto_disable_scheduler(app: tauri::AppHandle) {
+    let (wake, woken) = std::sync::mpsc::channel();
+    let _ = AUTO_DISABLE_WAKE.set(wake);
+    keyboard_hook::set_on_activity(wake_auto_disable_scheduler);
+    std::thread::spawn(move || loop {
+        let schedule = AUTO_DISABLE.lock().unwrap().schedule.clone();
+        let reason = schedule.current_reason();
+        let enabled = EngineHandle::global().is_enabled();
+        let (enable, reason_changed) = {
+            let mut state = AUTO_DISABLE.lock().unwrap();
+            match (reason, state.active) {
+                (None, active) => {
+                    state.overridden = false;
+                    state.active = None;
+                    (active.map(|_| true), false)
+                }
+                (Some(reason), Some(active)) => {
+                    state.active = Some(reason);
+                    (None, reason != active)
+                }
+                (Some(reason), None) if enabled && !state.overridden => {
+                    state.active = Some(reason);
+                    (Some(false), false)
+                }
+                (Some(_), None) => (None, false),
+            }
+        };
+        if reason_changed {
+            let _ = update_tray_menu(&app);
+        }
+        if let Some(enable) = enable {
+            let handle = EngineHandle::global();
+            if handle.is_enabled() != enable {
+                tracing::info!("Auto-disable: engine enabled = {} ({:?})", enable, reason);
+                handle.set_enabled(enable);
+            }
+        }
+        if reason == Some(AutoDisableReason::Idle) {
+            keyboard_hook::wake_on_next_typing();
+        }
+        let woke = match schedule.next_check() {
+            Some(wait) => woken.recv_timeout(wait).map_err(|_| ()),
+            None => woken.recv().map_err(|_| ()),
+        };
+        if woke.is_ok() {
+            while woken.try_recv().is_ok() {}
+        }
+    });
+}
+
+/// Shows a toast when notifications are enabled. Runs on its own thread so
+/// callers holding the engine lock are not delayed.
+fn show_notification(app: &tauri::AppHandle, body: String) {
+    if !NOTIFICATIONS_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let app_id = app.config().identifier.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = notify::show_toast(&app_id, tr(Text::AppTitle), &body) {
+            tracing::warn!("Failed to show notification: {}", e);
+        }
+    });
+}
+
 fn update_window_title(app: &tauri::AppHandle, layout_name: Option<&str>) {
     if let Some(window) = app.get_webview_window("main") {
-        let title_text = if let Some(name) = layout_name {
-            format!("桔梗 - {}", name)
-        } else {
-            "桔梗 - 配列定義なし".to_string()
-        };
+        let name = layout_name.unwrap_or(tr(Text::NoLayout));
+        let title_text = format!("{} - {}", tr(Text::AppTitle), name);
         let _ = window.set_title(&title_text);
     }
 }
@@ -484,7 +1074,15 @@ fn apply_layout_from_path(
         .map(|v| v.trim().to_string())
         .filter(|v| !v.is_empty())
         .unwrap_or_else(|| fallback_alias_from_path(path));
-    ENGINE.lock().load_layout(layout);
+    *state.layout_version.lock().unwrap() = layout.info.version.clone();
+    let is_current = state.current_yab_path.lock().unwrap().as_deref() == Some(path);
+    if is_current {
+        // Re-applying the same file, e.g. after editing it: keep keys in flight.
+        let reload = EngineHandle::global().reload_layout(layout);
+        tracing::info!("Reloaded layout {}: {:?}", path, reload);
+    } else {
+        EngineHandle::global().load_layout(layout);
+    }
     keyboard_hook::refresh_runtime_flags_from_engine();
 
     let resolved_display_name = display_name
@@ -494,12 +1092,37 @@ fn apply_layout_from_path(
 
     *state.current_yab_path.lock().unwrap() = Some(path.to_string());
     *state.layout_name.lock().unwrap() = Some(resolved_display_name.clone());
-    let enabled = ENGINE.lock().is_enabled();
+    let enabled = EngineHandle::global().is_enabled();
     let _ = update_tray_menu_with_state(app, Some(resolved_display_name.clone()), enabled);
     update_window_title(app, Some(resolved_display_name.as_str()));
     Ok(stats)
 }
 
+/// Moves `id` to the front of the recently-used list.
+fn push_layout_mru(mru: &mut Vec<String>, id: &str) {
+    mru.retain(|entry_id| entry_id != id);
+    mru.insert(0, id.to_string());
+    mru.truncate(LAYOUT_MRU_LEN);
+}
+
+/// Most recently used entry other than the active one that still exists.
+fn previous_layout_id(settings: &Settings) -> Option<&str> {
+    settings
+        .layout_mru
+        .iter()
+        .filter(|id| settings.active_layout_id.as_ref() != Some(*id))
+        .find(|id| settings.layout_entries.iter().any(|entry| &entry.id == *id))
+        .map(String::as_str)
+}
+
+fn activate_previous_layout(app: &tauri::AppHandle) -> Result<String, String> {
+    let settings = load_settings_with_migration(app);
+    let id = previous_layout_id(&settings)
+        .ok_or_else(|| tr(Text::NoPreviousLayout).to_string())?
+        .to_string();
+    activate_layout_entry_by_id(app, &app.state::<AppState>(), &id)
+}
+
 fn activate_layout_entry_by_id(
     app: &tauri::AppHandle,
     state: &AppState,
@@ -511,17 +1134,45 @@ fn activate_layout_entry_by_id(
         .iter()
         .find(|entry| entry.id == id)
         .cloned()
-        .ok_or_else(|| "Layout entry not found".to_string())?;
+        .ok_or_else(|| tr(Text::LayoutEntryNotFound).to_string())?;
 
     let display_name = preferred_entry_display_name(&entry);
-    let stats = apply_layout_from_path(app, state, &entry.path, Some(display_name))?;
-    settings.active_layout_id = Some(entry.id);
+    let stats = apply_layout_from_path(app, state, &entry.path, Some(display_name.clone()))?;
+    if let Some(previous) = settings.active_layout_id.take() {
+        push_layout_mru(&mut settings.layout_mru, &previous);
+    }
+    push_layout_mru(&mut settings.layout_mru, &entry.id);
+    settings.active_layout_id = Some(entry.id.clone());
     settings.last_layout_path = Some(entry.path);
     save_settings(app, &settings);
     let _ = update_tray_menu(app);
+    let _ = app.emit("layout-switched", entry.id);
+    show_notification(
+        app,
+        format!("{}: {}", tr(Text::LayoutSwitched), display_name),
+    );
     Ok(stats)
 }
 
+fn activate_profile_preset_by_id(app: &tauri::AppHandle, id: &str) -> Result<(), String> {
+    let mut settings = load_settings_with_migration(app);
+    let preset = settings
+        .profile_presets
+        .iter()
+        .find(|preset| preset.id == id)
+        .cloned()
+        .ok_or_else(|| tr(Text::ProfilePresetNotFound).to_string())?;
+
+    EngineHandle::global().set_profile(preset.profile.clone());
+    keyboard_hook::refresh_runtime_flags_from_engine();
+    settings.profile = Some(preset.profile);
+    settings.active_profile_preset_id = Some(preset.id);
+    save_settings(app, &settings);
+    let _ = update_tray_menu(app);
+    let _ = app.emit("profile-changed", ());
+    Ok(())
+}
+
 #[tauri::command]
 fn load_yab(
     app: tauri::AppHandle,
@@ -544,85 +1195,251 @@ fn load_yab(
 
 #[tauri::command]
 fn set_enabled(_app: tauri::AppHandle, enabled: bool) {
-    ENGINE.lock().set_enabled(enabled);
+    EngineHandle::global().set_enabled(enabled);
 }
 
 #[tauri::command]
 fn get_enabled() -> bool {
-    ENGINE.lock().is_enabled()
+    EngineHandle::global().is_enabled()
 }
 
 #[tauri::command]
 fn get_profile() -> Profile {
-    let profile = ENGINE.lock().get_profile();
+    let profile = EngineHandle::global().profile();
     // Remove layout-derived fields so JSON serialization works for UI.
     sanitize_profile_for_save(profile)
 }
 
 #[tauri::command]
 fn set_profile(app: tauri::AppHandle, profile: Profile) {
-    ENGINE.lock().set_profile(profile.clone());
+    EngineHandle::global().set_profile(profile.clone());
     keyboard_hook::refresh_runtime_flags_from_engine();
     let mut settings = load_settings_with_migration(&app);
-    settings.profile = Some(sanitize_profile_for_save(profile));
+    let profile = sanitize_profile_for_save(profile);
+    if !active_preset_matches(&settings, &profile) {
+        settings.active_profile_preset_id = None;
+    }
+    settings.profile = Some(profile);
     save_settings(&app, &settings);
+    let _ = update_tray_menu(&app);
 }
 
 #[tauri::command]
-fn get_app_version(app: tauri::AppHandle) -> String {
-    app.package_info().version.to_string()
+fn get_key_swaps() -> Vec<KeySwapCfg> {
+    EngineHandle::global().profile().key_swaps
+}
+
+/// Replaces the profile's key swaps, which apply on top of the layout's
+/// `[機能キー]` section.
+#[tauri::command]
+fn set_key_swaps(app: tauri::AppHandle, swaps: Vec<KeySwapCfg>) {
+    let mut profile = get_profile();
+    profile.key_swaps = swaps;
+    set_profile(app, profile);
+}
+
+/// What is wrong with the layout's and the profile's key swaps.
+#[tauri::command]
+fn get_key_swap_issues() -> Vec<KeySwapIssue> {
+    EngineHandle::global().with(|engine| engine.key_swap_issues().to_vec())
+}
+
+/// Whether the active preset still holds `profile` (no unsaved edits).
+fn active_preset_matches(settings: &Settings, profile: &Profile) -> bool {
+    let Some(active_id) = settings.active_profile_preset_id.as_deref() else {
+        return false;
+    };
+    settings
+        .profile_presets
+        .iter()
+        .find(|preset| preset.id == active_id)
+        .is_some_and(|preset| {
+            serde_json::to_value(&preset.profile).ok() == serde_json::to_value(profile).ok()
+        })
 }
 
 #[tauri::command]
-fn get_layout_entries(app: tauri::AppHandle) -> LayoutEntriesResponse {
+fn get_profile_presets(app: tauri::AppHandle) -> ProfilePresetsResponse {
     let settings = load_settings_with_migration(&app);
-    LayoutEntriesResponse {
-        entries: settings.layout_entries,
-        active_layout_id: settings.active_layout_id,
+    ProfilePresetsResponse {
+        presets: settings
+            .profile_presets
+            .iter()
+            .map(|preset| ProfilePresetSummary {
+                id: preset.id.clone(),
+                name: preset.name.clone(),
+            })
+            .collect(),
+        active_preset_id: settings.active_profile_preset_id,
     }
 }
 
+/// Stores the current profile under `name`, replacing a preset of the same name.
 #[tauri::command]
-fn create_layout_entry_from_path(
-    app: tauri::AppHandle,
-    path: String,
-) -> Result<LayoutEntry, String> {
-    let path = path.trim().to_string();
-    if path.is_empty() {
-        return Err("Path is empty".to_string());
+fn save_profile_preset(app: tauri::AppHandle, name: String) -> Result<String, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(tr(Text::PresetNameEmpty).to_string());
     }
-
+    let profile = sanitize_profile_for_save(EngineHandle::global().profile());
     let mut settings = load_settings_with_migration(&app);
-    let normalized = normalize_layout_path_for_compare(&path);
-    if settings
-        .layout_entries
-        .iter()
-        .any(|entry| normalize_layout_path_for_compare(&entry.path) == normalized)
+    let id = match settings
+        .profile_presets
+        .iter_mut()
+        .find(|preset| preset.name == name)
     {
-        return Err(DUPLICATE_LAYOUT_PATH_MESSAGE.to_string());
-    }
-    let layout_name = detect_layout_name_from_file(&path)?;
-    let entry = LayoutEntry {
-        id: generate_layout_entry_id(),
-        alias: layout_name.clone(),
-        layout_name,
-        path,
-        order: settings.layout_entries.len(),
+        Some(preset) => {
+            preset.profile = profile;
+            preset.id.clone()
+        }
+        None => {
+            let id = generate_profile_preset_id();
+            settings.profile_presets.push(ProfilePreset {
+                id: id.clone(),
+                name,
+                profile,
+            });
+            id
+        }
     };
-    settings.layout_entries.push(entry.clone());
-    let _ = refresh_layout_entry_order(&mut settings);
-    if settings.active_layout_id.is_none() {
-        settings.active_layout_id = Some(entry.id.clone());
-        let _ = sync_last_path_with_active(&mut settings);
+    settings.active_profile_preset_id = Some(id.clone());
+    save_settings(&app, &settings);
+    let _ = update_tray_menu(&app);
+    Ok(id)
+}
+
+#[tauri::command]
+fn delete_profile_preset(app: tauri::AppHandle, id: String) -> Result<(), String> {
+    let mut settings = load_settings_with_migration(&app);
+    let old_len = settings.profile_presets.len();
+    settings.profile_presets.retain(|preset| preset.id != id);
+    if settings.profile_presets.len() == old_len {
+        return Err(tr(Text::ProfilePresetNotFound).to_string());
+    }
+    if settings.active_profile_preset_id.as_deref() == Some(id.as_str()) {
+        settings.active_profile_preset_id = None;
     }
     save_settings(&app, &settings);
     let _ = update_tray_menu(&app);
-    Ok(entry)
+    Ok(())
 }
 
 #[tauri::command]
-fn update_layout_entry(
-    app: tauri::AppHandle,
+fn activate_profile_preset(app: tauri::AppHandle, id: String) -> Result<(), String> {
+    activate_profile_preset_by_id(&app, &id)
+}
+
+#[tauri::command]
+fn get_app_version(app: tauri::AppHandle) -> String {
+    app.package_info().version.to_string()
+}
+
+/// Trimmed, non-empty tags without duplicates, in their original order.
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized
+}
+
+/// Layout entries, optionally only those carrying `tag` or marked favorite.
+#[tauri::command]
+fn get_layout_entries(
+    app: tauri::AppHandle,
+    tag: Option<String>,
+    favorites_only: Option<bool>,
+) -> LayoutEntriesResponse {
+    let settings = load_settings_with_migration(&app);
+    let tag = tag.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    let favorites_only = favorites_only.unwrap_or(false);
+    LayoutEntriesResponse {
+        entries: settings
+            .layout_entries
+            .into_iter()
+            .filter(|entry| !favorites_only || entry.favorite)
+            .filter(|entry| tag.as_ref().is_none_or(|tag| entry.tags.contains(tag)))
+            .map(|entry| {
+                let (info, load_error) = match parser::load_yab(&entry.path) {
+                    Ok(layout) => (layout.info, None),
+                    Err(e) => (LayoutInfo::default(), Some(e.to_string())),
+                };
+                LayoutEntryView {
+                    entry,
+                    info,
+                    load_error,
+                }
+            })
+            .collect(),
+        active_layout_id: settings.active_layout_id,
+    }
+}
+
+#[tauri::command]
+fn set_layout_tags(
+    app: tauri::AppHandle,
+    id: String,
+    tags: Vec<String>,
+    favorite: bool,
+) -> Result<(), String> {
+    let mut settings = load_settings_with_migration(&app);
+    let entry = settings
+        .layout_entries
+        .iter_mut()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| tr(Text::LayoutEntryNotFound).to_string())?;
+    entry.tags = normalize_tags(tags);
+    entry.favorite = favorite;
+    save_settings(&app, &settings);
+    let _ = update_tray_menu(&app);
+    Ok(())
+}
+
+#[tauri::command]
+fn create_layout_entry_from_path(
+    app: tauri::AppHandle,
+    path: String,
+) -> Result<LayoutEntry, String> {
+    let path = path.trim().to_string();
+    if path.is_empty() {
+        return Err(tr(Text::PathEmpty).to_string());
+    }
+
+    let mut settings = load_settings_with_migration(&app);
+    let normalized = normalize_layout_path_for_compare(&path);
+    if settings
+        .layout_entries
+        .iter()
+        .any(|entry| normalize_layout_path_for_compare(&entry.path) == normalized)
+    {
+        return Err(tr(Text::DuplicateLayoutPath).to_string());
+    }
+    let layout_name = detect_layout_name_from_file(&path)?;
+    let entry = LayoutEntry {
+        id: generate_layout_entry_id(),
+        alias: layout_name.clone(),
+        layout_name,
+        path,
+        order: settings.layout_entries.len(),
+        ..Default::default()
+    };
+    settings.layout_entries.push(entry.clone());
+    let _ = refresh_layout_entry_order(&mut settings);
+    if settings.active_layout_id.is_none() {
+        settings.active_layout_id = Some(entry.id.clone());
+        let _ = sync_last_path_with_active(&mut settings);
+    }
+    save_settings(&app, &settings);
+    let _ = update_tray_menu(&app);
+    Ok(entry)
+}
+
+#[tauri::command]
+fn update_layout_entry(
+    app: tauri::AppHandle,
     state: tauri::State<AppState>,
     id: String,
     alias: String,
@@ -630,7 +1447,7 @@ fn update_layout_entry(
 ) -> Result<(), String> {
     let path = path.trim().to_string();
     if path.is_empty() {
-        return Err("Path is empty".to_string());
+        return Err(tr(Text::PathEmpty).to_string());
     }
 
     let mut settings = load_settings_with_migration(&app);
@@ -642,7 +1459,7 @@ fn update_layout_entry(
             .layout_entries
             .iter_mut()
             .find(|entry| entry.id == id)
-            .ok_or_else(|| "Layout entry not found".to_string())?;
+            .ok_or_else(|| tr(Text::LayoutEntryNotFound).to_string())?;
 
         let path_changed = entry.path != path;
         entry.path = path;
@@ -683,8 +1500,9 @@ fn delete_layout_entry(app: tauri::AppHandle, id: String) -> Result<(), String>
     let old_len = settings.layout_entries.len();
     settings.layout_entries.retain(|entry| entry.id != id);
     if settings.layout_entries.len() == old_len {
-        return Err("Layout entry not found".to_string());
+        return Err(tr(Text::LayoutEntryNotFound).to_string());
     }
+    settings.layout_mru.retain(|entry_id| entry_id != &id);
 
     if settings.active_layout_id.as_deref() == Some(id.as_str()) {
         settings.active_layout_id = settings
@@ -704,7 +1522,7 @@ fn delete_layout_entry(app: tauri::AppHandle, id: String) -> Result<(), String>
 fn reorder_layout_entries(app: tauri::AppHandle, ordered_ids: Vec<String>) -> Result<(), String> {
     let mut settings = load_settings_with_migration(&app);
     if ordered_ids.len() != settings.layout_entries.len() {
-        return Err("Invalid number of layout ids".to_string());
+        return Err(tr(Text::InvalidLayoutIdCount).to_string());
     }
 
     let mut by_id: HashMap<String, LayoutEntry> = HashMap::new();
@@ -716,12 +1534,12 @@ fn reorder_layout_entries(app: tauri::AppHandle, ordered_ids: Vec<String>) -> Re
     for id in ordered_ids {
         let entry = by_id
             .remove(&id)
-            .ok_or_else(|| "Unknown layout id".to_string())?;
+            .ok_or_else(|| tr(Text::UnknownLayoutId).to_string())?;
         reordered.push(entry);
     }
 
     if !by_id.is_empty() {
-        return Err("Some layout ids are missing in order payload".to_string());
+        return Err(tr(Text::MissingLayoutIds).to_string());
     }
 
     settings.layout_entries = reordered;
@@ -740,9 +1558,825 @@ fn activate_layout_entry(
     activate_layout_entry_by_id(&app, &state, id.as_str())
 }
 
+#[tauri::command]
+fn switch_to_previous_layout(app: tauri::AppHandle) -> Result<String, String> {
+    activate_previous_layout(&app)
+}
+
+#[tauri::command]
+fn get_previous_layout_hotkey(app: tauri::AppHandle) -> Option<Hotkey> {
+    load_settings_with_migration(&app).previous_layout_hotkey
+}
+
+#[tauri::command]
+fn set_previous_layout_hotkey(app: tauri::AppHandle, hotkey: Option<Hotkey>) {
+    let mut settings = load_settings_with_migration(&app);
+    settings.previous_layout_hotkey = hotkey.filter(|hotkey| hotkey.vk != 0);
+    save_settings(&app, &settings);
+    apply_runtime_settings(&settings);
+}
+
+#[tauri::command]
+fn get_tenkey_hotkey(app: tauri::AppHandle) -> Option<Hotkey> {
+    load_settings_with_migration(&app).tenkey_hotkey
+}
+
+#[tauri::command]
+fn set_tenkey_hotkey(app: tauri::AppHandle, hotkey: Option<Hotkey>) {
+    let mut settings = load_settings_with_migration(&app);
+    settings.tenkey_hotkey = hotkey.filter(|hotkey| hotkey.vk != 0);
+    save_settings(&app, &settings);
+    apply_runtime_settings(&settings);
+}
+
+#[tauri::command]
+fn compare_layouts(path_a: String, path_b: String) -> Result<LayoutDiff, String> {
+    layout_diff::compare_layouts(path_a.trim(), path_b.trim()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn start_training(target: String) -> Result<(), String> {
+    let target = target.trim();
+    if target.is_empty() {
+        return Err(tr(Text::TrainingTextEmpty).to_string());
+    }
+    EngineHandle::global().with(|engine| engine.start_training(target));
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_training() -> Option<TrainingReport> {
+    EngineHandle::global().with(|engine| engine.stop_training())
+}
+
+/// Stops typing and streams what chords would type as "chord-test"
+/// events, until `end_chord_test`.
+#[tauri::command]
+fn begin_chord_test() {
+    EngineHandle::global().with(|engine| engine.begin_chord_test());
+}
+
+#[tauri::command]
+fn end_chord_test() {
+    EngineHandle::global().with(|engine| engine.end_chord_test());
+}
+
+#[tauri::command]
+fn get_training_report() -> Option<TrainingReport> {
+    EngineHandle::global().with(|engine| engine.training_report())
+}
+
+#[tauri::command]
+fn get_usage_heatmap() -> UsageHeatmap {
+    EngineHandle::global().with(|engine| engine.usage_heatmap())
+}
+
+/// Which keys the loaded layout defines and what it lacks for `template`.
+#[tauri::command]
+fn get_layout_coverage(template: Option<CoverageTemplate>) -> Option<LayoutCoverage> {
+    EngineHandle::global().with(|engine| engine.layout_coverage(template.unwrap_or_default()))
+}
+
+/// Frequent unchorded key pairs and often-erased chords, for layout authors.
+#[tauri::command]
+fn get_layout_suggestions(limit: Option<usize>) -> LayoutSuggestions {
+    EngineHandle::global().with(|engine| engine.layout_suggestions(limit.unwrap_or(20)))
+}
+
+#[tauri::command]
+fn reset_usage_stats() {
+    EngineHandle::global().with(|engine| engine.reset_usage_stats());
+}
+
+/// Writes the key/chord usage heatmap as "json" or "csv".
+#[tauri::command]
+fn export_usage_heatmap(path: String, format: String) -> Result<(), String> {
+    let path = path.trim();
+    if path.is_empty() {
+        return Err(tr(Text::PathEmpty).to_string());
+    }
+    let heatmap = EngineHandle::global().with(|engine| engine.usage_heatmap());
+    let content = match format.to_ascii_lowercase().as_str() {
+        "json" => serde_json::to_string_pretty(&heatmap).map_err(|e| e.to_string())?,
+        "csv" => heatmap.to_csv(),
+        other => return Err(format!("{}: {}", tr(Text::UnsupportedExportFormat), other)),
+    };
+    fs::write(path, content).map_err(|e| e.to_string())
+}
+
+#[derive(serde::Serialize)]
+struct LogInfo {
+    level: LogLevel,
+    dir: Option<String>,
+}
+
+#[tauri::command]
+fn get_log_info(app: tauri::AppHandle) -> LogInfo {
+    LogInfo {
+        level: load_settings_with_migration(&app).log_level,
+        dir: get_log_dir(&app).map(|dir| dir.to_string_lossy().into_owned()),
+    }
+}
+
+#[tauri::command]
+fn set_log_level(app: tauri::AppHandle, level: LogLevel) -> Result<(), String> {
+    logging::set_level(level)?;
+    let mut settings = load_settings_with_migration(&app);
+    settings.log_level = level;
+    save_settings(&app, &settings);
+    Ok(())
+}
+
+/// Latest log lines (oldest first), for attaching to bug reports.
+#[tauri::command]
+fn get_recent_logs(limit: Option<usize>) -> Vec<String> {
+    logging::recent_lines(limit.unwrap_or(200))
+}
+
+/// Records key events into the log folder for `minutes`, so chord misfires
+/// can be reported. Only key positions are written, but with the layout
+/// they show what was typed.
+#[tauri::command]
+fn start_key_recording(
+    app: tauri::AppHandle,
+    minutes: u64,
+) -> Result<Option<RecordingStatus>, String> {
+    let dir = get_log_dir(&app).ok_or_else(|| tr(Text::LogDirUnavailable).to_string())?;
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = dir.join(format!("keys-{}.jsonl", now_ms));
+    let profile = sanitize_profile_for_save(EngineHandle::global().profile());
+    recorder::start(
+        &path,
+        std::time::Duration::from_secs(
+            minutes.clamp(1, recorder::MAX_RECORDING.as_secs() / 60) * 60,
+        ),
+        &profile,
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(recorder::status())
+}
+
+/// Stops the recording and reveals its file for attaching to a report.
+#[tauri::command]
+fn stop_key_recording(app: tauri::AppHandle) -> Result<(), String> {
+    match recorder::stop() {
+        Some(path) => app
+            .opener()
+            .reveal_item_in_dir(&path)
+            .map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+fn get_key_recording_status() -> Option<RecordingStatus> {
+    recorder::status()
+}
+
+/// The startup layout fallback, once. It happens during setup, before the
+/// window can listen for events, so the window asks when it loads.
+#[tauri::command]
+fn take_layout_fallback(state: tauri::State<AppState>) -> Option<LayoutFallback> {
+    state.layout_fallback.lock().unwrap().take()
+}
+
+#[tauri::command]
+fn get_safe_mode() -> Option<safe_mode::SafeModeInfo> {
+    safe_mode::current()
+}
+
+/// Applies the saved layout and enabled state that safe mode skipped.
+#[tauri::command]
+fn exit_safe_mode(app: tauri::AppHandle, state: tauri::State<AppState>) -> Result<(), String> {
+    if safe_mode::current().is_none() {
+        return Ok(());
+    }
+    safe_mode::leave();
+    let settings = load_settings_with_migration(&app);
+    if let Some(path) = startup_layout_path(&settings) {
+        let display_name = preferred_display_name_for_path(&settings, &path);
+        apply_layout_from_path(&app, &state, &path, display_name)?;
+    }
+    EngineHandle::global().set_enabled(settings.enabled);
+    Ok(())
+}
+
+/// Reports the next physical key through the "key-captured" event instead
+/// of typing it, for binding keys in the settings UI.
+#[tauri::command]
+fn capture_key(app: tauri::AppHandle) {
+    keyboard_hook::capture_next_key(move |key| {
+        let _ = app.emit("key-captured", key);
+    });
+}
+
+#[tauri::command]
+fn cancel_key_capture() {
+    keyboard_hook::cancel_key_capture();
+}
+
+/// Every named key with the name to show for it.
+#[tauri::command]
+fn get_key_names() -> Vec<KeyNameInfo> {
+    jis_map::key_names()
+}
+
+/// Keys of a JIS keyboard with their position, names and current roles,
+/// for drawing the keyboard.
+#[tauri::command]
+fn get_virtual_keyboard_model() -> VirtualKeyboard {
+    EngineHandle::global().with(|engine| engine.virtual_keyboard())
+}
+
+#[derive(serde::Serialize)]
+struct CrashReportSummary {
+    file_name: String,
+    message: String,
+}
+
+/// Crash reports written by the panic hook, newest first.
+#[tauri::command]
+fn list_crash_reports(app: tauri::AppHandle) -> Vec<CrashReportSummary> {
+    let Some(dir) = get_crash_report_dir(&app) else {
+        return Vec::new();
+    };
+    crash_report::list_reports(&dir)
+        .into_iter()
+        .filter_map(|path| {
+            let file_name = path.file_name()?.to_str()?.to_string();
+            let message = read_crash_report(&path)
+                .and_then(|report| report["message"].as_str().map(str::to_string))
+                .unwrap_or_default();
+            Some(CrashReportSummary { file_name, message })
+        })
+        .collect()
+}
+
+fn read_crash_report(path: &Path) -> Option<serde_json::Value> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Looks `file_name` up among the listed reports so arbitrary paths are rejected.
+fn find_crash_report(app: &tauri::AppHandle, file_name: &str) -> Result<PathBuf, String> {
+    let dir = get_crash_report_dir(app).ok_or_else(|| tr(Text::CrashReportNotFound).to_string())?;
+    crash_report::list_reports(&dir)
+        .into_iter()
+        .find(|path| path.file_name().and_then(|n| n.to_str()) == Some(file_name))
+        .ok_or_else(|| tr(Text::CrashReportNotFound).to_string())
+}
+
+#[tauri::command]
+fn open_crash_report_folder(app: tauri::AppHandle) -> Result<(), String> {
+    let dir =
+        get_crash_report_dir(&app).ok_or_else(|| tr(Text::CrashReportNotFound).to_string())?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    app.opener()
+        .open_path(dir.to_string_lossy(), None::<&str>)
+        .map_err(|e| e.to_string())
+}
+
+/// Opens a prefilled GitHub issue for the report and reveals the file so the
+/// user can review and attach it. Nothing is uploaded automatically.
+#[tauri::command]
+fn send_crash_report(app: tauri::AppHandle, file_name: String) -> Result<(), String> {
+    let path = find_crash_report(&app, &file_name)?;
+    let report = read_crash_report(&path).unwrap_or_default();
+    let field = |key: &str| report[key].as_str().unwrap_or("-").to_string();
+    let title = format!("Crash: {}", field("message"));
+    let body = format!(
+        "Version: {}\nThread: {}\nLocation: {}\nMessage: {}\n\nPlease attach `{}` after reviewing it.\n",
+        field("app_version"),
+        field("thread"),
+        field("location"),
+        field("message"),
+        file_name
+    );
+    let url = tauri::Url::parse_with_params(
+        CRASH_REPORT_ISSUE_URL,
+        &[("title", title.as_str()), ("body", body.as_str())],
+    )
+    .map_err(|e| e.to_string())?;
+    app.opener()
+        .reveal_item_in_dir(&path)
+        .map_err(|e| e.to_string())?;
+    app.opener()
+        .open_url(url.as_str(), None::<&str>)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_output_throttle(app: tauri::AppHandle) -> OutputThrottle {
+    load_settings_with_migration(&app).output_throttle
+}
+
+#[tauri::command]
+fn set_output_throttle(app: tauri::AppHandle, throttle: OutputThrottle) {
+    throttle::set_output_throttle(throttle.clone());
+    let mut settings = load_settings_with_migration(&app);
+    settings.output_throttle = throttle;
+    save_settings(&app, &settings);
+}
+
+#[derive(serde::Serialize)]
+struct OutputScriptsInfo {
+    enabled: bool,
+    dir: Option<String>,
+    running: Vec<String>,
+}
+
+#[tauri::command]
+fn get_output_scripts(app: tauri::AppHandle) -> OutputScriptsInfo {
+    OutputScriptsInfo {
+        enabled: load_settings_with_migration(&app).output_scripts_enabled,
+        dir: get_scripts_dir(&app).map(|dir| dir.to_string_lossy().into_owned()),
+        running: scripting::loaded_scripts(),
+    }
+}
+
+/// Turns the output scripts on or off. Also reloads them after edits.
+#[tauri::command]
+fn set_output_scripts_enabled(app: tauri::AppHandle, enabled: bool) -> Vec<String> {
+    let mut settings = load_settings_with_migration(&app);
+    settings.output_scripts_enabled = enabled;
+    save_settings(&app, &settings);
+    apply_output_scripts(&app, enabled)
+}
+
+#[tauri::command]
+fn open_scripts_folder(app: tauri::AppHandle) -> Result<(), String> {
+    let dir = get_scripts_dir(&app).ok_or_else(|| tr(Text::LogDirUnavailable).to_string())?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    app.opener()
+        .open_path(dir.to_string_lossy(), None::<&str>)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_sound_feedback(app: tauri::AppHandle) -> SoundFeedback {
+    load_settings_with_migration(&app).sound_feedback
+}
+
+#[tauri::command]
+fn set_sound_feedback(app: tauri::AppHandle, feedback: SoundFeedback) {
+    feedback::set_sound_feedback(feedback.clone());
+    let mut settings = load_settings_with_migration(&app);
+    settings.sound_feedback = feedback;
+    save_settings(&app, &settings);
+}
+
+fn unix_ms_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// The settings without machine-specific fields, plus every registered
+/// layout file and its includes. Unreadable layouts are left out.
+fn build_settings_bundle(
+    settings: &Settings,
+    exported_unix_ms: u64,
+) -> Result<SettingsBundle, String> {
+    let mut value = serde_json::to_value(settings).map_err(|e| e.to_string())?;
+    if let Some(map) = value.as_object_mut() {
+        for key in LOCAL_SETTINGS_KEYS {
+            map.remove(*key);
+        }
+    }
+    let layouts = settings
+        .layout_entries
+        .iter()
+        .filter_map(
+            |entry| match BundledLayout::from_layout(&entry.id, Path::new(&entry.path)) {
+                Ok(layout) => Some(layout),
+                Err(e) => {
+                    tracing::warn!("Left {} out of the bundle: {:#}", entry.path, e);
+                    None
+                }
+            },
+        )
+        .collect();
+    Ok(SettingsBundle {
+        format: BUNDLE_FORMAT,
+        exported_unix_ms,
+        exported_by: std::env::var("COMPUTERNAME").unwrap_or_default(),
+        settings: value,
+        layouts,
+    })
+}
+
+/// Replaces the settings with the bundle's, keeping machine-specific fields,
+/// and applies them. Layout files are unpacked into the config directory.
+fn import_settings_bundle_from(
+    app: &tauri::AppHandle,
+    state: &AppState,
+    bundle: &SettingsBundle,
+) -> Result<(), String> {
+    let current = load_settings_with_migration(app);
+    let mut value = bundle.settings.clone();
+    let local = serde_json::to_value(&current).map_err(|e| e.to_string())?;
+    if let (Some(map), Some(local)) = (value.as_object_mut(), local.as_object()) {
+        for key in LOCAL_SETTINGS_KEYS {
+            match local.get(*key) {
+                Some(v) => map.insert(key.to_string(), v.clone()),
+                None => map.remove(*key),
+            };
+        }
+    }
+    let mut settings: Settings = serde_json::from_value(value).map_err(|e| e.to_string())?;
+
+    let dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|e| e.to_string())?
+        .join(SYNCED_LAYOUT_DIR);
+    for layout in &bundle.layouts {
+        let mut components = Path::new(&layout.entry_id).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(std::path::Component::Normal(_)), None)
+        ) {
+            return Err(format!(
+                "{}: {}",
+                tr(Text::UnknownLayoutId),
+                layout.entry_id
+            ));
+        }
+        let path = layout
+            .extract(&dir.join(&layout.entry_id))
+            .map_err(|e| e.to_string())?;
+        if let Some(entry) = settings
+            .layout_entries
+            .iter_mut()
+            .find(|entry| entry.id == layout.entry_id)
+        {
+            entry.path = path.to_string_lossy().into_owned();
+        }
+    }
+    settings.sync_last_unix_ms = current.sync_last_unix_ms.max(bundle.exported_unix_ms);
+    let _ = migrate_settings(&mut settings);
+    let _ = sync_last_path_with_active(&mut settings);
+    save_settings(app, &settings);
+
+    apply_runtime_settings(&settings);
+    if let Some(profile) = settings.profile.as_ref() {
+        EngineHandle::global().set_profile(profile.clone());
+        keyboard_hook::refresh_runtime_flags_from_engine();
+    }
+    if let Some(path) = startup_layout_path(&settings) {
+        let display_name = preferred_display_name_for_path(&settings, &path);
+        apply_layout_from_path(app, state, &path, display_name)?;
+    }
+    let _ = update_tray_menu(app);
+    let _ = app.emit("settings-imported", ());
+    Ok(())
+}
+
+/// Imports the sync bundle when it is newer than the last one seen here.
+fn pull_settings_sync(app: &tauri::AppHandle) -> Result<bool, String> {
+    let settings = load_settings_with_migration(app);
+    let Some(path) = settings.sync_bundle_path.as_deref() else {
+        return Ok(false);
+    };
+    let bundle = SettingsBundle::read(Path::new(path)).map_err(|e| e.to_string())?;
+    if bundle.exported_unix_ms <= settings.sync_last_unix_ms {
+        return Ok(false);
+    }
+    let state = app.state::<AppState>();
+    import_settings_bundle_from(app, &state, &bundle)?;
+    tracing::info!("Imported settings synced from {}", bundle.exported_by);
+    show_notification(app, tr(Text::SettingsSynced).to_string());
+    Ok(true)
+}
+
+/// Writes the sync bundle, unless another PC wrote a newer one that has
+/// not been imported here yet.
+fn push_settings_sync_to(app: &tauri::AppHandle, path: &Path) -> Result<(), String> {
+    let mut settings = load_settings_with_migration(app);
+    if let Ok(existing) = SettingsBundle::read(path) {
+        if existing.exported_unix_ms > settings.sync_last_unix_ms {
+            return Err(tr(Text::SyncBundleNewer).to_string());
+        }
+    }
+    let now_ms = unix_ms_now().max(settings.sync_last_unix_ms + 1);
+    build_settings_bundle(&settings, now_ms)?
+        .write(path)
+        .map_err(|e| e.to_string())?;
+    settings.sync_last_unix_ms = now_ms;
+    save_settings(app, &settings);
+    Ok(())
+}
+
+/// Polls the sync bundle's modification time and imports it when it
+/// changes. Sync clients replace the file, so no change event is reliable.
+fn spawn_settings_sync_watcher(app: tauri::AppHandle) {
+    std::thread::spawn(move || {
+        let mut seen: Option<(PathBuf, SystemTime)> = None;
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(SYNC_POLL_INTERVAL_MS));
+            let Some(path) = SYNC_BUNDLE_PATH.lock().unwrap().clone() else {
+                continue;
+            };
+            let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) else {
+                continue;
+            };
+            let current = Some((path, modified));
+            if seen == current {
+                continue;
+            }
+            seen = current;
+            if let Err(e) = pull_settings_sync(&app) {
+                tracing::warn!("Failed to import synced settings: {}", e);
+            }
+        }
+    });
+}
+
+#[tauri::command]
+fn export_settings_bundle(app: tauri::AppHandle, path: String) -> Result<(), String> {
+    let path = path.trim();
+    if path.is_empty() {
+        return Err(tr(Text::PathEmpty).to_string());
+    }
+    let settings = load_settings_with_migration(&app);
+    build_settings_bundle(&settings, unix_ms_now())?
+        .write(Path::new(path))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn import_settings_bundle(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    path: String,
+) -> Result<(), String> {
+    let bundle = SettingsBundle::read(Path::new(path.trim())).map_err(|e| e.to_string())?;
+    import_settings_bundle_from(&app, &state, &bundle)
+}
+
+#[derive(serde::Serialize)]
+struct SettingsSyncInfo {
+    path: Option<String>,
+    last_unix_ms: u64,
+}
+
+#[tauri::command]
+fn get_settings_sync(app: tauri::AppHandle) -> SettingsSyncInfo {
+    let settings = load_settings_with_migration(&app);
+    SettingsSyncInfo {
+        path: settings.sync_bundle_path,
+        last_unix_ms: settings.sync_last_unix_ms,
+    }
+}
+
+/// Sets the bundle to sync through, or turns sync off with `None`. An
+/// existing newer bundle is imported; otherwise the settings are written.
+#[tauri::command]
+fn set_settings_sync_path(app: tauri::AppHandle, path: Option<String>) -> Result<(), String> {
+    let path = path.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+    let mut settings = load_settings_with_migration(&app);
+    settings.sync_bundle_path = path.clone();
+    save_settings(&app, &settings);
+    *SYNC_BUNDLE_PATH.lock().unwrap() = path.as_ref().map(PathBuf::from);
+    let Some(path) = path else {
+        return Ok(());
+    };
+    if Path::new(&path).exists() && pull_settings_sync(&app)? {
+        return Ok(());
+    }
+    push_settings_sync_to(&app, Path::new(&path))
+}
+
+/// Writes the current settings to the sync bundle.
+#[tauri::command]
+fn push_settings_sync(app: tauri::AppHandle) -> Result<(), String> {
+    let path = load_settings_with_migration(&app)
+        .sync_bundle_path
+        .ok_or_else(|| tr(Text::PathEmpty).to_string())?;
+    push_settings_sync_to(&app, Path::new(&path))
+}
+
+#[tauri::command]
+fn get_auto_disable_schedule(app: tauri::AppHandle) -> AutoDisableSchedule {
+    load_settings_with_migration(&app).auto_disable
+}
+
+#[tauri::command]
+fn set_auto_disable_schedule(app: tauri::AppHandle, schedule: AutoDisableSchedule) {
+    AUTO_DISABLE.lock().unwrap().schedule = schedule.clone();
+    wake_auto_disable_scheduler();
+    let mut settings = load_settings_with_migration(&app);
+    settings.auto_disable = schedule;
+    save_settings(&app, &settings);
+}
+
+#[tauri::command]
+fn get_notifications_enabled() -> bool {
+    NOTIFICATIONS_ENABLED.load(Ordering::Relaxed)
+}
+
+#[tauri::command]
+fn set_notifications_enabled(app: tauri::AppHandle, enabled: bool) {
+    NOTIFICATIONS_ENABLED.store(enabled, Ordering::Relaxed);
+    let mut settings = load_settings_with_migration(&app);
+    settings.notifications_enabled = enabled;
+    save_settings(&app, &settings);
+}
+
+#[tauri::command]
+fn get_bypass_password_fields() -> bool {
+    secure_input::is_enabled()
+}
+
+#[tauri::command]
+fn set_bypass_password_fields(app: tauri::AppHandle, enabled: bool) {
+    secure_input::set_enabled(enabled);
+    let mut settings = load_settings_with_migration(&app);
+    settings.bypass_password_fields = enabled;
+    save_settings(&app, &settings);
+}
+
+#[tauri::command]
+fn get_hook_fast_path() -> bool {
+    keyboard_hook::is_fast_path()
+}
+
+#[tauri::command]
+fn set_hook_fast_path(app: tauri::AppHandle, enabled: bool) {
+    keyboard_hook::set_fast_path(enabled);
+    let mut settings = load_settings_with_migration(&app);
+    settings.hook_fast_path = enabled;
+    save_settings(&app, &settings);
+}
+
+#[tauri::command]
+fn get_hook_health() -> HookHealth {
+    keyboard_hook::hook_health()
+}
+
+#[tauri::command]
+fn get_injection_health() -> InjectionHealth {
+    keyboard_hook::injection_health()
+}
+
+#[tauri::command]
+fn get_tray_show_layout_initial() -> bool {
+    TRAY_SHOW_LAYOUT_INITIAL.load(Ordering::Relaxed)
+}
+
+#[tauri::command]
+fn set_tray_show_layout_initial(app: tauri::AppHandle, enabled: bool) {
+    TRAY_SHOW_LAYOUT_INITIAL.store(enabled, Ordering::Relaxed);
+    let mut settings = load_settings_with_migration(&app);
+    settings.tray_show_layout_initial = enabled;
+    save_settings(&app, &settings);
+    let _ = update_tray_menu(&app);
+}
+
+#[tauri::command]
+fn get_tray_favorites_only(app: tauri::AppHandle) -> bool {
+    load_settings_with_migration(&app).tray_favorites_only
+}
+
+#[tauri::command]
+fn set_tray_favorites_only(app: tauri::AppHandle, enabled: bool) {
+    let mut settings = load_settings_with_migration(&app);
+    settings.tray_favorites_only = enabled;
+    save_settings(&app, &settings);
+    let _ = update_tray_menu(&app);
+}
+
+#[tauri::command]
+fn get_language() -> Language {
+    i18n::language()
+}
+
+#[tauri::command]
+fn set_language(app: tauri::AppHandle, language: Language) -> Result<(), String> {
+    i18n::set_language(language);
+    let mut settings = load_settings_with_migration(&app);
+    settings.language = language;
+    save_settings(&app, &settings);
+    let layout_name = app.state::<AppState>().layout_name.lock().unwrap().clone();
+    update_window_title(&app, layout_name.as_deref());
+    update_tray_menu(&app).map_err(|e| e.to_string())
+}
+
+#[derive(serde::Serialize)]
+struct ElevationResponse {
+    #[serde(flatten)]
+    status: ElevationStatus,
+    run_elevated: bool,
+}
+
+#[tauri::command]
+fn get_engine_state() -> EngineState {
+    EngineHandle::global().with(|engine| engine.engine_state())
+}
+
+/// Which IME backend answered for the foreground window, and what it saw.
+#[tauri::command]
+fn get_ime_debug_info() -> ime::ImeDebugInfo {
+    let mode = EngineHandle::global().with(|engine| engine.get_ime_mode());
+    ime::debug_info(mode)
+}
+
+#[tauri::command]
+fn get_ime_app_overrides(app: tauri::AppHandle) -> Vec<ime::AppImeOverride> {
+    load_settings_with_migration(&app).ime_app_overrides
+}
+
+#[tauri::command]
+fn set_ime_app_overrides(app: tauri::AppHandle, overrides: Vec<ime::AppImeOverride>) {
+    ime::set_app_overrides(overrides.clone());
+    let mut settings = load_settings_with_migration(&app);
+    settings.ime_app_overrides = overrides;
+    save_settings(&app, &settings);
+}
+
+#[tauri::command]
+fn get_elevation_status(app: tauri::AppHandle) -> ElevationResponse {
+    ElevationResponse {
+        status: elevation::elevation_status(),
+        run_elevated: load_settings_with_migration(&app).run_elevated,
+    }
+}
+
+#[tauri::command]
+fn set_run_elevated(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let mut settings = load_settings_with_migration(&app);
+    settings.run_elevated = enabled;
+    save_settings(&app, &settings);
+    Ok(())
+}
+
+/// Starts an elevated copy of the app and exits this one.
+#[tauri::command]
+fn restart_elevated() -> Result<(), String> {
+    if elevation::is_process_elevated() {
+        return Err(tr(Text::AlreadyElevated).to_string());
+    }
+    elevation::relaunch_elevated(ELEVATED_RESTART_ARG).map_err(|e| e.to_string())?;
+    safe_mode::mark_healthy();
+    keyboard_hook::shutdown();
+    scripting::unload_scripts();
+    std::process::exit(0);
+}
+
+fn is_elevated_restart() -> bool {
+    std::env::args().any(|arg| arg == ELEVATED_RESTART_ARG)
+}
+
+/// Process id of the instance this one takes over from, if started by a
+/// `--replace` handover.
+fn takeover_pid() -> Option<u32> {
+    let mut args = std::env::args().skip_while(|arg| arg != TAKEOVER_ARG);
+    args.next()?;
+    args.next()?.parse().ok()
+}
+
+/// Hands over to a second launch made with `--replace`: starts its
+/// executable again and exits, so that the keyboard hook has one owner.
+/// `args` and `cwd` are the second launch's.
+fn hand_over_to_replacement(args: &[String], cwd: &str) {
+    let Some(exe) = args.first() else {
+        return;
+    };
+    // A relative program path is relative to the second launch's folder.
+    let exe = Path::new(cwd).join(exe);
+    let spawned = std::process::Command::new(&exe)
+        .args(args[1..].iter().filter(|arg| *arg != REPLACE_ARG))
+        .arg(TAKEOVER_ARG)
+        .arg(std::process::id().to_string())
+        .current_dir(cwd)
+        .spawn();
+    if let Err(e) = spawned {
+        tracing::error!(
+            "Failed to start replacement instance {}: {}",
+            exe.display(),
+            e
+        );
+        return;
+    }
+    tracing::info!("Handing over to replacement instance {}", exe.display());
+    safe_mode::mark_healthy();
+    keyboard_hook::shutdown();
+    scripting::unload_scripts();
+    std::process::exit(0);
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{normalize_layout_path_for_compare, Settings};
+    use super::{
+        build_settings_bundle, normalize_layout_path_for_compare, normalize_tags,
+        previous_layout_id, push_layout_mru, startup_fallback_entries,
+        tray_profile_id_from_menu_id, tray_profile_item_menu_id, LayoutEntry, Settings,
+        LOCAL_SETTINGS_KEYS,
+    };
 
     #[test]
     fn settings_default_enabled_is_true() {
@@ -755,6 +2389,93 @@ mod tests {
         assert!(parsed.enabled);
     }
 
+    #[test]
+    fn tray_profile_menu_id_round_trips() {
+        let menu_id = tray_profile_item_menu_id("profile-1-2");
+        assert_eq!(tray_profile_id_from_menu_id(&menu_id), Some("profile-1-2"));
+        assert_eq!(tray_profile_id_from_menu_id("layout_entry::x"), None);
+    }
+
+    #[test]
+    fn previous_layout_skips_active_and_deleted_entries() {
+        let mut settings = Settings::default();
+        for id in ["a", "b", "c"] {
+            settings.layout_entries.push(LayoutEntry {
+                id: id.to_string(),
+                ..Default::default()
+            });
+        }
+        for id in ["a", "b", "gone", "c", "b"] {
+            push_layout_mru(&mut settings.layout_mru, id);
+        }
+        assert_eq!(settings.layout_mru, vec!["b", "c", "gone", "a"]);
+
+        settings.active_layout_id = Some("b".to_string());
+        assert_eq!(previous_layout_id(&settings), Some("c"));
+        settings.active_layout_id = Some("c".to_string());
+        assert_eq!(previous_layout_id(&settings), Some("b"));
+        settings.layout_mru = vec!["c".to_string(), "gone".to_string()];
+        assert_eq!(previous_layout_id(&settings), None);
+    }
+
+    #[test]
+    fn startup_falls_back_to_recent_entries_first() {
+        let mut settings = Settings::default();
+        for id in ["a", "b", "c", "d"] {
+            settings.layout_entries.push(LayoutEntry {
+                id: id.to_string(),
+                path: format!("{id}.yab"),
+                ..Default::default()
+            });
+        }
+        settings.layout_mru = vec!["b".to_string(), "gone".to_string(), "c".to_string()];
+        settings.active_layout_id = Some("b".to_string());
+        let ids: Vec<&str> = startup_fallback_entries(&settings, "b.yab")
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["c", "a", "d"]);
+
+        settings.active_layout_id = None;
+        let ids: Vec<&str> = startup_fallback_entries(&settings, "a.yab")
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["b", "c", "d"]);
+    }
+
+    #[test]
+    fn layout_tags_are_trimmed_and_deduplicated() {
+        let tags = vec![
+            " 親指 ".to_string(),
+            String::new(),
+            "試作".to_string(),
+            "親指".to_string(),
+        ];
+        assert_eq!(normalize_tags(tags), vec!["親指", "試作"]);
+    }
+
+    #[test]
+    fn settings_bundle_leaves_out_machine_specific_fields() {
+        let settings = Settings {
+            run_elevated: true,
+            sync_bundle_path: Some("D:\\Sync\\kikyo.kikyo".to_string()),
+            ..Settings::default()
+        };
+        let bundle = build_settings_bundle(&settings, 42).expect("bundle");
+        assert_eq!(bundle.exported_unix_ms, 42);
+        for key in LOCAL_SETTINGS_KEYS {
+            assert!(bundle.settings.get(*key).is_none(), "{} was bundled", key);
+        }
+        assert!(bundle.settings.get("language").is_some());
+    }
+
+    #[test]
+    fn settings_deserialize_without_run_elevated_defaults_to_false() {
+        let parsed: Settings = serde_json::from_str("{}").expect("settings json");
+        assert!(!parsed.run_elevated);
+    }
+
     #[test]
     fn settings_deserialize_legacy_last_yab_path_into_last_layout_path() {
         let parsed: Settings =
@@ -789,12 +2510,30 @@ mod tests {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tracing_subscriber::fmt::init();
+    logging::init();
+
+    if is_elevated_restart() {
+        // Give the previous instance time to exit so the single-instance
+        // plugin does not hand this launch over to it.
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+    if let Some(pid) = takeover_pid() {
+        // The single-instance plugin would hand this launch back to the
+        // previous instance while that still runs.
+        let timeout = std::time::Duration::from_millis(TAKEOVER_WAIT_MS);
+        if !elevation::wait_for_process_exit(pid, timeout) {
+            tracing::warn!("Previous instance {} has not exited", pid);
+        }
+    }
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            if args.iter().any(|arg| arg == REPLACE_ARG) {
+                hand_over_to_replacement(&args, &cwd);
+                return;
+            }
             if let Some(window) = app.get_webview_window("main") {
                 let _ = window.show();
                 let _ = window.set_focus();
@@ -807,6 +2546,8 @@ pub fn run() {
         .manage(AppState {
             current_yab_path: Mutex::new(None),
             layout_name: Mutex::new(None),
+            layout_version: Mutex::new(None),
+            layout_fallback: Mutex::new(None),
         })
         .invoke_handler(tauri::generate_handler![
             load_yab,
@@ -815,16 +2556,105 @@ pub fn run() {
             update_layout_entry,
             delete_layout_entry,
             reorder_layout_entries,
+            set_layout_tags,
+            switch_to_previous_layout,
+            get_previous_layout_hotkey,
+            set_previous_layout_hotkey,
+            get_tenkey_hotkey,
+            set_tenkey_hotkey,
             activate_layout_entry,
             set_enabled,
             get_enabled,
             get_profile,
             set_profile,
-            get_app_version
+            get_key_swaps,
+            set_key_swaps,
+            get_key_swap_issues,
+            get_app_version,
+            compare_layouts,
+            start_training,
+            stop_training,
+            begin_chord_test,
+            end_chord_test,
+            get_training_report,
+            get_usage_heatmap,
+            get_layout_coverage,
+            get_layout_suggestions,
+            reset_usage_stats,
+            export_usage_heatmap,
+            get_profile_presets,
+            save_profile_preset,
+            delete_profile_preset,
+            activate_profile_preset,
+            get_language,
+            set_language,
+            get_tray_show_layout_initial,
+            set_tray_show_layout_initial,
+            get_tray_favorites_only,
+            set_tray_favorites_only,
+            get_bypass_password_fields,
+            set_bypass_password_fields,
+            get_hook_fast_path,
+            set_hook_fast_path,
+            get_hook_health,
+            get_injection_health,
+            get_output_throttle,
+            set_output_throttle,
+            get_sound_feedback,
+            set_sound_feedback,
+            get_output_scripts,
+            set_output_scripts_enabled,
+            open_scripts_folder,
+            get_auto_disable_schedule,
+            export_settings_bundle,
+            import_settings_bundle,
+            get_settings_sync,
+            set_settings_sync_path,
+            push_settings_sync,
+            set_auto_disable_schedule,
+            get_notifications_enabled,
+            set_notifications_enabled,
+            get_log_info,
+            set_log_level,
+            get_recent_logs,
+            start_key_recording,
+            stop_key_recording,
+            get_key_recording_status,
+            take_layout_fallback,
+            get_safe_mode,
+            exit_safe_mode,
+            capture_key,
+            cancel_key_capture,
+            get_key_names,
+            get_virtual_keyboard_model,
+            list_crash_reports,
+            open_crash_report_folder,
+            send_crash_report,
+            get_engine_state,
+            get_ime_debug_info,
+            get_ime_app_overrides,
+            set_ime_app_overrides,
+            get_elevation_status,
+            set_run_elevated,
+            restart_elevated
         ])
         .setup(|app| {
+            if let Some(dir) = get_crash_report_dir(app.handle()) {
+                crash_report::install_panic_hook(dir, app.package_info().version.to_string());
+            }
+            keyboard_hook::install_exit_guard();
+            let settings = load_settings_with_migration(app.handle());
+            apply_runtime_settings(&settings);
+            if let Some(dir) = get_log_dir(app.handle()) {
+                if let Err(e) = logging::set_log_dir(&dir) {
+                    tracing::warn!("Failed to open log file in {:?}: {}", dir, e);
+                }
+            }
+            *SYNC_BUNDLE_PATH.lock().unwrap() =
+                settings.sync_bundle_path.as_ref().map(PathBuf::from);
+
             // Setup Tray with initial menu
-            let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+            let quit_i = MenuItem::with_id(app, "quit", tr(Text::Quit), true, None::<&str>)?;
             let menu = Menu::with_items(app, &[&quit_i])?;
 
             let _tray = TrayIconBuilder::with_id("kikyo-tray")
@@ -833,6 +2663,9 @@ pub fn run() {
                     let event_id = event.id.as_ref();
                     match event_id {
                         "quit" => {
+                            safe_mode::mark_healthy();
+                            keyboard_hook::shutdown();
+                            scripting::unload_scripts();
                             std::process::exit(0);
                         }
                         "show" => {
@@ -850,18 +2683,38 @@ pub fn run() {
                                     preferred_display_name_for_path(&settings, &path);
                                 match apply_layout_from_path(app, &state, &path, display_name) {
                                     Ok(_) => tracing::info!("Reloaded config from tray"),
-                                    Err(e) => tracing::error!("Failed to reload config: {}", e),
+                                    Err(e) => {
+                                        tracing::error!("Failed to reload config: {}", e);
+                                        show_notification(
+                                            app,
+                                            format!("{}: {}", tr(Text::LayoutReloadFailed), e),
+                                        );
+                                    }
                                 }
                             }
                         }
+                        PREVIOUS_LAYOUT_ID => {
+                            if let Err(e) = activate_previous_layout(app) {
+                                tracing::error!("Failed to switch to previous layout: {}", e);
+                            }
+                        }
                         "toggle" => {
-                            let current = ENGINE.lock().is_enabled();
-                            ENGINE.lock().set_enabled(!current);
+                            let current = EngineHandle::global().is_enabled();
+                            EngineHandle::global().set_enabled(!current);
                             let _ = update_tray_menu(app);
                             let _ = app.emit("enabled-state-changed", !current);
                         }
                         _ => {
-                            if let Some(layout_id) = tray_layout_id_from_menu_id(event_id) {
+                            if let Some(preset_id) = tray_profile_id_from_menu_id(event_id) {
+                                if let Err(e) = activate_profile_preset_by_id(app, preset_id) {
+                                    tracing::error!(
+                                        "Failed to activate profile from tray ({}): {}",
+                                        preset_id,
+                                        e
+                                    );
+                                    let _ = update_tray_menu(app);
+                                }
+                            } else if let Some(layout_id) = tray_layout_id_from_menu_id(event_id) {
                                 let state = app.state::<AppState>();
                                 match activate_layout_entry_by_id(app, &state, layout_id) {
                                     Ok(_) => {
@@ -896,30 +2749,46 @@ pub fn run() {
                 .icon(app.default_window_icon().unwrap().clone())
                 .build(app)?;
 
-            // Load settings (profile first, then layout)
-            let settings = load_settings_with_migration(app.handle());
-            ENGINE.lock().set_enabled(settings.enabled);
+            // Apply settings (profile first, then layout)
+            if settings.run_elevated && !is_elevated_restart() && !elevation::is_process_elevated()
+            {
+                match elevation::relaunch_elevated(ELEVATED_RESTART_ARG) {
+                    Ok(()) => std::process::exit(0),
+                    Err(e) => tracing::warn!("Elevated restart declined or failed: {}", e),
+                }
+            }
+            // A previous run that crashed or failed to load its layout starts
+            // disabled and without a layout, so a broken layout cannot lock
+            // the user out of their keyboard.
+            let safe_mode_info = app
+                .path()
+                .app_config_dir()
+                .ok()
+                .and_then(|dir| safe_mode::begin(&dir));
+            let previous_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                safe_mode::mark_failed(&format!("panic: {}", info));
+                previous_hook(info);
+            }));
+
+            EngineHandle::global().set_enabled(settings.enabled && safe_mode_info.is_none());
             if let Some(profile) = settings.profile.as_ref() {
-                ENGINE.lock().set_profile(profile.clone());
+                EngineHandle::global().set_profile(profile.clone());
                 keyboard_hook::refresh_runtime_flags_from_engine();
             }
-            let startup_path = settings
-                .active_layout_id
-                .as_ref()
-                .and_then(|active_id| {
-                    settings
-                        .layout_entries
-                        .iter()
-                        .find(|entry| &entry.id == active_id)
-                        .map(|entry| entry.path.clone())
-                })
-                .or_else(|| settings.last_layout_path.clone());
 
-            if let Some(path) = startup_path {
-                let display_name = preferred_display_name_for_path(&settings, &path);
+            if let Some(info) = safe_mode_info.as_ref() {
+                tracing::warn!("Starting in safe mode: {}", info.reason);
+                show_notification(app.handle(), tr(Text::SafeModeStarted).to_string());
+                let _ = app.emit("safe-mode", info.clone());
+            } else if let Some(path) = startup_layout_path(&settings) {
                 let app_state = app.state::<AppState>();
-                let _ = apply_layout_from_path(app.handle(), &app_state, &path, display_name);
+                load_startup_layout(app.handle(), &app_state, &settings, &path);
             }
+            std::thread::spawn(|| {
+                std::thread::sleep(safe_mode::HEALTHY_AFTER);
+                safe_mode::mark_healthy();
+            });
 
             // Update to correct initial state
             update_tray_menu(app.handle())?;
@@ -955,24 +2824,54 @@ pub fn run() {
                 }
             });
 
-            // Register callback for Engine state changes
-            let handle_for_cb = app.handle().clone();
-            ENGINE.lock().set_on_enabled_change(move |enabled| {
-                let mut settings = load_settings_with_migration(&handle_for_cb);
-                settings.enabled = enabled;
-                save_settings(&handle_for_cb, &settings);
-                let _ = handle_for_cb.emit("enabled-state-changed", enabled);
-                let layout_name = handle_for_cb
-                    .state::<AppState>()
-                    .layout_name
-                    .lock()
-                    .unwrap()
-                    .clone();
-                let _ = update_tray_menu_with_state(&handle_for_cb, layout_name, enabled);
+            spawn_engine_event_listener(app.handle().clone());
+            spawn_tray_icon_poller(app.handle().clone());
+            spawn_auto_disable_scheduler(app.handle().clone());
+            spawn_settings_sync_watcher(app.handle().clone());
+            apply_output_scripts(app.handle(), settings.output_scripts_enabled);
+
+            let handle_for_hotkey = app.handle().clone();
+            keyboard_hook::set_on_hotkey(move |id| {
+                if id == PREVIOUS_LAYOUT_ID {
+                    if let Err(e) = activate_previous_layout(&handle_for_hotkey) {
+                        tracing::warn!("Previous layout hotkey: {}", e);
+                    }
+                } else if id == TENKEY_ID {
+                    EngineHandle::global().with(|engine| {
+                        let on = engine.is_tenkey_on();
+                        engine.set_tenkey(!on);
+                    });
+                }
+            });
+
+            let handle_for_secure_input = app.handle().clone();
+            secure_input::set_on_change(move |active| {
+                let _ = handle_for_secure_input.emit("secure-input-changed", active);
+                let _ = update_tray_menu(&handle_for_secure_input);
+            });
+
+            // Let the UI know when the watchdog had to re-install the hook
+            let handle_for_recovery = app.handle().clone();
+            keyboard_hook::set_on_hook_recovered(move |reason| {
+                let _ = handle_for_recovery.emit("hook-recovered", reason);
+            });
+            let handle_for_slow_hook = app.handle().clone();
+            keyboard_hook::set_on_hook_slow(move |health| {
+                let _ = handle_for_slow_hook.emit("hook-slow", health);
+            });
+            let handle_for_injection = app.handle().clone();
+            keyboard_hook::set_on_injection_failed(move |dropped| {
+                let _ = handle_for_injection.emit("injection-failed", dropped);
             });
 
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                keyboard_hook::shutdown();
+                scripting::unload_scripts();
+            }
+        });
 }
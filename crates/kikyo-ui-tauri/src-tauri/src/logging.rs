@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+const LOG_FILE_NAME: &str = "kikyo.log";
+/// Rotate once the current file grows past this size.
+const MAX_LOG_FILE_BYTES: u64 = 1024 * 1024;
+/// Rotated files kept besides the current one (kikyo.1.log ...).
+const MAX_ROTATED_FILES: usize = 3;
+const MAX_RECENT_LINES: usize = 1000;
+
+static SINK: LogSink = LogSink::new();
+static LEVEL_HANDLE: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Installs the global subscriber: console output plus the file/memory sink.
+/// Lines logged before [`set_log_dir`] are only kept in memory.
+pub fn init() {
+    let (filter, handle) = reload::Layer::new(LogLevel::default().filter());
+    let _ = LEVEL_HANDLE.set(handle);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(fmt::layer().with_ansi(false).with_writer(|| SinkWriter))
+        .init();
+}
+
+pub fn set_level(level: LogLevel) -> Result<(), String> {
+    let handle = LEVEL_HANDLE
+        .get()
+        .ok_or_else(|| "Logging is not initialized".to_string())?;
+    handle
+        .modify(|filter| *filter = level.filter())
+        .map_err(|e| e.to_string())
+}
+
+/// Starts writing log files into `dir`.
+pub fn set_log_dir(dir: &Path) -> io::Result<()> {
+    SINK.state.lock().unwrap().open(dir)
+}
+
+/// Most recent log lines, oldest first.
+pub fn recent_lines(limit: usize) -> Vec<String> {
+    let state = SINK.state.lock().unwrap();
+    let skip = state.recent.len().saturating_sub(limit);
+    state.recent.iter().skip(skip).cloned().collect()
+}
+
+struct LogSink {
+    state: Mutex<SinkState>,
+}
+
+impl LogSink {
+    const fn new() -> Self {
+        Self {
+            state: Mutex::new(SinkState {
+                recent: VecDeque::new(),
+                file: None,
+                dir: None,
+                size: 0,
+            }),
+        }
+    }
+}
+
+struct SinkState {
+    recent: VecDeque<String>,
+    file: Option<File>,
+    dir: Option<PathBuf>,
+    size: u64,
+}
+
+impl SinkState {
+    fn open(&mut self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOG_FILE_NAME);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        self.file = Some(file);
+        self.dir = Some(dir.to_path_buf());
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        for line in String::from_utf8_lossy(buf).lines() {
+            if self.recent.len() >= MAX_RECENT_LINES {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(line.to_string());
+        }
+
+        if self.size + buf.len() as u64 > MAX_LOG_FILE_BYTES {
+            self.rotate();
+        }
+        if let Some(file) = self.file.as_mut() {
+            if file.write_all(buf).is_ok() {
+                self.size += buf.len() as u64;
+            }
+        }
+    }
+
+    /// kikyo.log -> kikyo.1.log -> kikyo.2.log ..., dropping the oldest.
+    fn rotate(&mut self) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        self.file = None;
+        for index in (1..MAX_ROTATED_FILES).rev() {
+            let _ = fs::rename(rotated_path(&dir, index), rotated_path(&dir, index + 1));
+        }
+        let _ = fs::rename(dir.join(LOG_FILE_NAME), rotated_path(&dir, 1));
+        if self.open(&dir).is_err() {
+            self.file = None;
+        }
+    }
+}
+
+fn rotated_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("kikyo.{}.log", index))
+}
+
+struct SinkWriter;
+
+impl Write for SinkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        SINK.state.lock().unwrap().write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_state() -> SinkState {
+        SinkState {
+            recent: VecDeque::new(),
+            file: None,
+            dir: None,
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn recent_lines_are_capped() {
+        let mut state = empty_state();
+        for i in 0..MAX_RECENT_LINES + 5 {
+            state.write(format!("line {}\n", i).as_bytes());
+        }
+        assert_eq!(state.recent.len(), MAX_RECENT_LINES);
+        assert_eq!(state.recent.front().map(String::as_str), Some("line 5"));
+    }
+
+    #[test]
+    fn log_file_rotates_when_full() {
+        let dir = std::env::temp_dir().join(format!("kikyo-log-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut state = empty_state();
+        state.open(&dir).expect("open log dir");
+
+        let chunk = vec![b'x'; (MAX_LOG_FILE_BYTES / 2) as usize];
+        for _ in 0..3 {
+            state.write(&chunk);
+        }
+        assert!(rotated_path(&dir, 1).exists());
+        assert_eq!(state.size, chunk.len() as u64);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
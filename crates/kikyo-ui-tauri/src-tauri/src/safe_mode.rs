@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const SENTINEL_FILE_NAME: &str = "startup.pending";
+/// The sentinel is removed once a run has lasted this long without failing.
+pub const HEALTHY_AFTER: Duration = Duration::from_secs(15);
+const REASON_STARTING: &str = "starting";
+
+static SENTINEL_PATH: OnceLock<PathBuf> = OnceLock::new();
+static RUN_FAILED: AtomicBool = AtomicBool::new(false);
+static CURRENT: Mutex<Option<SafeModeInfo>> = Mutex::new(None);
+
+/// Why the app started in safe mode, as recorded by the previous run.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SafeModeInfo {
+    pub reason: String,
+}
+
+/// Writes the startup sentinel into `dir`. Returns the previous run's failure
+/// if its sentinel was never cleared, in which case safe mode is entered.
+pub fn begin(dir: &Path) -> Option<SafeModeInfo> {
+    let path = dir.join(SENTINEL_FILE_NAME);
+    let previous = read_sentinel(&path);
+    let _ = fs::create_dir_all(dir);
+    let _ = fs::write(&path, REASON_STARTING);
+    let _ = SENTINEL_PATH.set(path);
+    *CURRENT.lock().unwrap() = previous.clone();
+    previous
+}
+
+/// Keeps the sentinel so the next start enters safe mode.
+pub fn mark_failed(reason: &str) {
+    RUN_FAILED.store(true, Ordering::Relaxed);
+    if let Some(path) = SENTINEL_PATH.get() {
+        let _ = fs::write(path, reason);
+    }
+}
+
+/// Removes the sentinel unless this run has failed.
+pub fn mark_healthy() {
+    if RUN_FAILED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(path) = SENTINEL_PATH.get() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Safe mode details while it is active.
+pub fn current() -> Option<SafeModeInfo> {
+    CURRENT.lock().unwrap().clone()
+}
+
+pub fn leave() {
+    CURRENT.lock().unwrap().take();
+}
+
+fn read_sentinel(path: &Path) -> Option<SafeModeInfo> {
+    let reason = fs::read_to_string(path).ok()?;
+    let reason = match reason.trim() {
+        // Never got past startup: killed, hung or crashed without a panic.
+        "" | REASON_STARTING => "startup did not complete".to_string(),
+        other => other.to_string(),
+    };
+    Some(SafeModeInfo { reason })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentinel_left_behind_reports_previous_failure() {
+        let dir = std::env::temp_dir().join(format!("kikyo-safe-mode-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join(SENTINEL_FILE_NAME);
+
+        assert_eq!(read_sentinel(&path), None);
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, REASON_STARTING).unwrap();
+        assert_eq!(
+            read_sentinel(&path).map(|info| info.reason),
+            Some("startup did not complete".to_string())
+        );
+
+        fs::write(&path, "layout: parse error").unwrap();
+        assert_eq!(
+            read_sentinel(&path).map(|info| info.reason),
+            Some("layout: parse error".to_string())
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
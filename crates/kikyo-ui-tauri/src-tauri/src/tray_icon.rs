@@ -0,0 +1,206 @@
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+const JAPANESE_BADGE: Rgba<u8> = Rgba([123, 79, 160, 255]);
+const ALPHA_BADGE: Rgba<u8> = Rgba([96, 96, 96, 255]);
+const NEUTRAL_BADGE: Rgba<u8> = Rgba([64, 64, 64, 255]);
+const DISABLED_LINE: Rgba<u8> = Rgba([255, 0, 0, 255]);
+const LETTER: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Everything the tray icon reflects. Compared to skip redundant redraws.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrayIconState {
+    pub enabled: bool,
+    /// Japanese input mode, or `None` when it is not shown.
+    pub japanese: Option<bool>,
+    pub light_taskbar: bool,
+    /// Letter drawn into the badge (active layout's initial).
+    pub initial: Option<char>,
+}
+
+/// First ASCII letter or digit of the first name that has one, upper-cased.
+pub fn layout_initial<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<char> {
+    names.into_iter().find_map(|name| {
+        name.chars()
+            .find(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+    })
+}
+
+/// Draws the icon for `state` on top of the application logo.
+pub fn render(base: &DynamicImage, state: &TrayIconState) -> RgbaImage {
+    let (width, height) = base.dimensions();
+    let mut img = if state.enabled {
+        base.to_rgba8()
+    } else {
+        disabled_image(base)
+    };
+
+    let badge = match state.japanese {
+        Some(true) => Some(JAPANESE_BADGE),
+        Some(false) => Some(ALPHA_BADGE),
+        None if state.initial.is_some() => Some(NEUTRAL_BADGE),
+        None => None,
+    };
+    if let Some(fill) = badge {
+        // Outline contrasts with the taskbar so the badge stays visible.
+        let outline = if state.light_taskbar {
+            Rgba([32, 32, 32, 255])
+        } else {
+            Rgba([255, 255, 255, 255])
+        };
+        let radius = width.min(height) as f32 * 0.27;
+        let cx = width as f32 - radius - 1.0;
+        let cy = height as f32 - radius - 1.0;
+        fill_circle(&mut img, cx, cy, radius, outline);
+        fill_circle(&mut img, cx, cy, radius * 0.85, fill);
+        if let Some(initial) = state.initial {
+            draw_letter(&mut img, initial, cx, cy, radius * 1.1);
+        }
+    }
+    img
+}
+
+/// Grayscale logo with a red diagonal line.
+fn disabled_image(base: &DynamicImage) -> RgbaImage {
+    let (width, _) = base.dimensions();
+    let mut img = base.to_rgba8();
+    for pixel in img.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let luma = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8;
+        *pixel = Rgba([luma, luma, luma, a]);
+    }
+    // Line thickness = 10% of width.
+    let thickness = (width as i32) / 10;
+    let (w, h) = img.dimensions();
+    for x in 0..w {
+        for y in 0..h {
+            if (x as i32 - y as i32).abs() < thickness / 2 {
+                img.put_pixel(x, y, DISABLED_LINE);
+            }
+        }
+    }
+    img
+}
+
+fn fill_circle(img: &mut RgbaImage, cx: f32, cy: f32, radius: f32, color: Rgba<u8>) {
+    let (width, height) = img.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let dx = x as f32 + 0.5 - cx;
+            let dy = y as f32 + 0.5 - cy;
+            if dx * dx + dy * dy <= radius * radius {
+                img.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+/// Draws `c` from the 5x7 font, centered on (cx, cy) and `size` pixels tall.
+fn draw_letter(img: &mut RgbaImage, c: char, cx: f32, cy: f32, size: f32) {
+    let Some(rows) = glyph(c) else {
+        return;
+    };
+    let scale = (size / 7.0).max(1.0) as u32;
+    let left = cx as i64 - (5 * scale as i64) / 2;
+    let top = cy as i64 - (7 * scale as i64) / 2;
+    let (width, height) = img.dimensions();
+    for (row, bits) in rows.iter().enumerate() {
+        for col in 0..5u32 {
+            if bits & (0x10 >> col) == 0 {
+                continue;
+            }
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    let x = left + (col * scale + dx) as i64;
+                    let y = top + (row as u32 * scale + dy) as i64;
+                    if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                        img.put_pixel(x as u32, y as u32, LETTER);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 5x7 bitmap rows (MSB = leftmost column) for A-Z and 0-9.
+fn glyph(c: char) -> Option<[u8; 7]> {
+    let rows = match c {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        _ => return None,
+    };
+    Some(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(enabled: bool, japanese: Option<bool>, initial: Option<char>) -> TrayIconState {
+        TrayIconState {
+            enabled,
+            japanese,
+            light_taskbar: false,
+            initial,
+        }
+    }
+
+    #[test]
+    fn layout_initial_uses_first_ascii_alphanumeric() {
+        assert_eq!(layout_initial(["新下駄", "shingeta"]), Some('S'));
+        assert_eq!(layout_initial(["月配列2-263"]), Some('2'));
+        assert_eq!(layout_initial(["飛鳥"]), None);
+    }
+
+    #[test]
+    fn render_draws_badge_only_when_mode_or_initial_is_shown() {
+        let base = DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 32, Rgba([0, 0, 0, 0])));
+        let corner = |img: &RgbaImage| *img.get_pixel(24, 24);
+
+        let plain = render(&base, &state(true, None, None));
+        assert_eq!(corner(&plain), Rgba([0, 0, 0, 0]));
+
+        let japanese = render(&base, &state(true, Some(true), None));
+        assert_eq!(corner(&japanese), JAPANESE_BADGE);
+
+        let alpha = render(&base, &state(true, Some(false), None));
+        assert_eq!(corner(&alpha), ALPHA_BADGE);
+
+        let disabled = render(&base, &state(false, None, None));
+        assert_eq!(*disabled.get_pixel(0, 0), DISABLED_LINE);
+    }
+}
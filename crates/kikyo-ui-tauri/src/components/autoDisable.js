@@ -0,0 +1,70 @@
+function toTime(minutes) {
+  const h = String(Math.floor(minutes / 60)).padStart(2, "0");
+  const m = String(minutes % 60).padStart(2, "0");
+  return `${h}:${m}`;
+}
+
+function toMinutes(value) {
+  const match = value.match(/^(\d{1,2}):(\d{2})$/);
+  if (!match) return null;
+  return parseInt(match[1], 10) * 60 + parseInt(match[2], 10);
+}
+
+export function mountAutoDisable(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const hoursCb = root.querySelector("#auto-disable-hours-enabled");
+  const startInput = root.querySelector("#auto-disable-start");
+  const endInput = root.querySelector("#auto-disable-end");
+  const idleInput = root.querySelector("#auto-disable-idle");
+  const fullscreenCb = root.querySelector("#auto-disable-fullscreen-games");
+  const gameAppsInput = root.querySelector("#auto-disable-game-apps");
+  const saveBtn = root.querySelector("#auto-disable-save-btn");
+  if (!hoursCb || !startInput || !endInput || !idleInput || !saveBtn) return;
+  if (!fullscreenCb || !gameAppsInput) return;
+
+  invoke("get_auto_disable_schedule")
+    .then((schedule) => {
+      hoursCb.checked = !!schedule.hours;
+      if (schedule.hours) {
+        startInput.value = toTime(schedule.hours.start);
+        endInput.value = toTime(schedule.hours.end);
+      }
+      idleInput.value = schedule.idle_minutes || "";
+      fullscreenCb.checked = !!schedule.fullscreen_games;
+      gameAppsInput.value = (schedule.game_apps || []).join("\n");
+    })
+    .catch((e) => console.error("Failed to get auto-disable schedule:", e));
+
+  saveBtn.addEventListener("click", async () => {
+    try {
+      let hours = null;
+      if (hoursCb.checked) {
+        const start = toMinutes(startInput.value);
+        const end = toMinutes(endInput.value);
+        if (start === null || end === null) {
+          throw new Error("開始と終了の時刻を指定してください");
+        }
+        hours = { start, end };
+      }
+      const idle = parseInt(idleInput.value, 10);
+      await invoke("set_auto_disable_schedule", {
+        schedule: {
+          hours,
+          idle_minutes: Number.isFinite(idle) && idle > 0 ? idle : 0,
+          fullscreen_games: fullscreenCb.checked,
+          game_apps: gameAppsInput.value
+            .split("\n")
+            .map((line) => line.trim())
+            .filter((line) => line),
+        },
+      });
+      saveBtn.textContent = "保存しました";
+    } catch (e) {
+      alert(String(e.message ?? e));
+    }
+    setTimeout(() => {
+      saveBtn.textContent = "保存";
+    }, 1500);
+  });
+}
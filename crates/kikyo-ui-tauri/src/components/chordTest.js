@@ -0,0 +1,64 @@
+const MAX_LOG_ROWS = 30;
+
+function formatTest(event) {
+  const output = event.output ?? "(文字以外)";
+  const section = event.section ? ` [${event.section}]` : "";
+  const offsets =
+    event.press_offsets_ms.length > 1
+      ? `  押下差 ${event.press_offsets_ms.map((ms) => `${ms}ms`).join("/")}`
+      : "";
+  return `${event.label} → ${output}${section}${offsets}  確定 ${event.decided_after_ms}ms`;
+}
+
+export function mountChordTest(root, { invoke, listen }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const startBtn = root.querySelector("#chord-test-start-btn");
+  const stopBtn = root.querySelector("#chord-test-stop-btn");
+  const logEl = root.querySelector("#chord-test-log");
+  if (!startBtn || !stopBtn || !logEl) return;
+
+  let running = false;
+  const setRunning = (value) => {
+    running = value;
+    startBtn.disabled = value;
+    stopBtn.disabled = !value;
+  };
+
+  const stop = async () => {
+    if (!running) return;
+    setRunning(false);
+    try {
+      await invoke("end_chord_test");
+    } catch (e) {
+      console.error("Failed to end chord test:", e);
+    }
+  };
+
+  startBtn.addEventListener("click", async () => {
+    try {
+      await invoke("begin_chord_test");
+      logEl.replaceChildren();
+      setRunning(true);
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+  stopBtn.addEventListener("click", stop);
+  // Keys typed in other apps must not be swallowed. The engine also ends
+  // the test on its own when another app comes forward or keys stop.
+  window.addEventListener("blur", stop);
+  window.addEventListener("beforeunload", stop);
+  listen("chord-test-ended", () => setRunning(false));
+
+  listen("chord-test", (event) => {
+    const item = document.createElement("li");
+    item.textContent = formatTest(event.payload);
+    logEl.prepend(item);
+    while (logEl.children.length > MAX_LOG_ROWS) {
+      logEl.lastElementChild.remove();
+    }
+  });
+
+  setRunning(false);
+}
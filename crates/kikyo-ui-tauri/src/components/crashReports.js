@@ -0,0 +1,51 @@
+export function mountCrashReports(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const select = root.querySelector("#crash-report-select");
+  const sendBtn = root.querySelector("#crash-report-send-btn");
+  const folderBtn = root.querySelector("#crash-report-folder-btn");
+  if (!select || !sendBtn || !folderBtn) return;
+
+  const refresh = async () => {
+    try {
+      const reports = await invoke("list_crash_reports");
+      select.innerHTML = "";
+      if (reports.length === 0) {
+        const opt = document.createElement("option");
+        opt.value = "";
+        opt.textContent = "なし";
+        select.appendChild(opt);
+      }
+      for (const report of reports) {
+        const opt = document.createElement("option");
+        opt.value = report.file_name;
+        opt.textContent = report.message
+          ? `${report.file_name} (${report.message})`
+          : report.file_name;
+        select.appendChild(opt);
+      }
+      sendBtn.disabled = reports.length === 0;
+    } catch (e) {
+      console.error("Failed to list crash reports:", e);
+    }
+  };
+
+  sendBtn.addEventListener("click", async () => {
+    if (!select.value) return;
+    try {
+      await invoke("send_crash_report", { fileName: select.value });
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+
+  folderBtn.addEventListener("click", async () => {
+    try {
+      await invoke("open_crash_report_folder");
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+
+  refresh();
+}
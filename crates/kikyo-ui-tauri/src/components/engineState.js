@@ -0,0 +1,50 @@
+const POLL_INTERVAL_MS = 200;
+
+export function mountEngineState(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const liveCb = root.querySelector("#engine-state-live");
+  const outputEl = root.querySelector("#engine-state-output");
+  if (!liveCb || !outputEl) return;
+
+  let timer = null;
+
+  const formatLatch = (latch) => (latch.kind === "None" ? "なし" : `${latch.kind} ${latch.plane}`);
+  const formatKeys = (keys) => (keys.length ? keys.join(" ") : "なし");
+
+  const render = (state) => {
+    const pending = state.pending.map((p) =>
+      p.released_ms_ago == null
+        ? `${p.key} (${p.age_ms}ms)`
+        : `${p.key} (${p.age_ms}ms, 離して ${p.released_ms_ago}ms)`
+    );
+    const plans = state.repeat_plans.map((plan) => `${plan.key} → ${plan.keys.join("+")}`);
+    outputEl.textContent = [
+      `有効: ${state.enabled ? "はい" : "いいえ"}`,
+      `押下中: ${formatKeys(state.pressed)}`,
+      `保留中: ${pending.length ? pending.join(", ") : "なし"}`,
+      `ラッチ: ${formatLatch(state.latch)}`,
+      `前置シフト: ${state.prefix_pending ?? "なし"}`,
+      `使用済みシフト: ${formatKeys(state.used_modifiers)}`,
+      `リピート: ${plans.length ? plans.join(", ") : "なし"}`,
+    ].join("\n");
+  };
+
+  const poll = async () => {
+    try {
+      render(await invoke("get_engine_state"));
+    } catch (e) {
+      console.error("Failed to get engine state:", e);
+      outputEl.textContent = String(e);
+    }
+  };
+
+  liveCb.addEventListener("change", () => {
+    clearInterval(timer);
+    timer = null;
+    if (liveCb.checked) {
+      poll();
+      timer = setInterval(poll, POLL_INTERVAL_MS);
+    }
+  });
+}
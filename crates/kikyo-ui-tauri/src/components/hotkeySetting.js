@@ -0,0 +1,43 @@
+const MODIFIERS = ["ctrl", "alt", "shift", "win"];
+
+// Hotkey editor made of `#<prefix>-ctrl` ... `#<prefix>-win` checkboxes,
+// a `#<prefix>-key` select and a `#<prefix>-save-btn` button.
+export function mountHotkeySetting(root, { invoke }, { prefix, getCommand, setCommand }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const keySel = root.querySelector(`#${prefix}-key`);
+  const saveBtn = root.querySelector(`#${prefix}-save-btn`);
+  if (!keySel || !saveBtn) return;
+  const modifierCbs = Object.fromEntries(
+    MODIFIERS.map((name) => [name, root.querySelector(`#${prefix}-${name}`)]),
+  );
+  if (MODIFIERS.some((name) => !modifierCbs[name])) return;
+
+  invoke(getCommand)
+    .then((hotkey) => {
+      keySel.value = hotkey ? String(hotkey.vk) : "0";
+      MODIFIERS.forEach((name) => {
+        modifierCbs[name].checked = !!hotkey?.[name];
+      });
+    })
+    .catch((e) => console.error(`Failed to get hotkey (${getCommand}):`, e));
+
+  saveBtn.addEventListener("click", async () => {
+    const vk = parseInt(keySel.value, 10) || 0;
+    const hotkey = vk ? { vk } : null;
+    if (hotkey) {
+      MODIFIERS.forEach((name) => {
+        hotkey[name] = modifierCbs[name].checked;
+      });
+    }
+    try {
+      await invoke(setCommand, { hotkey });
+      saveBtn.textContent = "保存しました";
+    } catch (e) {
+      alert(String(e.message ?? e));
+    }
+    setTimeout(() => {
+      saveBtn.textContent = "保存";
+    }, 1500);
+  });
+}
@@ -0,0 +1,45 @@
+const MODES = ["ForceAlpha", "Ignore", "Imm", "Tsf"];
+
+function formatOverrides(overrides) {
+  return overrides.map((o) => `${o.exe} ${o.mode}`).join("\n");
+}
+
+function parseOverrides(text) {
+  const overrides = [];
+  for (const line of text.split("\n")) {
+    const trimmed = line.trim();
+    if (!trimmed) continue;
+    const match = trimmed.match(/^(.+?)\s+(\S+)$/);
+    if (!match || !MODES.includes(match[2])) {
+      throw new Error(`書式が正しくありません: ${trimmed}`);
+    }
+    overrides.push({ exe: match[1], mode: match[2] });
+  }
+  return overrides;
+}
+
+export function mountImeAppOverrides(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const listInput = root.querySelector("#ime-app-overrides");
+  const saveBtn = root.querySelector("#ime-app-overrides-save-btn");
+  if (!listInput || !saveBtn) return;
+
+  invoke("get_ime_app_overrides")
+    .then((overrides) => {
+      listInput.value = formatOverrides(overrides || []);
+    })
+    .catch((e) => console.error("Failed to get IME app overrides:", e));
+
+  saveBtn.addEventListener("click", async () => {
+    try {
+      await invoke("set_ime_app_overrides", { overrides: parseOverrides(listInput.value) });
+      saveBtn.textContent = "保存しました";
+    } catch (e) {
+      alert(String(e.message ?? e));
+    }
+    setTimeout(() => {
+      saveBtn.textContent = "保存";
+    }, 1500);
+  });
+}
@@ -0,0 +1,39 @@
+/** Time to switch to the window whose IME state should be read. */
+const DETECT_DELAY_MS = 3000;
+
+export function mountImeDebug(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const detectBtn = root.querySelector("#ime-debug-btn");
+  const outputEl = root.querySelector("#ime-debug-output");
+  if (!detectBtn || !outputEl) return;
+
+  const formatState = (state) => {
+    if (!state) return "応答なし";
+    const mode = state.conversion_mode == null ? "不明" : `0x${state.conversion_mode.toString(16)}`;
+    return `${state.open ? "オン" : "オフ"} (変換モード ${mode})`;
+  };
+
+  detectBtn.addEventListener("click", () => {
+    detectBtn.disabled = true;
+    outputEl.textContent = `${DETECT_DELAY_MS / 1000} 秒後に、前面のウィンドウの IME 状態を読み取ります…`;
+    setTimeout(async () => {
+      try {
+        const info = await invoke("get_ime_debug_info");
+        const lines = [
+          `アプリ: ${info.foreground_exe ?? "不明"}`,
+          `方式の設定: ${info.mode}${info.app_override ? " (アプリごとの設定)" : ""}`,
+          ...info.probes.map((probe) => `${probe.backend}: ${formatState(probe.state)}`),
+          `使用した方式: ${info.backend ?? "なし"}`,
+          `日本語入力: ${info.japanese_input_active ? "オン" : "オフ"}`,
+        ];
+        outputEl.textContent = lines.join("\n");
+      } catch (e) {
+        console.error("Failed to get IME debug info:", e);
+        outputEl.textContent = String(e);
+      } finally {
+        detectBtn.disabled = false;
+      }
+    }, DETECT_DELAY_MS);
+  });
+}
@@ -0,0 +1,59 @@
+const CAPTURE_TIMEOUT_MS = 10000;
+const CAPTURE_LABEL = "キー入力";
+const WAITING_LABEL = "キーを押してください…";
+
+export function mountKeyCapture(root, { invoke, listen }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const buttons = root.querySelectorAll(".capture-key-btn[data-target]");
+  if (buttons.length === 0) return;
+
+  let active = null;
+
+  const finish = () => {
+    if (!active) return;
+    clearTimeout(active.timer);
+    active.button.textContent = CAPTURE_LABEL;
+    active = null;
+  };
+
+  listen("key-captured", (event) => {
+    if (!active) return;
+    const select = document.getElementById(active.button.dataset.target);
+    const key = event.payload;
+    finish();
+    if (!select) return;
+    const value = key.thumb_key;
+    if (value && select.querySelector(`option[value="${value}"]`)) {
+      select.value = value;
+      select.dispatchEvent(new Event("change"));
+    } else {
+      alert(`このキーは指定できません: ${key.display}`);
+    }
+  });
+
+  buttons.forEach((button) => {
+    button.addEventListener("click", async () => {
+      if (active) {
+        const same = active.button === button;
+        finish();
+        await invoke("cancel_key_capture").catch(() => {});
+        if (same) return;
+      }
+      active = {
+        button,
+        timer: setTimeout(() => {
+          finish();
+          invoke("cancel_key_capture").catch(() => {});
+        }, CAPTURE_TIMEOUT_MS),
+      };
+      button.textContent = WAITING_LABEL;
+      try {
+        await invoke("capture_key");
+      } catch (e) {
+        console.error("Failed to start key capture:", e);
+        finish();
+      }
+    });
+  });
+}
@@ -0,0 +1,64 @@
+const STATUS_POLL_MS = 1000;
+
+export function mountKeyRecorder(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const minutesInput = root.querySelector("#key-record-minutes");
+  const startBtn = root.querySelector("#key-record-start-btn");
+  const stopBtn = root.querySelector("#key-record-stop-btn");
+  const statusEl = root.querySelector("#key-record-status");
+  if (!minutesInput || !startBtn || !stopBtn || !statusEl) return;
+
+  let timer = null;
+
+  const render = (status) => {
+    startBtn.disabled = !!status;
+    stopBtn.disabled = !status;
+    if (status) {
+      const seconds = Math.ceil(status.remaining_ms / 1000);
+      statusEl.textContent = `記録中 (残り ${Math.floor(seconds / 60)}分${seconds % 60}秒): ${status.path}`;
+    } else {
+      statusEl.textContent = "";
+      clearInterval(timer);
+      timer = null;
+    }
+  };
+
+  const poll = async () => {
+    try {
+      render(await invoke("get_key_recording_status"));
+    } catch (e) {
+      console.error("Failed to get key recording status:", e);
+    }
+  };
+
+  const startPolling = () => {
+    if (!timer) timer = setInterval(poll, STATUS_POLL_MS);
+  };
+
+  startBtn.addEventListener("click", async () => {
+    try {
+      const minutes = parseInt(minutesInput.value, 10);
+      const status = await invoke("start_key_recording", {
+        minutes: Number.isFinite(minutes) && minutes > 0 ? minutes : 5,
+      });
+      render(status);
+      startPolling();
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+
+  stopBtn.addEventListener("click", async () => {
+    try {
+      await invoke("stop_key_recording");
+    } catch (e) {
+      alert(String(e));
+    }
+    render(null);
+  });
+
+  poll().then(() => {
+    if (!stopBtn.disabled) startPolling();
+  });
+}
@@ -0,0 +1,50 @@
+const LOG_LINE_LIMIT = 300;
+
+export function mountLogViewer(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const levelSel = root.querySelector("#log-level");
+  const refreshBtn = root.querySelector("#log-refresh-btn");
+  const copyBtn = root.querySelector("#log-copy-btn");
+  const dirEl = root.querySelector("#log-dir");
+  const outputEl = root.querySelector("#log-output");
+  if (!levelSel || !refreshBtn || !copyBtn || !dirEl || !outputEl) return;
+
+  const refresh = async () => {
+    try {
+      const lines = await invoke("get_recent_logs", { limit: LOG_LINE_LIMIT });
+      outputEl.textContent = lines.join("\n");
+      outputEl.scrollTop = outputEl.scrollHeight;
+    } catch (e) {
+      console.error("Failed to get logs:", e);
+    }
+  };
+
+  invoke("get_log_info")
+    .then((info) => {
+      levelSel.value = info.level;
+      dirEl.textContent = info.dir ? `保存先: ${info.dir}` : "";
+    })
+    .catch((e) => console.error("Failed to get log info:", e));
+
+  levelSel.addEventListener("change", async () => {
+    try {
+      await invoke("set_log_level", { level: levelSel.value });
+    } catch (e) {
+      console.error("Failed to set log level:", e);
+    }
+  });
+
+  refreshBtn.addEventListener("click", refresh);
+  copyBtn.addEventListener("click", async () => {
+    try {
+      await navigator.clipboard.writeText(outputEl.textContent);
+      copyBtn.textContent = "コピーしました";
+    } catch (e) {
+      console.error("Failed to copy logs:", e);
+    }
+    setTimeout(() => {
+      copyBtn.textContent = "コピー";
+    }, 1500);
+  });
+}
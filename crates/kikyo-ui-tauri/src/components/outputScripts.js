@@ -0,0 +1,39 @@
+function describeRunning(running) {
+  return running.length ? `読み込み済み: ${running.join(", ")}` : "読み込まれたスクリプトはありません";
+}
+
+export function mountOutputScripts(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const enabledCb = root.querySelector("#output-scripts-enabled");
+  const reloadBtn = root.querySelector("#output-scripts-reload-btn");
+  const openBtn = root.querySelector("#output-scripts-open-btn");
+  const statusEl = root.querySelector("#output-scripts-status");
+  if (!enabledCb || !reloadBtn || !openBtn || !statusEl) return;
+
+  invoke("get_output_scripts")
+    .then((info) => {
+      enabledCb.checked = !!info.enabled;
+      statusEl.textContent = describeRunning(info.running || []);
+    })
+    .catch((e) => console.error("Failed to get output scripts:", e));
+
+  const apply = async () => {
+    try {
+      const running = await invoke("set_output_scripts_enabled", { enabled: enabledCb.checked });
+      statusEl.textContent = describeRunning(running);
+    } catch (e) {
+      statusEl.textContent = String(e);
+    }
+  };
+
+  enabledCb.addEventListener("change", apply);
+  reloadBtn.addEventListener("click", apply);
+  openBtn.addEventListener("click", async () => {
+    try {
+      await invoke("open_scripts_folder");
+    } catch (e) {
+      alert(String(e.message ?? e));
+    }
+  });
+}
@@ -0,0 +1,49 @@
+function formatApps(apps) {
+  return apps.map((app) => `${app.exe} ${app.delay_ms}`).join("\n");
+}
+
+function parseApps(text) {
+  const apps = [];
+  for (const line of text.split("\n")) {
+    const trimmed = line.trim();
+    if (!trimmed) continue;
+    const match = trimmed.match(/^(.+?)\s+(\d+)$/);
+    if (!match) throw new Error(`書式が正しくありません: ${trimmed}`);
+    apps.push({ exe: match[1], delay_ms: parseInt(match[2], 10) });
+  }
+  return apps;
+}
+
+export function mountOutputThrottle(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const delayInput = root.querySelector("#output-delay");
+  const appsInput = root.querySelector("#output-delay-apps");
+  const saveBtn = root.querySelector("#output-delay-save-btn");
+  if (!delayInput || !appsInput || !saveBtn) return;
+
+  invoke("get_output_throttle")
+    .then((throttle) => {
+      delayInput.value = throttle.delay_ms || "";
+      appsInput.value = formatApps(throttle.apps || []);
+    })
+    .catch((e) => console.error("Failed to get output throttle:", e));
+
+  saveBtn.addEventListener("click", async () => {
+    try {
+      const delay = parseInt(delayInput.value, 10);
+      await invoke("set_output_throttle", {
+        throttle: {
+          delay_ms: Number.isFinite(delay) && delay > 0 ? delay : 0,
+          apps: parseApps(appsInput.value),
+        },
+      });
+      saveBtn.textContent = "保存しました";
+    } catch (e) {
+      alert(String(e.message ?? e));
+    }
+    setTimeout(() => {
+      saveBtn.textContent = "保存";
+    }, 1500);
+  });
+}
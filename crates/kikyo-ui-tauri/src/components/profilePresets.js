@@ -0,0 +1,79 @@
+function renderPresetOptions(select, response) {
+  select.replaceChildren();
+  const none = document.createElement("option");
+  none.value = "";
+  none.textContent = "（未保存の設定）";
+  select.appendChild(none);
+  for (const preset of response.presets) {
+    const option = document.createElement("option");
+    option.value = preset.id;
+    option.textContent = preset.name;
+    select.appendChild(option);
+  }
+  select.value = response.active_preset_id ?? "";
+}
+
+export function mountProfilePresets(root, { invoke, listen, onApplied }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const select = root.querySelector("#profile-preset-select");
+  const nameInput = root.querySelector("#profile-preset-name");
+  const saveBtn = root.querySelector("#profile-preset-save-btn");
+  const deleteBtn = root.querySelector("#profile-preset-delete-btn");
+  const statusEl = root.querySelector("#profile-preset-status");
+  if (!select || !nameInput || !saveBtn || !deleteBtn || !statusEl) return;
+
+  const refresh = async () => {
+    try {
+      const response = await invoke("get_profile_presets");
+      renderPresetOptions(select, response);
+      deleteBtn.disabled = !select.value;
+      const active = response.presets.find((p) => p.id === response.active_preset_id);
+      if (active && !nameInput.value) nameInput.value = active.name;
+    } catch (e) {
+      console.error("Failed to get profile presets:", e);
+    }
+  };
+
+  select.addEventListener("change", async () => {
+    if (!select.value) return;
+    try {
+      await invoke("activate_profile_preset", { id: select.value });
+      statusEl.textContent = "";
+    } catch (e) {
+      statusEl.textContent = "プロファイルの切り替えに失敗しました: " + e;
+      refresh();
+    }
+  });
+
+  saveBtn.addEventListener("click", async () => {
+    try {
+      await invoke("save_profile_preset", { name: nameInput.value });
+      statusEl.textContent = "保存しました";
+      await refresh();
+    } catch (e) {
+      statusEl.textContent = "保存に失敗しました: " + e;
+    }
+  });
+
+  deleteBtn.addEventListener("click", async () => {
+    if (!select.value) return;
+    try {
+      await invoke("delete_profile_preset", { id: select.value });
+      nameInput.value = "";
+      statusEl.textContent = "";
+      await refresh();
+    } catch (e) {
+      statusEl.textContent = "削除に失敗しました: " + e;
+    }
+  });
+
+  // Fired when a preset is applied here or from the tray menu.
+  listen("profile-changed", async () => {
+    await refresh();
+    if (onApplied) onApplied();
+  });
+
+  window.addEventListener("focus", refresh);
+  refresh();
+}
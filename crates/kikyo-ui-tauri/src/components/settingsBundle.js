@@ -0,0 +1,91 @@
+const BUNDLE_FILTERS = [{ name: "桔梗の設定", extensions: ["kikyo"] }];
+
+function flash(btn, text, original) {
+  btn.textContent = text;
+  setTimeout(() => {
+    btn.textContent = original;
+  }, 1500);
+}
+
+export function mountSettingsBundle(root, { invoke, listen }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const exportBtn = root.querySelector("#settings-export-btn");
+  const importBtn = root.querySelector("#settings-import-btn");
+  const syncPathInput = root.querySelector("#settings-sync-path");
+  const syncChooseBtn = root.querySelector("#settings-sync-choose-btn");
+  const syncApplyBtn = root.querySelector("#settings-sync-apply-btn");
+  const syncPushBtn = root.querySelector("#settings-sync-push-btn");
+  const syncStatusEl = root.querySelector("#settings-sync-status");
+  if (!exportBtn || !importBtn || !syncPathInput || !syncChooseBtn) return;
+  if (!syncApplyBtn || !syncPushBtn || !syncStatusEl) return;
+
+  const { open, save } = window.__TAURI_PLUGIN_DIALOG__;
+
+  const refresh = async () => {
+    try {
+      const sync = await invoke("get_settings_sync");
+      syncPathInput.value = sync.path || "";
+      syncStatusEl.textContent = sync.last_unix_ms
+        ? `最終同期: ${new Date(sync.last_unix_ms).toLocaleString()}`
+        : "";
+    } catch (e) {
+      console.error("Failed to get settings sync:", e);
+    }
+  };
+
+  exportBtn.addEventListener("click", async () => {
+    try {
+      const path = await save({ defaultPath: "settings.kikyo", filters: BUNDLE_FILTERS });
+      if (typeof path !== "string") return;
+      await invoke("export_settings_bundle", { path });
+      flash(exportBtn, "保存しました", "書き出し");
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+
+  importBtn.addEventListener("click", async () => {
+    try {
+      const path = await open({ multiple: false, filters: BUNDLE_FILTERS });
+      if (typeof path !== "string") return;
+      if (!confirm("現在の設定を置き換えます。よろしいですか?")) return;
+      await invoke("import_settings_bundle", { path });
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+
+  syncChooseBtn.addEventListener("click", async () => {
+    const path = await save({
+      defaultPath: syncPathInput.value || "settings.kikyo",
+      filters: BUNDLE_FILTERS,
+    });
+    if (typeof path === "string") syncPathInput.value = path;
+  });
+
+  syncApplyBtn.addEventListener("click", async () => {
+    try {
+      await invoke("set_settings_sync_path", { path: syncPathInput.value || null });
+      await refresh();
+      flash(syncApplyBtn, "設定しました", "適用");
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+
+  syncPushBtn.addEventListener("click", async () => {
+    try {
+      await invoke("push_settings_sync");
+      await refresh();
+      flash(syncPushBtn, "書き出しました", "今すぐ書き出す");
+    } catch (e) {
+      alert(String(e));
+    }
+  });
+
+  // Every other panel reads its values on load, so start over.
+  listen("settings-imported", () => window.location.reload());
+
+  refresh();
+}
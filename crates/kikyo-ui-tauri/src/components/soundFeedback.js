@@ -0,0 +1,38 @@
+const EVENTS = ["tap", "chord", "misfire"];
+
+export function mountSoundFeedback(root, { invoke }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const volumeInput = root.querySelector("#sound-feedback-volume");
+  const saveBtn = root.querySelector("#sound-feedback-save-btn");
+  if (!volumeInput || !saveBtn) return;
+  const eventCbs = Object.fromEntries(
+    EVENTS.map((name) => [name, root.querySelector(`#sound-feedback-${name}`)]),
+  );
+  if (EVENTS.some((name) => !eventCbs[name])) return;
+
+  invoke("get_sound_feedback")
+    .then((feedback) => {
+      EVENTS.forEach((name) => {
+        eventCbs[name].checked = !!feedback[name];
+      });
+      volumeInput.value = feedback.volume;
+    })
+    .catch((e) => console.error("Failed to get sound feedback:", e));
+
+  saveBtn.addEventListener("click", async () => {
+    const feedback = { volume: parseInt(volumeInput.value, 10) || 0 };
+    EVENTS.forEach((name) => {
+      feedback[name] = eventCbs[name].checked;
+    });
+    try {
+      await invoke("set_sound_feedback", { feedback });
+      saveBtn.textContent = "保存しました";
+    } catch (e) {
+      alert(String(e.message ?? e));
+    }
+    setTimeout(() => {
+      saveBtn.textContent = "保存";
+    }, 1500);
+  });
+}
@@ -0,0 +1,112 @@
+const MODIFIERS = ["ctrl", "alt", "shift", "win"];
+const CAPTURE_TIMEOUT_MS = 10000;
+const CAPTURE_LABEL = "キー入力";
+const WAITING_LABEL = "キーを押してください…";
+const NO_KEY_LABEL = "なし";
+
+// Suspend key editor: `#suspend-key-display` shows the key, which
+// `#suspend-key-capture-btn` takes from the keyboard and
+// `#suspend-key-clear-btn` removes; `#suspend-key-ctrl` ... `-win` are the
+// modifiers held with it. Returns accessors for the profile's suspend_key.
+export function mountSuspendKeySetting(root, { invoke, listen }, { onChange }) {
+  if (!(root instanceof HTMLElement)) return null;
+
+  const display = root.querySelector("#suspend-key-display");
+  const captureBtn = root.querySelector("#suspend-key-capture-btn");
+  const clearBtn = root.querySelector("#suspend-key-clear-btn");
+  if (!display || !captureBtn || !clearBtn) return null;
+  const modifierCbs = Object.fromEntries(
+    MODIFIERS.map((name) => [name, root.querySelector(`#suspend-key-${name}`)]),
+  );
+  if (MODIFIERS.some((name) => !modifierCbs[name])) return null;
+
+  let key = null;
+  let names = new Map();
+  let capturing = null;
+
+  const keyId = ({ sc, ext }) => `${sc}:${ext}`;
+  const keyName = (k) =>
+    names.get(keyId(k)) ??
+    `${k.sc.toString(16).toUpperCase().padStart(2, "0")}${k.ext ? "e" : ""}`;
+  const render = () => {
+    display.textContent = key ? keyName(key) : NO_KEY_LABEL;
+    MODIFIERS.forEach((name) => {
+      modifierCbs[name].disabled = !key;
+    });
+  };
+
+  invoke("get_key_names")
+    .then((infos) => {
+      names = new Map(infos.map((info) => [keyId(info), info.display]));
+      render();
+    })
+    .catch((e) => console.error("Failed to get key names:", e));
+
+  const finishCapture = () => {
+    if (!capturing) return;
+    clearTimeout(capturing);
+    capturing = null;
+    captureBtn.textContent = CAPTURE_LABEL;
+  };
+
+  listen("key-captured", (event) => {
+    if (!capturing) return;
+    finishCapture();
+    const { sc, ext, display: name } = event.payload;
+    key = { sc, ext };
+    names.set(keyId(key), name);
+    render();
+    onChange();
+  });
+
+  captureBtn.addEventListener("click", async () => {
+    if (capturing) {
+      finishCapture();
+      await invoke("cancel_key_capture").catch(() => {});
+      return;
+    }
+    capturing = setTimeout(() => {
+      finishCapture();
+      invoke("cancel_key_capture").catch(() => {});
+    }, CAPTURE_TIMEOUT_MS);
+    captureBtn.textContent = WAITING_LABEL;
+    try {
+      await invoke("capture_key");
+    } catch (e) {
+      console.error("Failed to start key capture:", e);
+      finishCapture();
+    }
+  });
+
+  clearBtn.addEventListener("click", () => {
+    key = null;
+    MODIFIERS.forEach((name) => {
+      modifierCbs[name].checked = false;
+    });
+    render();
+    onChange();
+  });
+
+  MODIFIERS.forEach((name) => {
+    modifierCbs[name].addEventListener("change", onChange);
+  });
+
+  render();
+
+  return {
+    setValue(suspendKey) {
+      key = suspendKey?.key ?? null;
+      MODIFIERS.forEach((name) => {
+        modifierCbs[name].checked = !!suspendKey?.[name];
+      });
+      render();
+    },
+    value() {
+      const value = { key };
+      MODIFIERS.forEach((name) => {
+        value[name] = !!key && modifierCbs[name].checked;
+      });
+      return value;
+    },
+  };
+}
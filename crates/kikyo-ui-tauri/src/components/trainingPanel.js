@@ -0,0 +1,247 @@
+const REPORT_POLL_INTERVAL_MS = 1000;
+const MAX_CHORD_ROWS = 10;
+const MAX_LOG_ROWS = 20;
+
+function formatNumber(value, digits = 0) {
+  return Number.isFinite(value) ? value.toFixed(digits) : "-";
+}
+
+function renderProgress(el, report) {
+  el.replaceChildren();
+  if (!report) return;
+  const chars = Array.from(report.target).filter((c) => !/\s/.test(c));
+  chars.forEach((c, index) => {
+    const span = document.createElement("span");
+    span.textContent = c;
+    if (index < report.position) span.className = "training-done";
+    else if (index === report.position) span.className = "training-current";
+    el.appendChild(span);
+  });
+}
+
+function renderStats(el, report) {
+  if (!report) {
+    el.textContent = "";
+    return;
+  }
+  el.textContent =
+    `${report.position} / ${report.total}` +
+    `  CPM ${formatNumber(report.chars_per_minute)}` +
+    `  WPM ${formatNumber(report.wpm, 1)}` +
+    `  正確率 ${formatNumber(report.accuracy * 100, 1)}%` +
+    `  ミス ${report.errors}` +
+    (report.finished ? "  (完了)" : "");
+}
+
+function renderChordTable(el, report) {
+  el.replaceChildren();
+  if (!report) return;
+  const rows = report.chords.filter((c) => c.errors > 0).slice(0, MAX_CHORD_ROWS);
+  for (const chord of rows) {
+    const item = document.createElement("li");
+    item.textContent =
+      `${chord.label}: ${chord.errors}/${chord.attempts}` +
+      ` (${formatNumber(chord.error_rate * 100)}%)`;
+    el.appendChild(item);
+  }
+}
+
+function appendTraceLog(el, trace) {
+  if (trace.matched === null || trace.matched === undefined) return;
+  const item = document.createElement("li");
+  item.className = trace.matched ? "training-hit" : "training-miss";
+  item.textContent = trace.matched
+    ? `${trace.label} → ${trace.output}`
+    : `${trace.label} → ${trace.output}（期待: ${trace.expected}）`;
+  el.prepend(item);
+  while (el.children.length > MAX_LOG_ROWS) {
+    el.lastElementChild.remove();
+  }
+}
+
+async function exportUsageHeatmap(invoke, format) {
+  const { save } = window.__TAURI_PLUGIN_DIALOG__;
+  const path = await save({
+    defaultPath: `kikyo-usage.${format}`,
+    filters: [{ name: format.toUpperCase(), extensions: [format] }],
+  });
+  if (typeof path !== "string") return false;
+  await invoke("export_usage_heatmap", { path, format });
+  return true;
+}
+
+function mountUsageExport(root, invoke) {
+  const summaryEl = root.querySelector("#usage-summary");
+  const jsonBtn = root.querySelector("#usage-export-json-btn");
+  const csvBtn = root.querySelector("#usage-export-csv-btn");
+  const resetBtn = root.querySelector("#usage-reset-btn");
+  if (!summaryEl || !jsonBtn || !csvBtn || !resetBtn) return;
+
+  const refresh = async () => {
+    try {
+      const heatmap = await invoke("get_usage_heatmap");
+      summaryEl.textContent = `集計済みの出力: ${heatmap.total_outputs}`;
+    } catch (e) {
+      console.error("Failed to get usage heatmap:", e);
+    }
+  };
+
+  for (const [btn, format] of [[jsonBtn, "json"], [csvBtn, "csv"]]) {
+    btn.addEventListener("click", async () => {
+      try {
+        if (await exportUsageHeatmap(invoke, format)) {
+          summaryEl.textContent = "保存しました";
+        }
+      } catch (e) {
+        summaryEl.textContent = "保存に失敗しました: " + e;
+      }
+    });
+  }
+  resetBtn.addEventListener("click", async () => {
+    await invoke("reset_usage_stats");
+    refresh();
+  });
+
+  root.closest(".settings-section")?.addEventListener("focusin", refresh);
+  window.addEventListener("focus", refresh);
+  refresh();
+}
+
+const SUGGESTION_LIMIT = 10;
+
+function formatSuggestions(suggestions) {
+  const lines = ["続けて打つ2キー (同時打鍵なし):"];
+  for (const seq of suggestions.unchorded_sequences) {
+    lines.push(`  ${seq.label}  ${seq.count}回`);
+  }
+  if (suggestions.unchorded_sequences.length === 0) lines.push("  なし");
+  lines.push("", "消されることの多い同時打鍵:");
+  for (const chord of suggestions.undone_chords) {
+    const rate = Math.round(chord.rate * 100);
+    lines.push(`  ${chord.label}  ${chord.undone}/${chord.count}回 (${rate}%)`);
+  }
+  if (suggestions.undone_chords.length === 0) lines.push("  なし");
+  return lines.join("\n");
+}
+
+function mountLayoutSuggestions(root, invoke) {
+  const btn = root.querySelector("#layout-suggestions-btn");
+  const outputEl = root.querySelector("#layout-suggestions-output");
+  if (!btn || !outputEl) return;
+
+  btn.addEventListener("click", async () => {
+    try {
+      const suggestions = await invoke("get_layout_suggestions", {
+        limit: SUGGESTION_LIMIT,
+      });
+      outputEl.textContent = formatSuggestions(suggestions);
+    } catch (e) {
+      outputEl.textContent = String(e);
+    }
+  });
+}
+
+function formatCoverage(coverage) {
+  if (!coverage) return "配列定義が読み込まれていません。";
+  const lines = ["セクションごとの定義数:"];
+  for (const section of coverage.sections) {
+    const planes = section.planes
+      .map((plane) => `${plane.tag ?? "単独"} ${plane.defined.length}`)
+      .join(", ");
+    lines.push(`  [${section.name}]  ${planes}`);
+  }
+  lines.push("", "足りないセクション:");
+  for (const name of coverage.missing_sections) lines.push(`  [${name}]`);
+  if (coverage.missing_sections.length === 0) lines.push("  なし");
+  lines.push("", "単独打鍵が定義されていないキーを使う面:");
+  for (const entry of coverage.undefined_tag_keys) {
+    lines.push(`  [${entry.section}] ${entry.tag}  ${entry.keys.join(", ")}`);
+  }
+  if (coverage.undefined_tag_keys.length === 0) lines.push("  なし");
+  return lines.join("\n");
+}
+
+function mountLayoutCoverage(root, invoke) {
+  const btn = root.querySelector("#layout-coverage-btn");
+  const templateSel = root.querySelector("#layout-coverage-template");
+  const outputEl = root.querySelector("#layout-coverage-output");
+  if (!btn || !templateSel || !outputEl) return;
+
+  btn.addEventListener("click", async () => {
+    try {
+      const coverage = await invoke("get_layout_coverage", {
+        template: templateSel.value,
+      });
+      outputEl.textContent = formatCoverage(coverage);
+    } catch (e) {
+      outputEl.textContent = String(e);
+    }
+  });
+}
+
+export function mountTrainingPanel(root, { invoke, listen }) {
+  if (!(root instanceof HTMLElement)) return;
+
+  const targetInput = root.querySelector("#training-target");
+  const startBtn = root.querySelector("#training-start-btn");
+  const stopBtn = root.querySelector("#training-stop-btn");
+  const progressEl = root.querySelector("#training-progress");
+  const statsEl = root.querySelector("#training-stats");
+  const chordsEl = root.querySelector("#training-chords");
+  const logEl = root.querySelector("#training-log");
+  let pollTimer = null;
+
+  const render = (report) => {
+    renderProgress(progressEl, report);
+    renderStats(statsEl, report);
+    renderChordTable(chordsEl, report);
+  };
+
+  const refresh = async () => {
+    try {
+      const report = await invoke("get_training_report");
+      if (report) render(report);
+    } catch (e) {
+      console.error("Failed to get training report:", e);
+    }
+  };
+
+  const setRunning = (running) => {
+    startBtn.disabled = running;
+    stopBtn.disabled = !running;
+    targetInput.disabled = running;
+    if (pollTimer) clearInterval(pollTimer);
+    pollTimer = running ? setInterval(refresh, REPORT_POLL_INTERVAL_MS) : null;
+  };
+
+  startBtn.addEventListener("click", async () => {
+    try {
+      await invoke("start_training", { target: targetInput.value });
+      logEl.replaceChildren();
+      setRunning(true);
+      await refresh();
+    } catch (e) {
+      statsEl.textContent = String(e);
+    }
+  });
+
+  stopBtn.addEventListener("click", async () => {
+    try {
+      const report = await invoke("stop_training");
+      render(report);
+    } catch (e) {
+      console.error("Failed to stop training:", e);
+    }
+    setRunning(false);
+  });
+
+  listen("engine-trace", (event) => {
+    appendTraceLog(logEl, event.payload);
+    refresh();
+  });
+
+  setRunning(false);
+  mountUsageExport(root, invoke);
+  mountLayoutSuggestions(root, invoke);
+  mountLayoutCoverage(root, invoke);
+}
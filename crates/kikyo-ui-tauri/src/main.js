@@ -1,4 +1,21 @@
 import { mountAboutContributors } from "./components/aboutContributors.js";
+import { mountAutoDisable } from "./components/autoDisable.js";
+import { mountChordTest } from "./components/chordTest.js";
+import { mountLogViewer } from "./components/logViewer.js";
+import { mountOutputScripts } from "./components/outputScripts.js";
+import { mountOutputThrottle } from "./components/outputThrottle.js";
+import { mountHotkeySetting } from "./components/hotkeySetting.js";
+import { mountCrashReports } from "./components/crashReports.js";
+import { mountEngineState } from "./components/engineState.js";
+import { mountImeAppOverrides } from "./components/imeAppOverrides.js";
+import { mountImeDebug } from "./components/imeDebug.js";
+import { mountKeyCapture } from "./components/keyCapture.js";
+import { mountKeyRecorder } from "./components/keyRecorder.js";
+import { mountProfilePresets } from "./components/profilePresets.js";
+import { mountSettingsBundle } from "./components/settingsBundle.js";
+import { mountSoundFeedback } from "./components/soundFeedback.js";
+import { mountSuspendKeySetting } from "./components/suspendKeySetting.js";
+import { mountTrainingPanel } from "./components/trainingPanel.js";
 
 const { invoke } = window.__TAURI__.core;
 const { listen } = window.__TAURI__.event;
@@ -6,11 +23,14 @@ let globalEnabledCb;
 let statusMsg;
 
 // Elements
-let layoutEntryListEl, addLayoutEntryBtn;
+let layoutEntryListEl, addLayoutEntryBtn, layoutEntryFilterSel;
 let layoutEntries = [];
+let layoutEntryFilter = "";
+const LAYOUT_FILTER_FAVORITES = "__favorites__";
 let activeLayoutEntryId = null;
 let layoutPointerDragState = null;
 const DUPLICATE_LAYOUT_ALERT_MESSAGE = "\u3059\u3067\u306b\u767b\u9332\u3055\u308c\u3066\u3044\u308b\u5b9a\u7fa9\u30d5\u30a1\u30a4\u30eb\u3067\u3059";
+const DUPLICATE_LAYOUT_ERROR_MESSAGE_EN = "This layout file is already registered";
 
 // Sidebar
 let navItems, sections;
@@ -37,13 +57,33 @@ let extThumb2KeySel, extThumb2ContinuousCb, extThumb2SinglePressSel, extThumb2Re
 let extThumb2RepeatLabel;
 
 // Thumb Common
-let thumbOverlapRatioInput, thumbOverlapVal;
+let thumbOverlapRatioInput, thumbOverlapVal, thumbDoubleTapMsInput, prefixShiftTimeoutInput;
+let sandsEnabledCb, sandsSideSel, sandsTapTimeoutInput, sandsRepeatCb;
+let dualRoleKeysInput;
+// Per-thumb timing (empty input = use the common setting)
+const THUMB_TIMING_FIELDS = [
+  { profileKey: "thumb_left", side: "left" },
+  { profileKey: "thumb_right", side: "right" },
+];
+const THUMB_DOUBLE_TAP_FIELDS = [
+  { profileKey: "thumb_left", id: "thumb-left-double-tap" },
+  { profileKey: "thumb_right", id: "thumb-right-double-tap" },
+  { profileKey: "extended_thumb1", id: "ext-thumb-1-double-tap" },
+  { profileKey: "extended_thumb2", id: "ext-thumb-2-double-tap" },
+];
 
 // Chord
 let charContinuousCb, charOverlapRatioInput, charOverlapVal;
+// Number-row shift (ChordStyle::NNumberKey)
+let numberKeyEnabledCb, numberKeyContinuousCb, numberKeyOverlapRatioInput, numberKeyMinOverlapInput;
+let pinkyShiftEnabledCb, pinkyShiftContinuousCb, pinkyShiftOverlapRatioInput, pinkyShiftMinOverlapInput;
 
 // Operation
-let imeModeSel, suspendKeySel;
+let imeModeSel, suspendKeySetting, capsLockCompensationCb, pasteGraphemeClustersCb, pasteMinCharsInput;
+let outputRulesInput, injectionChunkSizeInput, injectionChunkDelayInput;
+let focusChangeSel, imeCompositionSel, undefinedKeySel, spaceWidthSel, deferredEnterTimeoutInput;
+let decisionModeSel, chordWindowInput, longPressMsInput, passThroughKeysInput, keySwapsInput;
+let panicKeyEnabledCb, panicKeySel, panicKeyHoldInput;
 
 async function openLayoutFileDialog(defaultPath = null) {
   const { open } = window.__TAURI_PLUGIN_DIALOG__;
@@ -63,9 +103,60 @@ function normalizeLayoutEntry(entry) {
     id: entry?.id ?? "",
     alias: entry?.alias ?? "",
     path: entry?.path ?? "",
+    info: entry?.info ?? {},
+    loadError: entry?.load_error ?? null,
+    tags: Array.isArray(entry?.tags) ? entry.tags : [],
+    favorite: !!entry?.favorite,
   };
 }
 
+function parseLayoutTags(text) {
+  return text.split(/[,、]/).map((tag) => tag.trim()).filter((tag) => tag);
+}
+
+function layoutEntryMatchesFilter(entry) {
+  if (!layoutEntryFilter) return true;
+  if (layoutEntryFilter === LAYOUT_FILTER_FAVORITES) return entry.favorite;
+  return entry.tags.includes(layoutEntryFilter);
+}
+
+function renderLayoutEntryFilter() {
+  if (!layoutEntryFilterSel) return;
+  const tags = [...new Set(layoutEntries.flatMap((entry) => entry.tags))].sort();
+  if (layoutEntryFilter && layoutEntryFilter !== LAYOUT_FILTER_FAVORITES && !tags.includes(layoutEntryFilter)) {
+    layoutEntryFilter = "";
+  }
+  layoutEntryFilterSel.innerHTML = "";
+  const options = [["", "すべて"], [LAYOUT_FILTER_FAVORITES, "\u2605 お気に入り"]];
+  tags.forEach((tag) => options.push([tag, `#${tag}`]));
+  options.forEach(([value, label]) => {
+    const opt = document.createElement("option");
+    opt.value = value;
+    opt.textContent = label;
+    layoutEntryFilterSel.appendChild(opt);
+  });
+  layoutEntryFilterSel.value = layoutEntryFilter;
+}
+
+async function saveLayoutEntryTags(entry) {
+  try {
+    await invoke("set_layout_tags", { id: entry.id, tags: entry.tags, favorite: entry.favorite });
+    renderLayoutEntryFilter();
+    renderLayoutEntryList();
+  } catch (e) {
+    statusMsg.innerText = "タグの保存に失敗しました: " + e;
+  }
+}
+
+function layoutInfoTooltip(info) {
+  const lines = [];
+  if (info.author) lines.push(`作者: ${info.author}`);
+  if (info.version) lines.push(`バージョン: ${info.version}`);
+  if (info.description) lines.push(info.description);
+  if (info.url) lines.push(info.url);
+  return lines.join("\n");
+}
+
 function normalizeLayoutPathForCompare(path) {
   const trimmed = String(path ?? "").trim();
   const slashNormalized = trimmed.replace(/\\/g, "/");
@@ -80,7 +171,7 @@ function isDuplicateLayoutPath(path) {
 
 function isDuplicateLayoutPathError(error) {
   const text = String(error ?? "");
-  return text.includes(DUPLICATE_LAYOUT_ALERT_MESSAGE);
+  return text.includes(DUPLICATE_LAYOUT_ALERT_MESSAGE) || text.includes(DUPLICATE_LAYOUT_ERROR_MESSAGE_EN);
 }
 
 function moveLayoutEntryInMemory(draggedId, targetId) {
@@ -148,13 +239,35 @@ async function persistLayoutEntryOrder() {
   }
 }
 
+function describeKeySwapIssue(issue) {
+  switch (issue.kind) {
+    case "UnknownKey":
+      return `キー名「${issue.name}」がありません`;
+    case "LockAsSource":
+      return `${issue.name} は入れ替え先にしか指定できません`;
+    case "Conflict":
+      return `${issue.from} が 2 回指定されています (${issue.ignored} ではなく ${issue.used} を使います)`;
+    case "Cycle":
+      return `${issue.shift ? "Shift を押しているとき、" : ""}${issue.keys.join(" → ")} が循環しているため入れ替わりません`;
+    default:
+      return issue.kind;
+  }
+}
+
+// `message` with any problems in the key swaps appended.
+async function withKeySwapIssues(message) {
+  const issues = await invoke("get_key_swap_issues").catch(() => []);
+  if (issues.length === 0) return message;
+  return `${message} (キーの入れ替え: ${issues.map(describeKeySwapIssue).join(" / ")})`;
+}
+
 async function activateLayoutEntry(entryId) {
   if (!entryId) return;
   try {
     statusMsg.innerText = "\u8aad\u307f\u8fbc\u307f\u4e2d...";
     const res = await invoke("activate_layout_entry", { id: entryId });
     activeLayoutEntryId = entryId;
-    statusMsg.innerText = "\u914d\u5217\u5b9a\u7fa9\u3092\u8aad\u307f\u8fbc\u307f\u307e\u3057\u305f";
+    statusMsg.innerText = await withKeySwapIssues("\u914d\u5217\u5b9a\u7fa9\u3092\u8aad\u307f\u8fbc\u307f\u307e\u3057\u305f");
     renderLayoutEntryList();
   } catch (e) {
     console.error("activate_layout_entry error:", e);
@@ -194,6 +307,11 @@ function buildLayoutEntryRow(entry) {
   const row = document.createElement("div");
   row.className = "layout-entry-row";
   row.dataset.entryId = entry.id;
+  row.title = layoutInfoTooltip(entry.info);
+  if (entry.loadError) {
+    row.classList.add("is-broken");
+    row.title = `読み込めません: ${entry.loadError}`;
+  }
 
   const handle = document.createElement("div");
   handle.className = "layout-entry-handle";
@@ -282,6 +400,27 @@ function buildLayoutEntryRow(entry) {
   aliasInput.placeholder = "Alias";
   aliasInput.value = entry.alias || "";
 
+  const favoriteBtn = document.createElement("button");
+  favoriteBtn.type = "button";
+  favoriteBtn.className = "layout-entry-favorite-btn";
+  favoriteBtn.classList.toggle("is-favorite", entry.favorite);
+  favoriteBtn.textContent = entry.favorite ? "\u2605" : "\u2606";
+  favoriteBtn.title = "お気に入り";
+  favoriteBtn.addEventListener("click", async () => {
+    entry.favorite = !entry.favorite;
+    await saveLayoutEntryTags(entry);
+  });
+
+  const tagsInput = document.createElement("input");
+  tagsInput.type = "text";
+  tagsInput.className = "layout-entry-tags";
+  tagsInput.placeholder = "タグ (カンマ区切り)";
+  tagsInput.value = entry.tags.join(", ");
+  tagsInput.addEventListener("change", async () => {
+    entry.tags = parseLayoutTags(tagsInput.value);
+    await saveLayoutEntryTags(entry);
+  });
+
   const pathRow = document.createElement("div");
   pathRow.className = "layout-entry-path-row";
 
@@ -329,7 +468,9 @@ function buildLayoutEntryRow(entry) {
   });
 
   top.appendChild(radio);
+  top.appendChild(favoriteBtn);
   top.appendChild(aliasInput);
+  top.appendChild(tagsInput);
   pathRow.appendChild(pathInput);
   pathRow.appendChild(browseBtn);
   main.appendChild(top);
@@ -354,7 +495,9 @@ function renderLayoutEntryList() {
   }
 
   layoutEntries.forEach((entry) => {
-    layoutEntryListEl.appendChild(buildLayoutEntryRow(entry));
+    const row = buildLayoutEntryRow(entry);
+    row.hidden = !layoutEntryMatchesFilter(entry);
+    layoutEntryListEl.appendChild(row);
   });
 }
 
@@ -363,6 +506,7 @@ async function refreshLayoutEntries() {
     const res = await invoke("get_layout_entries");
     layoutEntries = (res?.entries || []).map(normalizeLayoutEntry);
     activeLayoutEntryId = res?.active_layout_id || null;
+    renderLayoutEntryFilter();
     renderLayoutEntryList();
   } catch (e) {
     statusMsg.innerText = "鬯ｯ・ｯ繝ｻ・ｩ髮倶ｼ∝ｱｮ繝ｻ・ｽ繝ｻ・ｦ鬩怜遜・ｽ・ｫ驛｢譎｢・ｽ・ｻ鬯ｮ・｣陋ｹ繝ｻ・ｽ・ｽ繝ｻ・ｳ郢晢ｽｻ邵ｺ・､・つ鬯ｯ・ｮ繝ｻ・ｫ髯具ｽｹ郢晢ｽｻ繝ｻ・ｽ繝ｻ・ｽ郢晢ｽｻ繝ｻ・ｧ鬯ｮ・ｯ繝ｻ・ｷ郢晢ｽｻ繝ｻ・ｿ鬯ｯ・ｮ繝ｻ・｢繝ｻ縺､ﾂ驛｢譎｢・ｽ・ｻ郢晢ｽｻ繝ｻ・ｾ鬮ｯ・ｷ闔ｨ螟ｲ・ｽ・ｽ繝ｻ・ｱ鬩搾ｽｵ繝ｻ・ｺ鬯ｯ蛟ｩ・ｲ・ｻ繝ｻ・ｽ繝ｻ・ｹ髫ｴ雜｣・ｽ・｢郢晢ｽｻ繝ｻ・ｽ郢晢ｽｻ繝ｻ・ｩ鬯ｩ蟷｢・ｽ・｢髫ｴ雜｣・ｽ・｢郢晢ｽｻ繝ｻ・ｽ郢晢ｽｻ繝ｻ・ｼ: " + e;
@@ -380,7 +524,7 @@ async function addLayoutEntry() {
     const wasEmpty = layoutEntries.length === 0;
     const created = await invoke("create_layout_entry_from_path", { path: selected }).catch((e) => {
       if (isDuplicateLayoutPathError(e)) {
-        window.alert(DUPLICATE_LAYOUT_ALERT_MESSAGE);
+        window.alert(String(e));
         return null;
       }
       throw e;
@@ -514,11 +658,48 @@ function updateUI(profile) {
 
   // Common
   if (imeModeSel) imeModeSel.value = profile.ime_mode || "Auto";
-  if (suspendKeySel) suspendKeySel.value = profile.suspend_key || "None";
+  if (suspendKeySetting) suspendKeySetting.setValue(profile.suspend_key);
+  const panicKey = profile.panic_key || {};
+  if (panicKeyEnabledCb) panicKeyEnabledCb.checked = panicKey.enabled ?? true;
+  if (panicKeySel) panicKeySel.value = panicKey.key || "F12";
+  if (panicKeyHoldInput) panicKeyHoldInput.value = panicKey.hold_ms ?? 2000;
+  if (capsLockCompensationCb) {
+    capsLockCompensationCb.checked = profile.caps_lock_compensation ?? true;
+  }
+  if (pasteGraphemeClustersCb) {
+    pasteGraphemeClustersCb.checked = profile.paste_grapheme_clusters ?? false;
+  }
+  if (pasteMinCharsInput) pasteMinCharsInput.value = profile.paste_min_chars ?? "";
+  const injection = profile.injection || {};
+  if (injectionChunkSizeInput) injectionChunkSizeInput.value = injection.chunk_size || "";
+  if (injectionChunkDelayInput) injectionChunkDelayInput.value = injection.chunk_delay_ms || "";
+  if (outputRulesInput) {
+    outputRulesInput.value = (profile.output_rules || [])
+      .map((rule) => (rule.to ? `${rule.from} ${rule.to}` : rule.from))
+      .join("\n");
+  }
+  if (focusChangeSel) focusChangeSel.value = profile.focus_change || "Discard";
+  if (imeCompositionSel) imeCompositionSel.value = profile.ime_composition || "Ignore";
+  if (undefinedKeySel) undefinedKeySel.value = profile.undefined_key || "Pass";
+  if (passThroughKeysInput) {
+    passThroughKeysInput.value = (profile.pass_through_keys || []).join(" ");
+  }
+  if (keySwapsInput) {
+    keySwapsInput.value = (profile.key_swaps || [])
+      .map((swap) => `${swap.from} ${swap.to}`)
+      .join("\n");
+  }
+  if (spaceWidthSel) spaceWidthSel.value = profile.space_width || "Ime";
+  if (deferredEnterTimeoutInput) {
+    deferredEnterTimeoutInput.value = profile.deferred_enter_timeout_ms ?? 500;
+  }
+  if (decisionModeSel) decisionModeSel.value = profile.decision_mode || "OverlapRatio";
+  if (chordWindowInput) chordWindowInput.value = profile.chord_window_ms ?? 200;
+  if (longPressMsInput) longPressMsInput.value = profile.long_press_ms ?? 500;
 
   // Ranges
   if (thumbOverlapRatioInput) {
-    const val = Math.round(profile.thumb_shift_overlap_ratio * 100);
+    const val = Math.round((profile.thumb_overlap_ratio ?? profile.char_key_overlap_ratio) * 100);
     thumbOverlapRatioInput.value = val;
     if (thumbOverlapVal) thumbOverlapVal.innerText = val + "%";
   }
@@ -528,6 +709,57 @@ function updateUI(profile) {
     if (charOverlapVal) charOverlapVal.innerText = val + "%";
   }
 
+  // Per-thumb timing
+  if (thumbDoubleTapMsInput) thumbDoubleTapMsInput.value = profile.double_tap_ms ?? 300;
+  if (prefixShiftTimeoutInput) prefixShiftTimeoutInput.value = profile.prefix_shift_timeout_ms ?? 0;
+  for (const { profileKey, id } of THUMB_DOUBLE_TAP_FIELDS) {
+    const sel = document.getElementById(id);
+    if (sel) sel.value = profile[profileKey]?.double_tap || "";
+  }
+
+  for (const { profileKey, side } of THUMB_TIMING_FIELDS) {
+    const cfg = profile[profileKey] || {};
+    const ratioInput = document.querySelector(`#thumb-${side}-overlap-ratio`);
+    const minInput = document.querySelector(`#thumb-${side}-min-overlap`);
+    if (ratioInput) {
+      ratioInput.value =
+        typeof cfg.overlap_ratio === "number" ? Math.round(cfg.overlap_ratio * 100) : "";
+    }
+    if (minInput) {
+      minInput.value = typeof cfg.min_overlap_ms === "number" ? cfg.min_overlap_ms : "";
+    }
+  }
+
+  // SandS
+  const sands = profile.sands || {};
+  if (sandsEnabledCb) sandsEnabledCb.checked = !!sands.enabled;
+  if (sandsSideSel) sandsSideSel.value = sands.side || "Right";
+  if (sandsTapTimeoutInput) sandsTapTimeoutInput.value = sands.tap_timeout_ms ?? 200;
+  if (sandsRepeatCb) sandsRepeatCb.checked = !!sands.repeat;
+  if (dualRoleKeysInput) {
+    dualRoleKeysInput.value = (profile.dual_role_keys || [])
+      .map((cfg) => `${cfg.key} ${cfg.modifier} ${cfg.hold_ms}`)
+      .join("\n");
+  }
+
+  // Number-row shift
+  const numberKey = profile.number_key || {};
+  if (numberKeyEnabledCb) numberKeyEnabledCb.checked = profile.chord_style === "NNumberKey";
+  if (numberKeyContinuousCb) numberKeyContinuousCb.checked = !!numberKey.continuous;
+  if (numberKeyOverlapRatioInput) {
+    numberKeyOverlapRatioInput.value = Math.round((numberKey.overlap_ratio ?? 0.35) * 100);
+  }
+  if (numberKeyMinOverlapInput) numberKeyMinOverlapInput.value = numberKey.min_overlap_ms ?? 0;
+
+  // Shift judged by overlap
+  const pinkyShift = profile.pinky_shift || {};
+  if (pinkyShiftEnabledCb) pinkyShiftEnabledCb.checked = !!pinkyShift.enabled;
+  if (pinkyShiftContinuousCb) pinkyShiftContinuousCb.checked = pinkyShift.continuous ?? true;
+  if (pinkyShiftOverlapRatioInput) {
+    pinkyShiftOverlapRatioInput.value = Math.round((pinkyShift.overlap_ratio ?? 0.35) * 100);
+  }
+  if (pinkyShiftMinOverlapInput) pinkyShiftMinOverlapInput.value = pinkyShift.min_overlap_ms ?? 0;
+
   syncThumbRepeatUI("left");
   syncThumbRepeatUI("right");
   syncThumbRepeatUI("ext1");
@@ -600,22 +832,154 @@ async function saveProfile() {
 
   // Common
   if (thumbOverlapRatioInput) {
-    currentProfile.thumb_shift_overlap_ratio =
+    currentProfile.thumb_overlap_ratio =
       parseInt(thumbOverlapRatioInput.value, 10) / 100.0;
   }
 
+  if (thumbDoubleTapMsInput) {
+    const interval = parseInt(thumbDoubleTapMsInput.value, 10);
+    currentProfile.double_tap_ms = Number.isFinite(interval) && interval >= 0 ? interval : 300;
+  }
+  if (prefixShiftTimeoutInput) {
+    const timeout = parseInt(prefixShiftTimeoutInput.value, 10);
+    currentProfile.prefix_shift_timeout_ms = Number.isFinite(timeout) && timeout >= 0 ? timeout : 0;
+  }
+  for (const { profileKey, id } of THUMB_DOUBLE_TAP_FIELDS) {
+    const sel = document.getElementById(id);
+    if (sel && currentProfile[profileKey]) currentProfile[profileKey].double_tap = sel.value;
+  }
+
+  for (const { profileKey, side } of THUMB_TIMING_FIELDS) {
+    const cfg = currentProfile[profileKey];
+    if (!cfg) continue;
+    const ratio = parseInt(document.querySelector(`#thumb-${side}-overlap-ratio`)?.value, 10);
+    const minOverlap = parseInt(document.querySelector(`#thumb-${side}-min-overlap`)?.value, 10);
+    cfg.overlap_ratio = Number.isFinite(ratio) ? Math.min(Math.max(ratio, 0), 100) / 100.0 : null;
+    cfg.min_overlap_ms = Number.isFinite(minOverlap) && minOverlap >= 0 ? minOverlap : null;
+  }
+
+  if (!currentProfile.sands) currentProfile.sands = {};
+  if (sandsEnabledCb) currentProfile.sands.enabled = sandsEnabledCb.checked;
+  if (sandsSideSel) currentProfile.sands.side = sandsSideSel.value;
+  if (sandsTapTimeoutInput) {
+    const timeout = parseInt(sandsTapTimeoutInput.value, 10);
+    currentProfile.sands.tap_timeout_ms = Number.isFinite(timeout) && timeout >= 0 ? timeout : 200;
+  }
+  if (sandsRepeatCb) currentProfile.sands.repeat = sandsRepeatCb.checked;
+  if (dualRoleKeysInput) {
+    currentProfile.dual_role_keys = dualRoleKeysInput.value
+      .split("\n")
+      .map((line) => line.trim().split(/\s+/))
+      .filter(([key, modifier]) => key && ["Ctrl", "Shift", "Alt", "Win"].includes(modifier))
+      .map(([key, modifier, holdMs]) => {
+        const hold = parseInt(holdMs, 10);
+        return { key, modifier, hold_ms: Number.isFinite(hold) && hold >= 0 ? hold : 200 };
+      });
+  }
+
   if (charContinuousCb) currentProfile.char_key_continuous = charContinuousCb.checked;
   if (charOverlapRatioInput) {
     currentProfile.char_key_overlap_ratio =
       parseInt(charOverlapRatioInput.value, 10) / 100.0;
   }
+  if (numberKeyEnabledCb) {
+    if (numberKeyEnabledCb.checked) {
+      currentProfile.chord_style = "NNumberKey";
+    } else if (currentProfile.chord_style === "NNumberKey") {
+      currentProfile.chord_style = "TriggerKey";
+    }
+  }
+  if (!currentProfile.number_key) currentProfile.number_key = {};
+  if (numberKeyContinuousCb) currentProfile.number_key.continuous = numberKeyContinuousCb.checked;
+  if (numberKeyOverlapRatioInput) {
+    const ratio = parseInt(numberKeyOverlapRatioInput.value, 10);
+    currentProfile.number_key.overlap_ratio =
+      Number.isFinite(ratio) ? Math.min(Math.max(ratio, 0), 100) / 100.0 : 0.35;
+  }
+  if (numberKeyMinOverlapInput) {
+    const minOverlap = parseInt(numberKeyMinOverlapInput.value, 10);
+    currentProfile.number_key.min_overlap_ms =
+      Number.isFinite(minOverlap) && minOverlap >= 0 ? minOverlap : 0;
+  }
+  if (!currentProfile.pinky_shift) currentProfile.pinky_shift = {};
+  if (pinkyShiftEnabledCb) currentProfile.pinky_shift.enabled = pinkyShiftEnabledCb.checked;
+  if (pinkyShiftContinuousCb) currentProfile.pinky_shift.continuous = pinkyShiftContinuousCb.checked;
+  if (pinkyShiftOverlapRatioInput) {
+    const ratio = parseInt(pinkyShiftOverlapRatioInput.value, 10);
+    currentProfile.pinky_shift.overlap_ratio =
+      Number.isFinite(ratio) ? Math.min(Math.max(ratio, 0), 100) / 100.0 : 0.35;
+  }
+  if (pinkyShiftMinOverlapInput) {
+    const minOverlap = parseInt(pinkyShiftMinOverlapInput.value, 10);
+    currentProfile.pinky_shift.min_overlap_ms =
+      Number.isFinite(minOverlap) && minOverlap >= 0 ? minOverlap : 0;
+  }
   if (imeModeSel) currentProfile.ime_mode = imeModeSel.value;
-  if (suspendKeySel) currentProfile.suspend_key = suspendKeySel.value;
+  if (suspendKeySetting) currentProfile.suspend_key = suspendKeySetting.value();
+  if (!currentProfile.panic_key) currentProfile.panic_key = {};
+  if (panicKeyEnabledCb) currentProfile.panic_key.enabled = panicKeyEnabledCb.checked;
+  if (panicKeySel) currentProfile.panic_key.key = panicKeySel.value;
+  if (panicKeyHoldInput) {
+    const hold = parseInt(panicKeyHoldInput.value, 10);
+    currentProfile.panic_key.hold_ms = Number.isFinite(hold) && hold >= 0 ? hold : 2000;
+  }
+  if (capsLockCompensationCb) {
+    currentProfile.caps_lock_compensation = capsLockCompensationCb.checked;
+  }
+  if (pasteGraphemeClustersCb) {
+    currentProfile.paste_grapheme_clusters = pasteGraphemeClustersCb.checked;
+  }
+  if (pasteMinCharsInput) {
+    const minChars = parseInt(pasteMinCharsInput.value, 10);
+    currentProfile.paste_min_chars = Number.isFinite(minChars) && minChars > 0 ? minChars : null;
+  }
+  if (injectionChunkSizeInput && injectionChunkDelayInput) {
+    const chunkSize = parseInt(injectionChunkSizeInput.value, 10);
+    const chunkDelay = parseInt(injectionChunkDelayInput.value, 10);
+    currentProfile.injection = {
+      chunk_size: Number.isFinite(chunkSize) && chunkSize > 0 ? chunkSize : 0,
+      chunk_delay_ms: Number.isFinite(chunkDelay) && chunkDelay > 0 ? chunkDelay : 0,
+    };
+  }
+  if (outputRulesInput) {
+    currentProfile.output_rules = outputRulesInput.value
+      .split("\n")
+      .map((line) => line.trim().split(/\s+/))
+      .filter(([from]) => from)
+      .map(([from, to]) => ({ from, to: to ?? "" }));
+  }
+  if (focusChangeSel) currentProfile.focus_change = focusChangeSel.value;
+  if (imeCompositionSel) currentProfile.ime_composition = imeCompositionSel.value;
+  if (undefinedKeySel) currentProfile.undefined_key = undefinedKeySel.value;
+  if (passThroughKeysInput) {
+    currentProfile.pass_through_keys = passThroughKeysInput.value.split(/\s+/).filter((name) => name);
+  }
+  if (keySwapsInput) {
+    currentProfile.key_swaps = keySwapsInput.value
+      .split("\n")
+      .map((line) => line.trim().split(/\s+/))
+      .filter(([from, to]) => from && to)
+      .map(([from, to]) => ({ from, to }));
+  }
+  if (spaceWidthSel) currentProfile.space_width = spaceWidthSel.value;
+  if (deferredEnterTimeoutInput) {
+    const timeout = parseInt(deferredEnterTimeoutInput.value, 10);
+    currentProfile.deferred_enter_timeout_ms = Number.isFinite(timeout) && timeout >= 0 ? timeout : 500;
+  }
+  if (decisionModeSel) currentProfile.decision_mode = decisionModeSel.value;
+  if (chordWindowInput) {
+    const windowMs = parseInt(chordWindowInput.value, 10);
+    currentProfile.chord_window_ms = Number.isFinite(windowMs) && windowMs > 0 ? windowMs : 200;
+  }
+  if (longPressMsInput) {
+    const longPressMs = parseInt(longPressMsInput.value, 10);
+    currentProfile.long_press_ms = Number.isFinite(longPressMs) && longPressMs > 0 ? longPressMs : 500;
+  }
 
   try {
     console.log("Saving profile:", currentProfile);
     await invoke("set_profile", { profile: currentProfile });
-    statusMsg.innerText = "\u8a2d\u5b9a\u3092\u4fdd\u5b58\u3057\u307e\u3057\u305f";
+    statusMsg.innerText = await withKeySwapIssues("\u8a2d\u5b9a\u3092\u4fdd\u5b58\u3057\u307e\u3057\u305f");
   } catch (e) {
     statusMsg.innerText = "設定の保存に失敗しました: " + e;
   }
@@ -630,6 +994,16 @@ function setupAutoSave() {
     extThumb1ContinuousCb, extThumb1RepeatCb,
     extThumb2ContinuousCb, extThumb2RepeatCb,
     charContinuousCb,
+    thumbDoubleTapMsInput, prefixShiftTimeoutInput,
+    sandsEnabledCb, sandsRepeatCb, sandsTapTimeoutInput, dualRoleKeysInput,
+    panicKeyEnabledCb, panicKeyHoldInput,
+    capsLockCompensationCb, pasteGraphemeClustersCb, pasteMinCharsInput, outputRulesInput,
+    passThroughKeysInput, keySwapsInput,
+    injectionChunkSizeInput, injectionChunkDelayInput,
+    deferredEnterTimeoutInput,
+    chordWindowInput, longPressMsInput,
+    numberKeyEnabledCb, numberKeyContinuousCb, numberKeyOverlapRatioInput, numberKeyMinOverlapInput,
+    pinkyShiftEnabledCb, pinkyShiftContinuousCb, pinkyShiftOverlapRatioInput, pinkyShiftMinOverlapInput,
   ];
   changeTargets.forEach((el) => {
     if (el) el.addEventListener("change", saveProfile);
@@ -641,7 +1015,10 @@ function setupAutoSave() {
     thumbRightKeySel,
     extThumb1KeySel,
     extThumb2KeySel,
-    imeModeSel, suspendKeySel
+    imeModeSel, panicKeySel, focusChangeSel, imeCompositionSel, undefinedKeySel,
+    spaceWidthSel,
+    decisionModeSel,
+    sandsSideSel
   ];
   selectTargets.forEach((el) => {
     if (el) el.addEventListener("change", saveProfile);
@@ -672,6 +1049,15 @@ function setupAutoSave() {
     });
   }
 
+  for (const { side } of THUMB_TIMING_FIELDS) {
+    for (const id of [`#thumb-${side}-overlap-ratio`, `#thumb-${side}-min-overlap`]) {
+      document.querySelector(id)?.addEventListener("change", saveProfile);
+    }
+  }
+  for (const { id } of THUMB_DOUBLE_TAP_FIELDS) {
+    document.getElementById(id)?.addEventListener("change", saveProfile);
+  }
+
   const rangeTargets = [thumbOverlapRatioInput, charOverlapRatioInput];
   rangeTargets.forEach((el) => {
     if (el) el.addEventListener("input", saveProfile);
@@ -704,6 +1090,7 @@ function ensureExtendedThumbSection() {
 
   const keyOptions = keyOptionsWithoutNone(document.querySelector("#thumb-left-key"));
   const singlePressOptions = document.querySelector("#thumb-left-single-press")?.innerHTML || "";
+  const doubleTapOptions = document.querySelector("#thumb-left-double-tap")?.innerHTML || "";
 
   const section = document.createElement("div");
   section.id = "section-extended-thumb";
@@ -717,6 +1104,7 @@ function ensureExtendedThumbSection() {
             <div class="setting-label">シフトキー</div>
             <div class="setting-control">
               <select id="ext-thumb-1-key">${keyOptions}</select>
+              <button class="capture-key-btn" data-target="ext-thumb-1-key">キー入力</button>
             </div>
           </div>
           <div class="setting-item">
@@ -734,6 +1122,12 @@ function ensureExtendedThumbSection() {
               <select id="ext-thumb-1-single-press">${singlePressOptions}</select>
             </div>
           </div>
+          <div class="setting-item">
+            <div class="setting-label">2回打鍵</div>
+            <div class="setting-control">
+              <select id="ext-thumb-1-double-tap">${doubleTapOptions}</select>
+            </div>
+          </div>
           <div class="setting-item">
             <div class="setting-label" id="ext-thumb-1-repeat-label">親指シフトキーリピート</div>
             <div class="setting-control">
@@ -750,6 +1144,7 @@ function ensureExtendedThumbSection() {
             <div class="setting-label">シフトキー</div>
             <div class="setting-control">
               <select id="ext-thumb-2-key">${keyOptions}</select>
+              <button class="capture-key-btn" data-target="ext-thumb-2-key">キー入力</button>
             </div>
           </div>
           <div class="setting-item">
@@ -767,6 +1162,12 @@ function ensureExtendedThumbSection() {
               <select id="ext-thumb-2-single-press">${singlePressOptions}</select>
             </div>
           </div>
+          <div class="setting-item">
+            <div class="setting-label">2回打鍵</div>
+            <div class="setting-control">
+              <select id="ext-thumb-2-double-tap">${doubleTapOptions}</select>
+            </div>
+          </div>
           <div class="setting-item">
             <div class="setting-label" id="ext-thumb-2-repeat-label">親指シフトキーリピート</div>
             <div class="setting-control">
@@ -829,6 +1230,11 @@ window.addEventListener("DOMContentLoaded", () => {
   };
 
   layoutEntryListEl = document.querySelector("#layout-entry-list");
+  layoutEntryFilterSel = document.querySelector("#layout-entry-filter");
+  layoutEntryFilterSel?.addEventListener("change", () => {
+    layoutEntryFilter = layoutEntryFilterSel.value;
+    renderLayoutEntryList();
+  });
   const reloadLayoutBtn = document.querySelector("#reload-layout-btn");
   if (reloadLayoutBtn) {
     reloadLayoutBtn.addEventListener("click", async () => {
@@ -885,15 +1291,51 @@ window.addEventListener("DOMContentLoaded", () => {
   // Reset old binding if any
   thumbOverlapRatioInput = document.querySelector("#thumb-overlap-ratio");
   thumbOverlapVal = document.querySelector("#thumb-overlap-val");
+  thumbDoubleTapMsInput = document.querySelector("#thumb-double-tap-ms");
+  prefixShiftTimeoutInput = document.querySelector("#prefix-shift-timeout");
+  sandsEnabledCb = document.querySelector("#sands-enabled");
+  sandsSideSel = document.querySelector("#sands-side");
+  sandsTapTimeoutInput = document.querySelector("#sands-tap-timeout");
+  sandsRepeatCb = document.querySelector("#sands-repeat");
+  dualRoleKeysInput = document.querySelector("#dual-role-keys");
 
   // Chord
   charContinuousCb = document.querySelector("#char-continuous");
   charOverlapRatioInput = document.querySelector("#char-overlap-ratio");
   charOverlapVal = document.querySelector("#char-overlap-val");
+  numberKeyEnabledCb = document.querySelector("#number-key-enabled");
+  numberKeyContinuousCb = document.querySelector("#number-key-continuous");
+  numberKeyOverlapRatioInput = document.querySelector("#number-key-overlap-ratio");
+  numberKeyMinOverlapInput = document.querySelector("#number-key-min-overlap");
+  pinkyShiftEnabledCb = document.querySelector("#pinky-shift-enabled");
+  pinkyShiftContinuousCb = document.querySelector("#pinky-shift-continuous");
+  pinkyShiftOverlapRatioInput = document.querySelector("#pinky-shift-overlap-ratio");
+  pinkyShiftMinOverlapInput = document.querySelector("#pinky-shift-min-overlap");
 
   // Op
   imeModeSel = document.querySelector("#ime-mode");
-  suspendKeySel = document.querySelector("#suspend-key");
+  suspendKeySetting = mountSuspendKeySetting(document.body, { invoke, listen }, {
+    onChange: saveProfile,
+  });
+  panicKeyEnabledCb = document.querySelector("#panic-key-enabled");
+  panicKeySel = document.querySelector("#panic-key");
+  panicKeyHoldInput = document.querySelector("#panic-key-hold");
+  capsLockCompensationCb = document.querySelector("#caps-lock-compensation");
+  pasteGraphemeClustersCb = document.querySelector("#paste-grapheme-clusters");
+  pasteMinCharsInput = document.querySelector("#paste-min-chars");
+  outputRulesInput = document.querySelector("#output-rules");
+  injectionChunkSizeInput = document.querySelector("#injection-chunk-size");
+  injectionChunkDelayInput = document.querySelector("#injection-chunk-delay");
+  focusChangeSel = document.querySelector("#focus-change");
+  imeCompositionSel = document.querySelector("#ime-composition");
+  undefinedKeySel = document.querySelector("#undefined-key");
+  passThroughKeysInput = document.querySelector("#pass-through-keys");
+  keySwapsInput = document.querySelector("#key-swaps");
+  spaceWidthSel = document.querySelector("#space-width");
+  deferredEnterTimeoutInput = document.querySelector("#deferred-enter-timeout");
+  decisionModeSel = document.querySelector("#decision-mode");
+  chordWindowInput = document.querySelector("#chord-window");
+  longPressMsInput = document.querySelector("#long-press-ms");
 
   // Sidebar
   navItems = document.querySelectorAll(".nav-item");
@@ -923,6 +1365,32 @@ window.addEventListener("DOMContentLoaded", () => {
     loadProfile();
   });
 
+  window.__TAURI__.event.listen("hook-recovered", () => {
+    statusMsg.innerText = "キー入力の監視が止まっていたため再開しました";
+  });
+
+  // The payload counts the events dropped since the previous report.
+  window.__TAURI__.event.listen("injection-failed", (event) => {
+    const dropped = event.payload;
+    statusMsg.innerText =
+      dropped.elevated > 0
+        ? "管理者として実行中のウィンドウには入力できませんでした。「管理者として実行」をお試しください"
+        : `入力の送信に失敗しました（${dropped.events} 件）`;
+  });
+
+  window.__TAURI__.event.listen("layout-switched", () => {
+    refreshLayoutEntries();
+  });
+
+  // The fallback happens during setup, before this page can listen.
+  invoke("take_layout_fallback")
+    .then((fallback) => {
+      if (!fallback) return;
+      statusMsg.innerText = "配列定義を読み込めないため、前に使った配列に切り替えました: " + fallback.error;
+      refreshLayoutEntries();
+    })
+    .catch((e) => console.error("Failed to get layout fallback:", e));
+
   window.__TAURI__.event.listen("enabled-state-changed", (event) => {
     const enabled = event.payload;
     if (globalEnabledCb) globalEnabledCb.checked = enabled;
@@ -931,7 +1399,31 @@ window.addEventListener("DOMContentLoaded", () => {
 
   // Autostart init
   initAutoLaunch();
+  initLanguage();
+  initTrayIconOptions();
+  initPreviousLayoutHotkey();
+  initTenkeyHotkey();
+  initPasswordFieldBypass();
+  initHookFastPath();
+  initNotifications();
+  initAutoDisable();
+  initElevation();
+  initSettingsBundle();
   initAboutContributors();
+  initTrainingPanel();
+  initChordTest();
+  initProfilePresets();
+  initOutputThrottle();
+  initSoundFeedback();
+  initOutputScripts();
+  initLogViewer();
+  initKeyRecorder();
+  initCrashReports();
+  initImeAppOverrides();
+  initImeDebug();
+  initEngineState();
+  initKeyCapture();
+  initSafeMode();
   initVersion();
 });
 
@@ -941,6 +1433,142 @@ function initAboutContributors() {
   mountAboutContributors(root);
 }
 
+function initPreviousLayoutHotkey() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountHotkeySetting(root, { invoke }, {
+    prefix: "previous-layout-hotkey",
+    getCommand: "get_previous_layout_hotkey",
+    setCommand: "set_previous_layout_hotkey",
+  });
+}
+
+function initTenkeyHotkey() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountHotkeySetting(root, { invoke }, {
+    prefix: "tenkey-hotkey",
+    getCommand: "get_tenkey_hotkey",
+    setCommand: "set_tenkey_hotkey",
+  });
+}
+
+function initAutoDisable() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountAutoDisable(root, { invoke });
+}
+
+function initSettingsBundle() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountSettingsBundle(root, { invoke, listen });
+}
+
+function initOutputThrottle() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountOutputThrottle(root, { invoke });
+}
+
+function initOutputScripts() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountOutputScripts(root, { invoke });
+}
+
+function initSoundFeedback() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountSoundFeedback(root, { invoke });
+}
+
+function initLogViewer() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountLogViewer(root, { invoke });
+}
+
+function initKeyRecorder() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountKeyRecorder(root, { invoke });
+}
+
+function initCrashReports() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountCrashReports(root, { invoke });
+}
+
+function initImeAppOverrides() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountImeAppOverrides(root, { invoke });
+}
+
+function initImeDebug() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountImeDebug(root, { invoke });
+}
+
+function initEngineState() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountEngineState(root, { invoke });
+}
+
+function initKeyCapture() {
+  // Buttons live in both the thumb and the extended thumb sections.
+  mountKeyCapture(document.body, { invoke, listen });
+}
+
+function initProfilePresets() {
+  const root = document.getElementById("section-behavior");
+  if (!root) return;
+  mountProfilePresets(root, { invoke, listen, onApplied: loadProfile });
+}
+
+function initTrainingPanel() {
+  const root = document.getElementById("section-training");
+  if (!root) return;
+  mountTrainingPanel(root, { invoke, listen });
+}
+
+function initChordTest() {
+  const root = document.getElementById("section-training");
+  if (!root) return;
+  mountChordTest(root, { invoke, listen });
+}
+
+async function initSafeMode() {
+  let prompted = false;
+  const prompt = async (info) => {
+    if (!info || prompted) return;
+    prompted = true;
+    statusMsg.innerText = "セーフモードで起動しました";
+    const resume = confirm(
+      `前回の起動で問題が発生したため、配列を無効にして起動しました。\n理由: ${info.reason}\n\n配列を読み込んで通常どおり起動しますか？`
+    );
+    if (!resume) return;
+    try {
+      await invoke("exit_safe_mode");
+      await refreshLayoutEntries();
+      await refreshEnabledState();
+    } catch (e) {
+      alert(String(e));
+    }
+  };
+
+  listen("safe-mode", (event) => prompt(event.payload));
+  try {
+    prompt(await invoke("get_safe_mode"));
+  } catch (e) {
+    console.error("Failed to get safe mode:", e);
+  }
+}
+
 async function refreshEnabledState() {
   if (!globalEnabledCb) return;
   try {
@@ -963,6 +1591,167 @@ async function initVersion() {
   }
 }
 
+async function initNotifications() {
+  const notificationsCb = document.querySelector("#notifications-enabled");
+  if (!notificationsCb) return;
+
+  try {
+    notificationsCb.checked = await invoke("get_notifications_enabled");
+  } catch (e) {
+    console.error("Failed to get notification setting:", e);
+  }
+
+  notificationsCb.addEventListener("change", async () => {
+    try {
+      await invoke("set_notifications_enabled", { enabled: notificationsCb.checked });
+    } catch (e) {
+      console.error("Failed to set notification setting:", e);
+    }
+  });
+}
+
+async function initTrayIconOptions() {
+  const showInitialCb = document.querySelector("#tray-show-layout-initial");
+  if (!showInitialCb) return;
+
+  try {
+    showInitialCb.checked = await invoke("get_tray_show_layout_initial");
+  } catch (e) {
+    console.error("Failed to get tray icon options:", e);
+  }
+
+  showInitialCb.addEventListener("change", async () => {
+    try {
+      await invoke("set_tray_show_layout_initial", { enabled: showInitialCb.checked });
+    } catch (e) {
+      console.error("Failed to set tray icon options:", e);
+    }
+  });
+
+  const favoritesOnlyCb = document.querySelector("#tray-favorites-only");
+  if (!favoritesOnlyCb) return;
+
+  try {
+    favoritesOnlyCb.checked = await invoke("get_tray_favorites_only");
+  } catch (e) {
+    console.error("Failed to get tray menu options:", e);
+  }
+
+  favoritesOnlyCb.addEventListener("change", async () => {
+    try {
+      await invoke("set_tray_favorites_only", { enabled: favoritesOnlyCb.checked });
+    } catch (e) {
+      console.error("Failed to set tray menu options:", e);
+    }
+  });
+}
+
+async function initHookFastPath() {
+  const fastPathCb = document.querySelector("#hook-fast-path");
+  if (!fastPathCb) return;
+
+  try {
+    fastPathCb.checked = await invoke("get_hook_fast_path");
+  } catch (e) {
+    console.error("Failed to get hook fast path setting:", e);
+  }
+
+  fastPathCb.addEventListener("change", async () => {
+    try {
+      await invoke("set_hook_fast_path", { enabled: fastPathCb.checked });
+    } catch (e) {
+      console.error("Failed to set hook fast path setting:", e);
+    }
+  });
+
+  listen("hook-slow", (event) => {
+    const health = event.payload;
+    if (fastPathCb.checked) return;
+    statusMsg.innerText = `キー入力の監視に時間がかかっています（最大 ${Math.round(health.max_us / 1000)}ms / 上限 ${health.timeout_ms}ms）。「キー入力の監視を軽くする」をお試しください`;
+  });
+}
+
+async function initPasswordFieldBypass() {
+  const bypassCb = document.querySelector("#bypass-password-fields");
+  if (!bypassCb) return;
+
+  try {
+    bypassCb.checked = await invoke("get_bypass_password_fields");
+  } catch (e) {
+    console.error("Failed to get password field setting:", e);
+  }
+
+  bypassCb.addEventListener("change", async () => {
+    try {
+      await invoke("set_bypass_password_fields", { enabled: bypassCb.checked });
+    } catch (e) {
+      console.error("Failed to set password field setting:", e);
+    }
+  });
+
+  listen("secure-input-changed", (event) => {
+    statusMsg.innerText = event.payload ? "パスワード入力欄のため配列を使わずに入力中" : "";
+  });
+}
+
+async function initLanguage() {
+  const languageSel = document.querySelector("#ui-language");
+  if (!languageSel) return;
+
+  try {
+    languageSel.value = await invoke("get_language");
+  } catch (e) {
+    console.error("Failed to get language:", e);
+  }
+
+  languageSel.addEventListener("change", async () => {
+    try {
+      await invoke("set_language", { language: languageSel.value });
+    } catch (e) {
+      console.error("Failed to set language:", e);
+    }
+  });
+}
+
+function elevationStatusText(status) {
+  if (status.ui_access) return "UIAccess 付きで実行中です。管理者権限のアプリにも入力できます。";
+  if (status.elevated) return "管理者として実行中です。";
+  return "通常の権限で実行中です。管理者権限のアプリではキーを置き換えません。";
+}
+
+async function initElevation() {
+  const runElevatedCb = document.querySelector("#run-elevated");
+  const restartBtn = document.querySelector("#restart-elevated-btn");
+  const statusEl = document.querySelector("#elevation-status");
+  if (!runElevatedCb || !restartBtn || !statusEl) return;
+
+  try {
+    const status = await invoke("get_elevation_status");
+    runElevatedCb.checked = status.run_elevated;
+    statusEl.innerText = elevationStatusText(status);
+    restartBtn.disabled = status.elevated || status.ui_access;
+  } catch (e) {
+    console.error("Failed to get elevation status:", e);
+  }
+
+  runElevatedCb.addEventListener("change", async () => {
+    try {
+      await invoke("set_run_elevated", { enabled: runElevatedCb.checked });
+    } catch (e) {
+      console.error("Failed to save run_elevated:", e);
+      runElevatedCb.checked = !runElevatedCb.checked;
+    }
+  });
+
+  restartBtn.addEventListener("click", async () => {
+    try {
+      await invoke("restart_elevated");
+    } catch (e) {
+      statusMsg.innerText = "管理者として再起動できませんでした: " + e;
+    }
+  });
+}
+
 async function initAutoLaunch() {
   const autoLaunchCb = document.querySelector("#auto-launch");
   if (!autoLaunchCb) return;